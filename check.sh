@@ -3,4 +3,6 @@
 cargo clippy &&
     cargo clippy --features=commercial &&
     cargo test &&
-    cargo test --features=commercial
+    cargo test --features=commercial &&
+    cargo test --features=arbitrary-precision &&
+    cargo test -p worterbuch-client --features=metrics
@@ -20,108 +20,26 @@
 use anyhow::Result;
 use clap::Parser;
 use std::time::Duration;
-use tokio::{select, sync::mpsc};
-use tokio_graceful_shutdown::{SubsystemHandle, Toplevel};
-use worterbuch_cli::{next_item, print_del_event, print_message, provide_keys};
-use worterbuch_client::{config::Config, connect, AuthToken};
+use tokio_graceful_shutdown::Toplevel;
+use worterbuch_cli::commands::{del, DelArgs};
 
 #[derive(Parser)]
 #[command(author, version, about = "Delete values for keys from a Wörterbuch.", long_about = None)]
 struct Args {
-    /// Connect to the Wörterbuch server using SSL encryption.
-    #[arg(short, long)]
-    ssl: bool,
-    /// The address of the Wörterbuch server. When omitted, the value of the env var WORTERBUCH_HOST_ADDRESS will be used. If that is not set, 127.0.0.1 will be used.
-    #[arg(short, long)]
-    addr: Option<String>,
-    /// The port of the Wörterbuch server. When omitted, the value of the env var WORTERBUCH_PORT will be used. If that is not set, 4242 will be used.
-    #[arg(short, long)]
-    port: Option<u16>,
-    /// Output data in JSON and expect input data to be JSON.
-    #[arg(short, long)]
-    json: bool,
-    /// Keys to be deleted from Wörterbuch in the form "KEY1 KEY2 KEY3 ...". When omitted, keys will be read from stdin. When reading keys from stdin, one key is expected per line.
-    keys: Option<Vec<String>>,
-    /// Auth token to be used for acquiring authorization from the server
-    #[arg(long)]
-    auth: Option<AuthToken>,
-    /// Print only the value of the deleted key/value pair
-    #[arg(short, long)]
-    raw: bool,
+    #[command(flatten)]
+    args: DelArgs,
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
     dotenv::dotenv().ok();
     env_logger::init();
+    let args: Args = Args::parse();
     Toplevel::new()
-        .start("wbdel", wbdel)
+        .start("wbdel", |subsys| del(subsys, args.args))
         .catch_signals()
         .handle_shutdown_requests(Duration::from_millis(1000))
         .await?;
 
     Ok(())
 }
-
-async fn wbdel(subsys: SubsystemHandle) -> Result<()> {
-    let mut config = Config::new();
-    let args: Args = Args::parse();
-
-    config.auth_token = args.auth.or(config.auth_token);
-
-    config.proto = if args.ssl {
-        "wss".to_owned()
-    } else {
-        "tcp".to_owned()
-    };
-    config.host_addr = args.addr.unwrap_or(config.host_addr);
-    config.port = args.port.unwrap_or(config.port);
-    let json = args.json;
-    let raw = args.raw;
-    let keys = args.keys;
-
-    let (disco_tx, mut disco_rx) = mpsc::channel(1);
-    let on_disconnect = async move {
-        disco_tx.send(()).await.ok();
-    };
-
-    let wb = connect(config, on_disconnect).await?;
-    let mut responses = wb.all_messages().await?;
-
-    let mut trans_id = 0;
-    let mut acked = 0;
-
-    let mut rx = provide_keys(keys, subsys.clone());
-    let mut done = false;
-
-    loop {
-        if done && acked >= trans_id {
-            break;
-        }
-        select! {
-            _ = subsys.on_shutdown_requested() => break,
-            _ = disco_rx.recv() => {
-                log::warn!("Connection to server lost.");
-                subsys.request_global_shutdown();
-            }
-            msg = responses.recv() => if let Some(msg) = msg {
-                if let Some(tid) = msg.transaction_id() {
-                    if tid > acked {
-                        acked = tid;
-                    }
-                }
-                if raw {
-                    print_del_event(&msg, json)
-                } else{
-                    print_message(&msg, json, false);
-                }
-            },
-            recv = next_item(&mut rx, done) => match recv {
-                Some(key ) => trans_id = wb.delete_async(key).await?,
-                None => done = true,
-            },
-        }
-    }
-
-    Ok(())
-}
@@ -0,0 +1,45 @@
+/*
+ *  Worterbuch cli client for replaying recorded value changes
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use anyhow::Result;
+use clap::Parser;
+use std::time::Duration;
+use tokio_graceful_shutdown::Toplevel;
+use worterbuch_cli::commands::{replay, ReplayArgs};
+
+#[derive(Parser)]
+#[command(author, version, about = "Replay a recording made with wbrecord against a Wörterbuch.", long_about = None)]
+struct Args {
+    #[command(flatten)]
+    args: ReplayArgs,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    env_logger::init();
+    let args: Args = Args::parse();
+    Toplevel::new()
+        .start("wbreplay", |subsys| replay(subsys, args.args))
+        .catch_signals()
+        .handle_shutdown_requests(Duration::from_millis(1000))
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,973 @@
+/*
+ *  Worterbuch cli commands module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{
+    glob_matches, next_item,
+    offline::Dump,
+    print_change_event, print_del_event, print_message, print_sorted_key_value_pairs,
+    print_sorted_keys, provide_key_value_pairs, provide_keys, provide_values,
+    recording::{provide_recording, ReplayOptions},
+};
+use anyhow::{anyhow, Result};
+use clap::{Args, Subcommand, ValueEnum};
+use serde_json::Value;
+use std::{
+    fs,
+    future::Future,
+    io::{Read, Write},
+    time::{Duration, Instant},
+};
+use tokio::{select, sync::mpsc};
+use tokio_graceful_shutdown::SubsystemHandle;
+use worterbuch_client::{
+    config::Config, connect, recording::RecordedEvent, AuthToken, ConnectionResult, KeyValuePair,
+    PState, PStateEvent, ServerMessage as SM, State, StateEvent, TransactionId, Worterbuch,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct ConnectionArgs {
+    /// Connect to the Wörterbuch server using SSL encryption.
+    #[arg(short, long)]
+    pub ssl: bool,
+    /// The address of the Wörterbuch server. When omitted, the value of the env var WORTERBUCH_HOST_ADDRESS will be used. If that is not set, 127.0.0.1 will be used.
+    #[arg(short, long)]
+    pub addr: Option<String>,
+    /// The port of the Wörterbuch server. When omitted, the value of the env var WORTERBUCH_PORT will be used. If that is not set, 4242 will be used.
+    #[arg(short, long)]
+    pub port: Option<u16>,
+    /// Auth token to be used for acquiring authorization from the server
+    #[arg(long)]
+    pub auth: Option<AuthToken>,
+}
+
+impl ConnectionArgs {
+    pub fn config(self) -> Config {
+        let mut config = Config::new();
+
+        config.auth_token = self.auth.or(config.auth_token);
+
+        config.proto = if self.ssl {
+            "wss".to_owned()
+        } else {
+            "tcp".to_owned()
+        };
+        config.host_addr = self.addr.unwrap_or(config.host_addr);
+        config.port = self.port.unwrap_or(config.port);
+
+        config
+    }
+
+    pub async fn connect(self) -> Result<(Worterbuch, Responses)> {
+        let (disco_tx, disconnected) = mpsc::channel(1);
+        let on_disconnect = async move {
+            disco_tx.send(()).await.ok();
+        };
+
+        let wb = connect(self.config(), on_disconnect).await?;
+        let messages = wb.all_messages().await?;
+
+        Ok((
+            wb,
+            Responses {
+                disconnected,
+                messages,
+            },
+        ))
+    }
+}
+
+pub struct Responses {
+    disconnected: mpsc::Receiver<()>,
+    messages: mpsc::UnboundedReceiver<SM>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct OutputArgs {
+    /// Output data in JSON and expect input data to be JSON.
+    #[arg(short, long)]
+    pub json: bool,
+}
+
+/// How values are read from or written to files.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    /// The file contains a JSON document.
+    Json,
+    /// The file contains plain text, which is stored as a JSON string.
+    Text,
+}
+
+impl ContentType {
+    fn resolve(content_type: Option<ContentType>, json: bool) -> ContentType {
+        match content_type {
+            Some(content_type) => content_type,
+            None if json => ContentType::Json,
+            None => ContentType::Text,
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct GetArgs {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Keys to be fetched from Wörterbuch in the form "KEY1 KEY2 KEY3 ...". When omitted, keys will be read from stdin. When reading keys from stdin, one key is expected per line.
+    pub keys: Option<Vec<String>>,
+    /// Print only the value of the specified key
+    #[arg(short, long)]
+    pub raw: bool,
+    /// Write the value of a single key verbatim to this file ('-' for stdout) instead of printing it line by line.
+    #[arg(short = 'o', long = "output")]
+    pub output_file: Option<String>,
+    /// How to write the value to the output file. 'text' writes strings without quotes and everything else as JSON, 'json' always writes JSON. Defaults to 'json' if --json is set, 'text' otherwise.
+    #[arg(long, value_enum)]
+    pub content_type: Option<ContentType>,
+    /// Answer requests from a persistence dump or export file instead of connecting to a server.
+    #[arg(long, value_name = "FILE")]
+    pub from_file: Option<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct PGetArgs {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Patterns to be fetched from Wörterbuch in the form "PATTERN1 PATTERN2 PATTERN3 ...". When omitted, patterns will be read from stdin. When reading patterns from stdin, one pattern is expected per line.
+    pub patterns: Option<Vec<String>>,
+    /// Print only the received key/value pairs
+    #[arg(short, long)]
+    pub raw: bool,
+    /// Answer requests from a persistence dump or export file instead of connecting to a server.
+    #[arg(long, value_name = "FILE")]
+    pub from_file: Option<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct PKeysArgs {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Patterns whose matching keys are to be fetched from Wörterbuch in the form "PATTERN1 PATTERN2 PATTERN3 ...". When omitted, patterns will be read from stdin. When reading patterns from stdin, one pattern is expected per line.
+    pub patterns: Option<Vec<String>>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct SetArgs {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Key/value pairs to be set on Wörterbuch in the form "KEY1=VALUE1 KEY2=VALUE2 KEY3=VALUE3 ...". When omitted, key/value pairs will be read from stdin. When reading key/value pairs from stdin, one key/value pair is expected per line. With --input, a single key is expected instead.
+    pub key_value_pairs: Option<Vec<String>>,
+    /// Read the value of a single key verbatim from this file ('-' for stdin) instead of parsing key/value pairs line by line.
+    #[arg(short = 'i', long = "input")]
+    pub input_file: Option<String>,
+    /// How to read the value from the input file. 'text' stores the file's content as a string, 'json' parses it as a JSON document. Defaults to 'json' if --json is set, 'text' otherwise.
+    #[arg(long, value_enum)]
+    pub content_type: Option<ContentType>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct PubArgs {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Key/value pairs to be published on Wörterbuch in the form "KEY1=VALUE1 KEY2=VALUE2 KEY3=VALUE3 ...". When omitted, key/value pairs will be read from stdin. When reading key/value pairs from stdin, one key/value pair is expected per line.
+    pub key_value_pairs: Option<Vec<String>>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct StreamArgs {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Wörterbuch key to send values to.
+    pub key: String,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct SubArgs {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Wörterbuch keys to be subscribed to in the form "PATTERN1 PATTERN2 PATTERN3 ...". When omitted, keys will be read from stdin. When reading keys from stdin, one key is expected per line.
+    pub keys: Option<Vec<String>>,
+    /// Only receive unique values, i.e. skip notifications when a key is set to a value it already has.
+    #[arg(short, long)]
+    pub unique: bool,
+    /// Only receive live values, i.e. do not receive a callback for the state currently stored on the broker.
+    #[arg(short, long)]
+    pub live_only: bool,
+    /// Print only the received events
+    #[arg(short, long)]
+    pub raw: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct PSubArgs {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Wörterbuch patterns to be subscribed to in the form "PATTERN1 PATTERN2 PATTERN3 ...". When omitted, patterns will be read from stdin. When reading patterns from stdin, one pattern is expected per line.
+    pub patterns: Option<Vec<String>>,
+    /// Only receive unique values, i.e. skip notifications when a key is set to a value it already has.
+    #[arg(short, long)]
+    pub unique: bool,
+    /// Only receive live values, i.e. do not receive a callback for the state currently stored on the broker.
+    #[arg(short, long)]
+    pub live_only: bool,
+    /// Print only the received events
+    #[arg(short, long)]
+    pub raw: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct RecordArgs {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    /// Wörterbuch patterns to be recorded in the form "PATTERN1 PATTERN2 PATTERN3 ...". When omitted, patterns will be read from stdin. When reading patterns from stdin, one pattern is expected per line.
+    pub patterns: Option<Vec<String>>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ReplayArgs {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    /// Recording to be replayed, as written by wbrecord. If omitted, the recording is read from stdin.
+    pub file: Option<String>,
+    /// Replay faster (e.g. 10) or slower (e.g. 0.5) than the original timing.
+    #[arg(long, default_value_t = 1.0)]
+    pub speed: f64,
+    /// Prefix all recorded keys with a string.
+    #[arg(long)]
+    pub prefix: Option<String>,
+    /// Publish the recorded values instead of setting them. Recorded deletions are skipped.
+    #[arg(long)]
+    pub publish: bool,
+    /// Start over once the end of the recording is reached. Only works with a file.
+    #[arg(short = 'l', long = "loop")]
+    pub repeat: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct LsArgs {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// The key for which to list sub keys. If omitted, root keys will be listed.
+    pub parent: Option<String>,
+    /// List all keys below the parent key recursively instead of only its direct children. Keys are printed in full, one per line and sorted, so listings of different servers can be compared with diff.
+    #[arg(short = 'R', long)]
+    pub recursive: bool,
+    /// Only list keys matching this glob pattern. '?' matches a single character and '*' any number of characters within a key segment, '**' matches across segments. May be given multiple times, keys matching any of the patterns are listed.
+    #[arg(long = "filter", value_name = "GLOB", requires = "recursive")]
+    pub filters: Vec<String>,
+    /// Print the value of every key next to it.
+    #[arg(long, requires = "recursive")]
+    pub values: bool,
+    /// Answer requests from a persistence dump or export file instead of connecting to a server.
+    #[arg(long, value_name = "FILE")]
+    pub from_file: Option<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct LsSubArgs {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Wörterbuch paths to be subscribed to in the form "PATH1 PATH2 PATH3 ...". When omitted, paths will be read from stdin. When reading paths from stdin, one path is expected per line.
+    pub paths: Option<Vec<String>>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct DelArgs {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Keys to be deleted from Wörterbuch in the form "KEY1 KEY2 KEY3 ...". When omitted, keys will be read from stdin. When reading keys from stdin, one key is expected per line.
+    pub keys: Option<Vec<String>>,
+    /// Print only the value of the deleted key/value pair
+    #[arg(short, long)]
+    pub raw: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct PDelArgs {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Patterns to be deleted from Wörterbuch in the form "PATTERN1 PATTERN2 PATTERN3 ...". When omitted, patterns will be read from stdin. When reading patterns from stdin, one pattern is expected per line.
+    pub patterns: Option<Vec<String>>,
+    /// Print only the deleted key/value pairs
+    #[arg(short, long)]
+    pub raw: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct JsonArgs {
+    /// Output data in JSON and expect input data to be JSON.
+    #[arg(short, long)]
+    pub json: bool,
+    /// JSON file to be converted. If omitted, JSON is read from stdin.
+    pub file: Option<String>,
+    /// Prefix the keys with a string.
+    #[arg(short, long)]
+    pub prefix: Option<String>,
+    /// Auth token to be used for acquiring authorization from the server
+    #[arg(long)]
+    pub auth: Option<AuthToken>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Get values for keys from a Wörterbuch.
+    Get(GetArgs),
+    /// Get values for patterns from a Wörterbuch.
+    #[command(name = "pget")]
+    PGet(PGetArgs),
+    /// Get keys matching patterns from a Wörterbuch without their values.
+    #[command(name = "pkeys")]
+    PKeys(PKeysArgs),
+    /// Set values of keys on a Wörterbuch.
+    Set(SetArgs),
+    /// Send a stream of values read from stdin to a single Wörterbuch key.
+    Sets(StreamArgs),
+    /// Publish values on a Wörterbuch.
+    Pub(PubArgs),
+    /// Publish a stream of values read from stdin to a single Wörterbuch key.
+    Pubs(StreamArgs),
+    /// Subscribe to values of Wörterbuch keys.
+    Sub(SubArgs),
+    /// Subscribe to values matching Wörterbuch patterns.
+    #[command(name = "psub")]
+    PSub(PSubArgs),
+    /// List child keys on a Wörterbuch server.
+    Ls(LsArgs),
+    /// Subscribe to child keys on a Wörterbuch server.
+    #[command(name = "lssub")]
+    LsSub(LsSubArgs),
+    /// Delete values for keys from a Wörterbuch.
+    Del(DelArgs),
+    /// Delete values for patterns from a Wörterbuch.
+    #[command(name = "pdel")]
+    PDel(PDelArgs),
+    /// Convert JSON into Wörterbuch key/value pairs.
+    Json(JsonArgs),
+    /// Record changes of values matching Wörterbuch patterns.
+    Record(RecordArgs),
+    /// Replay a recording against a Wörterbuch.
+    Replay(ReplayArgs),
+}
+
+impl Command {
+    pub async fn run(self, subsys: SubsystemHandle) -> Result<()> {
+        match self {
+            Command::Get(args) => get(subsys, args).await,
+            Command::PGet(args) => pget(subsys, args).await,
+            Command::PKeys(args) => pkeys(subsys, args).await,
+            Command::Set(args) => set(subsys, args).await,
+            Command::Sets(args) => sets(subsys, args).await,
+            Command::Pub(args) => publish(subsys, args).await,
+            Command::Pubs(args) => pubs(subsys, args).await,
+            Command::Sub(args) => sub(subsys, args).await,
+            Command::PSub(args) => psub(subsys, args).await,
+            Command::Ls(args) => ls(subsys, args).await,
+            Command::LsSub(args) => lssub(subsys, args).await,
+            Command::Del(args) => del(subsys, args).await,
+            Command::PDel(args) => pdel(subsys, args).await,
+            Command::Json(args) => json(args),
+            Command::Record(args) => record(subsys, args).await,
+            Command::Replay(args) => replay(subsys, args).await,
+        }
+    }
+}
+
+pub async fn get(subsys: SubsystemHandle, args: GetArgs) -> Result<()> {
+    let json = args.output.json;
+    let raw = args.raw;
+
+    if let Some(file) = args.output_file {
+        let key = single_key(args.keys, "--output")?;
+        let content_type = ContentType::resolve(args.content_type, json);
+        let rx = provide_keys(Some(vec![key]), subsys.clone());
+
+        let write = |msg: &SM| match msg {
+            SM::State(State {
+                event: StateEvent::KeyValue(kvp),
+                ..
+            }) => {
+                if let Err(e) = write_value(&file, &kvp.value, content_type) {
+                    eprintln!("Error writing value to {file}: {e}");
+                }
+            }
+            SM::Err(msg) => eprintln!("{msg}"),
+            _ => (),
+        };
+
+        if let Some(dump) = args.from_file {
+            let dump = Dump::load(&dump).await?;
+            return process_offline(&subsys, rx, write, |tid, key| dump.get(tid, key)).await;
+        }
+
+        let (wb, responses) = args.connection.connect().await?;
+        return process(&subsys, responses, rx, true, write, |key| wb.get_async(key)).await;
+    }
+
+    let rx = provide_keys(args.keys, subsys.clone());
+
+    let print = |msg: &SM| {
+        if raw {
+            print_change_event(msg, json)
+        } else {
+            print_message(msg, json, false);
+        }
+    };
+
+    if let Some(dump) = args.from_file {
+        let dump = Dump::load(&dump).await?;
+        return process_offline(&subsys, rx, print, |tid, key| dump.get(tid, key)).await;
+    }
+
+    let (wb, responses) = args.connection.connect().await?;
+    process(&subsys, responses, rx, true, print, |key| wb.get_async(key)).await
+}
+
+pub async fn pget(subsys: SubsystemHandle, args: PGetArgs) -> Result<()> {
+    let json = args.output.json;
+    let raw = args.raw;
+
+    let rx = provide_keys(args.patterns, subsys.clone());
+
+    let print = |msg: &SM| {
+        if raw {
+            print_change_event(msg, json);
+        } else {
+            print_message(msg, json, false);
+        }
+    };
+
+    if let Some(dump) = args.from_file {
+        let dump = Dump::load(&dump).await?;
+        return process_offline(&subsys, rx, print, |tid, pattern| dump.pget(tid, pattern)).await;
+    }
+
+    let (wb, responses) = args.connection.connect().await?;
+    process(&subsys, responses, rx, true, print, |pattern| {
+        wb.pget_async(pattern)
+    })
+    .await
+}
+
+pub async fn pkeys(subsys: SubsystemHandle, args: PKeysArgs) -> Result<()> {
+    let json = args.output.json;
+
+    let (wb, responses) = args.connection.connect().await?;
+    let rx = provide_keys(args.patterns, subsys.clone());
+
+    let print = |msg: &SM| print_message(msg, json, false);
+
+    process(&subsys, responses, rx, true, print, |pattern| {
+        wb.pkeys_async(pattern)
+    })
+    .await
+}
+
+pub async fn set(subsys: SubsystemHandle, args: SetArgs) -> Result<()> {
+    let json = args.output.json;
+
+    if let Some(file) = args.input_file {
+        let key = single_key(args.key_value_pairs, "--input")?;
+        let value = read_value(&file, ContentType::resolve(args.content_type, json))?;
+        let (wb, responses) = args.connection.connect().await?;
+        let (tx, rx) = mpsc::channel(1);
+        tx.send((key, value)).await?;
+        drop(tx);
+
+        let print = |msg: &SM| print_message(msg, json, false);
+
+        return process(&subsys, responses, rx, true, print, |(key, value)| {
+            wb.set_generic(key, value)
+        })
+        .await;
+    }
+
+    let (wb, responses) = args.connection.connect().await?;
+    let rx = provide_key_value_pairs(args.key_value_pairs, json, subsys.clone());
+
+    let print = |msg: &SM| print_message(msg, json, false);
+
+    process(&subsys, responses, rx, true, print, |(key, value)| {
+        wb.set_generic(key, value)
+    })
+    .await
+}
+
+pub async fn sets(subsys: SubsystemHandle, args: StreamArgs) -> Result<()> {
+    let json = args.output.json;
+    let key = args.key;
+
+    let (wb, responses) = args.connection.connect().await?;
+    let rx = provide_values(json, subsys.clone());
+
+    let print = |msg: &SM| print_message(msg, json, false);
+
+    process(&subsys, responses, rx, true, print, |value| {
+        wb.set_generic(key.clone(), value)
+    })
+    .await
+}
+
+pub async fn publish(subsys: SubsystemHandle, args: PubArgs) -> Result<()> {
+    let json = args.output.json;
+
+    let (wb, responses) = args.connection.connect().await?;
+    let rx = provide_key_value_pairs(args.key_value_pairs, json, subsys.clone());
+
+    let print = |msg: &SM| print_message(msg, json, false);
+
+    process(&subsys, responses, rx, true, print, |(key, value)| {
+        wb.publish_generic(key, value)
+    })
+    .await
+}
+
+pub async fn pubs(subsys: SubsystemHandle, args: StreamArgs) -> Result<()> {
+    let json = args.output.json;
+    let key = args.key;
+
+    let (wb, responses) = args.connection.connect().await?;
+    let rx = provide_values(json, subsys.clone());
+
+    let print = |msg: &SM| print_message(msg, json, false);
+
+    process(&subsys, responses, rx, true, print, |value| {
+        wb.publish_generic(key.clone(), value)
+    })
+    .await
+}
+
+pub async fn sub(subsys: SubsystemHandle, args: SubArgs) -> Result<()> {
+    let json = args.output.json;
+    let raw = args.raw;
+    let unique = args.unique;
+    let live_only = args.live_only;
+
+    let (wb, responses) = args.connection.connect().await?;
+    let rx = provide_keys(args.keys, subsys.clone());
+
+    let print = |msg: &SM| print_message(msg, json, raw);
+
+    process(&subsys, responses, rx, false, print, |key| {
+        wb.subscribe_async(key, unique, live_only)
+    })
+    .await
+}
+
+pub async fn psub(subsys: SubsystemHandle, args: PSubArgs) -> Result<()> {
+    let json = args.output.json;
+    let raw = args.raw;
+    let unique = args.unique;
+    let live_only = args.live_only;
+
+    let (wb, responses) = args.connection.connect().await?;
+    let rx = provide_keys(args.patterns, subsys.clone());
+
+    let print = |msg: &SM| print_message(msg, json, raw);
+
+    process(&subsys, responses, rx, false, print, |pattern| {
+        wb.psubscribe_async(pattern, unique, live_only, Some(Duration::from_millis(1)))
+    })
+    .await
+}
+
+pub async fn ls(subsys: SubsystemHandle, args: LsArgs) -> Result<()> {
+    if args.recursive {
+        return ls_recursive(subsys, args).await;
+    }
+
+    let json = args.output.json;
+
+    let (tx, rx) = mpsc::channel(1);
+    tx.send(args.parent).await?;
+    drop(tx);
+
+    let print = |msg: &SM| print_message(msg, json, false);
+
+    if let Some(dump) = args.from_file {
+        let dump = Dump::load(&dump).await?;
+        return process_offline(&subsys, rx, print, |tid, parent| dump.ls(tid, parent)).await;
+    }
+
+    let (wb, responses) = args.connection.connect().await?;
+    process(&subsys, responses, rx, true, print, |parent| {
+        wb.ls_async(parent)
+    })
+    .await
+}
+
+async fn ls_recursive(subsys: SubsystemHandle, args: LsArgs) -> Result<()> {
+    let json = args.output.json;
+    let filters = args.filters;
+    let included = |key: &str| filters.is_empty() || filters.iter().any(|f| glob_matches(f, key));
+
+    let pattern = match args.parent {
+        Some(parent) => format!("{parent}/#"),
+        None => "#".to_owned(),
+    };
+
+    let (tx, rx) = mpsc::channel(1);
+    tx.send(pattern).await?;
+    drop(tx);
+
+    let dump = match args.from_file {
+        Some(dump) => Some(Dump::load(&dump).await?),
+        None => None,
+    };
+
+    if args.values {
+        let print = |msg: &SM| match msg {
+            SM::PState(PState {
+                event: PStateEvent::KeyValuePairs(kvps),
+                ..
+            }) => print_sorted_key_value_pairs(kvps.iter().filter(|kvp| included(&kvp.key)), json),
+            msg => print_message(msg, json, false),
+        };
+
+        if let Some(dump) = dump {
+            return process_offline(&subsys, rx, print, |tid, pattern| dump.pget(tid, pattern))
+                .await;
+        }
+
+        let (wb, responses) = args.connection.connect().await?;
+        process(&subsys, responses, rx, true, print, |pattern| {
+            wb.pget_async(pattern)
+        })
+        .await
+    } else {
+        let print = |msg: &SM| match msg {
+            SM::Keys(msg) => print_sorted_keys(msg.keys.iter().filter(|key| included(key)), json),
+            msg => print_message(msg, json, false),
+        };
+
+        if let Some(dump) = dump {
+            return process_offline(&subsys, rx, print, |tid, pattern| dump.pkeys(tid, pattern))
+                .await;
+        }
+
+        let (wb, responses) = args.connection.connect().await?;
+        process(&subsys, responses, rx, true, print, |pattern| {
+            wb.pkeys_async(pattern)
+        })
+        .await
+    }
+}
+
+pub async fn lssub(subsys: SubsystemHandle, args: LsSubArgs) -> Result<()> {
+    let json = args.output.json;
+
+    let (wb, responses) = args.connection.connect().await?;
+    let rx = provide_keys(args.paths, subsys.clone());
+
+    let print = |msg: &SM| print_message(msg, json, true);
+
+    process(&subsys, responses, rx, false, print, |path| {
+        wb.subscribe_ls_async(if path.is_empty() { None } else { Some(path) })
+    })
+    .await
+}
+
+pub async fn del(subsys: SubsystemHandle, args: DelArgs) -> Result<()> {
+    let json = args.output.json;
+    let raw = args.raw;
+
+    let (wb, responses) = args.connection.connect().await?;
+    let rx = provide_keys(args.keys, subsys.clone());
+
+    let print = |msg: &SM| {
+        if raw {
+            print_del_event(msg, json)
+        } else {
+            print_message(msg, json, false);
+        }
+    };
+
+    process(&subsys, responses, rx, true, print, |key| {
+        wb.delete_async(key)
+    })
+    .await
+}
+
+pub async fn pdel(subsys: SubsystemHandle, args: PDelArgs) -> Result<()> {
+    let json = args.output.json;
+    let raw = args.raw;
+
+    let (wb, responses) = args.connection.connect().await?;
+    let rx = provide_keys(args.patterns, subsys.clone());
+
+    let print = |msg: &SM| {
+        if raw {
+            print_del_event(msg, json);
+        } else {
+            print_message(msg, json, false);
+        }
+    };
+
+    process(&subsys, responses, rx, true, print, |pattern| {
+        wb.pdelete_async(pattern)
+    })
+    .await
+}
+
+pub fn json(args: JsonArgs) -> Result<()> {
+    let json = if let Some(file) = args.file {
+        fs::read_to_string(file)?
+    } else {
+        let mut json = String::new();
+        let stdin = std::io::stdin();
+        let mut handle = stdin.lock();
+        handle.read_to_string(&mut json)?;
+        json
+    };
+
+    let kvps = convert(&json, args.prefix)?;
+
+    if args.json {
+        for kvp in kvps {
+            let json = serde_json::to_string(&kvp)?;
+            println!("{json}");
+        }
+    } else {
+        for KeyValuePair { key, value } in kvps {
+            println!("{key}={value}");
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn record(subsys: SubsystemHandle, args: RecordArgs) -> Result<()> {
+    let (wb, responses) = args.connection.connect().await?;
+    let rx = provide_keys(args.patterns, subsys.clone());
+
+    let start = Instant::now();
+    let print = |msg: &SM| match msg {
+        SM::PState(msg) => {
+            for event in RecordedEvent::from_pstate_event(start.elapsed(), &msg.event) {
+                match serde_json::to_string(&event) {
+                    Ok(json) => println!("{json}"),
+                    Err(e) => eprintln!("Error converting event to json: {e}"),
+                }
+            }
+        }
+        SM::Err(msg) => eprintln!("{msg}"),
+        _ => (),
+    };
+
+    process(&subsys, responses, rx, false, print, |pattern| {
+        wb.psubscribe_async(pattern, false, true, None)
+    })
+    .await
+}
+
+pub async fn replay(subsys: SubsystemHandle, args: ReplayArgs) -> Result<()> {
+    let publish = args.publish;
+    let prefix = args.prefix;
+    let options = ReplayOptions {
+        speed: args.speed,
+        repeat: args.repeat,
+        skip_deletions: publish,
+    };
+
+    let (wb, responses) = args.connection.connect().await?;
+    let rx = provide_recording(args.file, options, subsys.clone())?;
+
+    let print = |msg: &SM| print_message(msg, false, false);
+
+    process(&subsys, responses, rx, true, print, |event| {
+        let key = match &prefix {
+            Some(prefix) => format!("{prefix}/{}", event.key),
+            None => event.key,
+        };
+        let wb = &wb;
+        async move {
+            match event.value {
+                Some(value) if publish => wb.publish_generic(key, value).await,
+                Some(value) => wb.set_generic(key, value).await,
+                None => wb.delete_async(key).await,
+            }
+        }
+    })
+    .await
+}
+
+/// Answers every item received from `items` from a dump file instead of a server and prints the
+/// responses until either shutdown is requested or all items have been answered.
+async fn process_offline<T>(
+    subsys: &SubsystemHandle,
+    mut items: mpsc::Receiver<T>,
+    print: impl Fn(&SM),
+    answer: impl Fn(TransactionId, T) -> SM,
+) -> Result<()> {
+    let mut trans_id = 0;
+
+    loop {
+        select! {
+            _ = subsys.on_shutdown_requested() => break,
+            item = items.recv() => match item {
+                Some(item) => {
+                    trans_id += 1;
+                    print(&answer(trans_id, item));
+                }
+                None => break,
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends a request for every item received from `items` and prints all server messages until
+/// either shutdown is requested or, if `await_acks` is set, all items have been sent and the
+/// responses to all of them have been received.
+async fn process<T, F, Fut>(
+    subsys: &SubsystemHandle,
+    mut responses: Responses,
+    mut items: mpsc::Receiver<T>,
+    await_acks: bool,
+    print: impl Fn(&SM),
+    send: F,
+) -> Result<()>
+where
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = ConnectionResult<TransactionId>>,
+{
+    let mut trans_id = 0;
+    let mut acked = 0;
+    let mut done = false;
+
+    loop {
+        if await_acks && done && acked >= trans_id {
+            break;
+        }
+        select! {
+            _ = subsys.on_shutdown_requested() => break,
+            _ = responses.disconnected.recv() => {
+                log::warn!("Connection to server lost.");
+                subsys.request_global_shutdown();
+            }
+            msg = responses.messages.recv() => if let Some(msg) = msg {
+                if let Some(tid) = msg.transaction_id() {
+                    if tid > acked {
+                        acked = tid;
+                    }
+                }
+                print(&msg);
+            },
+            recv = next_item(&mut items, done) => match recv {
+                Some(item) => trans_id = send(item).await?,
+                None => done = true,
+            },
+        }
+    }
+
+    Ok(())
+}
+
+fn single_key(keys: Option<Vec<String>>, option: &str) -> Result<String> {
+    match keys.as_deref() {
+        Some([key]) => Ok(key.to_owned()),
+        _ => Err(anyhow!("{option} requires exactly one key")),
+    }
+}
+
+fn read_value(file: &str, content_type: ContentType) -> Result<Value> {
+    let mut content = Vec::new();
+    if file == "-" {
+        std::io::stdin().lock().read_to_end(&mut content)?;
+    } else {
+        content = fs::read(file)?;
+    }
+    match content_type {
+        ContentType::Json => Ok(serde_json::from_slice(&content)?),
+        ContentType::Text => match String::from_utf8(content) {
+            Ok(text) => Ok(Value::String(text)),
+            Err(_) => Err(anyhow!("{file} is not valid UTF-8 text")),
+        },
+    }
+}
+
+fn write_value(file: &str, value: &Value, content_type: ContentType) -> Result<()> {
+    let content = match (content_type, value) {
+        (ContentType::Text, Value::String(text)) => text.as_bytes().to_vec(),
+        _ => serde_json::to_vec(value)?,
+    };
+    if file == "-" {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&content)?;
+        stdout.flush()?;
+    } else {
+        fs::write(file, content)?;
+    }
+    Ok(())
+}
+
+fn convert(json: &str, prefix: Option<String>) -> Result<Vec<KeyValuePair>> {
+    let parsed: Value = serde_json::from_str(json)?;
+
+    let mut kvps = Vec::new();
+
+    if let Some(object) = parsed.as_object() {
+        for (key, value) in object {
+            let path = if let Some(prefix) = &prefix {
+                format!("{prefix}/{key}")
+            } else {
+                key.to_owned()
+            };
+            traverse(&path, value.to_owned(), &mut kvps);
+        }
+    }
+
+    Ok(kvps)
+}
+
+fn traverse(path: &str, value: Value, kvps: &mut Vec<KeyValuePair>) {
+    match value {
+        Value::Null | Value::Bool(_) | Value::Number(_) | Value::String(_) | Value::Array(_) => {
+            kvps.push((path, value).into())
+        }
+        Value::Object(o) => {
+            for (key, value) in o {
+                let path = format!("{path}/{key}");
+                traverse(&path, value.to_owned(), kvps);
+            }
+        }
+    }
+}
@@ -17,6 +17,10 @@
  *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+pub mod commands;
+pub mod offline;
+pub mod recording;
+
 use serde::Serialize;
 use serde_json::{json, Value};
 use std::{ops::ControlFlow, time::Duration};
@@ -28,7 +32,8 @@ use tokio::{
 };
 use tokio_graceful_shutdown::SubsystemHandle;
 use worterbuch_client::{
-    Err, Key, KeyValuePair, LsState, PState, PStateEvent, ServerMessage as SM, State, StateEvent,
+    Err, Key, KeyValuePair, KeysState, LsState, PState, PStateEvent, ServerMessage as SM, State,
+    StateEvent,
 };
 
 pub async fn next_item<T>(rx: &mut mpsc::Receiver<T>, done: bool) -> Option<T> {
@@ -176,6 +181,7 @@ pub fn print_message(msg: &SM, json: bool, raw: bool) {
         SM::State(msg) => print_state(msg, json, raw),
         SM::Err(msg) => print_err(msg, json),
         SM::LsState(msg) => print_ls(msg, json),
+        SM::Keys(msg) => print_keys(msg, json),
         _ => (),
     }
 }
@@ -247,6 +253,63 @@ fn print_ls(msg: &LsState, json: bool) {
     }
 }
 
+fn print_keys(msg: &KeysState, json: bool) {
+    if json {
+        print_msg_as_json(msg);
+    } else {
+        println!("{msg}");
+    }
+}
+
+/// Prints keys one per line in lexical order, so listings of different servers can be diffed.
+pub fn print_sorted_keys<'a>(keys: impl Iterator<Item = &'a Key>, json: bool) {
+    let mut keys: Vec<&Key> = keys.collect();
+    keys.sort();
+    for key in keys {
+        if json {
+            print_msg_as_json(key);
+        } else {
+            println!("{key}");
+        }
+    }
+}
+
+/// Prints key/value pairs one per line, ordered by key, so listings of different servers can be
+/// diffed.
+pub fn print_sorted_key_value_pairs<'a>(kvps: impl Iterator<Item = &'a KeyValuePair>, json: bool) {
+    let mut kvps: Vec<&KeyValuePair> = kvps.collect();
+    kvps.sort_by(|a, b| a.key.cmp(&b.key));
+    for kvp in kvps {
+        if json {
+            print_msg_as_json(kvp);
+        } else {
+            println!("{kvp}");
+        }
+    }
+}
+
+/// Matches a key against a glob pattern. `?` matches a single character and `*` any number of
+/// characters within a key segment, `**` matches any number of characters across segments.
+pub fn glob_matches(pattern: &str, key: &str) -> bool {
+    fn glob(pattern: &[char], key: &[char]) -> bool {
+        match pattern {
+            [] => key.is_empty(),
+            ['*', '*', rest @ ..] => (0..=key.len()).any(|i| glob(rest, &key[i..])),
+            ['*', rest @ ..] => (0..=key.len())
+                .take_while(|&i| i == 0 || key[i - 1] != '/')
+                .any(|i| glob(rest, &key[i..])),
+            ['?', rest @ ..] => {
+                matches!(key.first(), Some(c) if *c != '/') && glob(rest, &key[1..])
+            }
+            [c, rest @ ..] => key.first() == Some(c) && glob(rest, &key[1..]),
+        }
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let key: Vec<char> = key.chars().collect();
+    glob(&pattern, &key)
+}
+
 fn print_err(msg: &Err, json: bool) {
     if json {
         print_msg_as_json(msg);
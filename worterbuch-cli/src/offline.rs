@@ -0,0 +1,122 @@
+/*
+ *  Worterbuch cli offline module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use anyhow::{anyhow, Result};
+use tokio::fs;
+use worterbuch_client::{
+    Err, ErrorCode, KeyValuePair, KeysState, LsState, PState, PStateEvent, ServerMessage as SM,
+    State, StateEvent, TransactionId,
+};
+use worterbuch_common::{
+    dump::StoreDump,
+    error::{WorterbuchError, WorterbuchResult},
+    parse_segments,
+};
+
+/// A persistence dump or export file, requests are answered with the exact same semantics as by a
+/// server that restored the file.
+///
+/// Values that were encrypted at rest stay sealed, the key is only known to the server.
+pub struct Dump {
+    store: StoreDump,
+}
+
+impl Dump {
+    pub async fn load(path: &str) -> Result<Dump> {
+        let json = fs::read_to_string(path)
+            .await
+            .map_err(|e| anyhow!("could not read dump file {path}: {e}"))?;
+        let store =
+            StoreDump::parse(&json).map_err(|e| anyhow!("{path} is not a valid dump file: {e}"))?;
+        Ok(Dump { store })
+    }
+
+    pub fn get(&self, transaction_id: TransactionId, key: String) -> SM {
+        let value = parse_segments(&key).and_then(|path| {
+            self.store
+                .get(&path)
+                .cloned()
+                .ok_or_else(|| WorterbuchError::NoSuchValue(key.clone()))
+        });
+        match value {
+            Ok(value) => SM::State(State {
+                transaction_id,
+                event: StateEvent::KeyValue((key, value).into()),
+                deprecated: None,
+                seq: None,
+            }),
+            Err(e) => error(transaction_id, e),
+        }
+    }
+
+    pub fn pget(&self, transaction_id: TransactionId, pattern: String) -> SM {
+        match self.matches(&pattern) {
+            Ok(kvps) => SM::PState(PState {
+                transaction_id,
+                request_pattern: pattern,
+                event: PStateEvent::KeyValuePairs(kvps),
+                deprecated: None,
+                more: None,
+                seq: None,
+            }),
+            Err(e) => error(transaction_id, e),
+        }
+    }
+
+    pub fn pkeys(&self, transaction_id: TransactionId, pattern: String) -> SM {
+        match self.matches(&pattern) {
+            Ok(kvps) => SM::Keys(KeysState {
+                transaction_id,
+                keys: kvps.into_iter().map(|kvp| kvp.key).collect(),
+            }),
+            Err(e) => error(transaction_id, e),
+        }
+    }
+
+    pub fn ls(&self, transaction_id: TransactionId, parent: Option<String>) -> SM {
+        let path: Vec<&str> = parent.iter().flat_map(|p| p.split('/')).collect();
+        let children = self
+            .store
+            .ls(&path)
+            .ok_or_else(|| WorterbuchError::NoSuchValue(parent.unwrap_or_default()));
+        match children {
+            Ok(children) => SM::LsState(LsState {
+                transaction_id,
+                children,
+            }),
+            Err(e) => error(transaction_id, e),
+        }
+    }
+
+    fn matches(&self, pattern: &str) -> WorterbuchResult<Vec<KeyValuePair>> {
+        self.store.get_matches(pattern)
+    }
+}
+
+fn error(transaction_id: TransactionId, e: WorterbuchError) -> SM {
+    let metadata = match &e {
+        WorterbuchError::NoSuchValue(key) => format!("no value for key '{key}'"),
+        e => e.to_string(),
+    };
+    SM::Err(Err {
+        transaction_id,
+        error_code: ErrorCode::from(&e),
+        metadata: serde_json::Value::String(metadata).to_string(),
+    })
+}
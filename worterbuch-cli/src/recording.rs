@@ -0,0 +1,124 @@
+/*
+ *  Worterbuch cli recording module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use anyhow::{anyhow, Result};
+use tokio::{
+    fs::File,
+    io::{stdin, AsyncBufRead, AsyncBufReadExt, BufReader},
+    select, spawn,
+    sync::mpsc,
+    time::{sleep_until, Instant},
+};
+use tokio_graceful_shutdown::SubsystemHandle;
+use worterbuch_client::recording::RecordedEvent;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayOptions {
+    /// factor by which the replay is faster than the original
+    pub speed: f64,
+    /// start over from the beginning once the end of the recording is reached
+    pub repeat: bool,
+    /// drop recorded deletions, e.g. when values are only published
+    pub skip_deletions: bool,
+}
+
+/// Reads a recording from the given file or stdin and emits its events at their original pace,
+/// adjusted by the configured speed.
+pub fn provide_recording(
+    file: Option<String>,
+    options: ReplayOptions,
+    subsys: SubsystemHandle,
+) -> Result<mpsc::Receiver<RecordedEvent>> {
+    if options.speed <= 0.0 || !options.speed.is_finite() {
+        return Err(anyhow!("replay speed must be a positive number"));
+    }
+    if options.repeat && file.is_none() {
+        return Err(anyhow!("a recording read from stdin cannot be repeated"));
+    }
+
+    let (tx, rx) = mpsc::channel(1);
+
+    spawn(async move {
+        loop {
+            let res = match &file {
+                Some(path) => match File::open(path).await {
+                    Ok(file) => replay(BufReader::new(file), options, &tx, &subsys).await,
+                    Err(e) => {
+                        eprintln!("Could not open recording {path}: {e}");
+                        break;
+                    }
+                },
+                None => replay(BufReader::new(stdin()), options, &tx, &subsys).await,
+            };
+            if !res || !options.repeat {
+                break;
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+/// Returns `false` if the replay was interrupted.
+async fn replay(
+    input: impl AsyncBufRead + Unpin,
+    options: ReplayOptions,
+    tx: &mpsc::Sender<RecordedEvent>,
+    subsys: &SubsystemHandle,
+) -> bool {
+    let start = Instant::now();
+    let mut lines = input.lines();
+
+    loop {
+        let line = select! {
+            _ = subsys.on_shutdown_requested() => return false,
+            recv = lines.next_line() => match recv {
+                Ok(Some(line)) => line,
+                Ok(None) => return true,
+                Err(e) => {
+                    eprintln!("Error reading recording: {e}");
+                    return false;
+                }
+            },
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = match serde_json::from_str::<RecordedEvent>(&line) {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("Skipping invalid recorded event '{line}': {e}");
+                continue;
+            }
+        };
+        if options.skip_deletions && event.value.is_none() {
+            continue;
+        }
+
+        let due = start + event.due(options.speed);
+        select! {
+            _ = subsys.on_shutdown_requested() => return false,
+            _ = sleep_until(due) => (),
+        }
+
+        if tx.send(event).await.is_err() {
+            return false;
+        }
+    }
+}
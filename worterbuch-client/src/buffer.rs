@@ -132,7 +132,9 @@ impl SendBuffer {
 
     async fn do_publish_value(&self, key: Key, value: Value) -> ConnectionResult<()> {
         let (tx, rx) = oneshot::channel();
-        self.commands.send(Command::Publish(key, value, tx)).await?;
+        self.commands
+            .send(Command::Publish(key, value, None, None, tx))
+            .await?;
         rx.await.ok();
         Ok(())
     }
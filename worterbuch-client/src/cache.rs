@@ -0,0 +1,74 @@
+/*
+ *  Worterbuch client cache module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use tokio::sync::watch;
+use worterbuch_common::{Key, TransactionId};
+
+/// A local copy of a key's value created with
+/// [`Worterbuch::subscribe_cached`](crate::Worterbuch::subscribe_cached). A subscription keeps it
+/// up to date in the background, so the latest value can be read synchronously at any time.
+/// Clones share the same subscription, which is cancelled once the last clone is dropped.
+#[derive(Debug, Clone)]
+pub struct Cached<T> {
+    key: Key,
+    transaction_id: TransactionId,
+    rx: watch::Receiver<Option<T>>,
+}
+
+impl<T> Cached<T> {
+    pub(crate) fn new(
+        key: Key,
+        transaction_id: TransactionId,
+        rx: watch::Receiver<Option<T>>,
+    ) -> Self {
+        Self {
+            key,
+            transaction_id,
+            rx,
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn transaction_id(&self) -> TransactionId {
+        self.transaction_id
+    }
+
+    /// Borrows the latest value without cloning it. The cache cannot be updated while the
+    /// returned reference is held, so keep it short-lived.
+    pub fn borrow(&self) -> watch::Ref<'_, Option<T>> {
+        self.rx.borrow()
+    }
+
+    /// Waits until a new value has been received since the last call to this method. Fails once
+    /// the subscription has ended, e.g. because the connection was closed.
+    pub async fn changed(&mut self) -> Result<(), watch::error::RecvError> {
+        self.rx.changed().await
+    }
+}
+
+impl<T: Clone> Cached<T> {
+    /// Returns the latest value received for the key. `None` means that the key does not exist
+    /// or that its current value has not arrived yet.
+    pub fn get(&self) -> Option<T> {
+        self.rx.borrow().clone()
+    }
+}
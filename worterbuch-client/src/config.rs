@@ -17,17 +17,92 @@
  *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{env, time::Duration};
+use std::{
+    collections::hash_map::RandomState,
+    env,
+    hash::{BuildHasher, Hasher},
+    path::PathBuf,
+    time::Duration,
+};
+use worterbuch_common::{
+    wire::{Compression, WireFormat},
+    ClientInfo, Key,
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Config {
     pub proto: String,
     pub host_addr: String,
     pub port: u16,
+    /// Path of the server's websocket route, ignored for TCP connections.
+    pub ws_path: String,
+    pub ws_subprotocol: String,
+    /// Binary format to switch to after connecting over websocket, if the server supports it.
+    /// Stays with JSON otherwise.
+    pub wire_format: WireFormat,
+    /// Compression of binary frames to request along with a binary wire format, if the server
+    /// supports it. Worth it on slow links when messages carry large values.
+    pub wire_compression: Compression,
     pub keepalive_timeout: Duration,
     pub send_timeout: Duration,
     pub connection_timeout: Duration,
+    /// How long to wait for the server to answer a request before giving up with
+    /// [`ConnectionError::Timeout`](worterbuch_common::error::ConnectionError::Timeout).
+    pub request_timeout: Duration,
     pub auth_token: Option<String>,
+    /// Credentials for the server's built-in user database, used if no auth token is set.
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub subscription_registry: Option<PathBuf>,
+    pub key_prefix: Option<Key>,
+    /// Name, version and tags the client identifies itself with after connecting.
+    pub client_info: Option<ClientInfo>,
+    pub backoff: Backoff,
+    /// Re-establish subscriptions after reconnecting, delivering their current state again.
+    pub resubscribe: bool,
+}
+
+/// Controls how the client tries to re-establish a lost connection. Delays grow exponentially
+/// from `initial_delay` up to `max_delay`, each randomly shortened or prolonged by up to
+/// `jitter` (a fraction between 0 and 1) of its length. Setting `max_attempts` to 0 disables
+/// automatic reconnects.
+///
+/// Pending requests do not survive a reconnect, their receivers are closed when the connection
+/// is lost. The same goes for subscriptions, unless [`Config::resubscribe`] is set, which
+/// [`connect_with_retry`](crate::connect_with_retry) does implicitly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backoff {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: f64,
+    pub max_attempts: u32,
+}
+
+impl Backoff {
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self
+            .initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return delay;
+        }
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        delay.mul_f64(1.0 + jitter * (2.0 * random - 1.0))
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.2,
+            max_attempts: 0,
+        }
+    }
 }
 
 impl Config {
@@ -46,6 +121,26 @@ impl Config {
             }
         }
 
+        if let Ok(val) = env::var("WORTERBUCH_WS_PATH") {
+            self.ws_path = format!("/{}", val.trim_matches('/'));
+        }
+
+        if let Ok(val) = env::var("WORTERBUCH_WS_SUBPROTOCOL") {
+            self.ws_subprotocol = val;
+        }
+
+        if let Ok(val) = env::var("WORTERBUCH_WIRE_FORMAT") {
+            if let Ok(format) = val.parse() {
+                self.wire_format = format;
+            }
+        }
+
+        if let Ok(val) = env::var("WORTERBUCH_WIRE_COMPRESSION") {
+            if let Ok(compression) = val.parse() {
+                self.wire_compression = compression;
+            }
+        }
+
         if let Ok(val) = env::var("WORTERBUCH_KEEPALIVE_TIMEOUT") {
             if let Ok(secs) = val.parse() {
                 self.keepalive_timeout = Duration::from_secs(secs);
@@ -64,9 +159,76 @@ impl Config {
             }
         }
 
+        if let Ok(val) = env::var("WORTERBUCH_REQUEST_TIMEOUT") {
+            if let Ok(secs) = val.parse() {
+                self.request_timeout = Duration::from_secs(secs);
+            }
+        }
+
         if let Ok(val) = env::var("WORTERBUCH_AUTH_TOKEN") {
             self.auth_token = Some(val);
         }
+
+        if let Ok(val) = env::var("WORTERBUCH_USERNAME") {
+            self.username = Some(val);
+        }
+
+        if let Ok(val) = env::var("WORTERBUCH_PASSWORD") {
+            self.password = Some(val);
+        }
+
+        if let Ok(val) = env::var("WORTERBUCH_SUBSCRIPTION_REGISTRY") {
+            self.subscription_registry = Some(val.into());
+        }
+
+        if let Ok(val) = env::var("WORTERBUCH_KEY_PREFIX") {
+            self.key_prefix = Some(val);
+        }
+
+        if let Ok(val) = env::var("WORTERBUCH_CLIENT_NAME") {
+            self.client_info
+                .get_or_insert_with(ClientInfo::default)
+                .name = val;
+        }
+
+        if let Ok(val) = env::var("WORTERBUCH_CLIENT_TAGS") {
+            let tags = val
+                .split(',')
+                .filter_map(|tag| tag.split_once('='))
+                .map(|(k, v)| (k.trim().to_owned(), v.trim().to_owned()));
+            self.client_info
+                .get_or_insert_with(ClientInfo::default)
+                .tags
+                .extend(tags);
+        }
+
+        if let Ok(val) = env::var("WORTERBUCH_RECONNECT_INITIAL_DELAY") {
+            if let Ok(millis) = val.parse() {
+                self.backoff.initial_delay = Duration::from_millis(millis);
+            }
+        }
+
+        if let Ok(val) = env::var("WORTERBUCH_RECONNECT_MAX_DELAY") {
+            if let Ok(millis) = val.parse() {
+                self.backoff.max_delay = Duration::from_millis(millis);
+            }
+        }
+
+        if let Ok(val) = env::var("WORTERBUCH_RECONNECT_JITTER") {
+            if let Ok(jitter) = val.parse() {
+                self.backoff.jitter = jitter;
+            }
+        }
+
+        if let Ok(val) = env::var("WORTERBUCH_RECONNECT_MAX_ATTEMPTS") {
+            if let Ok(attempts) = val.parse() {
+                self.backoff.max_attempts = attempts;
+            }
+        }
+
+        if let Ok(val) = env::var("WORTERBUCH_RESUBSCRIBE") {
+            self.resubscribe = val.to_lowercase() == "true";
+        }
     }
 }
 
@@ -78,15 +240,28 @@ impl Default for Config {
         let keepalive_timeout = Duration::from_secs(5);
         let send_timeout = Duration::from_secs(5);
         let connection_timeout = Duration::from_secs(5);
+        let request_timeout = Duration::from_secs(30);
 
         Config {
             proto,
             host_addr,
             port,
+            ws_path: "/ws".to_owned(),
+            ws_subprotocol: "worterbuch".to_owned(),
+            wire_format: WireFormat::Json,
+            wire_compression: Compression::None,
             keepalive_timeout,
             send_timeout,
             connection_timeout,
+            request_timeout,
             auth_token: None,
+            username: None,
+            password: None,
+            subscription_registry: None,
+            key_prefix: None,
+            client_info: None,
+            backoff: Backoff::default(),
+            resubscribe: false,
         }
     }
 }
@@ -106,3 +281,40 @@ impl Config {
         config
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_max_delay() {
+        let backoff = Backoff {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            jitter: 0.0,
+            max_attempts: 10,
+        };
+
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(200));
+        assert_eq!(backoff.delay(4), Duration::from_millis(800));
+        assert_eq!(backoff.delay(5), Duration::from_millis(1000));
+        assert_eq!(backoff.delay(100), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn backoff_jitter_stays_within_bounds() {
+        let backoff = Backoff {
+            initial_delay: Duration::from_millis(1000),
+            max_delay: Duration::from_millis(1000),
+            jitter: 0.5,
+            max_attempts: 10,
+        };
+
+        for _ in 0..100 {
+            let delay = backoff.delay(1);
+            assert!(delay >= Duration::from_millis(500));
+            assert!(delay <= Duration::from_millis(1500));
+        }
+    }
+}
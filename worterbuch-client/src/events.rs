@@ -0,0 +1,123 @@
+/*
+ *  Worterbuch client connection events module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fmt;
+use tokio::sync::mpsc;
+use worterbuch_common::TransactionId;
+
+/// Changes of the client's connection state, delivered by [`crate::Worterbuch::events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    Connected,
+    Authenticated,
+    Disconnected(DisconnectReason),
+    Reconnecting(u32),
+    /// The server closed the connection, which it usually only does when it is shutting down.
+    ServerShutdown,
+    /// The given number of messages of the subscription with the given transaction ID got lost.
+    /// [`crate::Worterbuch::resync`] can be used to get its current state again.
+    MessagesMissed(TransactionId, u64),
+    /// The client reconnected to a server that has been restarted in the meantime.
+    ServerRestarted,
+    /// All subscriptions have been re-established after a reconnect and their current state has
+    /// been delivered again. Only emitted if [`crate::config::Config::resubscribe`] is set.
+    StateRefreshed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    ClosedByClient,
+    ClosedByServer,
+    KeepaliveTimeout,
+    Error(String),
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisconnectReason::ClosedByClient => write!(f, "connection closed by client"),
+            DisconnectReason::ClosedByServer => write!(f, "connection closed by server"),
+            DisconnectReason::KeepaliveTimeout => write!(f, "server keepalive timed out"),
+            DisconnectReason::Error(e) => write!(f, "connection error: {e}"),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct ConnectionEvents {
+    subscribers: Vec<mpsc::UnboundedSender<ConnectionEvent>>,
+    connected: bool,
+    authenticated: bool,
+}
+
+impl ConnectionEvents {
+    /// Registers a new subscriber and brings it up to date with the current connection state.
+    pub fn subscribe(&mut self, tx: mpsc::UnboundedSender<ConnectionEvent>) {
+        if self.connected && tx.send(ConnectionEvent::Connected).is_err() {
+            return;
+        }
+        if self.authenticated && tx.send(ConnectionEvent::Authenticated).is_err() {
+            return;
+        }
+        self.subscribers.push(tx);
+    }
+
+    pub fn emit(&mut self, event: ConnectionEvent) {
+        match &event {
+            ConnectionEvent::Connected => self.connected = true,
+            ConnectionEvent::Authenticated => self.authenticated = true,
+            ConnectionEvent::Disconnected(_) => {
+                self.connected = false;
+                self.authenticated = false;
+            }
+            ConnectionEvent::Reconnecting(_)
+            | ConnectionEvent::ServerShutdown
+            | ConnectionEvent::MessagesMissed(_, _)
+            | ConnectionEvent::ServerRestarted
+            | ConnectionEvent::StateRefreshed => (),
+        }
+        self.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn late_subscribers_receive_current_state() {
+        let mut events = ConnectionEvents::default();
+        events.emit(ConnectionEvent::Connected);
+        events.emit(ConnectionEvent::Authenticated);
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        events.subscribe(tx);
+        events.emit(ConnectionEvent::Disconnected(
+            DisconnectReason::ClosedByServer,
+        ));
+
+        assert_eq!(rx.try_recv().unwrap(), ConnectionEvent::Connected);
+        assert_eq!(rx.try_recv().unwrap(), ConnectionEvent::Authenticated);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            ConnectionEvent::Disconnected(DisconnectReason::ClosedByServer)
+        );
+        assert!(rx.try_recv().is_err());
+    }
+}
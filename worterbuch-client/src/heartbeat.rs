@@ -0,0 +1,100 @@
+/*
+ *  Worterbuch client heartbeat module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{events::ConnectionEvent, Worterbuch};
+use std::time::Duration;
+use tokio::{
+    select, spawn,
+    sync::mpsc,
+    task::JoinHandle,
+    time::{interval_at, Instant, MissedTickBehavior},
+};
+use worterbuch_common::{Key, Value};
+
+/// A running heartbeat started with [`Worterbuch::heartbeat`]. The heartbeat stops when this is
+/// dropped. The key is left as it is, so whoever watches it can tell the heartbeat stopped.
+pub struct Heartbeat {
+    key: Key,
+    task: JoinHandle<()>,
+}
+
+impl Heartbeat {
+    pub(crate) fn start(
+        wb: Worterbuch,
+        key: Key,
+        value: Value,
+        interval: Duration,
+        events: mpsc::UnboundedReceiver<ConnectionEvent>,
+    ) -> Self {
+        let task = spawn(run(wb, key.clone(), value, interval, events));
+        Self { key, task }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn stop(self) {}
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn run(
+    wb: Worterbuch,
+    key: Key,
+    value: Value,
+    period: Duration,
+    mut events: mpsc::UnboundedReceiver<ConnectionEvent>,
+) {
+    let mut ticker = interval_at(Instant::now() + period, period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // the event stream starts with the current connection state, which triggers the first beat
+    let mut connected = false;
+
+    loop {
+        select! {
+            _ = ticker.tick(), if connected => beat(&wb, &key, &value).await,
+            recv = events.recv() => match recv {
+                Some(ConnectionEvent::Connected | ConnectionEvent::Authenticated) => {
+                    connected = true;
+                    ticker.reset();
+                    beat(&wb, &key, &value).await;
+                }
+                Some(ConnectionEvent::Disconnected(_) | ConnectionEvent::Reconnecting(_)) => {
+                    connected = false;
+                }
+                Some(_) => (),
+                // the client has been closed
+                None => break,
+            },
+        }
+    }
+
+    log::debug!("Heartbeat of {key} stopped.");
+}
+
+async fn beat(wb: &Worterbuch, key: &Key, value: &Value) {
+    if let Err(e) = wb.set_generic(key.to_owned(), value.to_owned()).await {
+        log::debug!("Could not set heartbeat of {key}: {e}");
+    }
+}
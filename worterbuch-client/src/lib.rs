@@ -18,22 +18,39 @@
  */
 
 pub mod buffer;
+pub mod cache;
 pub mod config;
 pub mod error;
+pub mod events;
+pub mod heartbeat;
+pub mod metrics;
+pub mod pool;
+pub mod registry;
+pub mod replicated;
+mod session;
+pub mod sharding;
 pub mod tcp;
+pub mod tree;
 pub mod ws;
 
 use crate::config::Config;
 use buffer::SendBuffer;
+use cache::Cached;
 use error::SubscriptionError;
+use events::{ConnectionEvent, ConnectionEvents, DisconnectReason};
 use futures_util::{SinkExt, StreamExt};
+use heartbeat::Heartbeat;
+use metrics::RequestMetrics;
+use registry::SubscriptionRegistry;
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::{self as json};
+use session::Session;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     future::Future,
     io,
     ops::ControlFlow,
+    sync::Arc,
     time::{Duration, Instant},
 };
 use tcp::TcpClientSocket;
@@ -41,37 +58,95 @@ use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     net::TcpStream,
     select, spawn,
-    sync::{mpsc, oneshot},
+    sync::{mpsc, oneshot, watch},
     time::{interval, sleep, MissedTickBehavior},
 };
 use tokio_tungstenite::{
     connect_async_with_config,
     tungstenite::{handshake::client::generate_key, http::Request, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+use tree::FlattenRules;
+use worterbuch_common::{
+    error::WorterbuchError,
+    wire::{Compression, WireFormat},
 };
-use worterbuch_common::error::WorterbuchError;
 use ws::WsClientSocket;
 
 pub use worterbuch_common::*;
 pub use worterbuch_common::{
     self,
     error::{ConnectionError, ConnectionResult},
-    Ack, AuthorizationRequest, ClientMessage as CM, Delete, Err, Get, GraveGoods, Key,
-    KeyValuePairs, LastWill, LsState, PState, PStateEvent, ProtocolVersion, RegularKeySegment,
-    ServerMessage as SM, Set, State, StateEvent, TransactionId,
+    Ack, AuthenticationRequest, AuthorizationRequest, ClientMessage as CM, Delete, Err, Get,
+    GraveGoods, Key, KeyValuePairs, LastWill, LsState, PState, PStateEvent, ProtocolVersion,
+    RegularKeySegment, ServerMessage as SM, Set, State, StateEvent, TransactionId,
 };
 
 #[derive(Debug)]
 pub(crate) enum Command {
     Set(Key, Value, oneshot::Sender<TransactionId>),
-    Publish(Key, Value, oneshot::Sender<TransactionId>),
+    SetMany(KeyValuePairs, oneshot::Sender<TransactionId>),
+    SetIf(Key, Value, bool, oneshot::Sender<Result<(), Err>>),
+    GetAndSet(Key, Value, oneshot::Sender<Result<Option<Value>, Err>>),
+    GetAt(Key, String, oneshot::Sender<Result<Value, Err>>),
+    SetAt(Key, String, Value, oneshot::Sender<Result<(), Err>>),
+    Increment(Key, json::Number, oneshot::Sender<Result<Value, Err>>),
+    ArrayPush(Key, Value, oneshot::Sender<Result<Value, Err>>),
+    ArrayPop(Key, oneshot::Sender<Result<Value, Err>>),
+    ArrayRemove(Key, Value, oneshot::Sender<Result<Value, Err>>),
+    SetExpiring(Key, Value, Duration, oneshot::Sender<TransactionId>),
+    Expire(Key, Option<Duration>, oneshot::Sender<TransactionId>),
+    Cas(
+        Key,
+        Option<Value>,
+        Option<u64>,
+        Value,
+        oneshot::Sender<Result<(), Err>>,
+    ),
+    HintInvalidation(Key, Option<Duration>, oneshot::Sender<TransactionId>),
+    Publish(
+        Key,
+        Value,
+        Option<CorrelationId>,
+        Option<Key>,
+        oneshot::Sender<TransactionId>,
+    ),
+    PublishRetained(Key, Value, oneshot::Sender<TransactionId>),
     Get(Key, oneshot::Sender<(Option<Value>, TransactionId)>),
     GetAsync(Key, oneshot::Sender<TransactionId>),
     PGet(Key, oneshot::Sender<(KeyValuePairs, TransactionId)>),
     PGetAsync(Key, oneshot::Sender<TransactionId>),
+    PGetSorted(
+        Key,
+        Sort,
+        Option<usize>,
+        Option<usize>,
+        oneshot::Sender<(KeyValuePairs, TransactionId)>,
+    ),
+    PGetIgnoringCase(Key, oneshot::Sender<(KeyValuePairs, TransactionId)>),
+    PKeys(Key, oneshot::Sender<Result<Vec<Key>, Err>>),
+    PKeysAsync(Key, oneshot::Sender<TransactionId>),
+    PGetChunked(
+        Key,
+        usize,
+        oneshot::Sender<TransactionId>,
+        mpsc::UnboundedSender<KeyValuePairs>,
+    ),
     Delete(Key, oneshot::Sender<(Option<Value>, TransactionId)>),
     DeleteAsync(Key, oneshot::Sender<TransactionId>),
     PDelete(Key, oneshot::Sender<(KeyValuePairs, TransactionId)>),
     PDeleteAsync(Key, oneshot::Sender<TransactionId>),
+    Reserve(RequestPattern, oneshot::Sender<Result<(), Err>>),
+    Release(RequestPattern, oneshot::Sender<Result<(), Err>>),
+    Lock(Key, Duration, bool, oneshot::Sender<Result<(), Err>>),
+    Unlock(Key, oneshot::Sender<Result<(), Err>>),
+    Restore(
+        RequestPattern,
+        oneshot::Sender<(KeyValuePairs, TransactionId)>,
+    ),
+    StartRecording(String, RequestPattern, oneshot::Sender<TransactionId>),
+    StopRecording(String, oneshot::Sender<TransactionId>),
+    ReplayRecording(String, Key, Option<u32>, oneshot::Sender<TransactionId>),
     Ls(
         Option<Key>,
         oneshot::Sender<(Vec<RegularKeySegment>, TransactionId)>,
@@ -83,6 +158,7 @@ pub(crate) enum Command {
         oneshot::Sender<TransactionId>,
         mpsc::UnboundedSender<(Option<Value>, Key)>,
         LiveOnlyFlag,
+        Option<oneshot::Sender<()>>,
     ),
     SubscribeAsync(
         Key,
@@ -97,6 +173,7 @@ pub(crate) enum Command {
         mpsc::UnboundedSender<PStateEvent>,
         Option<u64>,
         LiveOnlyFlag,
+        Option<oneshot::Sender<()>>,
     ),
     PSubscribeAsync(
         Key,
@@ -106,6 +183,9 @@ pub(crate) enum Command {
         LiveOnlyFlag,
     ),
     Unsubscribe(TransactionId),
+    Resync(TransactionId),
+    Sync(Option<bool>, oneshot::Sender<Result<(), Err>>),
+    Cancel(TransactionId),
     SubscribeLs(
         Option<Key>,
         oneshot::Sender<TransactionId>,
@@ -113,9 +193,89 @@ pub(crate) enum Command {
     ),
     SubscribeLsAsync(Option<Key>, oneshot::Sender<TransactionId>),
     UnsubscribeLs(TransactionId),
+    SubscribeEvents(
+        oneshot::Sender<TransactionId>,
+        mpsc::UnboundedSender<ServerEvent>,
+    ),
     AllMessages(mpsc::UnboundedSender<ServerMessage>),
+    ConnectionEvents(mpsc::UnboundedSender<ConnectionEvent>),
+    WhoSubscribes(
+        RequestPattern,
+        oneshot::Sender<(Vec<SubscriberInfo>, TransactionId)>,
+    ),
+    GetHistory(
+        Key,
+        Option<usize>,
+        oneshot::Sender<(Vec<HistoryEntry>, TransactionId)>,
+    ),
+}
+
+impl Command {
+    /// The name of the command for logging, commands are never logged with their keys and values
+    /// since those may be sensitive.
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Set(..) => "Set",
+            Command::SetMany(..) => "SetMany",
+            Command::SetIf(..) => "SetIf",
+            Command::GetAndSet(..) => "GetAndSet",
+            Command::GetAt(..) => "GetAt",
+            Command::SetAt(..) => "SetAt",
+            Command::Increment(..) => "Increment",
+            Command::ArrayPush(..) => "ArrayPush",
+            Command::ArrayPop(..) => "ArrayPop",
+            Command::ArrayRemove(..) => "ArrayRemove",
+            Command::SetExpiring(..) => "SetExpiring",
+            Command::Expire(..) => "Expire",
+            Command::Cas(..) => "Cas",
+            Command::HintInvalidation(..) => "HintInvalidation",
+            Command::Publish(..) => "Publish",
+            Command::PublishRetained(..) => "PublishRetained",
+            Command::Get(..) => "Get",
+            Command::GetAsync(..) => "GetAsync",
+            Command::PGet(..) => "PGet",
+            Command::PGetAsync(..) => "PGetAsync",
+            Command::PGetSorted(..) => "PGetSorted",
+            Command::PGetIgnoringCase(..) => "PGetIgnoringCase",
+            Command::PKeys(..) => "PKeys",
+            Command::PKeysAsync(..) => "PKeysAsync",
+            Command::PGetChunked(..) => "PGetChunked",
+            Command::Delete(..) => "Delete",
+            Command::DeleteAsync(..) => "DeleteAsync",
+            Command::PDelete(..) => "PDelete",
+            Command::PDeleteAsync(..) => "PDeleteAsync",
+            Command::Reserve(..) => "Reserve",
+            Command::Release(..) => "Release",
+            Command::Lock(..) => "Lock",
+            Command::Unlock(..) => "Unlock",
+            Command::Restore(..) => "Restore",
+            Command::StartRecording(..) => "StartRecording",
+            Command::StopRecording(..) => "StopRecording",
+            Command::ReplayRecording(..) => "ReplayRecording",
+            Command::Ls(..) => "Ls",
+            Command::LsAsync(..) => "LsAsync",
+            Command::Subscribe(..) => "Subscribe",
+            Command::SubscribeAsync(..) => "SubscribeAsync",
+            Command::PSubscribe(..) => "PSubscribe",
+            Command::PSubscribeAsync(..) => "PSubscribeAsync",
+            Command::Unsubscribe(..) => "Unsubscribe",
+            Command::Resync(..) => "Resync",
+            Command::Sync(..) => "Sync",
+            Command::Cancel(..) => "Cancel",
+            Command::SubscribeLs(..) => "SubscribeLs",
+            Command::SubscribeLsAsync(..) => "SubscribeLsAsync",
+            Command::UnsubscribeLs(..) => "UnsubscribeLs",
+            Command::SubscribeEvents(..) => "SubscribeEvents",
+            Command::AllMessages(..) => "AllMessages",
+            Command::ConnectionEvents(..) => "ConnectionEvents",
+            Command::WhoSubscribes(..) => "WhoSubscribes",
+            Command::GetHistory(..) => "GetHistory",
+        }
+    }
 }
 
+// a client holds a single socket, boxing the websocket would not save anything
+#[allow(clippy::large_enum_variant)]
 enum ClientSocket {
     Tcp(TcpClientSocket),
     Ws(WsClientSocket),
@@ -137,19 +297,74 @@ impl ClientSocket {
     }
 }
 
+/// An item of a subscription stream that was requested with an initial state marker.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubscriptionEvent<E> {
+    Event(E),
+    /// Everything that was stored before the subscription was made has been delivered.
+    InitialStateComplete,
+}
+
 #[derive(Clone)]
 pub struct Worterbuch {
     commands: mpsc::Sender<Command>,
     stop: mpsc::Sender<()>,
     client_id: String,
+    registry: Option<SubscriptionRegistry>,
+    session: Option<Arc<Session>>,
+    request_timeout: Duration,
 }
 
 impl Worterbuch {
-    fn new(commands: mpsc::Sender<Command>, stop: mpsc::Sender<()>, client_id: String) -> Self {
+    fn new(
+        commands: mpsc::Sender<Command>,
+        stop: mpsc::Sender<()>,
+        client_id: String,
+        registry: Option<SubscriptionRegistry>,
+        request_timeout: Duration,
+    ) -> Self {
         Self {
             commands,
             stop,
             client_id,
+            registry,
+            session: None,
+            request_timeout,
+        }
+    }
+
+    /// Opens a logical session on this client's connection, so independent parts of an
+    /// application can share one socket instead of each opening their own.
+    ///
+    /// A session has its own set of subscriptions: it can only cancel subscriptions it made
+    /// itself, and closing it (or dropping its last handle) cancels all of them while the
+    /// connection and all other sessions stay untouched. Transaction IDs are unique across the
+    /// whole connection, so sessions never see each other's IDs.
+    pub fn session(&self) -> Worterbuch {
+        Self {
+            session: Some(Arc::new(Session::new(self.commands.clone()))),
+            ..self.clone()
+        }
+    }
+
+    /// Transaction IDs of the subscriptions held by this session. Always empty for the
+    /// connection's root handle, which does not track its subscriptions.
+    pub fn session_subscriptions(&self) -> Vec<TransactionId> {
+        self.session
+            .as_ref()
+            .map(|s| s.subscriptions())
+            .unwrap_or_default()
+    }
+
+    fn track_subscription(&self, transaction_id: TransactionId) {
+        if let Some(session) = &self.session {
+            session.track(transaction_id);
+        }
+    }
+
+    fn track_ls_subscription(&self, transaction_id: TransactionId) {
+        if let Some(session) = &self.session {
+            session.track_ls(transaction_id);
         }
     }
 
@@ -188,7 +403,7 @@ impl Worterbuch {
     pub async fn set_generic(&self, key: Key, value: Value) -> ConnectionResult<TransactionId> {
         let (tx, rx) = oneshot::channel();
         let cmd = Command::Set(key, value, tx);
-        log::debug!("Queuing command {cmd:?}");
+        log::debug!("Queuing command {}", cmd.name());
         self.commands.send(cmd).await?;
         log::debug!("Command queued.");
         let transaction_id = rx.await?;
@@ -200,216 +415,1189 @@ impl Worterbuch {
         self.set_generic(key, value).await
     }
 
-    pub async fn publish_generic(&self, key: Key, value: Value) -> ConnectionResult<TransactionId> {
+    /// Set several values in a single request, which the server stores as one operation.
+    pub async fn set_many_generic(
+        &self,
+        key_value_pairs: KeyValuePairs,
+    ) -> ConnectionResult<TransactionId> {
         let (tx, rx) = oneshot::channel();
-        let cmd = Command::Publish(key, value, tx);
-        log::debug!("Queuing command {cmd:?}");
+        let cmd = Command::SetMany(key_value_pairs, tx);
+        log::debug!("Queuing command {}", cmd.name());
         self.commands.send(cmd).await?;
         log::debug!("Command queued.");
         let transaction_id = rx.await?;
         Ok(transaction_id)
     }
 
-    pub async fn publish<T: Serialize>(
+    pub async fn set_many<T: Serialize>(
         &self,
-        key: Key,
-        value: &T,
+        values: Vec<(Key, T)>,
     ) -> ConnectionResult<TransactionId> {
-        let value = json::to_value(value)?;
-        self.publish_generic(key, value).await
+        let mut key_value_pairs = KeyValuePairs::with_capacity(values.len());
+        for (key, value) in values {
+            key_value_pairs.push((key, json::to_value(value)?).into());
+        }
+        self.set_many_generic(key_value_pairs).await
     }
 
-    pub async fn get_async(&self, key: Key) -> ConnectionResult<TransactionId> {
+    /// Set a value and return the one the key had before, if any, in a single round trip.
+    pub async fn get_and_set_generic(
+        &self,
+        key: Key,
+        value: Value,
+    ) -> ConnectionResult<Option<Value>> {
         let (tx, rx) = oneshot::channel();
-        let cmd = Command::GetAsync(key, tx);
-        log::debug!("Queuing command {cmd:?}");
+        let cmd = Command::GetAndSet(key, value, tx);
+        log::debug!("Queuing command {}", cmd.name());
         self.commands.send(cmd).await?;
         log::debug!("Command queued.");
-        let res = rx.await?;
-        Ok(res)
+        match await_response(rx, self.request_timeout).await? {
+            Ok(previous) => Ok(previous),
+            Err(err) => Err(ConnectionError::WorterbuchError(
+                WorterbuchError::ServerResponse(err),
+            )),
+        }
     }
 
-    pub async fn get_generic(&self, key: Key) -> ConnectionResult<(Option<Value>, TransactionId)> {
+    pub async fn get_and_set<T: Serialize + DeserializeOwned>(
+        &self,
+        key: Key,
+        value: &T,
+    ) -> ConnectionResult<Option<T>> {
+        let value = json::to_value(value)?;
+        Ok(match self.get_and_set_generic(key, value).await? {
+            Some(previous) => Some(json::from_value(previous)?),
+            None => None,
+        })
+    }
+
+    /// Replaces only the part of a value addressed by a JSON pointer (RFC 6901), e.g. `/a/b/0`.
+    /// The parent of the addressed location must exist, a final `-` appends to an array.
+    pub async fn set_at_generic(
+        &self,
+        key: Key,
+        pointer: String,
+        value: Value,
+    ) -> ConnectionResult<()> {
         let (tx, rx) = oneshot::channel();
-        let cmd = Command::Get(key, tx);
-        log::debug!("Queuing command {cmd:?}");
+        let cmd = Command::SetAt(key, pointer, value, tx);
+        log::debug!("Queuing command {}", cmd.name());
         self.commands.send(cmd).await?;
         log::debug!("Command queued.");
-        let res = rx.await?;
-        Ok(res)
+        match await_response(rx, self.request_timeout).await? {
+            Ok(()) => Ok(()),
+            Err(err) => Err(ConnectionError::WorterbuchError(
+                WorterbuchError::ServerResponse(err),
+            )),
+        }
     }
 
-    pub async fn get<T: DeserializeOwned>(
+    pub async fn set_at<T: Serialize>(
         &self,
         key: Key,
-    ) -> ConnectionResult<(Option<T>, TransactionId)> {
-        Ok(match self.get_generic(key).await? {
-            (Some(val), tid) => (Some(json::from_value(val)?), tid),
-            (None, tid) => (None, tid),
-        })
+        pointer: String,
+        value: &T,
+    ) -> ConnectionResult<()> {
+        let value = json::to_value(value)?;
+        self.set_at_generic(key, pointer, value).await
     }
 
-    pub async fn pget_async(&self, key: Key) -> ConnectionResult<TransactionId> {
+    /// Atomically add `delta` to a numeric value and return the new value. A missing value counts
+    /// as `0`, a value that is not a number is rejected with a `NotANumber` error.
+    pub async fn increment_generic(
+        &self,
+        key: Key,
+        delta: json::Number,
+    ) -> ConnectionResult<Value> {
         let (tx, rx) = oneshot::channel();
-        let cmd = Command::PGetAsync(key, tx);
-        log::debug!("Queuing command {cmd:?}");
+        let cmd = Command::Increment(key, delta, tx);
+        log::debug!("Queuing command {}", cmd.name());
         self.commands.send(cmd).await?;
         log::debug!("Command queued.");
-        let tid = rx.await?;
-        Ok(tid)
+        match await_response(rx, self.request_timeout).await? {
+            Ok(value) => Ok(value),
+            Err(err) => Err(ConnectionError::WorterbuchError(
+                WorterbuchError::ServerResponse(err),
+            )),
+        }
     }
 
-    pub async fn pget_generic(&self, key: Key) -> ConnectionResult<(KeyValuePairs, TransactionId)> {
+    /// Like [`Worterbuch::increment_generic`] for integer counters.
+    pub async fn increment(&self, key: Key, delta: i64) -> ConnectionResult<i64> {
+        let value = self.increment_generic(key, delta.into()).await?;
+        Ok(json::from_value(value)?)
+    }
+
+    /// Append a value to the array stored at a key, creating the array if the key does not exist
+    /// yet, and return the resulting array. Concurrent modifications of the same array never get
+    /// lost, since the server applies them one after the other.
+    pub async fn array_push_generic(&self, key: Key, value: Value) -> ConnectionResult<Vec<Value>> {
         let (tx, rx) = oneshot::channel();
-        let cmd = Command::PGet(key, tx);
-        log::debug!("Queuing command {cmd:?}");
-        self.commands.send(cmd).await?;
-        log::debug!("Command queued.");
-        let (kvps, tid) = rx.await?;
-        Ok((kvps, tid))
+        self.update_array(Command::ArrayPush(key, value, tx), rx)
+            .await
     }
 
-    pub async fn pget<T: DeserializeOwned>(
+    pub async fn array_push<T: Serialize + DeserializeOwned>(
         &self,
         key: Key,
-    ) -> ConnectionResult<(TypedKeyValuePairs<T>, TransactionId)> {
-        let (kvps, tid) = self.pget_generic(key).await?;
-        let typed_kvps = deserialize_key_value_pairs(kvps)?;
-        Ok((typed_kvps, tid))
+        value: &T,
+    ) -> ConnectionResult<Vec<T>> {
+        let (tx, rx) = oneshot::channel();
+        let value = json::to_value(value)?;
+        self.update_array(Command::ArrayPush(key, value, tx), rx)
+            .await
     }
 
-    pub async fn delete_async(&self, key: Key) -> ConnectionResult<TransactionId> {
+    /// Remove the last element of the array stored at a key and return the resulting array.
+    pub async fn array_pop_generic(&self, key: Key) -> ConnectionResult<Vec<Value>> {
         let (tx, rx) = oneshot::channel();
-        let cmd = Command::DeleteAsync(key, tx);
-        log::debug!("Queuing command {cmd:?}");
+        self.update_array(Command::ArrayPop(key, tx), rx).await
+    }
+
+    pub async fn array_pop<T: DeserializeOwned>(&self, key: Key) -> ConnectionResult<Vec<T>> {
+        let (tx, rx) = oneshot::channel();
+        self.update_array(Command::ArrayPop(key, tx), rx).await
+    }
+
+    /// Remove all elements equal to `value` from the array stored at a key and return the
+    /// resulting array.
+    pub async fn array_remove_generic(
+        &self,
+        key: Key,
+        value: Value,
+    ) -> ConnectionResult<Vec<Value>> {
+        let (tx, rx) = oneshot::channel();
+        self.update_array(Command::ArrayRemove(key, value, tx), rx)
+            .await
+    }
+
+    pub async fn array_remove<T: Serialize + DeserializeOwned>(
+        &self,
+        key: Key,
+        value: &T,
+    ) -> ConnectionResult<Vec<T>> {
+        let (tx, rx) = oneshot::channel();
+        let value = json::to_value(value)?;
+        self.update_array(Command::ArrayRemove(key, value, tx), rx)
+            .await
+    }
+
+    async fn update_array<T: DeserializeOwned>(
+        &self,
+        cmd: Command,
+        rx: oneshot::Receiver<Result<Value, Err>>,
+    ) -> ConnectionResult<Vec<T>> {
+        log::debug!("Queuing command {}", cmd.name());
         self.commands.send(cmd).await?;
         log::debug!("Command queued.");
-        let tid = rx.await?;
-        Ok(tid)
+        match await_response(rx, self.request_timeout).await? {
+            Ok(value) => Ok(json::from_value(value)?),
+            Err(err) => Err(ConnectionError::WorterbuchError(
+                WorterbuchError::ServerResponse(err),
+            )),
+        }
     }
 
-    pub async fn delete_generic(
+    /// Set a value that the server deletes again after `ttl`, unless it is set again before.
+    pub async fn set_expiring_generic(
         &self,
         key: Key,
-    ) -> ConnectionResult<(Option<Value>, TransactionId)> {
+        value: Value,
+        ttl: Duration,
+    ) -> ConnectionResult<TransactionId> {
         let (tx, rx) = oneshot::channel();
-        let cmd = Command::Delete(key, tx);
-        log::debug!("Queuing command {cmd:?}");
+        let cmd = Command::SetExpiring(key, value, ttl, tx);
+        log::debug!("Queuing command {}", cmd.name());
         self.commands.send(cmd).await?;
         log::debug!("Command queued.");
-        match rx.await? {
-            (Some(value), tid) => Ok((Some(value), tid)),
-            (None, tid) => Ok((None, tid)),
-        }
+        let transaction_id = rx.await?;
+        Ok(transaction_id)
     }
 
-    pub async fn delete<T: DeserializeOwned>(
+    pub async fn set_expiring<T: Serialize>(
         &self,
         key: Key,
-    ) -> ConnectionResult<(Option<T>, TransactionId)> {
-        Ok(match self.delete_generic(key).await? {
-            (Some(val), tid) => (Some(json::from_value(val)?), tid),
-            (None, tid) => (None, tid),
-        })
+        value: &T,
+        ttl: Duration,
+    ) -> ConnectionResult<TransactionId> {
+        let value = json::to_value(value)?;
+        self.set_expiring_generic(key, value, ttl).await
     }
 
-    pub async fn pdelete_async(&self, key: Key) -> ConnectionResult<TransactionId> {
+    /// Change the TTL of an existing value. Passing `None` makes it persist until deleted.
+    pub async fn expire(&self, key: Key, ttl: Option<Duration>) -> ConnectionResult<TransactionId> {
         let (tx, rx) = oneshot::channel();
-        let cmd = Command::PDeleteAsync(key, tx);
-        log::debug!("Queuing command {cmd:?}");
+        let cmd = Command::Expire(key, ttl, tx);
+        log::debug!("Queuing command {}", cmd.name());
         self.commands.send(cmd).await?;
         log::debug!("Command queued.");
-        let tid = rx.await?;
-        Ok(tid)
+        let transaction_id = rx.await?;
+        Ok(transaction_id)
     }
 
-    pub async fn pdelete_generic(
+    /// Set a value only if the key's current value equals `expected`, where `None` means that the
+    /// key must not exist yet. If the swap is rejected, the key's current value and version are
+    /// returned instead.
+    pub async fn cas_generic(
         &self,
         key: Key,
-    ) -> ConnectionResult<(KeyValuePairs, TransactionId)> {
+        expected: Option<Value>,
+        value: Value,
+    ) -> ConnectionResult<Result<(), CasConflict>> {
+        self.compare_and_swap(key, expected, None, value).await
+    }
+
+    pub async fn cas<T: Serialize>(
+        &self,
+        key: Key,
+        expected: Option<&T>,
+        value: &T,
+    ) -> ConnectionResult<Result<(), CasConflict>> {
+        let expected = expected.map(json::to_value).transpose()?;
+        let value = json::to_value(value)?;
+        self.cas_generic(key, expected, value).await
+    }
+
+    /// Like [`Worterbuch::cas_generic`], but compares the key's version instead of its value.
+    /// Version 0 means that the key must not exist yet.
+    pub async fn cas_version_generic(
+        &self,
+        key: Key,
+        version: u64,
+        value: Value,
+    ) -> ConnectionResult<Result<(), CasConflict>> {
+        self.compare_and_swap(key, None, Some(version), value).await
+    }
+
+    pub async fn cas_version<T: Serialize>(
+        &self,
+        key: Key,
+        version: u64,
+        value: &T,
+    ) -> ConnectionResult<Result<(), CasConflict>> {
+        let value = json::to_value(value)?;
+        self.cas_version_generic(key, version, value).await
+    }
+
+    /// Set a value only if the key does not exist yet. Returns `false` without touching the key
+    /// if it already exists, so a key can be claimed by exactly one client.
+    pub async fn set_nx_generic(&self, key: Key, value: Value) -> ConnectionResult<bool> {
+        self.set_if(key, value, false).await
+    }
+
+    pub async fn set_nx<T: Serialize>(&self, key: Key, value: &T) -> ConnectionResult<bool> {
+        let value = json::to_value(value)?;
+        self.set_nx_generic(key, value).await
+    }
+
+    /// Set a value only if the key already exists. Returns `false` without creating the key if it
+    /// does not.
+    pub async fn set_xx_generic(&self, key: Key, value: Value) -> ConnectionResult<bool> {
+        self.set_if(key, value, true).await
+    }
+
+    pub async fn set_xx<T: Serialize>(&self, key: Key, value: &T) -> ConnectionResult<bool> {
+        let value = json::to_value(value)?;
+        self.set_xx_generic(key, value).await
+    }
+
+    async fn set_if(&self, key: Key, value: Value, exists: bool) -> ConnectionResult<bool> {
         let (tx, rx) = oneshot::channel();
-        let cmd = Command::PDelete(key, tx);
-        log::debug!("Queuing command {cmd:?}");
+        let cmd = Command::SetIf(key, value, exists, tx);
+        log::debug!("Queuing command {}", cmd.name());
         self.commands.send(cmd).await?;
         log::debug!("Command queued.");
-        let (kvps, tid) = rx.await?;
-        Ok((kvps, tid))
+        let rejected = if exists {
+            ErrorCode::NoSuchValue
+        } else {
+            ErrorCode::KeyExists
+        };
+        match await_response(rx, self.request_timeout).await? {
+            Ok(()) => Ok(true),
+            Err(err) if err.error_code == rejected => Ok(false),
+            Err(err) => Err(ConnectionError::WorterbuchError(
+                WorterbuchError::ServerResponse(err),
+            )),
+        }
     }
 
-    pub async fn pdelete<T: DeserializeOwned>(
+    async fn compare_and_swap(
         &self,
         key: Key,
-    ) -> ConnectionResult<(TypedKeyValuePairs<T>, TransactionId)> {
-        let (kvps, tid) = self.pdelete_generic(key).await?;
-        let typed_kvps = deserialize_key_value_pairs(kvps)?;
-        Ok((typed_kvps, tid))
+        expected: Option<Value>,
+        version: Option<u64>,
+        value: Value,
+    ) -> ConnectionResult<Result<(), CasConflict>> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Cas(key, expected, version, value, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        match await_response(rx, self.request_timeout).await? {
+            Ok(()) => Ok(Ok(())),
+            Err(err) if err.error_code == ErrorCode::CasConflict => {
+                Ok(Err(json::from_str(&err.metadata)?))
+            }
+            Err(err) => Err(ConnectionError::WorterbuchError(
+                WorterbuchError::ServerResponse(err),
+            )),
+        }
     }
 
-    pub async fn ls_async(&self, parent: Option<Key>) -> ConnectionResult<TransactionId> {
+    /// Let caches know that the value of `key` is about to change, optionally within `stale_in`.
+    /// The hint is delivered to all clients that [subscribed to server
+    /// events](Self::subscribe_server_events).
+    pub async fn hint_invalidation(
+        &self,
+        key: Key,
+        stale_in: Option<Duration>,
+    ) -> ConnectionResult<TransactionId> {
         let (tx, rx) = oneshot::channel();
-        let cmd = Command::LsAsync(parent, tx);
-        log::debug!("Queuing command {cmd:?}");
+        let cmd = Command::HintInvalidation(key, stale_in, tx);
+        log::debug!("Queuing command {}", cmd.name());
         self.commands.send(cmd).await?;
         log::debug!("Command queued.");
-        let tid = rx.await?;
-        Ok(tid)
+        let transaction_id = rx.await?;
+        Ok(transaction_id)
     }
 
-    pub async fn ls(
+    pub async fn publish_generic(&self, key: Key, value: Value) -> ConnectionResult<TransactionId> {
+        self.publish_correlated(key, value, None, None).await
+    }
+
+    pub async fn publish_correlated(
         &self,
-        parent: Option<Key>,
-    ) -> ConnectionResult<(Vec<RegularKeySegment>, TransactionId)> {
+        key: Key,
+        value: Value,
+        correlation_id: Option<CorrelationId>,
+        reply_to: Option<Key>,
+    ) -> ConnectionResult<TransactionId> {
         let (tx, rx) = oneshot::channel();
-        let cmd = Command::Ls(parent, tx);
-        log::debug!("Queuing command {cmd:?}");
+        let cmd = Command::Publish(key, value, correlation_id, reply_to, tx);
+        log::debug!("Queuing command {}", cmd.name());
         self.commands.send(cmd).await?;
         log::debug!("Command queued.");
-        let children = rx.await?;
-        Ok(children)
+        let transaction_id = rx.await?;
+        Ok(transaction_id)
     }
 
-    pub async fn subscribe_async(
+    pub async fn publish<T: Serialize>(
         &self,
         key: Key,
-        unique: bool,
-        live_only: bool,
+        value: &T,
+    ) -> ConnectionResult<TransactionId> {
+        let value = json::to_value(value)?;
+        self.publish_generic(key, value).await
+    }
+
+    /// Set a value like [`Worterbuch::set_generic`], except that the server only notifies
+    /// subscribers if it differs from the stored one, so repeatedly publishing the same state
+    /// does not cause redundant updates.
+    pub async fn publish_retained_generic(
+        &self,
+        key: Key,
+        value: Value,
     ) -> ConnectionResult<TransactionId> {
         let (tx, rx) = oneshot::channel();
-        self.commands
-            .send(Command::SubscribeAsync(key, unique, tx, live_only))
-            .await?;
-        let tid = rx.await?;
-        Ok(tid)
+        let cmd = Command::PublishRetained(key, value, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let transaction_id = rx.await?;
+        Ok(transaction_id)
     }
 
-    pub async fn subscribe_generic(
+    pub async fn publish_retained<T: Serialize>(
         &self,
         key: Key,
-        unique: bool,
-        live_only: bool,
-    ) -> ConnectionResult<(mpsc::UnboundedReceiver<(Option<Value>, Key)>, TransactionId)> {
-        let (tid_tx, tid_rx) = oneshot::channel();
-        let (val_tx, val_rx) = mpsc::unbounded_channel();
-        self.commands
-            .send(Command::Subscribe(key, unique, tid_tx, val_tx, live_only))
-            .await?;
-        let transaction_id = tid_rx.await?;
-        Ok((val_rx, transaction_id))
+        value: &T,
+    ) -> ConnectionResult<TransactionId> {
+        let value = json::to_value(value)?;
+        self.publish_retained_generic(key, value).await
     }
 
-    pub async fn subscribe<T: DeserializeOwned + Send + 'static>(
+    /// Publish a request to the given key and wait for a correlated reply.
+    ///
+    /// Responders subscribe to the key, receive a [`CorrelatedValue`] and answer using [`Worterbuch::reply`].
+    /// If no reply arrives within `timeout`, [`ConnectionError::Timeout`] is returned.
+    pub async fn request_generic(
         &self,
         key: Key,
-        unique: bool,
-        live_only: bool,
-    ) -> ConnectionResult<(mpsc::UnboundedReceiver<Option<T>>, TransactionId)> {
-        let (val_rx, transaction_id) = self.subscribe_generic(key, unique, live_only).await?;
-        let (typed_val_tx, typed_val_rx) = mpsc::unbounded_channel();
-        spawn(deserialize_values(val_rx, typed_val_tx));
-        Ok((typed_val_rx, transaction_id))
+        value: Value,
+        timeout: Duration,
+    ) -> ConnectionResult<Value> {
+        let reply_to = topic!(key, "reply", self.client_id);
+        let (mut replies, transaction_id) = self
+            .subscribe_generic(reply_to.clone(), false, true)
+            .await?;
+        let correlation_id = format!("{}-{}", self.client_id, transaction_id);
+
+        let res = match self
+            .publish_correlated(key, value, Some(correlation_id.clone()), Some(reply_to))
+            .await
+        {
+            Ok(_) => select! {
+                reply = await_reply(&mut replies, &correlation_id) => reply,
+                _ = sleep(timeout) => Err(ConnectionError::Timeout),
+            },
+            Err(e) => Err(e),
+        };
+
+        self.unsubscribe(transaction_id).await?;
+
+        res
     }
 
-    pub async fn psubscribe_async(
+    pub async fn request<T: Serialize, R: DeserializeOwned>(
+        &self,
+        key: Key,
+        value: &T,
+        timeout: Duration,
+    ) -> ConnectionResult<R> {
+        let value = json::to_value(value)?;
+        let reply = self.request_generic(key, value, timeout).await?;
+        Ok(json::from_value(reply)?)
+    }
+
+    /// Answer a request received as a [`CorrelatedValue`]. Returns `None` if the request does not
+    /// specify a key to reply to.
+    pub async fn reply<T: Serialize>(
+        &self,
+        request: &CorrelatedValue,
+        value: &T,
+    ) -> ConnectionResult<Option<TransactionId>> {
+        let Some(reply_to) = request.reply_to.clone() else {
+            return Ok(None);
+        };
+        let value = json::to_value(value)?;
+        let transaction_id = self
+            .publish_correlated(reply_to, value, request.correlation_id.clone(), None)
+            .await?;
+        Ok(Some(transaction_id))
+    }
+
+    pub async fn get_async(&self, key: Key) -> ConnectionResult<TransactionId> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::GetAsync(key, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let res = rx.await?;
+        Ok(res)
+    }
+
+    pub async fn get_generic(&self, key: Key) -> ConnectionResult<(Option<Value>, TransactionId)> {
+        self.get_generic_with_timeout(key, self.request_timeout)
+            .await
+    }
+
+    /// Like [`Worterbuch::get_generic`], but with a custom timeout instead of
+    /// [`Config::request_timeout`].
+    pub async fn get_generic_with_timeout(
+        &self,
+        key: Key,
+        timeout: Duration,
+    ) -> ConnectionResult<(Option<Value>, TransactionId)> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Get(key, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let res = await_response(rx, timeout).await?;
+        Ok(res)
+    }
+
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(Option<T>, TransactionId)> {
+        self.get_with_timeout(key, self.request_timeout).await
+    }
+
+    pub async fn get_with_timeout<T: DeserializeOwned>(
+        &self,
+        key: Key,
+        timeout: Duration,
+    ) -> ConnectionResult<(Option<T>, TransactionId)> {
+        Ok(match self.get_generic_with_timeout(key, timeout).await? {
+            (Some(val), tid) => (Some(json::from_value(val)?), tid),
+            (None, tid) => (None, tid),
+        })
+    }
+
+    /// Gets only the part of a value addressed by a JSON pointer (RFC 6901), e.g. `/a/b/0`.
+    /// Returns `None` if the key or the addressed location does not exist.
+    pub async fn get_at_generic(
+        &self,
+        key: Key,
+        pointer: String,
+    ) -> ConnectionResult<Option<Value>> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::GetAt(key, pointer, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        match await_response(rx, self.request_timeout).await? {
+            Ok(value) => Ok(Some(value)),
+            Err(err) if err.error_code == ErrorCode::NoSuchValue => Ok(None),
+            Err(err) => Err(ConnectionError::WorterbuchError(
+                WorterbuchError::ServerResponse(err),
+            )),
+        }
+    }
+
+    pub async fn get_at<T: DeserializeOwned>(
+        &self,
+        key: Key,
+        pointer: String,
+    ) -> ConnectionResult<Option<T>> {
+        Ok(match self.get_at_generic(key, pointer).await? {
+            Some(value) => Some(json::from_value(value)?),
+            None => None,
+        })
+    }
+
+    pub async fn pget_async(&self, key: Key) -> ConnectionResult<TransactionId> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::PGetAsync(key, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let tid = rx.await?;
+        Ok(tid)
+    }
+
+    pub async fn pget_generic(&self, key: Key) -> ConnectionResult<(KeyValuePairs, TransactionId)> {
+        self.pget_generic_with_timeout(key, self.request_timeout)
+            .await
+    }
+
+    /// Like [`Worterbuch::pget_generic`], but with a custom timeout instead of
+    /// [`Config::request_timeout`].
+    pub async fn pget_generic_with_timeout(
+        &self,
+        key: Key,
+        timeout: Duration,
+    ) -> ConnectionResult<(KeyValuePairs, TransactionId)> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::PGet(key, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let (kvps, tid) = await_response(rx, timeout).await?;
+        Ok((kvps, tid))
+    }
+
+    /// Like [`Worterbuch::pget_generic`], but has the server sort the matches and only return at
+    /// most `limit` of them after skipping the first `offset`, e.g. to get the ten highest readings
+    /// without transferring all of them.
+    pub async fn pget_sorted_generic(
+        &self,
+        pattern: Key,
+        sort: Sort,
+        offset: Option<usize>,
+        limit: Option<usize>,
+    ) -> ConnectionResult<(KeyValuePairs, TransactionId)> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::PGetSorted(pattern, sort, offset, limit, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let (kvps, tid) = await_response(rx, self.request_timeout).await?;
+        Ok((kvps, tid))
+    }
+
+    pub async fn pget_sorted<T: DeserializeOwned>(
+        &self,
+        pattern: Key,
+        sort: Sort,
+        offset: Option<usize>,
+        limit: Option<usize>,
+    ) -> ConnectionResult<(TypedKeyValuePairs<T>, TransactionId)> {
+        let (kvps, tid) = self
+            .pget_sorted_generic(pattern, sort, offset, limit)
+            .await?;
+        let typed_kvps = deserialize_key_value_pairs(kvps)?;
+        Ok((typed_kvps, tid))
+    }
+
+    /// Like [`Worterbuch::pget_generic`], but the regular segments of the pattern match keys
+    /// regardless of their case, e.g. `sensors/?/temperature` also matches
+    /// `Sensors/kitchen/Temperature`.
+    pub async fn pget_ignoring_case_generic(
+        &self,
+        pattern: Key,
+    ) -> ConnectionResult<(KeyValuePairs, TransactionId)> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::PGetIgnoringCase(pattern, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let (kvps, tid) = await_response(rx, self.request_timeout).await?;
+        Ok((kvps, tid))
+    }
+
+    pub async fn pget_ignoring_case<T: DeserializeOwned>(
+        &self,
+        pattern: Key,
+    ) -> ConnectionResult<(TypedKeyValuePairs<T>, TransactionId)> {
+        let (kvps, tid) = self.pget_ignoring_case_generic(pattern).await?;
+        let typed_kvps = deserialize_key_value_pairs(kvps)?;
+        Ok((typed_kvps, tid))
+    }
+
+    pub async fn pkeys_async(&self, pattern: Key) -> ConnectionResult<TransactionId> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::PKeysAsync(pattern, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let tid = rx.await?;
+        Ok(tid)
+    }
+
+    /// Gets all keys matching the pattern without transferring their values.
+    pub async fn pkeys(&self, pattern: Key) -> ConnectionResult<Vec<Key>> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::PKeys(pattern, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        match await_response(rx, self.request_timeout).await? {
+            Ok(keys) => Ok(keys),
+            Err(err) => Err(ConnectionError::WorterbuchError(
+                WorterbuchError::ServerResponse(err),
+            )),
+        }
+    }
+
+    pub async fn pget<T: DeserializeOwned>(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(TypedKeyValuePairs<T>, TransactionId)> {
+        self.pget_with_timeout(key, self.request_timeout).await
+    }
+
+    pub async fn pget_with_timeout<T: DeserializeOwned>(
+        &self,
+        key: Key,
+        timeout: Duration,
+    ) -> ConnectionResult<(TypedKeyValuePairs<T>, TransactionId)> {
+        let (kvps, tid) = self.pget_generic_with_timeout(key, timeout).await?;
+        let typed_kvps = deserialize_key_value_pairs(kvps)?;
+        Ok((typed_kvps, tid))
+    }
+
+    /// Reads everything stored below `prefix` and maps it onto a nested structure, each key
+    /// segment becoming a field. Returns `None` if nothing is stored there.
+    pub async fn get_tree<T: DeserializeOwned>(&self, prefix: Key) -> ConnectionResult<Option<T>> {
+        self.get_tree_with(prefix, &FlattenRules::default()).await
+    }
+
+    /// Like [`Worterbuch::get_tree`], but with custom [`FlattenRules`]. These must match the
+    /// rules the tree was written with.
+    pub async fn get_tree_with<T: DeserializeOwned>(
+        &self,
+        prefix: Key,
+        rules: &FlattenRules,
+    ) -> ConnectionResult<Option<T>> {
+        let (kvps, _) = self.pget_generic(topic!(prefix, "#")).await?;
+        match tree::unflatten(&prefix, kvps, rules) {
+            Some(value) => Ok(Some(json::from_value(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes a structure as one key per leaf value below `prefix`. Keys that already exist
+    /// below `prefix` but are not part of `value` are left untouched.
+    pub async fn set_tree<T: Serialize>(&self, prefix: Key, value: &T) -> ConnectionResult<()> {
+        self.set_tree_with(prefix, value, &FlattenRules::default())
+            .await
+    }
+
+    /// Like [`Worterbuch::set_tree`], but with custom [`FlattenRules`].
+    pub async fn set_tree_with<T: Serialize>(
+        &self,
+        prefix: Key,
+        value: &T,
+        rules: &FlattenRules,
+    ) -> ConnectionResult<()> {
+        let value = json::to_value(value)?;
+        for KeyValuePair { key, value } in tree::flatten(&prefix, value, rules) {
+            self.set_generic(key, value).await?;
+        }
+        Ok(())
+    }
+
+    /// Like [`Worterbuch::pget_generic`], but the server streams the matches in chunks of at most
+    /// `chunk_size` key/value pairs. The receiver is closed after the last chunk has been received.
+    pub async fn pget_chunked_generic(
+        &self,
+        key: Key,
+        chunk_size: usize,
+    ) -> ConnectionResult<(mpsc::UnboundedReceiver<KeyValuePairs>, TransactionId)> {
+        let (tid_tx, tid_rx) = oneshot::channel();
+        let (chunk_tx, chunk_rx) = mpsc::unbounded_channel();
+        let cmd = Command::PGetChunked(key, chunk_size, tid_tx, chunk_tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let tid = tid_rx.await?;
+        Ok((chunk_rx, tid))
+    }
+
+    pub async fn pget_chunked<T: DeserializeOwned + Send + 'static>(
+        &self,
+        key: Key,
+        chunk_size: usize,
+    ) -> ConnectionResult<(
+        mpsc::UnboundedReceiver<TypedKeyValuePairs<T>>,
+        TransactionId,
+    )> {
+        let (chunk_rx, tid) = self.pget_chunked_generic(key, chunk_size).await?;
+        let (typed_chunk_tx, typed_chunk_rx) = mpsc::unbounded_channel();
+        spawn(deserialize_chunks(chunk_rx, typed_chunk_tx));
+        Ok((typed_chunk_rx, tid))
+    }
+
+    pub async fn delete_async(&self, key: Key) -> ConnectionResult<TransactionId> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::DeleteAsync(key, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let tid = rx.await?;
+        Ok(tid)
+    }
+
+    pub async fn delete_generic(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(Option<Value>, TransactionId)> {
+        self.delete_generic_with_timeout(key, self.request_timeout)
+            .await
+    }
+
+    /// Like [`Worterbuch::delete_generic`], but with a custom timeout instead of
+    /// [`Config::request_timeout`].
+    pub async fn delete_generic_with_timeout(
+        &self,
+        key: Key,
+        timeout: Duration,
+    ) -> ConnectionResult<(Option<Value>, TransactionId)> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Delete(key, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        match await_response(rx, timeout).await? {
+            (Some(value), tid) => Ok((Some(value), tid)),
+            (None, tid) => Ok((None, tid)),
+        }
+    }
+
+    pub async fn delete<T: DeserializeOwned>(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(Option<T>, TransactionId)> {
+        self.delete_with_timeout(key, self.request_timeout).await
+    }
+
+    pub async fn delete_with_timeout<T: DeserializeOwned>(
+        &self,
+        key: Key,
+        timeout: Duration,
+    ) -> ConnectionResult<(Option<T>, TransactionId)> {
+        Ok(
+            match self.delete_generic_with_timeout(key, timeout).await? {
+                (Some(val), tid) => (Some(json::from_value(val)?), tid),
+                (None, tid) => (None, tid),
+            },
+        )
+    }
+
+    pub async fn pdelete_async(&self, key: Key) -> ConnectionResult<TransactionId> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::PDeleteAsync(key, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let tid = rx.await?;
+        Ok(tid)
+    }
+
+    pub async fn pdelete_generic(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(KeyValuePairs, TransactionId)> {
+        self.pdelete_generic_with_timeout(key, self.request_timeout)
+            .await
+    }
+
+    /// Like [`Worterbuch::pdelete_generic`], but with a custom timeout instead of
+    /// [`Config::request_timeout`].
+    pub async fn pdelete_generic_with_timeout(
+        &self,
+        key: Key,
+        timeout: Duration,
+    ) -> ConnectionResult<(KeyValuePairs, TransactionId)> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::PDelete(key, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let (kvps, tid) = await_response(rx, timeout).await?;
+        Ok((kvps, tid))
+    }
+
+    pub async fn pdelete<T: DeserializeOwned>(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(TypedKeyValuePairs<T>, TransactionId)> {
+        self.pdelete_with_timeout(key, self.request_timeout).await
+    }
+
+    pub async fn pdelete_with_timeout<T: DeserializeOwned>(
+        &self,
+        key: Key,
+        timeout: Duration,
+    ) -> ConnectionResult<(TypedKeyValuePairs<T>, TransactionId)> {
+        let (kvps, tid) = self.pdelete_generic_with_timeout(key, timeout).await?;
+        let typed_kvps = deserialize_key_value_pairs(kvps)?;
+        Ok((typed_kvps, tid))
+    }
+
+    /// Claim exclusive write access to all keys matching the pattern, so that writes of other
+    /// clients are rejected. Returns `false` if another client has already reserved an
+    /// overlapping pattern. The reservation lasts until it is released or the connection is lost,
+    /// it is not restored after reconnecting.
+    pub async fn reserve(&self, pattern: RequestPattern) -> ConnectionResult<bool> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Reserve(pattern, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        match await_response(rx, self.request_timeout).await? {
+            Ok(()) => Ok(true),
+            Err(err) if err.error_code == ErrorCode::KeyReserved => Ok(false),
+            Err(err) => Err(ConnectionError::WorterbuchError(
+                WorterbuchError::ServerResponse(err),
+            )),
+        }
+    }
+
+    /// Give up a reservation made with [`Worterbuch::reserve`].
+    pub async fn release(&self, pattern: RequestPattern) -> ConnectionResult<()> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Release(pattern, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        match await_response(rx, self.request_timeout).await? {
+            Ok(()) => Ok(()),
+            Err(err) => Err(ConnectionError::WorterbuchError(
+                WorterbuchError::ServerResponse(err),
+            )),
+        }
+    }
+
+    /// Acquire an exclusive write lock on a key, waiting for as long as other clients hold it.
+    /// The lock is released when the lease expires unless it is renewed by locking the key again,
+    /// or when the connection is lost. Since waiting may take arbitrarily long, this is not
+    /// subject to the request timeout.
+    pub async fn lock(&self, key: Key, lease: Duration) -> ConnectionResult<()> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Lock(key, lease, true, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        match rx.await? {
+            Ok(()) => Ok(()),
+            Err(err) => Err(ConnectionError::WorterbuchError(
+                WorterbuchError::ServerResponse(err),
+            )),
+        }
+    }
+
+    /// Like [`Worterbuch::lock`], but returns `false` right away if another client holds the lock.
+    pub async fn try_lock(&self, key: Key, lease: Duration) -> ConnectionResult<bool> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Lock(key, lease, false, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        match await_response(rx, self.request_timeout).await? {
+            Ok(()) => Ok(true),
+            Err(err) if err.error_code == ErrorCode::KeyLocked => Ok(false),
+            Err(err) => Err(ConnectionError::WorterbuchError(
+                WorterbuchError::ServerResponse(err),
+            )),
+        }
+    }
+
+    /// Release a lock acquired with [`Worterbuch::lock`] or [`Worterbuch::try_lock`].
+    pub async fn unlock(&self, key: Key) -> ConnectionResult<()> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Unlock(key, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        match await_response(rx, self.request_timeout).await? {
+            Ok(()) => Ok(()),
+            Err(err) => Err(ConnectionError::WorterbuchError(
+                WorterbuchError::ServerResponse(err),
+            )),
+        }
+    }
+
+    /// Restore soft-deleted keys matching the pattern from the server's trash.
+    pub async fn restore_generic(
+        &self,
+        pattern: RequestPattern,
+    ) -> ConnectionResult<(KeyValuePairs, TransactionId)> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Restore(pattern, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let (kvps, tid) = await_response(rx, self.request_timeout).await?;
+        Ok((kvps, tid))
+    }
+
+    pub async fn restore<T: DeserializeOwned>(
+        &self,
+        pattern: RequestPattern,
+    ) -> ConnectionResult<(TypedKeyValuePairs<T>, TransactionId)> {
+        let (kvps, tid) = self.restore_generic(pattern).await?;
+        let typed_kvps = deserialize_key_value_pairs(kvps)?;
+        Ok((typed_kvps, tid))
+    }
+
+    /// Start recording all changes of values matching the pattern into a named recording on the
+    /// server. An existing recording with the same name is replaced.
+    pub async fn start_recording(
+        &self,
+        name: String,
+        pattern: RequestPattern,
+    ) -> ConnectionResult<TransactionId> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::StartRecording(name, pattern, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        Ok(rx.await?)
+    }
+
+    pub async fn stop_recording(&self, name: String) -> ConnectionResult<TransactionId> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::StopRecording(name, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        Ok(rx.await?)
+    }
+
+    /// Replay a stopped recording into the keys below `target_prefix` with its original timing,
+    /// optionally sped up by the given factor.
+    pub async fn replay_recording(
+        &self,
+        name: String,
+        target_prefix: Key,
+        speed: Option<u32>,
+    ) -> ConnectionResult<TransactionId> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::ReplayRecording(name, target_prefix, speed, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        Ok(rx.await?)
+    }
+
+    pub async fn ls_async(&self, parent: Option<Key>) -> ConnectionResult<TransactionId> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::LsAsync(parent, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let tid = rx.await?;
+        Ok(tid)
+    }
+
+    pub async fn ls(
+        &self,
+        parent: Option<Key>,
+    ) -> ConnectionResult<(Vec<RegularKeySegment>, TransactionId)> {
+        self.ls_with_timeout(parent, self.request_timeout).await
+    }
+
+    /// Like [`Worterbuch::ls`], but with a custom timeout instead of [`Config::request_timeout`].
+    pub async fn ls_with_timeout(
+        &self,
+        parent: Option<Key>,
+        timeout: Duration,
+    ) -> ConnectionResult<(Vec<RegularKeySegment>, TransactionId)> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Ls(parent, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let children = await_response(rx, timeout).await?;
+        Ok(children)
+    }
+
+    pub async fn subscribe_async(
+        &self,
+        key: Key,
+        unique: bool,
+        live_only: bool,
+    ) -> ConnectionResult<TransactionId> {
+        let (tx, rx) = oneshot::channel();
+        self.commands
+            .send(Command::SubscribeAsync(key, unique, tx, live_only))
+            .await?;
+        let tid = rx.await?;
+        self.track_subscription(tid);
+        Ok(tid)
+    }
+
+    pub async fn subscribe_generic(
+        &self,
+        key: Key,
+        unique: bool,
+        live_only: bool,
+    ) -> ConnectionResult<(mpsc::UnboundedReceiver<(Option<Value>, Key)>, TransactionId)> {
+        let (tid_tx, tid_rx) = oneshot::channel();
+        let (val_tx, val_rx) = mpsc::unbounded_channel();
+        self.commands
+            .send(Command::Subscribe(
+                key, unique, tid_tx, val_tx, live_only, None,
+            ))
+            .await?;
+        let transaction_id = tid_rx.await?;
+        self.track_subscription(transaction_id);
+        Ok((val_rx, transaction_id))
+    }
+
+    pub async fn subscribe<T: DeserializeOwned + Send + 'static>(
+        &self,
+        key: Key,
+        unique: bool,
+        live_only: bool,
+    ) -> ConnectionResult<(mpsc::UnboundedReceiver<Option<T>>, TransactionId)> {
+        let (val_rx, transaction_id) = self.subscribe_generic(key, unique, live_only).await?;
+        let (typed_val_tx, typed_val_rx) = mpsc::unbounded_channel();
+        spawn(deserialize_values(val_rx, typed_val_tx));
+        Ok((typed_val_rx, transaction_id))
+    }
+
+    /// Like [`Worterbuch::subscribe_generic`], but the stream contains a
+    /// [`SubscriptionEvent::InitialStateComplete`] marker right after the value that was stored
+    /// before the subscription was made, or as the first item if there was none.
+    pub async fn subscribe_marked_generic(
+        &self,
+        key: Key,
+        unique: bool,
+        live_only: bool,
+    ) -> ConnectionResult<(
+        mpsc::UnboundedReceiver<SubscriptionEvent<(Option<Value>, Key)>>,
+        TransactionId,
+    )> {
+        let (tid_tx, tid_rx) = oneshot::channel();
+        let (val_tx, val_rx) = mpsc::unbounded_channel();
+        let (marker_tx, marker_rx) = oneshot::channel();
+        self.commands
+            .send(Command::Subscribe(
+                key,
+                unique,
+                tid_tx,
+                val_tx,
+                live_only,
+                Some(marker_tx),
+            ))
+            .await?;
+        let transaction_id = tid_rx.await?;
+        self.track_subscription(transaction_id);
+        let (marked_tx, marked_rx) = mpsc::unbounded_channel();
+        spawn(mark_initial_state(val_rx, marker_rx, marked_tx));
+        Ok((marked_rx, transaction_id))
+    }
+
+    /// Like [`Worterbuch::subscribe`], but the stream contains a
+    /// [`SubscriptionEvent::InitialStateComplete`] marker right after the value that was stored
+    /// before the subscription was made, or as the first item if there was none.
+    pub async fn subscribe_marked<T: DeserializeOwned + Send + 'static>(
+        &self,
+        key: Key,
+        unique: bool,
+        live_only: bool,
+    ) -> ConnectionResult<(
+        mpsc::UnboundedReceiver<SubscriptionEvent<Option<T>>>,
+        TransactionId,
+    )> {
+        let (val_rx, transaction_id) = self
+            .subscribe_marked_generic(key, unique, live_only)
+            .await?;
+        let (typed_val_tx, typed_val_rx) = mpsc::unbounded_channel();
+        spawn(deserialize_marked_values(val_rx, typed_val_tx));
+        Ok((typed_val_rx, transaction_id))
+    }
+
+    /// Subscribes to `key` and keeps the returned receiver up to date with its latest value,
+    /// `None` meaning the key does not exist. The subscription is cancelled once all receivers
+    /// have been dropped.
+    pub async fn watch<T: DeserializeOwned + Send + Sync + 'static>(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(watch::Receiver<Option<T>>, TransactionId)> {
+        let (mut val_rx, transaction_id) = self.subscribe::<T>(key, true, false).await?;
+        let (watch_tx, watch_rx) = watch::channel(None);
+        let wb = self.clone();
+        spawn(async move {
+            loop {
+                select! {
+                    recv = val_rx.recv() => match recv {
+                        Some(value) => {
+                            watch_tx.send_replace(value);
+                        }
+                        None => break,
+                    },
+                    _ = watch_tx.closed() => {
+                        if let Err(e) = wb.unsubscribe(transaction_id).await {
+                            log::debug!("Could not cancel watch subscription {transaction_id}: {e}");
+                        }
+                        break;
+                    }
+                }
+            }
+        });
+        Ok((watch_rx, transaction_id))
+    }
+
+    /// Like [`Worterbuch::watch`], but wraps the receiver in a [`Cached`] handle whose
+    /// [`get`](Cached::get) returns the key's latest value without waiting.
+    pub async fn subscribe_cached<T: DeserializeOwned + Send + Sync + 'static>(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<Cached<T>> {
+        let (rx, transaction_id) = self.watch(key.clone()).await?;
+        Ok(Cached::new(key, transaction_id, rx))
+    }
+
+    /// Sets `key` to `value` right away and again every `interval` for as long as the returned
+    /// [`Heartbeat`] is kept. Beats pause while the connection is down and resume immediately after
+    /// reconnecting, so presence keys can be kept alive without any bookkeeping of their own.
+    pub async fn heartbeat<T: Serialize>(
+        &self,
+        key: Key,
+        value: &T,
+        interval: Duration,
+    ) -> ConnectionResult<Heartbeat> {
+        let value = json::to_value(value)?;
+        let events = self.events().await?;
+        Ok(Heartbeat::start(self.clone(), key, value, interval, events))
+    }
+
+    pub async fn psubscribe_async(
         &self,
         request_pattern: RequestPattern,
         unique: bool,
@@ -418,27 +1606,60 @@ impl Worterbuch {
     ) -> ConnectionResult<TransactionId> {
         let (tx, rx) = oneshot::channel();
         self.commands
-            .send(Command::PSubscribeAsync(
+            .send(Command::PSubscribeAsync(
+                request_pattern,
+                unique,
+                tx,
+                aggregation_duration.map(|d| d.as_millis() as u64),
+                live_only,
+            ))
+            .await?;
+        let tid = rx.await?;
+        self.track_subscription(tid);
+        Ok(tid)
+    }
+
+    pub async fn psubscribe_generic(
+        &self,
+        request_pattern: RequestPattern,
+        unique: bool,
+        live_only: bool,
+        aggregation_duration: Option<Duration>,
+    ) -> ConnectionResult<(mpsc::UnboundedReceiver<PStateEvent>, TransactionId)> {
+        let (tid_tx, tid_rx) = oneshot::channel();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        self.commands
+            .send(Command::PSubscribe(
                 request_pattern,
                 unique,
-                tx,
+                tid_tx,
+                event_tx,
                 aggregation_duration.map(|d| d.as_millis() as u64),
                 live_only,
+                None,
             ))
             .await?;
-        let tid = rx.await?;
-        Ok(tid)
+        let transaction_id = tid_rx.await?;
+        self.track_subscription(transaction_id);
+        Ok((event_rx, transaction_id))
     }
 
-    pub async fn psubscribe_generic(
+    /// Like [`Worterbuch::psubscribe_generic`], but the stream contains a
+    /// [`SubscriptionEvent::InitialStateComplete`] marker right after the events carrying the
+    /// state that was stored before the subscription was made.
+    pub async fn psubscribe_marked_generic(
         &self,
         request_pattern: RequestPattern,
         unique: bool,
         live_only: bool,
         aggregation_duration: Option<Duration>,
-    ) -> ConnectionResult<(mpsc::UnboundedReceiver<PStateEvent>, TransactionId)> {
+    ) -> ConnectionResult<(
+        mpsc::UnboundedReceiver<SubscriptionEvent<PStateEvent>>,
+        TransactionId,
+    )> {
         let (tid_tx, tid_rx) = oneshot::channel();
         let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let (marker_tx, marker_rx) = oneshot::channel();
         self.commands
             .send(Command::PSubscribe(
                 request_pattern,
@@ -447,10 +1668,35 @@ impl Worterbuch {
                 event_tx,
                 aggregation_duration.map(|d| d.as_millis() as u64),
                 live_only,
+                Some(marker_tx),
             ))
             .await?;
         let transaction_id = tid_rx.await?;
-        Ok((event_rx, transaction_id))
+        self.track_subscription(transaction_id);
+        let (marked_tx, marked_rx) = mpsc::unbounded_channel();
+        spawn(mark_initial_state(event_rx, marker_rx, marked_tx));
+        Ok((marked_rx, transaction_id))
+    }
+
+    /// Like [`Worterbuch::psubscribe`], but the stream contains a
+    /// [`SubscriptionEvent::InitialStateComplete`] marker right after the events carrying the
+    /// state that was stored before the subscription was made.
+    pub async fn psubscribe_marked<T: DeserializeOwned + Send + 'static>(
+        &self,
+        request_pattern: RequestPattern,
+        unique: bool,
+        live_only: bool,
+        aggregation_duration: Option<Duration>,
+    ) -> ConnectionResult<(
+        mpsc::UnboundedReceiver<SubscriptionEvent<TypedStateEvents<T>>>,
+        TransactionId,
+    )> {
+        let (event_rx, transaction_id) = self
+            .psubscribe_marked_generic(request_pattern, unique, live_only, aggregation_duration)
+            .await?;
+        let (typed_event_tx, typed_event_rx) = mpsc::unbounded_channel();
+        spawn(deserialize_marked_events(event_rx, typed_event_tx));
+        Ok((typed_event_rx, transaction_id))
     }
 
     pub async fn psubscribe<T: DeserializeOwned + Send + 'static>(
@@ -468,7 +1714,48 @@ impl Worterbuch {
         Ok((typed_event_rx, transaction_id))
     }
 
+    /// Like [`Worterbuch::psubscribe`], but subscribes to several patterns at once and merges their
+    /// events into a single stream. Every batch of events is tagged with the pattern it was
+    /// received for, so consumers can route it without having to match the keys again.
+    pub async fn psubscribe_tagged<T: DeserializeOwned + Send + 'static>(
+        &self,
+        request_patterns: Vec<RequestPattern>,
+        unique: bool,
+        live_only: bool,
+        aggregation_duration: Option<Duration>,
+    ) -> ConnectionResult<(
+        mpsc::UnboundedReceiver<(RequestPattern, TypedStateEvents<T>)>,
+        Vec<TransactionId>,
+    )> {
+        let (typed_event_tx, typed_event_rx) = mpsc::unbounded_channel();
+        let mut transaction_ids = Vec::with_capacity(request_patterns.len());
+        for request_pattern in request_patterns {
+            let (event_rx, transaction_id) = self
+                .psubscribe_generic(
+                    request_pattern.clone(),
+                    unique,
+                    live_only,
+                    aggregation_duration,
+                )
+                .await?;
+            spawn(deserialize_tagged_events(
+                request_pattern,
+                event_rx,
+                typed_event_tx.clone(),
+            ));
+            transaction_ids.push(transaction_id);
+        }
+        Ok((typed_event_rx, transaction_ids))
+    }
+
     pub async fn unsubscribe(&self, transaction_id: TransactionId) -> ConnectionResult<()> {
+        if let Some(session) = &self.session {
+            if !session.release(transaction_id) {
+                return Err(ConnectionError::WorterbuchError(
+                    WorterbuchError::NotSubscribed,
+                ));
+            }
+        }
         self.commands
             .send(Command::Unsubscribe(transaction_id))
             .await?;
@@ -481,6 +1768,7 @@ impl Worterbuch {
             .send(Command::SubscribeLsAsync(parent, tx))
             .await?;
         let tid = rx.await?;
+        self.track_ls_subscription(tid);
         Ok(tid)
     }
 
@@ -497,21 +1785,114 @@ impl Worterbuch {
             .send(Command::SubscribeLs(parent, tid_tx, children_tx))
             .await?;
         let transaction_id = tid_rx.await?;
+        self.track_ls_subscription(transaction_id);
         Ok((children_rx, transaction_id))
     }
 
+    /// Asks the server to deliver the current state of a subscription again, e.g. after a
+    /// [`ConnectionEvent::MessagesMissed`] has been reported for it. The state arrives through
+    /// the subscription's regular stream.
+    pub async fn resync(&self, transaction_id: TransactionId) -> ConnectionResult<()> {
+        self.commands.send(Command::Resync(transaction_id)).await?;
+        Ok(())
+    }
+
+    /// Waits until the server has applied all writes previously sent on this connection. With
+    /// `persist`, it also waits for them to be written to disk, which fails if the server has no
+    /// write-ahead log or the client lacks write access to `#`.
+    pub async fn sync(&self, persist: bool) -> ConnectionResult<()> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Sync(persist.then_some(true), tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        match await_response(rx, self.request_timeout).await? {
+            Ok(()) => Ok(()),
+            Err(err) => Err(ConnectionError::WorterbuchError(
+                WorterbuchError::ServerResponse(err),
+            )),
+        }
+    }
+
+    /// Cancels a pget or pdelete that has been started with e.g. [`Worterbuch::pget_async`] and
+    /// not been answered yet. The server answers the request with
+    /// [`ErrorCode::Cancelled`](worterbuch_common::ErrorCode::Cancelled) if it was still in
+    /// progress. A pdelete can only be cancelled while it is waiting to be processed, once keys
+    /// have been deleted it runs to completion.
+    pub async fn cancel(&self, transaction_id: TransactionId) -> ConnectionResult<()> {
+        self.commands.send(Command::Cancel(transaction_id)).await?;
+        Ok(())
+    }
+
     pub async fn unsubscribe_ls(&self, transaction_id: TransactionId) -> ConnectionResult<()> {
+        if let Some(session) = &self.session {
+            if !session.release_ls(transaction_id) {
+                return Err(ConnectionError::WorterbuchError(
+                    WorterbuchError::NotSubscribed,
+                ));
+            }
+        }
         self.commands
             .send(Command::UnsubscribeLs(transaction_id))
             .await?;
         Ok(())
     }
 
+    pub async fn subscribe_server_events(
+        &self,
+    ) -> ConnectionResult<(mpsc::UnboundedReceiver<ServerEvent>, TransactionId)> {
+        let (tid_tx, tid_rx) = oneshot::channel();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let cmd = Command::SubscribeEvents(tid_tx, event_tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        let transaction_id = tid_rx.await?;
+        self.track_subscription(transaction_id);
+        Ok((event_rx, transaction_id))
+    }
+
+    pub async fn who_subscribes(
+        &self,
+        request_pattern: RequestPattern,
+    ) -> ConnectionResult<(Vec<SubscriberInfo>, TransactionId)> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::WhoSubscribes(request_pattern, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let res = await_response(rx, self.request_timeout).await?;
+        Ok(res)
+    }
+
+    /// Get up to `limit` of the most recent values of a key, oldest first. The server only keeps
+    /// a history of keys its history rules apply to, for all other keys the result is empty.
+    pub async fn get_history(
+        &self,
+        key: Key,
+        limit: Option<usize>,
+    ) -> ConnectionResult<(Vec<HistoryEntry>, TransactionId)> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::GetHistory(key, limit, tx);
+        log::debug!("Queuing command {}", cmd.name());
+        self.commands.send(cmd).await?;
+        log::debug!("Command queued.");
+        let res = await_response(rx, self.request_timeout).await?;
+        Ok(res)
+    }
+
     pub async fn send_buffer(&self, delay: Duration) -> SendBuffer {
         SendBuffer::new(self.commands.clone(), delay).await
     }
 
+    /// Closes the connection. Closing a [session](Self::session) only cancels the session's
+    /// subscriptions and leaves the connection open.
     pub async fn close(&self) -> ConnectionResult<()> {
+        if let Some(session) = &self.session {
+            for cmd in session.release_all() {
+                self.commands.send(cmd).await?;
+            }
+            return Ok(());
+        }
         self.stop.send(()).await?;
         Ok(())
     }
@@ -522,9 +1903,22 @@ impl Worterbuch {
         Ok(rx)
     }
 
+    /// Returns a stream of connection state changes. The stream starts with the current state of
+    /// the connection and ends when the client is closed.
+    pub async fn events(&self) -> ConnectionResult<mpsc::UnboundedReceiver<ConnectionEvent>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.commands.send(Command::ConnectionEvents(tx)).await?;
+        Ok(rx)
+    }
+
     pub fn client_id(&self) -> &str {
         &self.client_id
     }
+
+    /// The subscription registry of this connection, if one was configured.
+    pub fn subscription_registry(&self) -> Option<&SubscriptionRegistry> {
+        self.registry.as_ref()
+    }
 }
 
 async fn deserialize_values<T: DeserializeOwned + Send + 'static>(
@@ -555,6 +1949,25 @@ async fn deserialize_values<T: DeserializeOwned + Send + 'static>(
     }
 }
 
+async fn deserialize_chunks<T: DeserializeOwned + Send + 'static>(
+    mut chunk_rx: mpsc::UnboundedReceiver<KeyValuePairs>,
+    typed_chunk_tx: mpsc::UnboundedSender<TypedKeyValuePairs<T>>,
+) {
+    while let Some(chunk) = chunk_rx.recv().await {
+        match deserialize_key_value_pairs(chunk) {
+            Ok(typed_chunk) => {
+                if typed_chunk_tx.send(typed_chunk).is_err() {
+                    break;
+                }
+            }
+            Result::Err(e) => {
+                log::error!("could not deserialize json to requested type: {e}");
+                break;
+            }
+        }
+    }
+}
+
 async fn deserialize_events<T: DeserializeOwned + Send + 'static>(
     mut event_rx: mpsc::UnboundedReceiver<PStateEvent>,
     typed_event_tx: mpsc::UnboundedSender<TypedStateEvents<T>>,
@@ -574,17 +1987,136 @@ async fn deserialize_events<T: DeserializeOwned + Send + 'static>(
     }
 }
 
+/// Merges the initial state marker into the event stream. The marker is only forwarded once no
+/// more events are queued, and since the marker is always delivered after the initial state
+/// events, this is guaranteed to preserve their order.
+async fn mark_initial_state<E>(
+    mut event_rx: mpsc::UnboundedReceiver<E>,
+    mut marker_rx: oneshot::Receiver<()>,
+    marked_tx: mpsc::UnboundedSender<SubscriptionEvent<E>>,
+) {
+    let mut marker_pending = true;
+    loop {
+        select! {
+            biased;
+            recv = event_rx.recv() => match recv {
+                Some(event) => {
+                    if marked_tx.send(SubscriptionEvent::Event(event)).is_err() {
+                        break;
+                    }
+                }
+                None => break,
+            },
+            res = &mut marker_rx, if marker_pending => {
+                marker_pending = false;
+                if res.is_ok() && marked_tx.send(SubscriptionEvent::InitialStateComplete).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn deserialize_marked_values<T: DeserializeOwned + Send + 'static>(
+    mut val_rx: mpsc::UnboundedReceiver<SubscriptionEvent<(Option<Value>, Key)>>,
+    typed_val_tx: mpsc::UnboundedSender<SubscriptionEvent<Option<T>>>,
+) {
+    while let Some(event) = val_rx.recv().await {
+        let typed_event = match event {
+            SubscriptionEvent::Event((Some(val), key)) => {
+                match json::from_value(val) {
+                    Ok(typed_val) => SubscriptionEvent::Event(Some(typed_val)),
+                    Err(e) => {
+                        log::error!("could not deserialize json value of key '{key}' to requested type: {e}");
+                        break;
+                    }
+                }
+            }
+            SubscriptionEvent::Event((None, _)) => SubscriptionEvent::Event(None),
+            SubscriptionEvent::InitialStateComplete => SubscriptionEvent::InitialStateComplete,
+        };
+        if typed_val_tx.send(typed_event).is_err() {
+            break;
+        }
+    }
+}
+
+async fn deserialize_marked_events<T: DeserializeOwned + Send + 'static>(
+    mut event_rx: mpsc::UnboundedReceiver<SubscriptionEvent<PStateEvent>>,
+    typed_event_tx: mpsc::UnboundedSender<SubscriptionEvent<TypedStateEvents<T>>>,
+) {
+    while let Some(event) = event_rx.recv().await {
+        let typed_event = match event {
+            SubscriptionEvent::Event(evt) => match deserialize_pstate_event(evt) {
+                Ok(typed_event) => SubscriptionEvent::Event(typed_event),
+                Result::Err(e) => {
+                    log::error!("could not deserialize json to requested type: {e}");
+                    break;
+                }
+            },
+            SubscriptionEvent::InitialStateComplete => SubscriptionEvent::InitialStateComplete,
+        };
+        if typed_event_tx.send(typed_event).is_err() {
+            break;
+        }
+    }
+}
+
+async fn deserialize_tagged_events<T: DeserializeOwned + Send + 'static>(
+    request_pattern: RequestPattern,
+    mut event_rx: mpsc::UnboundedReceiver<PStateEvent>,
+    typed_event_tx: mpsc::UnboundedSender<(RequestPattern, TypedStateEvents<T>)>,
+) {
+    while let Some(evt) = event_rx.recv().await {
+        match deserialize_pstate_event(evt) {
+            Ok(typed_event) => {
+                if typed_event_tx
+                    .send((request_pattern.clone(), typed_event))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            Result::Err(e) => {
+                log::error!("could not deserialize json to requested type: {e}");
+                break;
+            }
+        }
+    }
+}
+
 #[derive(Default)]
 struct Callbacks {
     all: Vec<mpsc::UnboundedSender<ServerMessage>>,
     get: HashMap<TransactionId, oneshot::Sender<(Option<Value>, TransactionId)>>,
     pget: HashMap<TransactionId, oneshot::Sender<(KeyValuePairs, TransactionId)>>,
+    pget_chunked: HashMap<TransactionId, mpsc::UnboundedSender<KeyValuePairs>>,
     del: HashMap<TransactionId, oneshot::Sender<(Option<Value>, TransactionId)>>,
     pdel: HashMap<TransactionId, oneshot::Sender<(KeyValuePairs, TransactionId)>>,
+    restore: HashMap<TransactionId, oneshot::Sender<(KeyValuePairs, TransactionId)>>,
     ls: HashMap<TransactionId, oneshot::Sender<(Vec<RegularKeySegment>, TransactionId)>>,
+    pkeys: HashMap<TransactionId, oneshot::Sender<Result<Vec<Key>, Err>>>,
     sub: HashMap<TransactionId, mpsc::UnboundedSender<(Option<Value>, Key)>>,
     psub: HashMap<TransactionId, mpsc::UnboundedSender<PStateEvent>>,
+    initial_state: HashMap<TransactionId, oneshot::Sender<()>>,
+    seqs: HashMap<TransactionId, u64>,
+    subscriptions: HashMap<TransactionId, CM>,
+    refreshing: HashSet<TransactionId>,
     subls: HashMap<TransactionId, mpsc::UnboundedSender<Vec<RegularKeySegment>>>,
+    events: HashMap<TransactionId, mpsc::UnboundedSender<ServerEvent>>,
+    connection_events: ConnectionEvents,
+    who_subscribes: HashMap<TransactionId, oneshot::Sender<(Vec<SubscriberInfo>, TransactionId)>>,
+    cas: HashMap<TransactionId, oneshot::Sender<Result<(), Err>>>,
+    set_if: HashMap<TransactionId, oneshot::Sender<Result<(), Err>>>,
+    reservations: HashMap<TransactionId, oneshot::Sender<Result<(), Err>>>,
+    locks: HashMap<TransactionId, oneshot::Sender<Result<(), Err>>>,
+    get_and_set: HashMap<TransactionId, oneshot::Sender<Result<Option<Value>, Err>>>,
+    get_at: HashMap<TransactionId, oneshot::Sender<Result<Value, Err>>>,
+    set_at: HashMap<TransactionId, oneshot::Sender<Result<(), Err>>>,
+    increment: HashMap<TransactionId, oneshot::Sender<Result<Value, Err>>>,
+    arrays: HashMap<TransactionId, oneshot::Sender<Result<Value, Err>>>,
+    sync: HashMap<TransactionId, oneshot::Sender<Result<(), Err>>>,
+    history: HashMap<TransactionId, oneshot::Sender<(Vec<HistoryEntry>, TransactionId)>>,
 }
 
 struct TransactionIds {
@@ -619,33 +2151,101 @@ pub async fn connect<F: Future<Output = ()> + Send + 'static>(
     config: Config,
     on_disconnect: F,
 ) -> ConnectionResult<Worterbuch> {
+    let connection = connect_socket(&config).await?;
+    connected(connection, on_disconnect, config)
+}
+
+/// Connects like [`connect`], but keeps retrying with the configured [`Backoff`](config::Backoff) until the server
+/// can be reached. Once connected, lost connections are re-established the same way and all
+/// subscriptions, pattern subscriptions and ls subscriptions are replayed, so the streams returned
+/// by `subscribe` and friends simply continue. Retries forever if `max_attempts` is 0.
+pub async fn connect_with_retry<F: Future<Output = ()> + Send + 'static>(
+    mut config: Config,
+    on_disconnect: F,
+) -> ConnectionResult<Worterbuch> {
+    config.resubscribe = true;
+    if config.backoff.max_attempts == 0 {
+        config.backoff.max_attempts = u32::MAX;
+    }
+
+    let mut attempt = 0;
+    loop {
+        match connect_socket(&config).await {
+            Ok(connection) => return connected(connection, on_disconnect, config),
+            Err(e) => {
+                attempt += 1;
+                if attempt >= config.backoff.max_attempts {
+                    return Err(e);
+                }
+                let delay = config.backoff.delay(attempt);
+                log::warn!(
+                    "Could not connect to server: {e}; retrying in {} ms …",
+                    delay.as_millis()
+                );
+                sleep(delay).await;
+            }
+        }
+    }
+}
+
+struct Connection {
+    socket: ClientSocket,
+    client_id: String,
+    protocol_version: ProtocolVersion,
+    authenticated: bool,
+    boot_id: Option<String>,
+}
+
+async fn connect_socket(config: &Config) -> ConnectionResult<Connection> {
     let proto = &config.proto;
     let host_addr = &config.host_addr;
     let port = config.port;
     let tcp = proto == "tcp";
-    let path = if tcp { "" } else { "/ws" };
+    let path = if tcp { "" } else { config.ws_path.as_str() };
     let url = format!("{proto}://{host_addr}:{port}{path}",);
 
     log::debug!("Got server url from config: {url}");
 
-    if tcp {
-        connect_tcp(host_addr.to_owned(), port, on_disconnect, config).await
+    let connection = if tcp {
+        connect_tcp(host_addr.to_owned(), port, config).await
+    } else {
+        connect_ws(url, config).await
+    }?;
+
+    // TODO properly implement different protocol versions
+    let supported_protocol_versions = vec!["0.7".to_owned()];
+
+    if !supported_protocol_versions.contains(&connection.protocol_version) {
+        return Err(ConnectionError::WorterbuchError(
+            WorterbuchError::ProtocolNegotiationFailed,
+        ));
+    }
+
+    Ok(connection)
+}
+
+fn auth_handshake(config: &Config) -> Option<CM> {
+    if let Some(auth_token) = config.auth_token.clone() {
+        Some(CM::AuthorizationRequest(AuthorizationRequest {
+            auth_token,
+        }))
+    } else if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        Some(CM::AuthenticationRequest(AuthenticationRequest {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        }))
     } else {
-        connect_ws(url, on_disconnect, config).await
+        None
     }
 }
 
-async fn connect_ws<F: Future<Output = ()> + Send + 'static>(
-    url: String,
-    on_disconnect: F,
-    config: Config,
-) -> Result<Worterbuch, ConnectionError> {
+async fn connect_ws(url: String, config: &Config) -> Result<Connection, ConnectionError> {
     log::debug!("Connecting to server {url} over websocket …");
 
     let auth_token = config.auth_token.clone();
     let mut request = Request::builder()
         .uri(url)
-        .header("Sec-WebSocket-Protocol", "worterbuch".to_owned())
+        .header("Sec-WebSocket-Protocol", config.ws_subprotocol.clone())
         .header("Sec-WebSocket-Key", generate_key());
 
     if let Some(auth_token) = auth_token {
@@ -663,6 +2263,9 @@ async fn connect_ws<F: Future<Output = ()> + Send + 'static>(
                 version: _,
                 protocol_version,
                 authorization_required,
+                boot_id,
+                wire_formats,
+                compression,
             },
     } = match websocket.next().await {
         Some(Ok(msg)) => match msg.to_text() {
@@ -701,10 +2304,12 @@ async fn connect_ws<F: Future<Output = ()> + Send + 'static>(
     };
 
     if authorization_required {
-        if let Some(auth_token) = config.auth_token.clone() {
-            let handshake = AuthorizationRequest { auth_token };
-            let msg = json::to_string(&CM::AuthorizationRequest(handshake))?;
-            log::debug!("Sending authorization message: {msg}");
+        if let Some(handshake) = auth_handshake(config) {
+            let msg = json::to_string(&handshake)?;
+            log::debug!(
+                "Sending authorization message: {}",
+                redact::redact_message(&msg, |_| false)
+            );
             websocket.send(Message::Text(msg)).await?;
 
             match websocket.next().await {
@@ -712,13 +2317,19 @@ async fn connect_ws<F: Future<Output = ()> + Send + 'static>(
                 Some(Ok(Message::Text(msg))) => match serde_json::from_str(&msg) {
                     Ok(SM::Authorized(_)) => {
                         log::debug!("Authorization accepted.");
-                        connected(
-                            ClientSocket::Ws(WsClientSocket::new(websocket)),
-                            on_disconnect,
-                            config,
+                        Ok(Connection {
+                            socket: ws_client_socket(
+                                websocket,
+                                config,
+                                &wire_formats,
+                                &compression,
+                            )
+                            .await?,
                             client_id,
                             protocol_version,
-                        )
+                            authenticated: true,
+                            boot_id,
+                        })
                     }
                     Ok(SM::Err(e)) => {
                         log::error!("Authorization failed: {e}");
@@ -746,26 +2357,66 @@ async fn connect_ws<F: Future<Output = ()> + Send + 'static>(
             }
         } else {
             Err(ConnectionError::AuthorizationError(
-                "Server requires authorization but no auth token was provided.".to_owned(),
+                "Server requires authorization but no credentials were provided.".to_owned(),
             ))
         }
     } else {
-        connected(
-            ClientSocket::Ws(WsClientSocket::new(websocket)),
-            on_disconnect,
-            config,
+        Ok(Connection {
+            socket: ws_client_socket(websocket, config, &wire_formats, &compression).await?,
             client_id,
             protocol_version,
-        )
+            authenticated: false,
+            boot_id,
+        })
+    }
+}
+
+/// Switches to the configured binary wire format if the server supports it.
+async fn ws_client_socket(
+    mut websocket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    config: &Config,
+    wire_formats: &[WireFormat],
+    compressions: &[Compression],
+) -> ConnectionResult<ClientSocket> {
+    let format = config.wire_format;
+    if !format.is_binary() {
+        return Ok(ClientSocket::Ws(WsClientSocket::new(
+            websocket,
+            format,
+            Compression::None,
+        )));
+    }
+    if !wire_formats.contains(&format) {
+        log::warn!("Server does not support wire format {format}, falling back to JSON.");
+        return Ok(ClientSocket::Ws(WsClientSocket::new(
+            websocket,
+            WireFormat::Json,
+            Compression::None,
+        )));
     }
+    let mut compression = config.wire_compression;
+    if !compression.is_none() && !compressions.contains(&compression) {
+        log::warn!("Server does not support {compression} compression, sending uncompressed.");
+        compression = Compression::None;
+    }
+    let msg = json::to_string(&CM::SwitchWireFormat(SwitchWireFormat {
+        format,
+        compression,
+    }))?;
+    log::debug!("Switching wire format to {format} (compression: {compression})");
+    websocket.send(Message::Text(msg)).await?;
+    Ok(ClientSocket::Ws(WsClientSocket::new(
+        websocket,
+        format,
+        compression,
+    )))
 }
 
-async fn connect_tcp<F: Future<Output = ()> + Send + 'static>(
+async fn connect_tcp(
     host_addr: String,
     port: u16,
-    on_disconnect: F,
-    config: Config,
-) -> Result<Worterbuch, ConnectionError> {
+    config: &Config,
+) -> Result<Connection, ConnectionError> {
     let timeout = config.connection_timeout;
     log::debug!(
         "Connecting to server tcp://{host_addr}:{port} (timeout: {} ms) …",
@@ -794,6 +2445,9 @@ async fn connect_tcp<F: Future<Output = ()> + Send + 'static>(
                 version: _,
                 protocol_version,
                 authorization_required,
+                boot_id,
+                wire_formats: _,
+                compression: _,
             },
     } = select! {
         line = tcp_rx.read_line(&mut line_buf) => match line {
@@ -835,11 +2489,13 @@ async fn connect_tcp<F: Future<Output = ()> + Send + 'static>(
     };
 
     if authorization_required {
-        if let Some(auth_token) = config.auth_token.clone() {
-            let handshake = AuthorizationRequest { auth_token };
-            let mut msg = json::to_string(&CM::AuthorizationRequest(handshake))?;
+        if let Some(handshake) = auth_handshake(config) {
+            let mut msg = json::to_string(&handshake)?;
             msg.push('\n');
-            log::debug!("Sending authorization message: {msg}");
+            log::debug!(
+                "Sending authorization message: {}",
+                redact::redact_message(&msg, |_| false)
+            );
             tcp_tx.write_all(msg.as_bytes()).await?;
 
             match tcp_rx.read_line(&mut line_buf).await {
@@ -853,15 +2509,15 @@ async fn connect_tcp<F: Future<Output = ()> + Send + 'static>(
                     match msg {
                         Ok(SM::Authorized(_)) => {
                             log::debug!("Authorization accepted.");
-                            connected(
-                                ClientSocket::Tcp(
+                            Ok(Connection {
+                                socket: ClientSocket::Tcp(
                                     TcpClientSocket::new(tcp_tx, tcp_rx.lines()).await,
                                 ),
-                                on_disconnect,
-                                config,
                                 client_id,
                                 protocol_version,
-                            )
+                                authenticated: true,
+                                boot_id,
+                            })
                         }
                         Ok(SM::Err(e)) => {
                             log::error!("Authorization failed: {e}");
@@ -883,67 +2539,227 @@ async fn connect_tcp<F: Future<Output = ()> + Send + 'static>(
             }
         } else {
             Err(ConnectionError::AuthorizationError(
-                "Server requires authorization but no auth token was provided.".to_owned(),
+                "Server requires authorization but no credentials were provided.".to_owned(),
             ))
         }
     } else {
-        connected(
-            ClientSocket::Tcp(TcpClientSocket::new(tcp_tx, tcp_rx.lines()).await),
-            on_disconnect,
-            config,
+        Ok(Connection {
+            socket: ClientSocket::Tcp(TcpClientSocket::new(tcp_tx, tcp_rx.lines()).await),
             client_id,
             protocol_version,
-        )
+            authenticated: false,
+            boot_id,
+        })
     }
 }
 
 fn connected<F: Future<Output = ()> + Send + 'static>(
-    client_socket: ClientSocket,
+    connection: Connection,
     on_disconnect: F,
     config: Config,
-    client_id: String,
-    protocol_version: ProtocolVersion,
 ) -> Result<Worterbuch, ConnectionError> {
-    // TODO properly implement different protocol versions
-    let supported_protocol_versions = vec!["0.7".to_owned()];
-
-    if !supported_protocol_versions.contains(&protocol_version) {
-        return Err(ConnectionError::WorterbuchError(
-            WorterbuchError::ProtocolNegotiationFailed,
-        ));
-    }
-
     let (stop_tx, stop_rx) = mpsc::channel(1);
     let (cmd_tx, cmd_rx) = mpsc::channel(1);
 
+    let registry = config
+        .subscription_registry
+        .as_ref()
+        .map(SubscriptionRegistry::load);
+    let run_registry = registry.clone();
+    let client_id = connection.client_id.clone();
+    let request_timeout = config.request_timeout;
+
     spawn(async move {
-        run(cmd_rx, client_socket, stop_rx, config).await;
+        run(cmd_rx, connection, stop_rx, config, run_registry).await;
         log::debug!("Connection closed.");
         on_disconnect.await;
     });
 
-    Ok(Worterbuch::new(cmd_tx, stop_tx, client_id))
+    Ok(Worterbuch::new(
+        cmd_tx,
+        stop_tx,
+        client_id,
+        registry,
+        request_timeout,
+    ))
 }
 
 async fn run(
     mut cmd_rx: mpsc::Receiver<Command>,
-    mut client_socket: ClientSocket,
+    mut connection: Connection,
     mut stop_rx: mpsc::Receiver<()>,
     config: Config,
+    registry: Option<SubscriptionRegistry>,
 ) {
     let mut callbacks = Callbacks::default();
     let mut transaction_ids = TransactionIds::default();
+    let mut boot_id = connection.boot_id.clone();
+
+    loop {
+        callbacks.connection_events.emit(ConnectionEvent::Connected);
+        if connection.authenticated {
+            callbacks
+                .connection_events
+                .emit(ConnectionEvent::Authenticated);
+        }
+        if boot_id.is_some() && connection.boot_id != boot_id {
+            log::info!("Server has been restarted.");
+            callbacks
+                .connection_events
+                .emit(ConnectionEvent::ServerRestarted);
+        }
+        boot_id = connection.boot_id.clone();
+
+        let reason = serve(
+            &mut connection.socket,
+            &mut cmd_rx,
+            &mut stop_rx,
+            &config,
+            &registry,
+            &mut callbacks,
+            &mut transaction_ids,
+        )
+        .await;
+
+        if reason == DisconnectReason::ClosedByServer {
+            callbacks
+                .connection_events
+                .emit(ConnectionEvent::ServerShutdown);
+        }
+        let closed_by_client = reason == DisconnectReason::ClosedByClient;
+        callbacks
+            .connection_events
+            .emit(ConnectionEvent::Disconnected(reason));
+
+        persist_registry(&registry).await;
+
+        if closed_by_client {
+            break;
+        }
+
+        // the server does not know about any of our pending requests or subscriptions anymore,
+        // so we drop their callbacks to let the application know they are not going to complete,
+        // unless subscriptions are going to be re-established after reconnecting
+        let mut next_callbacks = Callbacks {
+            all: std::mem::take(&mut callbacks.all),
+            connection_events: std::mem::take(&mut callbacks.connection_events),
+            ..Default::default()
+        };
+        if config.resubscribe {
+            next_callbacks.sub = std::mem::take(&mut callbacks.sub);
+            next_callbacks.psub = std::mem::take(&mut callbacks.psub);
+            next_callbacks.subls = std::mem::take(&mut callbacks.subls);
+            next_callbacks.subscriptions = std::mem::take(&mut callbacks.subscriptions);
+        }
+        callbacks = next_callbacks;
+
+        match reconnect(&config, &mut stop_rx, &mut callbacks).await {
+            Some(it) => {
+                metrics::reconnected();
+                connection = it;
+            }
+            None => break,
+        }
+    }
+}
+
+async fn reconnect(
+    config: &Config,
+    stop_rx: &mut mpsc::Receiver<()>,
+    callbacks: &mut Callbacks,
+) -> Option<Connection> {
+    for attempt in 1..=config.backoff.max_attempts {
+        let delay = config.backoff.delay(attempt);
+        log::info!(
+            "Reconnecting in {} ms (attempt {attempt}/{}) …",
+            delay.as_millis(),
+            config.backoff.max_attempts
+        );
+        callbacks
+            .connection_events
+            .emit(ConnectionEvent::Reconnecting(attempt));
+        select! {
+            _ = stop_rx.recv() => {
+                log::debug!("Shutdown request received.");
+                return None;
+            },
+            _ = sleep(delay) => (),
+        }
+        match connect_socket(config).await {
+            Ok(connection) => {
+                log::info!("Reconnected to server.");
+                return Some(connection);
+            }
+            Err(e) => log::warn!("Reconnect attempt {attempt} failed: {e}"),
+        }
+    }
+    None
+}
+
+async fn serve(
+    client_socket: &mut ClientSocket,
+    cmd_rx: &mut mpsc::Receiver<Command>,
+    stop_rx: &mut mpsc::Receiver<()>,
+    config: &Config,
+    registry: &Option<SubscriptionRegistry>,
+    callbacks: &mut Callbacks,
+    transaction_ids: &mut TransactionIds,
+) -> DisconnectReason {
     let mut last_keepalive_rx = Instant::now();
     let mut last_keepalive_tx = Instant::now();
     let mut keepalive_timer = interval(Duration::from_secs(1));
     keepalive_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut metrics = RequestMetrics::default();
+
+    if let Some(prefix) = config.key_prefix.clone() {
+        log::debug!("Setting key prefix '{prefix}' …");
+        let msg = CM::KeyPrefix(KeyPrefix { prefix });
+        metrics.sent(&msg);
+        if let Err(e) = send_with_timeout(client_socket, msg, config.send_timeout).await {
+            log::error!("Error setting key prefix: {e}");
+            return DisconnectReason::Error(e.to_string());
+        }
+    }
+
+    if let Some(info) = config.client_info.clone() {
+        log::debug!("Identifying as '{}' …", info.name);
+        let msg = CM::ClientInfo(info);
+        metrics.sent(&msg);
+        if let Err(e) = send_with_timeout(client_socket, msg, config.send_timeout).await {
+            log::error!("Error sending client info: {e}");
+            return DisconnectReason::Error(e.to_string());
+        }
+    }
+
+    let resubscriptions: Vec<CM> = callbacks
+        .subscriptions
+        .values()
+        .cloned()
+        .map(resubscription)
+        .collect();
+    if !resubscriptions.is_empty() {
+        log::info!(
+            "Re-establishing {} subscription(s) …",
+            resubscriptions.len()
+        );
+    }
+    for msg in resubscriptions {
+        if let Some(transaction_id) = msg.transaction_id() {
+            callbacks.refreshing.insert(transaction_id);
+        }
+        metrics.sent(&msg);
+        if let Err(e) = send_with_timeout(client_socket, msg, config.send_timeout).await {
+            log::error!("Error re-establishing subscription: {e}");
+            return DisconnectReason::Error(e.to_string());
+        }
+    }
 
     loop {
         log::trace!("loop: wait for command / ws message / shutdown request");
         select! {
             _ = stop_rx.recv() => {
                 log::debug!("Shutdown request received.");
-                break;
+                return DisconnectReason::ClosedByClient;
             },
             _ = keepalive_timer.tick() => {
                 let lag = last_keepalive_tx - last_keepalive_rx;
@@ -953,40 +2769,55 @@ async fn run(
                 }
                 if lag >= config.keepalive_timeout {
                     log::error!("Server has been inactive for too long. Disconnecting.");
-                    break;
+                    return DisconnectReason::KeepaliveTimeout;
                 }
                 if last_keepalive_tx.elapsed().as_secs() >= 1 {
                     last_keepalive_tx = Instant::now();
-                    if let Err(e) = send_keepalive(&mut client_socket, config.send_timeout).await {
+                    metrics.sent(&CM::Keepalive);
+                    if let Err(e) = send_keepalive(client_socket, config.send_timeout).await {
                         log::error!("Error sending keepalive signal: {e}");
-                        break;
+                        return DisconnectReason::Error(e.to_string());
                     }
                 }
+                persist_registry(registry).await;
             },
             ws_msg = client_socket.receive_msg() => {
                 last_keepalive_rx = Instant::now();
-                match process_incoming_server_message(ws_msg, &mut callbacks).await {
-                    Ok(ControlFlow::Break(_)) => break,
+                if let Ok(Some(msg)) = &ws_msg {
+                    metrics.received(msg);
+                    if let Some(registry) = registry {
+                        registry.track_message(msg);
+                    }
+                }
+                match process_incoming_server_message(ws_msg, callbacks).await {
+                    Ok(ControlFlow::Break(reason)) => return reason,
                     Err(e) => {
                         log::error!("Error processing server message: {e}");
-                        break;
+                        return DisconnectReason::Error(e.to_string());
                     },
                     _ => log::trace!("websocket message processing done")
                 }
             },
             cmd = cmd_rx.recv() => {
-                match process_incoming_command(cmd, &mut callbacks, &mut transaction_ids).await {
+                match process_incoming_command(cmd, callbacks, transaction_ids).await {
                     Ok(ControlFlow::Continue(msg)) => if let Some(msg) = msg {
                         last_keepalive_tx = Instant::now();
-                        if let Err(e) = send_with_timeout(&mut client_socket, msg, config.send_timeout).await {
+                        if let Some(registry) = registry {
+                            registry.track_command(&msg);
+                        }
+                        if config.resubscribe {
+                            track_subscription(&msg, callbacks);
+                        }
+                        metrics.sent(&msg);
+                        if let Err(e) = send_with_timeout(client_socket, msg, config.send_timeout).await {
                             log::error!("Error sending message to server: {e}");
-                            break;
+                            return DisconnectReason::Error(e.to_string());
                         }
                     },
-                    Ok(ControlFlow::Break(_)) => break,
+                    Ok(ControlFlow::Break(_)) => return DisconnectReason::ClosedByClient,
                     Err(e) => {
                         log::error!("Error processing command: {e}");
-                        break;
+                        return DisconnectReason::Error(e.to_string());
                     },
                 }
             }
@@ -994,6 +2825,89 @@ async fn run(
     }
 }
 
+/// Remembers subscriptions so they can be re-established after reconnecting.
+fn track_subscription(msg: &CM, callbacks: &mut Callbacks) {
+    match msg {
+        CM::Subscribe(Subscribe { transaction_id, .. })
+        | CM::PSubscribe(PSubscribe { transaction_id, .. })
+        | CM::SubscribeLs(SubscribeLs { transaction_id, .. }) => {
+            callbacks.subscriptions.insert(*transaction_id, msg.clone());
+        }
+        CM::Unsubscribe(Unsubscribe { transaction_id })
+        | CM::UnsubscribeLs(UnsubscribeLs { transaction_id }) => {
+            callbacks.subscriptions.remove(transaction_id);
+        }
+        _ => (),
+    }
+}
+
+/// Turns a tracked subscription into a request that re-establishes it and delivers its current
+/// state, followed by an initial state marker.
+fn resubscription(msg: CM) -> CM {
+    match msg {
+        CM::Subscribe(msg) => CM::Subscribe(Subscribe {
+            live_only: Some(false),
+            initial_state_marker: Some(true),
+            ..msg
+        }),
+        CM::PSubscribe(msg) => CM::PSubscribe(PSubscribe {
+            live_only: Some(false),
+            initial_state_marker: Some(true),
+            ..msg
+        }),
+        msg => msg,
+    }
+}
+
+/// Marks a re-established subscription as up to date and reports once all of them are.
+fn refreshed(transaction_id: TransactionId, callbacks: &mut Callbacks) {
+    if callbacks.refreshing.remove(&transaction_id) && callbacks.refreshing.is_empty() {
+        log::info!("All subscriptions have been re-established.");
+        callbacks
+            .connection_events
+            .emit(ConnectionEvent::StateRefreshed);
+    }
+}
+
+async fn persist_registry(registry: &Option<SubscriptionRegistry>) {
+    if let Some(registry) = registry {
+        if let Err(e) = registry.persist().await {
+            log::error!("Error persisting subscription registry: {e}");
+        }
+    }
+}
+
+async fn await_reply(
+    replies: &mut mpsc::UnboundedReceiver<(Option<Value>, Key)>,
+    correlation_id: &str,
+) -> ConnectionResult<Value> {
+    while let Some((value, _)) = replies.recv().await {
+        let Some(value) = value else {
+            continue;
+        };
+        match json::from_value::<CorrelatedValue>(value) {
+            Ok(reply) if reply.correlation_id.as_deref() == Some(correlation_id) => {
+                return Ok(reply.value)
+            }
+            Ok(_) => log::debug!("Ignoring reply with non-matching correlation ID."),
+            Err(e) => log::debug!("Ignoring uncorrelated reply: {e}"),
+        }
+    }
+    Err(ConnectionError::IoError(io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        "connection closed before a reply was received",
+    )))
+}
+
+/// Waits for the answer to a request. On timeout its callback stays registered until the
+/// server answers after all or the connection is lost.
+async fn await_response<T>(rx: oneshot::Receiver<T>, timeout: Duration) -> ConnectionResult<T> {
+    match tokio::time::timeout(timeout, rx).await {
+        Ok(res) => Ok(res?),
+        Err(_) => Err(ConnectionError::Timeout),
+    }
+}
+
 async fn send_with_timeout(
     sock: &mut ClientSocket,
     msg: ClientMessage,
@@ -1020,14 +2934,140 @@ async fn process_incoming_command(
                     transaction_id,
                     key,
                     value,
+                    return_previous: None,
+                }))
+            }
+            Command::SetMany(key_value_pairs, callback) => {
+                callback.send(transaction_id).expect("error in callback");
+                Some(CM::SetMany(SetMany {
+                    transaction_id,
+                    key_value_pairs,
+                }))
+            }
+            Command::GetAndSet(key, value, callback) => {
+                callbacks.get_and_set.insert(transaction_id, callback);
+                Some(CM::Set(Set {
+                    transaction_id,
+                    key,
+                    value,
+                    return_previous: Some(true),
+                }))
+            }
+            Command::GetAt(key, pointer, callback) => {
+                callbacks.get_at.insert(transaction_id, callback);
+                Some(CM::GetAt(GetAt {
+                    transaction_id,
+                    key,
+                    pointer,
+                }))
+            }
+            Command::SetAt(key, pointer, value, callback) => {
+                callbacks.set_at.insert(transaction_id, callback);
+                Some(CM::SetAt(SetAt {
+                    transaction_id,
+                    key,
+                    pointer,
+                    value,
+                }))
+            }
+            Command::Increment(key, delta, callback) => {
+                callbacks.increment.insert(transaction_id, callback);
+                Some(CM::Increment(Increment {
+                    transaction_id,
+                    key,
+                    delta,
+                }))
+            }
+            Command::ArrayPush(key, value, callback) => {
+                callbacks.arrays.insert(transaction_id, callback);
+                Some(CM::ArrayPush(ArrayPush {
+                    transaction_id,
+                    key,
+                    value,
+                }))
+            }
+            Command::ArrayPop(key, callback) => {
+                callbacks.arrays.insert(transaction_id, callback);
+                Some(CM::ArrayPop(ArrayPop {
+                    transaction_id,
+                    key,
+                }))
+            }
+            Command::ArrayRemove(key, value, callback) => {
+                callbacks.arrays.insert(transaction_id, callback);
+                Some(CM::ArrayRemove(ArrayRemove {
+                    transaction_id,
+                    key,
+                    value,
+                }))
+            }
+            Command::SetExpiring(key, value, ttl, callback) => {
+                callback.send(transaction_id).expect("error in callback");
+                Some(CM::SetExpiring(SetExpiring {
+                    transaction_id,
+                    key,
+                    value,
+                    ttl_millis: ttl.as_millis() as u64,
+                }))
+            }
+            Command::Expire(key, ttl, callback) => {
+                callback.send(transaction_id).expect("error in callback");
+                Some(CM::Expire(Expire {
+                    transaction_id,
+                    key,
+                    ttl_millis: ttl.map(|ttl| ttl.as_millis() as u64),
+                }))
+            }
+            Command::HintInvalidation(key, stale_in, callback) => {
+                callback.send(transaction_id).expect("error in callback");
+                Some(CM::HintInvalidation(HintInvalidation {
+                    transaction_id,
+                    key,
+                    stale_in_millis: stale_in.map(|d| d.as_millis() as u64),
+                }))
+            }
+            Command::SetIf(key, value, exists, callback) => {
+                callbacks.set_if.insert(transaction_id, callback);
+                Some(if exists {
+                    CM::SetXx(SetXx {
+                        transaction_id,
+                        key,
+                        value,
+                    })
+                } else {
+                    CM::SetNx(SetNx {
+                        transaction_id,
+                        key,
+                        value,
+                    })
+                })
+            }
+            Command::Cas(key, expected, version, value, callback) => {
+                callbacks.cas.insert(transaction_id, callback);
+                Some(CM::CompareAndSwap(CompareAndSwap {
+                    transaction_id,
+                    key,
+                    expected,
+                    version,
+                    value,
+                }))
+            }
+            Command::PublishRetained(key, value, callback) => {
+                callback.send(transaction_id).expect("error in callback");
+                Some(CM::PublishRetained(PublishRetained {
+                    transaction_id,
+                    key,
+                    value,
                 }))
             }
-            Command::Publish(key, value, callback) => {
+            Command::Publish(key, value, correlation_id, reply_to, callback) => {
                 callback.send(transaction_id).expect("error in callback");
                 Some(CM::Publish(Publish {
                     transaction_id,
                     key,
                     value,
+                    correlation_id,
+                    reply_to,
                 }))
             }
             Command::Get(key, callback) => {
@@ -1049,6 +3089,11 @@ async fn process_incoming_command(
                 Some(CM::PGet(PGet {
                     transaction_id,
                     request_pattern,
+                    chunk_size: None,
+                    sort: None,
+                    offset: None,
+                    limit: None,
+                    case_insensitive: None,
                 }))
             }
             Command::PGetAsync(request_pattern, callback) => {
@@ -1056,6 +3101,66 @@ async fn process_incoming_command(
                 Some(CM::PGet(PGet {
                     transaction_id,
                     request_pattern,
+                    chunk_size: None,
+                    sort: None,
+                    offset: None,
+                    limit: None,
+                    case_insensitive: None,
+                }))
+            }
+            Command::PGetSorted(request_pattern, sort, offset, limit, callback) => {
+                callbacks.pget.insert(transaction_id, callback);
+                Some(CM::PGet(PGet {
+                    transaction_id,
+                    request_pattern,
+                    chunk_size: None,
+                    sort: Some(sort),
+                    offset,
+                    limit,
+                    case_insensitive: None,
+                }))
+            }
+            Command::PGetIgnoringCase(request_pattern, callback) => {
+                callbacks.pget.insert(transaction_id, callback);
+                Some(CM::PGet(PGet {
+                    transaction_id,
+                    request_pattern,
+                    chunk_size: None,
+                    sort: None,
+                    offset: None,
+                    limit: None,
+                    case_insensitive: Some(true),
+                }))
+            }
+            Command::PKeys(request_pattern, callback) => {
+                callbacks.pkeys.insert(transaction_id, callback);
+                Some(CM::PKeys(PKeys {
+                    transaction_id,
+                    request_pattern,
+                }))
+            }
+            Command::PKeysAsync(request_pattern, callback) => {
+                callback.send(transaction_id).expect("error in callback");
+                Some(CM::PKeys(PKeys {
+                    transaction_id,
+                    request_pattern,
+                }))
+            }
+            Command::PGetChunked(request_pattern, chunk_size, tid_callback, chunk_callback) => {
+                callbacks
+                    .pget_chunked
+                    .insert(transaction_id, chunk_callback);
+                tid_callback
+                    .send(transaction_id)
+                    .expect("error in callback");
+                Some(CM::PGet(PGet {
+                    transaction_id,
+                    request_pattern,
+                    chunk_size: Some(chunk_size),
+                    sort: None,
+                    offset: None,
+                    limit: None,
+                    case_insensitive: None,
                 }))
             }
             Command::Delete(key, callback) => {
@@ -1086,6 +3191,75 @@ async fn process_incoming_command(
                     request_pattern,
                 }))
             }
+            Command::Reserve(request_pattern, callback) => {
+                callbacks.reservations.insert(transaction_id, callback);
+                Some(CM::Reserve(Reserve {
+                    transaction_id,
+                    request_pattern,
+                }))
+            }
+            Command::Release(request_pattern, callback) => {
+                callbacks.reservations.insert(transaction_id, callback);
+                Some(CM::Release(Release {
+                    transaction_id,
+                    request_pattern,
+                }))
+            }
+            Command::Lock(key, lease, wait, callback) => {
+                callbacks.locks.insert(transaction_id, callback);
+                let lease_millis = lease.as_millis() as u64;
+                Some(if wait {
+                    CM::Lock(Lock {
+                        transaction_id,
+                        key,
+                        lease_millis,
+                    })
+                } else {
+                    CM::TryLock(TryLock {
+                        transaction_id,
+                        key,
+                        lease_millis,
+                    })
+                })
+            }
+            Command::Unlock(key, callback) => {
+                callbacks.locks.insert(transaction_id, callback);
+                Some(CM::Unlock(Unlock {
+                    transaction_id,
+                    key,
+                }))
+            }
+            Command::Restore(request_pattern, callback) => {
+                callbacks.restore.insert(transaction_id, callback);
+                Some(CM::Restore(Restore {
+                    transaction_id,
+                    request_pattern,
+                }))
+            }
+            Command::StartRecording(name, request_pattern, callback) => {
+                callback.send(transaction_id).expect("error in callback");
+                Some(CM::StartRecording(StartRecording {
+                    transaction_id,
+                    name,
+                    request_pattern,
+                }))
+            }
+            Command::StopRecording(name, callback) => {
+                callback.send(transaction_id).expect("error in callback");
+                Some(CM::StopRecording(StopRecording {
+                    transaction_id,
+                    name,
+                }))
+            }
+            Command::ReplayRecording(name, target_prefix, speed, callback) => {
+                callback.send(transaction_id).expect("error in callback");
+                Some(CM::ReplayRecording(ReplayRecording {
+                    transaction_id,
+                    name,
+                    target_prefix,
+                    speed,
+                }))
+            }
             Command::Ls(parent, callback) => {
                 callbacks.ls.insert(transaction_id, callback);
                 Some(CM::Ls(Ls {
@@ -1100,8 +3274,19 @@ async fn process_incoming_command(
                     parent,
                 }))
             }
-            Command::Subscribe(key, unique, tid_callback, value_callback, live_only) => {
+            Command::Subscribe(
+                key,
+                unique,
+                tid_callback,
+                value_callback,
+                live_only,
+                initial_state_callback,
+            ) => {
                 callbacks.sub.insert(transaction_id, value_callback);
+                let initial_state_marker = initial_state_callback.is_some().then_some(true);
+                if let Some(cb) = initial_state_callback {
+                    callbacks.initial_state.insert(transaction_id, cb);
+                }
                 tid_callback
                     .send(transaction_id)
                     .expect("error in callback");
@@ -1110,6 +3295,7 @@ async fn process_incoming_command(
                     key,
                     unique,
                     live_only: Some(live_only),
+                    initial_state_marker,
                 }))
             }
             Command::SubscribeAsync(key, unique, callback, live_only) => {
@@ -1119,6 +3305,7 @@ async fn process_incoming_command(
                     key,
                     unique,
                     live_only: Some(live_only),
+                    initial_state_marker: None,
                 }))
             }
             Command::PSubscribe(
@@ -1128,8 +3315,13 @@ async fn process_incoming_command(
                 event_callback,
                 aggregate_events,
                 live_only,
+                initial_state_callback,
             ) => {
                 callbacks.psub.insert(transaction_id, event_callback);
+                let initial_state_marker = initial_state_callback.is_some().then_some(true);
+                if let Some(cb) = initial_state_callback {
+                    callbacks.initial_state.insert(transaction_id, cb);
+                }
                 tid_callback
                     .send(transaction_id)
                     .expect("error in callback");
@@ -1139,6 +3331,7 @@ async fn process_incoming_command(
                     unique,
                     aggregate_events,
                     live_only: Some(live_only),
+                    initial_state_marker,
                 }))
             }
             Command::PSubscribeAsync(
@@ -1155,13 +3348,31 @@ async fn process_incoming_command(
                     unique,
                     aggregate_events,
                     live_only: Some(live_only),
+                    initial_state_marker: None,
                 }))
             }
             Command::Unsubscribe(transaction_id) => {
                 callbacks.sub.remove(&transaction_id);
                 callbacks.psub.remove(&transaction_id);
+                callbacks.initial_state.remove(&transaction_id);
+                callbacks.seqs.remove(&transaction_id);
+                callbacks.events.remove(&transaction_id);
                 Some(CM::Unsubscribe(Unsubscribe { transaction_id }))
             }
+            Command::Resync(transaction_id) => Some(CM::Resync(Resync { transaction_id })),
+            Command::Sync(persist, callback) => {
+                callbacks.sync.insert(transaction_id, callback);
+                Some(CM::Sync(SyncRequest {
+                    transaction_id,
+                    persist,
+                }))
+            }
+            Command::Cancel(transaction_id) => {
+                callbacks.pget.remove(&transaction_id);
+                callbacks.pget_chunked.remove(&transaction_id);
+                callbacks.pdel.remove(&transaction_id);
+                Some(CM::Cancel(Cancel { transaction_id }))
+            }
             Command::SubscribeLs(parent, tid_callback, children_callback) => {
                 callbacks.subls.insert(transaction_id, children_callback);
                 tid_callback
@@ -1183,10 +3394,36 @@ async fn process_incoming_command(
                 callbacks.subls.remove(&transaction_id);
                 Some(CM::UnsubscribeLs(UnsubscribeLs { transaction_id }))
             }
+            Command::SubscribeEvents(tid_callback, event_callback) => {
+                callbacks.events.insert(transaction_id, event_callback);
+                tid_callback
+                    .send(transaction_id)
+                    .expect("error in callback");
+                Some(CM::SubscribeEvents(SubscribeEvents { transaction_id }))
+            }
             Command::AllMessages(tx) => {
                 callbacks.all.push(tx);
                 None
             }
+            Command::ConnectionEvents(tx) => {
+                callbacks.connection_events.subscribe(tx);
+                None
+            }
+            Command::WhoSubscribes(request_pattern, callback) => {
+                callbacks.who_subscribes.insert(transaction_id, callback);
+                Some(CM::WhoSubscribes(WhoSubscribes {
+                    transaction_id,
+                    request_pattern,
+                }))
+            }
+            Command::GetHistory(key, limit, callback) => {
+                callbacks.history.insert(transaction_id, callback);
+                Some(CM::GetHistory(GetHistory {
+                    transaction_id,
+                    key,
+                    limit,
+                }))
+            }
         };
         Ok(ControlFlow::Continue(cm))
     } else {
@@ -1198,7 +3435,7 @@ async fn process_incoming_command(
 async fn process_incoming_server_message(
     msg: ConnectionResult<Option<ServerMessage>>,
     callbacks: &mut Callbacks,
-) -> ConnectionResult<ControlFlow<()>> {
+) -> ConnectionResult<ControlFlow<DisconnectReason>> {
     match msg {
         Ok(Some(msg)) => {
             deliver_generic(&msg, callbacks);
@@ -1206,18 +3443,24 @@ async fn process_incoming_server_message(
                 SM::State(state) => deliver_state(state, callbacks).await?,
                 SM::PState(pstate) => deliver_pstate(pstate, callbacks).await?,
                 SM::LsState(ls) => deliver_ls(ls, callbacks).await?,
+                SM::Keys(keys) => deliver_keys(keys, callbacks),
+                SM::Event(event) => deliver_event(event, callbacks).await?,
+                SM::Subscribers(subs) => deliver_subscribers(subs, callbacks),
+                SM::History(history) => deliver_history(history, callbacks),
+                SM::InitialStateComplete(ack) => deliver_initial_state_complete(ack, callbacks),
                 SM::Err(err) => deliver_err(err, callbacks).await,
-                SM::Ack(_) | SM::Welcome(_) | SM::Authorized(_) | SM::Keepalive => (),
+                SM::Ack(ack) => deliver_ack(ack, callbacks),
+                SM::Welcome(_) | SM::Authorized(_) | SM::Keepalive => (),
             }
             Ok(ControlFlow::Continue(()))
         }
         Ok(None) => {
             log::warn!("Connection closed.");
-            Ok(ControlFlow::Break(()))
+            Ok(ControlFlow::Break(DisconnectReason::ClosedByServer))
         }
         Err(e) => {
             log::error!("Error receiving message: {e}");
-            Ok(ControlFlow::Break(()))
+            Ok(ControlFlow::Break(DisconnectReason::Error(e.to_string())))
         }
     }
 }
@@ -1233,12 +3476,33 @@ fn deliver_generic(msg: &ServerMessage, callbacks: &mut Callbacks) {
 }
 
 async fn deliver_state(state: State, callbacks: &mut Callbacks) -> ConnectionResult<()> {
+    check_seq(state.transaction_id, state.seq, callbacks);
     if let Some(cb) = callbacks.get.remove(&state.transaction_id) {
         if let StateEvent::KeyValue(kvp) = &state.event {
             cb.send((Some(kvp.value.clone()), state.transaction_id))
                 .expect("error in callback");
         }
     }
+    if let Some(cb) = callbacks.get_and_set.remove(&state.transaction_id) {
+        if let StateEvent::KeyValue(kvp) = &state.event {
+            cb.send(Ok(Some(kvp.value.clone()))).ok();
+        }
+    }
+    if let Some(cb) = callbacks.get_at.remove(&state.transaction_id) {
+        if let StateEvent::KeyValue(kvp) = &state.event {
+            cb.send(Ok(kvp.value.clone())).ok();
+        }
+    }
+    if let Some(cb) = callbacks.increment.remove(&state.transaction_id) {
+        if let StateEvent::KeyValue(kvp) = &state.event {
+            cb.send(Ok(kvp.value.clone())).ok();
+        }
+    }
+    if let Some(cb) = callbacks.arrays.remove(&state.transaction_id) {
+        if let StateEvent::KeyValue(kvp) = &state.event {
+            cb.send(Ok(kvp.value.clone())).ok();
+        }
+    }
     if let Some(cb) = callbacks.del.remove(&state.transaction_id) {
         if let StateEvent::Deleted(kvp) = &state.event {
             cb.send((Some(kvp.value.clone()), state.transaction_id))
@@ -1256,6 +3520,17 @@ async fn deliver_state(state: State, callbacks: &mut Callbacks) -> ConnectionRes
 }
 
 async fn deliver_pstate(pstate: PState, callbacks: &mut Callbacks) -> ConnectionResult<()> {
+    check_seq(pstate.transaction_id, pstate.seq, callbacks);
+    if let Some(cb) = callbacks.pget_chunked.get(&pstate.transaction_id) {
+        let last = pstate.more != Some(true);
+        if let PStateEvent::KeyValuePairs(kvps) = pstate.event {
+            cb.send(kvps).ok();
+        }
+        if last {
+            callbacks.pget_chunked.remove(&pstate.transaction_id);
+        }
+        return Ok(());
+    }
     if let Some(cb) = callbacks.pget.remove(&pstate.transaction_id) {
         if let PStateEvent::KeyValuePairs(kvps) = &pstate.event {
             cb.send((kvps.clone(), pstate.transaction_id))
@@ -1268,6 +3543,12 @@ async fn deliver_pstate(pstate: PState, callbacks: &mut Callbacks) -> Connection
                 .expect("error in callback");
         }
     }
+    if let Some(cb) = callbacks.restore.remove(&pstate.transaction_id) {
+        if let PStateEvent::KeyValuePairs(kvps) = &pstate.event {
+            cb.send((kvps.clone(), pstate.transaction_id))
+                .expect("error in callback");
+        }
+    }
     if let Some(cb) = callbacks.psub.get(&pstate.transaction_id) {
         cb.send(pstate.event)?;
     }
@@ -1286,7 +3567,83 @@ async fn deliver_ls(ls: LsState, callbacks: &mut Callbacks) -> ConnectionResult<
     Ok(())
 }
 
+fn deliver_keys(keys: KeysState, callbacks: &mut Callbacks) {
+    if let Some(cb) = callbacks.pkeys.remove(&keys.transaction_id) {
+        cb.send(Ok(keys.keys)).ok();
+    }
+}
+
+async fn deliver_event(event: EventState, callbacks: &mut Callbacks) -> ConnectionResult<()> {
+    if let Some(cb) = callbacks.events.get(&event.transaction_id) {
+        cb.send(event.event)?;
+    }
+    Ok(())
+}
+
+fn deliver_history(history: HistoryState, callbacks: &mut Callbacks) {
+    if let Some(cb) = callbacks.history.remove(&history.transaction_id) {
+        cb.send((history.entries, history.transaction_id))
+            .expect("error in callback");
+    }
+}
+
+fn deliver_subscribers(subs: SubscribersState, callbacks: &mut Callbacks) {
+    if let Some(cb) = callbacks.who_subscribes.remove(&subs.transaction_id) {
+        cb.send((subs.subscribers, subs.transaction_id))
+            .expect("error in callback");
+    }
+}
+
+/// Reports messages of a subscription that were lost on their way from the server.
+fn check_seq(transaction_id: TransactionId, seq: Option<u64>, callbacks: &mut Callbacks) {
+    let Some(seq) = seq else {
+        return;
+    };
+    let last = callbacks.seqs.insert(transaction_id, seq).unwrap_or(0);
+    if seq > last + 1 {
+        let missed = seq - last - 1;
+        log::warn!("Missed {missed} message(s) of subscription {transaction_id}.");
+        callbacks
+            .connection_events
+            .emit(ConnectionEvent::MessagesMissed(transaction_id, missed));
+    }
+}
+
+fn deliver_initial_state_complete(ack: Ack, callbacks: &mut Callbacks) {
+    refreshed(ack.transaction_id, callbacks);
+    if let Some(cb) = callbacks.initial_state.remove(&ack.transaction_id) {
+        cb.send(()).ok();
+    }
+}
+
+fn deliver_ack(ack: Ack, callbacks: &mut Callbacks) {
+    if let Some(cb) = callbacks.cas.remove(&ack.transaction_id) {
+        cb.send(Ok(())).ok();
+    }
+    if let Some(cb) = callbacks.set_at.remove(&ack.transaction_id) {
+        cb.send(Ok(())).ok();
+    }
+    if let Some(cb) = callbacks.reservations.remove(&ack.transaction_id) {
+        cb.send(Ok(())).ok();
+    }
+    if let Some(cb) = callbacks.locks.remove(&ack.transaction_id) {
+        cb.send(Ok(())).ok();
+    }
+    if let Some(cb) = callbacks.set_if.remove(&ack.transaction_id) {
+        cb.send(Ok(())).ok();
+    }
+    if let Some(cb) = callbacks.sync.remove(&ack.transaction_id) {
+        cb.send(Ok(())).ok();
+    }
+    if let Some(cb) = callbacks.get_and_set.remove(&ack.transaction_id) {
+        cb.send(Ok(None)).ok();
+    }
+}
+
 async fn deliver_err(err: Err, callbacks: &mut Callbacks) {
+    callbacks.pget_chunked.remove(&err.transaction_id);
+    callbacks.initial_state.remove(&err.transaction_id);
+    refreshed(err.transaction_id, callbacks);
     if let Some(cb) = callbacks.get.remove(&err.transaction_id) {
         cb.send((None, err.transaction_id))
             .expect("error in callback");
@@ -1295,6 +3652,51 @@ async fn deliver_err(err: Err, callbacks: &mut Callbacks) {
         cb.send((None, err.transaction_id))
             .expect("error in callback");
     }
+    if let Some(cb) = callbacks.restore.remove(&err.transaction_id) {
+        cb.send((KeyValuePairs::new(), err.transaction_id))
+            .expect("error in callback");
+    }
+    if let Some(cb) = callbacks.who_subscribes.remove(&err.transaction_id) {
+        cb.send((Vec::new(), err.transaction_id))
+            .expect("error in callback");
+    }
+    if let Some(cb) = callbacks.history.remove(&err.transaction_id) {
+        cb.send((Vec::new(), err.transaction_id))
+            .expect("error in callback");
+    }
+    if let Some(cb) = callbacks.pkeys.remove(&err.transaction_id) {
+        cb.send(Err(err.clone())).ok();
+    }
+    if let Some(cb) = callbacks.cas.remove(&err.transaction_id) {
+        cb.send(Err(err.clone())).ok();
+    }
+    if let Some(cb) = callbacks.reservations.remove(&err.transaction_id) {
+        cb.send(Err(err.clone())).ok();
+    }
+    if let Some(cb) = callbacks.locks.remove(&err.transaction_id) {
+        cb.send(Err(err.clone())).ok();
+    }
+    if let Some(cb) = callbacks.set_if.remove(&err.transaction_id) {
+        cb.send(Err(err.clone())).ok();
+    }
+    if let Some(cb) = callbacks.sync.remove(&err.transaction_id) {
+        cb.send(Err(err.clone())).ok();
+    }
+    if let Some(cb) = callbacks.get_and_set.remove(&err.transaction_id) {
+        cb.send(Err(err.clone())).ok();
+    }
+    if let Some(cb) = callbacks.get_at.remove(&err.transaction_id) {
+        cb.send(Err(err.clone())).ok();
+    }
+    if let Some(cb) = callbacks.set_at.remove(&err.transaction_id) {
+        cb.send(Err(err.clone())).ok();
+    }
+    if let Some(cb) = callbacks.increment.remove(&err.transaction_id) {
+        cb.send(Err(err.clone())).ok();
+    }
+    if let Some(cb) = callbacks.arrays.remove(&err.transaction_id) {
+        cb.send(Err(err)).ok();
+    }
 }
 
 async fn send_keepalive(websocket: &mut ClientSocket, timeout: Duration) -> ConnectionResult<()> {
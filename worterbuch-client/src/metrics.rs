@@ -0,0 +1,130 @@
+/*
+ *  Worterbuch client metrics
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Optional client health metrics. With the `metrics` feature enabled, the client reports its
+//! traffic through the [`metrics`](https://docs.rs/metrics) facade, so applications can export it
+//! with whatever recorder they already use. Without the feature all hooks are no-ops.
+//!
+//! | name                                         | type      | labels |
+//! |----------------------------------------------|-----------|--------|
+//! | `worterbuch_client_messages_sent_total`      | counter   | `type` |
+//! | `worterbuch_client_messages_received_total`  | counter   | `type` |
+//! | `worterbuch_client_request_duration_seconds` | histogram | `type` |
+//! | `worterbuch_client_reconnects_total`         | counter   |        |
+
+#[cfg(feature = "metrics")]
+use std::{collections::HashMap, time::Instant};
+#[cfg(feature = "metrics")]
+use worterbuch_common::TransactionId;
+use worterbuch_common::{ClientMessage as CM, ServerMessage as SM};
+
+pub const MESSAGES_SENT: &str = "worterbuch_client_messages_sent_total";
+pub const MESSAGES_RECEIVED: &str = "worterbuch_client_messages_received_total";
+pub const REQUEST_DURATION: &str = "worterbuch_client_request_duration_seconds";
+pub const RECONNECTS: &str = "worterbuch_client_reconnects_total";
+
+/// Tracks the messages of a single connection. The latency of a request is the time between
+/// sending it and receiving the first response carrying its transaction ID.
+#[derive(Default)]
+pub(crate) struct RequestMetrics {
+    #[cfg(feature = "metrics")]
+    pending: HashMap<TransactionId, (&'static str, Instant)>,
+}
+
+impl RequestMetrics {
+    #[cfg(feature = "metrics")]
+    pub fn sent(&mut self, msg: &CM) {
+        let kind = client_message_type(msg);
+        ::metrics::counter!(MESSAGES_SENT, "type" => kind).increment(1);
+        // transaction ID 0 is used for handshake messages, not all of which get a response
+        if let Some(tid) = msg.transaction_id().filter(|tid| *tid != 0) {
+            self.pending.insert(tid, (kind, Instant::now()));
+        }
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    pub fn sent(&mut self, _msg: &CM) {}
+
+    #[cfg(feature = "metrics")]
+    pub fn received(&mut self, msg: &SM) {
+        ::metrics::counter!(MESSAGES_RECEIVED, "type" => server_message_type(msg)).increment(1);
+        if let Some((kind, sent)) = msg
+            .transaction_id()
+            .and_then(|tid| self.pending.remove(&tid))
+        {
+            ::metrics::histogram!(REQUEST_DURATION, "type" => kind)
+                .record(sent.elapsed().as_secs_f64());
+        }
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    pub fn received(&mut self, _msg: &SM) {}
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn reconnected() {
+    ::metrics::counter!(RECONNECTS).increment(1);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn reconnected() {}
+
+pub fn client_message_type(msg: &CM) -> &'static str {
+    msg.message_type()
+}
+
+pub fn server_message_type(msg: &SM) -> &'static str {
+    match msg {
+        SM::Welcome(_) => "welcome",
+        SM::PState(_) => "pState",
+        SM::Ack(_) => "ack",
+        SM::State(_) => "state",
+        SM::Err(_) => "err",
+        SM::Authorized(_) => "authorized",
+        SM::LsState(_) => "lsState",
+        SM::Keys(_) => "keys",
+        SM::Event(_) => "event",
+        SM::Subscribers(_) => "subscribers",
+        SM::History(_) => "history",
+        SM::InitialStateComplete(_) => "initialStateComplete",
+        SM::Keepalive => "keepalive",
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod test {
+    use super::*;
+    use worterbuch_common::{Ack, Get};
+
+    #[test]
+    fn requests_are_completed_by_their_first_response() {
+        let mut metrics = RequestMetrics::default();
+        metrics.sent(&CM::Get(Get {
+            transaction_id: 1,
+            key: "a".to_owned(),
+        }));
+        metrics.sent(&CM::Keepalive);
+        assert_eq!(metrics.pending.len(), 1);
+
+        metrics.received(&SM::Ack(Ack { transaction_id: 2 }));
+        assert_eq!(metrics.pending.len(), 1);
+        metrics.received(&SM::Ack(Ack { transaction_id: 1 }));
+        assert!(metrics.pending.is_empty());
+    }
+}
@@ -0,0 +1,306 @@
+/*
+ *  Worterbuch client connection pool module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Spreads the requests of high-throughput producers over several connections to the same
+//! server, so they don't all have to go through a single socket.
+
+use crate::{cache::Cached, config::Config, connect, Worterbuch};
+use futures_util::future::try_join_all;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use worterbuch_common::{
+    error::ConnectionResult, Key, KeyValuePairs, PStateEvent, RegularKeySegment, RequestPattern,
+    TransactionId, TypedKeyValuePairs, TypedStateEvents, Value,
+};
+
+/// A facade over several connections to the same server. Requests are dispatched to the
+/// connections round-robin, so their order is only preserved per connection. Subscriptions are
+/// all pinned to the first connection, which keeps their transaction IDs unique and lets them be
+/// cancelled through the pool.
+#[derive(Clone)]
+pub struct Pool {
+    connections: Vec<Worterbuch>,
+    next: Arc<AtomicUsize>,
+}
+
+impl Pool {
+    /// Creates a pool of already established connections.
+    pub fn new(connections: Vec<Worterbuch>) -> Self {
+        assert!(
+            !connections.is_empty(),
+            "connection pool needs at least one connection"
+        );
+        Self {
+            connections,
+            next: Arc::default(),
+        }
+    }
+
+    pub fn connections(&self) -> &[Worterbuch] {
+        &self.connections
+    }
+
+    /// The connection to send the next request through.
+    pub fn connection(&self) -> &Worterbuch {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.connections.len();
+        &self.connections[index]
+    }
+
+    /// The connection all subscriptions are made on.
+    pub fn subscriber(&self) -> &Worterbuch {
+        &self.connections[0]
+    }
+
+    pub async fn set_generic(&self, key: Key, value: Value) -> ConnectionResult<TransactionId> {
+        self.connection().set_generic(key, value).await
+    }
+
+    pub async fn set<T: Serialize>(&self, key: Key, value: &T) -> ConnectionResult<TransactionId> {
+        self.connection().set(key, value).await
+    }
+
+    pub async fn set_many_generic(
+        &self,
+        key_value_pairs: KeyValuePairs,
+    ) -> ConnectionResult<TransactionId> {
+        self.connection().set_many_generic(key_value_pairs).await
+    }
+
+    pub async fn set_many<T: Serialize>(
+        &self,
+        values: Vec<(Key, T)>,
+    ) -> ConnectionResult<TransactionId> {
+        self.connection().set_many(values).await
+    }
+
+    pub async fn publish_retained_generic(
+        &self,
+        key: Key,
+        value: Value,
+    ) -> ConnectionResult<TransactionId> {
+        self.connection().publish_retained_generic(key, value).await
+    }
+
+    pub async fn publish_retained<T: Serialize>(
+        &self,
+        key: Key,
+        value: &T,
+    ) -> ConnectionResult<TransactionId> {
+        self.connection().publish_retained(key, value).await
+    }
+
+    pub async fn set_at_generic(
+        &self,
+        key: Key,
+        pointer: String,
+        value: Value,
+    ) -> ConnectionResult<()> {
+        self.connection().set_at_generic(key, pointer, value).await
+    }
+
+    pub async fn set_at<T: Serialize>(
+        &self,
+        key: Key,
+        pointer: String,
+        value: &T,
+    ) -> ConnectionResult<()> {
+        self.connection().set_at(key, pointer, value).await
+    }
+
+    pub async fn increment_generic(
+        &self,
+        key: Key,
+        delta: serde_json::Number,
+    ) -> ConnectionResult<Value> {
+        self.connection().increment_generic(key, delta).await
+    }
+
+    pub async fn increment(&self, key: Key, delta: i64) -> ConnectionResult<i64> {
+        self.connection().increment(key, delta).await
+    }
+
+    pub async fn publish_generic(&self, key: Key, value: Value) -> ConnectionResult<TransactionId> {
+        self.connection().publish_generic(key, value).await
+    }
+
+    pub async fn publish<T: Serialize>(
+        &self,
+        key: Key,
+        value: &T,
+    ) -> ConnectionResult<TransactionId> {
+        self.connection().publish(key, value).await
+    }
+
+    pub async fn get_generic(&self, key: Key) -> ConnectionResult<(Option<Value>, TransactionId)> {
+        self.connection().get_generic(key).await
+    }
+
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(Option<T>, TransactionId)> {
+        self.connection().get(key).await
+    }
+
+    pub async fn get_at_generic(
+        &self,
+        key: Key,
+        pointer: String,
+    ) -> ConnectionResult<Option<Value>> {
+        self.connection().get_at_generic(key, pointer).await
+    }
+
+    pub async fn get_at<T: DeserializeOwned>(
+        &self,
+        key: Key,
+        pointer: String,
+    ) -> ConnectionResult<Option<T>> {
+        self.connection().get_at(key, pointer).await
+    }
+
+    pub async fn pget_generic(&self, key: Key) -> ConnectionResult<(KeyValuePairs, TransactionId)> {
+        self.connection().pget_generic(key).await
+    }
+
+    pub async fn pget<T: DeserializeOwned>(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(TypedKeyValuePairs<T>, TransactionId)> {
+        self.connection().pget(key).await
+    }
+
+    pub async fn pget_ignoring_case_generic(
+        &self,
+        pattern: Key,
+    ) -> ConnectionResult<(KeyValuePairs, TransactionId)> {
+        self.connection().pget_ignoring_case_generic(pattern).await
+    }
+
+    pub async fn pkeys(&self, pattern: Key) -> ConnectionResult<Vec<Key>> {
+        self.connection().pkeys(pattern).await
+    }
+
+    pub async fn delete_generic(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(Option<Value>, TransactionId)> {
+        self.connection().delete_generic(key).await
+    }
+
+    pub async fn delete<T: DeserializeOwned>(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(Option<T>, TransactionId)> {
+        self.connection().delete(key).await
+    }
+
+    pub async fn pdelete_generic(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(KeyValuePairs, TransactionId)> {
+        self.connection().pdelete_generic(key).await
+    }
+
+    pub async fn pdelete<T: DeserializeOwned>(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(TypedKeyValuePairs<T>, TransactionId)> {
+        self.connection().pdelete(key).await
+    }
+
+    pub async fn ls(
+        &self,
+        parent: Option<Key>,
+    ) -> ConnectionResult<(Vec<RegularKeySegment>, TransactionId)> {
+        self.connection().ls(parent).await
+    }
+
+    pub async fn subscribe_generic(
+        &self,
+        key: Key,
+        unique: bool,
+        live_only: bool,
+    ) -> ConnectionResult<(mpsc::UnboundedReceiver<(Option<Value>, Key)>, TransactionId)> {
+        self.subscriber()
+            .subscribe_generic(key, unique, live_only)
+            .await
+    }
+
+    pub async fn subscribe<T: DeserializeOwned + Send + 'static>(
+        &self,
+        key: Key,
+        unique: bool,
+        live_only: bool,
+    ) -> ConnectionResult<(mpsc::UnboundedReceiver<Option<T>>, TransactionId)> {
+        self.subscriber().subscribe(key, unique, live_only).await
+    }
+
+    pub async fn subscribe_cached<T: DeserializeOwned + Send + Sync + 'static>(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<Cached<T>> {
+        self.subscriber().subscribe_cached(key).await
+    }
+
+    pub async fn psubscribe_generic(
+        &self,
+        request_pattern: RequestPattern,
+        unique: bool,
+        live_only: bool,
+        aggregation_duration: Option<Duration>,
+    ) -> ConnectionResult<(mpsc::UnboundedReceiver<PStateEvent>, TransactionId)> {
+        self.subscriber()
+            .psubscribe_generic(request_pattern, unique, live_only, aggregation_duration)
+            .await
+    }
+
+    pub async fn psubscribe<T: DeserializeOwned + Send + 'static>(
+        &self,
+        request_pattern: RequestPattern,
+        unique: bool,
+        live_only: bool,
+        aggregation_duration: Option<Duration>,
+    ) -> ConnectionResult<(mpsc::UnboundedReceiver<TypedStateEvents<T>>, TransactionId)> {
+        self.subscriber()
+            .psubscribe(request_pattern, unique, live_only, aggregation_duration)
+            .await
+    }
+
+    pub async fn unsubscribe(&self, transaction_id: TransactionId) -> ConnectionResult<()> {
+        self.subscriber().unsubscribe(transaction_id).await
+    }
+
+    pub async fn close(&self) -> ConnectionResult<()> {
+        try_join_all(self.connections.iter().map(|wb| wb.close())).await?;
+        Ok(())
+    }
+}
+
+/// Opens `size` connections to the configured server, at least one.
+pub async fn connect_pool(config: Config, size: usize) -> ConnectionResult<Pool> {
+    let connections = (0..size.max(1)).map(|_| connect(config.clone(), async {}));
+    Ok(Pool::new(try_join_all(connections).await?))
+}
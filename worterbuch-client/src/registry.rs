@@ -0,0 +1,293 @@
+/*
+ *  Worterbuch client subscription registry module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use worterbuch_common::{
+    ClientMessage as CM, Key, KeyValuePairs, PStateEvent, RequestPattern, ServerMessage as SM,
+    StateEvent, TransactionId,
+};
+
+const LOCK_MSG: &str = "the lock scope must not contain code that can panic!";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RegisteredSubscription {
+    #[serde(rename_all = "camelCase")]
+    Subscribe {
+        key: Key,
+        unique: bool,
+        live_only: bool,
+    },
+    #[serde(rename_all = "camelCase")]
+    PSubscribe {
+        request_pattern: RequestPattern,
+        unique: bool,
+        live_only: bool,
+        aggregate_events: Option<u64>,
+    },
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RegistryState {
+    subscriptions: Vec<RegisteredSubscription>,
+    values: HashMap<Key, Value>,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    previous: Vec<RegisteredSubscription>,
+    active: HashMap<TransactionId, RegisteredSubscription>,
+    values: HashMap<Key, Value>,
+    dirty: bool,
+}
+
+/// Keeps track of a client's active subscriptions and the last values received for them and
+/// persists both to a file, so that a restarted application can serve cached state right away
+/// while its subscriptions are being re-established.
+#[derive(Debug, Clone)]
+pub struct SubscriptionRegistry {
+    path: PathBuf,
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl SubscriptionRegistry {
+    /// Creates a registry backed by the given file, loading whatever state a previous run left
+    /// there. A missing or corrupt file results in an empty registry.
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_owned();
+
+        let state = match fs::read_to_string(&path) {
+            Ok(json) => match serde_json::from_str::<RegistryState>(&json) {
+                Ok(state) => state,
+                Err(e) => {
+                    log::warn!("Could not parse subscription registry {path:?}: {e}");
+                    RegistryState::default()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => RegistryState::default(),
+            Err(e) => {
+                log::warn!("Could not read subscription registry {path:?}: {e}");
+                RegistryState::default()
+            }
+        };
+
+        let registry = Registry {
+            previous: state.subscriptions,
+            values: state.values,
+            ..Default::default()
+        };
+
+        Self {
+            path,
+            registry: Arc::new(Mutex::new(registry)),
+        }
+    }
+
+    /// The last value received for the given key, if any.
+    pub fn cached(&self, key: &str) -> Option<Value> {
+        self.registry
+            .lock()
+            .expect(LOCK_MSG)
+            .values
+            .get(key)
+            .cloned()
+    }
+
+    /// All cached key/value pairs.
+    pub fn cached_values(&self) -> KeyValuePairs {
+        self.registry
+            .lock()
+            .expect(LOCK_MSG)
+            .values
+            .iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()).into())
+            .collect()
+    }
+
+    /// The subscriptions that were active when the registry was last persisted by a previous run.
+    pub fn previous_subscriptions(&self) -> Vec<RegisteredSubscription> {
+        self.registry.lock().expect(LOCK_MSG).previous.clone()
+    }
+
+    /// The subscriptions that are currently active on this connection.
+    pub fn active_subscriptions(&self) -> Vec<RegisteredSubscription> {
+        self.registry
+            .lock()
+            .expect(LOCK_MSG)
+            .active
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn track_command(&self, msg: &CM) {
+        let mut registry = self.registry.lock().expect(LOCK_MSG);
+        match msg {
+            CM::Subscribe(msg) => {
+                registry.active.insert(
+                    msg.transaction_id,
+                    RegisteredSubscription::Subscribe {
+                        key: msg.key.clone(),
+                        unique: msg.unique,
+                        live_only: msg.live_only.unwrap_or(false),
+                    },
+                );
+                registry.dirty = true;
+            }
+            CM::PSubscribe(msg) => {
+                registry.active.insert(
+                    msg.transaction_id,
+                    RegisteredSubscription::PSubscribe {
+                        request_pattern: msg.request_pattern.clone(),
+                        unique: msg.unique,
+                        live_only: msg.live_only.unwrap_or(false),
+                        aggregate_events: msg.aggregate_events,
+                    },
+                );
+                registry.dirty = true;
+            }
+            CM::Unsubscribe(msg) if registry.active.remove(&msg.transaction_id).is_some() => {
+                registry.dirty = true;
+            }
+            _ => (),
+        }
+    }
+
+    pub(crate) fn track_message(&self, msg: &SM) {
+        let mut registry = self.registry.lock().expect(LOCK_MSG);
+        match msg {
+            SM::State(state) if registry.active.contains_key(&state.transaction_id) => {
+                match &state.event {
+                    StateEvent::KeyValue(kvp) => {
+                        registry.values.insert(kvp.key.clone(), kvp.value.clone());
+                    }
+                    StateEvent::Deleted(kvp) => {
+                        registry.values.remove(&kvp.key);
+                    }
+                }
+                registry.dirty = true;
+            }
+            SM::PState(pstate) if registry.active.contains_key(&pstate.transaction_id) => {
+                match &pstate.event {
+                    PStateEvent::KeyValuePairs(kvps) => {
+                        for kvp in kvps {
+                            registry.values.insert(kvp.key.clone(), kvp.value.clone());
+                        }
+                    }
+                    PStateEvent::Deleted(kvps) => {
+                        for kvp in kvps {
+                            registry.values.remove(&kvp.key);
+                        }
+                    }
+                }
+                registry.dirty = true;
+            }
+            _ => (),
+        }
+    }
+
+    /// Writes the registry to its file if anything changed since it was last written.
+    pub(crate) async fn persist(&self) -> io::Result<()> {
+        let json = {
+            let mut registry = self.registry.lock().expect(LOCK_MSG);
+            if !registry.dirty {
+                return Ok(());
+            }
+            registry.dirty = false;
+            let state = RegistryState {
+                subscriptions: registry.active.values().cloned().collect(),
+                values: registry.values.clone(),
+            };
+            serde_json::to_string(&state)?
+        };
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        tokio::fs::write(&tmp, json).await?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use worterbuch_common::{KeyValuePair, PState, State, Subscribe, Unsubscribe};
+
+    #[tokio::test]
+    async fn registry_survives_restart() {
+        let path = std::env::temp_dir().join(format!(
+            "worterbuch-registry-test-{}.json",
+            std::process::id()
+        ));
+
+        let registry = SubscriptionRegistry::load(&path);
+        registry.track_command(&CM::Subscribe(Subscribe {
+            transaction_id: 1,
+            key: "hello/world".to_owned(),
+            unique: true,
+            live_only: None,
+            initial_state_marker: None,
+        }));
+        registry.track_message(&SM::State(State {
+            transaction_id: 1,
+            event: StateEvent::KeyValue(KeyValuePair {
+                key: "hello/world".to_owned(),
+                value: json!(42),
+            }),
+            deprecated: None,
+            seq: None,
+        }));
+        registry.track_message(&SM::PState(PState {
+            transaction_id: 2,
+            request_pattern: "#".to_owned(),
+            event: PStateEvent::KeyValuePairs(vec![("not/subscribed", json!(1)).into()]),
+            deprecated: None,
+            more: None,
+            seq: None,
+        }));
+        registry.persist().await.unwrap();
+        registry.track_command(&CM::Unsubscribe(Unsubscribe { transaction_id: 1 }));
+
+        let restarted = SubscriptionRegistry::load(&path);
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(restarted.cached("hello/world"), Some(json!(42)));
+        assert_eq!(restarted.cached("not/subscribed"), None);
+        assert_eq!(
+            restarted.previous_subscriptions(),
+            vec![RegisteredSubscription::Subscribe {
+                key: "hello/world".to_owned(),
+                unique: true,
+                live_only: false,
+            }]
+        );
+        assert!(restarted.active_subscriptions().is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+/*
+ *  Worterbuch client replication module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Talks to a primary server and its replicas as if they were a single server. Every server
+//! publishes its replication role at `$SYS/replication/role`, which the client follows on all
+//! connections, so writes keep going to whichever server is primary after a failover.
+
+use crate::{config::Config, connect, Worterbuch};
+use futures_util::future::try_join_all;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::{spawn, sync::watch};
+use worterbuch_common::{
+    error::{ConfigError, ConnectionError, ConnectionResult},
+    topic, Key, KeyValuePairs, TransactionId, TypedKeyValuePairs, Value, SYSTEM_TOPIC_REPLICATION,
+    SYSTEM_TOPIC_ROOT,
+};
+
+/// Where reads (`get`, `pget` and `pkeys`) are sent. Writes always go to the primary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadPreference {
+    /// Reads go to the primary, so they always see the client's own writes.
+    #[default]
+    Primary,
+    /// Reads are spread over the replicas, at the cost of possibly stale values. Falls back to the
+    /// primary while no replica is known.
+    Replica,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Role {
+    /// The server has not published a role (yet), e.g. because it does not replicate.
+    #[default]
+    Unknown,
+    Primary,
+    Replica,
+}
+
+impl Role {
+    fn parse(role: Option<&str>) -> Self {
+        match role {
+            Some("primary") => Role::Primary,
+            Some("replica") => Role::Replica,
+            _ => Role::Unknown,
+        }
+    }
+}
+
+/// A facade over one connection per server of a replicated setup. Writes are routed to the
+/// current primary, or to the first server while no server has announced itself as primary.
+#[derive(Clone)]
+pub struct ReplicatedWorterbuch {
+    servers: Vec<Worterbuch>,
+    roles: Vec<watch::Receiver<Role>>,
+    read_preference: ReadPreference,
+    next: Arc<AtomicUsize>,
+}
+
+impl ReplicatedWorterbuch {
+    /// Creates a facade over already established connections and starts following the
+    /// replication role of each server.
+    pub async fn new(
+        servers: Vec<Worterbuch>,
+        read_preference: ReadPreference,
+    ) -> ConnectionResult<Self> {
+        if servers.is_empty() {
+            return Err(ConnectionError::ConfigError(
+                ConfigError::InvalidClusterConfig("no servers configured".to_owned()),
+            ));
+        }
+        let mut roles = Vec::with_capacity(servers.len());
+        for server in &servers {
+            roles.push(follow_role(server).await?);
+        }
+        Ok(Self {
+            servers,
+            roles,
+            read_preference,
+            next: Arc::default(),
+        })
+    }
+
+    pub fn servers(&self) -> &[Worterbuch] {
+        &self.servers
+    }
+
+    /// The connection to the current primary.
+    pub fn primary(&self) -> &Worterbuch {
+        &self.servers[primary(&self.current_roles())]
+    }
+
+    /// The connection to send the next read to.
+    pub fn reader(&self) -> &Worterbuch {
+        let roles = self.current_roles();
+        let index = match self.read_preference {
+            ReadPreference::Primary => primary(&roles),
+            ReadPreference::Replica => replica(&roles, self.next.fetch_add(1, Ordering::Relaxed))
+                .unwrap_or_else(|| primary(&roles)),
+        };
+        &self.servers[index]
+    }
+
+    fn current_roles(&self) -> Vec<Role> {
+        self.roles.iter().map(|role| *role.borrow()).collect()
+    }
+
+    pub async fn set_generic(&self, key: Key, value: Value) -> ConnectionResult<TransactionId> {
+        self.primary().set_generic(key, value).await
+    }
+
+    pub async fn set<T: Serialize>(&self, key: Key, value: &T) -> ConnectionResult<TransactionId> {
+        self.primary().set(key, value).await
+    }
+
+    pub async fn publish_generic(&self, key: Key, value: Value) -> ConnectionResult<TransactionId> {
+        self.primary().publish_generic(key, value).await
+    }
+
+    pub async fn publish<T: Serialize>(
+        &self,
+        key: Key,
+        value: &T,
+    ) -> ConnectionResult<TransactionId> {
+        self.primary().publish(key, value).await
+    }
+
+    pub async fn get_generic(&self, key: Key) -> ConnectionResult<(Option<Value>, TransactionId)> {
+        self.reader().get_generic(key).await
+    }
+
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(Option<T>, TransactionId)> {
+        self.reader().get(key).await
+    }
+
+    pub async fn pget_generic(
+        &self,
+        pattern: Key,
+    ) -> ConnectionResult<(KeyValuePairs, TransactionId)> {
+        self.reader().pget_generic(pattern).await
+    }
+
+    pub async fn pget<T: DeserializeOwned>(
+        &self,
+        pattern: Key,
+    ) -> ConnectionResult<(TypedKeyValuePairs<T>, TransactionId)> {
+        self.reader().pget(pattern).await
+    }
+
+    pub async fn pkeys(&self, pattern: Key) -> ConnectionResult<Vec<Key>> {
+        self.reader().pkeys(pattern).await
+    }
+
+    pub async fn delete_generic(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(Option<Value>, TransactionId)> {
+        self.primary().delete_generic(key).await
+    }
+
+    pub async fn delete<T: DeserializeOwned>(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(Option<T>, TransactionId)> {
+        self.primary().delete(key).await
+    }
+
+    pub async fn pdelete_generic(
+        &self,
+        pattern: Key,
+    ) -> ConnectionResult<(KeyValuePairs, TransactionId)> {
+        self.primary().pdelete_generic(pattern).await
+    }
+
+    pub async fn close(&self) -> ConnectionResult<()> {
+        try_join_all(self.servers.iter().map(|wb| wb.close())).await?;
+        Ok(())
+    }
+}
+
+/// Connects to every configured server of a replicated setup.
+pub async fn connect_replicated(
+    configs: Vec<Config>,
+    read_preference: ReadPreference,
+) -> ConnectionResult<ReplicatedWorterbuch> {
+    let mut servers = Vec::with_capacity(configs.len());
+    for config in configs {
+        servers.push(connect(config, async {}).await?);
+    }
+    ReplicatedWorterbuch::new(servers, read_preference).await
+}
+
+/// Subscribes to the replication role of a server. The role becomes unknown again once the
+/// connection is lost.
+async fn follow_role(server: &Worterbuch) -> ConnectionResult<watch::Receiver<Role>> {
+    let (mut values, _) = server
+        .subscribe::<String>(
+            topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_REPLICATION, "role"),
+            true,
+            false,
+        )
+        .await?;
+    let (tx, rx) = watch::channel(Role::Unknown);
+    spawn(async move {
+        while let Some(role) = values.recv().await {
+            let role = Role::parse(role.as_deref());
+            log::debug!("Server changed its replication role to {role:?}.");
+            tx.send_replace(role);
+        }
+        tx.send_replace(Role::Unknown);
+    });
+    Ok(rx)
+}
+
+fn primary(roles: &[Role]) -> usize {
+    roles
+        .iter()
+        .position(|role| *role == Role::Primary)
+        .unwrap_or(0)
+}
+
+fn replica(roles: &[Role], counter: usize) -> Option<usize> {
+    let replicas: Vec<usize> = roles
+        .iter()
+        .enumerate()
+        .filter(|(_, role)| **role == Role::Replica)
+        .map(|(i, _)| i)
+        .collect();
+    (!replicas.is_empty()).then(|| replicas[counter % replicas.len()])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn requests_follow_the_replication_roles() {
+        let roles = [Role::Replica, Role::Primary, Role::Unknown, Role::Replica];
+        assert_eq!(primary(&roles), 1);
+        assert_eq!(replica(&roles, 0), Some(0));
+        assert_eq!(replica(&roles, 1), Some(3));
+        assert_eq!(replica(&roles, 2), Some(0));
+
+        // after a failover writes move to the new primary
+        let roles = [Role::Primary, Role::Replica, Role::Unknown, Role::Replica];
+        assert_eq!(primary(&roles), 0);
+
+        // without any announced roles everything goes to the first server
+        let roles = [Role::Unknown, Role::Unknown];
+        assert_eq!(primary(&roles), 0);
+        assert_eq!(replica(&roles, 5), None);
+    }
+}
@@ -0,0 +1,133 @@
+/*
+ *  Worterbuch client sessions
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::Command;
+use std::{
+    collections::HashSet,
+    sync::{Mutex, MutexGuard},
+};
+use tokio::{runtime::Handle, sync::mpsc};
+use worterbuch_common::TransactionId;
+
+/// The subscriptions owned by a logical session that shares its connection with other sessions.
+/// When the last handle of a session is dropped, all of its subscriptions are cancelled.
+pub(crate) struct Session {
+    commands: mpsc::Sender<Command>,
+    subscriptions: Mutex<HashSet<TransactionId>>,
+    ls_subscriptions: Mutex<HashSet<TransactionId>>,
+}
+
+impl Session {
+    pub fn new(commands: mpsc::Sender<Command>) -> Self {
+        Self {
+            commands,
+            subscriptions: Mutex::default(),
+            ls_subscriptions: Mutex::default(),
+        }
+    }
+
+    pub fn track(&self, transaction_id: TransactionId) {
+        lock(&self.subscriptions).insert(transaction_id);
+    }
+
+    pub fn track_ls(&self, transaction_id: TransactionId) {
+        lock(&self.ls_subscriptions).insert(transaction_id);
+    }
+
+    /// Removes a subscription from the session. Returns `false` if the session does not own it.
+    pub fn release(&self, transaction_id: TransactionId) -> bool {
+        lock(&self.subscriptions).remove(&transaction_id)
+    }
+
+    pub fn release_ls(&self, transaction_id: TransactionId) -> bool {
+        lock(&self.ls_subscriptions).remove(&transaction_id)
+    }
+
+    pub fn subscriptions(&self) -> Vec<TransactionId> {
+        let mut tids: Vec<TransactionId> = lock(&self.subscriptions)
+            .iter()
+            .chain(lock(&self.ls_subscriptions).iter())
+            .copied()
+            .collect();
+        tids.sort_unstable();
+        tids
+    }
+
+    /// Removes all subscriptions from the session and returns the commands to cancel them.
+    pub fn release_all(&self) -> Vec<Command> {
+        let mut commands: Vec<Command> = lock(&self.subscriptions)
+            .drain()
+            .map(Command::Unsubscribe)
+            .collect();
+        commands.extend(
+            lock(&self.ls_subscriptions)
+                .drain()
+                .map(Command::UnsubscribeLs),
+        );
+        commands
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let commands = self.release_all();
+        if commands.is_empty() {
+            return;
+        }
+        // without a runtime the connection is gone anyway
+        if let Ok(runtime) = Handle::try_current() {
+            let tx = self.commands.clone();
+            runtime.spawn(async move {
+                for cmd in commands {
+                    if tx.send(cmd).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    }
+}
+
+fn lock(set: &Mutex<HashSet<TransactionId>>) -> MutexGuard<'_, HashSet<TransactionId>> {
+    match set.lock() {
+        Ok(it) => it,
+        Err(e) => e.into_inner(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sessions_only_release_their_own_subscriptions() {
+        let (tx, _rx) = mpsc::channel(1);
+        let session = Session::new(tx);
+        session.track(1);
+        session.track_ls(3);
+
+        assert!(!session.release(2));
+        assert!(!session.release(3));
+        assert_eq!(session.subscriptions(), vec![1, 3]);
+
+        assert!(session.release(1));
+        assert_eq!(session.release_all().len(), 1);
+        assert!(session.subscriptions().is_empty());
+    }
+}
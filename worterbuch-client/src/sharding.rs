@@ -0,0 +1,289 @@
+/*
+ *  Worterbuch client sharding module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Spreads keys over several independent servers, for setups that outgrow a single instance but
+//! do not run a cluster. Keys are assigned to servers by the hash of their first segment, so
+//! everything below a top level key lives on the same server and can be queried there. Servers
+//! are placed on a hash ring, so adding or removing one only moves the keys of its neighbours.
+
+use crate::{config::Config, connect, Worterbuch};
+use futures_util::future::try_join_all;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeSet;
+use worterbuch_common::{
+    error::{ConfigError, ConnectionError, ConnectionResult},
+    Key, KeyValuePairs, RegularKeySegment, TransactionId, TypedKeyValuePairs, Value,
+};
+
+/// Points each server occupies on the hash ring. More points spread keys more evenly.
+const VIRTUAL_NODES: usize = 64;
+
+/// A facade over one connection per server that routes every request to the server owning its
+/// key. Pattern requests whose first segment is a wildcard are sent to all servers and their
+/// results merged. Since those have no single transaction ID, the fanned out methods only
+/// return the merged results.
+#[derive(Clone)]
+pub struct ShardedWorterbuch {
+    ring: Ring,
+    shards: Vec<Worterbuch>,
+}
+
+impl ShardedWorterbuch {
+    /// Creates a facade over already established connections. The names identify the servers on
+    /// the hash ring and must stay the same across restarts for keys to keep their server.
+    #[allow(clippy::result_large_err)]
+    pub fn new(shards: Vec<(String, Worterbuch)>) -> ConnectionResult<Self> {
+        if shards.is_empty() {
+            return Err(ConnectionError::ConfigError(
+                ConfigError::InvalidClusterConfig("no servers configured".to_owned()),
+            ));
+        }
+        let (names, shards): (Vec<String>, Vec<Worterbuch>) = shards.into_iter().unzip();
+        let ring = Ring::new(&names);
+        Ok(Self { ring, shards })
+    }
+
+    pub fn shards(&self) -> &[Worterbuch] {
+        &self.shards
+    }
+
+    /// The connection to the server that owns the given key.
+    pub fn shard(&self, key: &str) -> &Worterbuch {
+        &self.shards[self.ring.shard(first_segment(key))]
+    }
+
+    /// The connections a pattern needs to be sent to.
+    fn shards_for(&self, pattern: &str) -> Vec<&Worterbuch> {
+        match first_segment(pattern) {
+            "?" | "#" => self.shards.iter().collect(),
+            segment => vec![&self.shards[self.ring.shard(segment)]],
+        }
+    }
+
+    pub async fn set_generic(&self, key: Key, value: Value) -> ConnectionResult<TransactionId> {
+        self.shard(&key).set_generic(key, value).await
+    }
+
+    pub async fn set<T: Serialize>(&self, key: Key, value: &T) -> ConnectionResult<TransactionId> {
+        self.shard(&key).set(key, value).await
+    }
+
+    pub async fn publish_generic(&self, key: Key, value: Value) -> ConnectionResult<TransactionId> {
+        self.shard(&key).publish_generic(key, value).await
+    }
+
+    pub async fn publish<T: Serialize>(
+        &self,
+        key: Key,
+        value: &T,
+    ) -> ConnectionResult<TransactionId> {
+        self.shard(&key).publish(key, value).await
+    }
+
+    pub async fn get_generic(&self, key: Key) -> ConnectionResult<(Option<Value>, TransactionId)> {
+        self.shard(&key).get_generic(key).await
+    }
+
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(Option<T>, TransactionId)> {
+        self.shard(&key).get(key).await
+    }
+
+    pub async fn delete_generic(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(Option<Value>, TransactionId)> {
+        self.shard(&key).delete_generic(key).await
+    }
+
+    pub async fn delete<T: DeserializeOwned>(
+        &self,
+        key: Key,
+    ) -> ConnectionResult<(Option<T>, TransactionId)> {
+        self.shard(&key).delete(key).await
+    }
+
+    pub async fn pget_generic(&self, pattern: Key) -> ConnectionResult<KeyValuePairs> {
+        let requests = self
+            .shards_for(&pattern)
+            .into_iter()
+            .map(|wb| wb.pget_generic(pattern.clone()));
+        let results = try_join_all(requests).await?;
+        Ok(results.into_iter().flat_map(|(kvps, _)| kvps).collect())
+    }
+
+    pub async fn pget<T: DeserializeOwned>(
+        &self,
+        pattern: Key,
+    ) -> ConnectionResult<TypedKeyValuePairs<T>> {
+        let requests = self
+            .shards_for(&pattern)
+            .into_iter()
+            .map(|wb| wb.pget(pattern.clone()));
+        let results = try_join_all(requests).await?;
+        Ok(results.into_iter().flat_map(|(kvps, _)| kvps).collect())
+    }
+
+    pub async fn pkeys(&self, pattern: Key) -> ConnectionResult<Vec<Key>> {
+        let requests = self
+            .shards_for(&pattern)
+            .into_iter()
+            .map(|wb| wb.pkeys(pattern.clone()));
+        let results = try_join_all(requests).await?;
+        Ok(results.concat())
+    }
+
+    pub async fn pdelete_generic(&self, pattern: Key) -> ConnectionResult<KeyValuePairs> {
+        let requests = self
+            .shards_for(&pattern)
+            .into_iter()
+            .map(|wb| wb.pdelete_generic(pattern.clone()));
+        let results = try_join_all(requests).await?;
+        Ok(results.into_iter().flat_map(|(kvps, _)| kvps).collect())
+    }
+
+    /// Lists the children of a key. Top level keys are collected from all servers.
+    pub async fn ls(&self, parent: Option<Key>) -> ConnectionResult<Vec<RegularKeySegment>> {
+        match parent {
+            Some(parent) => {
+                let (children, _) = self.shard(&parent).ls(Some(parent)).await?;
+                Ok(children)
+            }
+            None => {
+                let requests = self.shards.iter().map(|wb| wb.ls(None));
+                let results = try_join_all(requests).await?;
+                let children: BTreeSet<RegularKeySegment> = results
+                    .into_iter()
+                    .flat_map(|(children, _)| children)
+                    .collect();
+                Ok(children.into_iter().collect())
+            }
+        }
+    }
+
+    pub async fn close(&self) -> ConnectionResult<()> {
+        try_join_all(self.shards.iter().map(|wb| wb.close())).await?;
+        Ok(())
+    }
+}
+
+/// Connects to every configured server and puts them on a hash ring named after their
+/// addresses, so the order of the configs does not matter.
+pub async fn connect_sharded(configs: Vec<Config>) -> ConnectionResult<ShardedWorterbuch> {
+    if configs.is_empty() {
+        return Err(ConnectionError::ConfigError(
+            ConfigError::InvalidClusterConfig("no servers configured".to_owned()),
+        ));
+    }
+    let mut shards = Vec::with_capacity(configs.len());
+    for config in configs {
+        let name = format!("{}://{}:{}", config.proto, config.host_addr, config.port);
+        let wb = connect(config, async {}).await?;
+        shards.push((name, wb));
+    }
+    ShardedWorterbuch::new(shards)
+}
+
+/// Consistent hash ring that assigns first key segments to one of a list of named servers. Servers
+/// only depend on their names, so every party building a ring from the same names agrees on it.
+#[derive(Debug, Clone)]
+pub struct Ring {
+    points: Vec<(u64, usize)>,
+}
+
+impl Ring {
+    pub fn new(names: &[String]) -> Self {
+        let mut points: Vec<(u64, usize)> = names
+            .iter()
+            .enumerate()
+            .flat_map(|(shard, name)| {
+                (0..VIRTUAL_NODES).map(move |i| (hash(&format!("{name}#{i}")), shard))
+            })
+            .collect();
+        points.sort_unstable();
+        Self { points }
+    }
+
+    /// The index of the server the segment belongs to.
+    pub fn shard(&self, segment: &str) -> usize {
+        let hash = hash(segment);
+        let index = self.points.partition_point(|(point, _)| *point < hash);
+        self.points
+            .get(index)
+            .or_else(|| self.points.first())
+            .map(|(_, shard)| *shard)
+            .expect("sharded client needs at least one server")
+    }
+}
+
+fn first_segment(key: &str) -> &str {
+    key.split('/').next().unwrap_or(key)
+}
+
+/// FNV-1a with a murmur3 finalizer, since plain FNV clusters similar strings like the virtual node
+/// names. Unlike the std hashers it is guaranteed to produce the same results everywhere.
+fn hash(value: &str) -> u64 {
+    let mut hash = value.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("tcp://wb-{i}:8081")).collect()
+    }
+
+    #[test]
+    fn keys_with_the_same_first_segment_share_a_shard() {
+        let ring = Ring::new(&names(4));
+        assert_eq!(
+            ring.shard(first_segment("hello/world")),
+            ring.shard(first_segment("hello/there/general/kenobi"))
+        );
+    }
+
+    #[test]
+    fn adding_a_shard_only_moves_some_keys() {
+        let before = Ring::new(&names(4));
+        let after = Ring::new(&names(5));
+
+        let keys: Vec<String> = (0..1000).map(|i| format!("key-{i}")).collect();
+        let moved = keys
+            .iter()
+            .filter(|key| before.shard(key) != after.shard(key))
+            .count();
+
+        assert!(moved > 0);
+        assert!(moved < 400, "{moved} of 1000 keys moved");
+        for key in &keys {
+            let shard = after.shard(key);
+            assert!(shard == before.shard(key) || shard == 4);
+        }
+    }
+}
@@ -24,7 +24,8 @@ use tokio::{
     sync::mpsc,
 };
 use worterbuch_common::{
-    error::ConnectionResult, tcp::write_line_and_flush, ClientMessage, ServerMessage,
+    error::ConnectionResult, redact::redact_message, tcp::write_line_and_flush, ClientMessage,
+    ServerMessage,
 };
 
 pub struct TcpClientSocket {
@@ -49,10 +50,13 @@ impl TcpClientSocket {
         match read {
             Ok(None) => Ok(None),
             Ok(Some(json)) => {
-                log::debug!("Received messaeg: {json}");
+                log::debug!("Received messaeg: {}", redact_message(&json, |_| false));
                 let sm = serde_json::from_str(&json);
                 if let Err(e) = &sm {
-                    log::error!("Error deserializing message '{json}': {e}")
+                    log::error!(
+                        "Error deserializing message '{}': {e}",
+                        redact_message(&json, |_| false)
+                    )
                 }
                 Ok(sm?)
             }
@@ -0,0 +1,179 @@
+/*
+ *  Worterbuch client tree mapping module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Conversion between nested structures and the flat keys they are stored under.
+
+use serde_json::{Map, Value};
+use worterbuch_common::{topic, KeyValuePair, KeyValuePairs};
+
+/// Controls how a nested value is split into individual keys.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlattenRules {
+    /// Store arrays element by element under their index instead of as a single value.
+    pub split_arrays: bool,
+    /// Values nested deeper than this below the prefix are stored as a single value.
+    pub max_depth: Option<usize>,
+}
+
+/// Splits a value into key/value pairs below `prefix`. Scalars (and anything the rules say must
+/// not be split any further) end up as values, objects become key segments.
+pub fn flatten(prefix: &str, value: Value, rules: &FlattenRules) -> KeyValuePairs {
+    let mut kvps = KeyValuePairs::new();
+    flatten_into(prefix.to_owned(), value, 0, rules, &mut kvps);
+    kvps
+}
+
+fn flatten_into(
+    key: String,
+    value: Value,
+    depth: usize,
+    rules: &FlattenRules,
+    kvps: &mut KeyValuePairs,
+) {
+    if rules.max_depth.map(|max| depth >= max).unwrap_or(false) {
+        kvps.push((key, value).into());
+        return;
+    }
+    match value {
+        Value::Object(object) if !object.is_empty() => {
+            for (segment, value) in object {
+                flatten_into(topic!(key, segment), value, depth + 1, rules, kvps);
+            }
+        }
+        Value::Array(array) if rules.split_arrays && !array.is_empty() => {
+            for (index, value) in array.into_iter().enumerate() {
+                flatten_into(topic!(key, index), value, depth + 1, rules, kvps);
+            }
+        }
+        value => kvps.push((key, value).into()),
+    }
+}
+
+/// Reassembles the value stored below `prefix` from key/value pairs, ignoring any key outside of
+/// it. Returns `None` if there is no such key.
+pub fn unflatten(prefix: &str, kvps: KeyValuePairs, rules: &FlattenRules) -> Option<Value> {
+    let mut root: Option<Value> = None;
+    let child_prefix = format!("{prefix}/");
+
+    for KeyValuePair { key, value } in kvps {
+        if key == prefix {
+            if root.is_none() {
+                root = Some(value);
+            }
+            continue;
+        }
+        let Some(rest) = key.strip_prefix(&child_prefix) else {
+            continue;
+        };
+
+        let mut node = root.get_or_insert_with(|| Value::Object(Map::new()));
+        for segment in rest.split('/') {
+            if !node.is_object() {
+                *node = Value::Object(Map::new());
+            }
+            let Value::Object(object) = node else {
+                unreachable!("node was just made an object");
+            };
+            node = object.entry(segment).or_insert(Value::Null);
+        }
+        if !node.is_object() {
+            *node = value;
+        }
+    }
+
+    if let (true, Some(root)) = (rules.split_arrays, root.as_mut()) {
+        restore_arrays(root);
+    }
+
+    root
+}
+
+/// Turns objects whose keys are exactly the indices `0..n` back into arrays.
+fn restore_arrays(value: &mut Value) {
+    let Value::Object(object) = value else {
+        return;
+    };
+    for child in object.values_mut() {
+        restore_arrays(child);
+    }
+    let is_array = (0..object.len()).all(|i| object.contains_key(&i.to_string()));
+    if is_array && !object.is_empty() {
+        let array = (0..object.len())
+            .filter_map(|i| object.remove(&i.to_string()))
+            .collect();
+        *value = Value::Array(array);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn trees_survive_a_round_trip() {
+        let display = json!({
+            "brightness": 80,
+            "theme": { "name": "dark", "accent": "#ff8800" },
+            "layouts": ["grid", "list"]
+        });
+
+        let rules = FlattenRules::default();
+        let mut kvps = flatten("config/display", display.clone(), &rules);
+        kvps.sort_by(|a, b| a.key.cmp(&b.key));
+        let keys: Vec<&str> = kvps.iter().map(|kvp| kvp.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "config/display/brightness",
+                "config/display/layouts",
+                "config/display/theme/accent",
+                "config/display/theme/name",
+            ]
+        );
+        assert_eq!(
+            unflatten("config/display", kvps, &rules),
+            Some(display.clone())
+        );
+
+        let rules = FlattenRules {
+            split_arrays: true,
+            max_depth: Some(1),
+        };
+        let kvps = flatten("config/display", display.clone(), &rules);
+        assert_eq!(kvps.len(), 3);
+        assert_eq!(
+            unflatten("config/display", kvps, &rules),
+            Some(display.clone())
+        );
+
+        let rules = FlattenRules {
+            split_arrays: true,
+            max_depth: None,
+        };
+        let kvps = flatten("config/display", display.clone(), &rules);
+        assert!(kvps.iter().any(|kvp| kvp.key == "config/display/layouts/1"));
+        assert_eq!(unflatten("config/display", kvps, &rules), Some(display));
+
+        assert_eq!(
+            unflatten("config/other", KeyValuePairs::new(), &rules),
+            None
+        );
+    }
+}
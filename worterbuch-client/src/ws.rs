@@ -20,21 +20,49 @@
 use futures_util::{SinkExt, StreamExt};
 use tokio::net::TcpStream;
 use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
-use worterbuch_common::{error::ConnectionResult, ClientMessage, ServerMessage};
+use worterbuch_common::{
+    error::ConnectionResult,
+    limits::DecoderLimits,
+    redact::redact_message,
+    wire::{Compression, WireFormat},
+    ClientMessage, ServerMessage,
+};
+
+/// The server is trusted not to send malicious messages, these limits only match the recursion
+/// limit that applies to JSON messages.
+const BINARY_DECODER_LIMITS: DecoderLimits = DecoderLimits {
+    max_message_size: usize::MAX,
+    max_depth: 128,
+};
 
 pub struct WsClientSocket {
     websocket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    format: WireFormat,
+    compression: Compression,
 }
 
 impl WsClientSocket {
-    pub fn new(websocket: WebSocketStream<MaybeTlsStream<TcpStream>>) -> Self {
-        Self { websocket }
+    pub fn new(
+        websocket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+        format: WireFormat,
+        compression: Compression,
+    ) -> Self {
+        Self {
+            websocket,
+            format,
+            compression,
+        }
     }
 
     pub async fn send_msg(&mut self, msg: &ClientMessage) -> ConnectionResult<()> {
-        let json = serde_json::to_string(msg)?;
-        log::debug!("Sending message: {json}");
-        let msg = Message::Text(json);
+        let msg = if self.format.is_binary() {
+            log::debug!("Sending {} message: {}", self.format, msg.message_type());
+            Message::Binary(self.format.encode(msg, self.compression)?)
+        } else {
+            let json = serde_json::to_string(msg)?;
+            log::debug!("Sending message: {}", redact_message(&json, |_| false));
+            Message::Text(json)
+        };
         self.websocket.send(msg).await?;
         Ok(())
     }
@@ -42,10 +70,19 @@ impl WsClientSocket {
     pub async fn receive_msg(&mut self) -> ConnectionResult<Option<ServerMessage>> {
         match self.websocket.next().await {
             Some(Ok(Message::Text(json))) => {
-                log::debug!("Received messaeg: {json}");
+                log::debug!("Received messaeg: {}", redact_message(&json, |_| false));
                 let msg = serde_json::from_str(&json)?;
                 Ok(Some(msg))
             }
+            // the server only sends binary frames after the client switched to a binary format
+            Some(Ok(Message::Binary(data))) if self.format.is_binary() => {
+                let msg = self
+                    .format
+                    .decode(&data, self.compression, &BINARY_DECODER_LIMITS)?;
+                // binary messages cannot be redacted, so they are not logged
+                log::debug!("Received {} message", self.format);
+                Ok(Some(msg))
+            }
             Some(Err(e)) => Err(e.into()),
             Some(Ok(_)) | None => Ok(None),
         }
@@ -17,25 +17,66 @@
  *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::{AuthToken, Key, LiveOnlyFlag, RequestPattern, TransactionId, UniqueFlag, Value};
+use crate::{
+    wire::{Compression, WireFormat},
+    AuthToken, CorrelationId, Key, KeyValuePairs, LiveOnlyFlag, RequestPattern, TransactionId,
+    UniqueFlag, Value,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::Number;
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub enum ClientMessage {
     AuthorizationRequest(AuthorizationRequest),
+    AuthenticationRequest(AuthenticationRequest),
+    KeyPrefix(KeyPrefix),
+    SwitchWireFormat(SwitchWireFormat),
+    ClientInfo(ClientInfo),
     Get(Get),
+    GetAt(GetAt),
     PGet(PGet),
+    PKeys(PKeys),
+    GetHistory(GetHistory),
     Set(Set),
+    SetAt(SetAt),
+    SetMany(SetMany),
+    SetExpiring(SetExpiring),
+    SetNx(SetNx),
+    SetXx(SetXx),
+    Expire(Expire),
+    CompareAndSwap(CompareAndSwap),
+    Increment(Increment),
+    ArrayPush(ArrayPush),
+    ArrayPop(ArrayPop),
+    ArrayRemove(ArrayRemove),
+    HintInvalidation(HintInvalidation),
     Publish(Publish),
+    PublishRetained(PublishRetained),
     Subscribe(Subscribe),
     PSubscribe(PSubscribe),
     Unsubscribe(Unsubscribe),
+    Resync(Resync),
+    Sync(SyncRequest),
+    Cancel(Cancel),
     Delete(Delete),
     PDelete(PDelete),
+    Restore(Restore),
+    Reserve(Reserve),
+    Release(Release),
+    Lock(Lock),
+    TryLock(TryLock),
+    Unlock(Unlock),
+    StartRecording(StartRecording),
+    StopRecording(StopRecording),
+    ReplayRecording(ReplayRecording),
     Ls(Ls),
     SubscribeLs(SubscribeLs),
     UnsubscribeLs(UnsubscribeLs),
+    SubscribeEvents(SubscribeEvents),
+    WhoSubscribes(WhoSubscribes),
     Transform(Transform),
     #[serde(rename = "")]
     Keepalive,
@@ -45,60 +86,432 @@ impl ClientMessage {
     pub fn transaction_id(&self) -> Option<TransactionId> {
         match self {
             ClientMessage::AuthorizationRequest(_) => Some(0),
+            ClientMessage::AuthenticationRequest(_) => Some(0),
+            ClientMessage::KeyPrefix(_) => Some(0),
+            ClientMessage::SwitchWireFormat(_) => Some(0),
+            ClientMessage::ClientInfo(_) => Some(0),
             ClientMessage::Get(m) => Some(m.transaction_id),
+            ClientMessage::GetAt(m) => Some(m.transaction_id),
             ClientMessage::PGet(m) => Some(m.transaction_id),
+            ClientMessage::PKeys(m) => Some(m.transaction_id),
+            ClientMessage::GetHistory(m) => Some(m.transaction_id),
             ClientMessage::Set(m) => Some(m.transaction_id),
+            ClientMessage::SetAt(m) => Some(m.transaction_id),
+            ClientMessage::SetMany(m) => Some(m.transaction_id),
+            ClientMessage::SetExpiring(m) => Some(m.transaction_id),
+            ClientMessage::SetNx(m) => Some(m.transaction_id),
+            ClientMessage::SetXx(m) => Some(m.transaction_id),
+            ClientMessage::Expire(m) => Some(m.transaction_id),
+            ClientMessage::CompareAndSwap(m) => Some(m.transaction_id),
+            ClientMessage::Increment(m) => Some(m.transaction_id),
+            ClientMessage::ArrayPush(m) => Some(m.transaction_id),
+            ClientMessage::ArrayPop(m) => Some(m.transaction_id),
+            ClientMessage::ArrayRemove(m) => Some(m.transaction_id),
+            ClientMessage::HintInvalidation(m) => Some(m.transaction_id),
             ClientMessage::Publish(m) => Some(m.transaction_id),
+            ClientMessage::PublishRetained(m) => Some(m.transaction_id),
             ClientMessage::Subscribe(m) => Some(m.transaction_id),
             ClientMessage::PSubscribe(m) => Some(m.transaction_id),
             ClientMessage::Unsubscribe(m) => Some(m.transaction_id),
+            ClientMessage::Resync(m) => Some(m.transaction_id),
+            ClientMessage::Sync(m) => Some(m.transaction_id),
+            ClientMessage::Cancel(m) => Some(m.transaction_id),
             ClientMessage::Delete(m) => Some(m.transaction_id),
             ClientMessage::PDelete(m) => Some(m.transaction_id),
+            ClientMessage::Restore(m) => Some(m.transaction_id),
+            ClientMessage::Reserve(m) => Some(m.transaction_id),
+            ClientMessage::Release(m) => Some(m.transaction_id),
+            ClientMessage::Lock(m) => Some(m.transaction_id),
+            ClientMessage::TryLock(m) => Some(m.transaction_id),
+            ClientMessage::Unlock(m) => Some(m.transaction_id),
+            ClientMessage::StartRecording(m) => Some(m.transaction_id),
+            ClientMessage::StopRecording(m) => Some(m.transaction_id),
+            ClientMessage::ReplayRecording(m) => Some(m.transaction_id),
             ClientMessage::Ls(m) => Some(m.transaction_id),
             ClientMessage::SubscribeLs(m) => Some(m.transaction_id),
             ClientMessage::UnsubscribeLs(m) => Some(m.transaction_id),
+            ClientMessage::SubscribeEvents(m) => Some(m.transaction_id),
+            ClientMessage::WhoSubscribes(m) => Some(m.transaction_id),
             ClientMessage::Transform(m) => Some(m.transaction_id),
             ClientMessage::Keepalive => None,
         }
     }
+
+    /// The name of the message type as it appears on the wire.
+    pub fn message_type(&self) -> &'static str {
+        match self {
+            ClientMessage::AuthorizationRequest(_) => "authorizationRequest",
+            ClientMessage::AuthenticationRequest(_) => "authenticationRequest",
+            ClientMessage::KeyPrefix(_) => "keyPrefix",
+            ClientMessage::SwitchWireFormat(_) => "switchWireFormat",
+            ClientMessage::ClientInfo(_) => "clientInfo",
+            ClientMessage::Get(_) => "get",
+            ClientMessage::GetAt(_) => "getAt",
+            ClientMessage::PGet(_) => "pGet",
+            ClientMessage::PKeys(_) => "pKeys",
+            ClientMessage::GetHistory(_) => "getHistory",
+            ClientMessage::Set(_) => "set",
+            ClientMessage::SetAt(_) => "setAt",
+            ClientMessage::SetMany(_) => "setMany",
+            ClientMessage::SetExpiring(_) => "setExpiring",
+            ClientMessage::SetNx(_) => "setNx",
+            ClientMessage::SetXx(_) => "setXx",
+            ClientMessage::Expire(_) => "expire",
+            ClientMessage::CompareAndSwap(_) => "compareAndSwap",
+            ClientMessage::Increment(_) => "increment",
+            ClientMessage::ArrayPush(_) => "arrayPush",
+            ClientMessage::ArrayPop(_) => "arrayPop",
+            ClientMessage::ArrayRemove(_) => "arrayRemove",
+            ClientMessage::HintInvalidation(_) => "hintInvalidation",
+            ClientMessage::Publish(_) => "publish",
+            ClientMessage::PublishRetained(_) => "publishRetained",
+            ClientMessage::Subscribe(_) => "subscribe",
+            ClientMessage::PSubscribe(_) => "pSubscribe",
+            ClientMessage::Unsubscribe(_) => "unsubscribe",
+            ClientMessage::Resync(_) => "resync",
+            ClientMessage::Sync(_) => "sync",
+            ClientMessage::Cancel(_) => "cancel",
+            ClientMessage::Delete(_) => "delete",
+            ClientMessage::PDelete(_) => "pDelete",
+            ClientMessage::Restore(_) => "restore",
+            ClientMessage::Reserve(_) => "reserve",
+            ClientMessage::Release(_) => "release",
+            ClientMessage::Lock(_) => "lock",
+            ClientMessage::TryLock(_) => "tryLock",
+            ClientMessage::Unlock(_) => "unlock",
+            ClientMessage::StartRecording(_) => "startRecording",
+            ClientMessage::StopRecording(_) => "stopRecording",
+            ClientMessage::ReplayRecording(_) => "replayRecording",
+            ClientMessage::Ls(_) => "ls",
+            ClientMessage::SubscribeLs(_) => "subscribeLs",
+            ClientMessage::UnsubscribeLs(_) => "unsubscribeLs",
+            ClientMessage::SubscribeEvents(_) => "subscribeEvents",
+            ClientMessage::WhoSubscribes(_) => "whoSubscribes",
+            ClientMessage::Transform(_) => "transform",
+            ClientMessage::Keepalive => "keepalive",
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct AuthorizationRequest {
     pub auth_token: AuthToken,
 }
 
+/// Authenticates the client against the server's built-in user database.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationRequest {
+    pub username: String,
+    pub password: String,
+}
+
+/// Sets a prefix the server prepends to all keys and patterns of this session. Keys in the server's
+/// responses are stripped of the prefix again. System keys (`$SYS/...`) are never prefixed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct KeyPrefix {
+    pub prefix: Key,
+}
+
+/// Switches the session to one of the binary formats listed in the server's
+/// [`ServerInfo::wire_formats`](crate::ServerInfo). This message itself is always sent as JSON,
+/// everything after it may be sent as binary frames in the new format.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct SwitchWireFormat {
+    pub format: WireFormat,
+    /// Compression of binary frames in both directions, one of the algorithms listed in
+    /// [`ServerInfo::compression`](crate::ServerInfo).
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    pub compression: Compression,
+}
+
+/// Tells the server who this client is, so humans can tell clients apart. The server publishes it
+/// under `$SYS/clients/<client ID>/info`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct ClientInfo {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tags: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Get {
     pub transaction_id: TransactionId,
     pub key: Key,
 }
 
+/// Requests only the part of a key's value addressed by a JSON pointer (RFC 6901), e.g.
+/// `/a/b/0`. The server answers with a `state` message carrying the fragment.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct GetAt {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+    pub pointer: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct PGet {
     pub transaction_id: TransactionId,
     pub request_pattern: RequestPattern,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<usize>,
+    /// If set, the server sorts the matches before applying `offset` and `limit`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort: Option<Sort>,
+    /// Number of matches to skip.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    /// Maximum number of matches to return.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    /// If true, regular segments of the pattern match keys regardless of their case.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub case_insensitive: Option<bool>,
+}
+
+impl PGet {
+    /// Whether the matches need to be collected and sorted or windowed before they can be sent.
+    pub fn is_windowed(&self) -> bool {
+        self.sort.is_some() || self.offset.is_some() || self.limit.is_some()
+    }
+
+    pub fn is_case_insensitive(&self) -> bool {
+        self.case_insensitive == Some(true)
+    }
+}
+
+/// The order of the results of a `PGet`. Without a pointer the matches are sorted by key,
+/// otherwise by the part of their values addressed by the JSON pointer. Numbers sort before
+/// strings, values the pointer does not resolve in always come last.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Sort {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pointer: Option<String>,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+/// Requests the keys matching a pattern without their values.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PKeys {
+    pub transaction_id: TransactionId,
+    pub request_pattern: RequestPattern,
+}
+
+/// Requests the recent values of a key, if the server keeps a history for it. Without a limit,
+/// all retained values are returned.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct GetHistory {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
 }
 
+/// Replaces the part of a key's value addressed by a JSON pointer (RFC 6901). The parent of the
+/// addressed location must exist; object members are added if missing and `-` appends to arrays.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct SetAt {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+    pub pointer: String,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Set {
     pub transaction_id: TransactionId,
     pub key: Key,
     pub value: Value,
+    /// If set, the server answers with a `state` message carrying the key's previous value
+    /// instead of an `ack`. An `ack` is still sent if the key did not exist before.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_previous: Option<bool>,
 }
 
+/// Sets several values as a single operation, answered with a single `ack`. If any of the keys is
+/// invalid or may not be written, none of the values are set. Subscribers receive all values
+/// matching their pattern in a single message.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct SetMany {
+    pub transaction_id: TransactionId,
+    pub key_value_pairs: KeyValuePairs,
+}
+
+/// Sets a value that is deleted again once its TTL has passed, unless it is refreshed before.
+/// Subscribers are notified of the deletion just like of an explicit `delete`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct SetExpiring {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+    pub value: Value,
+    pub ttl_millis: u64,
+}
+
+/// Sets a value only if the key does not exist yet. Otherwise the request is answered with a
+/// `KeyExists` error and the key is left untouched.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct SetNx {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+    pub value: Value,
+}
+
+/// Sets a value only if the key already exists. Otherwise the request is answered with a
+/// `NoSuchValue` error and the key is not created.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct SetXx {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+    pub value: Value,
+}
+
+/// Changes the TTL of an existing value without touching the value itself. Without a TTL, the
+/// value no longer expires.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Expire {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl_millis: Option<u64>,
+}
+
+/// Sets a value only if the key is still in the expected state. If a `version` is given, it must
+/// match the key's current version, with 0 meaning that the key must not exist. Otherwise the
+/// current value must equal `expected`, where a missing or `null` expected value means that the
+/// key must not exist. A rejected swap is answered with a `CasConflict` error.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct CompareAndSwap {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    pub value: Value,
+}
+
+/// Atomically adds `delta` to the numeric value of a key, which counts as `0` if the key does not
+/// exist yet. The server answers with the new value, or with a `NotANumber` error if the current
+/// value is not a number. Integers stay integers as long as the sum fits into 64 bits.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Increment {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+    pub delta: Number,
+}
+
+/// Appends a value to the array stored at a key, creating the array if the key does not exist
+/// yet. The server answers with the resulting array, or with a `NotAnArray` error if the current
+/// value is not an array.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct ArrayPush {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+    pub value: Value,
+}
+
+/// Removes the last element of the array stored at a key. The server answers with the resulting
+/// array, popping from an empty array leaves it unchanged.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct ArrayPop {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+}
+
+/// Removes all elements equal to `value` from the array stored at a key. The server answers with
+/// the resulting array.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct ArrayRemove {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+    pub value: Value,
+}
+
+/// Announces that the value of a key is about to change, for example because a write is
+/// scheduled. The server forwards the hint to all server event subscribers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct HintInvalidation {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stale_in_millis: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Publish {
     pub transaction_id: TransactionId,
     pub key: Key,
     pub value: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<CorrelationId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<Key>,
 }
+
+/// Sets a value like `set`, but only notifies subscribers if it differs from the stored one,
+/// regardless of whether they subscribed to unique updates.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PublishRetained {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+    pub value: Value,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Subscribe {
     pub transaction_id: TransactionId,
@@ -106,9 +519,12 @@ pub struct Subscribe {
     pub unique: UniqueFlag,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub live_only: Option<LiveOnlyFlag>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initial_state_marker: Option<bool>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct PSubscribe {
     pub transaction_id: TransactionId,
@@ -118,15 +534,49 @@ pub struct PSubscribe {
     pub aggregate_events: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub live_only: Option<LiveOnlyFlag>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initial_state_marker: Option<bool>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Unsubscribe {
     pub transaction_id: TransactionId,
 }
 
+/// Asks the server to deliver the current state of a subscription again, e.g. after the client
+/// noticed a gap in its sequence numbers. The transaction ID is that of the subscription.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Resync {
+    pub transaction_id: TransactionId,
+}
+
+/// Asks the server to acknowledge once all writes previously sent on this connection have been
+/// applied and, if `persist` is set, written to disk. Persisting requires write access to `#`
+/// and a write-ahead log on the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct SyncRequest {
+    pub transaction_id: TransactionId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persist: Option<bool>,
+}
+
+/// Asks the server to abort an in-flight pget or a pdelete it has not processed yet. The
+/// transaction ID is that of the request to cancel.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Cancel {
+    pub transaction_id: TransactionId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Delete {
     pub transaction_id: TransactionId,
@@ -134,6 +584,7 @@ pub struct Delete {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct PDelete {
     pub transaction_id: TransactionId,
@@ -141,6 +592,98 @@ pub struct PDelete {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Restore {
+    pub transaction_id: TransactionId,
+    pub request_pattern: RequestPattern,
+}
+
+/// Claims exclusive write access to all keys matching the pattern until it is released or the
+/// client disconnects. Writes of other clients to matching keys are rejected with a
+/// `KeyReserved` error, as is the reservation itself if another client has already reserved an
+/// overlapping pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Reserve {
+    pub transaction_id: TransactionId,
+    pub request_pattern: RequestPattern,
+}
+
+/// Gives up a reservation made with `reserve`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Release {
+    pub transaction_id: TransactionId,
+    pub request_pattern: RequestPattern,
+}
+
+/// Acquires an exclusive write lock on a key, waiting until other clients have released it. The
+/// lock is held until it is unlocked, the client disconnects or the lease expires. Locking a key
+/// the client already holds renews the lease. The server acknowledges the request once the lock
+/// is granted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Lock {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+    pub lease_millis: u64,
+}
+
+/// Like `lock`, but fails with a `KeyLocked` error instead of waiting if the key is locked by
+/// another client.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct TryLock {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+    pub lease_millis: u64,
+}
+
+/// Releases a lock acquired with `lock` or `tryLock`, handing it to the next waiting client.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Unlock {
+    pub transaction_id: TransactionId,
+    pub key: Key,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct StartRecording {
+    pub transaction_id: TransactionId,
+    pub name: String,
+    pub request_pattern: RequestPattern,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct StopRecording {
+    pub transaction_id: TransactionId,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayRecording {
+    pub transaction_id: TransactionId,
+    pub name: String,
+    pub target_prefix: Key,
+    /// Factor by which the replay is faster than the original, defaults to 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Ls {
     pub transaction_id: TransactionId,
@@ -148,6 +691,7 @@ pub struct Ls {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct SubscribeLs {
     pub transaction_id: TransactionId,
@@ -155,12 +699,32 @@ pub struct SubscribeLs {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct UnsubscribeLs {
     pub transaction_id: TransactionId,
 }
 
+/// Subscribes to server events like clients connecting or disconnecting. Events are delivered
+/// until the subscription is cancelled with an [`Unsubscribe`] message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct SubscribeEvents {
+    pub transaction_id: TransactionId,
+}
+
+/// Asks the server which subscriptions match a key or pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct WhoSubscribes {
+    pub transaction_id: TransactionId,
+    pub request_pattern: RequestPattern,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Transform {
     pub transaction_id: TransactionId,
@@ -210,10 +774,88 @@ mod test {
                 transaction_id: 2,
                 key: "hello/world".to_owned(),
                 value: json!({ "this value": "is a ", "complex": "JSON object"}),
+                return_previous: None,
             })
         );
     }
 
+    #[test]
+    fn set_returning_previous_is_deserialized_correctly() {
+        let json = r#"{"set": {"transactionId": 2, "key": "hello/world", "value": 2, "returnPrevious": true}}"#;
+        let msg = serde_json::from_str::<ClientMessage>(&json).unwrap();
+        assert_eq!(
+            msg,
+            ClientMessage::Set(Set {
+                transaction_id: 2,
+                key: "hello/world".to_owned(),
+                value: json!(2),
+                return_previous: Some(true),
+            })
+        );
+    }
+
+    #[test]
+    fn set_many_is_deserialized_correctly() {
+        let json = r#"{"setMany": {"transactionId": 4, "keyValuePairs": [{"key": "a/b", "value": 1}, {"key": "a/c", "value": "two"}]}}"#;
+        let msg = serde_json::from_str::<ClientMessage>(&json).unwrap();
+        assert_eq!(
+            msg,
+            ClientMessage::SetMany(SetMany {
+                transaction_id: 4,
+                key_value_pairs: vec![
+                    ("a/b".to_owned(), json!(1)).into(),
+                    ("a/c".to_owned(), json!("two")).into(),
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn set_nx_is_deserialized_correctly() {
+        let json = r#"{"setNx": {"transactionId": 5, "key": "leader", "value": "node-a"}}"#;
+        let msg = serde_json::from_str::<ClientMessage>(&json).unwrap();
+        assert_eq!(
+            msg,
+            ClientMessage::SetNx(SetNx {
+                transaction_id: 5,
+                key: "leader".to_owned(),
+                value: json!("node-a"),
+            })
+        );
+    }
+
+    #[test]
+    fn set_expiring_is_deserialized_correctly() {
+        let json = r#"{"setExpiring": {"transactionId": 3, "key": "presence/kitchen", "value": true, "ttlMillis": 5000}}"#;
+        let msg = serde_json::from_str::<ClientMessage>(&json).unwrap();
+        assert_eq!(
+            msg,
+            ClientMessage::SetExpiring(SetExpiring {
+                transaction_id: 3,
+                key: "presence/kitchen".to_owned(),
+                value: json!(true),
+                ttl_millis: 5000,
+            })
+        );
+    }
+
+    #[test]
+    fn compare_and_swap_is_serialized_correctly() {
+        let msg = ClientMessage::CompareAndSwap(CompareAndSwap {
+            transaction_id: 4,
+            key: "counter".to_owned(),
+            expected: None,
+            version: Some(3),
+            value: json!(4),
+        });
+
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            json,
+            r#"{"compareAndSwap":{"transactionId":4,"key":"counter","version":3,"value":4}}"#
+        );
+    }
+
     #[test]
     fn psubscribe_without_aggregation_is_serialized_correctly() {
         let msg = ClientMessage::PSubscribe(PSubscribe {
@@ -222,6 +864,7 @@ mod test {
             unique: true,
             aggregate_events: None,
             live_only: None,
+            initial_state_marker: None,
         });
 
         let json = serde_json::to_string(&msg).unwrap();
@@ -239,6 +882,7 @@ mod test {
             unique: true,
             aggregate_events: Some(10),
             live_only: Some(true),
+            initial_state_marker: None,
         });
 
         let json = serde_json::to_string(&msg).unwrap();
@@ -262,6 +906,7 @@ mod test {
                 unique: true,
                 aggregate_events: None,
                 live_only: None,
+                initial_state_marker: None,
             })
         );
     }
@@ -279,6 +924,7 @@ mod test {
                 unique: true,
                 aggregate_events: Some(10),
                 live_only: Some(false),
+                initial_state_marker: None,
             })
         );
     }
@@ -343,4 +989,35 @@ mod test {
             })
         );
     }
+
+    #[test]
+    fn publish_without_correlation_is_serialized_correctly() {
+        let msg = ClientMessage::Publish(Publish {
+            transaction_id: 1,
+            key: "hello/world".to_owned(),
+            value: json!(42),
+            correlation_id: None,
+            reply_to: None,
+        });
+
+        let json = r#"{"publish":{"transactionId":1,"key":"hello/world","value":42}}"#;
+
+        assert_eq!(&serde_json::to_string(&msg).unwrap(), json);
+    }
+
+    #[test]
+    fn correlated_publish_is_deserialized_correctly() {
+        let json = r#"{"publish": {"transactionId": 3, "key": "rpc/add", "value": [1, 2], "correlationId": "abc-3", "replyTo": "rpc/add/reply/abc"}}"#;
+        let msg = serde_json::from_str::<ClientMessage>(json).unwrap();
+        assert_eq!(
+            msg,
+            ClientMessage::Publish(Publish {
+                transaction_id: 3,
+                key: "rpc/add".to_owned(),
+                value: json!([1, 2]),
+                correlation_id: Some("abc-3".to_owned()),
+                reply_to: Some("rpc/add/reply/abc".to_owned()),
+            })
+        );
+    }
 }
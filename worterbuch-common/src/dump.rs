@@ -0,0 +1,151 @@
+/*
+ *  Worterbuch dump file module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{
+    error::{WorterbuchError, WorterbuchResult},
+    KeySegment, KeyValuePair, RegularKeySegment,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[derive(Debug, Default, Deserialize)]
+struct Node {
+    #[serde(default)]
+    v: Option<Value>,
+    #[serde(default)]
+    t: HashMap<RegularKeySegment, Node>,
+}
+
+/// Read-only view of a persistence dump or export file, in the format the server writes its store
+/// in. Patterns match the same keys as they would on a server that restored the file.
+#[derive(Debug, Default, Deserialize)]
+pub struct StoreDump {
+    data: Node,
+}
+
+impl StoreDump {
+    pub fn parse(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    pub fn get(&self, path: &[impl AsRef<str>]) -> Option<&Value> {
+        self.node(path).and_then(|node| node.v.as_ref())
+    }
+
+    pub fn get_matches(&self, pattern: &str) -> WorterbuchResult<Vec<KeyValuePair>> {
+        let path = KeySegment::parse(pattern);
+        let mut matches = Vec::new();
+        collect_matches(&self.data, &mut Vec::new(), &path, &mut matches)
+            .map_err(|()| WorterbuchError::IllegalMultiWildcard(pattern.to_owned()))?;
+        Ok(matches)
+    }
+
+    /// The children of a key, or of the root if the path is empty.
+    pub fn ls(&self, path: &[impl AsRef<str>]) -> Option<Vec<RegularKeySegment>> {
+        self.node(path)
+            .map(|node| node.t.keys().map(ToOwned::to_owned).collect())
+    }
+
+    fn node(&self, path: &[impl AsRef<str>]) -> Option<&Node> {
+        path.iter()
+            .try_fold(&self.data, |node, elem| node.t.get(elem.as_ref()))
+    }
+}
+
+fn collect_matches<'n>(
+    node: &'n Node,
+    traversed: &mut Vec<&'n str>,
+    remaining: &[KeySegment],
+    matches: &mut Vec<KeyValuePair>,
+) -> Result<(), ()> {
+    let Some((next, tail)) = remaining.split_first() else {
+        if let Some(value) = &node.v {
+            matches.push((traversed.join("/"), value.to_owned()).into());
+        }
+        return Ok(());
+    };
+
+    let remaining = match next {
+        KeySegment::MultiWildcard if !tail.is_empty() => return Err(()),
+        KeySegment::MultiWildcard => {
+            if let Some(value) = &node.v {
+                matches.push((traversed.join("/"), value.to_owned()).into());
+            }
+            remaining
+        }
+        _ => tail,
+    };
+
+    for (key, child) in &node.t {
+        if let KeySegment::Regular(elem) = next {
+            if key != elem {
+                continue;
+            }
+        }
+        traversed.push(key);
+        collect_matches(child, traversed, remaining, matches)?;
+        traversed.pop();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn dumps_are_queried_like_the_store() {
+        let dump = StoreDump::parse(
+            &json!({
+                "data": { "t": {
+                    "a": { "v": 1, "t": {
+                        "b": { "v": 2 },
+                        "c": { "t": { "d": { "v": 3 } } }
+                    } },
+                    "e": { "v": 4 }
+                } },
+                "encrypted": []
+            })
+            .to_string(),
+        )
+        .expect("valid dump");
+
+        assert_eq!(dump.get(&["a", "b"]), Some(&json!(2)));
+        assert_eq!(dump.get(&["a", "c"]), None);
+
+        let mut keys: Vec<_> = dump
+            .get_matches("a/#")
+            .expect("valid pattern")
+            .into_iter()
+            .map(|kvp| kvp.key)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["a", "a/b", "a/c/d"]);
+        assert_eq!(dump.get_matches("?/b").expect("valid pattern").len(), 1);
+        assert!(dump.get_matches("#/b").is_err());
+
+        let mut children = dump.ls(&[] as &[&str]).expect("root exists");
+        children.sort();
+        assert_eq!(children, ["a", "e"]);
+        assert_eq!(dump.ls(&["x"]), None);
+    }
+}
@@ -17,8 +17,8 @@
  *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::{server::Err, ErrorCode, Key, MetaData, Privilege, RequestPattern};
-use std::{fmt, io, net::AddrParseError, num::ParseIntError};
+use crate::{server::Err, CasConflict, ErrorCode, Key, MetaData, Privilege, RequestPattern};
+use std::{fmt, io, net::AddrParseError, num::ParseIntError, time::Duration};
 use tokio::sync::{
     broadcast,
     mpsc::{self, error::SendError},
@@ -34,6 +34,20 @@ pub enum ConfigError {
     InvalidAddr(AddrParseError),
     InvalidInterval(ParseIntError),
     InvalidLicense(String),
+    InvalidValueTemplates(String),
+    InvalidDeprecations(String),
+    InvalidMetricsPushFormat(String),
+    InvalidTlsCertificate(String),
+    InvalidLogSink(String),
+    InvalidRetentionRules(String),
+    InvalidEncryptionKey(String),
+    InvalidHistoryRules(String),
+    InvalidAcl(String),
+    InvalidPersistenceMode(String),
+    InvalidReplicationRole(String),
+    InvalidServerMode(String),
+    InvalidClusterConfig(String),
+    InvalidKeyRules(String),
 }
 
 impl std::error::Error for ConfigError {}
@@ -57,6 +71,50 @@ impl fmt::Display for ConfigError {
             ConfigError::InvalidAddr(e) => write!(f, "invalid address: {e}"),
             ConfigError::InvalidInterval(e) => write!(f, "invalid interval: {e}"),
             ConfigError::InvalidLicense(e) => write!(f, "license file could not be loaded: {e}"),
+            ConfigError::InvalidValueTemplates(e) => {
+                write!(f, "value templates could not be loaded: {e}")
+            }
+            ConfigError::InvalidDeprecations(e) => {
+                write!(f, "key deprecations could not be loaded: {e}")
+            }
+            ConfigError::InvalidMetricsPushFormat(e) => write!(
+                f,
+                "invalid metrics push format: {e}; supported formats are 'pushgateway' and 'otlp'"
+            ),
+            ConfigError::InvalidTlsCertificate(e) => write!(
+                f,
+                "invalid TLS certificate: {e}; expected '[<server name>=]<cert file>:<key file>'"
+            ),
+            ConfigError::InvalidLogSink(e) => write!(
+                f,
+                "invalid log sink: {e}; supported sinks are 'journald', 'syslog' and 'syslog://<host>:<port>'"
+            ),
+            ConfigError::InvalidRetentionRules(e) => {
+                write!(f, "retention rules could not be loaded: {e}")
+            }
+            ConfigError::InvalidEncryptionKey(e) => {
+                write!(f, "invalid encryption key: {e}")
+            }
+            ConfigError::InvalidHistoryRules(e) => {
+                write!(f, "history rules could not be loaded: {e}")
+            }
+            ConfigError::InvalidAcl(e) => {
+                write!(f, "ACL could not be loaded: {e}")
+            }
+            ConfigError::InvalidPersistenceMode(e) => write!(
+                f,
+                "invalid persistence mode: {e}; supported modes are 'snapshot', 'wal' and 'both'"
+            ),
+            ConfigError::InvalidReplicationRole(e) => write!(
+                f,
+                "invalid replication role: {e}; supported roles are 'primary' and 'replica'"
+            ),
+            ConfigError::InvalidServerMode(e) => write!(
+                f,
+                "invalid server mode: {e}; supported modes are 'read-write' and 'read-only'"
+            ),
+            ConfigError::InvalidClusterConfig(e) => write!(f, "invalid cluster config: {e}"),
+            ConfigError::InvalidKeyRules(e) => write!(f, "invalid key rules: {e}"),
         }
     }
 }
@@ -93,6 +151,9 @@ pub enum AuthorizationError {
     TokenDecodeError(String),
     MissingToken,
     MissingSecret,
+    InvalidCredentials,
+    UserDatabaseDisabled,
+    LockedOut(u64),
 }
 
 impl fmt::Display for AuthorizationError {
@@ -104,6 +165,12 @@ impl fmt::Display for AuthorizationError {
             AuthorizationError::TokenDecodeError(msg) => msg.fmt(f),
             AuthorizationError::MissingToken => "No JWT was included in the request".fmt(f),
             AuthorizationError::MissingSecret => "No JWT was configured".fmt(f),
+            AuthorizationError::InvalidCredentials => "Invalid username or password".fmt(f),
+            AuthorizationError::UserDatabaseDisabled => "No user database was configured".fmt(f),
+            AuthorizationError::LockedOut(secs) => write!(
+                f,
+                "Too many failed authentication attempts, try again in {secs} s"
+            ),
         }
     }
 }
@@ -128,6 +195,34 @@ pub enum WorterbuchError {
     AuthorizationRequired(Privilege),
     AlreadyAuthorized,
     Unauthorized(AuthorizationError),
+    IllegalKey(Key, MetaData),
+    DeprecatedKey(Key, Option<Key>),
+    TooManySubscriptions(usize),
+    MessageTooLarge(MetaData),
+    NoSuchRecording(String),
+    CasConflict(Key, CasConflict),
+    /// The server's request queue is full and it is configured to reject requests instead of
+    /// waiting for it to drain.
+    Busy,
+    /// The store did not take or answer a request within the server's API timeout, it is
+    /// either overloaded or stuck.
+    Timeout(Duration),
+    /// The client cancelled the request before it was completed.
+    Cancelled,
+    /// A value was only to be set if the key did not exist yet, but it does.
+    KeyExists(Key),
+    /// The key or pattern is reserved by another client, which has exclusive write access to it.
+    KeyReserved(Key, RequestPattern),
+    /// The key is locked by another client, which has exclusive write access to it until it
+    /// unlocks it or its lease expires.
+    KeyLocked(Key),
+    /// The value was to be treated as a number, but it is not one, or the result of a calculation
+    /// cannot be represented as a JSON number.
+    NotANumber(Key),
+    /// The value was to be modified as an array, but it is not one.
+    NotAnArray(Key),
+    /// The server is in read-only mode and rejects all modifications until it is switched back.
+    ServerReadOnly,
 }
 
 impl std::error::Error for WorterbuchError {}
@@ -169,6 +264,42 @@ impl fmt::Display for WorterbuchError {
                 write!(f, "Handshake already done")
             }
             WorterbuchError::Unauthorized(err) => err.fmt(f),
+            WorterbuchError::IllegalKey(key, rule) => {
+                write!(f, "Key '{key}' violates naming rules: {rule}")
+            }
+            WorterbuchError::DeprecatedKey(key, redirect) => match redirect {
+                Some(redirect) => write!(f, "Key '{key}' is deprecated, use '{redirect}' instead"),
+                None => write!(f, "Key '{key}' is deprecated"),
+            },
+            WorterbuchError::TooManySubscriptions(max) => {
+                write!(f, "Client has reached its limit of {max} subscriptions")
+            }
+            WorterbuchError::MessageTooLarge(meta) => write!(f, "Message too large: {meta}"),
+            WorterbuchError::NoSuchRecording(name) => write!(f, "no recording named '{name}'"),
+            WorterbuchError::CasConflict(key, conflict) => write!(
+                f,
+                "compare-and-swap on '{key}' failed, current version is {}",
+                conflict.version
+            ),
+            WorterbuchError::Busy => write!(f, "server is busy, try again later"),
+            WorterbuchError::Cancelled => write!(f, "request was cancelled"),
+            WorterbuchError::KeyExists(key) => write!(f, "key '{key}' already exists"),
+            WorterbuchError::KeyReserved(key, pattern) => write!(
+                f,
+                "key '{key}' is reserved by another client with pattern '{pattern}'"
+            ),
+            WorterbuchError::KeyLocked(key) => write!(f, "key '{key}' is locked by another client"),
+            WorterbuchError::NotANumber(key) => write!(f, "value of key '{key}' is not a number"),
+            WorterbuchError::NotAnArray(key) => write!(f, "value of key '{key}' is not an array"),
+            WorterbuchError::ServerReadOnly => {
+                write!(f, "server is in read-only mode, modifications are rejected")
+            }
+            WorterbuchError::Timeout(t) => {
+                write!(
+                    f,
+                    "store did not respond within {t:?}, it may be overloaded or stuck"
+                )
+            }
         }
     }
 }
@@ -222,6 +353,7 @@ pub enum ConnectionError {
     Timeout,
     HttpError(tungstenite::http::Error),
     AuthorizationError(String),
+    WireFormatError(MetaData),
 }
 
 impl std::error::Error for ConnectionError {}
@@ -242,6 +374,7 @@ impl fmt::Display for ConnectionError {
             Self::Timeout => fmt::Display::fmt("timeout", f),
             Self::HttpError(e) => fmt::Display::fmt(&e, f),
             Self::AuthorizationError(msg) => fmt::Display::fmt(&msg, f),
+            Self::WireFormatError(msg) => fmt::Display::fmt(&msg, f),
         }
     }
 }
@@ -320,6 +453,21 @@ impl From<&WorterbuchError> for ErrorCode {
             WorterbuchError::AuthorizationRequired(_) => ErrorCode::AuthorizationRequired,
             WorterbuchError::AlreadyAuthorized => ErrorCode::AlreadyAuthorized,
             WorterbuchError::Unauthorized(_) => ErrorCode::Unauthorized,
+            WorterbuchError::IllegalKey(_, _) => ErrorCode::IllegalKey,
+            WorterbuchError::DeprecatedKey(_, _) => ErrorCode::DeprecatedKey,
+            WorterbuchError::TooManySubscriptions(_) => ErrorCode::TooManySubscriptions,
+            WorterbuchError::MessageTooLarge(_) => ErrorCode::MessageTooLarge,
+            WorterbuchError::NoSuchRecording(_) => ErrorCode::NoSuchRecording,
+            WorterbuchError::CasConflict(_, _) => ErrorCode::CasConflict,
+            WorterbuchError::Busy => ErrorCode::Busy,
+            WorterbuchError::Timeout(_) => ErrorCode::Timeout,
+            WorterbuchError::Cancelled => ErrorCode::Cancelled,
+            WorterbuchError::KeyExists(_) => ErrorCode::KeyExists,
+            WorterbuchError::KeyReserved(_, _) => ErrorCode::KeyReserved,
+            WorterbuchError::KeyLocked(_) => ErrorCode::KeyLocked,
+            WorterbuchError::NotANumber(_) => ErrorCode::NotANumber,
+            WorterbuchError::NotAnArray(_) => ErrorCode::NotAnArray,
+            WorterbuchError::ServerReadOnly => ErrorCode::ServerReadOnly,
             WorterbuchError::Other(_, _) | WorterbuchError::ServerResponse(_) => ErrorCode::Other,
         }
     }
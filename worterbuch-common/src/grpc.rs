@@ -0,0 +1,99 @@
+/*
+ *  Worterbuch gRPC bindings module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Types and service stubs generated from `proto/worterbuch.proto`, plus conversions between
+//! them and their JSON protocol counterparts.
+
+mod proto {
+    #![allow(clippy::all)]
+    tonic::include_proto!("worterbuch");
+}
+
+pub use proto::*;
+
+impl From<crate::KeyValuePair> for KeyValuePair {
+    fn from(kvp: crate::KeyValuePair) -> Self {
+        KeyValuePair {
+            key: kvp.key,
+            value: kvp.value.to_string(),
+        }
+    }
+}
+
+impl TryFrom<KeyValuePair> for crate::KeyValuePair {
+    type Error = serde_json::Error;
+
+    fn try_from(kvp: KeyValuePair) -> Result<Self, Self::Error> {
+        Ok(crate::KeyValuePair {
+            key: kvp.key,
+            value: serde_json::from_str(&kvp.value)?,
+        })
+    }
+}
+
+impl From<crate::KeyValuePairs> for KeyValuePairs {
+    fn from(kvps: crate::KeyValuePairs) -> Self {
+        KeyValuePairs {
+            key_value_pairs: kvps.into_iter().map(KeyValuePair::from).collect(),
+        }
+    }
+}
+
+impl From<crate::StateEvent> for StateEvent {
+    fn from(e: crate::StateEvent) -> Self {
+        let event = match e {
+            crate::StateEvent::KeyValue(kvp) => state_event::Event::Value(kvp.value.to_string()),
+            crate::StateEvent::Deleted(kvp) => state_event::Event::Deleted(kvp.value.to_string()),
+        };
+        StateEvent { event: Some(event) }
+    }
+}
+
+impl From<crate::PStateEvent> for PStateEvent {
+    fn from(e: crate::PStateEvent) -> Self {
+        let event = match e {
+            crate::PStateEvent::KeyValuePairs(kvps) => {
+                p_state_event::Event::KeyValuePairs(kvps.into())
+            }
+            crate::PStateEvent::Deleted(kvps) => p_state_event::Event::Deleted(kvps.into()),
+        };
+        PStateEvent { event: Some(event) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn values_are_transmitted_as_json() {
+        let kvp: KeyValuePair = crate::KeyValuePair::from(("a/b", json!({"c": [1, 2]}))).into();
+        assert_eq!(kvp.value, r#"{"c":[1,2]}"#);
+
+        let kvp: crate::KeyValuePair = kvp.try_into().unwrap();
+        assert_eq!(kvp.value, json!({"c": [1, 2]}));
+
+        let invalid = KeyValuePair {
+            key: "a/b".to_owned(),
+            value: "not json".to_owned(),
+        };
+        assert!(crate::KeyValuePair::try_from(invalid).is_err());
+    }
+}
@@ -19,9 +19,18 @@
 
 pub mod benchmark;
 mod client;
+pub mod dump;
 pub mod error;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod limits;
+pub mod recording;
+pub mod redact;
+#[cfg(feature = "schema")]
+pub mod schema;
 mod server;
 pub mod tcp;
+pub mod wire;
 
 pub use client::*;
 pub use server::*;
@@ -41,9 +50,24 @@ pub const SYSTEM_TOPIC_SOURCES: &str = "source-code";
 pub const SYSTEM_TOPIC_SUBSCRIPTIONS: &str = "subscriptions";
 pub const SYSTEM_TOPIC_CLIENTS_PROTOCOL: &str = "protocol";
 pub const SYSTEM_TOPIC_CLIENTS_ADDRESS: &str = "address";
+pub const SYSTEM_TOPIC_CLIENTS_INFO: &str = "info";
 pub const SYSTEM_TOPIC_LAST_WILL: &str = "lastWill";
 pub const SYSTEM_TOPIC_GRAVE_GOODS: &str = "graveGoods";
 pub const SYSTEM_TOPIC_SUPPORTED_PROTOCOL_VERSION: &str = "protocolVersion";
+pub const SYSTEM_TOPIC_DEPRECATED: &str = "deprecated";
+pub const SYSTEM_TOPIC_AUTH: &str = "auth";
+pub const SYSTEM_TOPIC_RETENTION: &str = "retention";
+pub const SYSTEM_TOPIC_ACL: &str = "acl";
+pub const SYSTEM_TOPIC_QUEUE: &str = "queue";
+pub const SYSTEM_TOPIC_REPLICATION: &str = "replication";
+pub const SYSTEM_TOPIC_LOCKS: &str = "locks";
+pub const SYSTEM_TOPIC_MODE: &str = "mode";
+pub const SYSTEM_TOPIC_EXPIRING: &str = "expiring";
+pub const TRASH_TOPIC_ROOT: &str = "$trash";
+pub const TRASH_TOPIC_ROOT_PREFIX: &str = "$trash/";
+pub const AUTH_TOPIC_ROOT: &str = "$auth";
+pub const AUTH_TOPIC_USERS: &str = "users";
+pub const AUTH_TOPIC_ROLES: &str = "roles";
 
 pub type TransactionId = u64;
 pub type RequestPattern = String;
@@ -61,8 +85,10 @@ pub type GraveGoods = RequestPatterns;
 pub type UniqueFlag = bool;
 pub type LiveOnlyFlag = bool;
 pub type AuthToken = String;
+pub type CorrelationId = String;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub enum Privilege {
     Read,
@@ -81,6 +107,7 @@ impl fmt::Display for Privilege {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize_repr, Deserialize_repr)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema_repr))]
 #[repr(u8)]
 pub enum ErrorCode {
     IllegalWildcard = 0b00000000,
@@ -98,6 +125,21 @@ pub enum ErrorCode {
     AlreadyAuthorized = 0b00001100,
     MissingValue = 0b00001101,
     Unauthorized = 0b00001110,
+    IllegalKey = 0b00001111,
+    DeprecatedKey = 0b00010000,
+    TooManySubscriptions = 0b00010001,
+    MessageTooLarge = 0b00010010,
+    NoSuchRecording = 0b00010011,
+    CasConflict = 0b00010100,
+    Busy = 0b00010101,
+    Timeout = 0b00010110,
+    Cancelled = 0b00010111,
+    KeyExists = 0b00011000,
+    KeyReserved = 0b00011001,
+    KeyLocked = 0b00011010,
+    NotANumber = 0b00011011,
+    NotAnArray = 0b00011100,
+    ServerReadOnly = 0b00011101,
     Other = 0b11111111,
 }
 
@@ -124,6 +166,7 @@ pub type Version = String;
 pub type ProtocolVersion = String;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Hash, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum Protocol {
     TCP,
     WS,
@@ -131,12 +174,24 @@ pub enum Protocol {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct KeyValuePair {
     pub key: Key,
     pub value: Value,
 }
 
+/// The current state of a key whose compare-and-swap was rejected. A `version` of 0 means that
+/// the key does not exist.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct CasConflict {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+    pub version: u64,
+}
+
 impl fmt::Display for KeyValuePair {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}={}", self.key, self.value)
@@ -203,6 +258,19 @@ impl TryFrom<(&str, &str)> for KeyValuePair {
     }
 }
 
+/// The value subscribers receive for a message that was published with a correlation ID and/or a
+/// reply-to key. Responders publish their reply to `reply_to`, carrying over the `correlation_id`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct CorrelatedValue {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<CorrelationId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<Key>,
+    pub value: Value,
+}
+
 // #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord, Tags)]
 pub type RegularKeySegment = String;
 
@@ -0,0 +1,116 @@
+/*
+ *  Worterbuch message decoder limits
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::error::{WorterbuchError, WorterbuchResult};
+
+/// Limits that are enforced on incoming messages before they are decoded, so a peer cannot make
+/// the decoder allocate arbitrary amounts of memory or recurse arbitrarily deep.
+///
+/// JSON carries no length prefixes, so the memory needed to decode a message is bounded by its size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecoderLimits {
+    /// Maximum size of a single message in bytes (not characters)
+    pub max_message_size: usize,
+    /// Maximum nesting depth of arrays and objects within a message
+    pub max_depth: usize,
+}
+
+impl Default for DecoderLimits {
+    fn default() -> Self {
+        Self {
+            max_message_size: 16 * 1024 * 1024,
+            max_depth: 64,
+        }
+    }
+}
+
+impl DecoderLimits {
+    pub fn check(&self, msg: &str) -> WorterbuchResult<()> {
+        self.check_size(msg.len())?;
+        self.check_depth(msg)
+    }
+
+    pub fn check_size(&self, size: usize) -> WorterbuchResult<()> {
+        if size > self.max_message_size {
+            return Err(WorterbuchError::MessageTooLarge(format!(
+                "message size of {size} bytes exceeds limit of {} bytes",
+                self.max_message_size
+            )));
+        }
+        Ok(())
+    }
+
+    /// Scans the raw message for its nesting depth without decoding it.
+    fn check_depth(&self, msg: &str) -> WorterbuchResult<()> {
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        for b in msg.bytes() {
+            if in_string {
+                match b {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => (),
+                }
+                continue;
+            }
+            match b {
+                b'"' => in_string = true,
+                b'{' | b'[' => {
+                    depth += 1;
+                    if depth > self.max_depth {
+                        return Err(WorterbuchError::MessageTooLarge(format!(
+                            "message nesting exceeds limit of {} levels",
+                            self.max_depth
+                        )));
+                    }
+                }
+                b'}' | b']' => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn limits_are_enforced() {
+        let limits = DecoderLimits {
+            max_message_size: 64,
+            max_depth: 3,
+        };
+
+        assert!(limits.check(r#"{"set":{"value":[1,2]}}"#).is_ok());
+        assert!(limits.check(r#"{"set":{"value":[[1],2]}}"#).is_err());
+        // brackets inside of strings don't count
+        assert!(limits.check(r#"{"set":{"value":"[[[\"{{"}}"#).is_ok());
+        assert!(limits
+            .check(&format!(r#"{{"set":"{}"}}"#, "x".repeat(64)))
+            .is_err());
+        // 30 characters, but 60 bytes
+        assert!(limits
+            .check(&format!(r#"{{"set":"{}"}}"#, "ü".repeat(30)))
+            .is_err());
+    }
+}
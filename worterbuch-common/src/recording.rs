@@ -0,0 +1,66 @@
+/*
+ *  Worterbuch recorded events
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{Key, PStateEvent};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+
+/// A single change of a recording. Recordings are stored as newline delimited JSON, one event per
+/// line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedEvent {
+    /// Milliseconds since the start of the recording.
+    pub offset_ms: u64,
+    pub key: Key,
+    /// The new value of the key or `None` if it was deleted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+}
+
+impl RecordedEvent {
+    pub fn new(offset: Duration, key: Key, value: Option<Value>) -> Self {
+        RecordedEvent {
+            offset_ms: offset.as_millis() as u64,
+            key,
+            value,
+        }
+    }
+
+    pub fn from_pstate_event(offset: Duration, event: &PStateEvent) -> Vec<RecordedEvent> {
+        match event {
+            PStateEvent::KeyValuePairs(kvps) => kvps
+                .iter()
+                .map(|kvp| {
+                    RecordedEvent::new(offset, kvp.key.to_owned(), Some(kvp.value.to_owned()))
+                })
+                .collect(),
+            PStateEvent::Deleted(kvps) => kvps
+                .iter()
+                .map(|kvp| RecordedEvent::new(offset, kvp.key.to_owned(), None))
+                .collect(),
+        }
+    }
+
+    /// The point in time at which the event is due when replayed at the given speed.
+    pub fn due(&self, speed: f64) -> Duration {
+        Duration::from_secs_f64(self.offset_ms as f64 / 1_000.0 / speed)
+    }
+}
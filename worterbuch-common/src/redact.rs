@@ -0,0 +1,86 @@
+/*
+ *  Worterbuch log redaction
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use serde_json::Value;
+
+/// Placeholder that replaces redacted values.
+pub const REDACTED: &str = "<redacted>";
+
+/// Fields that carry credentials and are always redacted.
+const CREDENTIALS: &[&str] = &["password", "authToken"];
+
+/// Returns a version of a raw JSON message that is safe to log. Credentials are always replaced, and
+/// so is the value of every key/value pair whose key is considered `sensitive`. Messages that are not
+/// valid JSON cannot be inspected and are replaced entirely.
+pub fn redact_message(msg: &str, sensitive: impl Fn(&str) -> bool) -> String {
+    match serde_json::from_str::<Value>(msg) {
+        Ok(mut value) => {
+            redact(&mut value, &sensitive);
+            value.to_string()
+        }
+        Err(_) => format!("{REDACTED} ({} bytes of invalid JSON)", msg.len()),
+    }
+}
+
+/// Replaces credentials and sensitive values within a JSON value in place.
+pub fn redact(value: &mut Value, sensitive: &impl Fn(&str) -> bool) {
+    match value {
+        Value::Object(obj) => {
+            let sensitive_kvp = obj
+                .get("key")
+                .and_then(Value::as_str)
+                .is_some_and(sensitive);
+            for (field, value) in obj.iter_mut() {
+                if CREDENTIALS.contains(&field.as_str()) || (sensitive_kvp && field == "value") {
+                    *value = Value::String(REDACTED.to_owned());
+                } else {
+                    redact(value, sensitive);
+                }
+            }
+        }
+        Value::Array(arr) => arr.iter_mut().for_each(|v| redact(v, sensitive)),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn credentials_and_sensitive_values_are_redacted() {
+        let sensitive = |key: &str| key.starts_with("secrets/");
+
+        let msg = r#"{"authenticationRequest":{"username":"admin","password":"hunter2"}}"#;
+        let redacted = redact_message(msg, sensitive);
+        assert!(!redacted.contains("hunter2"));
+        assert!(redacted.contains("admin"));
+
+        let mut msg = json!({"pState": {"transactionId": 1, "requestPattern": "#", "keyValuePairs": [
+            {"key": "secrets/db", "value": "hunter2"},
+            {"key": "public/answer", "value": 42}
+        ]}});
+        redact(&mut msg, &sensitive);
+        assert_eq!(msg["pState"]["keyValuePairs"][0]["value"], json!(REDACTED));
+        assert_eq!(msg["pState"]["keyValuePairs"][1]["value"], json!(42));
+
+        assert!(!redact_message("{\"password\": \"hunter2", sensitive).contains("hunter2"));
+    }
+}
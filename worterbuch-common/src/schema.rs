@@ -0,0 +1,227 @@
+/*
+ *  Worterbuch protocol schema module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{ClientMessage, ServerMessage};
+use schemars::gen::SchemaSettings;
+use serde_json::{json, Value};
+
+/// JSON schemas of all protocol messages, keyed by type name. References between them point to
+/// `definitions_path`.
+fn definitions(definitions_path: &str) -> Value {
+    let mut gen = SchemaSettings::draft07()
+        .with(|s| s.definitions_path = definitions_path.to_owned())
+        .into_generator();
+    // registers both messages and everything they reference under their type names
+    gen.subschema_for::<ClientMessage>();
+    gen.subschema_for::<ServerMessage>();
+    let definitions = gen.take_definitions();
+    serde_json::to_value(definitions).expect("schemas can always be serialized")
+}
+
+/// A JSON Schema document containing the definitions of all messages exchanged between client
+/// and server, with [`ClientMessage`] and [`ServerMessage`] as entry points.
+pub fn json_schema() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "Wörterbuch protocol",
+        "definitions": definitions("#/definitions/"),
+    })
+}
+
+/// An AsyncAPI document describing the websocket protocol served at `path`. It is generated from
+/// the message types themselves and can therefore not drift from the implementation.
+pub fn asyncapi(server_version: &str, protocol_version: &str, path: &str) -> Value {
+    json!({
+        "asyncapi": "2.6.0",
+        "info": {
+            "title": "Wörterbuch",
+            "version": server_version,
+            "description": format!("Wörterbuch websocket protocol version {protocol_version}"),
+        },
+        "defaultContentType": "application/json",
+        "channels": {
+            path: {
+                "publish": {
+                    "summary": "Messages sent from clients to the server",
+                    "message": {
+                        "name": "ClientMessage",
+                        "payload": { "$ref": "#/components/schemas/ClientMessage" }
+                    }
+                },
+                "subscribe": {
+                    "summary": "Messages sent from the server to clients",
+                    "message": {
+                        "name": "ServerMessage",
+                        "payload": { "$ref": "#/components/schemas/ServerMessage" }
+                    }
+                },
+                "bindings": { "ws": { "bindingVersion": "0.1.0" } }
+            }
+        },
+        "components": { "schemas": definitions("#/components/schemas/") }
+    })
+}
+
+/// TypeScript type definitions for all protocol messages, derived from their JSON schemas.
+pub fn typescript() -> String {
+    let definitions = definitions("#/definitions/");
+    let mut out = String::from("// Generated from the Wörterbuch protocol schema, do not edit.\n");
+    if let Value::Object(definitions) = definitions {
+        for (name, schema) in &definitions {
+            out.push_str(&format!("\nexport type {name} = {};\n", ts_type(schema)));
+        }
+    }
+    out
+}
+
+fn ts_type(schema: &Value) -> String {
+    let Value::Object(schema) = schema else {
+        // a schema of `true` accepts any value
+        return "unknown".to_owned();
+    };
+
+    if let Some(Value::String(r)) = schema.get("$ref") {
+        return r.rsplit('/').next().unwrap_or(r).to_owned();
+    }
+
+    if let Some(Value::Array(values)) = schema.get("enum") {
+        return union(values.iter().map(Value::to_string));
+    }
+
+    let mut parts = Vec::new();
+    if let Some(Value::Array(all)) = schema.get("allOf") {
+        parts.extend(all.iter().map(|s| format!("({})", ts_type(s))));
+    }
+    if let Some(Value::Array(any)) = schema.get("oneOf").or_else(|| schema.get("anyOf")) {
+        parts.push(format!("({})", union(any.iter().map(ts_type))));
+    }
+
+    match schema.get("type") {
+        Some(Value::String(t)) => parts.insert(0, ts_primitive(t, schema)),
+        Some(Value::Array(types)) => parts.insert(
+            0,
+            union(
+                types
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(|t| ts_primitive(t, schema)),
+            ),
+        ),
+        _ => (),
+    }
+
+    if parts.is_empty() {
+        "unknown".to_owned()
+    } else {
+        parts.join(" & ")
+    }
+}
+
+fn ts_primitive(t: &str, schema: &serde_json::Map<String, Value>) -> String {
+    match t {
+        "string" => "string".to_owned(),
+        "integer" | "number" => "number".to_owned(),
+        "boolean" => "boolean".to_owned(),
+        "null" => "null".to_owned(),
+        "array" => match schema.get("items") {
+            Some(items) => format!("({})[]", ts_type(items)),
+            None => "unknown[]".to_owned(),
+        },
+        "object" => ts_object(schema),
+        _ => "unknown".to_owned(),
+    }
+}
+
+fn ts_object(schema: &serde_json::Map<String, Value>) -> String {
+    let Some(Value::Object(properties)) = schema.get("properties") else {
+        return match schema.get("additionalProperties") {
+            Some(Value::Object(values)) => {
+                format!(
+                    "Record<string, {}>",
+                    ts_type(&Value::Object(values.clone()))
+                )
+            }
+            _ => "Record<string, unknown>".to_owned(),
+        };
+    };
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|r| r.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let fields: Vec<String> = properties
+        .iter()
+        .map(|(name, schema)| {
+            let optional = if required.contains(&name.as_str()) {
+                ""
+            } else {
+                "?"
+            };
+            format!(
+                "{}{optional}: {}",
+                Value::String(name.to_owned()),
+                ts_type(schema)
+            )
+        })
+        .collect();
+    format!("{{ {} }}", fields.join("; "))
+}
+
+fn union(types: impl Iterator<Item = String>) -> String {
+    types.collect::<Vec<_>>().join(" | ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn refs(value: &Value, acc: &mut Vec<String>) {
+        match value {
+            Value::Object(obj) => {
+                if let Some(Value::String(r)) = obj.get("$ref") {
+                    acc.push(r.to_owned());
+                }
+                obj.values().for_each(|v| refs(v, acc));
+            }
+            Value::Array(arr) => arr.iter().for_each(|v| refs(v, acc)),
+            _ => (),
+        }
+    }
+
+    #[test]
+    fn all_asyncapi_references_resolve() {
+        let doc = asyncapi("1.0.0", "0.7", "/ws");
+        let mut acc = Vec::new();
+        refs(&doc, &mut acc);
+        assert!(acc.contains(&"#/components/schemas/ClientMessage".to_owned()));
+        for r in acc {
+            assert!(doc.pointer(&r[1..]).is_some(), "unresolved reference {r}");
+        }
+    }
+
+    #[test]
+    fn typescript_defines_all_messages() {
+        let ts = typescript();
+        assert!(ts.contains("export type ClientMessage = "));
+        assert!(ts.contains("export type ServerMessage = "));
+        assert!(ts.contains("export type Set = { "));
+        assert!(ts.contains("\"transactionId\": number"));
+        assert!(!ts.contains("#/definitions"));
+    }
+}
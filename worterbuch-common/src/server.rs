@@ -18,13 +18,15 @@
  */
 
 use crate::{
-    ErrorCode, KeyValuePair, KeyValuePairs, MetaData, ProtocolVersion, RequestPattern,
+    wire::{Compression, WireFormat},
+    ErrorCode, Key, KeyValuePair, KeyValuePairs, MetaData, ProtocolVersion, RequestPattern,
     TransactionId, TypedKeyValuePair, Value, Version,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::fmt;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub enum ServerMessage {
     Welcome(Welcome),
@@ -34,6 +36,11 @@ pub enum ServerMessage {
     Err(Err),
     Authorized(Ack),
     LsState(LsState),
+    Keys(KeysState),
+    Event(EventState),
+    Subscribers(SubscribersState),
+    History(HistoryState),
+    InitialStateComplete(Ack),
     #[serde(rename = "")]
     Keepalive,
 }
@@ -47,6 +54,11 @@ impl ServerMessage {
             ServerMessage::State(msg) => Some(msg.transaction_id),
             ServerMessage::Err(msg) => Some(msg.transaction_id),
             ServerMessage::LsState(msg) => Some(msg.transaction_id),
+            ServerMessage::Keys(msg) => Some(msg.transaction_id),
+            ServerMessage::Event(msg) => Some(msg.transaction_id),
+            ServerMessage::Subscribers(msg) => Some(msg.transaction_id),
+            ServerMessage::History(msg) => Some(msg.transaction_id),
+            ServerMessage::InitialStateComplete(msg) => Some(msg.transaction_id),
             ServerMessage::Authorized(_) => Some(0),
             ServerMessage::Keepalive => None,
         }
@@ -54,6 +66,7 @@ impl ServerMessage {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Welcome {
     pub info: ServerInfo,
@@ -61,15 +74,25 @@ pub struct Welcome {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct PState {
     pub transaction_id: TransactionId,
     pub request_pattern: RequestPattern,
     #[serde(flatten)]
     pub event: PStateEvent,
+    /// Set if the requested pattern is deprecated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub more: Option<bool>,
+    /// Position of this message within its subscription, see [`State::seq`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub enum PStateEvent {
     KeyValuePairs(KeyValuePairs),
@@ -132,6 +155,7 @@ impl fmt::Display for PState {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Ack {
     pub transaction_id: TransactionId,
@@ -144,14 +168,23 @@ impl fmt::Display for Ack {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct State {
     pub transaction_id: TransactionId,
     #[serde(flatten)]
     pub event: StateEvent,
+    /// Set if the requested key is deprecated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<bool>,
+    /// Position of this message within its subscription, counting from 1. Only set on messages
+    /// delivered for a subscription, a gap means that messages were lost.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub enum StateEvent {
     KeyValue(KeyValuePair),
@@ -238,6 +271,7 @@ impl fmt::Display for State {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Err {
     pub transaction_id: TransactionId,
@@ -254,6 +288,7 @@ impl fmt::Display for Err {
 impl std::error::Error for Err {}
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Handshake {
     pub protocol_version: ProtocolVersion,
@@ -269,7 +304,137 @@ impl fmt::Display for Handshake {
     }
 }
 
+/// A server event delivered to clients that subscribed to server events.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct EventState {
+    pub transaction_id: TransactionId,
+    #[serde(flatten)]
+    pub event: ServerEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum ServerEvent {
+    ClientConnected(ClientEvent),
+    ClientDisconnected(ClientEvent),
+    Persistence(PersistenceEvent),
+    Error(MetaData),
+    InvalidationHint(InvalidationHint),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct ClientEvent {
+    pub client_id: String,
+    pub remote_addr: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PersistenceEvent {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<MetaData>,
+}
+
+/// Advises caches that the value of a key is about to change or be deleted, so they can stop
+/// serving it. Hints are purely advisory, the change itself is delivered like any other.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct InvalidationHint {
+    pub key: Key,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stale_in_millis: Option<u64>,
+}
+
+impl fmt::Display for ServerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerEvent::ClientConnected(e) => {
+                write!(f, "client connected: {} ({})", e.client_id, e.remote_addr)
+            }
+            ServerEvent::ClientDisconnected(e) => {
+                write!(
+                    f,
+                    "client disconnected: {} ({})",
+                    e.client_id, e.remote_addr
+                )
+            }
+            ServerEvent::Persistence(PersistenceEvent { error: Some(e), .. }) => {
+                write!(f, "persistence failed: {e}")
+            }
+            ServerEvent::Persistence(_) => write!(f, "persistence completed"),
+            ServerEvent::Error(e) => write!(f, "error: {e}"),
+            ServerEvent::InvalidationHint(InvalidationHint {
+                key,
+                stale_in_millis: Some(millis),
+            }) => write!(f, "value of {key} becomes stale in {millis} ms"),
+            ServerEvent::InvalidationHint(hint) => {
+                write!(f, "value of {} is about to change", hint.key)
+            }
+        }
+    }
+}
+
+/// The response to a `WhoSubscribes` request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct SubscribersState {
+    pub transaction_id: TransactionId,
+    pub subscribers: Vec<SubscriberInfo>,
+}
+
+/// The response to a `PKeys` request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct KeysState {
+    pub transaction_id: TransactionId,
+    pub keys: Vec<Key>,
+}
+
+impl fmt::Display for KeysState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.keys.join("\n"))
+    }
+}
+
+/// The response to a `GetHistory` request, oldest value first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryState {
+    pub transaction_id: TransactionId,
+    pub entries: Vec<HistoryEntry>,
+}
+
+/// A past value of a key and when it was written, in milliseconds since the Unix epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub timestamp: u64,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriberInfo {
+    pub client_id: String,
+    pub transaction_id: TransactionId,
+    pub pattern: RequestPattern,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct LsState {
     pub transaction_id: TransactionId,
@@ -306,11 +471,21 @@ fn escape_path_segment(str: impl AsRef<str>) -> String {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct ServerInfo {
     pub version: Version,
     pub protocol_version: ProtocolVersion,
     pub authorization_required: bool,
+    /// Changes whenever the server is restarted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot_id: Option<String>,
+    /// Binary formats the client may switch to with a `switchWireFormat` message.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wire_formats: Vec<WireFormat>,
+    /// Compression algorithms the client may request for binary frames along with a wire format.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub compression: Vec<Compression>,
 }
 
 #[cfg(test)]
@@ -320,11 +495,21 @@ mod test {
 
     use super::*;
 
+    #[cfg(feature = "arbitrary-precision")]
+    #[test]
+    fn numbers_survive_round_trips_unchanged() {
+        let json = r#"{"transactionId":1,"requestPattern":"ids/#","keyValuePairs":[{"key":"ids/a","value":18446744073709551617},{"key":"ids/b","value":0.10000000000000000000000001}]}"#;
+        let pstate: PState = serde_json::from_str(json).unwrap();
+        assert_eq!(json, &serde_json::to_string(&pstate).unwrap());
+    }
+
     #[test]
     fn state_is_serialized_correctly() {
         let state = State {
             transaction_id: 1,
             event: StateEvent::KeyValue(("$SYS/clients", json!(2)).into()),
+            deprecated: None,
+            seq: None,
         };
 
         let json = r#"{"transactionId":1,"keyValue":{"key":"$SYS/clients","value":2}}"#;
@@ -334,6 +519,8 @@ mod test {
         let state = State {
             transaction_id: 1,
             event: StateEvent::Deleted(("$SYS/clients", json!(2)).into()),
+            deprecated: None,
+            seq: None,
         };
 
         let json = r#"{"transactionId":1,"deleted":{"key":"$SYS/clients","value":2}}"#;
@@ -346,6 +533,8 @@ mod test {
         let state = State {
             transaction_id: 1,
             event: StateEvent::KeyValue(("$SYS/clients", json!(2)).into()),
+            deprecated: None,
+            seq: None,
         };
 
         let json = r#"{"transactionId":1,"keyValue":{"key":"$SYS/clients","value":2}}"#;
@@ -355,6 +544,8 @@ mod test {
         let state = State {
             transaction_id: 1,
             event: StateEvent::Deleted(("$SYS/clients", json!(2)).into()),
+            deprecated: None,
+            seq: None,
         };
 
         let json = r#"{"transactionId":1,"deleted":{"key":"$SYS/clients","value":2}}"#;
@@ -362,12 +553,48 @@ mod test {
         assert_eq!(state, serde_json::from_str(&json).unwrap());
     }
 
+    #[test]
+    fn deprecated_state_is_serialized_correctly() {
+        let state = State {
+            transaction_id: 1,
+            event: StateEvent::KeyValue(("old/key", json!(2)).into()),
+            deprecated: Some(true),
+            seq: None,
+        };
+
+        let json =
+            r#"{"transactionId":1,"keyValue":{"key":"old/key","value":2},"deprecated":true}"#;
+
+        assert_eq!(json, &serde_json::to_string(&state).unwrap());
+        assert_eq!(state, serde_json::from_str(&json).unwrap());
+    }
+
+    #[test]
+    fn sequenced_pstate_survives_round_trip() {
+        let pstate = PState {
+            transaction_id: 1,
+            request_pattern: "hello/#".to_owned(),
+            event: PStateEvent::KeyValuePairs(vec![("hello/world", json!(2)).into()]),
+            deprecated: None,
+            more: None,
+            seq: Some(7),
+        };
+
+        let json = r#"{"transactionId":1,"requestPattern":"hello/#","keyValuePairs":[{"key":"hello/world","value":2}],"seq":7}"#;
+
+        assert_eq!(json, &serde_json::to_string(&pstate).unwrap());
+        assert_eq!(pstate, serde_json::from_str(json).unwrap());
+    }
+
     #[test]
     fn pstate_is_serialized_correctly() {
         let pstate = PState {
             transaction_id: 1,
             request_pattern: "$SYS/clients".to_owned(),
             event: PStateEvent::KeyValuePairs(vec![("$SYS/clients", json!(2)).into()]),
+            deprecated: None,
+            more: None,
+            seq: None,
         };
 
         let json = r#"{"transactionId":1,"requestPattern":"$SYS/clients","keyValuePairs":[{"key":"$SYS/clients","value":2}]}"#;
@@ -378,6 +605,9 @@ mod test {
             transaction_id: 1,
             request_pattern: "$SYS/clients".to_owned(),
             event: PStateEvent::Deleted(vec![("$SYS/clients", json!(2)).into()]),
+            deprecated: None,
+            more: None,
+            seq: None,
         };
 
         let json = r#"{"transactionId":1,"requestPattern":"$SYS/clients","deleted":[{"key":"$SYS/clients","value":2}]}"#;
@@ -391,6 +621,9 @@ mod test {
             transaction_id: 1,
             request_pattern: "$SYS/clients".to_owned(),
             event: PStateEvent::KeyValuePairs(vec![("$SYS/clients", json!(2)).into()]),
+            deprecated: None,
+            more: None,
+            seq: None,
         };
 
         let json = r#"{"transactionId":1,"requestPattern":"$SYS/clients","keyValuePairs":[{"key":"$SYS/clients","value":2}]}"#;
@@ -401,10 +634,29 @@ mod test {
             transaction_id: 1,
             request_pattern: "$SYS/clients".to_owned(),
             event: PStateEvent::Deleted(vec![("$SYS/clients", json!(2)).into()]),
+            deprecated: None,
+            more: None,
+            seq: None,
         };
 
         let json = r#"{"transactionId":1,"requestPattern":"$SYS/clients","deleted":[{"key":"$SYS/clients","value":2}]}"#;
 
         assert_eq!(pstate, serde_json::from_str(&json).unwrap());
     }
+
+    #[test]
+    fn server_event_is_serialized_correctly() {
+        let event = EventState {
+            transaction_id: 3,
+            event: ServerEvent::ClientConnected(ClientEvent {
+                client_id: "abc".to_owned(),
+                remote_addr: "127.0.0.1:51234".to_owned(),
+            }),
+        };
+
+        let json = r#"{"transactionId":3,"clientConnected":{"clientId":"abc","remoteAddr":"127.0.0.1:51234"}}"#;
+
+        assert_eq!(json, &serde_json::to_string(&event).unwrap());
+        assert_eq!(event, serde_json::from_str(&json).unwrap());
+    }
 }
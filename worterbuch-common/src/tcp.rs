@@ -19,8 +19,8 @@
 
 use crate::error::{ConnectionError, ConnectionResult};
 use serde::Serialize;
-use std::io;
-use tokio::io::AsyncWriteExt;
+use std::{io, mem};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt};
 
 pub async fn write_line_and_flush(
     msg: impl Serialize,
@@ -39,7 +39,8 @@ pub async fn write_line_and_flush(
             format!("invalid JSON: '{json}' is empty"),
         )));
     }
-    log::debug!("Sending message: {json}");
+    // the line is not logged since only the caller knows which of its values are sensitive
+    log::debug!("Sending message ({} bytes)", json.len());
     log::trace!("Writing line …");
     tx.write_all(json.as_bytes()).await?;
     log::trace!("Writing line done.");
@@ -52,3 +53,83 @@ pub async fn write_line_and_flush(
 
     Ok(())
 }
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Complete(String),
+    /// A line that exceeded the length limit. Only its length in bytes is reported, its content is
+    /// discarded.
+    TooLong(usize),
+    /// A line that is not valid UTF-8. Contains the byte offset of the first invalid sequence.
+    InvalidUtf8(usize),
+}
+
+/// A line reader that never buffers more than a fixed number of bytes per line, no matter how
+/// long the lines sent by the peer are. All lengths are counted in bytes, not characters, and
+/// malformed UTF-8 is reported as a [`Line`] rather than an I/O error, so callers can tell a
+/// misbehaving peer from a broken connection. Like [`tokio::io::Lines`], reading the next line is
+/// cancel safe.
+pub struct LimitedLines<R> {
+    reader: R,
+    max_len: usize,
+    buf: Vec<u8>,
+    len: usize,
+}
+
+impl<R: AsyncBufRead + Unpin> LimitedLines<R> {
+    pub fn new(reader: R, max_len: usize) -> Self {
+        Self {
+            reader,
+            max_len,
+            buf: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub async fn next_line(&mut self) -> io::Result<Option<Line>> {
+        loop {
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                if self.len == 0 {
+                    return Ok(None);
+                }
+                return self.take_line().map(Some);
+            }
+
+            let newline = available.iter().position(|b| *b == b'\n');
+            let chunk = &available[..newline.unwrap_or(available.len())];
+            self.len += chunk.len();
+            if self.len <= self.max_len {
+                self.buf.extend_from_slice(chunk);
+            } else {
+                self.buf.clear();
+            }
+
+            match newline {
+                Some(pos) => {
+                    self.reader.consume(pos + 1);
+                    return self.take_line().map(Some);
+                }
+                None => {
+                    let consumed = available.len();
+                    self.reader.consume(consumed);
+                }
+            }
+        }
+    }
+
+    fn take_line(&mut self) -> io::Result<Line> {
+        let len = mem::take(&mut self.len);
+        let mut buf = mem::take(&mut self.buf);
+        if len > self.max_len {
+            return Ok(Line::TooLong(len));
+        }
+        if buf.last() == Some(&b'\r') {
+            buf.pop();
+        }
+        match String::from_utf8(buf) {
+            Ok(line) => Ok(Line::Complete(line)),
+            Err(e) => Ok(Line::InvalidUtf8(e.utf8_error().valid_up_to())),
+        }
+    }
+}
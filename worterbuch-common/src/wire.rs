@@ -0,0 +1,412 @@
+/*
+ *  Worterbuch wire format module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{
+    error::{ConnectionError, ConnectionResult},
+    limits::DecoderLimits,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{borrow::Cow, fmt, str::FromStr};
+
+/// Encoded messages smaller than this are sent uncompressed even if compression was negotiated,
+/// they rarely shrink enough to be worth the effort.
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+const UNCOMPRESSED: u8 = 0;
+const COMPRESSED: u8 = 1;
+
+/// The serialization used for client and server messages.
+///
+/// Every connection starts out with JSON. Over websocket connections a client may switch to one of
+/// the binary formats the server lists in [`ServerInfo::wire_formats`](crate::ServerInfo) by
+/// sending a [`SwitchWireFormat`](crate::SwitchWireFormat) message. From then on both sides send
+/// binary frames in the negotiated format, while text frames are still accepted and decoded as
+/// JSON, so messages that are already in flight during the switch are not lost.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum WireFormat {
+    #[default]
+    Json,
+    Cbor,
+    MsgPack,
+}
+
+impl WireFormat {
+    /// The binary formats a client can switch to.
+    pub fn binary() -> Vec<WireFormat> {
+        vec![WireFormat::Cbor, WireFormat::MsgPack]
+    }
+
+    pub fn is_binary(&self) -> bool {
+        *self != WireFormat::Json
+    }
+
+    #[allow(clippy::result_large_err)]
+    pub fn encode<T: Serialize>(
+        &self,
+        msg: &T,
+        compression: Compression,
+    ) -> ConnectionResult<Vec<u8>> {
+        let data = match self {
+            WireFormat::Json => serde_json::to_vec(msg)?,
+            WireFormat::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::ser::into_writer(msg, &mut buf)
+                    .map_err(|e| ConnectionError::WireFormatError(e.to_string()))?;
+                buf
+            }
+            // structs are encoded as maps, flattened and optional fields depend on field names
+            WireFormat::MsgPack => rmp_serde::to_vec_named(msg)
+                .map_err(|e| ConnectionError::WireFormatError(e.to_string()))?,
+        };
+        Ok(compression.compress(data))
+    }
+
+    /// Decodes a message, enforcing the given limits on its size and nesting depth. The size limit
+    /// applies to both the frame and the decompressed message.
+    #[allow(clippy::result_large_err)]
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        data: &[u8],
+        compression: Compression,
+        limits: &DecoderLimits,
+    ) -> ConnectionResult<T> {
+        limits
+            .check_size(data.len())
+            .map_err(ConnectionError::WorterbuchError)?;
+        let data = compression.decompress(data, limits)?;
+        let data = data.as_ref();
+        match self {
+            WireFormat::Json => {
+                let json = std::str::from_utf8(data)
+                    .map_err(|e| ConnectionError::WireFormatError(e.to_string()))?;
+                limits
+                    .check(json)
+                    .map_err(ConnectionError::WorterbuchError)?;
+                Ok(serde_json::from_str(json)?)
+            }
+            WireFormat::Cbor => {
+                ciborium::de::from_reader_with_recursion_limit(data, limits.max_depth)
+                    .map_err(|e| ConnectionError::WireFormatError(e.to_string()))
+            }
+            WireFormat::MsgPack => {
+                let mut de = rmp_serde::Deserializer::new(data);
+                de.set_max_depth(limits.max_depth);
+                T::deserialize(&mut de).map_err(|e| ConnectionError::WireFormatError(e.to_string()))
+            }
+        }
+    }
+}
+
+impl fmt::Display for WireFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireFormat::Json => "json".fmt(f),
+            WireFormat::Cbor => "cbor".fmt(f),
+            WireFormat::MsgPack => "msgpack".fmt(f),
+        }
+    }
+}
+
+/// Compression of individual binary frames, requested by a client together with a binary wire
+/// format in its [`SwitchWireFormat`](crate::SwitchWireFormat) message. The server lists the
+/// algorithms it supports in [`ServerInfo::compression`](crate::ServerInfo).
+///
+/// With compression negotiated, every binary frame starts with a flag byte telling whether the rest
+/// of it is compressed, which it is for messages of at least [`COMPRESSION_THRESHOLD`] bytes that
+/// actually shrink. Compressed LZ4 frames carry the decompressed size as a little endian `u32`
+/// in front of the compressed block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+}
+
+impl Compression {
+    /// The algorithms a client can request.
+    pub fn supported() -> Vec<Compression> {
+        vec![Compression::Lz4]
+    }
+
+    pub fn is_none(&self) -> bool {
+        *self == Compression::None
+    }
+
+    fn compress(&self, data: Vec<u8>) -> Vec<u8> {
+        match self {
+            Compression::None => data,
+            Compression::Lz4 => {
+                let compressed = if data.len() >= COMPRESSION_THRESHOLD {
+                    Some(lz4_flex::compress_prepend_size(&data))
+                        .filter(|compressed| compressed.len() < data.len())
+                } else {
+                    None
+                };
+                let (flag, payload) = match &compressed {
+                    Some(compressed) => (COMPRESSED, compressed),
+                    None => (UNCOMPRESSED, &data),
+                };
+                let mut frame = Vec::with_capacity(payload.len() + 1);
+                frame.push(flag);
+                frame.extend_from_slice(payload);
+                frame
+            }
+        }
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn decompress<'a>(
+        &self,
+        frame: &'a [u8],
+        limits: &DecoderLimits,
+    ) -> ConnectionResult<Cow<'a, [u8]>> {
+        match self {
+            Compression::None => Ok(Cow::Borrowed(frame)),
+            Compression::Lz4 => match frame.split_first() {
+                Some((&UNCOMPRESSED, data)) => Ok(Cow::Borrowed(data)),
+                Some((&COMPRESSED, data)) => {
+                    let Some((size, block)) = data.split_first_chunk::<4>() else {
+                        return Err(ConnectionError::WireFormatError(
+                            "compressed frame is missing its size".to_owned(),
+                        ));
+                    };
+                    // checked before decompressing so a forged size cannot exhaust memory
+                    let size = u32::from_le_bytes(*size) as usize;
+                    limits
+                        .check_size(size)
+                        .map_err(ConnectionError::WorterbuchError)?;
+                    lz4_flex::block::decompress(block, size)
+                        .map(Cow::Owned)
+                        .map_err(|e| ConnectionError::WireFormatError(e.to_string()))
+                }
+                _ => Err(ConnectionError::WireFormatError(
+                    "invalid compression flag".to_owned(),
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => "none".fmt(f),
+            Compression::Lz4 => "lz4".fmt(f),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            other => Err(format!("unknown compression '{other}'")),
+        }
+    }
+}
+
+impl FromStr for WireFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(WireFormat::Json),
+            "cbor" => Ok(WireFormat::Cbor),
+            "msgpack" | "messagepack" => Ok(WireFormat::MsgPack),
+            other => Err(format!("unknown wire format '{other}'")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ClientMessage, KeyValuePair, PState, PStateEvent, ServerMessage, Set, State, StateEvent,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn messages_survive_round_trips_in_all_formats() {
+        let limits = DecoderLimits::default();
+
+        let client_msg = ClientMessage::Set(Set {
+            transaction_id: 42,
+            key: "telemetry/temperature".to_owned(),
+            value: json!({"celsius": 21.5, "samples": [1, -2, u64::MAX]}),
+            return_previous: None,
+        });
+        let server_msgs = vec![
+            ServerMessage::State(State {
+                transaction_id: 1,
+                event: StateEvent::KeyValue(KeyValuePair {
+                    key: "a/b".to_owned(),
+                    value: json!("hello"),
+                }),
+                deprecated: None,
+                seq: Some(3),
+            }),
+            ServerMessage::PState(PState {
+                transaction_id: 2,
+                request_pattern: "a/#".to_owned(),
+                event: PStateEvent::Deleted(vec![KeyValuePair {
+                    key: "a/c".to_owned(),
+                    value: json!(null),
+                }]),
+                deprecated: None,
+                more: None,
+                seq: None,
+            }),
+            ServerMessage::Keepalive,
+        ];
+
+        for format in [WireFormat::Json, WireFormat::Cbor, WireFormat::MsgPack] {
+            for compression in [Compression::None, Compression::Lz4] {
+                let data = format.encode(&client_msg, compression).unwrap();
+                let decoded: ClientMessage = format.decode(&data, compression, &limits).unwrap();
+                assert_eq!(client_msg, decoded, "{format}/{compression}");
+
+                for msg in &server_msgs {
+                    let data = format.encode(msg, compression).unwrap();
+                    let decoded: ServerMessage =
+                        format.decode(&data, compression, &limits).unwrap();
+                    assert_eq!(msg, &decoded, "{format}/{compression}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn binary_formats_are_more_compact_than_json() {
+        let msg = ServerMessage::State(State {
+            transaction_id: 1234,
+            event: StateEvent::KeyValue(KeyValuePair {
+                key: "telemetry/samples".to_owned(),
+                value: json!([0.125, 1024, 65536, -7]),
+            }),
+            deprecated: None,
+            seq: None,
+        });
+        let json = WireFormat::Json
+            .encode(&msg, Compression::None)
+            .unwrap()
+            .len();
+        assert!(
+            WireFormat::Cbor
+                .encode(&msg, Compression::None)
+                .unwrap()
+                .len()
+                < json
+        );
+        assert!(
+            WireFormat::MsgPack
+                .encode(&msg, Compression::None)
+                .unwrap()
+                .len()
+                < json
+        );
+    }
+
+    #[test]
+    fn only_large_messages_are_compressed() {
+        let limits = DecoderLimits::default();
+        let msg = |value| {
+            ServerMessage::State(State {
+                transaction_id: 1,
+                event: StateEvent::KeyValue(KeyValuePair {
+                    key: "logs/latest".to_owned(),
+                    value,
+                }),
+                deprecated: None,
+                seq: None,
+            })
+        };
+
+        let small = msg(json!("hello"));
+        let frame = WireFormat::Cbor.encode(&small, Compression::Lz4).unwrap();
+        assert_eq!(frame[0], UNCOMPRESSED);
+        assert_eq!(
+            frame[1..],
+            WireFormat::Cbor.encode(&small, Compression::None).unwrap()
+        );
+
+        let large = msg(json!("all work and no play ".repeat(200)));
+        let uncompressed = WireFormat::Cbor.encode(&large, Compression::None).unwrap();
+        let frame = WireFormat::Cbor.encode(&large, Compression::Lz4).unwrap();
+        assert_eq!(frame[0], COMPRESSED);
+        assert!(frame.len() < uncompressed.len() / 4);
+        let decoded: ServerMessage = WireFormat::Cbor
+            .decode(&frame, Compression::Lz4, &limits)
+            .unwrap();
+        assert_eq!(large, decoded);
+
+        // the limit applies to the decompressed size, not just the frame
+        let limits = DecoderLimits {
+            max_message_size: uncompressed.len() - 1,
+            max_depth: 128,
+        };
+        assert!(WireFormat::Cbor
+            .decode::<ServerMessage>(&frame, Compression::Lz4, &limits)
+            .is_err());
+        assert!(WireFormat::Cbor
+            .decode::<ServerMessage>(&[7, 1, 2, 3], Compression::Lz4, &limits)
+            .is_err());
+    }
+
+    #[test]
+    fn decoder_limits_apply_to_binary_formats() {
+        let limits = DecoderLimits {
+            max_message_size: 1024,
+            max_depth: 4,
+        };
+        let msg = ClientMessage::Set(Set {
+            transaction_id: 1,
+            key: "a".to_owned(),
+            value: json!([[[[[[1]]]]]]),
+            return_previous: None,
+        });
+
+        for format in [WireFormat::Cbor, WireFormat::MsgPack] {
+            let data = format.encode(&msg, Compression::None).unwrap();
+            assert!(format
+                .decode::<ClientMessage>(&data, Compression::None, &limits)
+                .is_err());
+            let data = vec![0; 2048];
+            assert!(format
+                .decode::<ClientMessage>(&data, Compression::None, &limits)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn wire_formats_are_parsed_from_strings() {
+        assert_eq!("CBOR".parse(), Ok(WireFormat::Cbor));
+        assert_eq!("msgpack".parse(), Ok(WireFormat::MsgPack));
+        assert!("xml".parse::<WireFormat>().is_err());
+        assert_eq!("LZ4".parse(), Ok(Compression::Lz4));
+        assert!("zip".parse::<Compression>().is_err());
+    }
+}
@@ -0,0 +1,763 @@
+/*
+ *  Worterbuch conformance test suite
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{CaseResult, Connection, Failure, Options};
+use serde_json::json;
+use std::{fmt, time::Duration};
+use tokio::time::Instant;
+use worterbuch_common::{
+    AuthorizationRequest, ClientMessage as CM, Delete, ErrorCode, Get, Key, KeyValuePair, Ls,
+    PDelete, PGet, PStateEvent, PSubscribe, Publish, ServerMessage as SM, Set, StateEvent,
+    Subscribe, TransactionId, Unsubscribe, Value,
+};
+
+/// Servers send a keepalive after one second without any other message.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+/// How long to wait for messages that must not arrive.
+const QUIET_PERIOD: Duration = Duration::from_millis(500);
+
+macro_rules! ensure {
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err(Failure::new(format!($($arg)+)));
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Case {
+    Handshake,
+    ClientKeepalive,
+    ServerKeepalive,
+    SetGet,
+    GetMissingKey,
+    PGet,
+    Delete,
+    PDelete,
+    Ls,
+    Subscribe,
+    PSubscribe,
+    Unsubscribe,
+    UnsubscribeUnknown,
+    Publish,
+    WildcardInKey,
+    MultiWildcardPosition,
+    ReadOnlyKey,
+    DeeplyNestedMessage,
+    MalformedMessage,
+}
+
+impl Case {
+    pub const ALL: &'static [Case] = &[
+        Case::Handshake,
+        Case::ClientKeepalive,
+        Case::ServerKeepalive,
+        Case::SetGet,
+        Case::GetMissingKey,
+        Case::PGet,
+        Case::Delete,
+        Case::PDelete,
+        Case::Ls,
+        Case::Subscribe,
+        Case::PSubscribe,
+        Case::Unsubscribe,
+        Case::UnsubscribeUnknown,
+        Case::Publish,
+        Case::WildcardInKey,
+        Case::MultiWildcardPosition,
+        Case::ReadOnlyKey,
+        Case::DeeplyNestedMessage,
+        Case::MalformedMessage,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Case::Handshake => "handshake",
+            Case::ClientKeepalive => "client-keepalive",
+            Case::ServerKeepalive => "server-keepalive",
+            Case::SetGet => "set-get",
+            Case::GetMissingKey => "get-missing-key",
+            Case::PGet => "pget",
+            Case::Delete => "delete",
+            Case::PDelete => "pdelete",
+            Case::Ls => "ls",
+            Case::Subscribe => "subscribe",
+            Case::PSubscribe => "psubscribe",
+            Case::Unsubscribe => "unsubscribe",
+            Case::UnsubscribeUnknown => "unsubscribe-unknown",
+            Case::Publish => "publish",
+            Case::WildcardInKey => "wildcard-in-key",
+            Case::MultiWildcardPosition => "multi-wildcard-position",
+            Case::ReadOnlyKey => "read-only-key",
+            Case::DeeplyNestedMessage => "deeply-nested-message",
+            Case::MalformedMessage => "malformed-message",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Case> {
+        Case::ALL.iter().find(|c| c.name() == name).copied()
+    }
+
+    pub async fn run(&self, options: &Options) -> CaseResult {
+        let mut session = Session::open(options).await?;
+        let result = match self {
+            Case::Handshake => Ok(()),
+            Case::ClientKeepalive => client_keepalive(&mut session).await,
+            Case::ServerKeepalive => server_keepalive(&mut session).await,
+            Case::SetGet => set_get(&mut session).await,
+            Case::GetMissingKey => get_missing_key(&mut session).await,
+            Case::PGet => pget(&mut session).await,
+            Case::Delete => delete(&mut session).await,
+            Case::PDelete => pdelete(&mut session).await,
+            Case::Ls => ls(&mut session).await,
+            Case::Subscribe => subscribe(&mut session).await,
+            Case::PSubscribe => psubscribe(&mut session).await,
+            Case::Unsubscribe => unsubscribe(&mut session).await,
+            Case::UnsubscribeUnknown => unsubscribe_unknown(&mut session).await,
+            Case::Publish => publish(&mut session).await,
+            Case::WildcardInKey => wildcard_in_key(&mut session).await,
+            Case::MultiWildcardPosition => multi_wildcard_position(&mut session).await,
+            Case::ReadOnlyKey => read_only_key(&mut session).await,
+            Case::DeeplyNestedMessage => deeply_nested_message(&mut session).await,
+            Case::MalformedMessage => malformed_message(&mut session).await,
+        };
+        if result.is_ok() && *self != Case::MalformedMessage {
+            session.cleanup().await?;
+        }
+        result
+    }
+}
+
+impl fmt::Display for Case {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.name().fmt(f)
+    }
+}
+
+/// A handshaked (and if required authorized) connection with its own key namespace.
+struct Session {
+    conn: Connection,
+    timeout: Duration,
+    root: Key,
+    next_tid: TransactionId,
+    pending: Vec<SM>,
+}
+
+impl Session {
+    async fn open(options: &Options) -> Result<Self, Failure> {
+        let mut conn = options.endpoint.connect().await?;
+        let welcome = match conn.recv(options.timeout).await? {
+            Some(SM::Welcome(welcome)) => welcome,
+            Some(msg) => return Err(Failure::new(format!("expected welcome, got {msg:?}"))),
+            None => return Err(Failure::new("server closed connection before welcome")),
+        };
+        ensure!(
+            !welcome.client_id.is_empty(),
+            "welcome message contains no client ID"
+        );
+        ensure!(
+            !welcome.info.protocol_version.is_empty(),
+            "welcome message contains no protocol version"
+        );
+
+        let mut session = Session {
+            conn,
+            timeout: options.timeout,
+            root: format!("conformance/{}", welcome.client_id),
+            next_tid: 1,
+            pending: Vec::new(),
+        };
+
+        if welcome.info.authorization_required {
+            let Some(auth_token) = options.auth_token.clone() else {
+                return Err(Failure::new(
+                    "server requires authorization but no token was provided",
+                ));
+            };
+            session
+                .send(CM::AuthorizationRequest(AuthorizationRequest {
+                    auth_token,
+                }))
+                .await?;
+            match session.expect_for(0).await? {
+                SM::Authorized(_) => (),
+                msg => return Err(Failure::new(format!("authorization failed: {msg:?}"))),
+            }
+        }
+
+        Ok(session)
+    }
+
+    fn tid(&mut self) -> TransactionId {
+        let tid = self.next_tid;
+        self.next_tid += 1;
+        tid
+    }
+
+    fn key(&self, key: &str) -> Key {
+        format!("{}/{key}", self.root)
+    }
+
+    async fn send(&mut self, msg: CM) -> CaseResult {
+        self.conn.send(&msg).await
+    }
+
+    /// Receives the next message that is not a keepalive.
+    async fn recv(&mut self) -> Result<SM, Failure> {
+        loop {
+            match self.conn.recv(self.timeout).await? {
+                Some(SM::Keepalive) => continue,
+                Some(msg) => return Ok(msg),
+                None => return Err(Failure::new("server closed connection unexpectedly")),
+            }
+        }
+    }
+
+    /// Receives the next message for the given transaction ID. Messages for other transactions
+    /// are kept for later, since the protocol does not guarantee any order between them.
+    async fn expect_for(&mut self, tid: TransactionId) -> Result<SM, Failure> {
+        if let Some(pos) = self
+            .pending
+            .iter()
+            .position(|m| m.transaction_id() == Some(tid))
+        {
+            return Ok(self.pending.remove(pos));
+        }
+        let deadline = Instant::now() + self.timeout;
+        while Instant::now() < deadline {
+            let msg = self.recv().await?;
+            if msg.transaction_id() == Some(tid) {
+                return Ok(msg);
+            }
+            self.pending.push(msg);
+        }
+        Err(Failure::new(format!(
+            "no response for transaction {tid} within {} ms",
+            self.timeout.as_millis()
+        )))
+    }
+
+    async fn expect_ack(&mut self, tid: TransactionId) -> CaseResult {
+        match self.expect_for(tid).await? {
+            SM::Ack(_) => Ok(()),
+            msg => Err(Failure::new(format!("expected ack, got {msg:?}"))),
+        }
+    }
+
+    async fn expect_err(&mut self, tid: TransactionId, error_code: ErrorCode) -> CaseResult {
+        match self.expect_for(tid).await? {
+            SM::Err(e) if e.error_code == error_code => Ok(()),
+            msg => Err(Failure::new(format!(
+                "expected error code {error_code:?}, got {msg:?}"
+            ))),
+        }
+    }
+
+    /// Makes sure no message for the given transaction ID arrives within the quiet period.
+    async fn expect_nothing_for(&mut self, tid: TransactionId) -> CaseResult {
+        let deadline = Instant::now() + QUIET_PERIOD;
+        loop {
+            if let Some(msg) = self
+                .pending
+                .iter()
+                .find(|m| m.transaction_id() == Some(tid))
+            {
+                return Err(Failure::new(format!("unexpected message {msg:?}")));
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(());
+            }
+            match self.conn.recv(remaining).await {
+                Ok(Some(SM::Keepalive)) => (),
+                Ok(Some(msg)) => self.pending.push(msg),
+                Ok(None) => return Err(Failure::new("server closed connection unexpectedly")),
+                // timeout, nothing arrived
+                Err(_) => return Ok(()),
+            }
+        }
+    }
+
+    async fn set(&mut self, key: &str, value: Value) -> CaseResult {
+        let tid = self.tid();
+        let key = self.key(key);
+        self.send(CM::Set(Set {
+            transaction_id: tid,
+            key,
+            value,
+            return_previous: None,
+        }))
+        .await?;
+        self.expect_ack(tid).await
+    }
+
+    async fn get(&mut self, key: &str) -> Result<SM, Failure> {
+        let tid = self.tid();
+        let key = self.key(key);
+        self.send(CM::Get(Get {
+            transaction_id: tid,
+            key,
+        }))
+        .await?;
+        self.expect_for(tid).await
+    }
+
+    /// Removes everything the test case has written.
+    async fn cleanup(&mut self) -> CaseResult {
+        let tid = self.tid();
+        let request_pattern = format!("{}/#", self.root);
+        self.send(CM::PDelete(PDelete {
+            transaction_id: tid,
+            request_pattern,
+        }))
+        .await?;
+        match self.expect_for(tid).await? {
+            SM::PState(_) => Ok(()),
+            msg => Err(Failure::new(format!("cleanup failed: {msg:?}"))),
+        }
+    }
+}
+
+fn expect_value(msg: SM, key: &str, value: &Value) -> CaseResult {
+    match msg {
+        SM::State(state) => match state.event {
+            StateEvent::KeyValue(kvp) if kvp.key == key && &kvp.value == value => Ok(()),
+            event => Err(Failure::new(format!(
+                "expected {key}={value}, got {event:?}"
+            ))),
+        },
+        msg => Err(Failure::new(format!("expected state, got {msg:?}"))),
+    }
+}
+
+fn sorted(mut kvps: Vec<KeyValuePair>) -> Vec<KeyValuePair> {
+    kvps.sort_by(|a, b| a.key.cmp(&b.key));
+    kvps
+}
+
+async fn client_keepalive(session: &mut Session) -> CaseResult {
+    session.send(CM::Keepalive).await?;
+    // the connection must still be usable afterwards
+    session.set("keepalive", json!(true)).await
+}
+
+async fn server_keepalive(session: &mut Session) -> CaseResult {
+    let wait = KEEPALIVE_INTERVAL * 3;
+    let deadline = Instant::now() + wait;
+    while Instant::now() < deadline {
+        match session.conn.recv(wait).await? {
+            Some(SM::Keepalive) => return Ok(()),
+            Some(msg) => session.pending.push(msg),
+            None => return Err(Failure::new("server closed idle connection")),
+        }
+    }
+    Err(Failure::new(format!(
+        "no keepalive received within {} ms",
+        wait.as_millis()
+    )))
+}
+
+async fn set_get(session: &mut Session) -> CaseResult {
+    let value = json!({ "hello": "world", "numbers": [1, 2.5, -3] });
+    session.set("set-get", value.clone()).await?;
+    let msg = session.get("set-get").await?;
+    expect_value(msg, &session.key("set-get"), &value)?;
+
+    // overwriting a value is not an error
+    session.set("set-get", json!("updated")).await?;
+    let msg = session.get("set-get").await?;
+    expect_value(msg, &session.key("set-get"), &json!("updated"))
+}
+
+async fn get_missing_key(session: &mut Session) -> CaseResult {
+    let tid = session.tid();
+    let key = session.key("does/not/exist");
+    session
+        .send(CM::Get(Get {
+            transaction_id: tid,
+            key,
+        }))
+        .await?;
+    session.expect_err(tid, ErrorCode::NoSuchValue).await
+}
+
+async fn pget(session: &mut Session) -> CaseResult {
+    session.set("pget/a/x", json!(1)).await?;
+    session.set("pget/b/x", json!(2)).await?;
+    session.set("pget/b/y", json!(3)).await?;
+
+    let tid = session.tid();
+    let request_pattern = session.key("pget/?/x");
+    session
+        .send(CM::PGet(PGet {
+            transaction_id: tid,
+            request_pattern,
+            chunk_size: None,
+            sort: None,
+            offset: None,
+            limit: None,
+            case_insensitive: None,
+        }))
+        .await?;
+    let expected: Vec<KeyValuePair> = vec![
+        (session.key("pget/a/x"), json!(1)).into(),
+        (session.key("pget/b/x"), json!(2)).into(),
+    ];
+    match session.expect_for(tid).await? {
+        SM::PState(pstate) => match pstate.event {
+            PStateEvent::KeyValuePairs(kvps) => {
+                let kvps = sorted(kvps);
+                ensure!(kvps == expected, "expected {expected:?}, got {kvps:?}");
+                Ok(())
+            }
+            event => Err(Failure::new(format!(
+                "expected key/value pairs, got {event:?}"
+            ))),
+        },
+        msg => Err(Failure::new(format!("expected pstate, got {msg:?}"))),
+    }
+}
+
+async fn delete(session: &mut Session) -> CaseResult {
+    session.set("delete", json!("gone soon")).await?;
+
+    let tid = session.tid();
+    let key = session.key("delete");
+    session
+        .send(CM::Delete(Delete {
+            transaction_id: tid,
+            key: key.clone(),
+        }))
+        .await?;
+    match session.expect_for(tid).await? {
+        SM::State(state) => match state.event {
+            StateEvent::Deleted(kvp) if kvp.key == key && kvp.value == json!("gone soon") => (),
+            event => {
+                return Err(Failure::new(format!(
+                    "expected deleted value, got {event:?}"
+                )))
+            }
+        },
+        msg => return Err(Failure::new(format!("expected state, got {msg:?}"))),
+    }
+
+    match session.get("delete").await? {
+        SM::Err(e) if e.error_code == ErrorCode::NoSuchValue => Ok(()),
+        msg => Err(Failure::new(format!("value was not deleted: {msg:?}"))),
+    }
+}
+
+async fn pdelete(session: &mut Session) -> CaseResult {
+    session.set("pdelete/a", json!(1)).await?;
+    session.set("pdelete/b", json!(2)).await?;
+
+    let tid = session.tid();
+    let request_pattern = session.key("pdelete/#");
+    session
+        .send(CM::PDelete(PDelete {
+            transaction_id: tid,
+            request_pattern,
+        }))
+        .await?;
+    let expected: Vec<KeyValuePair> = vec![
+        (session.key("pdelete/a"), json!(1)).into(),
+        (session.key("pdelete/b"), json!(2)).into(),
+    ];
+    match session.expect_for(tid).await? {
+        SM::PState(pstate) => match pstate.event {
+            PStateEvent::Deleted(kvps) => {
+                let kvps = sorted(kvps);
+                ensure!(kvps == expected, "expected {expected:?}, got {kvps:?}");
+            }
+            event => {
+                return Err(Failure::new(format!(
+                    "expected deleted values, got {event:?}"
+                )))
+            }
+        },
+        msg => return Err(Failure::new(format!("expected pstate, got {msg:?}"))),
+    }
+
+    match session.get("pdelete/a").await? {
+        SM::Err(e) if e.error_code == ErrorCode::NoSuchValue => Ok(()),
+        msg => Err(Failure::new(format!("value was not deleted: {msg:?}"))),
+    }
+}
+
+async fn ls(session: &mut Session) -> CaseResult {
+    session.set("ls/a/x", json!(1)).await?;
+    session.set("ls/b", json!(2)).await?;
+    session.set("ls/c/x/y", json!(3)).await?;
+
+    let tid = session.tid();
+    let parent = Some(session.key("ls"));
+    session
+        .send(CM::Ls(Ls {
+            transaction_id: tid,
+            parent,
+        }))
+        .await?;
+    match session.expect_for(tid).await? {
+        SM::LsState(state) => {
+            let mut children = state.children;
+            children.sort();
+            ensure!(
+                children == ["a", "b", "c"],
+                "expected children [a, b, c], got {children:?}"
+            );
+            Ok(())
+        }
+        msg => Err(Failure::new(format!("expected ls state, got {msg:?}"))),
+    }
+}
+
+async fn subscribe(session: &mut Session) -> CaseResult {
+    session.set("subscribe", json!("initial")).await?;
+
+    let sub = session.tid();
+    let key = session.key("subscribe");
+    session
+        .send(CM::Subscribe(Subscribe {
+            transaction_id: sub,
+            key: key.clone(),
+            unique: true,
+            live_only: None,
+            initial_state_marker: None,
+        }))
+        .await?;
+    session.expect_ack(sub).await?;
+    // subscribers receive the current value right away
+    let msg = session.expect_for(sub).await?;
+    expect_value(msg, &key, &json!("initial"))?;
+
+    session.set("subscribe", json!("changed")).await?;
+    let msg = session.expect_for(sub).await?;
+    expect_value(msg, &key, &json!("changed"))?;
+
+    // unique subscriptions don't get notified if the value does not change
+    session.set("subscribe", json!("changed")).await?;
+    session.expect_nothing_for(sub).await
+}
+
+async fn psubscribe(session: &mut Session) -> CaseResult {
+    let sub = session.tid();
+    let request_pattern = session.key("psubscribe/?");
+    session
+        .send(CM::PSubscribe(PSubscribe {
+            transaction_id: sub,
+            request_pattern,
+            unique: false,
+            aggregate_events: None,
+            live_only: None,
+            initial_state_marker: None,
+        }))
+        .await?;
+    session.expect_ack(sub).await?;
+
+    session.set("psubscribe/a", json!(1)).await?;
+    let expected: Vec<KeyValuePair> = vec![(session.key("psubscribe/a"), json!(1)).into()];
+    match session.expect_for(sub).await? {
+        SM::PState(pstate) => match pstate.event {
+            PStateEvent::KeyValuePairs(kvps) => {
+                ensure!(kvps == expected, "expected {expected:?}, got {kvps:?}")
+            }
+            event => {
+                return Err(Failure::new(format!(
+                    "expected key/value pairs, got {event:?}"
+                )))
+            }
+        },
+        msg => return Err(Failure::new(format!("expected pstate, got {msg:?}"))),
+    }
+
+    // keys that don't match the pattern must not be delivered
+    session.set("psubscribe/a/b", json!(2)).await?;
+    session.expect_nothing_for(sub).await
+}
+
+async fn unsubscribe(session: &mut Session) -> CaseResult {
+    let sub = session.tid();
+    let key = session.key("unsubscribe");
+    session
+        .send(CM::Subscribe(Subscribe {
+            transaction_id: sub,
+            key,
+            unique: false,
+            live_only: Some(true),
+            initial_state_marker: None,
+        }))
+        .await?;
+    session.expect_ack(sub).await?;
+
+    session
+        .send(CM::Unsubscribe(Unsubscribe {
+            transaction_id: sub,
+        }))
+        .await?;
+    session.expect_ack(sub).await?;
+
+    session.set("unsubscribe", json!(1)).await?;
+    session.expect_nothing_for(sub).await
+}
+
+async fn unsubscribe_unknown(session: &mut Session) -> CaseResult {
+    let tid = session.tid();
+    session
+        .send(CM::Unsubscribe(Unsubscribe {
+            transaction_id: tid,
+        }))
+        .await?;
+    session.expect_err(tid, ErrorCode::NotSubscribed).await
+}
+
+async fn publish(session: &mut Session) -> CaseResult {
+    let sub = session.tid();
+    let key = session.key("publish");
+    session
+        .send(CM::Subscribe(Subscribe {
+            transaction_id: sub,
+            key: key.clone(),
+            unique: false,
+            live_only: None,
+            initial_state_marker: None,
+        }))
+        .await?;
+    session.expect_ack(sub).await?;
+
+    let tid = session.tid();
+    session
+        .send(CM::Publish(Publish {
+            transaction_id: tid,
+            key: key.clone(),
+            value: json!("transient"),
+            correlation_id: None,
+            reply_to: None,
+        }))
+        .await?;
+    session.expect_ack(tid).await?;
+    let msg = session.expect_for(sub).await?;
+    expect_value(msg, &key, &json!("transient"))?;
+
+    // published values are not stored
+    match session.get("publish").await? {
+        SM::Err(e) if e.error_code == ErrorCode::NoSuchValue => Ok(()),
+        msg => Err(Failure::new(format!("published value was stored: {msg:?}"))),
+    }
+}
+
+async fn wildcard_in_key(session: &mut Session) -> CaseResult {
+    let tid = session.tid();
+    let key = session.key("wildcard/?/key");
+    session
+        .send(CM::Set(Set {
+            transaction_id: tid,
+            key,
+            value: json!(1),
+            return_previous: None,
+        }))
+        .await?;
+    session.expect_err(tid, ErrorCode::IllegalWildcard).await?;
+
+    let tid = session.tid();
+    let key = session.key("wildcard/#");
+    session
+        .send(CM::Set(Set {
+            transaction_id: tid,
+            key,
+            value: json!(1),
+            return_previous: None,
+        }))
+        .await?;
+    session
+        .expect_err(tid, ErrorCode::IllegalMultiWildcard)
+        .await
+}
+
+async fn multi_wildcard_position(session: &mut Session) -> CaseResult {
+    let tid = session.tid();
+    let request_pattern = session.key("#/illegal");
+    session
+        .send(CM::PGet(PGet {
+            transaction_id: tid,
+            request_pattern,
+            chunk_size: None,
+            sort: None,
+            offset: None,
+            limit: None,
+            case_insensitive: None,
+        }))
+        .await?;
+    session
+        .expect_err(tid, ErrorCode::MultiWildcardAtIllegalPosition)
+        .await
+}
+
+async fn read_only_key(session: &mut Session) -> CaseResult {
+    let tid = session.tid();
+    session
+        .send(CM::Set(Set {
+            transaction_id: tid,
+            key: "$SYS/conformance".to_owned(),
+            value: json!(1),
+            return_previous: None,
+        }))
+        .await?;
+    session.expect_err(tid, ErrorCode::ReadOnlyKey).await
+}
+
+async fn deeply_nested_message(session: &mut Session) -> CaseResult {
+    // far beyond any sensible nesting limit, the server must reject this without decoding it
+    let depth = 10_000;
+    let key = session.key("nested");
+    let json = format!(
+        r#"{{"set":{{"transactionId":0,"key":"{key}","value":{}{}}}}}"#,
+        "[".repeat(depth),
+        "]".repeat(depth)
+    );
+    session.conn.send_raw(&json).await?;
+    session.expect_err(0, ErrorCode::MessageTooLarge).await?;
+
+    // the connection must still be usable afterwards
+    session.set("nested", json!([[[]]])).await
+}
+
+async fn malformed_message(session: &mut Session) -> CaseResult {
+    session.conn.send_raw("{ this is not JSON").await?;
+    // the server gives up on clients that don't speak the protocol
+    loop {
+        match session.conn.recv(session.timeout).await? {
+            Some(SM::Keepalive) => continue,
+            Some(msg) => return Err(Failure::new(format!("expected disconnect, got {msg:?}"))),
+            None => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn case_names_are_unique_and_round_trip() {
+        for case in Case::ALL {
+            assert_eq!(Case::from_name(case.name()), Some(*case));
+        }
+        assert_eq!(Case::from_name("no-such-case"), None);
+    }
+}
@@ -0,0 +1,133 @@
+/*
+ *  Worterbuch conformance test suite
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A protocol conformance test suite for Wörterbuch servers.
+//!
+//! The suite connects to a running server over any of the supported transports and checks that the
+//! server behaves as the protocol specification demands: the handshake, every message type, error
+//! codes, keepalives and a couple of edge cases. Each [`Case`] runs on its own connection, so a
+//! server that misbehaves in one case does not drag down the others.
+//!
+//! ```no_run
+//! # async fn example() {
+//! use std::time::Duration;
+//! use worterbuch_conformance::{run, Endpoint, Options};
+//!
+//! let options = Options {
+//!     endpoint: Endpoint::Tcp("localhost:8081".to_owned()),
+//!     auth_token: None,
+//!     timeout: Duration::from_secs(3),
+//! };
+//! let report = run(&options).await;
+//! assert!(report.is_success(), "{report}");
+//! # }
+//! ```
+
+mod cases;
+mod transport;
+
+pub use cases::Case;
+pub use transport::{Connection, Endpoint};
+
+use std::{fmt, time::Duration};
+use worterbuch_common::AuthToken;
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub endpoint: Endpoint,
+    /// Token to authorize with if the server requires authorization
+    pub auth_token: Option<AuthToken>,
+    /// How long to wait for any single response from the server
+    pub timeout: Duration,
+}
+
+/// The reason a test case failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure(String);
+
+impl Failure {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Failure(reason.into())
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+pub type CaseResult = Result<(), Failure>;
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub endpoint: Endpoint,
+    pub results: Vec<(Case, CaseResult)>,
+}
+
+impl Report {
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|(_, r)| r.is_ok()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed()
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed() == 0
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "conformance report for {}:", self.endpoint)?;
+        for (case, result) in &self.results {
+            match result {
+                Ok(()) => writeln!(f, "  PASS {case}")?,
+                Err(e) => writeln!(f, "  FAIL {case}: {e}")?,
+            }
+        }
+        write!(f, "{} passed, {} failed", self.passed(), self.failed())
+    }
+}
+
+/// Runs all test cases against the configured endpoint.
+pub async fn run(options: &Options) -> Report {
+    run_cases(options, Case::ALL).await
+}
+
+/// Runs a selection of test cases against the configured endpoint.
+pub async fn run_cases(options: &Options, cases: &[Case]) -> Report {
+    let mut results = Vec::with_capacity(cases.len());
+    for case in cases {
+        log::info!("Running conformance test '{case}' …");
+        let result = case.run(options).await;
+        if let Err(e) = &result {
+            log::warn!("Conformance test '{case}' failed: {e}");
+        }
+        results.push((*case, result));
+    }
+    Report {
+        endpoint: options.endpoint.clone(),
+        results,
+    }
+}
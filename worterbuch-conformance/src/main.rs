@@ -0,0 +1,80 @@
+/*
+ *  Worterbuch conformance test suite
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use std::{env, process::exit, time::Duration};
+use worterbuch_conformance::{run_cases, Case, Endpoint, Options};
+
+#[derive(Parser)]
+#[command(author, version, about = "Check a Wörterbuch server for protocol conformance.", long_about = None)]
+struct Args {
+    /// Websocket URL of the server to test, e.g. ws://localhost:8080/ws
+    #[arg(long, conflicts_with = "tcp", required_unless_present = "tcp")]
+    ws: Option<String>,
+    /// TCP address of the server to test, e.g. localhost:8081
+    #[arg(long)]
+    tcp: Option<String>,
+    /// Auth token to use if the server requires authorization [default: $WORTERBUCH_AUTH_TOKEN]
+    #[arg(long)]
+    auth: Option<String>,
+    /// Timeout for individual responses in milliseconds
+    #[arg(long, default_value_t = 3_000)]
+    timeout: u64,
+    /// Only run the test cases with the given names
+    #[arg(long, short)]
+    case: Vec<String>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    env_logger::init();
+    let args: Args = Args::parse();
+
+    let endpoint = match (args.ws, args.tcp) {
+        (Some(url), _) => Endpoint::Ws(url),
+        (None, Some(addr)) => Endpoint::Tcp(addr),
+        (None, None) => return Err(anyhow!("no endpoint specified")),
+    };
+
+    let cases = if args.case.is_empty() {
+        Case::ALL.to_vec()
+    } else {
+        args.case
+            .iter()
+            .map(|name| Case::from_name(name).ok_or_else(|| anyhow!("unknown test case '{name}'")))
+            .collect::<Result<_>>()?
+    };
+
+    let options = Options {
+        endpoint,
+        auth_token: args.auth.or_else(|| env::var("WORTERBUCH_AUTH_TOKEN").ok()),
+        timeout: Duration::from_millis(args.timeout),
+    };
+
+    let report = run_cases(&options, &cases).await;
+    println!("{report}");
+
+    if !report.is_success() {
+        exit(1);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,160 @@
+/*
+ *  Worterbuch conformance test suite
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::Failure;
+use futures_util::{SinkExt, StreamExt};
+use std::{fmt, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    time::timeout,
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use worterbuch_common::{ClientMessage, ServerMessage};
+
+/// A server endpoint the suite can be run against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// A websocket URL like `ws://localhost:8080/ws`
+    Ws(String),
+    /// A TCP socket address like `localhost:8081`
+    Tcp(String),
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Ws(url) => write!(f, "{url}"),
+            Endpoint::Tcp(addr) => write!(f, "tcp://{addr}"),
+        }
+    }
+}
+
+impl Endpoint {
+    pub async fn connect(&self) -> Result<Connection, Failure> {
+        match self {
+            Endpoint::Ws(url) => {
+                let (websocket, _) = connect_async(url)
+                    .await
+                    .map_err(|e| Failure::new(format!("could not connect to {url}: {e}")))?;
+                Ok(Connection::Ws(websocket))
+            }
+            Endpoint::Tcp(addr) => {
+                let socket = TcpStream::connect(addr)
+                    .await
+                    .map_err(|e| Failure::new(format!("could not connect to {addr}: {e}")))?;
+                let (rx, tx) = socket.into_split();
+                Ok(Connection::Tcp(tx, BufReader::new(rx).lines()))
+            }
+        }
+    }
+}
+
+/// A raw connection to a server. Unlike the client library this does not interpret any of the
+/// messages it sends or receives, so the test cases can observe exactly what the server does.
+pub enum Connection {
+    Ws(WebSocketStream<MaybeTlsStream<TcpStream>>),
+    Tcp(OwnedWriteHalf, Lines<BufReader<OwnedReadHalf>>),
+}
+
+impl Connection {
+    pub async fn send(&mut self, msg: &ClientMessage) -> Result<(), Failure> {
+        let json = serde_json::to_string(msg)
+            .map_err(|e| Failure::new(format!("could not serialize message: {e}")))?;
+        self.send_raw(&json).await
+    }
+
+    /// Sends a string as-is, regardless of whether or not it is a valid message.
+    pub async fn send_raw(&mut self, json: &str) -> Result<(), Failure> {
+        log::debug!("Sending message: {json}");
+        match self {
+            Connection::Ws(websocket) => websocket
+                .send(Message::Text(json.to_owned()))
+                .await
+                .map_err(|e| Failure::new(format!("could not send message: {e}"))),
+            Connection::Tcp(tx, _) => {
+                let line = format!("{json}\n");
+                tx.write_all(line.as_bytes())
+                    .await
+                    .map_err(|e| Failure::new(format!("could not send message: {e}")))?;
+                tx.flush()
+                    .await
+                    .map_err(|e| Failure::new(format!("could not send message: {e}")))
+            }
+        }
+    }
+
+    /// Waits for the next message from the server. Returns `Ok(None)` if the server closed the
+    /// connection and fails if nothing arrives within the given timeout or the server sends
+    /// something that is not a valid message.
+    pub async fn recv(&mut self, wait: Duration) -> Result<Option<ServerMessage>, Failure> {
+        let json = match timeout(wait, self.recv_raw()).await {
+            Ok(it) => it?,
+            Err(_) => {
+                return Err(Failure::new(format!(
+                    "no message received within {} ms",
+                    wait.as_millis()
+                )))
+            }
+        };
+        match json {
+            Some(json) => serde_json::from_str(&json)
+                .map(Some)
+                .map_err(|e| Failure::new(format!("invalid server message '{json}': {e}"))),
+            None => Ok(None),
+        }
+    }
+
+    async fn recv_raw(&mut self) -> Result<Option<String>, Failure> {
+        match self {
+            Connection::Ws(websocket) => loop {
+                match websocket.next().await {
+                    Some(Ok(Message::Text(json))) => {
+                        log::debug!("Received message: {json}");
+                        return Ok(Some(json));
+                    }
+                    Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                    Some(Ok(Message::Close(_))) | None => return Ok(None),
+                    Some(Ok(msg)) => {
+                        return Err(Failure::new(format!("unexpected websocket frame: {msg}")))
+                    }
+                    // a reset connection is just as closed as one that has been shut down cleanly
+                    Some(Err(e)) => {
+                        log::debug!("Websocket closed with error: {e}");
+                        return Ok(None);
+                    }
+                }
+            },
+            Connection::Tcp(_, rx) => match rx.next_line().await {
+                Ok(Some(json)) => {
+                    log::debug!("Received message: {json}");
+                    Ok(Some(json))
+                }
+                Ok(None) => Ok(None),
+                Err(e) => {
+                    log::debug!("TCP stream closed with error: {e}");
+                    Ok(None)
+                }
+            },
+        }
+    }
+}
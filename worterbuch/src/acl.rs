@@ -0,0 +1,355 @@
+/*
+ *  Worterbuch access control list module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{
+    auth::pattern_matches, server::common::CloneableWbApi, worterbuch::patterns_overlap,
+    INTERNAL_CLIENT_ID,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs, iter,
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::{select, time::interval};
+use tokio_graceful_shutdown::SubsystemHandle;
+use uuid::Uuid;
+use worterbuch_common::{
+    error::{AuthorizationError, AuthorizationResult, ConfigError, ConfigResult},
+    topic, KeySegment, PStateEvent, Privilege, RequestPattern, SYSTEM_TOPIC_ACL, SYSTEM_TOPIC_ROOT,
+};
+
+/// How often the ACL file is checked for changes.
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Role every client has in addition to the roles listed in its JWT.
+pub const DEFAULT_ROLE: &str = "default";
+
+const ROLES: &str = "roles";
+
+/// A single ACL entry. A rule allows privileges on all keys matching its pattern and denies
+/// privileges on every pattern that could match any of those keys. Denials always take
+/// precedence over allowances, privileges that are neither allowed nor denied are denied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AclRule {
+    pub pattern: RequestPattern,
+    #[serde(default)]
+    pub allow: Vec<Privilege>,
+    #[serde(default)]
+    pub deny: Vec<Privilege>,
+}
+
+/// The rules of a role, stored under `$SYS/acl/roles/<role>`.
+pub type AclRole = Vec<AclRule>;
+
+/// Contents of an ACL file, e.g.
+///
+/// ```yaml
+/// roles:
+///   default:
+///     - pattern: public/#
+///       allow: [read]
+///   operator:
+///     - pattern: plant/#
+///       allow: [read, write, delete]
+///     - pattern: plant/safety/#
+///       deny: [write, delete]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AclFile {
+    #[serde(default)]
+    pub roles: HashMap<String, AclRole>,
+}
+
+impl AclFile {
+    pub fn load(path: &Path) -> ConfigResult<Self> {
+        let yaml = fs::read_to_string(path).map_err(|e| {
+            ConfigError::InvalidAcl(format!("could not read {}: {e}", path.display()))
+        })?;
+        serde_yaml::from_str(&yaml).map_err(|e| {
+            ConfigError::InvalidAcl(format!("could not parse {}: {e}", path.display()))
+        })
+    }
+}
+
+/// Server-side access control, evaluated for every operation in addition to the privileges of
+/// a client's JWT. Roles are initially loaded from a YAML file and published under
+/// `$SYS/acl/roles`, where they can be edited live. Changes to the file are picked up without a
+/// restart and replace any live edits.
+#[derive(Debug, Clone)]
+pub struct SharedAcl {
+    file: PathBuf,
+    roles: Arc<RwLock<HashMap<String, AclRole>>>,
+}
+
+impl SharedAcl {
+    pub fn load(file: PathBuf) -> ConfigResult<Self> {
+        let roles = AclFile::load(&file)?.roles;
+        Ok(Self {
+            file,
+            roles: Arc::new(RwLock::new(roles)),
+        })
+    }
+
+    pub fn authorize(
+        &self,
+        roles: &[String],
+        privilege: &Privilege,
+        pattern: &str,
+    ) -> AuthorizationResult<()> {
+        let acl = self.roles.read().unwrap_or_else(PoisonError::into_inner);
+        let rules = iter::once(DEFAULT_ROLE)
+            .chain(roles.iter().map(String::as_str))
+            .filter_map(|role| acl.get(role))
+            .flatten();
+
+        // wildcard rules do not allow modifying the ACL itself
+        let path = KeySegment::parse(pattern);
+        let protected = privilege != &Privilege::Read
+            && patterns_overlap(
+                &path,
+                &KeySegment::parse(topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_ACL, "#")),
+            );
+
+        let mut allowed = false;
+        for rule in rules {
+            if rule.deny.contains(privilege)
+                && patterns_overlap(&KeySegment::parse(&rule.pattern), &path)
+            {
+                return Err(insufficient_privileges(privilege, pattern));
+            }
+            allowed |= rule.allow.contains(privilege)
+                && pattern_matches(&rule.pattern, pattern)
+                && (!protected || is_acl_pattern(&rule.pattern));
+        }
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(insufficient_privileges(privilege, pattern))
+        }
+    }
+
+    fn apply(&self, event: PStateEvent) {
+        let mut acl = self.roles.write().unwrap_or_else(PoisonError::into_inner);
+        match event {
+            PStateEvent::KeyValuePairs(kvps) => {
+                for kvp in kvps {
+                    let role = role_name(&kvp.key);
+                    match serde_json::from_value(kvp.value) {
+                        Ok(rules) => {
+                            acl.insert(role.to_owned(), rules);
+                        }
+                        Err(e) => log::warn!("Ignoring invalid rules of ACL role '{role}': {e}"),
+                    }
+                }
+            }
+            PStateEvent::Deleted(kvps) => {
+                for kvp in kvps {
+                    acl.remove(role_name(&kvp.key));
+                }
+            }
+        }
+    }
+}
+
+impl PartialEq for SharedAcl {
+    fn eq(&self, other: &Self) -> bool {
+        self.file == other.file && Arc::ptr_eq(&self.roles, &other.roles)
+    }
+}
+
+/// Publishes the ACL roles to `$SYS/acl/roles`, keeps the shared ACL in sync with live edits
+/// made there and reloads the ACL file whenever it changes.
+pub(crate) async fn sync(
+    worterbuch: CloneableWbApi,
+    acl: SharedAcl,
+    subsys: SubsystemHandle,
+) -> Result<()> {
+    let mut last_modified = modified(&acl.file);
+    let roles = acl
+        .roles
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    publish(&worterbuch, &roles).await?;
+
+    let (mut events, _) = worterbuch
+        .psubscribe(Uuid::new_v4(), 0, roles_pattern(), true, false)
+        .await?;
+    let mut interval = interval(RELOAD_INTERVAL);
+
+    loop {
+        select! {
+            Some(event) = events.recv() => acl.apply(event),
+            _ = interval.tick() => {
+                let modified = modified(&acl.file);
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+                match AclFile::load(&acl.file) {
+                    Ok(file) => {
+                        log::info!("ACL file changed, reloading …");
+                        publish(&worterbuch, &file.roles).await?;
+                    }
+                    Err(e) => log::error!("Could not reload ACL: {e}"),
+                }
+            },
+            _ = subsys.on_shutdown_requested() => break,
+        }
+    }
+
+    Ok(())
+}
+
+async fn publish(worterbuch: &CloneableWbApi, roles: &HashMap<String, AclRole>) -> Result<()> {
+    for kvp in worterbuch.pget(roles_pattern()).await? {
+        if !roles.contains_key(role_name(&kvp.key)) {
+            worterbuch
+                .delete(kvp.key, INTERNAL_CLIENT_ID.to_owned())
+                .await?;
+        }
+    }
+    for (role, rules) in roles {
+        worterbuch
+            .set(
+                topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_ACL, ROLES, role),
+                serde_json::to_value(rules)?,
+                INTERNAL_CLIENT_ID.to_owned(),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+fn modified(file: &Path) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|m| m.modified()).ok()
+}
+
+fn roles_pattern() -> RequestPattern {
+    topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_ACL, ROLES, "?")
+}
+
+fn role_name(key: &str) -> &str {
+    key.rsplit('/').next().unwrap_or(key)
+}
+
+fn is_acl_pattern(pattern: &str) -> bool {
+    let mut segments = pattern.split('/');
+    segments.next() == Some(SYSTEM_TOPIC_ROOT) && segments.next() == Some(SYSTEM_TOPIC_ACL)
+}
+
+fn insufficient_privileges(privilege: &Privilege, pattern: &str) -> AuthorizationError {
+    AuthorizationError::InsufficientPrivileges(privilege.to_owned(), pattern.to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn acl() -> SharedAcl {
+        let file: AclFile = serde_yaml::from_str(
+            r##"
+roles:
+  default:
+    - pattern: public/#
+      allow: [read]
+  operator:
+    - pattern: plant/#
+      allow: [read, write]
+    - pattern: plant/safety/#
+      deny: [write]
+  admin:
+    - pattern: "#"
+      allow: [read, write, delete]
+    - pattern: $SYS/acl/#
+      allow: [write, delete]
+"##,
+        )
+        .expect("valid ACL");
+        SharedAcl {
+            file: PathBuf::new(),
+            roles: Arc::new(RwLock::new(file.roles)),
+        }
+    }
+
+    #[test]
+    fn rules_are_evaluated_per_role() {
+        let acl = acl();
+        let operator = ["operator".to_owned()];
+
+        assert!(acl.authorize(&[], &Privilege::Read, "public/news").is_ok());
+        assert!(acl.authorize(&[], &Privilege::Read, "plant/temp").is_err());
+        assert!(acl
+            .authorize(&operator, &Privilege::Read, "public/news")
+            .is_ok());
+        assert!(acl
+            .authorize(&operator, &Privilege::Write, "plant/temp")
+            .is_ok());
+        assert!(acl
+            .authorize(&operator, &Privilege::Delete, "plant/temp")
+            .is_err());
+    }
+
+    #[test]
+    fn denials_apply_to_overlapping_patterns() {
+        let acl = acl();
+        let operator = ["operator".to_owned()];
+
+        assert!(acl
+            .authorize(&operator, &Privilege::Write, "plant/safety/valve")
+            .is_err());
+        assert!(acl
+            .authorize(&operator, &Privilege::Write, "plant/#")
+            .is_err());
+        assert!(acl
+            .authorize(&operator, &Privilege::Write, "plant/?/valve")
+            .is_err());
+        assert!(acl
+            .authorize(&operator, &Privilege::Write, "plant/other/valve")
+            .is_ok());
+    }
+
+    #[test]
+    fn acl_can_only_be_modified_with_explicit_rules() {
+        let acl = acl();
+        let admin = ["admin".to_owned()];
+        let operator = ["operator".to_owned(), "superuser".to_owned()];
+        acl.apply(PStateEvent::KeyValuePairs(vec![(
+            "$SYS/acl/roles/superuser".to_owned(),
+            serde_json::json!([{ "pattern": "#", "allow": ["write"] }]),
+        )
+            .into()]));
+
+        assert!(acl
+            .authorize(&operator, &Privilege::Write, "anything/else")
+            .is_ok());
+        assert!(acl
+            .authorize(&operator, &Privilege::Write, "$SYS/acl/roles/operator")
+            .is_err());
+        assert!(acl
+            .authorize(&admin, &Privilege::Write, "$SYS/acl/roles/operator")
+            .is_ok());
+    }
+}
@@ -20,7 +20,8 @@
 use crate::Config;
 use jsonwebtoken::{decode, DecodingKey, Validation};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, sync::Arc};
+use uuid::Uuid;
 use worterbuch_common::{
     error::{AuthorizationError, AuthorizationResult},
     KeySegment, Privilege, RequestPattern,
@@ -33,6 +34,9 @@ pub struct JwtClaims {
     pub name: String,
     pub exp: u64,
     pub worterbuch_privileges: HashMap<Privilege, Vec<RequestPattern>>,
+    /// ACL roles of the client, see [`SharedAcl`](crate::acl::SharedAcl).
+    #[serde(default)]
+    pub roles: Vec<String>,
 }
 
 impl JwtClaims {
@@ -58,6 +62,60 @@ impl JwtClaims {
     }
 }
 
+/// Everything an [`Authorizer`] gets to know about an operation it is asked to authorize.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthorizationContext<'a> {
+    /// ID of the client performing the operation, `None` for REST requests
+    pub client_id: Option<Uuid>,
+    /// claims of the JWT the client authorized with, if any
+    pub claims: Option<&'a JwtClaims>,
+    pub privilege: &'a Privilege,
+    pub pattern: &'a str,
+}
+
+/// Authorization callback for applications that embed the server.
+///
+/// If an authorizer is configured, it is consulted for every operation a client performs. If an
+/// auth token is configured as well, the authorizer is consulted in addition to the JWT check,
+/// otherwise it replaces it.
+pub trait Authorizer: Send + Sync {
+    fn authorize(&self, ctx: AuthorizationContext) -> AuthorizationResult<()>;
+}
+
+impl<F> Authorizer for F
+where
+    F: Fn(AuthorizationContext) -> AuthorizationResult<()> + Send + Sync,
+{
+    fn authorize(&self, ctx: AuthorizationContext) -> AuthorizationResult<()> {
+        self(ctx)
+    }
+}
+
+#[derive(Clone)]
+pub struct SharedAuthorizer(Arc<dyn Authorizer>);
+
+impl SharedAuthorizer {
+    pub fn new(authorizer: impl Authorizer + 'static) -> Self {
+        Self(Arc::new(authorizer))
+    }
+
+    pub fn authorize(&self, ctx: AuthorizationContext) -> AuthorizationResult<()> {
+        self.0.authorize(ctx)
+    }
+}
+
+impl fmt::Debug for SharedAuthorizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedAuthorizer")
+    }
+}
+
+impl PartialEq for SharedAuthorizer {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
 pub fn get_claims(jwt: Option<&str>, config: &Config) -> AuthorizationResult<JwtClaims> {
     if let Some(secret) = &config.auth_token {
         if let Some(token) = jwt {
@@ -105,6 +163,37 @@ pub fn pattern_matches(pattern: &str, key: &str) -> bool {
 mod test {
     use super::*;
 
+    #[test]
+    fn authorizer_gets_called_with_context() {
+        let client_id = Uuid::new_v4();
+        let authorizer = SharedAuthorizer::new(move |ctx: AuthorizationContext| {
+            if ctx.client_id == Some(client_id) && ctx.privilege == &Privilege::Read {
+                Ok(())
+            } else {
+                Err(AuthorizationError::InsufficientPrivileges(
+                    ctx.privilege.to_owned(),
+                    ctx.pattern.to_owned(),
+                ))
+            }
+        });
+
+        let ctx = |client_id, privilege| AuthorizationContext {
+            client_id,
+            claims: None,
+            privilege,
+            pattern: "hello/world",
+        };
+
+        assert!(authorizer
+            .authorize(ctx(Some(client_id), &Privilege::Read))
+            .is_ok());
+        assert!(authorizer
+            .authorize(ctx(Some(client_id), &Privilege::Write))
+            .is_err());
+        assert!(authorizer.authorize(ctx(None, &Privilege::Read)).is_err());
+        assert_eq!(authorizer, authorizer.clone());
+    }
+
     #[test]
     fn test_matches() {
         assert!(pattern_matches("hello", "hello"));
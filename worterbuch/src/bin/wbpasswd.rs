@@ -0,0 +1,32 @@
+use std::{env, io};
+use worterbuch::users::{hash_password, User};
+use worterbuch_common::{topic, AUTH_TOPIC_ROOT, AUTH_TOPIC_USERS};
+
+/// Reads a password from stdin and prints the user database entry for the given user and roles,
+/// ready to be stored with e.g. `wbset`.
+fn main() -> anyhow::Result<()> {
+    let mut args = env::args().skip(1);
+
+    let Some(username) = args.next() else {
+        eprintln!("Usage: wbpasswd <username> [role …] < password");
+        std::process::exit(1);
+    };
+    let roles = args.collect();
+
+    let mut password = String::new();
+    io::stdin().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']);
+
+    let user = User {
+        password_hash: hash_password(password)?,
+        roles,
+    };
+
+    println!(
+        "{}={}",
+        topic!(AUTH_TOPIC_ROOT, AUTH_TOPIC_USERS, username),
+        serde_json::to_string(&user)?
+    );
+
+    Ok(())
+}
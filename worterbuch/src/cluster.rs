@@ -0,0 +1,450 @@
+/*
+ *  Worterbuch cluster module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{auth::JwtClaims, standby::client_config, subscribers::SubscriptionId};
+use futures::future::try_join_all;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    select, spawn,
+    sync::{mpsc, oneshot},
+};
+use uuid::Uuid;
+use worterbuch_client::{connect, sharding::Ring, Worterbuch};
+use worterbuch_common::{
+    error::{ConfigError, ConnectionError, WorterbuchError, WorterbuchResult},
+    ClientInfo, Key, KeySegment, KeyValuePairs, PStateEvent, RequestPattern, Value,
+    SYSTEM_TOPIC_ROOT,
+};
+
+/// Tag in the [`ClientInfo`] a node sends when connecting to another node of its cluster. It is
+/// informational only, peers are recognized by [`CLUSTER_ROLE`].
+pub const CLUSTER_NODE_TAG: &str = "worterbuch-cluster-node";
+
+/// JWT role the `auth_token` of the nodes needs to carry, requests of clients with this role are
+/// not forwarded again.
+pub const CLUSTER_ROLE: &str = "cluster-node";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub id: String,
+    /// Address of the node, e.g. `ws://node-a:8080/ws` or `tcp://node-a:8081`.
+    pub address: String,
+}
+
+/// Shares the key space between several servers. Each key is owned by exactly one node, chosen by
+/// consistent hashing of its first segment, so all nodes need to be configured with the same list
+/// of nodes. Gets, sets, publishes and deletes of keys owned by another node are forwarded to it
+/// over the regular client protocol, pgets, pdeletes and subscriptions whose pattern starts with
+/// a wildcard are fanned out to all nodes. Everything else, including `$SYS` keys, is local to
+/// each node. Nodes authenticate to each other with `auth_token`, a JWT carrying
+/// [`CLUSTER_ROLE`].
+#[derive(Clone)]
+pub struct Cluster {
+    pub node_id: String,
+    pub nodes: Vec<Node>,
+    pub auth_token: Option<String>,
+    ring: Arc<Ring>,
+    state: Arc<Mutex<State>>,
+    clients: Arc<tokio::sync::Mutex<HashMap<String, Worterbuch>>>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Subscriptions on other nodes, they are stopped by dropping the senders.
+    subscriptions: HashMap<SubscriptionId, Vec<oneshot::Sender<()>>>,
+}
+
+impl Cluster {
+    pub fn new(
+        node_id: String,
+        nodes: Vec<Node>,
+        auth_token: Option<String>,
+    ) -> Result<Self, ConfigError> {
+        if !nodes.iter().any(|n| n.id == node_id) {
+            return Err(ConfigError::InvalidClusterConfig(format!(
+                "node '{node_id}' is not part of the cluster"
+            )));
+        }
+
+        let ids: Vec<String> = nodes.iter().map(|n| n.id.clone()).collect();
+        let ring = Ring::new(&ids);
+
+        Ok(Self {
+            node_id,
+            nodes,
+            auth_token,
+            ring: Arc::new(ring),
+            state: Arc::default(),
+            clients: Arc::default(),
+        })
+    }
+
+    /// The node a key belongs to, if that is not this one.
+    pub fn owner(&self, key: &str) -> Option<&Node> {
+        let first = key.split('/').next().unwrap_or_default();
+        if first == SYSTEM_TOPIC_ROOT {
+            return None;
+        }
+        let node = &self.nodes[self.ring.shard(first)];
+        (node.id != self.node_id).then_some(node)
+    }
+
+    /// The other nodes that may hold keys matching the pattern.
+    pub fn remotes(&self, pattern: &str) -> Vec<&Node> {
+        let first = pattern.split('/').next().unwrap_or_default();
+        match KeySegment::from(first) {
+            KeySegment::Regular(_) => self.owner(pattern).into_iter().collect(),
+            KeySegment::Wildcard | KeySegment::MultiWildcard => {
+                self.nodes.iter().filter(|n| n.id != self.node_id).collect()
+            }
+        }
+    }
+
+    /// Whether a client is another node of the cluster, judged by the role in its verified JWT.
+    pub(crate) fn is_peer(claims: Option<&JwtClaims>) -> bool {
+        claims.is_some_and(|c| c.roles.iter().any(|r| r == CLUSTER_ROLE))
+    }
+
+    pub(crate) fn disconnected(&self, client_id: Uuid) {
+        self.state()
+            .subscriptions
+            .retain(|id, _| id.client_id != client_id);
+    }
+
+    pub(crate) async fn get(&self, node: &Node, key: Key) -> WorterbuchResult<(Key, Value)> {
+        let client = self.client(node).await?;
+        match client.get_generic(key.clone()).await {
+            Ok((Some(value), _)) => Ok((key, value)),
+            Ok((None, _)) => Err(WorterbuchError::NoSuchValue(key)),
+            Err(e) => Err(self.remote_error(node, e).await),
+        }
+    }
+
+    pub(crate) async fn set(&self, node: &Node, key: Key, value: Value) -> WorterbuchResult<()> {
+        let client = self.client(node).await?;
+        match client.set_generic(key, value).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(self.remote_error(node, e).await),
+        }
+    }
+
+    pub(crate) async fn get_and_set(
+        &self,
+        node: &Node,
+        key: Key,
+        value: Value,
+    ) -> WorterbuchResult<Option<Value>> {
+        let client = self.client(node).await?;
+        match client.get_and_set_generic(key, value).await {
+            Ok(previous) => Ok(previous),
+            Err(e) => Err(self.remote_error(node, e).await),
+        }
+    }
+
+    pub(crate) async fn set_many(
+        &self,
+        node: &Node,
+        key_value_pairs: KeyValuePairs,
+    ) -> WorterbuchResult<()> {
+        let client = self.client(node).await?;
+        match client.set_many_generic(key_value_pairs).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(self.remote_error(node, e).await),
+        }
+    }
+
+    pub(crate) async fn publish_retained(
+        &self,
+        node: &Node,
+        key: Key,
+        value: Value,
+    ) -> WorterbuchResult<()> {
+        let client = self.client(node).await?;
+        match client.publish_retained_generic(key, value).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(self.remote_error(node, e).await),
+        }
+    }
+
+    pub(crate) async fn publish(
+        &self,
+        node: &Node,
+        key: Key,
+        value: Value,
+    ) -> WorterbuchResult<()> {
+        let client = self.client(node).await?;
+        match client.publish_generic(key, value).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(self.remote_error(node, e).await),
+        }
+    }
+
+    pub(crate) async fn delete(&self, node: &Node, key: Key) -> WorterbuchResult<(Key, Value)> {
+        let client = self.client(node).await?;
+        match client.delete_generic(key.clone()).await {
+            Ok((Some(value), _)) => Ok((key, value)),
+            Ok((None, _)) => Err(WorterbuchError::NoSuchValue(key)),
+            Err(e) => Err(self.remote_error(node, e).await),
+        }
+    }
+
+    /// Matches of the pattern on other nodes. Keys are distributed by their exact first segment,
+    /// so a case-insensitive pget has to ask all of them.
+    pub(crate) async fn pget(
+        &self,
+        pattern: &RequestPattern,
+        case_insensitive: bool,
+    ) -> WorterbuchResult<KeyValuePairs> {
+        let nodes: Vec<&Node> = if case_insensitive {
+            self.nodes.iter().filter(|n| n.id != self.node_id).collect()
+        } else {
+            self.remotes(pattern)
+        };
+        let requests = nodes.into_iter().map(|node| async move {
+            let client = self.client(node).await?;
+            let response = if case_insensitive {
+                client.pget_ignoring_case_generic(pattern.to_owned()).await
+            } else {
+                client.pget_generic(pattern.to_owned()).await
+            };
+            match response {
+                Ok((kvps, _)) => Ok(kvps),
+                Err(e) => Err(self.remote_error(node, e).await),
+            }
+        });
+        Ok(try_join_all(requests).await?.concat())
+    }
+
+    /// Keys matching the pattern on other nodes.
+    pub(crate) async fn pkeys(&self, pattern: &RequestPattern) -> WorterbuchResult<Vec<Key>> {
+        let requests = self.remotes(pattern).into_iter().map(|node| async move {
+            let client = self.client(node).await?;
+            match client.pkeys(pattern.to_owned()).await {
+                Ok(keys) => Ok(keys),
+                Err(e) => Err(self.remote_error(node, e).await),
+            }
+        });
+        Ok(try_join_all(requests).await?.concat())
+    }
+
+    /// Deletes matches of the pattern on other nodes.
+    pub(crate) async fn pdelete(
+        &self,
+        pattern: &RequestPattern,
+    ) -> WorterbuchResult<KeyValuePairs> {
+        let requests = self.remotes(pattern).into_iter().map(|node| async move {
+            let client = self.client(node).await?;
+            match client.pdelete_generic(pattern.to_owned()).await {
+                Ok((kvps, _)) => Ok(kvps),
+                Err(e) => Err(self.remote_error(node, e).await),
+            }
+        });
+        Ok(try_join_all(requests).await?.concat())
+    }
+
+    /// Subscribes to the pattern on all other nodes that may hold matching keys and forwards their
+    /// events until the subscription is cancelled or `tx` is closed.
+    pub(crate) async fn psubscribe(
+        &self,
+        id: SubscriptionId,
+        pattern: &RequestPattern,
+        unique: bool,
+        live_only: bool,
+        tx: &mpsc::Sender<PStateEvent>,
+    ) -> WorterbuchResult<()> {
+        for node in self.remotes(pattern) {
+            let client = self.client(node).await?;
+            let (mut events, transaction_id) = match client
+                .psubscribe_generic(pattern.to_owned(), unique, live_only, None)
+                .await
+            {
+                Ok(it) => it,
+                Err(e) => return Err(self.remote_error(node, e).await),
+            };
+            let (stop_tx, mut stop_rx) = oneshot::channel::<()>();
+            self.state()
+                .subscriptions
+                .entry(id.clone())
+                .or_default()
+                .push(stop_tx);
+            let tx = tx.clone();
+            spawn(async move {
+                loop {
+                    select! {
+                        event = events.recv() => match event {
+                            Some(event) => if tx.send(event).await.is_err() {
+                                break;
+                            },
+                            None => break,
+                        },
+                        _ = &mut stop_rx => break,
+                        _ = tx.closed() => break,
+                    }
+                }
+                client.unsubscribe(transaction_id).await.ok();
+            });
+        }
+        Ok(())
+    }
+
+    /// Stops the subscription on all other nodes, returns `false` if there was none.
+    pub(crate) fn unsubscribe(&self, id: &SubscriptionId) -> bool {
+        self.state().subscriptions.remove(id).is_some()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("cluster state mutex poisoned")
+    }
+
+    async fn client(&self, node: &Node) -> WorterbuchResult<Worterbuch> {
+        if let Some(client) = self.clients.lock().await.get(&node.id) {
+            return Ok(client.clone());
+        }
+
+        log::info!(
+            "Connecting to cluster node {} at {} …",
+            node.id,
+            node.address
+        );
+        let error = |e: &dyn fmt::Display| {
+            WorterbuchError::Other(
+                e.to_string().into(),
+                format!("cluster node '{}' is unreachable", node.id),
+            )
+        };
+        let mut config =
+            client_config(&node.address, self.auth_token.clone()).map_err(|e| error(&e))?;
+        config.client_info = Some(ClientInfo {
+            name: "worterbuch".to_owned(),
+            version: None,
+            tags: HashMap::from([(CLUSTER_NODE_TAG.to_owned(), self.node_id.clone())]),
+        });
+        // not holding the lock while connecting, so one unreachable node does not stall requests to
+        // all others; if two requests connect at the same time, the first connection is kept
+        let client = connect(config, async {}).await.map_err(|e| error(&e))?;
+        let mut clients = self.clients.lock().await;
+        Ok(clients.entry(node.id.clone()).or_insert(client).clone())
+    }
+
+    /// Drops the connection to a node after a failed request, so the next one reconnects.
+    async fn remote_error(&self, node: &Node, e: ConnectionError) -> WorterbuchError {
+        match e {
+            ConnectionError::WorterbuchError(e) => e,
+            e => {
+                self.clients.lock().await.remove(&node.id);
+                WorterbuchError::Other(
+                    Box::new(e),
+                    format!("request to cluster node '{}' failed", node.id),
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Cluster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cluster")
+            .field("node_id", &self.node_id)
+            .field("nodes", &self.nodes)
+            .finish()
+    }
+}
+
+impl PartialEq for Cluster {
+    fn eq(&self, other: &Self) -> bool {
+        self.node_id == other.node_id
+            && self.nodes == other.nodes
+            && self.auth_token == other.auth_token
+            && Arc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn nodes() -> Vec<Node> {
+        ["a", "b", "c"]
+            .into_iter()
+            .map(|id| Node {
+                id: id.to_owned(),
+                address: format!("tcp://{id}:8081"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_key_has_exactly_one_owner() {
+        let clusters: Vec<Cluster> = ["a", "b", "c"]
+            .into_iter()
+            .map(|id| Cluster::new(id.to_owned(), nodes(), None).expect("valid cluster"))
+            .collect();
+
+        let mut owned = HashMap::new();
+        for i in 0..100 {
+            let key = format!("segment{i}/some/key");
+            let local: Vec<_> = clusters
+                .iter()
+                .filter(|c| c.owner(&key).is_none())
+                .map(|c| c.node_id.clone())
+                .collect();
+            assert_eq!(local.len(), 1, "{key} is owned by {local:?}");
+            for cluster in &clusters {
+                if let Some(owner) = cluster.owner(&key) {
+                    assert_eq!(owner.id, local[0]);
+                }
+            }
+            *owned.entry(local[0].clone()).or_insert(0) += 1;
+        }
+
+        assert_eq!(owned.len(), 3);
+    }
+
+    #[test]
+    fn wildcards_are_fanned_out_and_sys_keys_stay_local() {
+        let cluster = Cluster::new("a".to_owned(), nodes(), None).expect("valid cluster");
+
+        assert_eq!(cluster.remotes("#").len(), 2);
+        assert_eq!(cluster.remotes("?/b/c").len(), 2);
+        assert!(cluster.remotes("$SYS/clients/#").is_empty());
+        assert!(cluster.owner("$SYS/uptime").is_none());
+        assert!(Cluster::new("d".to_owned(), nodes(), None).is_err());
+    }
+
+    #[test]
+    fn peers_are_recognized_by_their_jwt_role() {
+        let claims = |roles: Vec<String>| JwtClaims {
+            sub: "node".to_owned(),
+            name: "node".to_owned(),
+            exp: 0,
+            worterbuch_privileges: Default::default(),
+            roles,
+        };
+
+        assert!(Cluster::is_peer(Some(&claims(vec![
+            CLUSTER_ROLE.to_owned()
+        ]))));
+        assert!(!Cluster::is_peer(Some(&claims(vec!["admin".to_owned()]))));
+        assert!(!Cluster::is_peer(None));
+    }
+}
@@ -0,0 +1,46 @@
+/*
+ *  Worterbuch store compaction module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::server::common::CloneableWbApi;
+use anyhow::Result;
+use std::time::Duration;
+use tokio::{select, time::interval};
+use tokio_graceful_shutdown::SubsystemHandle;
+
+pub(crate) async fn compact_periodically(
+    worterbuch: CloneableWbApi,
+    period: Duration,
+    subsys: SubsystemHandle,
+) -> Result<()> {
+    let mut interval = interval(period);
+
+    loop {
+        select! {
+            _ = interval.tick() => {
+                let reclaimed = worterbuch.compact().await?;
+                if reclaimed > 0 {
+                    log::info!("Reclaimed {reclaimed} empty nodes from the store");
+                }
+            },
+            _ = subsys.on_shutdown_requested() => break,
+        }
+    }
+
+    Ok(())
+}
@@ -17,11 +17,36 @@
  *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::license::{load_license, License};
-use std::{env, net::IpAddr, time::Duration};
+#[cfg(feature = "cluster")]
+use crate::cluster::{Cluster, Node, CLUSTER_ROLE};
+#[cfg(feature = "s3")]
+use crate::s3::S3Persistence;
+#[cfg(feature = "standby")]
+use crate::standby::Standby;
+use crate::{
+    acl::SharedAcl,
+    auth::{pattern_matches, SharedAuthorizer},
+    deprecations::Deprecations,
+    encryption::Encryption,
+    history::HistoryRules,
+    hooks::ChangeHooks,
+    key_rules::KeyRules,
+    license::{load_license, License},
+    logging::LogSink,
+    metrics::{MetricsFormat, MetricsPush},
+    mode::{Mode, ServerMode},
+    replication::Replication,
+    retention::RetentionRules,
+    templates::ValueTemplates,
+    usage::KeyUsageSampling,
+    wal::PersistenceMode,
+};
+use std::{env, fs, net::IpAddr, time::Duration};
 use worterbuch_common::{
     error::{ConfigError, ConfigIntContext, ConfigResult},
-    AuthToken, Path,
+    limits::DecoderLimits,
+    redact::REDACTED,
+    AuthToken, Path, RequestPattern,
 };
 
 #[derive(Debug, Clone, PartialEq)]
@@ -31,30 +56,193 @@ pub struct Endpoint {
     pub port: u16,
 }
 
+impl Endpoint {
+    /// Only reachable from the local machine unless configured otherwise.
+    fn admin_default() -> Self {
+        Endpoint {
+            tls: false,
+            bind_addr: [127, 0, 0, 1].into(),
+            port: 8082,
+        }
+    }
+
+    #[cfg(feature = "grpc")]
+    fn grpc_default() -> Self {
+        Endpoint {
+            tls: false,
+            bind_addr: [127, 0, 0, 1].into(),
+            port: 50051,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct WsEndpoint {
     pub endpoint: Endpoint,
     pub public_addr: String,
+    /// Certificates to serve if TLS is enabled. If this is empty, TLS is expected to be
+    /// terminated by a reverse proxy.
+    pub certificates: Vec<TlsCertificate>,
+    /// Path of the websocket route. Each supported protocol version is additionally served at
+    /// `<path>/v<version>`.
+    pub path: String,
+    /// Subprotocols accepted during the websocket handshake.
+    pub subprotocols: Vec<String>,
+}
+
+/// A PEM encoded certificate chain and private key. Certificates with a server name are selected
+/// via SNI, the one without a server name is served to all other clients.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsCertificate {
+    pub server_name: Option<String>,
+    pub cert_file: Path,
+    pub key_file: Path,
+}
+
+impl TlsCertificate {
+    /// Parses a comma separated list of `[<server name>=]<cert file>:<key file>` entries.
+    pub fn parse_list(list: &str) -> ConfigResult<Vec<TlsCertificate>> {
+        let mut certificates = Vec::new();
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (server_name, files) = match entry.split_once('=') {
+                Some((name, files)) => (Some(name.trim().to_owned()), files),
+                None => (None, entry),
+            };
+            let Some((cert_file, key_file)) = files.split_once(':') else {
+                return Err(ConfigError::InvalidTlsCertificate(entry.to_owned()));
+            };
+            if cert_file.is_empty() || key_file.is_empty() {
+                return Err(ConfigError::InvalidTlsCertificate(entry.to_owned()));
+            }
+            certificates.push(TlsCertificate {
+                server_name,
+                cert_file: cert_file.trim().to_owned(),
+                key_file: key_file.trim().to_owned(),
+            });
+        }
+        if certificates
+            .iter()
+            .filter(|c| c.server_name.is_none())
+            .count()
+            > 1
+        {
+            return Err(ConfigError::InvalidTlsCertificate(
+                "only one certificate may be configured without a server name".to_owned(),
+            ));
+        }
+        Ok(certificates)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Config {
     pub ws_endpoint: Option<WsEndpoint>,
     pub tcp_endpoint: Option<Endpoint>,
+    /// Serves the REST API and admin routes on their own address instead of the websocket endpoint.
+    pub admin_endpoint: Option<Endpoint>,
+    /// Serves the gRPC API defined in `worterbuch-common/proto/worterbuch.proto`.
+    #[cfg(feature = "grpc")]
+    pub grpc_endpoint: Option<Endpoint>,
+    /// Serves metrics in Prometheus text format at `/metrics`, next to the REST API.
+    pub prometheus_endpoint: bool,
+    pub tcp_oneshot_port: Option<u16>,
     pub use_persistence: bool,
     pub persistence_interval: Duration,
+    pub persistence_mode: PersistenceMode,
+    /// Size in bytes beyond which the write-ahead log is compacted into a snapshot in
+    /// [`PersistenceMode::Wal`], checked every persistence interval.
+    pub wal_max_size: u64,
     pub data_dir: Path,
+    /// Directory of JSON/YAML files whose keys are set on startup, unless they already exist.
+    pub seed_dir: Option<Path>,
     pub single_threaded: bool,
     pub web_root_path: Option<String>,
     pub keepalive_timeout: Duration,
     pub send_timeout: Duration,
     pub channel_buffer_size: usize,
+    /// Reject client requests with a `Busy` error instead of waiting while the request queue is full.
+    pub reject_when_busy: bool,
+    /// Client requests still queued after this long are skipped, should match the clients' request timeout.
+    pub request_timeout: Option<Duration>,
+    /// How long server handlers wait for the store to take and answer a request before failing it
+    /// with a timeout error. They wait indefinitely if unset.
+    pub api_timeout: Option<Duration>,
+    pub decoder_limits: DecoderLimits,
     pub extended_monitoring: bool,
     pub auth_token: Option<AuthToken>,
+    pub authorizer: Option<SharedAuthorizer>,
+    /// Closures called whenever the value of a matching key changes.
+    pub change_hooks: ChangeHooks,
+    /// Role based access control list, evaluated in addition to JWT privileges.
+    pub acl: Option<SharedAcl>,
+    pub user_db: bool,
+    pub admin_password: Option<String>,
     pub license: License,
+    pub value_templates: ValueTemplates,
+    pub materialize_value_templates: bool,
+    pub key_rules: KeyRules,
+    pub deprecations: Deprecations,
+    pub trash_retention: Option<Duration>,
+    /// How long before their TTL passes expiring values are announced at `$SYS/expiring/<key>`.
+    pub expiry_warning: Option<Duration>,
+    pub compaction_interval: Option<Duration>,
+    pub normalize_values: bool,
+    pub metrics_push: Option<MetricsPush>,
+    pub max_subscriptions_per_client: Option<usize>,
+    pub log_sink: Option<LogSink>,
+    pub key_usage: Option<KeyUsageSampling>,
+    pub retention: RetentionRules,
+    /// Keys whose recent values are kept for `GetHistory` requests.
+    pub history: HistoryRules,
+    pub encryption: Option<Encryption>,
+    pub redacted_patterns: Vec<RequestPattern>,
+    /// Key that support bundles are encrypted with. Support bundles are disabled without one.
+    pub support_bundle_key: Option<Encryption>,
+    #[cfg(feature = "s3")]
+    pub s3: Option<S3Persistence>,
+    pub replication: Option<Replication>,
+    /// Read-only maintenance mode, can be switched at runtime via `$SYS/mode`.
+    pub mode: ServerMode,
+    /// Primary server this server follows as a warm standby.
+    #[cfg(feature = "standby")]
+    pub standby: Option<Standby>,
+    /// Other servers this one shares the key space with.
+    #[cfg(feature = "cluster")]
+    pub cluster: Option<Cluster>,
 }
 
 impl Config {
+    /// Whether clients need to authorize with a JWT or authenticate against the user database.
+    pub fn auth_required(&self) -> bool {
+        self.auth_token.is_some() || self.user_db
+    }
+
+    /// Whether values of the key must never show up in logs. Encrypted keys are always sensitive.
+    pub fn is_sensitive(&self, key: &str) -> bool {
+        self.redacted_patterns
+            .iter()
+            .any(|p| pattern_matches(p, key))
+            || self
+                .encryption
+                .as_ref()
+                .is_some_and(|e| e.is_sensitive(key))
+    }
+
+    /// A copy of this config without any credentials, safe to be handed out for diagnostics.
+    pub fn redacted(&self) -> Config {
+        Config {
+            auth_token: self.auth_token.as_ref().map(|_| REDACTED.to_owned()),
+            admin_password: self.admin_password.as_ref().map(|_| REDACTED.to_owned()),
+            replication: self.replication.as_ref().map(Replication::redacted),
+            #[cfg(feature = "standby")]
+            standby: self.standby.as_ref().map(|standby| Standby {
+                auth_token: standby.auth_token.as_ref().map(|_| REDACTED.to_owned()),
+                ..standby.clone()
+            }),
+            ..self.clone()
+        }
+    }
+
     pub fn load_env(&mut self) -> ConfigResult<()> {
         self.load_env_with_prefix("WORTERBUCH")
     }
@@ -84,6 +272,29 @@ impl Config {
             }
         }
 
+        if let Ok(val) = env::var(prefix.to_owned() + "_WS_TLS_CERTIFICATES") {
+            if let Some(ep) = &mut self.ws_endpoint {
+                ep.certificates = TlsCertificate::parse_list(&val)?;
+            }
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_WS_PATH") {
+            if let Some(ep) = &mut self.ws_endpoint {
+                ep.path = format!("/{}", val.trim_matches('/'));
+            }
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_WS_SUBPROTOCOLS") {
+            if let Some(ep) = &mut self.ws_endpoint {
+                ep.subprotocols = val
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|p| !p.is_empty())
+                    .map(ToOwned::to_owned)
+                    .collect();
+            }
+        }
+
         if let Ok(val) = env::var(prefix.to_owned() + "_TCP_SERVER_PORT") {
             if let Some(ep) = &mut self.tcp_endpoint {
                 ep.port = val.parse().to_port()?;
@@ -96,6 +307,46 @@ impl Config {
             }
         }
 
+        if let Ok(val) = env::var(prefix.to_owned() + "_ADMIN_PORT") {
+            self.admin_endpoint
+                .get_or_insert_with(Endpoint::admin_default)
+                .port = val.parse().to_port()?;
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_ADMIN_BIND_ADDRESS") {
+            if let Some(ep) = &mut self.admin_endpoint {
+                ep.bind_addr = val.parse()?;
+            }
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_ADMIN_TLS") {
+            if let Some(ep) = &mut self.admin_endpoint {
+                ep.tls = val.to_lowercase() == "true" || val == "1";
+            }
+        }
+
+        #[cfg(feature = "grpc")]
+        if let Ok(val) = env::var(prefix.to_owned() + "_GRPC_PORT") {
+            self.grpc_endpoint
+                .get_or_insert_with(Endpoint::grpc_default)
+                .port = val.parse().to_port()?;
+        }
+
+        #[cfg(feature = "grpc")]
+        if let Ok(val) = env::var(prefix.to_owned() + "_GRPC_BIND_ADDRESS") {
+            if let Some(ep) = &mut self.grpc_endpoint {
+                ep.bind_addr = val.parse()?;
+            }
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_PROMETHEUS_ENDPOINT") {
+            self.prometheus_endpoint = val.to_lowercase() == "true" || val == "1";
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_TCP_ONESHOT_PORT") {
+            self.tcp_oneshot_port = Some(val.parse().to_port()?);
+        }
+
         if let Ok(val) = env::var(prefix.to_owned() + "_USE_PERSISTENCE") {
             self.use_persistence = val.to_lowercase() == "true";
         }
@@ -105,10 +356,22 @@ impl Config {
             self.persistence_interval = Duration::from_secs(secs);
         }
 
+        if let Ok(val) = env::var(prefix.to_owned() + "_PERSISTENCE_MODE") {
+            self.persistence_mode = val.parse()?;
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_WAL_MAX_SIZE") {
+            self.wal_max_size = val.parse().to_interval()?;
+        }
+
         if let Ok(val) = env::var(prefix.to_owned() + "_DATA_DIR") {
             self.data_dir = val;
         }
 
+        if let Ok(val) = env::var(prefix.to_owned() + "_SEED_DIR") {
+            self.seed_dir = Some(val);
+        }
+
         if let Ok(val) = env::var(prefix.to_owned() + "_SINGLE_THREADED") {
             self.single_threaded = val.to_lowercase() == "true";
         }
@@ -132,6 +395,28 @@ impl Config {
             self.channel_buffer_size = size;
         }
 
+        if let Ok(val) = env::var(prefix.to_owned() + "_REJECT_WHEN_BUSY") {
+            self.reject_when_busy = val.to_lowercase() == "true" || val == "1";
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_REQUEST_TIMEOUT") {
+            let secs = val.parse().to_interval()?;
+            self.request_timeout = Some(Duration::from_secs(secs));
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_API_TIMEOUT") {
+            let secs = val.parse().to_interval()?;
+            self.api_timeout = Some(Duration::from_secs(secs));
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_MAX_MESSAGE_SIZE") {
+            self.decoder_limits.max_message_size = val.parse().to_interval()?;
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_MAX_MESSAGE_DEPTH") {
+            self.decoder_limits.max_depth = val.parse().to_interval()?;
+        }
+
         if let Ok(val) = env::var(prefix.to_owned() + "_EXTENDED_MONITORING") {
             let enabled = val.to_lowercase();
             let enabled = enabled.trim();
@@ -142,6 +427,231 @@ impl Config {
             self.auth_token = Some(val);
         }
 
+        if let Ok(val) = env::var(prefix.to_owned() + "_LOG_SINK") {
+            self.log_sink = Some(val.parse()?);
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_USER_DB") {
+            self.user_db = val.to_lowercase() == "true";
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_ACL_FILE") {
+            self.acl = Some(SharedAcl::load(val.into())?);
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_ADMIN_PASSWORD") {
+            self.admin_password = Some(val);
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_VALUE_TEMPLATES") {
+            self.value_templates = ValueTemplates::load(&val)?;
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_MATERIALIZE_VALUE_TEMPLATES") {
+            let enabled = val.to_lowercase();
+            let enabled = enabled.trim();
+            self.materialize_value_templates = enabled == "true" || enabled == "1";
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_KEY_ALLOWED_CHARS") {
+            self.key_rules.allowed_chars = Some(val);
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_KEY_MAX_DEPTH") {
+            self.key_rules.max_depth =
+                Some(val.parse::<usize>().map_err(|e| {
+                    ConfigError::InvalidKeyRules(format!("max depth '{val}': {e}"))
+                })?);
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_KEY_MAX_SEGMENT_LENGTH") {
+            self.key_rules.max_segment_length = Some(val.parse::<usize>().map_err(|e| {
+                ConfigError::InvalidKeyRules(format!("max segment length '{val}': {e}"))
+            })?);
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_KEY_FORBIDDEN_PREFIXES") {
+            self.key_rules.forbidden_prefixes = val
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(ToOwned::to_owned)
+                .collect();
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_DEPRECATED_KEYS") {
+            self.deprecations = Deprecations::load(&val)?;
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_RETENTION_RULES") {
+            self.retention = RetentionRules::load(&val)?;
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_HISTORY_RULES") {
+            self.history = HistoryRules::load(&val)?;
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_REDACTED_PATTERNS") {
+            self.redacted_patterns = val
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(ToOwned::to_owned)
+                .collect();
+        }
+
+        let encryption_key = match env::var(prefix.to_owned() + "_ENCRYPTION_KEY_FILE") {
+            Ok(path) => Some(fs::read_to_string(&path).map_err(|e| {
+                ConfigError::InvalidEncryptionKey(format!("could not read {path}: {e}"))
+            })?),
+            Err(_) => env::var(prefix.to_owned() + "_ENCRYPTION_KEY").ok(),
+        };
+        if let Some(key) = encryption_key {
+            let patterns = env::var(prefix.to_owned() + "_ENCRYPTED_PATTERNS").unwrap_or_default();
+            self.encryption = Some(Encryption::load(&key, &patterns)?);
+        }
+
+        let support_bundle_key = match env::var(prefix.to_owned() + "_SUPPORT_BUNDLE_KEY_FILE") {
+            Ok(path) => Some(fs::read_to_string(&path).map_err(|e| {
+                ConfigError::InvalidEncryptionKey(format!("could not read {path}: {e}"))
+            })?),
+            Err(_) => env::var(prefix.to_owned() + "_SUPPORT_BUNDLE_KEY").ok(),
+        };
+        if let Some(key) = support_bundle_key {
+            self.support_bundle_key = Some(Encryption::load(&key, "")?);
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_TRASH_RETENTION") {
+            let secs = val.parse().to_interval()?;
+            self.trash_retention = Some(Duration::from_secs(secs));
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_EXPIRY_WARNING") {
+            let secs = val.parse().to_interval()?;
+            self.expiry_warning = (secs > 0).then_some(Duration::from_secs(secs));
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_COMPACTION_INTERVAL") {
+            let secs = val.parse().to_interval()?;
+            self.compaction_interval = (secs > 0).then_some(Duration::from_secs(secs));
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_NORMALIZE_VALUES") {
+            self.normalize_values = val.to_lowercase() == "true";
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_MAX_SUBSCRIPTIONS_PER_CLIENT") {
+            self.max_subscriptions_per_client = Some(val.parse().to_interval()?);
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_METRICS_PUSH_URL") {
+            self.metrics_push = Some(MetricsPush {
+                url: val,
+                interval: Duration::from_secs(15),
+                format: MetricsFormat::PushGateway,
+            });
+        }
+
+        if let Some(metrics_push) = &mut self.metrics_push {
+            if let Ok(val) = env::var(prefix.to_owned() + "_METRICS_PUSH_INTERVAL") {
+                let secs = val.parse().to_interval()?;
+                metrics_push.interval = Duration::from_secs(secs);
+            }
+
+            if let Ok(val) = env::var(prefix.to_owned() + "_METRICS_PUSH_FORMAT") {
+                metrics_push.format = match val.to_lowercase().trim() {
+                    "pushgateway" | "prometheus" => MetricsFormat::PushGateway,
+                    "otlp" => MetricsFormat::Otlp,
+                    _ => return Err(ConfigError::InvalidMetricsPushFormat(val)),
+                };
+            }
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_KEY_USAGE_SAMPLE_RATE") {
+            let sample_rate = val.parse().to_interval()?;
+            self.key_usage = (sample_rate > 0).then(|| KeyUsageSampling {
+                sample_rate,
+                ..Default::default()
+            });
+        }
+
+        if let Some(key_usage) = &mut self.key_usage {
+            if let Ok(val) = env::var(prefix.to_owned() + "_KEY_USAGE_BUCKET_DEPTH") {
+                key_usage.bucket_depth = val.parse().to_interval()?;
+            }
+        }
+
+        #[cfg(feature = "s3")]
+        if let Ok(bucket) = env::var(prefix.to_owned() + "_S3_BUCKET") {
+            self.s3 = Some(S3Persistence {
+                bucket,
+                prefix: env::var(prefix.to_owned() + "_S3_PREFIX")
+                    .unwrap_or_else(|_| "worterbuch".to_owned()),
+                retain: 24,
+            });
+        }
+
+        #[cfg(feature = "s3")]
+        if let Some(s3) = &mut self.s3 {
+            if let Ok(val) = env::var(prefix.to_owned() + "_S3_RETAIN") {
+                s3.retain = val.parse().to_interval()?;
+            }
+        }
+
+        #[cfg(feature = "standby")]
+        if let Ok(primary) = env::var(prefix.to_owned() + "_STANDBY_PRIMARY") {
+            self.standby = Some(Standby {
+                primary,
+                auth_token: env::var(prefix.to_owned() + "_STANDBY_AUTH_TOKEN").ok(),
+            });
+        }
+
+        if let Ok(val) = env::var(prefix.to_owned() + "_SERVER_MODE") {
+            self.mode = ServerMode::new(val.parse()?);
+        }
+
+        let replicas = env::var(prefix.to_owned() + "_REPLICAS").ok();
+        let role = env::var(prefix.to_owned() + "_REPLICATION_ROLE").ok();
+        if replicas.is_some() || role.is_some() {
+            let role = role.map(|r| r.parse()).transpose()?.unwrap_or_default();
+            let replicas = replicas
+                .iter()
+                .flat_map(|r| r.split(','))
+                .map(str::trim)
+                .filter(|r| !r.is_empty())
+                .map(ToOwned::to_owned)
+                .collect();
+            let auth_token = env::var(prefix.to_owned() + "_REPLICATION_AUTH_TOKEN").ok();
+            self.replication = Some(Replication::new(role, replicas, auth_token));
+        }
+
+        #[cfg(feature = "cluster")]
+        if let Ok(node_id) = env::var(prefix.to_owned() + "_CLUSTER_NODE_ID") {
+            let nodes = env::var(prefix.to_owned() + "_CLUSTER_NODES")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|n| !n.is_empty())
+                .map(|n| match n.split_once('=') {
+                    Some((id, address)) => Ok(Node {
+                        id: id.trim().to_owned(),
+                        address: address.trim().to_owned(),
+                    }),
+                    None => Err(ConfigError::InvalidClusterConfig(format!(
+                        "node '{n}' is not of the form <id>=<address>"
+                    ))),
+                })
+                .collect::<Result<_, _>>()?;
+            let auth_token = env::var(prefix.to_owned() + "_CLUSTER_AUTH_TOKEN").ok();
+            if self.auth_token.is_none() || auth_token.is_none() {
+                return Err(ConfigError::InvalidClusterConfig(format!(
+                    "nodes recognize each other by the '{CLUSTER_ROLE}' role of their JWT, so \
+                     {prefix}_AUTH_TOKEN and {prefix}_CLUSTER_AUTH_TOKEN must be set"
+                )));
+            }
+            self.cluster = Some(Cluster::new(node_id, nodes, auth_token)?);
+        }
+
         Ok(())
     }
 
@@ -156,23 +666,68 @@ impl Config {
                             port: 8080,
                         },
                         public_addr: "localhost".to_owned(),
+                        certificates: Vec::new(),
+                        path: "/ws".to_owned(),
+                        subprotocols: vec!["worterbuch".to_owned()],
                     }),
                     tcp_endpoint: Some(Endpoint {
                         tls: false,
                         bind_addr: [127, 0, 0, 1].into(),
                         port: 8081,
                     }),
+                    admin_endpoint: None,
+                    #[cfg(feature = "grpc")]
+                    grpc_endpoint: None,
+                    prometheus_endpoint: false,
+                    tcp_oneshot_port: None,
                     use_persistence: false,
                     persistence_interval: Duration::from_secs(30),
+                    persistence_mode: PersistenceMode::default(),
+                    wal_max_size: 64 * 1024 * 1024,
                     data_dir: "./data".into(),
+                    seed_dir: None,
                     single_threaded: false,
                     web_root_path: None,
                     keepalive_timeout: Duration::from_secs(5),
                     send_timeout: Duration::from_secs(5),
                     channel_buffer_size: 1_000,
+                    reject_when_busy: false,
+                    request_timeout: None,
+                    api_timeout: None,
+                    decoder_limits: DecoderLimits::default(),
                     extended_monitoring: true,
                     auth_token: None,
+                    authorizer: None,
+                    change_hooks: ChangeHooks::default(),
+                    acl: None,
+                    user_db: false,
+                    admin_password: None,
                     license,
+                    value_templates: ValueTemplates::default(),
+                    materialize_value_templates: false,
+                    key_rules: KeyRules::default(),
+                    deprecations: Deprecations::default(),
+                    trash_retention: None,
+                    expiry_warning: None,
+                    compaction_interval: Some(Duration::from_secs(5 * 60)),
+                    normalize_values: false,
+                    metrics_push: None,
+                    max_subscriptions_per_client: None,
+                    log_sink: None,
+                    key_usage: None,
+                    retention: RetentionRules::default(),
+                    history: HistoryRules::default(),
+                    encryption: None,
+                    redacted_patterns: Vec::new(),
+                    support_bundle_key: None,
+                    #[cfg(feature = "s3")]
+                    s3: None,
+                    replication: None,
+                    mode: ServerMode::new(Mode::default()),
+                    #[cfg(feature = "standby")]
+                    standby: None,
+                    #[cfg(feature = "cluster")]
+                    cluster: None,
                 };
                 config.load_env()?;
                 Ok(config)
@@ -181,3 +736,35 @@ impl Config {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tls_certificates_are_parsed() {
+        let certs = TlsCertificate::parse_list(
+            "internal.example.com=/certs/int.pem:/certs/int.key, /certs/pub.pem:/certs/pub.key",
+        )
+        .expect("valid certificate list");
+        assert_eq!(
+            certs,
+            vec![
+                TlsCertificate {
+                    server_name: Some("internal.example.com".to_owned()),
+                    cert_file: "/certs/int.pem".to_owned(),
+                    key_file: "/certs/int.key".to_owned(),
+                },
+                TlsCertificate {
+                    server_name: None,
+                    cert_file: "/certs/pub.pem".to_owned(),
+                    key_file: "/certs/pub.key".to_owned(),
+                },
+            ]
+        );
+
+        assert!(TlsCertificate::parse_list("example.com=/certs/cert.pem").is_err());
+        assert!(TlsCertificate::parse_list("/a.pem:/a.key,/b.pem:/b.key").is_err());
+        assert!(matches!(TlsCertificate::parse_list(""), Ok(certs) if certs.is_empty()));
+    }
+}
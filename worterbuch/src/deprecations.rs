@@ -0,0 +1,114 @@
+/*
+ *  Worterbuch key deprecation module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::auth::pattern_matches;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use worterbuch_common::{
+    error::{ConfigError, ConfigResult},
+    Key, KeySegment, RequestPattern,
+};
+
+/// Marks all keys matching a pattern as deprecated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Deprecation {
+    pub pattern: RequestPattern,
+    /// Reads and writes of deprecated keys are redirected to this key. The literal prefix of the
+    /// pattern (i.e. everything before the first wildcard) is replaced with the redirect target.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect: Option<Key>,
+    #[serde(default)]
+    pub reject_writes: bool,
+}
+
+impl Deprecation {
+    pub fn redirect_key(&self, key: &str) -> Option<Key> {
+        let target = self.redirect.as_ref()?;
+        let prefix_len = KeySegment::parse(&self.pattern)
+            .iter()
+            .take_while(|s| matches!(s, KeySegment::Regular(_)))
+            .count();
+        let rest: Vec<&str> = key.split('/').skip(prefix_len).collect();
+        if rest.is_empty() {
+            Some(target.to_owned())
+        } else {
+            Some(format!("{target}/{}", rest.join("/")))
+        }
+    }
+}
+
+/// An ordered list of deprecations. If more than one deprecation matches a key, the first one wins.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deprecations(Vec<Deprecation>);
+
+impl Deprecations {
+    pub fn new(deprecations: Vec<Deprecation>) -> Self {
+        Self(deprecations)
+    }
+
+    pub fn load(path: &str) -> ConfigResult<Self> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| ConfigError::InvalidDeprecations(format!("could not read {path}: {e}")))?;
+        serde_json::from_str(&json)
+            .map_err(|e| ConfigError::InvalidDeprecations(format!("could not parse {path}: {e}")))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn find(&self, key: &str) -> Option<&Deprecation> {
+        self.0.iter().find(|d| pattern_matches(&d.pattern, key))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deprecated_keys_are_redirected() {
+        let deprecations = Deprecations::new(vec![
+            Deprecation {
+                pattern: "old/?/value".to_owned(),
+                redirect: Some("new".to_owned()),
+                reject_writes: false,
+            },
+            Deprecation {
+                pattern: "legacy/flag".to_owned(),
+                redirect: Some("settings/flag".to_owned()),
+                reject_writes: true,
+            },
+            Deprecation {
+                pattern: "gone/#".to_owned(),
+                redirect: None,
+                reject_writes: true,
+            },
+        ]);
+
+        let redirect = |key: &str| deprecations.find(key).and_then(|d| d.redirect_key(key));
+
+        assert_eq!(redirect("old/a/value"), Some("new/a/value".to_owned()));
+        assert_eq!(redirect("legacy/flag"), Some("settings/flag".to_owned()));
+        assert_eq!(redirect("gone/a/b"), None);
+        assert!(deprecations.find("gone/a/b").is_some());
+        assert!(deprecations.find("old/a/other").is_none());
+    }
+}
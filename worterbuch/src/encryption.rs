@@ -0,0 +1,249 @@
+/*
+ *  Worterbuch value encryption module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::auth::pattern_matches;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde_json::{Map, Value};
+use std::{collections::HashSet, fmt};
+use worterbuch_common::{
+    error::{ConfigError, ConfigResult, Context, WorterbuchError, WorterbuchResult},
+    RequestPattern,
+};
+
+/// Top level field of an exported store listing the keys whose values are encrypted.
+const SEALED_KEYS: &str = "encrypted";
+
+/// Encrypts the values of all keys matching one of the configured patterns whenever the store is
+/// written to a persistence file or exported. The in-memory store keeps plaintext values, so reading
+/// them is governed by the regular authorization rules.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Encryption {
+    key: [u8; 32],
+    patterns: Vec<RequestPattern>,
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encryption")
+            .field("key", &"<redacted>")
+            .field("patterns", &self.patterns)
+            .finish()
+    }
+}
+
+impl Encryption {
+    pub fn new(key: [u8; 32], patterns: Vec<RequestPattern>) -> Self {
+        Self { key, patterns }
+    }
+
+    /// Creates an encryption from a hex encoded 256 bit key and a comma separated list of patterns.
+    pub fn load(key: &str, patterns: &str) -> ConfigResult<Self> {
+        let bytes = hex::decode(key.trim())
+            .map_err(|e| ConfigError::InvalidEncryptionKey(e.to_string()))?;
+        let key = bytes.try_into().map_err(|b: Vec<u8>| {
+            ConfigError::InvalidEncryptionKey(format!(
+                "expected 32 bytes, got {}; generate one with 'openssl rand -hex 32'",
+                b.len()
+            ))
+        })?;
+        let patterns = patterns
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(ToOwned::to_owned)
+            .collect();
+        Ok(Self::new(key, patterns))
+    }
+
+    pub fn is_sensitive(&self, key: &str) -> bool {
+        self.patterns.iter().any(|p| pattern_matches(p, key))
+    }
+
+    /// Encrypts the values of all sensitive keys in an exported store and lists their keys in the
+    /// store, so that opening it does not depend on what the values look like.
+    pub fn seal_store(&self, store: &mut Value) -> WorterbuchResult<()> {
+        let mut sealed = Vec::new();
+        visit_values(store, &mut |key, value| {
+            if let Some(encoded) = self.seal_value(key, value)? {
+                *value = Value::String(encoded);
+                sealed.push(Value::String(key.to_owned()));
+            }
+            Ok(())
+        })?;
+        if let Value::Object(obj) = store {
+            obj.insert(SEALED_KEYS.to_owned(), Value::Array(sealed));
+        }
+        Ok(())
+    }
+
+    /// Decrypts the values of all keys listed as encrypted in an exported store, regardless of
+    /// whether they are still considered sensitive, so that removing a pattern does not make values
+    /// unreadable.
+    pub fn open_store(&self, store: &mut Value) -> WorterbuchResult<()> {
+        let sealed = match store
+            .as_object_mut()
+            .and_then(|obj| obj.remove(SEALED_KEYS))
+        {
+            Some(Value::Array(keys)) => keys
+                .into_iter()
+                .filter_map(|k| k.as_str().map(ToOwned::to_owned))
+                .collect::<HashSet<String>>(),
+            _ => return Ok(()),
+        };
+        visit_values(store, &mut |key, value| {
+            if sealed.contains(key) {
+                let encoded = value
+                    .as_str()
+                    .ok_or_else(|| crypto_error(key, "encrypted value is not a string"))?;
+                *value = self.open_value(key, encoded)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Encrypts a single value if its key is sensitive.
+    pub(crate) fn seal_value(&self, key: &str, value: &Value) -> WorterbuchResult<Option<String>> {
+        if !self.is_sensitive(key) {
+            return Ok(None);
+        }
+        let data = serde_json::to_vec(value).context(|| format!("could not encode {key}"))?;
+        self.seal_bytes(key, data).map(Some)
+    }
+
+    /// Decrypts a single value that was encrypted with [`Encryption::seal_value`].
+    pub(crate) fn open_value(&self, key: &str, encoded: &str) -> WorterbuchResult<Value> {
+        let plain = self.open_bytes(key, encoded)?;
+        serde_json::from_slice(&plain).context(|| format!("could not decode {key}"))
+    }
+
+    fn cipher(&self) -> LessSafeKey {
+        let key = UnboundKey::new(&AES_256_GCM, &self.key).expect("key has the correct length");
+        LessSafeKey::new(key)
+    }
+
+    /// Encrypts arbitrary data and returns the base64 encoded nonce and ciphertext. The data can
+    /// only be decrypted again with the same `context`, values use their key so that they cannot be
+    /// moved to other keys.
+    pub fn seal_bytes(&self, context: &str, mut data: Vec<u8>) -> WorterbuchResult<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| crypto_error(context, "could not generate nonce"))?;
+        self.cipher()
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(context.as_bytes()),
+                &mut data,
+            )
+            .map_err(|_| crypto_error(context, "encryption failed"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(data);
+        Ok(STANDARD.encode(sealed))
+    }
+
+    pub fn open_bytes(&self, context: &str, encoded: &str) -> WorterbuchResult<Vec<u8>> {
+        let mut sealed = STANDARD
+            .decode(encoded)
+            .map_err(|e| crypto_error(context, &e.to_string()))?;
+        if sealed.len() < NONCE_LEN {
+            return Err(crypto_error(context, "encrypted value is too short"));
+        }
+        let mut data = sealed.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&sealed)
+            .map_err(|_| crypto_error(context, "invalid nonce"))?;
+        let plain = self
+            .cipher()
+            .open_in_place(nonce, Aad::from(context.as_bytes()), &mut data)
+            .map_err(|_| crypto_error(context, "decryption failed, wrong key?"))?;
+        Ok(plain.to_vec())
+    }
+}
+
+fn crypto_error(key: &str, msg: &str) -> WorterbuchError {
+    WorterbuchError::Other(
+        msg.to_owned().into(),
+        format!("could not process value of {key}"),
+    )
+}
+
+pub(crate) fn visit_values(
+    store: &mut Value,
+    visitor: &mut impl FnMut(&str, &mut Value) -> WorterbuchResult<()>,
+) -> WorterbuchResult<()> {
+    if let Some(Value::Object(tree)) = store.pointer_mut("/data/t") {
+        visit_tree(tree, "", visitor)?;
+    }
+    Ok(())
+}
+
+fn visit_tree(
+    tree: &mut Map<String, Value>,
+    prefix: &str,
+    visitor: &mut impl FnMut(&str, &mut Value) -> WorterbuchResult<()>,
+) -> WorterbuchResult<()> {
+    for (segment, node) in tree.iter_mut() {
+        let key = if prefix.is_empty() {
+            segment.to_owned()
+        } else {
+            format!("{prefix}/{segment}")
+        };
+        if let Some(value) = node.get_mut("v") {
+            visitor(&key, value)?;
+        }
+        if let Some(Value::Object(children)) = node.get_mut("t") {
+            visit_tree(children, &key, visitor)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn sensitive_values_survive_round_trip() {
+        let encryption = Encryption::new([7; 32], vec!["secrets/#".to_owned()]);
+        let original = json!({"data": {"t": {
+            "secrets": {"t": {"db": {"v": {"password": "hunter2"}}}},
+            "public": {"v": 42},
+            "lookalike": {"v": {"$encrypted": "not really"}}
+        }}});
+
+        let mut store = original.clone();
+        encryption.seal_store(&mut store).unwrap();
+        assert!(store["data"]["t"]["secrets"]["t"]["db"]["v"].is_string());
+        assert_eq!(store[SEALED_KEYS], json!(["secrets/db"]));
+        assert!(!store.to_string().contains("hunter2"));
+        assert_eq!(store["data"]["t"]["public"]["v"], json!(42));
+
+        encryption.open_store(&mut store).unwrap();
+        assert_eq!(store, original);
+
+        let mut store = original.clone();
+        encryption.seal_store(&mut store).unwrap();
+        let other = Encryption::new([8; 32], vec![]);
+        assert!(other.open_store(&mut store).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+/*
+ *  Worterbuch key expiry module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    mem,
+    time::{Duration, Instant},
+};
+use worterbuch_common::Key;
+
+/// Deadlines of values that were set with a TTL, ordered by when they expire so the next one can
+/// be looked up without scanning all of them.
+///
+/// With a warning period, keys are also reported once that period before their deadline, so that
+/// owners can refresh them in time. Keys that were reported and then expired or got a new TTL are
+/// reported as withdrawn.
+#[derive(Debug, Default)]
+pub struct Expiries {
+    deadlines: BTreeSet<(Instant, Key)>,
+    warnings: BTreeSet<(Instant, Key)>,
+    by_key: HashMap<Key, (Instant, Option<Instant>)>,
+    warning: Option<Duration>,
+    warned: HashSet<Key>,
+    withdrawn: Vec<Key>,
+}
+
+impl Expiries {
+    pub fn new(warning: Option<Duration>) -> Self {
+        Self {
+            warning,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the deadline of a key, replacing any previous one.
+    pub fn schedule(&mut self, key: Key, deadline: Instant) {
+        self.cancel(&key);
+        let warn_at = self
+            .warning
+            .map(|warning| deadline.checked_sub(warning).unwrap_or(deadline));
+        if let Some(warn_at) = warn_at {
+            self.warnings.insert((warn_at, key.clone()));
+        }
+        self.deadlines.insert((deadline, key.clone()));
+        self.by_key.insert(key, (deadline, warn_at));
+    }
+
+    pub fn cancel(&mut self, key: &str) {
+        if let Some((deadline, warn_at)) = self.by_key.remove(key) {
+            self.deadlines.remove(&(deadline, key.to_owned()));
+            if let Some(warn_at) = warn_at {
+                self.warnings.remove(&(warn_at, key.to_owned()));
+            }
+        }
+        if self.warned.remove(key) {
+            self.withdrawn.push(key.to_owned());
+        }
+    }
+
+    /// The next time a key expires, is due for a warning or has a warning to withdraw.
+    pub fn next(&self) -> Option<Instant> {
+        if !self.withdrawn.is_empty() {
+            return Some(Instant::now());
+        }
+        [self.deadlines.first(), self.warnings.first()]
+            .into_iter()
+            .flatten()
+            .map(|(instant, _)| *instant)
+            .min()
+    }
+
+    /// Removes and returns all keys whose deadline has passed.
+    pub fn take_due(&mut self, now: Instant) -> Vec<Key> {
+        let mut due = Vec::new();
+        while let Some((deadline, key)) = self.deadlines.first().cloned() {
+            if deadline > now {
+                break;
+            }
+            self.cancel(&key);
+            due.push(key);
+        }
+        due
+    }
+
+    /// Returns all keys whose warning is due, along with their deadline.
+    pub fn take_warnings(&mut self, now: Instant) -> Vec<(Key, Instant)> {
+        let mut due = Vec::new();
+        while self
+            .warnings
+            .first()
+            .is_some_and(|(warn_at, _)| *warn_at <= now)
+        {
+            if let Some((_, key)) = self.warnings.pop_first() {
+                if let Some((deadline, _)) = self.by_key.get(&key) {
+                    due.push((key.clone(), *deadline));
+                    self.warned.insert(key);
+                }
+            }
+        }
+        due
+    }
+
+    /// Returns all keys whose warning was issued but that have since expired or been rescheduled.
+    pub fn take_withdrawn(&mut self) -> Vec<Key> {
+        mem::take(&mut self.withdrawn)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn keys_are_due_in_order_of_their_deadlines() {
+        let now = Instant::now();
+        let mut expiries = Expiries::default();
+        expiries.schedule("b".to_owned(), now + Duration::from_secs(2));
+        expiries.schedule("a".to_owned(), now + Duration::from_secs(1));
+        expiries.schedule("c".to_owned(), now + Duration::from_secs(3));
+        expiries.schedule("c".to_owned(), now + Duration::from_secs(10));
+        expiries.cancel("b");
+
+        assert_eq!(expiries.next(), Some(now + Duration::from_secs(1)));
+        assert_eq!(expiries.take_due(now + Duration::from_secs(5)), vec!["a"]);
+        assert_eq!(expiries.next(), Some(now + Duration::from_secs(10)));
+        assert!(expiries.take_due(now + Duration::from_secs(5)).is_empty());
+    }
+
+    #[test]
+    fn warnings_are_due_ahead_of_deadlines_and_withdrawn_afterwards() {
+        let now = Instant::now();
+        let mut expiries = Expiries::new(Some(Duration::from_secs(30)));
+        expiries.schedule("lease/a".to_owned(), now + Duration::from_secs(60));
+        expiries.schedule("lease/b".to_owned(), now + Duration::from_secs(90));
+
+        assert_eq!(expiries.next(), Some(now + Duration::from_secs(30)));
+        assert_eq!(
+            expiries.take_warnings(now + Duration::from_secs(30)),
+            vec![("lease/a".to_owned(), now + Duration::from_secs(60))]
+        );
+        assert!(expiries.take_withdrawn().is_empty());
+
+        // refreshing a lease withdraws its warning
+        expiries.schedule("lease/a".to_owned(), now + Duration::from_secs(120));
+        assert_eq!(expiries.take_withdrawn(), vec!["lease/a"]);
+
+        assert_eq!(
+            expiries.take_warnings(now + Duration::from_secs(90)),
+            vec![
+                ("lease/b".to_owned(), now + Duration::from_secs(90)),
+                ("lease/a".to_owned(), now + Duration::from_secs(120))
+            ]
+        );
+        assert_eq!(
+            expiries.take_due(now + Duration::from_secs(90)),
+            vec!["lease/b"]
+        );
+        assert_eq!(expiries.take_withdrawn(), vec!["lease/b"]);
+    }
+}
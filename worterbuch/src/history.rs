@@ -0,0 +1,123 @@
+/*
+ *  Worterbuch value history module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::auth::pattern_matches;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use worterbuch_common::{
+    error::{ConfigError, ConfigResult},
+    HistoryEntry, Key, RequestPattern, Value,
+};
+
+/// Keeps the last `max_entries` values of all keys matching the pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryRule {
+    pub pattern: RequestPattern,
+    pub max_entries: usize,
+}
+
+/// An ordered list of history rules. If more than one rule matches a key, the first one wins.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryRules(Vec<HistoryRule>);
+
+impl HistoryRules {
+    pub fn new(rules: Vec<HistoryRule>) -> Self {
+        Self(rules)
+    }
+
+    pub fn load(path: &str) -> ConfigResult<Self> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| ConfigError::InvalidHistoryRules(format!("could not read {path}: {e}")))?;
+        serde_json::from_str(&json)
+            .map_err(|e| ConfigError::InvalidHistoryRules(format!("could not parse {path}: {e}")))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn find(&self, key: &str) -> Option<&HistoryRule> {
+        self.0.iter().find(|r| pattern_matches(&r.pattern, key))
+    }
+}
+
+/// The recent values of all keys covered by a history rule. The history is only kept in memory
+/// and outlives deletes of its key.
+#[derive(Debug, Default)]
+pub struct History {
+    entries: HashMap<Key, VecDeque<HistoryEntry>>,
+}
+
+impl History {
+    pub fn record(&mut self, key: &str, value: &Value, max_entries: usize) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let entries = self.entries.entry(key.to_owned()).or_default();
+        entries.push_back(HistoryEntry {
+            timestamp,
+            value: value.to_owned(),
+        });
+        while entries.len() > max_entries {
+            entries.pop_front();
+        }
+    }
+
+    /// The most recent `limit` values of a key, oldest first.
+    pub fn get(&self, key: &str, limit: Option<usize>) -> Vec<HistoryEntry> {
+        let Some(entries) = self.entries.get(key) else {
+            return Vec::new();
+        };
+        let skip = limit.map_or(0, |limit| entries.len().saturating_sub(limit));
+        entries.iter().skip(skip).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn history_keeps_the_most_recent_values() {
+        let mut history = History::default();
+        for i in 0..5 {
+            history.record("sensors/temp", &json!(i), 3);
+        }
+
+        let values = |entries: Vec<HistoryEntry>| -> Vec<Value> {
+            entries.into_iter().map(|e| e.value).collect()
+        };
+        assert_eq!(
+            values(history.get("sensors/temp", None)),
+            vec![json!(2), json!(3), json!(4)]
+        );
+        assert_eq!(
+            values(history.get("sensors/temp", Some(2))),
+            vec![json!(3), json!(4)]
+        );
+        assert!(history.get("sensors/humidity", None).is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+/*
+ *  Worterbuch change hooks module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::auth::pattern_matches;
+use futures::{future::BoxFuture, FutureExt};
+use serde_json::Value;
+use std::{fmt, future::Future, sync::Arc};
+use tokio::spawn;
+use worterbuch_common::{Key, RequestPattern};
+
+/// A change of a key's value, passed to change hooks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyChange {
+    pub key: Key,
+    /// value before the change, `None` if the key did not exist
+    pub old_value: Option<Value>,
+    /// value after the change, `None` if the key was deleted
+    pub new_value: Option<Value>,
+}
+
+type SyncHook = Arc<dyn Fn(&KeyChange) + Send + Sync>;
+type AsyncHook = Arc<dyn Fn(KeyChange) -> BoxFuture<'static, ()> + Send + Sync>;
+
+#[derive(Clone)]
+enum Hook {
+    Sync(SyncHook),
+    Async(AsyncHook),
+}
+
+/// Callbacks for applications that embed the server, the in-process equivalent of webhooks.
+///
+/// Hooks are called after a set or delete on a key matching their pattern succeeded and the
+/// value actually changed. Synchronous hooks run inside the server's event loop and must not
+/// block, asynchronous hooks are spawned as separate tasks.
+#[derive(Clone, Default)]
+pub struct ChangeHooks(Vec<(RequestPattern, Hook)>);
+
+impl ChangeHooks {
+    pub fn on_change(
+        &mut self,
+        pattern: impl Into<RequestPattern>,
+        hook: impl Fn(&KeyChange) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.0.push((pattern.into(), Hook::Sync(Arc::new(hook))));
+        self
+    }
+
+    pub fn on_change_async<F, Fut>(
+        &mut self,
+        pattern: impl Into<RequestPattern>,
+        hook: F,
+    ) -> &mut Self
+    where
+        F: Fn(KeyChange) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let hook: AsyncHook = Arc::new(move |change| hook(change).boxed());
+        self.0.push((pattern.into(), Hook::Async(hook)));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        self.0
+            .iter()
+            .any(|(pattern, _)| pattern_matches(pattern, key))
+    }
+
+    pub(crate) fn notify(&self, key: &str, old_value: Option<&Value>, new_value: Option<&Value>) {
+        if !self.matches(key) {
+            return;
+        }
+
+        let change = KeyChange {
+            key: key.to_owned(),
+            old_value: old_value.cloned(),
+            new_value: new_value.cloned(),
+        };
+
+        for (pattern, hook) in &self.0 {
+            if !pattern_matches(pattern, key) {
+                continue;
+            }
+            match hook {
+                Hook::Sync(hook) => hook(&change),
+                Hook::Async(hook) => {
+                    spawn(hook(change.clone()));
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ChangeHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|(pattern, _)| pattern))
+            .finish()
+    }
+}
+
+impl PartialEq for ChangeHooks {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self.0.iter().zip(&other.0).all(|((pa, ha), (pb, hb))| {
+                pa == pb
+                    && match (ha, hb) {
+                        (Hook::Sync(a), Hook::Sync(b)) => Arc::ptr_eq(a, b),
+                        (Hook::Async(a), Hook::Async(b)) => Arc::ptr_eq(a, b),
+                        _ => false,
+                    }
+            })
+    }
+}
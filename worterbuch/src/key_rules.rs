@@ -0,0 +1,135 @@
+/*
+ *  Worterbuch key naming rules module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use worterbuch_common::{
+    error::{WorterbuchError, WorterbuchResult},
+    Key,
+};
+
+/// Naming rules keys have to follow when they are set or published to by a client.
+/// Rules that are not configured are not enforced.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyRules {
+    /// Characters allowed in key segments in addition to ASCII letters and digits.
+    pub allowed_chars: Option<String>,
+    pub max_depth: Option<usize>,
+    pub max_segment_length: Option<usize>,
+    pub forbidden_prefixes: Vec<Key>,
+}
+
+impl KeyRules {
+    pub fn is_empty(&self) -> bool {
+        self.allowed_chars.is_none()
+            && self.max_depth.is_none()
+            && self.max_segment_length.is_none()
+            && self.forbidden_prefixes.is_empty()
+    }
+
+    pub fn check(&self, key: &str) -> WorterbuchResult<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        for prefix in &self.forbidden_prefixes {
+            if key == prefix || key.starts_with(&format!("{}/", prefix.trim_end_matches('/'))) {
+                return Err(illegal_key(key, format!("prefix '{prefix}' is reserved")));
+            }
+        }
+
+        let segments: Vec<&str> = key.split('/').collect();
+
+        if let Some(max_depth) = self.max_depth {
+            if segments.len() > max_depth {
+                return Err(illegal_key(
+                    key,
+                    format!(
+                        "key has {} segments, at most {max_depth} are allowed",
+                        segments.len()
+                    ),
+                ));
+            }
+        }
+
+        for segment in segments {
+            if let Some(max_len) = self.max_segment_length {
+                if segment.chars().count() > max_len {
+                    return Err(illegal_key(
+                        key,
+                        format!("segment '{segment}' is longer than {max_len} characters"),
+                    ));
+                }
+            }
+
+            if let Some(allowed) = &self.allowed_chars {
+                if let Some(c) = segment
+                    .chars()
+                    .find(|c| !c.is_ascii_alphanumeric() && !allowed.contains(*c))
+                {
+                    return Err(illegal_key(
+                        key,
+                        format!("segment '{segment}' contains illegal character '{c}'"),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn illegal_key(key: &str, rule: String) -> WorterbuchError {
+    WorterbuchError::IllegalKey(key.to_owned(), rule)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rules() -> KeyRules {
+        KeyRules {
+            allowed_chars: Some("-_".to_owned()),
+            max_depth: Some(3),
+            max_segment_length: Some(12),
+            forbidden_prefixes: vec!["internal".to_owned()],
+        }
+    }
+
+    #[test]
+    fn empty_rules_allow_everything() {
+        assert!(KeyRules::default()
+            .check("some/ünicode key/with spaces")
+            .is_ok());
+    }
+
+    #[test]
+    fn valid_keys_pass() {
+        assert!(rules().check("hello/world").is_ok());
+        assert!(rules().check("a-b/c_d/e1").is_ok());
+        assert!(rules().check("internally").is_ok());
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected() {
+        assert!(rules().check("hello world").is_err());
+        assert!(rules().check("a/b/c/d").is_err());
+        assert!(rules().check("toolongsegment").is_err());
+        assert!(rules().check("internal").is_err());
+        assert!(rules().check("internal/key").is_err());
+    }
+}
@@ -25,31 +25,102 @@
 //! still an application. Just one that you can start from within your
 //! own application.
 
-mod auth;
+pub mod acl;
+pub mod auth;
+#[cfg(feature = "cluster")]
+pub mod cluster;
+mod compaction;
 mod config;
+pub mod deprecations;
+pub mod encryption;
+mod expiry;
+pub mod history;
+pub mod hooks;
+pub mod key_rules;
 pub mod license;
+mod lockout;
+mod locks;
+pub mod logging;
+mod lscache;
+pub mod metrics;
+pub mod mode;
+mod normalize;
 mod persistence;
+mod recorder;
+pub mod replication;
+mod reservations;
+pub mod retention;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod seed;
 mod server;
+#[cfg(feature = "standby")]
+pub mod standby;
 mod stats;
 pub mod store;
 mod subscribers;
+pub mod support;
+pub mod templates;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+mod trash;
+pub mod usage;
+pub mod users;
+pub mod wal;
 mod worterbuch;
 
 pub use crate::worterbuch::*;
 pub use config::*;
-use serde_json::Value;
+pub use persistence::{check_dump, DumpCheck};
+use serde_json::{json, Value};
 use server::common::{CloneableWbApi, WbFunction};
-use tokio_graceful_shutdown::SubsystemHandle;
-use worterbuch_common::{topic, SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_SUPPORTED_PROTOCOL_VERSION};
+use worterbuch_common::{
+    topic, SYSTEM_TOPIC_QUEUE, SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_SUPPORTED_PROTOCOL_VERSION,
+};
 
-use crate::stats::track_stats;
-use anyhow::Result;
-use tokio::{select, sync::mpsc};
+use crate::stats::{track_stats, STATS_FLUSH_INTERVAL};
+use anyhow::{anyhow, Result};
+use std::{future::pending, time::Duration};
+use tokio::{
+    runtime::{Runtime, RuntimeFlavor},
+    select,
+    sync::mpsc,
+    time::{interval, sleep_until, MissedTickBehavior},
+};
+use tokio_graceful_shutdown::{SubsystemHandle, Toplevel};
 
 pub const INTERNAL_CLIENT_ID: &str = "internal_client_id";
 
 pub async fn run_worterbuch(subsys: SubsystemHandle) -> Result<()> {
     let config = Config::new().await?;
+    run_worterbuch_with_config(subsys, config).await
+}
+
+/// Runs worterbuch on a current thread runtime and blocks until it has shut down. All tasks share
+/// that one thread. Besides the enabled servers, only the server mode task is always spawned,
+/// persistence, trash purging, retention, compaction, ACL sync, metrics push, replication,
+/// standby and cluster tasks are only spawned if they are configured. With a minimal config this
+/// is suitable for embedding worterbuch into resource constrained applications.
+pub fn run_worterbuch_local(runtime: &Runtime, mut config: Config) -> Result<()> {
+    if runtime.handle().runtime_flavor() != RuntimeFlavor::CurrentThread {
+        return Err(anyhow!(
+            "run_worterbuch_local must be called with a current thread runtime"
+        ));
+    }
+    config.single_threaded = true;
+    runtime.block_on(async move {
+        Toplevel::new()
+            .start("worterbuch", move |subsys| {
+                run_worterbuch_with_config(subsys, config)
+            })
+            .catch_signals()
+            .handle_shutdown_requests(Duration::from_millis(1000))
+            .await?;
+        Ok(())
+    })
+}
+
+pub async fn run_worterbuch_with_config(subsys: SubsystemHandle, config: Config) -> Result<()> {
     let config_pers = config.clone();
 
     let channel_buffer_size = config.channel_buffer_size;
@@ -71,11 +142,41 @@ pub async fn run_worterbuch(subsys: SubsystemHandle) -> Result<()> {
         )
         .await?;
 
+    track_stats(&mut worterbuch).await?;
+
+    worterbuch
+        .set(
+            topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_QUEUE, "capacity"),
+            json!(channel_buffer_size),
+            INTERNAL_CLIENT_ID,
+        )
+        .await?;
+
+    if let Some(seed_dir) = &config.seed_dir {
+        seed::apply_dir(&mut worterbuch, seed_dir).await?;
+    }
+
+    if let (true, Some(password)) = (config.user_db, &config.admin_password) {
+        users::bootstrap_admin(&mut worterbuch, password).await?;
+    }
+
     let (api_tx, mut api_rx) = mpsc::channel(channel_buffer_size);
     let api = CloneableWbApi::new(api_tx);
+    // requests of internal subsystems always wait for the queue, only client requests are rejected
+    // or skipped once their requester has given up on them
+    let mut client_api = api
+        .with_request_timeout(config.request_timeout)
+        .with_response_timeout(config.api_timeout);
+    if config.reject_when_busy {
+        client_api = client_api.rejecting_when_busy();
+    }
+    // internal subsystems work on the local part of the key space only
+    #[cfg(feature = "cluster")]
+    if let Some(cluster) = config.cluster.clone() {
+        client_api = client_api.with_cluster(cluster);
+    }
 
     let worterbuch_pers = api.clone();
-    let worterbuch_uptime = api.clone();
 
     if use_persistence {
         subsys.start("persistence", |subsys| {
@@ -83,24 +184,91 @@ pub async fn run_worterbuch(subsys: SubsystemHandle) -> Result<()> {
         });
     }
 
-    subsys.start("stats", |subsys| track_stats(worterbuch_uptime, subsys));
+    if let Some(retention) = config.trash_retention {
+        let worterbuch_trash = api.clone();
+        subsys.start("trash", move |subsys| {
+            trash::purge_periodically(worterbuch_trash, retention, subsys)
+        });
+    }
 
-    if let Some(WsEndpoint {
-        endpoint: Endpoint {
-            tls,
-            bind_addr,
-            port,
-        },
-        public_addr,
-    }) = &config.ws_endpoint
-    {
-        let sapi = api.clone();
-        let tls = tls.to_owned();
-        let bind_addr = bind_addr.to_owned();
-        let port = port.to_owned();
-        let public_addr = public_addr.to_owned();
+    if !config.retention.is_empty() {
+        let worterbuch_retention = api.clone();
+        subsys.start("retention", move |subsys| {
+            retention::enforce_periodically(worterbuch_retention, subsys)
+        });
+    }
+
+    if let Some(period) = config.compaction_interval {
+        let worterbuch_compaction = api.clone();
+        subsys.start("compaction", move |subsys| {
+            compaction::compact_periodically(worterbuch_compaction, period, subsys)
+        });
+    }
+
+    if let Some(acl) = config.acl.clone() {
+        let worterbuch_acl = api.clone();
+        subsys.start("acl", move |subsys| acl::sync(worterbuch_acl, acl, subsys));
+    }
+
+    if let Some(metrics_push) = config.metrics_push.clone() {
+        let worterbuch_metrics = api.clone();
+        subsys.start("metrics", move |subsys| {
+            metrics::push_periodically(worterbuch_metrics, metrics_push, subsys)
+        });
+    }
+
+    let worterbuch_mode = api.clone();
+    let mode = config.mode.clone();
+    subsys.start("mode", move |subsys| {
+        mode::run(worterbuch_mode, mode, subsys)
+    });
+
+    if let Some(replication) = config.replication.clone() {
+        let worterbuch_replication = api.clone();
+        subsys.start("replication", move |subsys| {
+            replication::run(worterbuch_replication, replication, subsys)
+        });
+    }
+
+    #[cfg(feature = "standby")]
+    if let Some(standby) = config.standby.clone() {
+        let worterbuch_standby = api.clone();
+        subsys.start("standby", move |subsys| {
+            standby::follow(worterbuch_standby, standby, subsys)
+        });
+    }
+
+    if let Some(endpoint) = &config.ws_endpoint {
+        let sapi = client_api.clone();
+        let endpoint = endpoint.to_owned();
         subsys.start("webserver", move |subsys| {
-            server::poem::start(sapi, tls, bind_addr, port, public_addr, subsys)
+            server::poem::start(sapi, endpoint, subsys)
+        });
+    }
+
+    if let Some(endpoint) = &config.admin_endpoint {
+        let sapi = client_api.clone();
+        let endpoint = endpoint.to_owned();
+        let certificates = config
+            .ws_endpoint
+            .as_ref()
+            .map(|ep| ep.certificates.clone())
+            .unwrap_or_default();
+        subsys.start("adminserver", move |subsys| {
+            server::poem::start_admin(sapi, endpoint, certificates, subsys)
+        });
+    }
+
+    #[cfg(feature = "grpc")]
+    if let Some(Endpoint {
+        tls: _,
+        bind_addr,
+        port,
+    }) = config.grpc_endpoint.clone()
+    {
+        let sapi = client_api.clone();
+        subsys.start("grpcserver", move |subsys| {
+            server::grpc::start(sapi, bind_addr, port, subsys)
         });
     }
 
@@ -110,24 +278,53 @@ pub async fn run_worterbuch(subsys: SubsystemHandle) -> Result<()> {
         port,
     }) = &config.tcp_endpoint
     {
-        let sapi = api.clone();
+        let sapi = client_api.clone();
         let bind_addr = bind_addr.to_owned();
         let port = port.to_owned();
         subsys.start("tcpserver", move |subsys| {
-            server::tcp::start(sapi, bind_addr, port, subsys)
+            server::tcp::start(sapi, bind_addr, port, false, subsys)
         });
+
+        if let Some(port) = config.tcp_oneshot_port {
+            let sapi = client_api.clone();
+            subsys.start("tcpserver-oneshot", move |subsys| {
+                server::tcp::start(sapi, bind_addr, port, true, subsys)
+            });
+        }
     }
 
+    // monitoring writes are queued and only applied when there are no client requests waiting,
+    // unless they have been held back for too long
+    let mut stats_interval = interval(STATS_FLUSH_INTERVAL);
+    stats_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
     loop {
         select! {
+            biased;
+            () = subsys.on_shutdown_requested() => break,
             recv = api_rx.recv() => match recv {
-                Some(function) => process_api_call(&mut worterbuch, function).await,
+                Some(call) => {
+                    if call.expired() {
+                        log::debug!("Skipping request whose deadline has passed.");
+                    } else {
+                        process_api_call(&mut worterbuch, call.function).await;
+                    }
+                    if worterbuch.stats_overdue() {
+                        worterbuch.flush_stats().await;
+                    }
+                }
                 None => break,
             },
-            () = subsys.on_shutdown_requested() => break,
+            () = expiry(worterbuch.next_expiry()) => worterbuch.expire_keys().await,
+            _ = stats_interval.tick() => {
+                worterbuch.track_queue_depth(api.queue_depth());
+                worterbuch.flush_stats().await;
+            },
         }
     }
 
+    worterbuch.flush_stats().await;
+
     log::info!("Shutting down.");
 
     if use_persistence {
@@ -137,22 +334,93 @@ pub async fn run_worterbuch(subsys: SubsystemHandle) -> Result<()> {
     Ok(())
 }
 
+async fn expiry(deadline: Option<std::time::Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline.into()).await,
+        None => pending().await,
+    }
+}
+
 async fn process_api_call(worterbuch: &mut Worterbuch, function: WbFunction) {
     match function {
         WbFunction::Get(key, tx) => {
-            tx.send(worterbuch.get(&key)).ok();
+            tx.send(worterbuch.get_or_template(&key).await).ok();
         }
         WbFunction::Set(key, value, client_id, tx) => {
             tx.send(worterbuch.set(key, value, &client_id).await).ok();
         }
-        WbFunction::Publish(key, value, tx) => {
-            tx.send(worterbuch.publish(key, value).await).ok();
+        WbFunction::GetAndSet(key, value, client_id, tx) => {
+            tx.send(worterbuch.get_and_set(key, value, &client_id).await)
+                .ok();
+        }
+        WbFunction::SetMany(key_value_pairs, client_id, tx) => {
+            tx.send(worterbuch.set_many(key_value_pairs, &client_id).await)
+                .ok();
+        }
+        WbFunction::SetExpiring(key, value, ttl, client_id, tx) => {
+            tx.send(worterbuch.set_expiring(key, value, ttl, &client_id).await)
+                .ok();
+        }
+        WbFunction::Expire(key, ttl, client_id, tx) => {
+            tx.send(worterbuch.expire(key, ttl, &client_id).await).ok();
+        }
+        WbFunction::SetIf(key, value, exists, client_id, tx) => {
+            tx.send(worterbuch.set_if(key, value, exists, &client_id).await)
+                .ok();
+        }
+        WbFunction::Reserve(pattern, client_id, tx) => {
+            tx.send(worterbuch.reserve(pattern, &client_id)).ok();
+        }
+        WbFunction::Release(pattern, client_id, tx) => {
+            worterbuch.release(&pattern, &client_id);
+            tx.send(Ok(())).ok();
+        }
+        WbFunction::Lock(key, lease, client_id, tx) => {
+            worterbuch.lock(key, lease, client_id, tx).await;
+        }
+        WbFunction::TryLock(key, lease, client_id, tx) => {
+            tx.send(worterbuch.try_lock(key, lease, &client_id).await)
+                .ok();
+        }
+        WbFunction::Unlock(key, client_id, tx) => {
+            tx.send(worterbuch.unlock(key, &client_id).await).ok();
+        }
+        WbFunction::Cas(key, expected, version, value, client_id, tx) => {
+            tx.send(
+                worterbuch
+                    .compare_and_swap(key, expected, version, value, &client_id)
+                    .await,
+            )
+            .ok();
+        }
+        WbFunction::Increment(key, delta, client_id, tx) => {
+            tx.send(worterbuch.increment(key, &delta, &client_id).await)
+                .ok();
+        }
+        WbFunction::SetAt(key, pointer, value, client_id, tx) => {
+            tx.send(worterbuch.set_at(key, &pointer, value, &client_id).await)
+                .ok();
+        }
+        WbFunction::UpdateArray(key, op, client_id, tx) => {
+            tx.send(worterbuch.update_array(key, op, &client_id).await)
+                .ok();
+        }
+        WbFunction::PublishRetained(key, value, client_id, tx) => {
+            tx.send(worterbuch.publish_retained(key, value, &client_id).await)
+                .ok();
+        }
+        WbFunction::Publish(key, value, client_id, tx) => {
+            tx.send(worterbuch.publish(key, value, &client_id).await)
+                .ok();
         }
         WbFunction::Ls(parent, tx) => {
             tx.send(worterbuch.ls(&parent)).ok();
         }
-        WbFunction::PGet(pattern, tx) => {
-            tx.send(worterbuch.pget(&pattern)).ok();
+        WbFunction::Snapshot(tx) => {
+            tx.send(worterbuch.snapshot()).ok();
+        }
+        WbFunction::PGetSnapshot(pattern, tx) => {
+            tx.send(worterbuch.pget_snapshot(&pattern)).ok();
         }
         WbFunction::Subscribe(client_id, transaction_id, key, unique, live_only, tx) => {
             tx.send(
@@ -182,35 +450,112 @@ async fn process_api_call(worterbuch: &mut Worterbuch, function: WbFunction) {
             tx.send(worterbuch.unsubscribe(client_id, transaction_id).await)
                 .ok();
         }
+        WbFunction::Resync(client_id, transaction_id, tx) => {
+            tx.send(worterbuch.resync(client_id, transaction_id).await)
+                .ok();
+        }
         WbFunction::UnsubscribeLs(client_id, transaction_id, tx) => {
             tx.send(worterbuch.unsubscribe_ls(client_id, transaction_id))
                 .ok();
         }
+        WbFunction::SubscribeEvents(client_id, transaction_id, tx) => {
+            tx.send(worterbuch.subscribe_events(client_id, transaction_id))
+                .ok();
+        }
+        WbFunction::Event(event) => {
+            worterbuch.emit_event(event);
+        }
+        WbFunction::WhoSubscribes(pattern, tx) => {
+            tx.send(worterbuch.who_subscribes(&pattern)).ok();
+        }
+        WbFunction::GetHistory(key, limit, tx) => {
+            tx.send(worterbuch.get_history(&key, limit)).ok();
+        }
         WbFunction::Delete(key, client_id, tx) => {
             tx.send(worterbuch.delete(key, &client_id).await).ok();
         }
         WbFunction::PDelete(pattern, client_id, tx) => {
+            if tx.is_closed() {
+                log::debug!("Skipping cancelled pdelete of '{pattern}'.");
+                return;
+            }
             tx.send(worterbuch.pdelete(pattern, &client_id).await).ok();
         }
+        WbFunction::Restore(pattern, client_id, tx) => {
+            tx.send(worterbuch.restore(pattern, &client_id).await).ok();
+        }
+        WbFunction::PurgeTrash(tx) => {
+            tx.send(worterbuch.purge_trash().await).ok();
+        }
+        WbFunction::StaleKeys(tx) => {
+            tx.send(worterbuch.stale_keys()).ok();
+        }
+        WbFunction::EnforceRetention(tx) => {
+            tx.send(worterbuch.enforce_retention().await).ok();
+        }
+        WbFunction::StartRecording(name, pattern) => {
+            worterbuch.start_recording(name, pattern);
+        }
+        WbFunction::StopRecording(name, tx) => {
+            tx.send(worterbuch.stop_recording(&name)).ok();
+        }
+        WbFunction::RecordingPattern(name, tx) => {
+            tx.send(worterbuch.recording_pattern(&name)).ok();
+        }
+        WbFunction::Recording(name, tx) => {
+            tx.send(worterbuch.recording(&name)).ok();
+        }
+        WbFunction::Compact(tx) => {
+            tx.send(worterbuch.compact()).ok();
+        }
         WbFunction::Connected(client_id, remote_addr, protocol) => {
             worterbuch
                 .connected(client_id, remote_addr, &protocol)
                 .await;
         }
+        WbFunction::ClientInfo(client_id, info) => {
+            if let Err(e) = worterbuch.set_client_info(&client_id, &info).await {
+                log::error!("Error updating client info: {e}");
+            }
+        }
         WbFunction::Disconnected(client_id, remote_addr) => {
             worterbuch.disconnected(client_id, remote_addr).await.ok();
         }
         WbFunction::Config(tx) => {
             tx.send(worterbuch.config().clone()).ok();
         }
-        WbFunction::Export(tx) => {
-            tx.send(worterbuch.export()).ok();
+        WbFunction::Metrics(tx) => {
+            tx.send(worterbuch.metrics()).ok();
         }
-        WbFunction::Len(tx) => {
-            tx.send(worterbuch.len()).ok();
+        WbFunction::KeyUsage(top, tx) => {
+            tx.send(worterbuch.key_usage(top)).ok();
+        }
+        WbFunction::RemoteAddr(client_id, tx) => {
+            tx.send(worterbuch.remote_addr(&client_id)).ok();
+        }
+        WbFunction::AuthAttempt(remote_addr, success, tx) => {
+            tx.send(worterbuch.auth_attempt(remote_addr, success).await)
+                .ok();
         }
         WbFunction::SupportedProtocolVersion(tx) => {
             tx.send(worterbuch.supported_protocol_version()).ok();
         }
+        WbFunction::BootId(tx) => {
+            tx.send(worterbuch.boot_id()).ok();
+        }
+        WbFunction::WalSize(tx) => {
+            tx.send(worterbuch.wal_size()).ok();
+        }
+        WbFunction::RotateWal(tx) => {
+            tx.send(worterbuch.rotate_wal()).ok();
+        }
+        WbFunction::Sync(persist, tx) => {
+            let res = if persist {
+                worterbuch.sync_wal()
+            } else {
+                Ok(true)
+            };
+            tx.send(res).ok();
+        }
     }
 }
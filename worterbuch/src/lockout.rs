@@ -0,0 +1,138 @@
+/*
+ *  Worterbuch authentication lockout module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+/// Failed attempts an address gets for free before it is locked out.
+const FREE_ATTEMPTS: u32 = 3;
+const INITIAL_LOCKOUT: Duration = Duration::from_secs(1);
+const MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Counters of authentication attempts, published under `$SYS/auth`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AuthAttempts {
+    pub succeeded: u64,
+    pub failed: u64,
+    /// attempts that were rejected without checking them because the address was locked out
+    pub rejected: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Tracks failed authentication attempts per remote address. Once an address has used up its free
+/// attempts, every further failure locks it out for twice as long as the previous one.
+#[derive(Debug, Default)]
+pub struct AuthLockout {
+    failures: HashMap<IpAddr, Failures>,
+}
+
+impl AuthLockout {
+    /// Returns how much longer the address is locked out, if it is.
+    pub fn locked_out(&self, addr: &IpAddr, now: Instant) -> Option<Duration> {
+        self.failures
+            .get(addr)
+            .and_then(|f| f.locked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    /// Records a failed attempt and returns the lockout it caused, if any.
+    pub fn failed(&mut self, addr: IpAddr, now: Instant) -> Option<Duration> {
+        // forget addresses that have behaved for a while
+        self.failures
+            .retain(|_, f| now.saturating_duration_since(f.last) < MAX_LOCKOUT * 2);
+
+        let failures = self.failures.entry(addr).or_insert(Failures {
+            count: 0,
+            last: now,
+            locked_until: None,
+        });
+        failures.count += 1;
+        failures.last = now;
+
+        if failures.count <= FREE_ATTEMPTS {
+            return None;
+        }
+
+        let exponent = (failures.count - FREE_ATTEMPTS - 1).min(16);
+        let lockout = (INITIAL_LOCKOUT * 2u32.pow(exponent)).min(MAX_LOCKOUT);
+        failures.locked_until = Some(now + lockout);
+        Some(lockout)
+    }
+
+    pub fn succeeded(&mut self, addr: &IpAddr) {
+        self.failures.remove(addr);
+    }
+
+    pub fn locked_out_addresses(&self, now: Instant) -> usize {
+        self.failures
+            .values()
+            .filter(|f| f.locked_until.map(|until| until > now).unwrap_or(false))
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lockout_grows_exponentially() {
+        let mut lockout = AuthLockout::default();
+        let addr: IpAddr = [10, 0, 0, 1].into();
+        let other: IpAddr = [10, 0, 0, 2].into();
+        let now = Instant::now();
+
+        for _ in 0..FREE_ATTEMPTS {
+            assert_eq!(lockout.failed(addr, now), None);
+        }
+        assert_eq!(lockout.locked_out(&addr, now), None);
+
+        assert_eq!(lockout.failed(addr, now), Some(Duration::from_secs(1)));
+        assert_eq!(lockout.failed(addr, now), Some(Duration::from_secs(2)));
+        assert_eq!(lockout.failed(addr, now), Some(Duration::from_secs(4)));
+        assert_eq!(
+            lockout.locked_out(&addr, now + Duration::from_secs(1)),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            lockout.locked_out(&addr, now + Duration::from_secs(4)),
+            None
+        );
+        assert_eq!(lockout.locked_out(&other, now), None);
+        assert_eq!(lockout.locked_out_addresses(now), 1);
+
+        for _ in 0..100 {
+            lockout.failed(addr, now);
+        }
+        assert_eq!(lockout.locked_out(&addr, now), Some(MAX_LOCKOUT));
+
+        lockout.succeeded(&addr);
+        assert_eq!(lockout.locked_out(&addr, now), None);
+    }
+}
@@ -0,0 +1,202 @@
+/*
+ *  Worterbuch locks module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{expiry::Expiries, worterbuch::patterns_overlap};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+use tokio::sync::oneshot;
+use worterbuch_common::{error::WorterbuchResult, Key, KeySegment};
+
+/// Keys that clients locked for exclusive write access. Unlike a reservation, a lock only lasts as
+/// long as its lease unless it is renewed, and clients trying to lock a key that is already
+/// locked queue up to get it next.
+#[derive(Debug, Default)]
+pub struct Locks {
+    held: HashMap<Key, Lease>,
+    waiting: HashMap<Key, VecDeque<Waiter>>,
+    leases: Expiries,
+}
+
+#[derive(Debug)]
+struct Lease {
+    path: Vec<KeySegment>,
+    client_id: String,
+}
+
+/// A client waiting for a locked key, notified through `tx` once the lock is granted.
+#[derive(Debug)]
+pub struct Waiter {
+    pub client_id: String,
+    pub lease: Duration,
+    pub tx: oneshot::Sender<WorterbuchResult<()>>,
+}
+
+impl Locks {
+    /// Grants a client the lock on a key, or renews its lease if the client already holds it.
+    /// Fails with the ID of the holding client if the key is locked by another client.
+    pub fn acquire(
+        &mut self,
+        key: &str,
+        client_id: &str,
+        lease: Duration,
+        now: Instant,
+    ) -> Result<(), String> {
+        match self.held.get(key) {
+            Some(held) if held.client_id != client_id => return Err(held.client_id.clone()),
+            Some(_) => (),
+            None => {
+                self.held.insert(
+                    key.to_owned(),
+                    Lease {
+                        path: KeySegment::parse(key),
+                        client_id: client_id.to_owned(),
+                    },
+                );
+            }
+        }
+        self.leases.schedule(key.to_owned(), now + lease);
+        Ok(())
+    }
+
+    /// Queues a client to get the lock on a key once it is released.
+    pub fn enqueue(&mut self, key: Key, waiter: Waiter) {
+        self.waiting.entry(key).or_default().push_back(waiter);
+    }
+
+    /// Releases a client's lock on a key, returns `false` if the client does not hold it.
+    pub fn release(&mut self, key: &str, client_id: &str) -> bool {
+        if self.holder(key) != Some(client_id) {
+            return false;
+        }
+        self.held.remove(key);
+        self.leases.cancel(key);
+        true
+    }
+
+    /// Releases all locks of a client and drops the requests it is waiting on. Returns the
+    /// released keys.
+    pub fn release_all(&mut self, client_id: &str) -> Vec<Key> {
+        for waiters in self.waiting.values_mut() {
+            waiters.retain(|w| w.client_id != client_id);
+        }
+        self.waiting.retain(|_, waiters| !waiters.is_empty());
+
+        let released: Vec<Key> = self
+            .held
+            .iter()
+            .filter(|(_, lease)| lease.client_id == client_id)
+            .map(|(key, _)| key.to_owned())
+            .collect();
+        for key in &released {
+            self.held.remove(key);
+            self.leases.cancel(key);
+        }
+        released
+    }
+
+    /// Removes and returns the next client waiting for a key, skipping those that gave up waiting.
+    pub fn next_waiter(&mut self, key: &str) -> Option<Waiter> {
+        let waiters = self.waiting.get_mut(key)?;
+        let mut next = None;
+        while let Some(waiter) = waiters.pop_front() {
+            if !waiter.tx.is_closed() {
+                next = Some(waiter);
+                break;
+            }
+        }
+        if waiters.is_empty() {
+            self.waiting.remove(key);
+        }
+        next
+    }
+
+    pub fn holder(&self, key: &str) -> Option<&str> {
+        self.held.get(key).map(|lease| lease.client_id.as_str())
+    }
+
+    /// Returns the key locked by another client that matches the given key or pattern, if any.
+    pub fn conflict(&self, path: &[KeySegment], client_id: &str) -> Option<&str> {
+        self.held
+            .iter()
+            .find(|(_, lease)| lease.client_id != client_id && patterns_overlap(&lease.path, path))
+            .map(|(key, _)| key.as_str())
+    }
+
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.leases.next()
+    }
+
+    /// Releases all locks whose lease has passed and returns their keys.
+    pub fn take_expired(&mut self, now: Instant) -> Vec<Key> {
+        let expired = self.leases.take_due(now);
+        for key in &expired {
+            self.held.remove(key);
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn locks_are_exclusive_and_handed_to_waiters_in_order() {
+        let now = Instant::now();
+        let lease = Duration::from_secs(10);
+        let mut locks = Locks::default();
+
+        locks.acquire("a/b", "1", lease, now).unwrap();
+        assert_eq!(locks.acquire("a/b", "2", lease, now), Err("1".to_owned()));
+        assert_eq!(locks.conflict(&KeySegment::parse("a/#"), "2"), Some("a/b"));
+        assert_eq!(locks.conflict(&KeySegment::parse("a/#"), "1"), None);
+
+        let (tx2, rx2) = oneshot::channel();
+        let (tx3, _rx3) = oneshot::channel();
+        let (tx4, rx4) = oneshot::channel();
+        for (client_id, tx) in [("2", tx2), ("3", tx3), ("4", tx4)] {
+            let client_id = client_id.to_owned();
+            locks.enqueue(
+                "a/b".to_owned(),
+                Waiter {
+                    client_id,
+                    lease,
+                    tx,
+                },
+            );
+        }
+        drop(rx2);
+        assert_eq!(locks.release_all("3"), Vec::<Key>::new());
+
+        assert!(!locks.release("a/b", "2"));
+        assert!(locks.release("a/b", "1"));
+        let next = locks.next_waiter("a/b").unwrap();
+        assert_eq!(next.client_id, "4");
+        assert!(locks.next_waiter("a/b").is_none());
+        drop(rx4);
+
+        locks.acquire("a/b", "4", lease, now).unwrap();
+        assert_eq!(locks.next_expiry(), Some(now + lease));
+        assert!(locks.take_expired(now).is_empty());
+        assert_eq!(locks.take_expired(now + lease), vec!["a/b"]);
+        assert_eq!(locks.holder("a/b"), None);
+    }
+}
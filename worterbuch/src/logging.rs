@@ -0,0 +1,360 @@
+/*
+ *  Worterbuch log shipping module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{
+    collections::VecDeque,
+    env,
+    fmt::{self, Write},
+    fs, io,
+    net::UdpSocket,
+    process,
+    str::FromStr,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{
+    field::{Field, Visit},
+    level_filters::LevelFilter,
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+    filter::Targets,
+    fmt as fmt_layer,
+    layer::{Context, Layer, SubscriberExt},
+    util::SubscriberInitExt,
+};
+use worterbuch_common::error::ConfigError;
+
+const APP_NAME: &str = "worterbuch";
+const DEFAULT_SYSLOG_ADDR: &str = "127.0.0.1:514";
+#[cfg(unix)]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+/// Number of log messages kept in memory for support bundles.
+const RECENT_LOGS_CAPACITY: usize = 1000;
+
+static RECENT_LOGS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// The most recent log messages that passed the log level filter, oldest first.
+pub fn recent_logs() -> Vec<String> {
+    RECENT_LOGS
+        .lock()
+        .map(|logs| logs.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Where log messages are shipped to in addition to stdout.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogSink {
+    /// RFC 5424 messages over UDP to the given address
+    Syslog(String),
+    /// systemd's journal, using its native protocol
+    Journald,
+}
+
+impl FromStr for LogSink {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "journald" => Ok(LogSink::Journald),
+            "syslog" => Ok(LogSink::Syslog(DEFAULT_SYSLOG_ADDR.to_owned())),
+            s => match s.strip_prefix("syslog://") {
+                Some(addr) if !addr.is_empty() => Ok(LogSink::Syslog(addr.to_owned())),
+                _ => Err(ConfigError::InvalidLogSink(s.to_owned())),
+            },
+        }
+    }
+}
+
+/// Installs the global log subscriber, which writes to stdout and, if configured, to the given
+/// sink. Log levels are filtered according to `RUST_LOG`.
+pub fn init(sink: Option<&LogSink>) -> anyhow::Result<()> {
+    let targets = env::var("RUST_LOG")
+        .ok()
+        .and_then(|var| Targets::from_str(&var).ok())
+        .unwrap_or_else(|| Targets::new().with_default(LevelFilter::INFO));
+    let sink = sink.map(SinkLayer::new).transpose()?;
+
+    tracing_subscriber::registry()
+        .with(fmt_layer::layer())
+        .with(RecentLogsLayer)
+        .with(sink)
+        .with(targets)
+        .try_init()?;
+
+    Ok(())
+}
+
+enum Transport {
+    Syslog(UdpSocket),
+    #[cfg(unix)]
+    Journald(std::os::unix::net::UnixDatagram),
+}
+
+struct SinkLayer {
+    transport: Transport,
+    hostname: String,
+    pid: u32,
+}
+
+impl SinkLayer {
+    fn new(sink: &LogSink) -> io::Result<Self> {
+        let transport = match sink {
+            LogSink::Syslog(addr) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(addr)?;
+                Transport::Syslog(socket)
+            }
+            #[cfg(unix)]
+            LogSink::Journald => {
+                let socket = std::os::unix::net::UnixDatagram::unbound()?;
+                socket.connect(JOURNALD_SOCKET)?;
+                Transport::Journald(socket)
+            }
+            #[cfg(not(unix))]
+            LogSink::Journald => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "journald is only available on unix systems",
+                ))
+            }
+        };
+        let hostname = fs::read_to_string("/etc/hostname")
+            .map(|it| it.trim().to_owned())
+            .ok()
+            .filter(|it| !it.is_empty())
+            .or_else(|| env::var("HOSTNAME").ok())
+            .unwrap_or_else(|| "-".to_owned());
+        Ok(SinkLayer {
+            transport,
+            hostname,
+            pid: process::id(),
+        })
+    }
+}
+
+impl<S: Subscriber> Layer<S> for SinkLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let level = *event.metadata().level();
+        let target = fields
+            .log_target
+            .take()
+            .unwrap_or_else(|| event.metadata().target().to_owned());
+
+        let res = match &self.transport {
+            Transport::Syslog(socket) => {
+                let msg = syslog_message(level, &self.hostname, self.pid, &target, &fields);
+                socket.send(msg.as_bytes()).map(|_| ())
+            }
+            #[cfg(unix)]
+            Transport::Journald(socket) => socket
+                .send(&journald_message(level, &target, &fields))
+                .map(|_| ()),
+        };
+
+        if let Err(e) = res {
+            // logging the error would only cause more errors
+            eprintln!("Could not ship log message: {e}");
+        }
+    }
+}
+
+struct RecentLogsLayer;
+
+impl<S: Subscriber> Layer<S> for RecentLogsLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let target = fields
+            .log_target
+            .take()
+            .unwrap_or_else(|| event.metadata().target().to_owned());
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let line = format!(
+            "{timestamp} {} {target}: {}",
+            event.metadata().level(),
+            fields.message
+        );
+
+        if let Ok(mut logs) = RECENT_LOGS.lock() {
+            if logs.len() >= RECENT_LOGS_CAPACITY {
+                logs.pop_front();
+            }
+            logs.push_back(line);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Fields {
+    message: String,
+    log_target: Option<String>,
+    structured: Vec<(&'static str, String)>,
+}
+
+impl Fields {
+    fn record(&mut self, field: &Field, value: String) {
+        match field.name() {
+            "message" => self.message = value,
+            "log.target" => self.log_target = Some(value),
+            name if name.starts_with("log.") => (),
+            name => self.structured.push((name, value)),
+        }
+    }
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_owned());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record(field, format!("{value:?}"));
+    }
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// Formats an RFC 5424 message. Structured fields of the event end up in its structured data.
+fn syslog_message(level: Level, hostname: &str, pid: u32, target: &str, fields: &Fields) -> String {
+    // facility 'user-level messages'
+    let pri = 8 + severity(level);
+    let mut msg = format!("<{pri}>1 - {hostname} {APP_NAME} {pid} - ");
+    if fields.structured.is_empty() {
+        msg.push('-');
+    } else {
+        msg.push_str("[fields@32473");
+        for (name, value) in &fields.structured {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace(']', "\\]");
+            write!(msg, " {name}=\"{value}\"").ok();
+        }
+        msg.push(']');
+    }
+    write!(msg, " {target}: {}", fields.message).ok();
+    msg
+}
+
+/// Encodes a message in journald's native protocol. Field names are upper cased, values that
+/// contain newlines are length prefixed.
+#[cfg(unix)]
+fn journald_message(level: Level, target: &str, fields: &Fields) -> Vec<u8> {
+    let mut msg = Vec::new();
+    let mut field = |name: &str, value: &str| {
+        msg.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            msg.push(b'\n');
+            msg.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            msg.push(b'=');
+        }
+        msg.extend_from_slice(value.as_bytes());
+        msg.push(b'\n');
+    };
+    field("MESSAGE", &fields.message);
+    field("PRIORITY", &severity(level).to_string());
+    field("SYSLOG_IDENTIFIER", APP_NAME);
+    field("TARGET", target);
+    for (name, value) in &fields.structured {
+        let name: String = name
+            .trim_start_matches('_')
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        if !name.is_empty() {
+            field(&name, value);
+        }
+    }
+    msg
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fields() -> Fields {
+        Fields {
+            message: "Value set".to_owned(),
+            log_target: None,
+            structured: vec![
+                ("client_id", "1234".to_owned()),
+                ("key", "hello/\"world\"".to_owned()),
+            ],
+        }
+    }
+
+    #[test]
+    fn log_sinks_are_parsed() {
+        assert_eq!("journald".parse::<LogSink>().ok(), Some(LogSink::Journald));
+        assert_eq!(
+            "syslog".parse::<LogSink>().ok(),
+            Some(LogSink::Syslog(DEFAULT_SYSLOG_ADDR.to_owned()))
+        );
+        assert_eq!(
+            "syslog://logs.local:1514".parse::<LogSink>().ok(),
+            Some(LogSink::Syslog("logs.local:1514".to_owned()))
+        );
+        assert!("syslog://".parse::<LogSink>().is_err());
+        assert!("stdout".parse::<LogSink>().is_err());
+    }
+
+    #[test]
+    fn syslog_messages_contain_structured_data() {
+        let msg = syslog_message(Level::WARN, "host", 42, "worterbuch::server", &fields());
+        assert_eq!(
+            msg,
+            "<12>1 - host worterbuch 42 - [fields@32473 client_id=\"1234\" key=\"hello/\\\"world\\\"\"] worterbuch::server: Value set"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn journald_messages_contain_structured_fields() {
+        let mut fields = fields();
+        fields.message = "multi\nline".to_owned();
+        let msg = journald_message(Level::INFO, "worterbuch", &fields);
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&10u64.to_le_bytes());
+        expected.extend_from_slice(b"multi\nline\n");
+        expected.extend_from_slice(
+            b"PRIORITY=6\nSYSLOG_IDENTIFIER=worterbuch\nTARGET=worterbuch\nCLIENT_ID=1234\nKEY=hello/\"world\"\n",
+        );
+        assert_eq!(msg, expected);
+    }
+}
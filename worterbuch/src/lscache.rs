@@ -0,0 +1,140 @@
+/*
+ *  Worterbuch ls cache module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use hashlink::LinkedHashMap;
+use worterbuch_common::RegularKeySegment;
+
+/// Maximum number of parents whose children are kept in the cache.
+const CAPACITY: usize = 1024;
+
+/// Hit/miss counters of the ls cache, published under `$SYS/stats/lscache`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LsCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+impl LsCacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+/// Caches the children of recently listed parents. Parents that are listed over and over again
+/// (e.g. by explorer UIs or `ls` subscriptions) stay in the cache, the least recently listed parent
+/// is evicted once the cache is full. Entries must be invalidated whenever a child of the parent is
+/// added or removed.
+#[derive(Debug, Default)]
+pub struct LsCache {
+    entries: LinkedHashMap<String, Vec<RegularKeySegment>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl LsCache {
+    pub fn get(&mut self, path: &[impl AsRef<str>]) -> Option<Vec<RegularKeySegment>> {
+        let key = cache_key(path);
+        match self.entries.to_back(&key) {
+            Some(children) => {
+                self.hits += 1;
+                Some(children.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, path: &[impl AsRef<str>], children: Vec<RegularKeySegment>) {
+        self.entries.replace(cache_key(path), children);
+        while self.entries.len() > CAPACITY {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Drops the cached children of the given parent.
+    pub fn invalidate(&mut self, path: &[impl AsRef<str>]) {
+        if !self.entries.is_empty() {
+            self.entries.remove(&cache_key(path));
+        }
+    }
+
+    /// Drops the cached children of all ancestors of the given key, including the root.
+    pub fn invalidate_ancestors(&mut self, path: &[impl AsRef<str>]) {
+        for i in 0..path.len() {
+            self.invalidate(&path[..i]);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn stats(&self) -> LsCacheStats {
+        LsCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+        }
+    }
+}
+
+fn cache_key(path: &[impl AsRef<str>]) -> String {
+    path.iter()
+        .map(AsRef::as_ref)
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cache_counts_hits_and_evicts_least_recently_listed() {
+        let mut cache = LsCache::default();
+
+        assert_eq!(cache.get(&["a"]), None);
+        cache.insert(&["a"], vec!["b".to_owned()]);
+        assert_eq!(cache.get(&["a"]), Some(vec!["b".to_owned()]));
+        assert_eq!(cache.get(&["a"]), Some(vec!["b".to_owned()]));
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.entries, 1);
+
+        cache.invalidate_ancestors(&["a", "b"]);
+        assert_eq!(cache.get(&["a"]), None);
+
+        for i in 0..=CAPACITY {
+            cache.insert(&[i.to_string()], Vec::new());
+        }
+        assert_eq!(cache.stats().entries, CAPACITY);
+        assert_eq!(cache.get(&["0"]), None);
+        assert_eq!(cache.get(&[CAPACITY.to_string()]), Some(Vec::new()));
+    }
+}
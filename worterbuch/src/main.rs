@@ -17,13 +17,17 @@
  *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use anyhow::Result;
-use clap::Parser;
-use std::time::Duration;
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::{path::PathBuf, time::Duration};
 #[cfg(all(not(target_env = "msvc"), feature = "jemalloc"))]
 use tikv_jemallocator::Jemalloc;
+use tokio::runtime;
 use tokio_graceful_shutdown::Toplevel;
-use worterbuch::run_worterbuch;
+use worterbuch::{
+    check_dump, logging, run_worterbuch_local, run_worterbuch_with_config, Config, PROTOCOL_VERSION,
+};
+use worterbuch_common::schema;
 
 #[cfg(all(not(target_env = "msvc"), feature = "jemalloc"))]
 #[global_allocator]
@@ -31,22 +35,117 @@ static GLOBAL: Jemalloc = Jemalloc;
 
 #[derive(Parser)]
 #[command(author, version, about = "An in-memory data base / message broker hybrid", long_about = None)]
-struct Args {}
+struct Args {
+    /// Print the schema of all protocol messages to stdout and exit
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "json-schema")]
+    dump_schema: Option<SchemaFormat>,
+    /// Env file to load the configuration from instead of .env in the working directory.
+    /// Variables that are already set in the environment take precedence over the file
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check that the configuration is valid and exit
+    ValidateConfig,
+    /// Check a persistence dump for integrity and exit
+    CheckDump {
+        /// Dump file to check, defaults to the snapshot in the configured data dir
+        file: Option<PathBuf>,
+    },
+    /// Print the configuration resolved from environment, config file and defaults and exit
+    PrintEffectiveConfig,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SchemaFormat {
+    JsonSchema,
+    Typescript,
+    Asyncapi,
+}
 
-#[tokio::main()]
-async fn main() -> Result<()> {
-    dotenv::dotenv().ok();
+fn main() -> Result<()> {
+    let args: Args = Args::parse();
+    match &args.config {
+        Some(path) => {
+            dotenv::from_path(path)
+                .map_err(|e| anyhow!("could not load config file {}: {e}", path.display()))?;
+        }
+        None => {
+            dotenv::dotenv().ok();
+        }
+    }
     if std::env::var_os("RUST_LOG").is_none() {
         std::env::set_var("RUST_LOG", "info");
     }
-    tracing_subscriber::fmt::init();
-    let _args: Args = Args::parse();
 
-    Toplevel::new()
-        .start("worterbuch", run_worterbuch)
-        .catch_signals()
-        .handle_shutdown_requests(Duration::from_millis(1000))
-        .await?;
+    if let Some(format) = args.dump_schema {
+        let schema = match format {
+            SchemaFormat::JsonSchema => serde_json::to_string_pretty(&schema::json_schema())?,
+            SchemaFormat::Typescript => schema::typescript(),
+            SchemaFormat::Asyncapi => serde_json::to_string_pretty(&schema::asyncapi(
+                env!("CARGO_PKG_VERSION"),
+                PROTOCOL_VERSION,
+                "/ws",
+            ))?,
+        };
+        println!("{schema}");
+        return Ok(());
+    }
+
+    let local_runtime = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let config = local_runtime.block_on(Config::new())?;
 
+    if let Some(command) = args.command {
+        return local_runtime.block_on(run_command(command, config));
+    }
+
+    logging::init(config.log_sink.as_ref())?;
+
+    if config.single_threaded {
+        log::info!("Running in single threaded mode.");
+        return run_worterbuch_local(&local_runtime, config);
+    }
+    drop(local_runtime);
+
+    runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(async move {
+            Toplevel::new()
+                .start("worterbuch", move |subsys| {
+                    run_worterbuch_with_config(subsys, config)
+                })
+                .catch_signals()
+                .handle_shutdown_requests(Duration::from_millis(1000))
+                .await?;
+            Ok(())
+        })
+}
+
+async fn run_command(command: Command, config: Config) -> Result<()> {
+    match command {
+        // loading the config already validated it
+        Command::ValidateConfig => println!("Configuration is valid."),
+        Command::CheckDump { file } => {
+            let check = check_dump(file.as_deref(), config).await?;
+            let checksum = if check.checksum_verified {
+                "checksum verified"
+            } else {
+                "no checksum file found"
+            };
+            println!(
+                "{} is intact: {} values, {checksum}.",
+                check.path.display(),
+                check.values
+            );
+        }
+        Command::PrintEffectiveConfig => println!("{:#?}", config.redacted()),
+    }
     Ok(())
 }
@@ -0,0 +1,289 @@
+/*
+ *  Worterbuch metrics module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{server::common::CloneableWbApi, stats::VERSION};
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    select,
+    time::{interval, MissedTickBehavior},
+};
+use tokio_graceful_shutdown::SubsystemHandle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricsFormat {
+    /// Prometheus text exposition format, as accepted by the Prometheus push gateway.
+    PushGateway,
+    /// OTLP over HTTP with JSON encoding, as accepted by OpenTelemetry collectors.
+    Otlp,
+}
+
+/// Configures periodic pushing of metrics to an external collector. Only plain `http://` URLs
+/// are supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetricsPush {
+    pub url: String,
+    pub interval: Duration,
+    pub format: MetricsFormat,
+}
+
+/// Messages received from clients since the server started, by message type.
+static MESSAGES_RECEIVED: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+/// Duration of the last persistence run in milliseconds, `u64::MAX` if there was none yet.
+static PERSISTENCE_MILLIS: AtomicU64 = AtomicU64::new(u64::MAX);
+
+pub(crate) fn message_received(kind: &'static str) {
+    if let Ok(mut messages) = MESSAGES_RECEIVED.lock() {
+        *messages.entry(kind).or_default() += 1;
+    }
+}
+
+pub(crate) fn persisted(duration: Duration) {
+    PERSISTENCE_MILLIS.store(duration.as_millis() as u64, Ordering::Relaxed);
+}
+
+pub(crate) fn messages_received() -> Vec<(&'static str, u64)> {
+    MESSAGES_RECEIVED
+        .lock()
+        .map(|messages| messages.iter().map(|(k, v)| (*k, *v)).collect())
+        .unwrap_or_default()
+}
+
+pub(crate) fn persistence_millis() -> Option<u64> {
+    Some(PERSISTENCE_MILLIS.load(Ordering::Relaxed)).filter(|millis| *millis != u64::MAX)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Metrics {
+    pub clients: usize,
+    pub subscriptions: usize,
+    pub ls_subscriptions: usize,
+    pub values: usize,
+    pub uptime_secs: u64,
+    /// Requests queued for the store that it has not picked up yet.
+    pub channel_backlog: usize,
+    pub persistence_millis: Option<u64>,
+    pub messages_received: Vec<(&'static str, u64)>,
+}
+
+impl Metrics {
+    fn gauges(&self) -> Vec<(&'static str, &'static str, u64)> {
+        let mut gauges = vec![
+            (
+                "clients",
+                "Number of connected clients",
+                self.clients as u64,
+            ),
+            (
+                "subscriptions",
+                "Number of active subscriptions",
+                self.subscriptions as u64,
+            ),
+            (
+                "ls_subscriptions",
+                "Number of active ls subscriptions",
+                self.ls_subscriptions as u64,
+            ),
+            ("values", "Number of stored values", self.values as u64),
+            (
+                "uptime_seconds",
+                "Server uptime in seconds",
+                self.uptime_secs,
+            ),
+            (
+                "channel_backlog",
+                "Number of requests waiting to be processed by the store",
+                self.channel_backlog as u64,
+            ),
+        ];
+        if let Some(millis) = self.persistence_millis {
+            gauges.push((
+                "persistence_duration_milliseconds",
+                "Duration of the last persistence run in milliseconds",
+                millis,
+            ));
+        }
+        gauges
+    }
+
+    pub fn to_prometheus_text(&self) -> String {
+        let mut text = String::new();
+        for (name, help, value) in self.gauges() {
+            text.push_str(&format!(
+                "# HELP worterbuch_{name} {help}\n# TYPE worterbuch_{name} gauge\nworterbuch_{name} {value}\n"
+            ));
+        }
+        if !self.messages_received.is_empty() {
+            text.push_str("# HELP worterbuch_messages_received_total Number of messages received from clients\n# TYPE worterbuch_messages_received_total counter\n");
+            for (kind, count) in &self.messages_received {
+                text.push_str(&format!(
+                    "worterbuch_messages_received_total{{type=\"{kind}\"}} {count}\n"
+                ));
+            }
+        }
+        text
+    }
+
+    pub fn to_otlp_json(&self) -> Value {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            .to_string();
+        let metrics: Vec<Value> = self
+            .gauges()
+            .iter()
+            .map(|(name, help, value)| {
+                json!({
+                    "name": format!("worterbuch.{name}"),
+                    "description": help,
+                    "gauge": {
+                        "dataPoints": [{ "asInt": value.to_string(), "timeUnixNano": now }]
+                    }
+                })
+            })
+            .collect();
+        json!({
+            "resourceMetrics": [{
+                "resource": {
+                    "attributes": [{ "key": "service.name", "value": { "stringValue": "worterbuch" } }]
+                },
+                "scopeMetrics": [{
+                    "scope": { "name": "worterbuch", "version": VERSION },
+                    "metrics": metrics
+                }]
+            }]
+        })
+    }
+}
+
+pub(crate) async fn push_periodically(
+    worterbuch: CloneableWbApi,
+    config: MetricsPush,
+    subsys: SubsystemHandle,
+) -> Result<()> {
+    log::info!(
+        "Pushing metrics to {} every {} seconds.",
+        config.url,
+        config.interval.as_secs()
+    );
+
+    let mut interval = interval(config.interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        select! {
+            _ = interval.tick() => {
+                let metrics = worterbuch.metrics().await?;
+                if let Err(e) = push(&config, &metrics).await {
+                    log::warn!("Could not push metrics to {}: {e}", config.url);
+                }
+            },
+            _ = subsys.on_shutdown_requested() => break,
+        }
+    }
+
+    Ok(())
+}
+
+async fn push(config: &MetricsPush, metrics: &Metrics) -> Result<()> {
+    let (content_type, body) = match config.format {
+        MetricsFormat::PushGateway => ("text/plain; version=0.0.4", metrics.to_prometheus_text()),
+        MetricsFormat::Otlp => ("application/json", metrics.to_otlp_json().to_string()),
+    };
+    http_post(&config.url, content_type, &body).await
+}
+
+async fn http_post(url: &str, content_type: &str, body: &str) -> Result<()> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| anyhow!("unsupported metrics push URL: {url}"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let addr = if authority.contains(':') {
+        authority.to_owned()
+    } else {
+        format!("{authority}:80")
+    };
+
+    let mut stream = TcpStream::connect(addr).await?;
+    let request = format!(
+        "POST {path} HTTP/1.1\r\nHost: {authority}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .ok_or_else(|| anyhow!("invalid HTTP response"))?;
+    if status.starts_with('2') {
+        Ok(())
+    } else {
+        Err(anyhow!("server responded with status {status}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn metrics_are_rendered_in_prometheus_format() {
+        let metrics = Metrics {
+            clients: 2,
+            values: 10,
+            ..Default::default()
+        };
+        let text = metrics.to_prometheus_text();
+        assert!(text.contains("# TYPE worterbuch_clients gauge\nworterbuch_clients 2\n"));
+        assert!(text.contains("worterbuch_values 10\n"));
+        assert!(text.contains("worterbuch_subscriptions 0\n"));
+        assert!(!text.contains("persistence_duration"));
+    }
+
+    #[test]
+    fn message_counts_are_rendered_as_labelled_counters() {
+        let metrics = Metrics {
+            messages_received: vec![("get", 3), ("set", 5)],
+            persistence_millis: Some(12),
+            ..Default::default()
+        };
+        let text = metrics.to_prometheus_text();
+        assert!(text.contains("# TYPE worterbuch_messages_received_total counter\n"));
+        assert!(text.contains("worterbuch_messages_received_total{type=\"set\"} 5\n"));
+        assert!(text.contains("worterbuch_persistence_duration_milliseconds 12\n"));
+    }
+}
@@ -0,0 +1,151 @@
+/*
+ *  Worterbuch server mode module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{server::common::CloneableWbApi, INTERNAL_CLIENT_ID};
+use anyhow::Result;
+use serde_json::json;
+use std::{fmt, str::FromStr, sync::Arc};
+use tokio::{select, sync::watch};
+use tokio_graceful_shutdown::SubsystemHandle;
+use uuid::Uuid;
+use worterbuch_common::{
+    error::ConfigError, topic, Key, PStateEvent, SYSTEM_TOPIC_MODE, SYSTEM_TOPIC_ROOT,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    ReadWrite,
+    ReadOnly,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::ReadWrite => "read-write".fmt(f),
+            Mode::ReadOnly => "read-only".fmt(f),
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().trim() {
+            "read-write" => Ok(Mode::ReadWrite),
+            "read-only" => Ok(Mode::ReadOnly),
+            _ => Err(ConfigError::InvalidServerMode(s.to_owned())),
+        }
+    }
+}
+
+/// Maintenance mode for migrations and backups. While the server is read-only, every set,
+/// delete, pdelete and publish of a client is rejected with a `ServerReadOnly` error, while
+/// gets and subscriptions keep working.
+///
+/// The current mode is published at `$SYS/mode`, writing `read-write` or `read-only` to that key
+/// switches modes at runtime. The key itself stays writable in read-only mode.
+#[derive(Debug, Clone)]
+pub struct ServerMode {
+    mode: Arc<watch::Sender<Mode>>,
+}
+
+impl ServerMode {
+    pub fn new(mode: Mode) -> Self {
+        let (mode, _) = watch::channel(mode);
+        Self {
+            mode: Arc::new(mode),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        *self.mode.borrow()
+    }
+
+    pub fn set_mode(&self, mode: Mode) {
+        self.mode.send_replace(mode);
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.mode() == Mode::ReadOnly
+    }
+}
+
+impl PartialEq for ServerMode {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.mode, &other.mode)
+    }
+}
+
+pub(crate) fn mode_key() -> Key {
+    topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_MODE)
+}
+
+/// Publishes the server mode and applies mode changes written to `$SYS/mode`.
+pub(crate) async fn run(
+    worterbuch: CloneableWbApi,
+    mode: ServerMode,
+    subsys: SubsystemHandle,
+) -> Result<()> {
+    worterbuch
+        .set(
+            mode_key(),
+            json!(mode.mode().to_string()),
+            INTERNAL_CLIENT_ID.to_owned(),
+        )
+        .await?;
+
+    let (mut events, _) = worterbuch
+        .psubscribe(Uuid::new_v4(), 0, mode_key(), true, true)
+        .await?;
+
+    loop {
+        select! {
+            Some(event) = events.recv() => {
+                let PStateEvent::KeyValuePairs(kvps) = event else {
+                    continue;
+                };
+                for kvp in kvps {
+                    match kvp.value.as_str().map(str::parse::<Mode>) {
+                        Some(Ok(m)) => {
+                            if m != mode.mode() {
+                                log::info!("Switching server to {m} mode.");
+                                mode.set_mode(m);
+                            }
+                        }
+                        _ => {
+                            log::warn!("Ignoring invalid server mode {}", kvp.value);
+                            worterbuch
+                                .set(
+                                    mode_key(),
+                                    json!(mode.mode().to_string()),
+                                    INTERNAL_CLIENT_ID.to_owned(),
+                                )
+                                .await?;
+                        }
+                    }
+                }
+            },
+            _ = subsys.on_shutdown_requested() => break,
+        }
+    }
+
+    Ok(())
+}
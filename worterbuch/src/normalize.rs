@@ -0,0 +1,133 @@
+/*
+ *  Worterbuch value normalization module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use serde_json::{Map, Number, Value};
+
+/// Brings a JSON value into a canonical form, so that values that only differ in the way their
+/// producer serialized them compare as equal: object keys are sorted and numbers are written
+/// without exponents, trailing fractional zeros or negative zero (e.g. `1.0`, `1e0` and `1`
+/// all become `1`).
+pub fn normalize(value: Value) -> Value {
+    match value {
+        Value::Number(n) => Value::Number(normalize_number(n)),
+        Value::Array(values) => Value::Array(values.into_iter().map(normalize).collect()),
+        Value::Object(obj) => {
+            let mut entries: Vec<(String, Value)> = obj.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            let mut normalized = Map::new();
+            for (key, value) in entries {
+                normalized.insert(key, normalize(value));
+            }
+            Value::Object(normalized)
+        }
+        other => other,
+    }
+}
+
+fn normalize_number(n: Number) -> Number {
+    canonical_decimal(&n.to_string())
+        .and_then(|it| serde_json::from_str(&it).ok())
+        .unwrap_or(n)
+}
+
+/// Rewrites a JSON number literal as a plain decimal without exponent and without redundant zeros.
+fn canonical_decimal(literal: &str) -> Option<String> {
+    let (negative, literal) = match literal.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, literal),
+    };
+    let (mantissa, exponent) = match literal.find(['e', 'E']) {
+        Some(i) => (&literal[..i], literal[i + 1..].parse::<i64>().ok()?),
+        None => (literal, 0),
+    };
+    let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+
+    let digits: String = format!("{int_part}{frac_part}");
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    // position of the decimal point within `digits`
+    let point = int_part.len() as i64 + exponent;
+    // refuse to expand absurd exponents into huge strings
+    if point.unsigned_abs() > 4096 {
+        return None;
+    }
+
+    let (int_digits, frac_digits) = if point <= 0 {
+        (
+            String::new(),
+            "0".repeat(point.unsigned_abs() as usize) + &digits,
+        )
+    } else if point as usize >= digits.len() {
+        (
+            digits.clone() + &"0".repeat(point as usize - digits.len()),
+            String::new(),
+        )
+    } else {
+        let (int_digits, frac_digits) = digits.split_at(point as usize);
+        (int_digits.to_owned(), frac_digits.to_owned())
+    };
+
+    let int_digits = int_digits.trim_start_matches('0');
+    let frac_digits = frac_digits.trim_end_matches('0');
+    let int_digits = if int_digits.is_empty() {
+        "0"
+    } else {
+        int_digits
+    };
+
+    let mut canonical = String::new();
+    if negative && (int_digits != "0" || !frac_digits.is_empty()) {
+        canonical.push('-');
+    }
+    canonical.push_str(int_digits);
+    if !frac_digits.is_empty() {
+        canonical.push('.');
+        canonical.push_str(frac_digits);
+    }
+    Some(canonical)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn numbers_are_normalized() {
+        assert_eq!(canonical_decimal("1.0").as_deref(), Some("1"));
+        assert_eq!(canonical_decimal("1e0").as_deref(), Some("1"));
+        assert_eq!(canonical_decimal("1.50E2").as_deref(), Some("150"));
+        assert_eq!(canonical_decimal("-0.0").as_deref(), Some("0"));
+        assert_eq!(canonical_decimal("0.0125e-1").as_deref(), Some("0.00125"));
+        assert_eq!(canonical_decimal("-12.340").as_deref(), Some("-12.34"));
+        assert_eq!(canonical_decimal("1e99999"), None);
+    }
+
+    #[test]
+    fn values_are_normalized() {
+        let a: Value = serde_json::from_str(r#"{"b":[1.0,2e1],"a":{"y":-0.0,"x":0.5}}"#).unwrap();
+        let b: Value = serde_json::from_str(r#"{"a":{"x":0.50,"y":0},"b":[1,20]}"#).unwrap();
+        assert_eq!(normalize(a), normalize(b));
+        assert_eq!(
+            normalize(json!({"b": 1.0, "a": "1.0"})).to_string(),
+            r#"{"a":"1.0","b":1}"#
+        );
+    }
+}
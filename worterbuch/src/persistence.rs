@@ -17,17 +17,23 @@
  *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::{config::Config, server::common::CloneableWbApi, worterbuch::Worterbuch};
+use crate::{config::Config, metrics, server::common::CloneableWbApi, wal, worterbuch::Worterbuch};
 use anyhow::Result;
 use sha2::{Digest, Sha256};
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Instant,
+};
 use tokio::{
     fs::{self, File},
     io::AsyncWriteExt,
     select,
+    sync::Mutex,
     time::interval,
 };
 use tokio_graceful_shutdown::SubsystemHandle;
+use worterbuch_common::{PersistenceEvent, ServerEvent};
 
 pub(crate) async fn periodic(
     worterbuch: CloneableWbApi,
@@ -38,7 +44,22 @@ pub(crate) async fn periodic(
 
     loop {
         select! {
-            _ = interval.tick() => once(&worterbuch, config.clone()).await?,
+            _ = interval.tick() => {
+                if !config.persistence_mode.periodic_snapshots()
+                    && worterbuch.wal_size().await?.unwrap_or_default() < config.wal_max_size
+                {
+                    continue;
+                }
+                let started = Instant::now();
+                let res = once(&worterbuch, config.clone()).await;
+                metrics::persisted(started.elapsed());
+                let event = PersistenceEvent {
+                    success: res.is_ok(),
+                    error: res.as_ref().err().map(ToString::to_string),
+                };
+                worterbuch.emit_event(ServerEvent::Persistence(event)).await?;
+                res?;
+            },
             _ = subsys.on_shutdown_requested() => break,
         }
     }
@@ -46,10 +67,25 @@ pub(crate) async fn periodic(
     Ok(())
 }
 
+/// Snapshots can be requested by clients as well as taken periodically, they must not write the
+/// same files at the same time.
+static SNAPSHOT_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+
 pub(crate) async fn once(worterbuch: &CloneableWbApi, config: Config) -> Result<()> {
+    let _lock = SNAPSHOT_LOCK.get_or_init(Mutex::default).lock().await;
+
     let (json_temp_path, json_path, sha_temp_path, sha_path) = file_paths(&config);
 
-    let json = worterbuch.export().await?.to_string();
+    // the log is moved aside before the export, so that no change can get lost in between
+    if config.persistence_mode.wal() {
+        worterbuch.rotate_wal().await?;
+    }
+
+    let mut export = worterbuch.export().await?;
+    if let Some(encryption) = &config.encryption {
+        encryption.seal_store(&mut export)?;
+    }
+    let json = export.to_string();
 
     let mut hasher = Sha256::new();
     hasher.update(&json);
@@ -65,15 +101,39 @@ pub(crate) async fn once(worterbuch: &CloneableWbApi, config: Config) -> Result<
     fs::copy(&json_temp_path, &json_path).await?;
     fs::copy(&sha_temp_path, &sha_path).await?;
 
+    if config.persistence_mode.wal() {
+        wal::remove_rotated(&config).await?;
+    }
+
+    #[cfg(feature = "s3")]
+    if let Some(s3) = &config.s3 {
+        crate::s3::upload(s3, json).await?;
+    }
+
     Ok(())
 }
 
 pub(crate) async fn load(config: Config) -> Result<Worterbuch> {
+    let mut worterbuch = load_snapshot(config.clone()).await?;
+    if config.persistence_mode.wal() {
+        wal::recover(&mut worterbuch, &config).await?;
+    }
+    Ok(worterbuch)
+}
+
+async fn load_snapshot(config: Config) -> Result<Worterbuch> {
     log::info!("Restoring Wörterbuch form persistence …");
 
     let (json_temp_path, json_path, sha_temp_path, sha_path) = file_paths(&config);
 
     if !json_path.exists() && !json_temp_path.exists() {
+        #[cfg(feature = "s3")]
+        if let Some(s3) = &config.s3 {
+            if let Some(worterbuch) = crate::s3::load_latest(s3, &config).await? {
+                log::info!("Wörterbuch successfully restored from S3.");
+                return Ok(worterbuch);
+            }
+        }
         log::info!("No persistence file found, starting empty instance.");
         return Ok(Worterbuch::with_config(config));
     }
@@ -97,19 +157,78 @@ async fn try_load(json_path: &PathBuf, sha_path: &PathBuf, config: &Config) -> R
     let json = fs::read_to_string(json_path).await?;
     let sha = fs::read_to_string(sha_path).await?;
 
+    verify_checksum(&json, &sha)?;
+    let worterbuch = Worterbuch::from_json(&json, config.to_owned())?;
+    Ok(worterbuch)
+}
+
+fn verify_checksum(json: &str, sha: &str) -> Result<()> {
     let mut hasher = Sha256::new();
-    hasher.update(&json);
+    hasher.update(json);
     let result = hasher.finalize();
     let loaded_sha = hex::encode(result);
 
     if sha != loaded_sha {
         Err(anyhow::Error::msg("checksums did not match"))
     } else {
-        let worterbuch = Worterbuch::from_json(&json, config.to_owned())?;
-        Ok(worterbuch)
+        Ok(())
     }
 }
 
+/// Result of checking a persistence dump with [`check_dump`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpCheck {
+    pub path: PathBuf,
+    /// Number of values in the dump.
+    pub values: usize,
+    /// Whether a checksum file was found next to the dump and matched it.
+    pub checksum_verified: bool,
+}
+
+/// Checks a persistence dump for integrity without starting a server. Without a path, the
+/// snapshot in the configured data dir is checked. The dump is verified against its checksum
+/// file if there is one, and must parse into a store, which also opens encrypted values with the
+/// configured key.
+pub async fn check_dump(path: Option<&Path>, config: Config) -> Result<DumpCheck> {
+    let json_path = match path {
+        Some(path) => path.to_owned(),
+        None => file_paths(&config).1,
+    };
+    let sha_path = checksum_path(&json_path);
+
+    let json = fs::read_to_string(&json_path)
+        .await
+        .map_err(|e| anyhow::Error::msg(format!("could not read {}: {e}", json_path.display())))?;
+    let checksum_verified = if sha_path.exists() {
+        let sha = fs::read_to_string(&sha_path).await?;
+        verify_checksum(&json, &sha)?;
+        true
+    } else {
+        false
+    };
+    let worterbuch = Worterbuch::from_json(&json, config)?;
+
+    Ok(DumpCheck {
+        path: json_path,
+        values: worterbuch.len(),
+        checksum_verified,
+    })
+}
+
+/// The checksum file belonging to a dump, `.store.sha` for `.store.json` and `.store.sha~` for
+/// `.store.json~`.
+fn checksum_path(json_path: &Path) -> PathBuf {
+    let name = json_path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let sha_name = match name.strip_suffix(".json~") {
+        Some(stem) => format!("{stem}.sha~"),
+        None => format!("{}.sha", name.strip_suffix(".json").unwrap_or(&name)),
+    };
+    json_path.with_file_name(sha_name)
+}
+
 fn file_paths(config: &Config) -> (PathBuf, PathBuf, PathBuf, PathBuf) {
     let dir = PathBuf::from(&config.data_dir);
 
@@ -0,0 +1,180 @@
+/*
+ *  Worterbuch recorder module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{auth::pattern_matches, server::common::CloneableWbApi};
+use serde_json::Value;
+use std::{collections::HashMap, sync::Arc, time::Instant};
+use tokio::time::sleep_until;
+use worterbuch_common::{
+    error::{WorterbuchError, WorterbuchResult},
+    recording::RecordedEvent,
+    topic, Key, RequestPattern,
+};
+
+/// Recordings stop taking new events once they reach this size.
+const MAX_EVENTS: usize = 100_000;
+
+#[derive(Debug)]
+struct ActiveRecording {
+    pattern: RequestPattern,
+    started: Instant,
+    events: Vec<RecordedEvent>,
+}
+
+/// Named server side recordings of all changes to keys matching a pattern. Recordings are kept in
+/// memory only.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    active: HashMap<String, ActiveRecording>,
+    finished: HashMap<String, (RequestPattern, Arc<Vec<RecordedEvent>>)>,
+}
+
+impl Recorder {
+    /// Starts a new recording, replacing any previous one with the same name.
+    pub fn start(&mut self, name: String, pattern: RequestPattern) {
+        log::info!("Starting recording '{name}' of {pattern}");
+        self.finished.remove(&name);
+        self.active.insert(
+            name,
+            ActiveRecording {
+                pattern,
+                started: Instant::now(),
+                events: Vec::new(),
+            },
+        );
+    }
+
+    pub fn stop(&mut self, name: &str) -> WorterbuchResult<usize> {
+        let Some(recording) = self.active.remove(name) else {
+            return Err(WorterbuchError::NoSuchRecording(name.to_owned()));
+        };
+        let len = recording.events.len();
+        log::info!("Stopped recording '{name}' after {len} events");
+        self.finished.insert(
+            name.to_owned(),
+            (recording.pattern, Arc::new(recording.events)),
+        );
+        Ok(len)
+    }
+
+    pub fn record(&mut self, key: &str, value: Option<&Value>) {
+        for (name, recording) in &mut self.active {
+            if !pattern_matches(&recording.pattern, key) {
+                continue;
+            }
+            if recording.events.len() >= MAX_EVENTS {
+                log::warn!("Recording '{name}' is full, dropping event for {key}");
+                continue;
+            }
+            recording.events.push(RecordedEvent::new(
+                recording.started.elapsed(),
+                key.to_owned(),
+                value.cloned(),
+            ));
+        }
+    }
+
+    /// Returns the pattern of an active or finished recording.
+    pub fn pattern(&self, name: &str) -> WorterbuchResult<RequestPattern> {
+        self.active
+            .get(name)
+            .map(|r| r.pattern.clone())
+            .or_else(|| self.finished.get(name).map(|(p, _)| p.clone()))
+            .ok_or_else(|| WorterbuchError::NoSuchRecording(name.to_owned()))
+    }
+
+    /// Returns the recorded pattern and the events of a finished recording.
+    pub fn recording(
+        &self,
+        name: &str,
+    ) -> WorterbuchResult<(RequestPattern, Arc<Vec<RecordedEvent>>)> {
+        self.finished
+            .get(name)
+            .cloned()
+            .ok_or_else(|| WorterbuchError::NoSuchRecording(name.to_owned()))
+    }
+}
+
+/// Writes the events of a recording to the keys below the target prefix with their original timing,
+/// sped up by the given factor.
+pub(crate) async fn replay(
+    worterbuch: CloneableWbApi,
+    events: Arc<Vec<RecordedEvent>>,
+    target_prefix: Key,
+    speed: u32,
+    client_id: String,
+) {
+    let start = tokio::time::Instant::now();
+    for event in events.iter() {
+        sleep_until(start + event.due(speed.max(1) as f64)).await;
+        let key = topic!(target_prefix, event.key);
+        let res = match &event.value {
+            Some(value) => {
+                worterbuch
+                    .set(key.clone(), value.to_owned(), client_id.clone())
+                    .await
+            }
+            None => worterbuch
+                .delete(key.clone(), client_id.clone())
+                .await
+                .map(|_| ()),
+        };
+        match res {
+            Ok(()) | Err(WorterbuchError::NoSuchValue(_)) => (),
+            Err(e) => {
+                log::warn!("Stopping replay, could not write {key}: {e}");
+                return;
+            }
+        }
+    }
+    log::info!("Replayed {} events into {target_prefix}", events.len());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn recordings_capture_matching_changes_until_stopped() {
+        let mut recorder = Recorder::default();
+        recorder.start("line1".to_owned(), "plant/line1/#".to_owned());
+
+        recorder.record("plant/line1/temp", Some(&json!(21)));
+        recorder.record("plant/line2/temp", Some(&json!(22)));
+        recorder.record("plant/line1/temp", None);
+
+        assert!(recorder.recording("line1").is_err());
+        assert_eq!(
+            recorder.pattern("line1").expect("active recording"),
+            "plant/line1/#"
+        );
+        assert_eq!(recorder.stop("line1").expect("active recording"), 2);
+        recorder.record("plant/line1/temp", Some(&json!(23)));
+
+        let (pattern, events) = recorder.recording("line1").expect("finished recording");
+        assert_eq!(pattern, "plant/line1/#");
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].key, "plant/line1/temp");
+        assert_eq!(events[0].value, Some(json!(21)));
+        assert_eq!(events[1].value, None);
+
+        assert!(recorder.stop("line1").is_err());
+    }
+}
@@ -0,0 +1,367 @@
+/*
+ *  Worterbuch replication module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{auth::JwtClaims, server::common::CloneableWbApi, INTERNAL_CLIENT_ID};
+use anyhow::Result;
+use serde_json::json;
+use std::{fmt, str::FromStr, sync::Arc};
+use tokio::{select, sync::watch};
+use tokio_graceful_shutdown::SubsystemHandle;
+use uuid::Uuid;
+use worterbuch_common::{
+    error::{AuthorizationError, AuthorizationResult, ConfigError},
+    redact::REDACTED,
+    topic, Key, PStateEvent, Privilege, SYSTEM_TOPIC_REPLICATION, SYSTEM_TOPIC_ROOT,
+    SYSTEM_TOPIC_ROOT_PREFIX,
+};
+
+/// JWT role a primary needs to write to a replica.
+pub const REPLICATION_ROLE: &str = "replication";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Role {
+    #[default]
+    Primary,
+    Replica,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Primary => "primary".fmt(f),
+            Role::Replica => "replica".fmt(f),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().trim() {
+            "primary" => Ok(Role::Primary),
+            "replica" => Ok(Role::Replica),
+            _ => Err(ConfigError::InvalidReplicationRole(s.to_owned())),
+        }
+    }
+}
+
+/// Live replication between servers. While a server is primary, it streams every change to its
+/// replicas over the regular client protocol, authenticating with a JWT that carries the
+/// [`REPLICATION_ROLE`]. Replicas serve reads but reject writes from anyone else, so they need
+/// JWT auth to be configured.
+///
+/// The current role is published at `$SYS/replication/role`, writing `primary` or `replica` to
+/// that key switches roles for a manual failover. `$SYS` keys are local to each server and are
+/// not replicated.
+#[derive(Clone)]
+pub struct Replication {
+    /// Addresses of the replicas, e.g. `ws://replica:8080/ws` or `tcp://replica:8081`.
+    pub replicas: Vec<String>,
+    pub auth_token: Option<String>,
+    role: Arc<watch::Sender<Role>>,
+}
+
+impl Replication {
+    pub fn new(role: Role, replicas: Vec<String>, auth_token: Option<String>) -> Self {
+        let (role, _) = watch::channel(role);
+        Self {
+            replicas,
+            auth_token,
+            role: Arc::new(role),
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        *self.role.borrow()
+    }
+
+    /// A copy that is safe to print, with the auth token blanked out.
+    pub fn redacted(&self) -> Self {
+        Self {
+            auth_token: self.auth_token.as_ref().map(|_| REDACTED.to_owned()),
+            ..self.clone()
+        }
+    }
+
+    pub fn set_role(&self, role: Role) {
+        self.role.send_replace(role);
+    }
+
+    pub fn authorize(
+        &self,
+        claims: Option<&JwtClaims>,
+        privilege: &Privilege,
+        pattern: &str,
+    ) -> AuthorizationResult<()> {
+        if self.role() == Role::Primary
+            || privilege == &Privilege::Read
+            || pattern.starts_with(SYSTEM_TOPIC_ROOT_PREFIX)
+            || claims.is_some_and(|c| c.roles.iter().any(|r| r == REPLICATION_ROLE))
+        {
+            Ok(())
+        } else {
+            Err(AuthorizationError::InsufficientPrivileges(
+                privilege.to_owned(),
+                pattern.to_owned(),
+            ))
+        }
+    }
+}
+
+impl fmt::Debug for Replication {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Replication")
+            .field("replicas", &self.replicas)
+            .field("auth_token", &self.auth_token.as_ref().map(|_| REDACTED))
+            .field("role", &self.role())
+            .finish()
+    }
+}
+
+impl PartialEq for Replication {
+    fn eq(&self, other: &Self) -> bool {
+        self.replicas == other.replicas
+            && self.auth_token == other.auth_token
+            && Arc::ptr_eq(&self.role, &other.role)
+    }
+}
+
+fn role_key() -> Key {
+    topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_REPLICATION, "role")
+}
+
+/// Publishes the replication role, applies role changes written to `$SYS/replication/role` and
+/// streams changes to the replicas while this server is primary.
+pub(crate) async fn run(
+    worterbuch: CloneableWbApi,
+    replication: Replication,
+    subsys: SubsystemHandle,
+) -> Result<()> {
+    worterbuch
+        .set(
+            role_key(),
+            json!(replication.role().to_string()),
+            INTERNAL_CLIENT_ID.to_owned(),
+        )
+        .await?;
+
+    #[cfg(feature = "replication")]
+    for replica in replication.replicas.clone() {
+        let worterbuch = worterbuch.clone();
+        let replication = replication.clone();
+        subsys.start(&format!("replica {replica}"), move |subsys| {
+            push::push(worterbuch, replication, replica, subsys)
+        });
+    }
+    #[cfg(not(feature = "replication"))]
+    if !replication.replicas.is_empty() {
+        log::warn!("Streaming to replicas requires the 'replication' feature, ignoring replicas.");
+    }
+
+    let (mut events, _) = worterbuch
+        .psubscribe(Uuid::new_v4(), 0, role_key(), true, true)
+        .await?;
+
+    loop {
+        select! {
+            Some(event) = events.recv() => {
+                let PStateEvent::KeyValuePairs(kvps) = event else {
+                    continue;
+                };
+                for kvp in kvps {
+                    match kvp.value.as_str().map(str::parse::<Role>) {
+                        Some(Ok(role)) => {
+                            if role != replication.role() {
+                                log::info!("Switching replication role to {role}.");
+                                replication.set_role(role);
+                            }
+                        }
+                        _ => {
+                            log::warn!("Ignoring invalid replication role {}", kvp.value);
+                            worterbuch
+                                .set(
+                                    role_key(),
+                                    json!(replication.role().to_string()),
+                                    INTERNAL_CLIENT_ID.to_owned(),
+                                )
+                                .await?;
+                        }
+                    }
+                }
+            },
+            _ = subsys.on_shutdown_requested() => break,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "replication")]
+mod push {
+    use super::{Replication, Role};
+    use crate::{
+        server::common::CloneableWbApi,
+        standby::{client_config, RECONNECT_DELAY},
+    };
+    use anyhow::Result;
+    use std::collections::HashSet;
+    use tokio::{select, sync::watch, time::sleep};
+    use tokio_graceful_shutdown::SubsystemHandle;
+    use uuid::Uuid;
+    use worterbuch_client::{config::Config as ClientConfig, connect, Worterbuch};
+    use worterbuch_common::{
+        Key, KeyValuePair, KeyValuePairs, PStateEvent, SYSTEM_TOPIC_ROOT_PREFIX,
+    };
+
+    pub(super) async fn push(
+        worterbuch: CloneableWbApi,
+        replication: Replication,
+        replica: String,
+        subsys: SubsystemHandle,
+    ) -> Result<()> {
+        let config = client_config(&replica, replication.auth_token.clone())?;
+        let mut role = replication.role.subscribe();
+
+        loop {
+            select! {
+                res = wait_for(&mut role, Role::Primary) => res?,
+                _ = subsys.on_shutdown_requested() => break,
+            }
+            log::info!("Replicating to {replica} …");
+            let client_id = Uuid::new_v4();
+            select! {
+                res = forward(&worterbuch, client_id, config.clone()) => match res {
+                    Ok(()) => log::warn!("Lost connection to replica {replica}."),
+                    Err(e) => log::warn!("Error replicating to {replica}: {e}"),
+                },
+                res = wait_for(&mut role, Role::Replica) => {
+                    res?;
+                    log::info!("Stopped replicating to {replica}, this server is a replica now.");
+                },
+                _ = subsys.on_shutdown_requested() => break,
+            }
+            worterbuch.unsubscribe(client_id, 0).await.ok();
+            select! {
+                _ = sleep(RECONNECT_DELAY) => (),
+                _ = subsys.on_shutdown_requested() => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn wait_for(role: &mut watch::Receiver<Role>, expected: Role) -> Result<()> {
+        while *role.borrow_and_update() != expected {
+            role.changed().await?;
+        }
+        Ok(())
+    }
+
+    async fn forward(
+        worterbuch: &CloneableWbApi,
+        client_id: Uuid,
+        config: ClientConfig,
+    ) -> Result<()> {
+        let replica = connect(config, async {}).await?;
+        let (mut events, _) = worterbuch
+            .psubscribe(client_id, 0, "#".to_owned(), false, false)
+            .await?;
+
+        // the first event is the current state, anything else the replica has is stale
+        if let Some(PStateEvent::KeyValuePairs(kvps)) = events.recv().await {
+            let current: HashSet<_> = kvps.iter().map(|kvp| kvp.key.clone()).collect();
+            let (existing, _) = replica.pget_generic("#".to_owned()).await?;
+            for kvp in replicated(existing) {
+                if !current.contains(&kvp.key) {
+                    delete(&replica, kvp.key).await;
+                }
+            }
+            apply(&replica, PStateEvent::KeyValuePairs(kvps)).await?;
+            log::info!("Replica is up to date, {} keys replicated.", current.len());
+        }
+
+        while let Some(event) = events.recv().await {
+            apply(&replica, event).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn apply(replica: &Worterbuch, event: PStateEvent) -> Result<()> {
+        match event {
+            PStateEvent::KeyValuePairs(kvps) => {
+                for kvp in replicated(kvps) {
+                    replica.set_generic(kvp.key, kvp.value).await?;
+                }
+            }
+            PStateEvent::Deleted(kvps) => {
+                for kvp in replicated(kvps) {
+                    delete(replica, kvp.key).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The key may already be gone on the replica, which is fine.
+    async fn delete(replica: &Worterbuch, key: Key) {
+        if let Err(e) = replica.delete_generic(key.clone()).await {
+            log::debug!("Could not delete {key} on replica: {e}");
+        }
+    }
+
+    fn replicated(kvps: KeyValuePairs) -> impl Iterator<Item = KeyValuePair> {
+        kvps.into_iter()
+            .filter(|kvp| !kvp.key.starts_with(SYSTEM_TOPIC_ROOT_PREFIX))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replicas_only_accept_writes_from_primary() {
+        let replication = Replication::new(Role::Replica, Vec::new(), None);
+        let primary = JwtClaims {
+            sub: "primary".to_owned(),
+            name: "primary".to_owned(),
+            exp: 0,
+            worterbuch_privileges: Default::default(),
+            roles: vec![REPLICATION_ROLE.to_owned()],
+        };
+
+        assert!(replication.authorize(None, &Privilege::Read, "a/b").is_ok());
+        assert!(replication
+            .authorize(None, &Privilege::Write, "a/b")
+            .is_err());
+        assert!(replication
+            .authorize(Some(&primary), &Privilege::Write, "a/b")
+            .is_ok());
+        assert!(replication
+            .authorize(None, &Privilege::Write, "$SYS/replication/role")
+            .is_ok());
+
+        replication.set_role(Role::Primary);
+        assert!(replication
+            .authorize(None, &Privilege::Delete, "a/b")
+            .is_ok());
+    }
+}
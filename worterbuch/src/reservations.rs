@@ -0,0 +1,122 @@
+/*
+ *  Worterbuch key reservations module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::worterbuch::patterns_overlap;
+use worterbuch_common::{KeySegment, RequestPattern};
+
+/// Key patterns that clients claimed exclusive write access to. A reservation lasts until it is
+/// released or its client disconnects, writes of other clients to matching keys are rejected in
+/// the meantime.
+#[derive(Debug, Default)]
+pub struct Reservations {
+    reservations: Vec<Reservation>,
+}
+
+#[derive(Debug)]
+struct Reservation {
+    pattern: RequestPattern,
+    path: Vec<KeySegment>,
+    client_id: String,
+}
+
+impl Reservations {
+    /// Reserves a pattern for a client. Fails with the conflicting pattern if it overlaps with one
+    /// that is reserved by another client. Reserving a pattern twice has no effect.
+    pub fn reserve(
+        &mut self,
+        pattern: RequestPattern,
+        client_id: &str,
+    ) -> Result<(), RequestPattern> {
+        let path = KeySegment::parse(&pattern);
+        if let Some(conflict) = self.conflict(&path, client_id) {
+            return Err(conflict.to_owned());
+        }
+        if !self
+            .reservations
+            .iter()
+            .any(|r| r.pattern == pattern && r.client_id == client_id)
+        {
+            self.reservations.push(Reservation {
+                pattern,
+                path,
+                client_id: client_id.to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Releases a client's reservation of a pattern, returns `false` if there was none.
+    pub fn release(&mut self, pattern: &str, client_id: &str) -> bool {
+        let len = self.reservations.len();
+        self.reservations
+            .retain(|r| r.pattern != pattern || r.client_id != client_id);
+        self.reservations.len() != len
+    }
+
+    /// Releases all reservations of a client and returns their patterns.
+    pub fn release_all(&mut self, client_id: &str) -> Vec<RequestPattern> {
+        let (released, kept): (Vec<_>, Vec<_>) = self
+            .reservations
+            .drain(..)
+            .partition(|r| r.client_id == client_id);
+        self.reservations = kept;
+        released.into_iter().map(|r| r.pattern).collect()
+    }
+
+    /// Returns the pattern reserved by another client that overlaps with the given key or
+    /// pattern, if any.
+    pub fn conflict(&self, path: &[KeySegment], client_id: &str) -> Option<&str> {
+        self.reservations
+            .iter()
+            .find(|r| r.client_id != client_id && patterns_overlap(&r.path, path))
+            .map(|r| r.pattern.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reserved_patterns_are_exclusive_until_released() {
+        let mut reservations = Reservations::default();
+        let parse = |key: &str| KeySegment::parse(key);
+
+        reservations.reserve("service/a/#".to_owned(), "1").unwrap();
+        assert_eq!(
+            reservations.reserve("service/?/state".to_owned(), "2"),
+            Err("service/a/#".to_owned())
+        );
+        reservations.reserve("service/b/#".to_owned(), "2").unwrap();
+
+        assert_eq!(reservations.conflict(&parse("service/a/state"), "1"), None);
+        assert_eq!(
+            reservations.conflict(&parse("service/a/state"), "2"),
+            Some("service/a/#")
+        );
+        assert_eq!(reservations.conflict(&parse("other/key"), "2"), None);
+
+        assert!(reservations.release("service/a/#", "1"));
+        assert!(!reservations.release("service/a/#", "1"));
+        assert_eq!(reservations.conflict(&parse("service/a/state"), "2"), None);
+
+        assert_eq!(reservations.release_all("2"), vec!["service/b/#"]);
+        assert_eq!(reservations.conflict(&parse("service/b/state"), "1"), None);
+    }
+}
@@ -0,0 +1,136 @@
+/*
+ *  Worterbuch key retention module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{auth::pattern_matches, server::common::CloneableWbApi};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{fs, time::Duration};
+use tokio::{select, time::interval};
+use tokio_graceful_shutdown::SubsystemHandle;
+use worterbuch_common::{
+    error::{ConfigError, ConfigResult},
+    Key, RequestPattern,
+};
+
+/// How often retention rules are checked against the store.
+pub const ENFORCEMENT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RetentionAction {
+    /// only report stale keys
+    #[default]
+    Flag,
+    Delete,
+}
+
+/// Applies to all keys matching the pattern that have not been written for longer than
+/// `max_age_secs`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionRule {
+    pub pattern: RequestPattern,
+    pub max_age_secs: u64,
+    #[serde(default)]
+    pub action: RetentionAction,
+}
+
+/// An ordered list of retention rules. If more than one rule matches a key, the first one wins.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionRules(Vec<RetentionRule>);
+
+impl RetentionRules {
+    pub fn new(rules: Vec<RetentionRule>) -> Self {
+        Self(rules)
+    }
+
+    pub fn load(path: &str) -> ConfigResult<Self> {
+        let json = fs::read_to_string(path).map_err(|e| {
+            ConfigError::InvalidRetentionRules(format!("could not read {path}: {e}"))
+        })?;
+        serde_json::from_str(&json)
+            .map_err(|e| ConfigError::InvalidRetentionRules(format!("could not parse {path}: {e}")))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn rules(&self) -> &[RetentionRule] {
+        &self.0
+    }
+
+    pub fn find(&self, key: &str) -> Option<&RetentionRule> {
+        self.0.iter().find(|r| pattern_matches(&r.pattern, key))
+    }
+}
+
+/// A key that has not been written for longer than its retention rule allows.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StaleKey {
+    pub key: Key,
+    pub pattern: RequestPattern,
+    pub idle_secs: u64,
+    pub action: RetentionAction,
+}
+
+pub(crate) async fn enforce_periodically(
+    worterbuch: CloneableWbApi,
+    subsys: SubsystemHandle,
+) -> Result<()> {
+    let mut interval = interval(ENFORCEMENT_INTERVAL);
+
+    loop {
+        select! {
+            _ = interval.tick() => {
+                worterbuch.enforce_retention().await?;
+            },
+            _ = subsys.on_shutdown_requested() => break,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules: RetentionRules = serde_json::from_str(
+            r#"[
+                { "pattern": "devices/legacy/#", "maxAgeSecs": 60, "action": "delete" },
+                { "pattern": "devices/#", "maxAgeSecs": 3600 }
+            ]"#,
+        )
+        .expect("valid rules");
+
+        let legacy = rules.find("devices/legacy/a/temp").expect("matching rule");
+        assert_eq!(legacy.action, RetentionAction::Delete);
+        assert_eq!(legacy.max_age_secs, 60);
+
+        let other = rules.find("devices/b/temp").expect("matching rule");
+        assert_eq!(other.action, RetentionAction::Flag);
+        assert_eq!(other.max_age_secs, 3600);
+
+        assert!(rules.find("settings/x").is_none());
+    }
+}
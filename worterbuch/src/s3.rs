@@ -0,0 +1,92 @@
+/*
+ *  Worterbuch S3 persistence module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{config::Config, worterbuch::Worterbuch};
+use anyhow::{anyhow, Result};
+use futures::TryStreamExt;
+use object_store::{aws::AmazonS3Builder, path::Path, ObjectStore, PutPayload};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Uploads every persisted snapshot to an S3 compatible bucket, so a server running on an
+/// ephemeral disk can restore its state after being moved. Credentials, region and endpoint are
+/// taken from the usual `AWS_*` environment variables. Only the `retain` most recent snapshots
+/// are kept, older ones are deleted after each upload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Persistence {
+    pub bucket: String,
+    pub prefix: String,
+    pub retain: usize,
+}
+
+impl S3Persistence {
+    fn store(&self) -> Result<impl ObjectStore> {
+        Ok(AmazonS3Builder::from_env()
+            .with_bucket_name(&self.bucket)
+            .build()?)
+    }
+
+    async fn snapshots(&self, store: &impl ObjectStore) -> Result<Vec<Path>> {
+        let prefix = Path::from(self.prefix.as_str());
+        let mut snapshots: Vec<Path> = store
+            .list(Some(&prefix))
+            .map_ok(|meta| meta.location)
+            .try_filter(|path| futures::future::ready(path.as_ref().ends_with(".json")))
+            .try_collect()
+            .await?;
+        // snapshot names are zero padded timestamps, so they sort chronologically
+        snapshots.sort();
+        Ok(snapshots)
+    }
+}
+
+pub(crate) async fn upload(s3: &S3Persistence, json: String) -> Result<()> {
+    let store = s3.store()?;
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let path = Path::from(format!("{}/{timestamp:020}.json", s3.prefix));
+    store
+        .put(&path, PutPayload::from(json.into_bytes()))
+        .await?;
+    log::debug!("Uploaded snapshot to s3://{}/{path}", s3.bucket);
+
+    let snapshots = s3.snapshots(&store).await?;
+    let expired = snapshots.len().saturating_sub(s3.retain.max(1));
+    for path in &snapshots[..expired] {
+        log::debug!("Deleting expired snapshot s3://{}/{path}", s3.bucket);
+        store.delete(path).await?;
+    }
+
+    Ok(())
+}
+
+/// Restores the most recent snapshot from the bucket. Returns `None` if there is none yet.
+pub(crate) async fn load_latest(s3: &S3Persistence, config: &Config) -> Result<Option<Worterbuch>> {
+    let store = s3.store()?;
+
+    let Some(latest) = s3.snapshots(&store).await?.pop() else {
+        return Ok(None);
+    };
+
+    log::info!("Restoring Wörterbuch from s3://{}/{latest} …", s3.bucket);
+    let bytes = store.get(&latest).await?.bytes().await?;
+    let json = String::from_utf8(bytes.to_vec())
+        .map_err(|e| anyhow!("snapshot {latest} is not valid UTF-8: {e}"))?;
+    let worterbuch = Worterbuch::from_json(&json, config.to_owned())?;
+    Ok(Some(worterbuch))
+}
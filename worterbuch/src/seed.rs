@@ -0,0 +1,203 @@
+/*
+ *  Worterbuch seed data module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{worterbuch::Worterbuch, INTERNAL_CLIENT_ID};
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::{collections::BTreeMap, env, path::Path};
+use tokio::fs;
+use worterbuch_common::Key;
+
+/// Contents of a seed file, mapping keys to the values they are initialized with, e.g.
+/// `{"config/mqtt/port": 1883}`. Keys and string values may reference environment variables as
+/// `${VAR}` or `${VAR:-default}`, see [`interpolate`].
+pub type Seed = BTreeMap<Key, Value>;
+
+/// Applies all JSON and YAML files in the seed directory in alphabetical order. Only keys that
+/// do not have a value yet are set, so seeds never overwrite data that was changed at runtime.
+pub async fn apply_dir(worterbuch: &mut Worterbuch, dir: &str) -> Result<()> {
+    let mut entries = fs::read_dir(dir)
+        .await
+        .with_context(|| format!("could not read seed directory {dir}"))?;
+    let mut files = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if is_json(&path) || is_yaml(&path) {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    for file in files {
+        let seed = load(&file).await?;
+        let mut applied = 0;
+        for (key, value) in seed {
+            if worterbuch.get(&key).is_ok() {
+                continue;
+            }
+            worterbuch.set(key, value, INTERNAL_CLIENT_ID).await?;
+            applied += 1;
+        }
+        log::info!(
+            "Applied {applied} key(s) from seed file {}.",
+            file.display()
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn load(file: &Path) -> Result<Seed> {
+    let content = fs::read_to_string(file)
+        .await
+        .with_context(|| format!("could not read seed file {}", file.display()))?;
+    let seed: Seed = if is_yaml(file) {
+        serde_yaml::from_str(&content)
+            .with_context(|| format!("could not parse seed file {}", file.display()))?
+    } else {
+        serde_json::from_str(&content)
+            .with_context(|| format!("could not parse seed file {}", file.display()))?
+    };
+    seed.into_iter()
+        .map(|(key, value)| Ok((interpolate(&key)?, interpolate_value(value)?)))
+        .collect::<Result<Seed>>()
+        .with_context(|| format!("could not interpolate seed file {}", file.display()))
+}
+
+/// Replaces every `${VAR}` in a string with the value of the environment variable `VAR`. With
+/// `${VAR:-default}`, `default` is used if the variable is unset or empty, otherwise an unset
+/// variable is an error.
+pub fn interpolate(text: &str) -> Result<String> {
+    let mut interpolated = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        interpolated.push_str(&rest[..start]);
+        let expression = &rest[start + 2..];
+        let end = expression
+            .find('}')
+            .ok_or_else(|| anyhow!("unterminated variable in '{text}'"))?;
+        let (name, default) = match expression[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&expression[..end], None),
+        };
+        match (env::var(name), default) {
+            (Ok(value), None) => interpolated.push_str(&value),
+            (Ok(value), Some(_)) if !value.is_empty() => interpolated.push_str(&value),
+            (_, Some(default)) => interpolated.push_str(default),
+            (Err(_), None) => return Err(anyhow!("environment variable {name} is not set")),
+        }
+        rest = &expression[end + 1..];
+    }
+
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+fn interpolate_value(value: Value) -> Result<Value> {
+    Ok(match value {
+        Value::String(text) => Value::String(interpolate(&text)?),
+        Value::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(interpolate_value)
+                .collect::<Result<_>>()?,
+        ),
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| Ok((interpolate(&key)?, interpolate_value(value)?)))
+                .collect::<Result<_>>()?,
+        ),
+        value => value,
+    })
+}
+
+fn is_json(file: &Path) -> bool {
+    file.extension().is_some_and(|ext| ext == "json")
+}
+
+fn is_yaml(file: &Path) -> bool {
+    file.extension()
+        .is_some_and(|ext| ext == "yaml" || ext == "yml")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Config;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn seeds_only_fill_in_missing_keys() {
+        let dir = std::env::temp_dir().join(format!("wb-seed-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("could not create seed dir");
+        std::fs::write(dir.join("01-base.json"), r#"{"a/b": 1, "a/c": "json"}"#)
+            .expect("could not write seed file");
+        std::fs::write(dir.join("02-overrides.yaml"), "a/c: yaml\na/d: [1, 2]\n")
+            .expect("could not write seed file");
+        std::fs::write(dir.join("README.md"), "not a seed file").expect("could not write file");
+
+        let mut wb = Worterbuch::with_config(Config::new().await.expect("invalid config"));
+        wb.set("a/b".to_owned(), json!(0), "test")
+            .await
+            .expect("set failed");
+
+        apply_dir(&mut wb, &dir.to_string_lossy())
+            .await
+            .expect("seeding failed");
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(wb.get(&"a/b".to_owned()).expect("no value").1, json!(0));
+        assert_eq!(
+            wb.get(&"a/c".to_owned()).expect("no value").1,
+            json!("json")
+        );
+        assert_eq!(
+            wb.get(&"a/d".to_owned()).expect("no value").1,
+            json!([1, 2])
+        );
+    }
+
+    #[test]
+    fn environment_variables_are_interpolated() {
+        env::set_var("WB_SEED_TEST_HOST", "broker.local");
+        env::set_var("WB_SEED_TEST_EMPTY", "");
+
+        assert_eq!(
+            interpolate("mqtt://${WB_SEED_TEST_HOST}:${WB_SEED_TEST_PORT:-1883}/x").unwrap(),
+            "mqtt://broker.local:1883/x"
+        );
+        assert_eq!(
+            interpolate("${WB_SEED_TEST_EMPTY:-fallback}").unwrap(),
+            "fallback"
+        );
+        assert_eq!(interpolate("${WB_SEED_TEST_EMPTY}").unwrap(), "");
+        assert_eq!(interpolate("no variables").unwrap(), "no variables");
+        assert!(interpolate("${WB_SEED_TEST_UNSET}").is_err());
+        assert!(interpolate("${WB_SEED_TEST_HOST").is_err());
+
+        assert_eq!(
+            interpolate_value(
+                json!({"url": "${WB_SEED_TEST_HOST}", "list": ["${WB_SEED_TEST_HOST}", 1]})
+            )
+            .unwrap(),
+            json!({"url": "broker.local", "list": ["broker.local", 1]})
+        );
+    }
+}
@@ -17,32 +17,67 @@
  *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+#[cfg(feature = "cluster")]
+use crate::cluster::Cluster;
 use crate::{
-    auth::{get_claims, JwtClaims},
+    acl::SharedAcl,
+    auth::{get_claims, AuthorizationContext, JwtClaims, SharedAuthorizer},
+    metrics::{self, Metrics},
+    recorder,
+    replication::Replication,
+    retention::StaleKey,
+    server::prefix::{add_key_prefix, prefixed},
+    sort_and_window,
     subscribers::SubscriptionId,
-    Config, PStateAggregator, INTERNAL_CLIENT_ID,
+    usage::KeyUsageReport,
+    users::{self, check_protected},
+    ArrayOp, Config, PStateAggregator, Snapshot, INTERNAL_CLIENT_ID,
 };
 use anyhow::anyhow;
 use serde::Serialize;
+use serde_json::Number;
 use std::{
-    net::SocketAddr,
+    collections::HashMap,
+    future::Future,
+    mem,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 use tokio::{
     spawn,
     sync::{
-        mpsc::{self, Receiver},
-        oneshot,
+        mpsc::{
+            self,
+            error::{SendError, TrySendError},
+            Receiver,
+        },
+        oneshot, watch,
     },
+    task::{spawn_blocking, AbortHandle, JoinHandle},
+    time::timeout,
 };
 use uuid::Uuid;
 use worterbuch_common::{
-    error::{Context, WorterbuchError, WorterbuchResult},
-    Ack, AuthorizationRequest, ClientMessage as CM, Delete, Err, ErrorCode, Get, Key, KeyValuePair,
-    KeyValuePairs, LiveOnlyFlag, Ls, LsState, MetaData, PDelete, PGet, PState, PStateEvent,
-    PSubscribe, Privilege, Protocol, ProtocolVersion, Publish, RegularKeySegment, RequestPattern,
-    ServerMessage, Set, State, StateEvent, Subscribe, SubscribeLs, TransactionId, UniqueFlag,
-    Unsubscribe, UnsubscribeLs, Value,
+    error::{
+        AuthorizationError, AuthorizationResult, ConnectionError, Context, WorterbuchError,
+        WorterbuchResult,
+    },
+    recording::RecordedEvent,
+    redact::redact_message,
+    topic,
+    wire::{Compression, WireFormat},
+    Ack, ArrayPop, ArrayPush, ArrayRemove, AuthenticationRequest, AuthorizationRequest, Cancel,
+    ClientInfo, ClientMessage as CM, CompareAndSwap, CorrelatedValue, Delete, Err, ErrorCode,
+    EventState, Expire, Get, GetAt, GetHistory, HintInvalidation, HistoryEntry, HistoryState,
+    Increment, InvalidationHint, Key, KeyValuePair, KeyValuePairs, KeysState, LiveOnlyFlag, Lock,
+    Ls, LsState, MetaData, PDelete, PGet, PKeys, PState, PStateEvent, PSubscribe, Privilege,
+    Protocol, ProtocolVersion, ProtocolVersions, Publish, PublishRetained, RegularKeySegment,
+    Release, ReplayRecording, RequestPattern, Reserve, Restore, Resync, ServerEvent, ServerMessage,
+    Set, SetAt, SetExpiring, SetMany, SetNx, SetXx, StartRecording, State, StateEvent,
+    StopRecording, Subscribe, SubscribeEvents, SubscribeLs, SubscriberInfo, SubscribersState,
+    SyncRequest, TransactionId, TryLock, UniqueFlag, Unlock, Unsubscribe, UnsubscribeLs, Value,
+    WhoSubscribes, AUTH_TOPIC_ROOT, SYSTEM_TOPIC_ROOT,
 };
 
 #[derive(Debug, Clone, PartialEq)]
@@ -50,22 +85,40 @@ struct SubscriptionInfo {
     transaction_id: TransactionId,
     request_pattern: RequestPattern,
     live_only: bool,
+    initial_state_marker: bool,
     aggregate_duration: Duration,
     channel_buffer_size: usize,
+    deprecated: Option<bool>,
+}
+
+struct AuthSettings<'a> {
+    client_id: Uuid,
+    required: bool,
+    user_db: bool,
+    authorizer: Option<&'a SharedAuthorizer>,
+    acl: Option<&'a SharedAcl>,
+    replication: Option<&'a Replication>,
 }
 
 async fn check_auth(
-    auth_required: bool,
+    settings: &AuthSettings<'_>,
     privilege: Privilege,
     pattern: &str,
     auth: &Option<JwtClaims>,
     client: &mpsc::Sender<ServerMessage>,
     transaction_id: u64,
 ) -> WorterbuchResult<bool> {
-    if auth_required {
+    if settings.required {
         match auth {
             Some(claims) => {
-                if let Err(e) = claims.authorize(&privilege, pattern) {
+                let authorized = claims.authorize(&privilege, pattern).and_then(|()| {
+                    if settings.user_db {
+                        check_protected(Some(claims), &privilege, pattern)
+                    } else {
+                        Ok(())
+                    }
+                });
+                if let Err(e) = authorized {
                     log::trace!("Client is not authorized, sending error …");
                     handle_store_error(
                         WorterbuchError::Unauthorized(e.clone()),
@@ -80,97 +133,540 @@ async fn check_auth(
             None => return Err(WorterbuchError::AuthorizationRequired(privilege)),
         }
     }
+    if let Some(authorizer) = settings.authorizer {
+        let ctx = AuthorizationContext {
+            client_id: Some(settings.client_id),
+            claims: auth.as_ref(),
+            privilege: &privilege,
+            pattern,
+        };
+        if let Err(e) = authorizer.authorize(ctx) {
+            log::trace!("Client was rejected by authorizer, sending error …");
+            handle_store_error(WorterbuchError::Unauthorized(e), client, transaction_id).await?;
+            log::trace!("Client was rejected by authorizer, sending error done.");
+            return Ok(false);
+        }
+    }
+    if let Some(acl) = settings.acl {
+        let roles = auth
+            .as_ref()
+            .map(|c| c.roles.as_slice())
+            .unwrap_or_default();
+        if let Err(e) = acl.authorize(roles, &privilege, pattern) {
+            log::trace!("Client was rejected by ACL, sending error …");
+            handle_store_error(WorterbuchError::Unauthorized(e), client, transaction_id).await?;
+            log::trace!("Client was rejected by ACL, sending error done.");
+            return Ok(false);
+        }
+    }
+    if let Some(replication) = settings.replication {
+        if let Err(e) = replication.authorize(auth.as_ref(), &privilege, pattern) {
+            log::trace!("Client tried to write to a replica, sending error …");
+            handle_store_error(WorterbuchError::Unauthorized(e), client, transaction_id).await?;
+            log::trace!("Client tried to write to a replica, sending error done.");
+            return Ok(false);
+        }
+    }
     Ok(true)
 }
 
+/// Requests of a single connection that are still being worked on in the background and can be
+/// cancelled by the client, keyed by their transaction ID.
+#[derive(Clone, Default)]
+pub struct InFlight {
+    tasks: Arc<Mutex<HashMap<TransactionId, AbortHandle>>>,
+}
+
+impl InFlight {
+    fn spawn(
+        &self,
+        transaction_id: TransactionId,
+        task: impl Future<Output = ()> + Send + 'static,
+    ) {
+        let tasks = self.tasks.clone();
+        // hold the lock until the handle is registered so a quickly finishing task can't try to
+        // remove itself before it was inserted
+        let mut guard = self.tasks.lock().expect("in-flight tasks mutex poisoned");
+        let handle = spawn(async move {
+            task.await;
+            tasks
+                .lock()
+                .expect("in-flight tasks mutex poisoned")
+                .remove(&transaction_id);
+        });
+        guard.insert(transaction_id, handle.abort_handle());
+    }
+
+    /// Aborts the request with the given transaction ID, returns `false` if there is no such
+    /// request (anymore).
+    fn cancel(&self, transaction_id: TransactionId) -> bool {
+        let handle = self
+            .tasks
+            .lock()
+            .expect("in-flight tasks mutex poisoned")
+            .remove(&transaction_id);
+        match handle {
+            Some(handle) => {
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn process_incoming_message(
     client_id: Uuid,
     msg: &str,
     worterbuch: &CloneableWbApi,
     tx: &mpsc::Sender<ServerMessage>,
-    auth_required: bool,
     auth: Option<JwtClaims>,
     config: &Config,
+    key_prefix: &watch::Sender<Option<Key>>,
+    wire_format: Option<&watch::Sender<(WireFormat, Compression)>>,
+    in_flight: &InFlight,
 ) -> WorterbuchResult<(bool, Option<JwtClaims>)> {
-    log::debug!("Received message: {msg}");
-    let mut authorized = auth;
+    if log::log_enabled!(log::Level::Debug) {
+        let prefix = key_prefix.borrow().clone();
+        let redacted = redact_message(msg, |key| match &prefix {
+            Some(prefix) => config.is_sensitive(&prefixed(prefix, key.to_owned())),
+            None => config.is_sensitive(key),
+        });
+        log::debug!("Received message: {redacted}");
+    }
+    if let Err(e) = config.decoder_limits.check(msg) {
+        reject_message(e, tx).await?;
+        return Ok((true, auth));
+    }
     match serde_json::from_str(msg) {
-        Ok(Some(msg)) => match msg {
-            CM::AuthorizationRequest(msg) => {
-                if authorized.is_some() {
-                    return Err(WorterbuchError::AlreadyAuthorized);
-                }
-                log::trace!("Authorizing client {client_id} …");
-                authorized = Some(authorize(msg, tx, config).await?);
-                log::trace!("Authorizing client {client_id} done.");
+        Ok(Some(msg)) => {
+            process_client_message(
+                client_id,
+                msg,
+                worterbuch,
+                tx,
+                auth,
+                config,
+                key_prefix,
+                wire_format,
+                in_flight,
+            )
+            .await
+        }
+        Ok(None) => {
+            // client disconnected
+            Ok((false, auth))
+        }
+        Err(e) => {
+            log::error!("Error decoding message: {e}");
+            Ok((false, auth))
+        }
+    }
+}
+
+/// Processes a binary frame, which is decoded using the wire format the client switched to.
+#[allow(clippy::too_many_arguments)]
+pub async fn process_incoming_binary_message(
+    client_id: Uuid,
+    msg: &[u8],
+    worterbuch: &CloneableWbApi,
+    tx: &mpsc::Sender<ServerMessage>,
+    auth: Option<JwtClaims>,
+    config: &Config,
+    key_prefix: &watch::Sender<Option<Key>>,
+    wire_format: &watch::Sender<(WireFormat, Compression)>,
+    in_flight: &InFlight,
+) -> WorterbuchResult<(bool, Option<JwtClaims>)> {
+    let (format, compression) = *wire_format.borrow();
+    if !format.is_binary() {
+        log::error!(
+            "Client {client_id} sent a binary message without switching to a binary wire format."
+        );
+        return Ok((false, auth));
+    }
+    match format.decode::<CM>(msg, compression, &config.decoder_limits) {
+        Ok(msg) => {
+            // binary messages cannot be redacted, so only their type is logged
+            log::debug!("Received {format} message: {}", msg.message_type());
+            process_client_message(
+                client_id,
+                msg,
+                worterbuch,
+                tx,
+                auth,
+                config,
+                key_prefix,
+                Some(wire_format),
+                in_flight,
+            )
+            .await
+        }
+        Err(ConnectionError::WorterbuchError(e)) => {
+            reject_message(e, tx).await?;
+            Ok((true, auth))
+        }
+        Err(e) => {
+            log::error!("Error decoding {format} message: {e}");
+            Ok((false, auth))
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn process_client_message(
+    client_id: Uuid,
+    msg: CM,
+    worterbuch: &CloneableWbApi,
+    tx: &mpsc::Sender<ServerMessage>,
+    auth: Option<JwtClaims>,
+    config: &Config,
+    key_prefix: &watch::Sender<Option<Key>>,
+    wire_format: Option<&watch::Sender<(WireFormat, Compression)>>,
+    in_flight: &InFlight,
+) -> WorterbuchResult<(bool, Option<JwtClaims>)> {
+    #[cfg(feature = "cluster")]
+    let worterbuch = &worterbuch.for_client(auth.as_ref());
+    let worterbuch =
+        &worterbuch.hiding_user_db(config.user_db && users::hides_user_db(auth.as_ref()));
+    let mut authorized = auth;
+    let auth_settings = AuthSettings {
+        client_id,
+        required: config.auth_required(),
+        user_db: config.user_db,
+        authorizer: config.authorizer.as_ref(),
+        acl: config.acl.as_ref(),
+        replication: config.replication.as_ref(),
+    };
+    match with_key_prefix(counted(msg), key_prefix) {
+        CM::KeyPrefix(msg) => {
+            log::trace!(
+                "Setting key prefix for client {client_id} to '{}'",
+                msg.prefix
+            );
+            let prefix = Some(msg.prefix).filter(|p| !p.is_empty());
+            key_prefix.send_replace(prefix);
+        }
+        CM::SwitchWireFormat(msg) => match wire_format {
+            Some(wire_format)
+                if msg.compression.is_none()
+                    || Compression::supported().contains(&msg.compression) =>
+            {
+                log::debug!(
+                    "Switching wire format for client {client_id} to {} (compression: {})",
+                    msg.format,
+                    msg.compression
+                );
+                wire_format.send_replace((msg.format, msg.compression));
             }
-            CM::Get(msg) => {
-                if check_auth(
-                    auth_required,
-                    Privilege::Read,
-                    &msg.key,
+            _ => {
+                reject_message(WorterbuchError::ProtocolNegotiationFailed, tx).await?;
+            }
+        },
+        CM::ClientInfo(msg) => {
+            log::trace!("Setting info for client {client_id} to {msg:?}");
+            worterbuch.set_client_info(client_id, msg).await?;
+        }
+        CM::AuthorizationRequest(msg) => {
+            if authorized.is_some() {
+                return Err(WorterbuchError::AlreadyAuthorized);
+            }
+            log::trace!("Authorizing client {client_id} …");
+            authorized = Some(authorize(msg, client_id, worterbuch, tx, config).await?);
+            log::trace!("Authorizing client {client_id} done.");
+        }
+        CM::AuthenticationRequest(msg) => {
+            if authorized.is_some() {
+                return Err(WorterbuchError::AlreadyAuthorized);
+            }
+            log::trace!("Authenticating client {client_id} …");
+            authorized = Some(authenticate(msg, client_id, worterbuch, tx, config).await?);
+            log::trace!("Authenticating client {client_id} done.");
+        }
+        CM::Get(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Read,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Getting value for client {} …", client_id);
+                get(msg, worterbuch, tx, config).await?;
+                log::trace!("Getting value for client {} done.", client_id);
+            }
+        }
+        CM::GetAt(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Read,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                get_at(msg, worterbuch, tx).await?;
+            }
+        }
+        CM::PGet(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Read,
+                &msg.request_pattern,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("PGetting values for client {} …", client_id);
+                pget(msg, worterbuch, tx, config, in_flight).await?;
+                log::trace!("PGetting values for client {} done.", client_id);
+            }
+        }
+        CM::PKeys(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Read,
+                &msg.request_pattern,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                pkeys(msg, worterbuch, tx).await?;
+            }
+        }
+        CM::GetHistory(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Read,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Getting value history for client {} …", client_id);
+                get_history(msg, worterbuch, tx).await?;
+                log::trace!("Getting value history for client {} done.", client_id);
+            }
+        }
+        CM::Set(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+                && (msg.return_previous != Some(true)
+                    || check_auth(
+                        &auth_settings,
+                        Privilege::Read,
+                        &msg.key,
+                        &authorized,
+                        tx,
+                        msg.transaction_id,
+                    )
+                    .await?)
+            {
+                log::trace!("Setting value for client {} …", client_id);
+                set(msg, worterbuch, tx, client_id.to_string()).await?;
+                log::trace!("Setting values for client {} done.", client_id);
+            }
+        }
+        CM::SetMany(msg) => {
+            let mut authorized_all = true;
+            for kvp in &msg.key_value_pairs {
+                if !check_auth(
+                    &auth_settings,
+                    Privilege::Write,
+                    &kvp.key,
                     &authorized,
                     tx,
                     msg.transaction_id,
                 )
                 .await?
                 {
-                    log::trace!("Getting value for client {} …", client_id);
-                    get(msg, worterbuch, tx).await?;
-                    log::trace!("Getting value for client {} done.", client_id);
+                    authorized_all = false;
+                    break;
                 }
             }
-            CM::PGet(msg) => {
-                if check_auth(
-                    auth_required,
-                    Privilege::Read,
-                    &msg.request_pattern,
+            if authorized_all {
+                log::trace!("Setting multiple values for client {} …", client_id);
+                set_many(msg, worterbuch, tx, client_id.to_string()).await?;
+                log::trace!("Setting multiple values for client {} done.", client_id);
+            }
+        }
+        CM::SetExpiring(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+                && check_auth(
+                    &auth_settings,
+                    Privilege::Delete,
+                    &msg.key,
                     &authorized,
                     tx,
                     msg.transaction_id,
                 )
                 .await?
-                {
-                    log::trace!("PGetting values for client {} …", client_id);
-                    pget(msg, worterbuch, tx).await?;
-                    log::trace!("PGetting values for client {} done.", client_id);
-                }
+            {
+                log::trace!("Setting expiring value for client {} …", client_id);
+                set_expiring(msg, worterbuch, tx, client_id.to_string()).await?;
+                log::trace!("Setting expiring value for client {} done.", client_id);
             }
-            CM::Set(msg) => {
-                if check_auth(
-                    auth_required,
-                    Privilege::Write,
+        }
+        CM::Expire(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+                && check_auth(
+                    &auth_settings,
+                    Privilege::Delete,
                     &msg.key,
                     &authorized,
                     tx,
                     msg.transaction_id,
                 )
                 .await?
-                {
-                    log::trace!("Setting value for client {} …", client_id);
-                    set(msg, worterbuch, tx, client_id.to_string()).await?;
-                    log::trace!("Setting values for client {} done.", client_id);
-                }
+            {
+                log::trace!("Changing TTL for client {} …", client_id);
+                expire(msg, worterbuch, tx, client_id.to_string()).await?;
+                log::trace!("Changing TTL for client {} done.", client_id);
             }
-            CM::Publish(msg) => {
-                if check_auth(
-                    auth_required,
-                    Privilege::Write,
+        }
+        CM::SetNx(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Setting value if absent for client {} …", client_id);
+                let SetNx {
+                    transaction_id,
+                    key,
+                    value,
+                } = msg;
+                set_if(transaction_id, key, value, false, worterbuch, tx, client_id).await?;
+                log::trace!("Setting value if absent for client {} done.", client_id);
+            }
+        }
+        CM::SetXx(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Setting value if present for client {} …", client_id);
+                let SetXx {
+                    transaction_id,
+                    key,
+                    value,
+                } = msg;
+                set_if(transaction_id, key, value, true, worterbuch, tx, client_id).await?;
+                log::trace!("Setting value if present for client {} done.", client_id);
+            }
+        }
+        CM::CompareAndSwap(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Compare-and-swap for client {} …", client_id);
+                cas(msg, worterbuch, tx, client_id.to_string()).await?;
+                log::trace!("Compare-and-swap for client {} done.", client_id);
+            }
+        }
+        CM::SetAt(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                set_at(msg, worterbuch, tx, client_id.to_string()).await?;
+            }
+        }
+        CM::Increment(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+                && check_auth(
+                    &auth_settings,
+                    Privilege::Read,
                     &msg.key,
                     &authorized,
                     tx,
                     msg.transaction_id,
                 )
                 .await?
-                {
-                    log::trace!("Publishing value for client {} …", client_id);
-                    publish(msg, worterbuch, tx).await?;
-                    log::trace!("Publishing value for client {} done.", client_id);
-                }
+            {
+                log::trace!("Incrementing value for client {} …", client_id);
+                increment(msg, worterbuch, tx, client_id.to_string()).await?;
+                log::trace!("Incrementing value for client {} done.", client_id);
             }
-            CM::Subscribe(msg) => {
-                if check_auth(
-                    auth_required,
+        }
+        CM::ArrayPush(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+                && check_auth(
+                    &auth_settings,
                     Privilege::Read,
                     &msg.key,
                     &authorized,
@@ -178,139 +674,515 @@ pub async fn process_incoming_message(
                     msg.transaction_id,
                 )
                 .await?
-                {
-                    log::trace!("Making subscription for client {} …", client_id);
-                    subscribe(msg, client_id, worterbuch, tx).await?;
-                    log::trace!("Making subscription for client {} done.", client_id);
-                }
+            {
+                log::trace!("Pushing to array for client {} …", client_id);
+                let ArrayPush {
+                    transaction_id,
+                    key,
+                    value,
+                } = msg;
+                let op = ArrayOp::Push(value);
+                update_array(transaction_id, key, op, worterbuch, tx, client_id).await?;
+                log::trace!("Pushing to array for client {} done.", client_id);
             }
-            CM::PSubscribe(msg) => {
-                if check_auth(
-                    auth_required,
+        }
+        CM::ArrayPop(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+                && check_auth(
+                    &auth_settings,
                     Privilege::Read,
-                    &msg.request_pattern,
+                    &msg.key,
                     &authorized,
                     tx,
                     msg.transaction_id,
                 )
                 .await?
-                {
-                    log::trace!("Making psubscription for client {} …", client_id);
-                    psubscribe(msg, client_id, worterbuch, tx).await?;
-                    log::trace!("Making psubscription for client {} done.", client_id);
-                }
+            {
+                log::trace!("Popping from array for client {} …", client_id);
+                let ArrayPop {
+                    transaction_id,
+                    key,
+                } = msg;
+                let op = ArrayOp::Pop;
+                update_array(transaction_id, key, op, worterbuch, tx, client_id).await?;
+                log::trace!("Popping from array for client {} done.", client_id);
             }
-            CM::Unsubscribe(msg) => unsubscribe(msg, worterbuch, tx, client_id).await?,
-            CM::Delete(msg) => {
-                if check_auth(
-                    auth_required,
-                    Privilege::Delete,
+        }
+        CM::ArrayRemove(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+                && check_auth(
+                    &auth_settings,
+                    Privilege::Read,
                     &msg.key,
                     &authorized,
                     tx,
                     msg.transaction_id,
                 )
                 .await?
-                {
-                    log::trace!("Deleting value for client {} …", client_id);
-                    delete(msg, worterbuch, tx, client_id.to_string()).await?;
-                    log::trace!("Deleting value for client {} done.", client_id);
-                }
+            {
+                log::trace!("Removing from array for client {} …", client_id);
+                let ArrayRemove {
+                    transaction_id,
+                    key,
+                    value,
+                } = msg;
+                let op = ArrayOp::Remove(value);
+                update_array(transaction_id, key, op, worterbuch, tx, client_id).await?;
+                log::trace!("Removing from array for client {} done.", client_id);
             }
-            CM::PDelete(msg) => {
-                if check_auth(
-                    auth_required,
-                    Privilege::Delete,
-                    &msg.request_pattern,
+        }
+        CM::HintInvalidation(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Forwarding invalidation hint of client {} …", client_id);
+                hint_invalidation(msg, worterbuch, tx).await?;
+                log::trace!("Forwarding invalidation hint of client {} done.", client_id);
+            }
+        }
+        CM::Publish(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Publishing value for client {} …", client_id);
+                publish(msg, worterbuch, tx, client_id.to_string()).await?;
+                log::trace!("Publishing value for client {} done.", client_id);
+            }
+        }
+        CM::PublishRetained(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Publishing retained value for client {} …", client_id);
+                publish_retained(msg, worterbuch, tx, client_id.to_string()).await?;
+                log::trace!("Publishing retained value for client {} done.", client_id);
+            }
+        }
+        CM::Reserve(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.request_pattern,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Reserving keys for client {} …", client_id);
+                reserve(msg, worterbuch, tx, client_id.to_string()).await?;
+                log::trace!("Reserving keys for client {} done.", client_id);
+            }
+        }
+        CM::Release(msg) => {
+            release(msg, worterbuch, tx, client_id.to_string()).await?;
+        }
+        CM::Lock(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                lock(msg, worterbuch, tx, client_id.to_string(), in_flight).await?;
+            }
+        }
+        CM::TryLock(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                try_lock(msg, worterbuch, tx, client_id.to_string()).await?;
+            }
+        }
+        CM::Unlock(msg) => {
+            unlock(msg, worterbuch, tx, client_id.to_string()).await?;
+        }
+        CM::Subscribe(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Read,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Making subscription for client {} …", client_id);
+                subscribe(msg, client_id, worterbuch, tx, config).await?;
+                log::trace!("Making subscription for client {} done.", client_id);
+            }
+        }
+        CM::PSubscribe(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Read,
+                &msg.request_pattern,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Making psubscription for client {} …", client_id);
+                psubscribe(msg, client_id, worterbuch, tx, config).await?;
+                log::trace!("Making psubscription for client {} done.", client_id);
+            }
+        }
+        CM::Unsubscribe(msg) => unsubscribe(msg, worterbuch, tx, client_id).await?,
+        CM::Resync(msg) => resync(msg, worterbuch, tx, client_id).await?,
+        CM::Sync(msg) => {
+            if !msg.persist.unwrap_or(false)
+                || check_auth(
+                    &auth_settings,
+                    Privilege::Write,
+                    "#",
                     &authorized,
                     tx,
                     msg.transaction_id,
                 )
                 .await?
-                {
-                    log::trace!("DPeleting value for client {} …", client_id);
-                    pdelete(msg, worterbuch, tx, client_id.to_string()).await?;
-                    log::trace!("DPeleting value for client {} done.", client_id);
-                }
+            {
+                sync(msg, worterbuch, tx).await?;
             }
-            CM::Ls(msg) => {
-                let pattern = &msg
-                    .parent
-                    .as_ref()
-                    .map(|it| format!("{it}/?"))
-                    .unwrap_or("?".to_owned());
-                if check_auth(
-                    auth_required,
-                    Privilege::Read,
-                    pattern,
+        }
+        CM::Cancel(msg) => cancel(msg, tx, in_flight).await?,
+        CM::Delete(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Delete,
+                &msg.key,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Deleting value for client {} …", client_id);
+                delete(msg, worterbuch, tx, client_id.to_string()).await?;
+                log::trace!("Deleting value for client {} done.", client_id);
+            }
+        }
+        CM::PDelete(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Delete,
+                &msg.request_pattern,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("DPeleting value for client {} …", client_id);
+                pdelete(msg, worterbuch, tx, client_id.to_string(), in_flight).await?;
+                log::trace!("DPeleting value for client {} done.", client_id);
+            }
+        }
+        CM::Restore(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &msg.request_pattern,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Restoring values for client {} …", client_id);
+                restore(msg, worterbuch, tx, client_id.to_string()).await?;
+                log::trace!("Restoring values for client {} done.", client_id);
+            }
+        }
+        CM::StartRecording(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Read,
+                &msg.request_pattern,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Starting recording for client {} …", client_id);
+                start_recording(msg, worterbuch, tx).await?;
+                log::trace!("Starting recording for client {} done.", client_id);
+            }
+        }
+        CM::StopRecording(msg) => {
+            log::trace!("Stopping recording for client {} …", client_id);
+            stop_recording(msg, worterbuch, tx, &auth_settings, &authorized).await?;
+            log::trace!("Stopping recording for client {} done.", client_id);
+        }
+        CM::ReplayRecording(msg) => {
+            let target = topic!(msg.target_prefix, "#");
+            if check_auth(
+                &auth_settings,
+                Privilege::Write,
+                &target,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+                && check_auth(
+                    &auth_settings,
+                    Privilege::Delete,
+                    &target,
                     &authorized,
                     tx,
                     msg.transaction_id,
                 )
                 .await?
-                {
-                    log::trace!("Listing subkeys for client {} …", client_id);
-                    ls(msg, worterbuch, tx).await?;
-                    log::trace!("Listing subkeys for client {} done.", client_id);
-                }
-            }
-            CM::SubscribeLs(msg) => {
-                let pattern = &msg
-                    .parent
-                    .as_ref()
-                    .map(|it| format!("{it}/?"))
-                    .unwrap_or("?".to_owned());
-                if check_auth(
-                    auth_required,
-                    Privilege::Read,
-                    pattern,
-                    &authorized,
+            {
+                log::trace!("Replaying recording for client {} …", client_id);
+                replay_recording(
+                    msg,
+                    worterbuch,
                     tx,
-                    msg.transaction_id,
+                    client_id.to_string(),
+                    &auth_settings,
+                    &authorized,
                 )
-                .await?
-                {
-                    log::trace!("Subscribing to subkeys for client {} …", client_id);
-                    subscribe_ls(msg, client_id, worterbuch, tx).await?;
-                    log::trace!("Subscribing to subkeys for client {} done.", client_id);
-                }
+                .await?;
+                log::trace!("Replaying recording for client {} done.", client_id);
             }
-            CM::UnsubscribeLs(msg) => {
-                log::trace!("Unsubscribing to subkeys for client {} …", client_id);
-                unsubscribe_ls(msg, client_id, worterbuch, tx).await?;
-                log::trace!("Unsubscribing to subkeys for client {} done.", client_id);
+        }
+        CM::Ls(msg) => {
+            let pattern = &msg
+                .parent
+                .as_ref()
+                .map(|it| format!("{it}/?"))
+                .unwrap_or("?".to_owned());
+            if check_auth(
+                &auth_settings,
+                Privilege::Read,
+                pattern,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Listing subkeys for client {} …", client_id);
+                ls(msg, worterbuch, tx).await?;
+                log::trace!("Listing subkeys for client {} done.", client_id);
             }
-            CM::Transform(_) => {
-                log::error!("State transformers not implemented yet.");
-                // TODO
-                return Ok((false, authorized));
+        }
+        CM::SubscribeLs(msg) => {
+            let pattern = &msg
+                .parent
+                .as_ref()
+                .map(|it| format!("{it}/?"))
+                .unwrap_or("?".to_owned());
+            if check_auth(
+                &auth_settings,
+                Privilege::Read,
+                pattern,
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Subscribing to subkeys for client {} …", client_id);
+                subscribe_ls(msg, client_id, worterbuch, tx).await?;
+                log::trace!("Subscribing to subkeys for client {} done.", client_id);
             }
-            CM::Keepalive => (),
-        },
-        Ok(None) => {
-            // client disconnected
-            return Ok((false, authorized));
         }
-        Err(e) => {
-            log::error!("Error decoding message: {e}");
+        CM::UnsubscribeLs(msg) => {
+            log::trace!("Unsubscribing to subkeys for client {} …", client_id);
+            unsubscribe_ls(msg, client_id, worterbuch, tx).await?;
+            log::trace!("Unsubscribing to subkeys for client {} done.", client_id);
+        }
+        CM::SubscribeEvents(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Read,
+                &topic!(SYSTEM_TOPIC_ROOT, "#"),
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Subscribing to server events for client {} …", client_id);
+                subscribe_events(msg, client_id, worterbuch, tx).await?;
+                log::trace!(
+                    "Subscribing to server events for client {} done.",
+                    client_id
+                );
+            }
+        }
+        CM::WhoSubscribes(msg) => {
+            if check_auth(
+                &auth_settings,
+                Privilege::Read,
+                &topic!(SYSTEM_TOPIC_ROOT, "#"),
+                &authorized,
+                tx,
+                msg.transaction_id,
+            )
+            .await?
+            {
+                log::trace!("Listing subscribers for client {} …", client_id);
+                who_subscribes(msg, worterbuch, tx).await?;
+                log::trace!("Listing subscribers for client {} done.", client_id);
+            }
+        }
+        CM::Transform(_) => {
+            log::error!("State transformers not implemented yet.");
+            // TODO
             return Ok((false, authorized));
         }
+        CM::Keepalive => (),
     }
 
     Ok((true, authorized))
 }
 
+fn counted(msg: CM) -> CM {
+    metrics::message_received(msg.message_type());
+    msg
+}
+
+fn with_key_prefix(msg: CM, key_prefix: &watch::Sender<Option<Key>>) -> CM {
+    match key_prefix.borrow().as_deref() {
+        Some(prefix) => add_key_prefix(msg, prefix),
+        None => msg,
+    }
+}
+
 pub enum WbFunction {
     Get(Key, oneshot::Sender<WorterbuchResult<(String, Value)>>),
     Set(Key, Value, String, oneshot::Sender<WorterbuchResult<()>>),
-    Publish(Key, Value, oneshot::Sender<WorterbuchResult<()>>),
+    GetAndSet(
+        Key,
+        Value,
+        String,
+        oneshot::Sender<WorterbuchResult<Option<Value>>>,
+    ),
+    SetMany(KeyValuePairs, String, oneshot::Sender<WorterbuchResult<()>>),
+    SetIf(
+        Key,
+        Value,
+        bool,
+        String,
+        oneshot::Sender<WorterbuchResult<()>>,
+    ),
+    SetExpiring(
+        Key,
+        Value,
+        Duration,
+        String,
+        oneshot::Sender<WorterbuchResult<()>>,
+    ),
+    Expire(
+        Key,
+        Option<Duration>,
+        String,
+        oneshot::Sender<WorterbuchResult<()>>,
+    ),
+    Cas(
+        Key,
+        Option<Value>,
+        Option<u64>,
+        Value,
+        String,
+        oneshot::Sender<WorterbuchResult<()>>,
+    ),
+    Increment(
+        Key,
+        Number,
+        String,
+        oneshot::Sender<WorterbuchResult<Value>>,
+    ),
+    SetAt(
+        Key,
+        String,
+        Value,
+        String,
+        oneshot::Sender<WorterbuchResult<()>>,
+    ),
+    UpdateArray(
+        Key,
+        ArrayOp,
+        String,
+        oneshot::Sender<WorterbuchResult<Value>>,
+    ),
+    Publish(Key, Value, String, oneshot::Sender<WorterbuchResult<()>>),
+    PublishRetained(Key, Value, String, oneshot::Sender<WorterbuchResult<bool>>),
+    Reserve(
+        RequestPattern,
+        String,
+        oneshot::Sender<WorterbuchResult<()>>,
+    ),
+    Release(
+        RequestPattern,
+        String,
+        oneshot::Sender<WorterbuchResult<()>>,
+    ),
+    Lock(Key, Duration, String, oneshot::Sender<WorterbuchResult<()>>),
+    TryLock(Key, Duration, String, oneshot::Sender<WorterbuchResult<()>>),
+    Unlock(Key, String, oneshot::Sender<WorterbuchResult<()>>),
     Ls(
         Option<Key>,
         oneshot::Sender<WorterbuchResult<Vec<RegularKeySegment>>>,
     ),
-    PGet(
+    Snapshot(oneshot::Sender<Snapshot>),
+    PGetSnapshot(
         RequestPattern,
-        oneshot::Sender<WorterbuchResult<KeyValuePairs>>,
+        oneshot::Sender<WorterbuchResult<(RequestPattern, Snapshot)>>,
     ),
     Subscribe(
         Uuid,
@@ -335,53 +1207,445 @@ pub enum WbFunction {
         oneshot::Sender<WorterbuchResult<(Receiver<Vec<RegularKeySegment>>, SubscriptionId)>>,
     ),
     Unsubscribe(Uuid, TransactionId, oneshot::Sender<WorterbuchResult<()>>),
+    Resync(Uuid, TransactionId, oneshot::Sender<WorterbuchResult<()>>),
     UnsubscribeLs(Uuid, TransactionId, oneshot::Sender<WorterbuchResult<()>>),
+    SubscribeEvents(Uuid, TransactionId, oneshot::Sender<Receiver<ServerEvent>>),
+    Event(ServerEvent),
+    WhoSubscribes(RequestPattern, oneshot::Sender<Vec<SubscriberInfo>>),
+    GetHistory(Key, Option<usize>, oneshot::Sender<Vec<HistoryEntry>>),
     Delete(Key, String, oneshot::Sender<WorterbuchResult<(Key, Value)>>),
     PDelete(
         RequestPattern,
         String,
         oneshot::Sender<WorterbuchResult<KeyValuePairs>>,
     ),
+    Restore(
+        RequestPattern,
+        String,
+        oneshot::Sender<WorterbuchResult<KeyValuePairs>>,
+    ),
+    PurgeTrash(oneshot::Sender<WorterbuchResult<()>>),
+    StartRecording(String, RequestPattern),
+    StopRecording(String, oneshot::Sender<WorterbuchResult<usize>>),
+    RecordingPattern(String, oneshot::Sender<WorterbuchResult<RequestPattern>>),
+    Recording(
+        String,
+        oneshot::Sender<WorterbuchResult<(RequestPattern, Arc<Vec<RecordedEvent>>)>>,
+    ),
+    StaleKeys(oneshot::Sender<WorterbuchResult<Vec<StaleKey>>>),
+    EnforceRetention(oneshot::Sender<WorterbuchResult<Vec<StaleKey>>>),
+    Compact(oneshot::Sender<usize>),
     Connected(Uuid, SocketAddr, Protocol),
+    ClientInfo(Uuid, ClientInfo),
     Disconnected(Uuid, SocketAddr),
     Config(oneshot::Sender<Config>),
-    Export(oneshot::Sender<WorterbuchResult<Value>>),
-    Len(oneshot::Sender<usize>),
+    Metrics(oneshot::Sender<Metrics>),
+    KeyUsage(usize, oneshot::Sender<Option<KeyUsageReport>>),
+    RemoteAddr(Uuid, oneshot::Sender<Option<SocketAddr>>),
+    AuthAttempt(IpAddr, bool, oneshot::Sender<Option<Duration>>),
     SupportedProtocolVersion(oneshot::Sender<ProtocolVersion>),
+    BootId(oneshot::Sender<String>),
+    WalSize(oneshot::Sender<Option<u64>>),
+    RotateWal(oneshot::Sender<WorterbuchResult<()>>),
+    Sync(bool, oneshot::Sender<WorterbuchResult<bool>>),
+}
+
+impl WbFunction {
+    /// Calls that clean up after a client. They always wait for room in the queue instead of being
+    /// rejected or timing out, since that would leave subscriptions, reservations, locks or the
+    /// client itself behind.
+    fn is_cleanup(&self) -> bool {
+        matches!(
+            self,
+            WbFunction::Unsubscribe(..)
+                | WbFunction::UnsubscribeLs(..)
+                | WbFunction::Release(..)
+                | WbFunction::Unlock(..)
+                | WbFunction::Disconnected(..)
+        )
+    }
+
+    /// Whether the call may be skipped once its requester has given up on the result. Cleanup
+    /// calls and calls that only have side effects nobody waits for must always be processed.
+    fn may_expire(&self) -> bool {
+        !self.is_cleanup()
+            && !matches!(
+                self,
+                WbFunction::Event(..)
+                    | WbFunction::StartRecording(..)
+                    | WbFunction::Connected(..)
+                    | WbFunction::ClientInfo(..)
+                    | WbFunction::AuthAttempt(..)
+            )
+    }
+}
+
+/// A [`WbFunction`] together with the point in time after which its requester no longer waits
+/// for the result.
+pub struct ApiCall {
+    pub function: WbFunction,
+    pub deadline: Option<Instant>,
+}
+
+impl ApiCall {
+    pub fn expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| deadline <= Instant::now())
+    }
 }
 
 #[derive(Clone)]
 pub struct CloneableWbApi {
-    tx: mpsc::Sender<WbFunction>,
+    tx: mpsc::Sender<ApiCall>,
+    reject_when_busy: bool,
+    request_timeout: Option<Duration>,
+    response_timeout: Option<Duration>,
+    hide_user_db: bool,
+    #[cfg(feature = "cluster")]
+    cluster: Option<Cluster>,
 }
 
 impl CloneableWbApi {
-    pub fn new(tx: mpsc::Sender<WbFunction>) -> Self {
-        CloneableWbApi { tx }
+    pub fn new(tx: mpsc::Sender<ApiCall>) -> Self {
+        CloneableWbApi {
+            tx,
+            reject_when_busy: false,
+            request_timeout: None,
+            response_timeout: None,
+            hide_user_db: false,
+            #[cfg(feature = "cluster")]
+            cluster: None,
+        }
+    }
+
+    /// A handle that forwards requests for keys owned by other nodes of the cluster.
+    #[cfg(feature = "cluster")]
+    pub fn with_cluster(&self, cluster: Cluster) -> Self {
+        CloneableWbApi {
+            cluster: Some(cluster),
+            ..self.clone()
+        }
+    }
+
+    /// The handle to serve a client's requests with. Requests forwarded by other cluster nodes
+    /// are served locally, they have been routed already.
+    #[cfg(feature = "cluster")]
+    pub fn for_client(&self, claims: Option<&JwtClaims>) -> Self {
+        match &self.cluster {
+            Some(_) if Cluster::is_peer(claims) => CloneableWbApi {
+                cluster: None,
+                ..self.clone()
+            },
+            _ => self.clone(),
+        }
+    }
+
+    /// A handle that leaves the user database out of the results of pattern requests and exports,
+    /// for clients that may read with wildcards but were not granted access to `$auth`.
+    pub fn hiding_user_db(&self, hide: bool) -> Self {
+        CloneableWbApi {
+            hide_user_db: hide,
+            ..self.clone()
+        }
+    }
+
+    fn visible(&self, mut kvps: KeyValuePairs) -> KeyValuePairs {
+        if self.hide_user_db {
+            kvps.retain(|kvp| !users::is_user_db_key(&kvp.key));
+        }
+        kvps
+    }
+
+    /// A handle whose requests fail with [`WorterbuchError::Busy`] instead of waiting when the
+    /// request queue is full.
+    pub fn rejecting_when_busy(&self) -> Self {
+        CloneableWbApi {
+            reject_when_busy: true,
+            ..self.clone()
+        }
+    }
+
+    /// A handle whose requests are skipped by the store if they are still queued when `timeout`
+    /// has passed, because their requester will have given up on them by then.
+    pub fn with_request_timeout(&self, timeout: Option<Duration>) -> Self {
+        CloneableWbApi {
+            request_timeout: timeout,
+            ..self.clone()
+        }
+    }
+
+    /// A handle whose requests fail with [`WorterbuchError::Timeout`] if the store does not take
+    /// them or does not answer them within `timeout`, instead of waiting forever.
+    pub fn with_response_timeout(&self, timeout: Option<Duration>) -> Self {
+        CloneableWbApi {
+            response_timeout: timeout,
+            ..self.clone()
+        }
+    }
+
+    /// Number of requests waiting to be processed.
+    pub fn queue_depth(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+
+    async fn send(&self, function: WbFunction) -> WorterbuchResult<()> {
+        let deadline = match self.request_timeout {
+            Some(timeout) if function.may_expire() => Some(Instant::now() + timeout),
+            _ => None,
+        };
+        let call = ApiCall { function, deadline };
+        if call.function.is_cleanup() {
+            return Ok(self.tx.send(call).await?);
+        }
+        if !self.reject_when_busy {
+            return match self.response_timeout {
+                Some(t) => Ok(timeout(t, self.tx.send(call))
+                    .await
+                    .map_err(|_| WorterbuchError::Timeout(t))??),
+                None => Ok(self.tx.send(call).await?),
+            };
+        }
+        match self.tx.try_send(call) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(WorterbuchError::Busy),
+            Err(TrySendError::Closed(call)) => Err(SendError(call).into()),
+        }
+    }
+
+    async fn receive<T>(&self, rx: oneshot::Receiver<T>) -> WorterbuchResult<T> {
+        match self.response_timeout {
+            Some(t) => Ok(timeout(t, rx)
+                .await
+                .map_err(|_| WorterbuchError::Timeout(t))??),
+            None => Ok(rx.await?),
+        }
     }
 
     pub async fn get(&self, key: Key) -> WorterbuchResult<(String, Value)> {
+        #[cfg(feature = "cluster")]
+        if let Some((cluster, node)) = self.remote_owner(&key) {
+            return cluster.get(node, key).await;
+        }
         let (tx, rx) = oneshot::channel();
-        self.tx.send(WbFunction::Get(key, tx)).await?;
-        rx.await?
+        self.send(WbFunction::Get(key, tx)).await?;
+        self.receive(rx).await?
+    }
+
+    /// Like [`CloneableWbApi::get`], but only returns the part of the value addressed by the JSON
+    /// pointer.
+    pub async fn get_at(&self, key: Key, pointer: &str) -> WorterbuchResult<Value> {
+        let (key, mut value) = self.get(key).await?;
+        match value.pointer_mut(pointer) {
+            Some(fragment) => Ok(fragment.take()),
+            None => Err(WorterbuchError::NoSuchValue(key + pointer)),
+        }
     }
 
     pub async fn pget<'a>(&self, pattern: RequestPattern) -> WorterbuchResult<KeyValuePairs> {
+        let (pattern, snapshot) = self.pget_snapshot(pattern).await?;
+        self.pget_on_snapshot(snapshot, pattern, false).await
+    }
+
+    /// Like [`CloneableWbApi::pget`], but regular segments of the pattern match keys regardless of
+    /// their case.
+    pub async fn pget_ignoring_case(
+        &self,
+        pattern: RequestPattern,
+    ) -> WorterbuchResult<KeyValuePairs> {
+        let (pattern, snapshot) = self.pget_snapshot(pattern).await?;
+        self.pget_on_snapshot(snapshot, pattern, true).await
+    }
+
+    pub async fn pget_chunked(
+        &self,
+        pattern: RequestPattern,
+        chunk_size: usize,
+    ) -> WorterbuchResult<Receiver<WorterbuchResult<KeyValuePairs>>> {
+        let (pattern, snapshot) = self.pget_snapshot(pattern).await?;
+        let mut chunks = pget_chunked_on_snapshot(snapshot, pattern, chunk_size);
+        if !self.hide_user_db {
+            return Ok(chunks);
+        }
+        let (tx, rx) = mpsc::channel(1);
+        let visible = self.clone();
+        spawn(async move {
+            while let Some(chunk) = chunks.recv().await {
+                if tx.send(chunk.map(|c| visible.visible(c))).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    async fn pget_on_snapshot(
+        &self,
+        snapshot: Snapshot,
+        pattern: RequestPattern,
+        case_insensitive: bool,
+    ) -> WorterbuchResult<KeyValuePairs> {
+        let local_pattern = pattern.clone();
+        let mut kvps = self
+            .on_snapshot(snapshot, move |snapshot| {
+                if case_insensitive {
+                    snapshot.pget_ignoring_case(&local_pattern)
+                } else {
+                    snapshot.pget(&local_pattern)
+                }
+            })
+            .await??;
+        kvps.extend(self.remote_pget(&pattern, case_insensitive).await?);
+        Ok(self.visible(kvps))
+    }
+
+    pub async fn pkeys(&self, pattern: RequestPattern) -> WorterbuchResult<Vec<Key>> {
+        let (pattern, snapshot) = self.pget_snapshot(pattern).await?;
+        let local_pattern = pattern.clone();
+        let mut keys = self
+            .on_snapshot(snapshot, move |snapshot| snapshot.pkeys(&local_pattern))
+            .await??;
+        keys.extend(self.remote_pkeys(&pattern).await?);
+        if self.hide_user_db {
+            keys.retain(|key| !users::is_user_db_key(key));
+        }
+        Ok(keys)
+    }
+
+    #[cfg(feature = "cluster")]
+    fn remote_owner(&self, key: &str) -> Option<(&Cluster, &crate::cluster::Node)> {
+        let cluster = self.cluster.as_ref()?;
+        cluster.owner(key).map(|node| (cluster, node))
+    }
+
+    #[cfg(feature = "cluster")]
+    async fn remote_pget(
+        &self,
+        pattern: &RequestPattern,
+        case_insensitive: bool,
+    ) -> WorterbuchResult<KeyValuePairs> {
+        match &self.cluster {
+            Some(cluster) => cluster.pget(pattern, case_insensitive).await,
+            None => Ok(KeyValuePairs::new()),
+        }
+    }
+
+    #[cfg(not(feature = "cluster"))]
+    async fn remote_pget(
+        &self,
+        _pattern: &RequestPattern,
+        _case_insensitive: bool,
+    ) -> WorterbuchResult<KeyValuePairs> {
+        Ok(KeyValuePairs::new())
+    }
+
+    #[cfg(feature = "cluster")]
+    async fn remote_pkeys(&self, pattern: &RequestPattern) -> WorterbuchResult<Vec<Key>> {
+        match &self.cluster {
+            Some(cluster) => cluster.pkeys(pattern).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    #[cfg(not(feature = "cluster"))]
+    async fn remote_pkeys(&self, _pattern: &RequestPattern) -> WorterbuchResult<Vec<Key>> {
+        Ok(Vec::new())
+    }
+
+    #[cfg(feature = "cluster")]
+    async fn remote_pdelete(&self, pattern: &RequestPattern) -> WorterbuchResult<KeyValuePairs> {
+        match &self.cluster {
+            Some(cluster) => cluster.pdelete(pattern).await,
+            None => Ok(KeyValuePairs::new()),
+        }
+    }
+
+    #[cfg(not(feature = "cluster"))]
+    async fn remote_pdelete(&self, _pattern: &RequestPattern) -> WorterbuchResult<KeyValuePairs> {
+        Ok(KeyValuePairs::new())
+    }
+
+    /// Merges the events of a local subscription with those of the same subscription on the
+    /// other nodes that may hold matching keys.
+    #[cfg(feature = "cluster")]
+    async fn with_remote_events(
+        &self,
+        (mut local, id): (Receiver<PStateEvent>, SubscriptionId),
+        pattern: &RequestPattern,
+        unique: bool,
+        live_only: bool,
+    ) -> WorterbuchResult<(Receiver<PStateEvent>, SubscriptionId)> {
+        let Some(cluster) = &self.cluster else {
+            return Ok((local, id));
+        };
+        if cluster.remotes(pattern).is_empty() {
+            return Ok((local, id));
+        }
+
+        let (tx, rx) = mpsc::channel(1);
+        if let Err(e) = cluster
+            .psubscribe(id.clone(), pattern, unique, live_only, &tx)
+            .await
+        {
+            cluster.unsubscribe(&id);
+            self.unsubscribe(id.client_id, id.transaction_id).await.ok();
+            return Err(e);
+        }
+        spawn(async move {
+            while let Some(event) = local.recv().await {
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok((rx, id))
+    }
+
+    pub async fn snapshot(&self) -> WorterbuchResult<Snapshot> {
         let (tx, rx) = oneshot::channel();
-        self.tx.send(WbFunction::PGet(pattern, tx)).await?;
-        rx.await?
+        self.send(WbFunction::Snapshot(tx)).await?;
+        self.receive(rx).await
+    }
+
+    async fn pget_snapshot(
+        &self,
+        pattern: RequestPattern,
+    ) -> WorterbuchResult<(RequestPattern, Snapshot)> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::PGetSnapshot(pattern, tx)).await?;
+        self.receive(rx).await?
+    }
+
+    /// Takes a snapshot and runs the given function on it outside of the worterbuch's own task, so
+    /// expensive reads don't block writes.
+    async fn with_snapshot<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(Snapshot) -> T + Send + 'static,
+    ) -> WorterbuchResult<T> {
+        let snapshot = self.snapshot().await?;
+        self.on_snapshot(snapshot, f).await
+    }
+
+    async fn on_snapshot<T: Send + 'static>(
+        &self,
+        snapshot: Snapshot,
+        f: impl FnOnce(Snapshot) -> T + Send + 'static,
+    ) -> WorterbuchResult<T> {
+        spawn_blocking(move || f(snapshot))
+            .await
+            .map_err(|e| WorterbuchError::Other(Box::new(e), "Internal server error".to_owned()))
     }
 
     pub async fn set(&self, key: Key, value: Value, client_id: String) -> WorterbuchResult<()> {
+        #[cfg(feature = "cluster")]
+        if let Some((cluster, node)) = self.remote_owner(&key) {
+            return cluster.set(node, key, value).await;
+        }
         let (tx, rx) = oneshot::channel();
         let trace = client_id != INTERNAL_CLIENT_ID;
         if trace {
             log::trace!("Sending set request to core system …");
         }
-        let res = self
-            .tx
-            .send(WbFunction::Set(key, value, client_id, tx))
-            .await;
+        let res = self.send(WbFunction::Set(key, value, client_id, tx)).await;
         if trace {
             log::trace!("Sending set request to core system done.");
         }
@@ -389,206 +1653,1392 @@ impl CloneableWbApi {
         if trace {
             log::trace!("Waiting for response to set request …");
         }
-        let res = rx.await;
+        let res = self.receive(rx).await;
         if trace {
             log::trace!("Waiting for response to set request done.");
         }
         res?
     }
 
-    pub async fn publish(&self, key: Key, value: Value) -> WorterbuchResult<()> {
-        let (tx, rx) = oneshot::channel();
-        self.tx.send(WbFunction::Publish(key, value, tx)).await?;
-        rx.await?
-    }
-
-    pub async fn ls(&self, parent: Option<Key>) -> WorterbuchResult<Vec<RegularKeySegment>> {
-        let (tx, rx) = oneshot::channel();
-        self.tx.send(WbFunction::Ls(parent, tx)).await?;
-        rx.await?
-    }
-
-    pub async fn subscribe(
+    pub async fn get_and_set(
         &self,
-        client_id: Uuid,
-        transaction_id: TransactionId,
         key: Key,
-        unique: bool,
-        live_only: bool,
-    ) -> WorterbuchResult<(Receiver<PStateEvent>, SubscriptionId)> {
+        value: Value,
+        client_id: String,
+    ) -> WorterbuchResult<Option<Value>> {
+        #[cfg(feature = "cluster")]
+        if let Some((cluster, node)) = self.remote_owner(&key) {
+            return cluster.get_and_set(node, key, value).await;
+        }
         let (tx, rx) = oneshot::channel();
-        self.tx
-            .send(WbFunction::Subscribe(
-                client_id,
-                transaction_id,
-                key,
-                unique,
-                live_only,
-                tx,
-            ))
+        self.send(WbFunction::GetAndSet(key, value, client_id, tx))
             .await?;
-        rx.await?
+        self.receive(rx).await?
     }
 
-    pub async fn psubscribe(
+    pub async fn set_many(
         &self,
-        client_id: Uuid,
-        transaction_id: TransactionId,
-        pattern: RequestPattern,
-        unique: bool,
-        live_only: bool,
-    ) -> WorterbuchResult<(Receiver<PStateEvent>, SubscriptionId)> {
+        key_value_pairs: KeyValuePairs,
+        client_id: String,
+    ) -> WorterbuchResult<()> {
+        #[cfg(feature = "cluster")]
+        let key_value_pairs = self.remote_set_many(key_value_pairs).await?;
         let (tx, rx) = oneshot::channel();
-        self.tx
-            .send(WbFunction::PSubscribe(
-                client_id,
-                transaction_id,
-                pattern,
-                unique,
-                live_only,
-                tx,
-            ))
+        self.send(WbFunction::SetMany(key_value_pairs, client_id, tx))
             .await?;
-        rx.await?
+        self.receive(rx).await?
     }
 
-    pub async fn subscribe_ls(
+    /// Forwards the pairs owned by other nodes and returns the ones to be stored locally.
+    #[cfg(feature = "cluster")]
+    async fn remote_set_many(
         &self,
-        client_id: Uuid,
-        transaction_id: TransactionId,
-        parent: Option<Key>,
-    ) -> WorterbuchResult<(Receiver<Vec<RegularKeySegment>>, SubscriptionId)> {
-        let (tx, rx) = oneshot::channel();
-        self.tx
-            .send(WbFunction::SubscribeLs(
-                client_id,
-                transaction_id,
-                parent,
-                tx,
-            ))
-            .await?;
-        rx.await?
+        key_value_pairs: KeyValuePairs,
+    ) -> WorterbuchResult<KeyValuePairs> {
+        let Some(cluster) = &self.cluster else {
+            return Ok(key_value_pairs);
+        };
+        let mut local = KeyValuePairs::new();
+        let mut remote: Vec<(&crate::cluster::Node, KeyValuePairs)> = Vec::new();
+        for kvp in key_value_pairs {
+            match cluster.owner(&kvp.key) {
+                Some(node) => match remote.iter_mut().find(|(n, _)| *n == node) {
+                    Some((_, kvps)) => kvps.push(kvp),
+                    None => remote.push((node, vec![kvp])),
+                },
+                None => local.push(kvp),
+            }
+        }
+        for (node, kvps) in remote {
+            cluster.set_many(node, kvps).await?;
+        }
+        Ok(local)
     }
 
-    pub async fn unsubscribe(
+    pub async fn set_expiring(
         &self,
-        client_id: Uuid,
-        transaction_id: TransactionId,
+        key: Key,
+        value: Value,
+        ttl: Duration,
+        client_id: String,
     ) -> WorterbuchResult<()> {
         let (tx, rx) = oneshot::channel();
-        self.tx
-            .send(WbFunction::Unsubscribe(client_id, transaction_id, tx))
+        self.send(WbFunction::SetExpiring(key, value, ttl, client_id, tx))
             .await?;
-        rx.await?
+        self.receive(rx).await?
     }
 
-    pub async fn unsubscribe_ls(
+    pub async fn expire(
         &self,
-        client_id: Uuid,
-        transaction_id: TransactionId,
+        key: Key,
+        ttl: Option<Duration>,
+        client_id: String,
     ) -> WorterbuchResult<()> {
         let (tx, rx) = oneshot::channel();
-        self.tx
-            .send(WbFunction::UnsubscribeLs(client_id, transaction_id, tx))
+        self.send(WbFunction::Expire(key, ttl, client_id, tx))
             .await?;
-        rx.await?
+        self.receive(rx).await?
     }
 
-    pub async fn delete(&self, key: Key, client_id: String) -> WorterbuchResult<(Key, Value)> {
+    pub async fn set_if(
+        &self,
+        key: Key,
+        value: Value,
+        exists: bool,
+        client_id: String,
+    ) -> WorterbuchResult<()> {
         let (tx, rx) = oneshot::channel();
-        self.tx.send(WbFunction::Delete(key, client_id, tx)).await?;
-        rx.await?
+        self.send(WbFunction::SetIf(key, value, exists, client_id, tx))
+            .await?;
+        self.receive(rx).await?
     }
 
-    pub async fn pdelete(
+    pub async fn cas(
         &self,
-        pattern: RequestPattern,
+        key: Key,
+        expected: Option<Value>,
+        version: Option<u64>,
+        value: Value,
         client_id: String,
-    ) -> WorterbuchResult<KeyValuePairs> {
+    ) -> WorterbuchResult<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::Cas(
+            key, expected, version, value, client_id, tx,
+        ))
+        .await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn increment(
+        &self,
+        key: Key,
+        delta: Number,
+        client_id: String,
+    ) -> WorterbuchResult<Value> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::Increment(key, delta, client_id, tx))
+            .await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn set_at(
+        &self,
+        key: Key,
+        pointer: String,
+        value: Value,
+        client_id: String,
+    ) -> WorterbuchResult<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::SetAt(key, pointer, value, client_id, tx))
+            .await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn update_array(
+        &self,
+        key: Key,
+        op: ArrayOp,
+        client_id: String,
+    ) -> WorterbuchResult<Value> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::UpdateArray(key, op, client_id, tx))
+            .await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn publish(&self, key: Key, value: Value, client_id: String) -> WorterbuchResult<()> {
+        #[cfg(feature = "cluster")]
+        if let Some((cluster, node)) = self.remote_owner(&key) {
+            return cluster.publish(node, key, value).await;
+        }
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::Publish(key, value, client_id, tx))
+            .await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn publish_retained(
+        &self,
+        key: Key,
+        value: Value,
+        client_id: String,
+    ) -> WorterbuchResult<()> {
+        #[cfg(feature = "cluster")]
+        if let Some((cluster, node)) = self.remote_owner(&key) {
+            return cluster.publish_retained(node, key, value).await;
+        }
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::PublishRetained(key, value, client_id, tx))
+            .await?;
+        self.receive(rx).await??;
+        Ok(())
+    }
+
+    pub async fn reserve(
+        &self,
+        pattern: RequestPattern,
+        client_id: String,
+    ) -> WorterbuchResult<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::Reserve(pattern, client_id, tx))
+            .await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn release(
+        &self,
+        pattern: RequestPattern,
+        client_id: String,
+    ) -> WorterbuchResult<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::Release(pattern, client_id, tx))
+            .await?;
+        self.receive(rx).await?
+    }
+
+    /// Waits until the lock is granted, which may take arbitrarily long, so unlike other requests
+    /// this is not subject to the API timeout.
+    pub async fn lock(&self, key: Key, lease: Duration, client_id: String) -> WorterbuchResult<()> {
         let (tx, rx) = oneshot::channel();
-        self.tx
-            .send(WbFunction::PDelete(pattern, client_id, tx))
+        self.send(WbFunction::Lock(key, lease, client_id, tx))
             .await?;
         rx.await?
     }
 
+    pub async fn try_lock(
+        &self,
+        key: Key,
+        lease: Duration,
+        client_id: String,
+    ) -> WorterbuchResult<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::TryLock(key, lease, client_id, tx))
+            .await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn unlock(&self, key: Key, client_id: String) -> WorterbuchResult<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::Unlock(key, client_id, tx)).await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn ls(&self, parent: Option<Key>) -> WorterbuchResult<Vec<RegularKeySegment>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::Ls(parent, tx)).await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn subscribe(
+        &self,
+        client_id: Uuid,
+        transaction_id: TransactionId,
+        key: Key,
+        unique: bool,
+        live_only: bool,
+    ) -> WorterbuchResult<(Receiver<PStateEvent>, SubscriptionId)> {
+        #[cfg(feature = "cluster")]
+        let pattern = key.clone();
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::Subscribe(
+            client_id,
+            transaction_id,
+            key,
+            unique,
+            live_only,
+            tx,
+        ))
+        .await?;
+        let subscription = self.receive(rx).await??;
+        #[cfg(feature = "cluster")]
+        let subscription = self
+            .with_remote_events(subscription, &pattern, unique, live_only)
+            .await?;
+        Ok(subscription)
+    }
+
+    pub async fn psubscribe(
+        &self,
+        client_id: Uuid,
+        transaction_id: TransactionId,
+        pattern: RequestPattern,
+        unique: bool,
+        live_only: bool,
+    ) -> WorterbuchResult<(Receiver<PStateEvent>, SubscriptionId)> {
+        #[cfg(feature = "cluster")]
+        let remote_pattern = pattern.clone();
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::PSubscribe(
+            client_id,
+            transaction_id,
+            pattern,
+            unique,
+            live_only,
+            tx,
+        ))
+        .await?;
+        let subscription = self.receive(rx).await??;
+        #[cfg(feature = "cluster")]
+        let subscription = self
+            .with_remote_events(subscription, &remote_pattern, unique, live_only)
+            .await?;
+        if self.hide_user_db {
+            let (events, id) = subscription;
+            return Ok((without_user_db(events, live_only), id));
+        }
+        Ok(subscription)
+    }
+
+    pub async fn subscribe_ls(
+        &self,
+        client_id: Uuid,
+        transaction_id: TransactionId,
+        parent: Option<Key>,
+    ) -> WorterbuchResult<(Receiver<Vec<RegularKeySegment>>, SubscriptionId)> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::SubscribeLs(
+            client_id,
+            transaction_id,
+            parent,
+            tx,
+        ))
+        .await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn unsubscribe(
+        &self,
+        client_id: Uuid,
+        transaction_id: TransactionId,
+    ) -> WorterbuchResult<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::Unsubscribe(client_id, transaction_id, tx))
+            .await?;
+        let res = self.receive(rx).await?;
+        #[cfg(feature = "cluster")]
+        if let Some(cluster) = &self.cluster {
+            let id = SubscriptionId::new(client_id, transaction_id);
+            if cluster.unsubscribe(&id) {
+                return Ok(());
+            }
+        }
+        res
+    }
+
+    pub async fn resync(
+        &self,
+        client_id: Uuid,
+        transaction_id: TransactionId,
+    ) -> WorterbuchResult<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::Resync(client_id, transaction_id, tx))
+            .await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn unsubscribe_ls(
+        &self,
+        client_id: Uuid,
+        transaction_id: TransactionId,
+    ) -> WorterbuchResult<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::UnsubscribeLs(client_id, transaction_id, tx))
+            .await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn subscribe_events(
+        &self,
+        client_id: Uuid,
+        transaction_id: TransactionId,
+    ) -> WorterbuchResult<Receiver<ServerEvent>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::SubscribeEvents(client_id, transaction_id, tx))
+            .await?;
+        self.receive(rx).await
+    }
+
+    pub async fn emit_event(&self, event: ServerEvent) -> WorterbuchResult<()> {
+        self.send(WbFunction::Event(event)).await?;
+        Ok(())
+    }
+
+    pub async fn who_subscribes(
+        &self,
+        pattern: RequestPattern,
+    ) -> WorterbuchResult<Vec<SubscriberInfo>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::WhoSubscribes(pattern, tx)).await?;
+        self.receive(rx).await
+    }
+
+    pub async fn get_history(
+        &self,
+        key: Key,
+        limit: Option<usize>,
+    ) -> WorterbuchResult<Vec<HistoryEntry>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::GetHistory(key, limit, tx)).await?;
+        self.receive(rx).await
+    }
+
+    pub async fn delete(&self, key: Key, client_id: String) -> WorterbuchResult<(Key, Value)> {
+        #[cfg(feature = "cluster")]
+        if let Some((cluster, node)) = self.remote_owner(&key) {
+            return cluster.delete(node, key).await;
+        }
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::Delete(key, client_id, tx)).await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn pdelete(
+        &self,
+        pattern: RequestPattern,
+        client_id: String,
+    ) -> WorterbuchResult<KeyValuePairs> {
+        let rx = self.queue_pdelete(pattern.clone(), client_id).await?;
+        self.finish_pdelete(&pattern, rx).await
+    }
+
+    /// Waits for a queued pdelete and adds the keys deleted on other cluster nodes.
+    async fn finish_pdelete(
+        &self,
+        pattern: &RequestPattern,
+        rx: oneshot::Receiver<WorterbuchResult<KeyValuePairs>>,
+    ) -> WorterbuchResult<KeyValuePairs> {
+        let mut deleted = self.receive(rx).await??;
+        deleted.extend(self.remote_pdelete(pattern).await?);
+        Ok(deleted)
+    }
+
+    /// Enqueues a pdelete without waiting for it to be processed. The store skips it if the
+    /// returned receiver has been dropped by then.
+    async fn queue_pdelete(
+        &self,
+        pattern: RequestPattern,
+        client_id: String,
+    ) -> WorterbuchResult<oneshot::Receiver<WorterbuchResult<KeyValuePairs>>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::PDelete(pattern, client_id, tx))
+            .await?;
+        Ok(rx)
+    }
+
+    pub async fn restore(
+        &self,
+        pattern: RequestPattern,
+        client_id: String,
+    ) -> WorterbuchResult<KeyValuePairs> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::Restore(pattern, client_id, tx))
+            .await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn purge_trash(&self) -> WorterbuchResult<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::PurgeTrash(tx)).await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn start_recording(
+        &self,
+        name: String,
+        pattern: RequestPattern,
+    ) -> WorterbuchResult<()> {
+        self.send(WbFunction::StartRecording(name, pattern)).await?;
+        Ok(())
+    }
+
+    pub async fn stop_recording(&self, name: String) -> WorterbuchResult<usize> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::StopRecording(name, tx)).await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn recording_pattern(&self, name: String) -> WorterbuchResult<RequestPattern> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::RecordingPattern(name, tx)).await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn recording(
+        &self,
+        name: String,
+    ) -> WorterbuchResult<(RequestPattern, Arc<Vec<RecordedEvent>>)> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::Recording(name, tx)).await?;
+        let (pattern, events) = self.receive(rx).await??;
+        if !self.hide_user_db {
+            return Ok((pattern, events));
+        }
+        let events = events
+            .iter()
+            .filter(|event| !users::is_user_db_key(&event.key))
+            .cloned()
+            .collect();
+        Ok((pattern, Arc::new(events)))
+    }
+
+    pub async fn stale_keys(&self) -> WorterbuchResult<Vec<StaleKey>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::StaleKeys(tx)).await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn enforce_retention(&self) -> WorterbuchResult<Vec<StaleKey>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::EnforceRetention(tx)).await?;
+        self.receive(rx).await?
+    }
+
+    pub async fn compact(&self) -> WorterbuchResult<usize> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::Compact(tx)).await?;
+        self.receive(rx).await
+    }
+
+    pub async fn wal_size(&self) -> WorterbuchResult<Option<u64>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::WalSize(tx)).await?;
+        self.receive(rx).await
+    }
+
+    pub async fn rotate_wal(&self) -> WorterbuchResult<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::RotateWal(tx)).await?;
+        self.receive(rx).await?
+    }
+
+    /// Resolves once all requests queued before it have been applied. With `persist`, the
+    /// write-ahead log is written through to the disk as well, `false` is returned if there is no
+    /// log to make the changes durable.
+    pub async fn sync(&self, persist: bool) -> WorterbuchResult<bool> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::Sync(persist, tx)).await?;
+        self.receive(rx).await?
+    }
+
     pub async fn connected(
         &self,
         client_id: Uuid,
         remote_addr: SocketAddr,
         protocol: Protocol,
     ) -> WorterbuchResult<()> {
-        self.tx
-            .send(WbFunction::Connected(client_id, remote_addr, protocol))
+        self.send(WbFunction::Connected(client_id, remote_addr, protocol))
             .await?;
         Ok(())
     }
 
+    pub async fn set_client_info(&self, client_id: Uuid, info: ClientInfo) -> WorterbuchResult<()> {
+        self.send(WbFunction::ClientInfo(client_id, info)).await?;
+        Ok(())
+    }
+
     pub async fn disconnected(
         &self,
         client_id: Uuid,
         remote_addr: SocketAddr,
     ) -> WorterbuchResult<()> {
-        self.tx
-            .send(WbFunction::Disconnected(client_id, remote_addr))
+        #[cfg(feature = "cluster")]
+        if let Some(cluster) = &self.cluster {
+            cluster.disconnected(client_id);
+        }
+        self.send(WbFunction::Disconnected(client_id, remote_addr))
             .await?;
         Ok(())
     }
 
     pub async fn config(&self) -> WorterbuchResult<Config> {
         let (tx, rx) = oneshot::channel();
-        self.tx.send(WbFunction::Config(tx)).await?;
-        Ok(rx.await?)
+        self.send(WbFunction::Config(tx)).await?;
+        self.receive(rx).await
     }
 
-    pub async fn export(&self) -> WorterbuchResult<Value> {
+    pub async fn metrics(&self) -> WorterbuchResult<Metrics> {
         let (tx, rx) = oneshot::channel();
-        self.tx.send(WbFunction::Export(tx)).await?;
-        rx.await?
+        self.send(WbFunction::Metrics(tx)).await?;
+        let mut metrics = self.receive(rx).await?;
+        metrics.channel_backlog = self.queue_depth();
+        Ok(metrics)
+    }
+
+    pub async fn key_usage(&self, top: usize) -> WorterbuchResult<Option<KeyUsageReport>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::KeyUsage(top, tx)).await?;
+        self.receive(rx).await
+    }
+
+    pub async fn remote_addr(&self, client_id: Uuid) -> WorterbuchResult<Option<SocketAddr>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::RemoteAddr(client_id, tx)).await?;
+        self.receive(rx).await
+    }
+
+    pub async fn auth_attempt(
+        &self,
+        remote_addr: IpAddr,
+        success: bool,
+    ) -> WorterbuchResult<Option<Duration>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::AuthAttempt(remote_addr, success, tx))
+            .await?;
+        self.receive(rx).await
+    }
+
+    pub async fn export(&self) -> WorterbuchResult<Value> {
+        let mut export = self.with_snapshot(|snapshot| snapshot.export()).await??;
+        if self.hide_user_db {
+            if let Some(Value::Object(obj)) = export.pointer_mut("/data/t") {
+                obj.remove(AUTH_TOPIC_ROOT);
+            }
+        }
+        Ok(export)
     }
 
-    pub async fn len(&self) -> WorterbuchResult<usize> {
+    pub async fn supported_protocol_version(&self) -> WorterbuchResult<ProtocolVersion> {
         let (tx, rx) = oneshot::channel();
-        self.tx.send(WbFunction::Len(tx)).await?;
-        Ok(rx.await?)
+        self.send(WbFunction::SupportedProtocolVersion(tx)).await?;
+        self.receive(rx).await
     }
 
-    pub async fn supported_protocol_version(&self) -> WorterbuchResult<ProtocolVersion> {
-        let (tx, rx) = oneshot::channel();
-        self.tx
-            .send(WbFunction::SupportedProtocolVersion(tx))
-            .await?;
-        Ok(rx.await?)
-    }
-}
+    /// All protocol versions this server can speak. Each gets its own websocket path.
+    pub async fn supported_protocol_versions(&self) -> WorterbuchResult<ProtocolVersions> {
+        Ok(vec![self.supported_protocol_version().await?])
+    }
+
+    pub async fn boot_id(&self) -> WorterbuchResult<String> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WbFunction::BootId(tx)).await?;
+        self.receive(rx).await
+    }
+}
+
+/// Runs an authentication attempt and records its outcome. If the remote address is locked out
+/// after too many failed attempts by the time the outcome is recorded, the attempt is rejected.
+pub async fn guarded_auth_attempt(
+    worterbuch: &CloneableWbApi,
+    remote_addr: Option<IpAddr>,
+    attempt: impl Future<Output = AuthorizationResult<JwtClaims>>,
+) -> WorterbuchResult<AuthorizationResult<JwtClaims>> {
+    let Some(remote_addr) = remote_addr else {
+        return Ok(attempt.await);
+    };
+    let res = attempt.await;
+    if let Some(remaining) = worterbuch.auth_attempt(remote_addr, res.is_ok()).await? {
+        log::warn!("Rejecting authentication attempt from locked out address {remote_addr}.");
+        return Ok(Err(AuthorizationError::LockedOut(
+            remaining.as_secs().max(1),
+        )));
+    }
+    Ok(res)
+}
+
+async fn authorize(
+    msg: AuthorizationRequest,
+    client_id: Uuid,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    config: &Config,
+) -> WorterbuchResult<JwtClaims> {
+    let remote_addr = worterbuch.remote_addr(client_id).await?.map(|a| a.ip());
+    let claims = guarded_auth_attempt(worterbuch, remote_addr, async {
+        get_claims(Some(&msg.auth_token), config)
+    })
+    .await?;
+    match claims {
+        Ok(claims) => {
+            client
+                .send(ServerMessage::Authorized(Ack { transaction_id: 0 }))
+                .await
+                .context(|| "Error sending HANDSHAKE message".to_owned())?;
+            Ok(claims)
+        }
+        Err(e) => {
+            handle_store_error(WorterbuchError::Unauthorized(e.clone()), client, 0).await?;
+            Err(WorterbuchError::Unauthorized(e))
+        }
+    }
+}
+
+async fn authenticate(
+    msg: AuthenticationRequest,
+    client_id: Uuid,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    config: &Config,
+) -> WorterbuchResult<JwtClaims> {
+    if !config.user_db {
+        let e = AuthorizationError::UserDatabaseDisabled;
+        handle_store_error(WorterbuchError::Unauthorized(e.clone()), client, 0).await?;
+        return Err(WorterbuchError::Unauthorized(e));
+    }
+    let remote_addr = worterbuch.remote_addr(client_id).await?.map(|a| a.ip());
+    let claims = guarded_auth_attempt(
+        worterbuch,
+        remote_addr,
+        users::authenticate(worterbuch, &msg.username, msg.password),
+    )
+    .await?;
+    match claims {
+        Ok(claims) => {
+            client
+                .send(ServerMessage::Authorized(Ack { transaction_id: 0 }))
+                .await
+                .context(|| "Error sending HANDSHAKE message".to_owned())?;
+            Ok(claims)
+        }
+        Err(e) => {
+            handle_store_error(WorterbuchError::Unauthorized(e.clone()), client, 0).await?;
+            Err(WorterbuchError::Unauthorized(e))
+        }
+    }
+}
+
+async fn get(
+    msg: Get,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    config: &Config,
+) -> WorterbuchResult<()> {
+    let deprecated = config.deprecations.find(&msg.key).map(|_| true);
+
+    let key_value = match worterbuch.get(msg.key).await {
+        Ok(key_value) => key_value.into(),
+        Err(e) => {
+            handle_store_error(e, client, msg.transaction_id).await?;
+            return Ok(());
+        }
+    };
+
+    let response = State {
+        transaction_id: msg.transaction_id,
+        event: StateEvent::KeyValue(key_value),
+        deprecated,
+        seq: None,
+    };
+
+    client
+        .send(ServerMessage::State(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending STATE message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+/// Relays subscription events without keys of the user database. Events that only contained such
+/// keys are dropped, except for the initial state.
+fn without_user_db(mut events: Receiver<PStateEvent>, live_only: bool) -> Receiver<PStateEvent> {
+    let (tx, rx) = mpsc::channel(1);
+    spawn(async move {
+        let mut initial_state = !live_only;
+        while let Some(event) = events.recv().await {
+            let initial = mem::replace(&mut initial_state, false);
+            let (kvps, deleted) = match event {
+                PStateEvent::KeyValuePairs(kvps) => (kvps, false),
+                PStateEvent::Deleted(kvps) => (kvps, true),
+            };
+            let total = kvps.len();
+            let kvps: KeyValuePairs = kvps
+                .into_iter()
+                .filter(|kvp| !users::is_user_db_key(&kvp.key))
+                .collect();
+            if kvps.is_empty() && total > 0 && !initial {
+                continue;
+            }
+            let event = if deleted {
+                PStateEvent::Deleted(kvps)
+            } else {
+                PStateEvent::KeyValuePairs(kvps)
+            };
+            if tx.send(event).await.is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+fn pget_chunked_on_snapshot(
+    snapshot: Snapshot,
+    pattern: RequestPattern,
+    chunk_size: usize,
+) -> Receiver<WorterbuchResult<KeyValuePairs>> {
+    let (tx, rx) = mpsc::channel(1);
+    spawn_blocking(move || snapshot.pget_chunked(&pattern, chunk_size, &tx));
+    rx
+}
+
+/// Takes the snapshot right away so the result reflects the state at the time of the request, but
+/// scans it in the background so the client can cancel the request while it is running.
+async fn pget(
+    msg: PGet,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    config: &Config,
+    in_flight: &InFlight,
+) -> WorterbuchResult<()> {
+    let deprecated = config.deprecations.find(&msg.request_pattern).map(|_| true);
+
+    let (pattern, snapshot) = match worterbuch.pget_snapshot(msg.request_pattern.clone()).await {
+        Ok(it) => it,
+        Err(e) => {
+            handle_store_error(e, client, msg.transaction_id).await?;
+            return Ok(());
+        }
+    };
+
+    let transaction_id = msg.transaction_id;
+    let worterbuch = worterbuch.clone();
+    let client = client.clone();
+    in_flight.spawn(transaction_id, async move {
+        let res = match msg.chunk_size {
+            Some(chunk_size) if !msg.is_windowed() && !msg.is_case_insensitive() => {
+                pget_chunked(
+                    msg,
+                    pattern,
+                    snapshot,
+                    chunk_size,
+                    deprecated,
+                    &worterbuch,
+                    &client,
+                )
+                .await
+            }
+            _ => pget_unchunked(msg, pattern, snapshot, deprecated, &worterbuch, &client).await,
+        };
+        if let Err(e) = res {
+            log::debug!("Could not answer pget request {transaction_id}: {e}");
+        }
+    });
+
+    Ok(())
+}
+
+async fn pget_unchunked(
+    msg: PGet,
+    pattern: RequestPattern,
+    snapshot: Snapshot,
+    deprecated: Option<bool>,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+) -> WorterbuchResult<()> {
+    let values = match worterbuch
+        .pget_on_snapshot(snapshot, pattern, msg.is_case_insensitive())
+        .await
+    {
+        Ok(values) => values.into_iter().map(KeyValuePair::from).collect(),
+        Err(e) => {
+            handle_store_error(e, client, msg.transaction_id).await?;
+            return Ok(());
+        }
+    };
+
+    let mut values = sort_and_window(values, msg.sort.as_ref(), msg.offset, msg.limit);
+
+    // windowed requests can only be chunked once all matches have been collected and sorted
+    let chunk_size = msg.chunk_size.unwrap_or(usize::MAX).max(1);
+    loop {
+        let rest = values.split_off(chunk_size.min(values.len()));
+        let more = !rest.is_empty();
+        send_pstate_chunk(&msg, values, more, deprecated, client).await?;
+        if !more {
+            return Ok(());
+        }
+        values = rest;
+    }
+}
+
+async fn pget_chunked(
+    msg: PGet,
+    pattern: RequestPattern,
+    snapshot: Snapshot,
+    chunk_size: usize,
+    deprecated: Option<bool>,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+) -> WorterbuchResult<()> {
+    let mut chunks = pget_chunked_on_snapshot(snapshot, pattern.clone(), chunk_size);
+
+    // always hold back one chunk so the last one can be sent without the 'more' flag
+    let mut pending = KeyValuePairs::new();
+    while let Some(chunk) = chunks.recv().await {
+        let chunk = match chunk {
+            Ok(chunk) => worterbuch.visible(chunk),
+            Err(e) => {
+                handle_store_error(e, client, msg.transaction_id).await?;
+                return Ok(());
+            }
+        };
+        if chunk.is_empty() {
+            continue;
+        }
+        let previous = mem::replace(&mut pending, chunk);
+        if !previous.is_empty() {
+            send_pstate_chunk(&msg, previous, true, deprecated, client).await?;
+        }
+    }
+
+    let remote = match worterbuch.remote_pget(&pattern, false).await {
+        Ok(remote) => worterbuch.visible(remote),
+        Err(e) => {
+            handle_store_error(e, client, msg.transaction_id).await?;
+            return Ok(());
+        }
+    };
+    for chunk in remote.chunks(chunk_size.max(1)) {
+        let previous = mem::replace(&mut pending, chunk.to_vec());
+        if !previous.is_empty() {
+            send_pstate_chunk(&msg, previous, true, deprecated, client).await?;
+        }
+    }
+
+    send_pstate_chunk(&msg, pending, false, deprecated, client).await
+}
+
+async fn send_pstate_chunk(
+    msg: &PGet,
+    values: KeyValuePairs,
+    more: bool,
+    deprecated: Option<bool>,
+    client: &mpsc::Sender<ServerMessage>,
+) -> WorterbuchResult<()> {
+    let response = PState {
+        transaction_id: msg.transaction_id,
+        request_pattern: msg.request_pattern.clone(),
+        event: PStateEvent::KeyValuePairs(values),
+        deprecated,
+        more: more.then_some(true),
+        seq: None,
+    };
+
+    client
+        .send(ServerMessage::PState(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending PSTATE message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+async fn set(
+    msg: Set,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
+) -> WorterbuchResult<()> {
+    if msg.return_previous == Some(true) {
+        return get_and_set(msg, worterbuch, client, client_id).await;
+    }
+
+    if let Err(e) = worterbuch.set(msg.key, msg.value, client_id).await {
+        handle_store_error(e, client, msg.transaction_id).await?;
+        return Ok(());
+    }
+
+    let response = Ack {
+        transaction_id: msg.transaction_id,
+    };
+
+    log::trace!("Value set, queuing Ack …");
+    let res = client.send(ServerMessage::Ack(response)).await;
+    log::trace!("Value set, queuing Ack done.");
+    res.context(|| {
+        format!(
+            "Error sending ACK message for transaction ID {}",
+            msg.transaction_id
+        )
+    })?;
+
+    Ok(())
+}
+
+async fn get_and_set(
+    msg: Set,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
+) -> WorterbuchResult<()> {
+    let previous = match worterbuch
+        .get_and_set(msg.key.clone(), msg.value, client_id)
+        .await
+    {
+        Ok(it) => it,
+        Err(e) => {
+            handle_store_error(e, client, msg.transaction_id).await?;
+            return Ok(());
+        }
+    };
+
+    let response = match previous {
+        Some(value) => ServerMessage::State(State {
+            transaction_id: msg.transaction_id,
+            event: StateEvent::KeyValue(KeyValuePair {
+                key: msg.key,
+                value,
+            }),
+            deprecated: None,
+            seq: None,
+        }),
+        None => ServerMessage::Ack(Ack {
+            transaction_id: msg.transaction_id,
+        }),
+    };
+
+    client.send(response).await.context(|| {
+        format!(
+            "Error sending previous value for transaction ID {}",
+            msg.transaction_id
+        )
+    })?;
+
+    Ok(())
+}
+
+async fn set_many(
+    msg: SetMany,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
+) -> WorterbuchResult<()> {
+    if let Err(e) = worterbuch.set_many(msg.key_value_pairs, client_id).await {
+        handle_store_error(e, client, msg.transaction_id).await?;
+        return Ok(());
+    }
+
+    let response = Ack {
+        transaction_id: msg.transaction_id,
+    };
+
+    client
+        .send(ServerMessage::Ack(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending ACK message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+async fn publish_retained(
+    msg: PublishRetained,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
+) -> WorterbuchResult<()> {
+    if let Err(e) = worterbuch
+        .publish_retained(msg.key, msg.value, client_id)
+        .await
+    {
+        handle_store_error(e, client, msg.transaction_id).await?;
+        return Ok(());
+    }
+
+    let response = Ack {
+        transaction_id: msg.transaction_id,
+    };
+
+    client
+        .send(ServerMessage::Ack(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending ACK message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+async fn set_expiring(
+    msg: SetExpiring,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
+) -> WorterbuchResult<()> {
+    let ttl = Duration::from_millis(msg.ttl_millis);
+    if let Err(e) = worterbuch
+        .set_expiring(msg.key, msg.value, ttl, client_id)
+        .await
+    {
+        handle_store_error(e, client, msg.transaction_id).await?;
+        return Ok(());
+    }
+
+    let response = Ack {
+        transaction_id: msg.transaction_id,
+    };
+
+    client
+        .send(ServerMessage::Ack(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending ACK message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+async fn reserve(
+    msg: Reserve,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
+) -> WorterbuchResult<()> {
+    if let Err(e) = worterbuch.reserve(msg.request_pattern, client_id).await {
+        handle_store_error(e, client, msg.transaction_id).await?;
+        return Ok(());
+    }
+
+    let response = Ack {
+        transaction_id: msg.transaction_id,
+    };
+
+    client
+        .send(ServerMessage::Ack(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending ACK message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+async fn release(
+    msg: Release,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
+) -> WorterbuchResult<()> {
+    if let Err(e) = worterbuch.release(msg.request_pattern, client_id).await {
+        handle_store_error(e, client, msg.transaction_id).await?;
+        return Ok(());
+    }
+
+    let response = Ack {
+        transaction_id: msg.transaction_id,
+    };
+
+    client
+        .send(ServerMessage::Ack(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending ACK message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+/// Waits for the lock in the background so the client can keep using the connection and cancel
+/// the request while it is waiting.
+async fn lock(
+    msg: Lock,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
+    in_flight: &InFlight,
+) -> WorterbuchResult<()> {
+    let transaction_id = msg.transaction_id;
+    let worterbuch = worterbuch.clone();
+    let client = client.clone();
+    in_flight.spawn(transaction_id, async move {
+        let lease = Duration::from_millis(msg.lease_millis);
+        let res = match worterbuch.lock(msg.key, lease, client_id).await {
+            Ok(()) => client
+                .send(ServerMessage::Ack(Ack { transaction_id }))
+                .await
+                .context(|| {
+                    format!("Error sending ACK message for transaction ID {transaction_id}")
+                }),
+            Err(e) => handle_store_error(e, &client, transaction_id).await,
+        };
+        if let Err(e) = res {
+            log::debug!("Could not answer lock request {transaction_id}: {e}");
+        }
+    });
+
+    Ok(())
+}
+
+async fn try_lock(
+    msg: TryLock,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
+) -> WorterbuchResult<()> {
+    let lease = Duration::from_millis(msg.lease_millis);
+    if let Err(e) = worterbuch.try_lock(msg.key, lease, client_id).await {
+        handle_store_error(e, client, msg.transaction_id).await?;
+        return Ok(());
+    }
+
+    let response = Ack {
+        transaction_id: msg.transaction_id,
+    };
+
+    client
+        .send(ServerMessage::Ack(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending ACK message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+async fn unlock(
+    msg: Unlock,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
+) -> WorterbuchResult<()> {
+    if let Err(e) = worterbuch.unlock(msg.key, client_id).await {
+        handle_store_error(e, client, msg.transaction_id).await?;
+        return Ok(());
+    }
+
+    let response = Ack {
+        transaction_id: msg.transaction_id,
+    };
+
+    client
+        .send(ServerMessage::Ack(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending ACK message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+async fn set_if(
+    transaction_id: TransactionId,
+    key: Key,
+    value: Value,
+    exists: bool,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: Uuid,
+) -> WorterbuchResult<()> {
+    if let Err(e) = worterbuch
+        .set_if(key, value, exists, client_id.to_string())
+        .await
+    {
+        handle_store_error(e, client, transaction_id).await?;
+        return Ok(());
+    }
+
+    let response = Ack { transaction_id };
+
+    client
+        .send(ServerMessage::Ack(response))
+        .await
+        .context(|| format!("Error sending ACK message for transaction ID {transaction_id}"))?;
+
+    Ok(())
+}
+
+async fn update_array(
+    transaction_id: TransactionId,
+    key: Key,
+    op: ArrayOp,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: Uuid,
+) -> WorterbuchResult<()> {
+    let value = match worterbuch
+        .update_array(key.clone(), op, client_id.to_string())
+        .await
+    {
+        Ok(it) => it,
+        Err(e) => {
+            handle_store_error(e, client, transaction_id).await?;
+            return Ok(());
+        }
+    };
+
+    let response = State {
+        transaction_id,
+        event: StateEvent::KeyValue(KeyValuePair { key, value }),
+        deprecated: None,
+        seq: None,
+    };
+
+    client
+        .send(ServerMessage::State(response))
+        .await
+        .context(|| format!("Error sending STATE message for transaction ID {transaction_id}"))?;
+
+    Ok(())
+}
+
+async fn get_at(
+    msg: GetAt,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+) -> WorterbuchResult<()> {
+    let value = match worterbuch.get_at(msg.key.clone(), &msg.pointer).await {
+        Ok(it) => it,
+        Err(e) => {
+            handle_store_error(e, client, msg.transaction_id).await?;
+            return Ok(());
+        }
+    };
+
+    let response = State {
+        transaction_id: msg.transaction_id,
+        event: StateEvent::KeyValue(KeyValuePair {
+            key: msg.key,
+            value,
+        }),
+        deprecated: None,
+        seq: None,
+    };
+
+    client
+        .send(ServerMessage::State(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending STATE message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+async fn set_at(
+    msg: SetAt,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
+) -> WorterbuchResult<()> {
+    if let Err(e) = worterbuch
+        .set_at(msg.key, msg.pointer, msg.value, client_id)
+        .await
+    {
+        handle_store_error(e, client, msg.transaction_id).await?;
+        return Ok(());
+    }
+
+    let response = Ack {
+        transaction_id: msg.transaction_id,
+    };
+
+    client
+        .send(ServerMessage::Ack(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending ACK message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
 
-async fn authorize(
-    msg: AuthorizationRequest,
-    client: &mpsc::Sender<ServerMessage>,
-    config: &Config,
-) -> WorterbuchResult<JwtClaims> {
-    match get_claims(Some(&msg.auth_token), config) {
-        Ok(claims) => {
-            client
-                .send(ServerMessage::Authorized(Ack { transaction_id: 0 }))
-                .await
-                .context(|| "Error sending HANDSHAKE message".to_owned())?;
-            Ok(claims)
-        }
-        Err(e) => {
-            handle_store_error(WorterbuchError::Unauthorized(e.clone()), client, 0).await?;
-            Err(WorterbuchError::Unauthorized(e))
-        }
-    }
+    Ok(())
 }
 
-async fn get(
-    msg: Get,
+async fn increment(
+    msg: Increment,
     worterbuch: &CloneableWbApi,
     client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
 ) -> WorterbuchResult<()> {
-    let key_value = match worterbuch.get(msg.key).await {
-        Ok(key_value) => key_value.into(),
+    let value = match worterbuch
+        .increment(msg.key.clone(), msg.delta, client_id)
+        .await
+    {
+        Ok(it) => it,
         Err(e) => {
             handle_store_error(e, client, msg.transaction_id).await?;
             return Ok(());
@@ -597,7 +3047,12 @@ async fn get(
 
     let response = State {
         transaction_id: msg.transaction_id,
-        event: StateEvent::KeyValue(key_value),
+        event: StateEvent::KeyValue(KeyValuePair {
+            key: msg.key,
+            value,
+        }),
+        deprecated: None,
+        seq: None,
     };
 
     client
@@ -613,31 +3068,64 @@ async fn get(
     Ok(())
 }
 
-async fn pget(
-    msg: PGet,
+async fn cas(
+    msg: CompareAndSwap,
     worterbuch: &CloneableWbApi,
     client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
 ) -> WorterbuchResult<()> {
-    let values = match worterbuch.pget(msg.request_pattern.clone()).await {
-        Ok(values) => values.into_iter().map(KeyValuePair::from).collect(),
-        Err(e) => {
-            handle_store_error(e, client, msg.transaction_id).await?;
-            return Ok(());
-        }
+    if let Err(e) = worterbuch
+        .cas(msg.key, msg.expected, msg.version, msg.value, client_id)
+        .await
+    {
+        handle_store_error(e, client, msg.transaction_id).await?;
+        return Ok(());
+    }
+
+    let response = Ack {
+        transaction_id: msg.transaction_id,
     };
 
-    let response = PState {
+    client
+        .send(ServerMessage::Ack(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending ACK message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+async fn hint_invalidation(
+    msg: HintInvalidation,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+) -> WorterbuchResult<()> {
+    let hint = InvalidationHint {
+        key: msg.key,
+        stale_in_millis: msg.stale_in_millis,
+    };
+    if let Err(e) = worterbuch
+        .emit_event(ServerEvent::InvalidationHint(hint))
+        .await
+    {
+        handle_store_error(e, client, msg.transaction_id).await?;
+        return Ok(());
+    }
+
+    let response = Ack {
         transaction_id: msg.transaction_id,
-        request_pattern: msg.request_pattern,
-        event: PStateEvent::KeyValuePairs(values),
     };
 
     client
-        .send(ServerMessage::PState(response))
+        .send(ServerMessage::Ack(response))
         .await
         .context(|| {
             format!(
-                "Error sending PSTATE message for transaction ID {}",
+                "Error sending ACK message for transaction ID {}",
                 msg.transaction_id
             )
         })?;
@@ -645,13 +3133,14 @@ async fn pget(
     Ok(())
 }
 
-async fn set(
-    msg: Set,
+async fn expire(
+    msg: Expire,
     worterbuch: &CloneableWbApi,
     client: &mpsc::Sender<ServerMessage>,
     client_id: String,
 ) -> WorterbuchResult<()> {
-    if let Err(e) = worterbuch.set(msg.key, msg.value, client_id).await {
+    let ttl = msg.ttl_millis.map(Duration::from_millis);
+    if let Err(e) = worterbuch.expire(msg.key, ttl, client_id).await {
         handle_store_error(e, client, msg.transaction_id).await?;
         return Ok(());
     }
@@ -660,15 +3149,15 @@ async fn set(
         transaction_id: msg.transaction_id,
     };
 
-    log::trace!("Value set, queuing Ack …");
-    let res = client.send(ServerMessage::Ack(response)).await;
-    log::trace!("Value set, queuing Ack done.");
-    res.context(|| {
-        format!(
-            "Error sending ACK message for transaction ID {}",
-            msg.transaction_id
-        )
-    })?;
+    client
+        .send(ServerMessage::Ack(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending ACK message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
 
     Ok(())
 }
@@ -677,8 +3166,28 @@ async fn publish(
     msg: Publish,
     worterbuch: &CloneableWbApi,
     client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
 ) -> WorterbuchResult<()> {
-    if let Err(e) = worterbuch.publish(msg.key, msg.value).await {
+    let value = if msg.correlation_id.is_some() || msg.reply_to.is_some() {
+        let correlated = CorrelatedValue {
+            correlation_id: msg.correlation_id,
+            reply_to: msg.reply_to,
+            value: msg.value,
+        };
+        match serde_json::to_value(correlated) {
+            Ok(it) => it,
+            Err(e) => {
+                let e =
+                    WorterbuchError::SerDeError(e, "could not wrap correlated value".to_owned());
+                handle_store_error(e, client, msg.transaction_id).await?;
+                return Ok(());
+            }
+        }
+    } else {
+        msg.value
+    };
+
+    if let Err(e) = worterbuch.publish(msg.key, value, client_id).await {
         handle_store_error(e, client, msg.transaction_id).await?;
         return Ok(());
     }
@@ -705,7 +3214,10 @@ async fn subscribe(
     client_id: Uuid,
     worterbuch: &CloneableWbApi,
     client: &mpsc::Sender<ServerMessage>,
+    config: &Config,
 ) -> WorterbuchResult<bool> {
+    let deprecated = config.deprecations.find(&msg.key).map(|_| true);
+
     let (mut rx, subscription) = match worterbuch
         .subscribe(
             client_id,
@@ -738,25 +3250,44 @@ async fn subscribe(
         })?;
 
     let transaction_id = msg.transaction_id;
+    let live_only = msg.live_only.unwrap_or(false);
+    let initial_state_marker = msg.initial_state_marker.unwrap_or(false);
+
+    if initial_state_marker && live_only {
+        send_initial_state_complete(client, transaction_id).await?;
+    }
 
     let wb_unsub = worterbuch.clone();
     let client_sub = client.clone();
 
     spawn(async move {
         log::debug!("Receiving events for subscription {subscription:?} …");
+        let mut initial_state_pending = initial_state_marker && !live_only;
+        let mut seq = 0;
         while let Some(event) = rx.recv().await {
             let state_events: Vec<StateEvent> = event.into();
 
             for event in state_events {
+                seq += 1;
                 let state = State {
                     transaction_id,
                     event,
+                    deprecated,
+                    seq: Some(seq),
                 };
                 if let Err(e) = client_sub.send(ServerMessage::State(state)).await {
                     log::error!("Error sending STATE message to client: {e}");
                     break;
                 };
             }
+
+            if initial_state_pending {
+                initial_state_pending = false;
+                if let Err(e) = send_initial_state_complete(&client_sub, transaction_id).await {
+                    log::error!("{e}");
+                    break;
+                }
+            }
         }
 
         match wb_unsub.unsubscribe(client_id, transaction_id).await {
@@ -778,7 +3309,9 @@ async fn psubscribe(
     client_id: Uuid,
     worterbuch: &CloneableWbApi,
     client: &mpsc::Sender<ServerMessage>,
+    config: &Config,
 ) -> WorterbuchResult<bool> {
+    let deprecated = config.deprecations.find(&msg.request_pattern).map(|_| true);
     let live_only = msg.live_only.unwrap_or(false);
 
     let (rx, subscription) = match worterbuch
@@ -814,6 +3347,11 @@ async fn psubscribe(
 
     let transaction_id = msg.transaction_id;
     let request_pattern = msg.request_pattern;
+    let initial_state_marker = msg.initial_state_marker.unwrap_or(false);
+
+    if initial_state_marker && live_only {
+        send_initial_state_complete(client, transaction_id).await?;
+    }
 
     let wb_unsub = worterbuch.clone();
     let client_sub = client.clone();
@@ -826,8 +3364,10 @@ async fn psubscribe(
             aggregate_duration,
             channel_buffer_size,
             live_only,
+            initial_state_marker,
             request_pattern,
             transaction_id,
+            deprecated,
         };
         spawn(async move {
             aggregate_loop(rx, subscription, client_sub).await;
@@ -850,6 +3390,8 @@ async fn psubscribe(
                 request_pattern,
                 client_sub,
                 subscription,
+                initial_state_marker && !live_only,
+                deprecated,
             )
             .await;
 
@@ -874,34 +3416,68 @@ async fn forward_loop(
     request_pattern: String,
     client_sub: mpsc::Sender<ServerMessage>,
     subscription: SubscriptionId,
+    mut initial_state_pending: bool,
+    deprecated: Option<bool>,
 ) {
     log::debug!("Receiving events for subscription {subscription:?} …");
+    let mut seq = 0;
     while let Some(event) = rx.recv().await {
+        seq += 1;
         let event = PState {
             transaction_id,
             request_pattern: request_pattern.clone(),
             event,
+            deprecated,
+            more: None,
+            seq: Some(seq),
         };
         if let Err(e) = client_sub.send(ServerMessage::PState(event)).await {
             log::error!("Error sending STATE message to client: {e}");
             break;
         }
+        if initial_state_pending {
+            initial_state_pending = false;
+            if let Err(e) = send_initial_state_complete(&client_sub, transaction_id).await {
+                log::error!("{e}");
+                break;
+            }
+        }
     }
 }
 
+async fn send_initial_state_complete(
+    client: &mpsc::Sender<ServerMessage>,
+    transaction_id: TransactionId,
+) -> WorterbuchResult<()> {
+    client
+        .send(ServerMessage::InitialStateComplete(Ack { transaction_id }))
+        .await
+        .context(|| {
+            format!(
+                "Error sending INITIAL STATE COMPLETE message for transaction ID {transaction_id}"
+            )
+        })
+}
+
 async fn aggregate_loop(
     mut rx: Receiver<PStateEvent>,
     subscription: SubscriptionInfo,
     client_sub: mpsc::Sender<ServerMessage>,
 ) {
+    let mut seq = 0;
+
     if !subscription.live_only {
         log::debug!("Immediately forwarding current state to new subscription {subscription:?} …");
 
         if let Some(event) = rx.recv().await {
+            seq += 1;
             let event = PState {
                 transaction_id: subscription.transaction_id,
                 request_pattern: subscription.request_pattern.clone(),
                 event,
+                deprecated: subscription.deprecated,
+                more: None,
+                seq: Some(seq),
             };
 
             if let Err(e) = client_sub.send(ServerMessage::PState(event)).await {
@@ -911,6 +3487,15 @@ async fn aggregate_loop(
         } else {
             return;
         }
+
+        if subscription.initial_state_marker {
+            if let Err(e) =
+                send_initial_state_complete(&client_sub, subscription.transaction_id).await
+            {
+                log::error!("{e}");
+                return;
+            }
+        }
     }
 
     log::debug!("Aggregating events for subscription {subscription:?} …");
@@ -921,6 +3506,8 @@ async fn aggregate_loop(
         subscription.aggregate_duration,
         subscription.transaction_id,
         subscription.channel_buffer_size,
+        seq,
+        subscription.deprecated,
     );
 
     while let Some(event) = rx.recv().await {
@@ -958,31 +3545,181 @@ async fn unsubscribe(
     Ok(())
 }
 
-async fn delete(
-    msg: Delete,
+/// Requests of a connection are processed one after another and writes are only acknowledged
+/// once they have been applied, so by now all previous writes of this client are in the store or
+/// at least queued ahead of the sync.
+async fn sync(
+    msg: SyncRequest,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+) -> WorterbuchResult<()> {
+    // without a write-ahead log, changes only become durable with the next periodic snapshot,
+    // a snapshot per request would let any client keep the server busy exporting the store
+    let res = match worterbuch.sync(msg.persist.unwrap_or(false)).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(WorterbuchError::Other(
+            "no write-ahead log configured".into(),
+            "could not persist store".to_owned(),
+        )),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = res {
+        handle_store_error(e, client, msg.transaction_id).await?;
+        return Ok(());
+    }
+
+    let response = Ack {
+        transaction_id: msg.transaction_id,
+    };
+
+    client
+        .send(ServerMessage::Ack(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending ACK message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+async fn resync(
+    msg: Resync,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: Uuid,
+) -> WorterbuchResult<()> {
+    if let Err(e) = worterbuch.resync(client_id, msg.transaction_id).await {
+        handle_store_error(e, client, msg.transaction_id).await?;
+        return Ok(());
+    };
+    let response = Ack {
+        transaction_id: msg.transaction_id,
+    };
+
+    client
+        .send(ServerMessage::Ack(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending ACK message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+/// Answers the cancelled request with a [`WorterbuchError::Cancelled`] error. Cancelling a request
+/// that has already been answered is a no-op.
+async fn cancel(
+    msg: Cancel,
+    client: &mpsc::Sender<ServerMessage>,
+    in_flight: &InFlight,
+) -> WorterbuchResult<()> {
+    if in_flight.cancel(msg.transaction_id) {
+        handle_store_error(WorterbuchError::Cancelled, client, msg.transaction_id).await?;
+    }
+    Ok(())
+}
+
+async fn delete(
+    msg: Delete,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
+) -> WorterbuchResult<()> {
+    let key_value = match worterbuch.delete(msg.key, client_id).await {
+        Ok(key_value) => key_value.into(),
+        Err(e) => {
+            handle_store_error(e, client, msg.transaction_id).await?;
+            return Ok(());
+        }
+    };
+
+    let response = State {
+        transaction_id: msg.transaction_id,
+        event: StateEvent::Deleted(key_value),
+        deprecated: None,
+        seq: None,
+    };
+
+    client
+        .send(ServerMessage::State(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending STATE message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+/// Queues the deletion right away to keep it in order with the client's other requests, but waits
+/// for it in the background so the client can still cancel it while it is queued.
+async fn pdelete(
+    msg: PDelete,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
+    in_flight: &InFlight,
+) -> WorterbuchResult<()> {
+    let rx = match worterbuch
+        .queue_pdelete(msg.request_pattern.clone(), client_id)
+        .await
+    {
+        Ok(it) => it,
+        Result::Err(e) => {
+            handle_store_error(e, client, msg.transaction_id).await?;
+            return Ok(());
+        }
+    };
+
+    let transaction_id = msg.transaction_id;
+    let worterbuch = worterbuch.clone();
+    let client = client.clone();
+    in_flight.spawn(transaction_id, async move {
+        if let Err(e) = pdelete_response(msg, rx, &worterbuch, &client).await {
+            log::debug!("Could not answer pdelete request {transaction_id}: {e}");
+        }
+    });
+
+    Ok(())
+}
+
+async fn pdelete_response(
+    msg: PDelete,
+    rx: oneshot::Receiver<WorterbuchResult<KeyValuePairs>>,
     worterbuch: &CloneableWbApi,
     client: &mpsc::Sender<ServerMessage>,
-    client_id: String,
 ) -> WorterbuchResult<()> {
-    let key_value = match worterbuch.delete(msg.key, client_id).await {
-        Ok(key_value) => key_value.into(),
-        Err(e) => {
+    let deleted = match worterbuch.finish_pdelete(&msg.request_pattern, rx).await {
+        Ok(it) => it,
+        Result::Err(e) => {
             handle_store_error(e, client, msg.transaction_id).await?;
             return Ok(());
         }
     };
 
-    let response = State {
+    let response = PState {
         transaction_id: msg.transaction_id,
-        event: StateEvent::Deleted(key_value),
+        request_pattern: msg.request_pattern,
+        event: PStateEvent::Deleted(deleted),
+        deprecated: None,
+        more: None,
+        seq: None,
     };
 
     client
-        .send(ServerMessage::State(response))
+        .send(ServerMessage::PState(response))
         .await
         .context(|| {
             format!(
-                "Error sending STATE message for transaction ID {}",
+                "Error sending PSTATE message for transaction ID {}",
                 msg.transaction_id
             )
         })?;
@@ -990,14 +3727,14 @@ async fn delete(
     Ok(())
 }
 
-async fn pdelete(
-    msg: PDelete,
+async fn restore(
+    msg: Restore,
     worterbuch: &CloneableWbApi,
     client: &mpsc::Sender<ServerMessage>,
     client_id: String,
 ) -> WorterbuchResult<()> {
-    let deleted = match worterbuch
-        .pdelete(msg.request_pattern.clone(), client_id)
+    let restored = match worterbuch
+        .restore(msg.request_pattern.clone(), client_id)
         .await
     {
         Ok(it) => it,
@@ -1010,7 +3747,10 @@ async fn pdelete(
     let response = PState {
         transaction_id: msg.transaction_id,
         request_pattern: msg.request_pattern,
-        event: PStateEvent::Deleted(deleted),
+        event: PStateEvent::KeyValuePairs(restored),
+        deprecated: None,
+        more: None,
+        seq: None,
     };
 
     client
@@ -1026,6 +3766,100 @@ async fn pdelete(
     Ok(())
 }
 
+async fn start_recording(
+    msg: StartRecording,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+) -> WorterbuchResult<()> {
+    worterbuch
+        .start_recording(msg.name, msg.request_pattern)
+        .await?;
+    send_ack(msg.transaction_id, client).await
+}
+
+/// Only clients that may read the recorded pattern can stop a recording.
+async fn stop_recording(
+    msg: StopRecording,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    auth_settings: &AuthSettings<'_>,
+    authorized: &Option<JwtClaims>,
+) -> WorterbuchResult<()> {
+    let pattern = match worterbuch.recording_pattern(msg.name.clone()).await {
+        Ok(it) => it,
+        Err(e) => {
+            handle_store_error(e, client, msg.transaction_id).await?;
+            return Ok(());
+        }
+    };
+    if !check_auth(
+        auth_settings,
+        Privilege::Read,
+        &pattern,
+        authorized,
+        client,
+        msg.transaction_id,
+    )
+    .await?
+    {
+        return Ok(());
+    }
+    if let Err(e) = worterbuch.stop_recording(msg.name).await {
+        handle_store_error(e, client, msg.transaction_id).await?;
+        return Ok(());
+    }
+    send_ack(msg.transaction_id, client).await
+}
+
+/// Replaying makes the recorded values readable below the target prefix, so the client must also
+/// be allowed to read the recorded pattern.
+async fn replay_recording(
+    msg: ReplayRecording,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+    client_id: String,
+    auth_settings: &AuthSettings<'_>,
+    authorized: &Option<JwtClaims>,
+) -> WorterbuchResult<()> {
+    let (pattern, events) = match worterbuch.recording(msg.name).await {
+        Ok(it) => it,
+        Err(e) => {
+            handle_store_error(e, client, msg.transaction_id).await?;
+            return Ok(());
+        }
+    };
+    if !check_auth(
+        auth_settings,
+        Privilege::Read,
+        &pattern,
+        authorized,
+        client,
+        msg.transaction_id,
+    )
+    .await?
+    {
+        return Ok(());
+    }
+    spawn(recorder::replay(
+        worterbuch.clone(),
+        events,
+        msg.target_prefix,
+        msg.speed.unwrap_or(1),
+        client_id,
+    ));
+    send_ack(msg.transaction_id, client).await
+}
+
+async fn send_ack(
+    transaction_id: TransactionId,
+    client: &mpsc::Sender<ServerMessage>,
+) -> WorterbuchResult<()> {
+    client
+        .send(ServerMessage::Ack(Ack { transaction_id }))
+        .await
+        .context(|| format!("Error sending ACK message for transaction ID {transaction_id}"))
+}
+
 async fn ls(
     msg: Ls,
     worterbuch: &CloneableWbApi,
@@ -1120,6 +3954,151 @@ async fn subscribe_ls(
     Ok(true)
 }
 
+async fn subscribe_events(
+    msg: SubscribeEvents,
+    client_id: Uuid,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+) -> WorterbuchResult<bool> {
+    let mut rx = match worterbuch
+        .subscribe_events(client_id, msg.transaction_id)
+        .await
+    {
+        Ok(it) => it,
+        Err(e) => {
+            handle_store_error(e, client, msg.transaction_id).await?;
+            return Ok(false);
+        }
+    };
+
+    let response = Ack {
+        transaction_id: msg.transaction_id,
+    };
+
+    client
+        .send(ServerMessage::Ack(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending ACK message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    let transaction_id = msg.transaction_id;
+    let client_sub = client.clone();
+
+    spawn(async move {
+        log::debug!("Receiving server events for client {client_id} …");
+        while let Some(event) = rx.recv().await {
+            let state = EventState {
+                transaction_id,
+                event,
+            };
+            if let Err(e) = client_sub.send(ServerMessage::Event(state)).await {
+                log::error!("Error sending EVENT message to client: {e}");
+                break;
+            };
+        }
+        log::debug!("Server event subscription of client {client_id} ended.");
+    });
+
+    Ok(true)
+}
+
+async fn get_history(
+    msg: GetHistory,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+) -> WorterbuchResult<()> {
+    let entries = match worterbuch.get_history(msg.key, msg.limit).await {
+        Ok(it) => it,
+        Err(e) => {
+            handle_store_error(e, client, msg.transaction_id).await?;
+            return Ok(());
+        }
+    };
+
+    let response = HistoryState {
+        transaction_id: msg.transaction_id,
+        entries,
+    };
+
+    client
+        .send(ServerMessage::History(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending HISTORY message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+async fn pkeys(
+    msg: PKeys,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+) -> WorterbuchResult<()> {
+    let keys = match worterbuch.pkeys(msg.request_pattern).await {
+        Ok(it) => it,
+        Err(e) => {
+            handle_store_error(e, client, msg.transaction_id).await?;
+            return Ok(());
+        }
+    };
+
+    let response = KeysState {
+        transaction_id: msg.transaction_id,
+        keys,
+    };
+
+    client
+        .send(ServerMessage::Keys(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending KEYS message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
+async fn who_subscribes(
+    msg: WhoSubscribes,
+    worterbuch: &CloneableWbApi,
+    client: &mpsc::Sender<ServerMessage>,
+) -> WorterbuchResult<()> {
+    let subscribers = match worterbuch.who_subscribes(msg.request_pattern).await {
+        Ok(it) => it,
+        Err(e) => {
+            handle_store_error(e, client, msg.transaction_id).await?;
+            return Ok(());
+        }
+    };
+
+    let response = SubscribersState {
+        transaction_id: msg.transaction_id,
+        subscribers,
+    };
+
+    client
+        .send(ServerMessage::Subscribers(response))
+        .await
+        .context(|| {
+            format!(
+                "Error sending SUBSCRIBERS message for transaction ID {}",
+                msg.transaction_id
+            )
+        })?;
+
+    Ok(())
+}
+
 async fn unsubscribe_ls(
     msg: UnsubscribeLs,
     client_id: Uuid,
@@ -1150,6 +4129,16 @@ async fn unsubscribe_ls(
     Ok(())
 }
 
+/// Rejects a message without decoding it. Since its transaction ID is unknown, the error is
+/// reported with transaction ID 0.
+pub async fn reject_message(
+    e: WorterbuchError,
+    client: &mpsc::Sender<ServerMessage>,
+) -> WorterbuchResult<()> {
+    log::warn!("Rejecting message: {e}");
+    handle_store_error(e, client, 0).await
+}
+
 async fn handle_store_error(
     e: WorterbuchError,
     client: &mpsc::Sender<ServerMessage>,
@@ -1240,6 +4229,103 @@ async fn handle_store_error(
             transaction_id,
             metadata: auth_err.to_string(),
         },
+        WorterbuchError::DeprecatedKey(key, redirect) => Err {
+            error_code,
+            transaction_id,
+            metadata: serde_json::to_string(&match redirect {
+                Some(redirect) => format!("key '{key}' is deprecated, use '{redirect}' instead"),
+                None => format!("key '{key}' is deprecated"),
+            })
+            .expect("failed to serialize error message"),
+        },
+        WorterbuchError::TooManySubscriptions(max) => Err {
+            error_code,
+            transaction_id,
+            metadata: serde_json::to_string(&format!(
+                "client has reached its limit of {max} subscriptions"
+            ))
+            .expect("failed to serialize error message"),
+        },
+        WorterbuchError::IllegalKey(key, rule) => Err {
+            error_code,
+            transaction_id,
+            metadata: serde_json::to_string(&format!("key '{key}' violates naming rules: {rule}"))
+                .expect("failed to serialize error message"),
+        },
+        WorterbuchError::MessageTooLarge(meta) => Err {
+            error_code,
+            transaction_id,
+            metadata: serde_json::to_string(&meta).expect("failed to serialize metadata"),
+        },
+        WorterbuchError::NoSuchRecording(name) => Err {
+            error_code,
+            transaction_id,
+            metadata: serde_json::to_string(&format!("no recording named '{name}'"))
+                .expect("failed to serialize error message"),
+        },
+        WorterbuchError::CasConflict(_, conflict) => Err {
+            error_code,
+            transaction_id,
+            metadata: serde_json::to_string(&conflict).expect("failed to serialize conflict"),
+        },
+        WorterbuchError::Busy => Err {
+            error_code,
+            transaction_id,
+            metadata: serde_json::to_string("server is busy, try again later")
+                .expect("failed to serialize error message"),
+        },
+        WorterbuchError::Timeout(t) => Err {
+            error_code,
+            transaction_id,
+            metadata: serde_json::to_string(&format!("server did not respond within {t:?}"))
+                .expect("failed to serialize error message"),
+        },
+        WorterbuchError::Cancelled => Err {
+            error_code,
+            transaction_id,
+            metadata: serde_json::to_string("request was cancelled")
+                .expect("failed to serialize error message"),
+        },
+        WorterbuchError::KeyExists(key) => Err {
+            error_code,
+            transaction_id,
+            metadata: serde_json::to_string(&format!("key '{key}' already exists"))
+                .expect("failed to serialize error message"),
+        },
+        WorterbuchError::KeyReserved(key, pattern) => Err {
+            error_code,
+            transaction_id,
+            metadata: serde_json::to_string(&format!(
+                "key '{key}' is reserved by another client with pattern '{pattern}'"
+            ))
+            .expect("failed to serialize error message"),
+        },
+        WorterbuchError::KeyLocked(key) => Err {
+            error_code,
+            transaction_id,
+            metadata: serde_json::to_string(&format!("key '{key}' is locked by another client"))
+                .expect("failed to serialize error message"),
+        },
+        WorterbuchError::NotANumber(key) => Err {
+            error_code,
+            transaction_id,
+            metadata: serde_json::to_string(&format!("value of key '{key}' is not a number"))
+                .expect("failed to serialize error message"),
+        },
+        WorterbuchError::NotAnArray(key) => Err {
+            error_code,
+            transaction_id,
+            metadata: serde_json::to_string(&format!("value of key '{key}' is not an array"))
+                .expect("failed to serialize error message"),
+        },
+        WorterbuchError::ServerReadOnly => Err {
+            error_code,
+            transaction_id,
+            metadata: serde_json::to_string(
+                "server is in read-only mode, modifications are rejected",
+            )
+            .expect("failed to serialize error message"),
+        },
     };
     log::trace!("Error in store, queuing error message for client …");
     let res = client
@@ -1276,6 +4362,20 @@ pub async fn send_keepalive(
     Ok(())
 }
 
+/// Lets the send loop of a one-shot session deliver all queued responses before the session is
+/// closed. Gives up after `send_timeout`, e.g. if a subscription still holds on to the sender.
+pub async fn finish_oneshot_session(
+    client_id: Uuid,
+    send_tx: mpsc::Sender<ServerMessage>,
+    send_loop: JoinHandle<()>,
+    send_timeout: Duration,
+) {
+    drop(send_tx);
+    if timeout(send_timeout, send_loop).await.is_err() {
+        log::warn!("Could not flush all responses to one-shot client {client_id} in time.");
+    }
+}
+
 pub fn check_client_keepalive(
     last_keepalive_rx: Instant,
     last_keepalive_tx: Instant,
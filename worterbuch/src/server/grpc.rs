@@ -0,0 +1,313 @@
+/*
+ *  Worterbuch server gRPC module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{
+    auth::get_claims,
+    server::{common::CloneableWbApi, poem::auth::RestPrivileges},
+    Config,
+};
+use futures::{stream, Stream, StreamExt};
+use std::{
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    time::Duration,
+};
+use tokio::{spawn, sync::mpsc::Receiver};
+use tokio_graceful_shutdown::SubsystemHandle;
+use tonic::{transport::Server, Request, Response, Status};
+use uuid::Uuid;
+use worterbuch_common::{
+    error::WorterbuchError,
+    grpc::{
+        self,
+        worterbuch_server::{Worterbuch, WorterbuchServer},
+    },
+    KeyValuePair, PStateEvent, Privilege, StateEvent,
+};
+
+type EventStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+pub async fn start(
+    worterbuch: CloneableWbApi,
+    bind_addr: IpAddr,
+    port: u16,
+    subsys: SubsystemHandle,
+) -> anyhow::Result<()> {
+    let addr = SocketAddr::new(bind_addr, port);
+    let config = worterbuch.config().await?;
+
+    log::info!("Serving gRPC endpoint at {addr}");
+    Server::builder()
+        .add_service(WorterbuchServer::new(GrpcApi { worterbuch, config }))
+        .serve_with_shutdown(addr, subsys.on_shutdown_requested())
+        .await?;
+
+    Ok(())
+}
+
+struct GrpcApi {
+    worterbuch: CloneableWbApi,
+    config: Config,
+}
+
+impl GrpcApi {
+    /// Checks the JWT passed as `authorization: Bearer <token>` metadata, if auth is required.
+    fn authorize<T>(
+        &self,
+        request: &Request<T>,
+        privilege: Privilege,
+        pattern: &str,
+    ) -> Result<RestPrivileges, Status> {
+        let claims = if self.config.auth_required() {
+            let jwt = request
+                .metadata()
+                .get("authorization")
+                .and_then(|it| it.to_str().ok())
+                .and_then(|it| it.strip_prefix("Bearer "));
+            let claims = get_claims(jwt, &self.config)
+                .map_err(|e| Status::unauthenticated(e.to_string()))?;
+            Some(claims)
+        } else {
+            None
+        };
+        let privileges = RestPrivileges::new(claims, &self.config);
+        privileges
+            .authorize(&privilege, pattern)
+            .map_err(|e| Status::permission_denied(e.to_string()))?;
+        Ok(privileges)
+    }
+
+    /// Hands the deadline a client sends as `grpc-timeout` metadata on to the store.
+    fn api<T>(&self, request: &Request<T>) -> CloneableWbApi {
+        match grpc_timeout(request) {
+            Some(timeout) => self.worterbuch.with_request_timeout(Some(timeout)),
+            None => self.worterbuch.clone(),
+        }
+    }
+
+    fn events(
+        &self,
+        client_id: Uuid,
+        rx: Receiver<PStateEvent>,
+    ) -> impl Stream<Item = PStateEvent> {
+        let unsubscriber = Unsubscriber {
+            worterbuch: self.worterbuch.clone(),
+            client_id,
+        };
+        stream::unfold((rx, unsubscriber), |(mut rx, unsubscriber)| async move {
+            rx.recv().await.map(|event| (event, (rx, unsubscriber)))
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl Worterbuch for GrpcApi {
+    type SubscribeStream = EventStream<grpc::StateEvent>;
+    type PSubscribeStream = EventStream<grpc::PStateEvent>;
+
+    async fn get(
+        &self,
+        request: Request<grpc::GetRequest>,
+    ) -> Result<Response<grpc::KeyValuePair>, Status> {
+        self.authorize(&request, Privilege::Read, &request.get_ref().key)?;
+        let kvp = self
+            .api(&request)
+            .get(request.into_inner().key)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(KeyValuePair::from(kvp).into()))
+    }
+
+    async fn p_get(
+        &self,
+        request: Request<grpc::PGetRequest>,
+    ) -> Result<Response<grpc::KeyValuePairs>, Status> {
+        let privileges = self.authorize(
+            &request,
+            Privilege::Read,
+            &request.get_ref().request_pattern,
+        )?;
+        let kvps = privileges
+            .api(&self.api(&request))
+            .pget(request.into_inner().request_pattern)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(kvps.into()))
+    }
+
+    async fn set(&self, request: Request<grpc::SetRequest>) -> Result<Response<grpc::Ack>, Status> {
+        self.authorize(&request, Privilege::Write, &request.get_ref().key)?;
+        let api = self.api(&request);
+        let grpc::SetRequest { key, value } = request.into_inner();
+        let value = serde_json::from_str(&value)
+            .map_err(|e| Status::invalid_argument(format!("value is not valid JSON: {e}")))?;
+        let client_id = Uuid::new_v4();
+        api.set(key, value, client_id.to_string())
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(grpc::Ack {}))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<grpc::DeleteRequest>,
+    ) -> Result<Response<grpc::KeyValuePair>, Status> {
+        self.authorize(&request, Privilege::Delete, &request.get_ref().key)?;
+        let client_id = Uuid::new_v4();
+        let kvp = self
+            .api(&request)
+            .delete(request.into_inner().key, client_id.to_string())
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(KeyValuePair::from(kvp).into()))
+    }
+
+    async fn subscribe(
+        &self,
+        request: Request<grpc::SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        self.authorize(&request, Privilege::Read, &request.get_ref().key)?;
+        let grpc::SubscribeRequest {
+            key,
+            unique,
+            live_only,
+        } = request.into_inner();
+        let client_id = Uuid::new_v4();
+        let (rx, _) = self
+            .worterbuch
+            .subscribe(client_id, 1, key, unique, live_only)
+            .await
+            .map_err(to_status)?;
+        let events = self.events(client_id, rx).flat_map(|event| {
+            stream::iter(
+                Vec::<StateEvent>::from(event)
+                    .into_iter()
+                    .map(|e| Ok(e.into())),
+            )
+        });
+        Ok(Response::new(Box::pin(events)))
+    }
+
+    async fn p_subscribe(
+        &self,
+        request: Request<grpc::PSubscribeRequest>,
+    ) -> Result<Response<Self::PSubscribeStream>, Status> {
+        let privileges = self.authorize(
+            &request,
+            Privilege::Read,
+            &request.get_ref().request_pattern,
+        )?;
+        let grpc::PSubscribeRequest {
+            request_pattern,
+            unique,
+            live_only,
+        } = request.into_inner();
+        let client_id = Uuid::new_v4();
+        let (rx, _) = privileges
+            .api(&self.worterbuch)
+            .psubscribe(client_id, 1, request_pattern, unique, live_only)
+            .await
+            .map_err(to_status)?;
+        let events = self.events(client_id, rx).map(|event| Ok(event.into()));
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+/// Removes a streaming RPC's subscription once the client cancels the stream.
+struct Unsubscriber {
+    worterbuch: CloneableWbApi,
+    client_id: Uuid,
+}
+
+impl Drop for Unsubscriber {
+    fn drop(&mut self) {
+        let worterbuch = self.worterbuch.clone();
+        let client_id = self.client_id;
+        spawn(async move {
+            if let Err(e) = worterbuch.unsubscribe(client_id, 1).await {
+                log::debug!("Could not unsubscribe gRPC stream: {e}");
+            }
+        });
+    }
+}
+
+/// Parses the `grpc-timeout` header, an integer followed by one of the units `H`, `M`, `S`, `m`,
+/// `u` or `n`.
+fn grpc_timeout<T>(request: &Request<T>) -> Option<Duration> {
+    let value = request.metadata().get("grpc-timeout")?.to_str().ok()?;
+    let (amount, unit) = value.split_at(value.len().checked_sub(1)?);
+    let amount: u64 = amount.parse().ok()?;
+    let timeout = match unit {
+        "H" => Duration::from_secs(amount * 60 * 60),
+        "M" => Duration::from_secs(amount * 60),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    };
+    Some(timeout)
+}
+
+fn to_status(e: WorterbuchError) -> Status {
+    match &e {
+        WorterbuchError::NoSuchValue(_) => Status::not_found(e.to_string()),
+        WorterbuchError::IllegalMultiWildcard(_)
+        | WorterbuchError::IllegalWildcard(_)
+        | WorterbuchError::MultiWildcardAtIllegalPosition(_)
+        | WorterbuchError::IllegalKey(_, _)
+        | WorterbuchError::DeprecatedKey(_, _)
+        | WorterbuchError::ReadOnlyKey(_) => Status::invalid_argument(e.to_string()),
+        WorterbuchError::TooManySubscriptions(_) => Status::resource_exhausted(e.to_string()),
+        WorterbuchError::Busy => Status::unavailable(e.to_string()),
+        WorterbuchError::ServerReadOnly => Status::failed_precondition(e.to_string()),
+        WorterbuchError::Timeout(_) => Status::deadline_exceeded(e.to_string()),
+        WorterbuchError::Unauthorized(_) | WorterbuchError::AuthorizationRequired(_) => {
+            Status::permission_denied(e.to_string())
+        }
+        _ => Status::internal(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request_with_timeout(timeout: &str) -> Request<()> {
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("grpc-timeout", timeout.parse().expect("valid metadata"));
+        request
+    }
+
+    #[test]
+    fn grpc_timeout_is_parsed() {
+        assert_eq!(
+            grpc_timeout(&request_with_timeout("3S")),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            grpc_timeout(&request_with_timeout("250m")),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(grpc_timeout(&request_with_timeout("5x")), None);
+        assert_eq!(grpc_timeout(&Request::new(())), None);
+    }
+}
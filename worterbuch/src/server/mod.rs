@@ -18,5 +18,8 @@
  */
 
 pub(crate) mod common;
+#[cfg(feature = "grpc")]
+pub(crate) mod grpc;
 pub(crate) mod poem;
+pub(crate) mod prefix;
 pub(crate) mod tcp;
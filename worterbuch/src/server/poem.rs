@@ -17,20 +17,27 @@
  *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-mod auth;
+pub(crate) mod auth;
 mod websocket;
 
 use crate::{
-    auth::JwtClaims,
-    server::{common::CloneableWbApi, poem::auth::BearerAuth},
+    retention::StaleKey,
+    server::{
+        common::CloneableWbApi,
+        poem::auth::{BearerAuth, RestPrivileges},
+    },
+    sort_and_window,
     stats::VERSION,
+    support,
+    usage::KeyUsageReport,
+    Config, Endpoint, TlsCertificate, WsEndpoint,
 };
 use poem::{
     delete,
     endpoint::StaticFilesEndpoint,
     get, handler,
     http::StatusCode,
-    listener::TcpListener,
+    listener::{Listener, RustlsCertificate, RustlsConfig, TcpListener},
     middleware::AddData,
     post,
     web::{
@@ -40,19 +47,18 @@ use poem::{
     },
     Addr, EndpointExt, IntoResponse, Request, Response, Result, Route,
 };
-use serde_json::Value;
-use std::{
-    collections::HashMap,
-    io,
-    net::{IpAddr, SocketAddr},
-    time::Duration,
-};
-use tokio::{select, spawn, sync::mpsc};
+use serde::Deserialize;
+use serde_json::{Number, Value};
+use std::{collections::HashMap, io, net::SocketAddr, time::Duration};
+use tokio::{fs, select, spawn, sync::mpsc};
 use tokio_graceful_shutdown::SubsystemHandle;
 use uuid::Uuid;
 use worterbuch_common::{
-    error::WorterbuchError, Key, KeyValuePairs, Privilege, Protocol, RegularKeySegment, ServerInfo,
-    StateEvent,
+    error::WorterbuchError,
+    schema, topic,
+    wire::{Compression, WireFormat},
+    Key, KeyValuePairs, Privilege, Protocol, RegularKeySegment, ServerInfo, Sort, StateEvent,
+    SYSTEM_TOPIC_ROOT,
 };
 
 fn to_error_response<T>(e: WorterbuchError) -> Result<T> {
@@ -63,24 +69,50 @@ fn to_error_response<T>(e: WorterbuchError) -> Result<T> {
         | WorterbuchError::NoSuchValue(_)
         | WorterbuchError::AlreadyAuthorized
         | WorterbuchError::AuthorizationRequired(_)
+        | WorterbuchError::IllegalKey(_, _)
+        | WorterbuchError::DeprecatedKey(_, _)
+        | WorterbuchError::TooManySubscriptions(_)
         | WorterbuchError::ReadOnlyKey(_) => Err(poem::Error::new(e, StatusCode::BAD_REQUEST)),
+        WorterbuchError::MessageTooLarge(_) => {
+            Err(poem::Error::new(e, StatusCode::PAYLOAD_TOO_LARGE))
+        }
+        WorterbuchError::CasConflict(_, _)
+        | WorterbuchError::KeyExists(_)
+        | WorterbuchError::KeyReserved(_, _)
+        | WorterbuchError::KeyLocked(_)
+        | WorterbuchError::NotANumber(_)
+        | WorterbuchError::NotAnArray(_) => Err(poem::Error::new(e, StatusCode::CONFLICT)),
+        WorterbuchError::Busy | WorterbuchError::ServerReadOnly => {
+            Err(poem::Error::new(e, StatusCode::SERVICE_UNAVAILABLE))
+        }
+        WorterbuchError::Timeout(_) => Err(poem::Error::new(e, StatusCode::GATEWAY_TIMEOUT)),
         e => Err(poem::Error::new(e, StatusCode::INTERNAL_SERVER_ERROR)),
     }
 }
 
+/// Subprotocols the websocket endpoint accepts during the handshake.
+#[derive(Clone)]
+struct Subprotocols(Vec<String>);
+
 #[handler]
 fn ws(
     ws: WebSocket,
+    Query(params): Query<HashMap<String, String>>,
     Data(wb): Data<&CloneableWbApi>,
+    Data(subprotocols): Data<&Subprotocols>,
     RemoteAddr(addr): &RemoteAddr,
 ) -> Result<impl IntoResponse> {
     log::info!("Client connected");
     let worterbuch = wb.to_owned();
     let remote = to_socket_addr(addr)?;
+    let oneshot: bool = params
+        .get("oneshot")
+        .map(|it| it.to_lowercase() != "false")
+        .unwrap_or(false);
     Ok(ws
-        .protocols(vec!["worterbuch"])
+        .protocols(subprotocols.0.clone())
         .on_upgrade(move |socket| async move {
-            if let Err(e) = websocket::serve(remote, worterbuch, socket).await {
+            if let Err(e) = websocket::serve(remote, worterbuch, socket, oneshot).await {
                 log::error!("Error in WS connection: {e}");
             }
         }))
@@ -92,31 +124,128 @@ async fn info(Data(wb): Data<&CloneableWbApi>) -> Result<Json<ServerInfo>> {
         Ok(it) => it,
         Err(e) => return to_error_response(e),
     };
+    let boot_id = match wb.boot_id().await {
+        Ok(it) => it,
+        Err(e) => return to_error_response(e),
+    };
     let config = match wb.config().await {
         Ok(it) => it,
         Err(e) => return to_error_response(e),
     };
     let info = ServerInfo {
         version: VERSION.to_owned(),
-        authorization_required: config.auth_token.is_some(),
+        authorization_required: config.auth_required(),
         protocol_version: proto,
+        boot_id: Some(boot_id),
+        wire_formats: WireFormat::binary(),
+        compression: Compression::supported(),
     };
 
     Ok(Json(info))
 }
 
+/// The AsyncAPI document of the websocket protocol, generated from the message types.
+#[handler]
+async fn asyncapi(Data(wb): Data<&CloneableWbApi>) -> Result<Json<Value>> {
+    let proto = match wb.supported_protocol_version().await {
+        Ok(it) => it,
+        Err(e) => return to_error_response(e),
+    };
+    let config = match wb.config().await {
+        Ok(it) => it,
+        Err(e) => return to_error_response(e),
+    };
+    let path = config
+        .ws_endpoint
+        .map(|ep| ep.path)
+        .unwrap_or_else(|| "/ws".to_owned());
+    Ok(Json(schema::asyncapi(VERSION, &proto, &path)))
+}
+
+#[handler]
+async fn prometheus_metrics(Data(wb): Data<&CloneableWbApi>) -> Result<Response> {
+    match wb.metrics().await {
+        Ok(metrics) => Ok(metrics
+            .to_prometheus_text()
+            .with_content_type("text/plain; version=0.0.4")
+            .into_response()),
+        Err(e) => to_error_response(e),
+    }
+}
+
+#[handler]
+async fn key_usage(
+    Query(params): Query<HashMap<String, String>>,
+    Data(wb): Data<&CloneableWbApi>,
+    Data(privileges): Data<&RestPrivileges>,
+) -> Result<Json<KeyUsageReport>> {
+    if let Err(e) = privileges.authorize(&Privilege::Read, &topic!(SYSTEM_TOPIC_ROOT, "#")) {
+        return to_error_response(WorterbuchError::Unauthorized(e));
+    }
+    let top = match params.get("top").map(|it| it.parse()) {
+        Some(Ok(top)) => top,
+        Some(Err(e)) => return Err(poem::Error::new(e, StatusCode::BAD_REQUEST)),
+        None => 20,
+    };
+    match wb.key_usage(top).await {
+        Ok(Some(report)) => Ok(Json(report)),
+        Ok(None) => Err(poem::Error::from_string(
+            "key usage tracking is disabled",
+            StatusCode::NOT_FOUND,
+        )),
+        Err(e) => to_error_response(e),
+    }
+}
+
+#[handler]
+async fn stale_keys(
+    Data(wb): Data<&CloneableWbApi>,
+    Data(privileges): Data<&RestPrivileges>,
+) -> Result<Json<Vec<StaleKey>>> {
+    if let Err(e) = privileges.authorize(&Privilege::Read, &topic!(SYSTEM_TOPIC_ROOT, "#")) {
+        return to_error_response(WorterbuchError::Unauthorized(e));
+    }
+    match wb.stale_keys().await {
+        Ok(stale) => Ok(Json(stale)),
+        Err(e) => to_error_response(e),
+    }
+}
+
+/// An encrypted support bundle, see [`support::create`].
+#[handler]
+async fn support_bundle(
+    Data(wb): Data<&CloneableWbApi>,
+    Data(privileges): Data<&RestPrivileges>,
+) -> Result<Json<Value>> {
+    if let Err(e) = privileges.authorize(&Privilege::Read, "#") {
+        return to_error_response(WorterbuchError::Unauthorized(e));
+    }
+    let config = match wb.config().await {
+        Ok(it) => it,
+        Err(e) => return to_error_response(e),
+    };
+    let Some(key) = config.support_bundle_key else {
+        return Err(poem::Error::from_string(
+            "no support bundle key configured",
+            StatusCode::NOT_FOUND,
+        ));
+    };
+    match support::create(&privileges.api(wb), &key).await {
+        Ok(bundle) => Ok(Json(bundle)),
+        Err(e) => to_error_response(e),
+    }
+}
+
 #[handler]
 async fn get_value(
     req: &Request,
     Path(key): Path<Key>,
     Query(params): Query<HashMap<String, String>>,
     Data(wb): Data<&CloneableWbApi>,
-    Data(privileges): Data<&Option<JwtClaims>>,
+    Data(privileges): Data<&RestPrivileges>,
 ) -> Result<Response> {
-    if let Some(privileges) = privileges {
-        if let Err(e) = privileges.authorize(&Privilege::Read, &key) {
-            return to_error_response(WorterbuchError::Unauthorized(e));
-        }
+    if let Err(e) = privileges.authorize(&Privilege::Read, &key) {
+        return to_error_response(WorterbuchError::Unauthorized(e));
     }
     let pointer = params.get("pointer");
     let raw = params.get("raw");
@@ -149,19 +278,62 @@ async fn get_value(
     }
 }
 
+/// Query parameters of a pget, e.g. `?sort=/temperature&descending=true&limit=10`. A `sort` of
+/// `key` sorts by key, anything else is taken as a JSON pointer into the values.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PGetQuery {
+    sort: Option<String>,
+    #[serde(default)]
+    descending: bool,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    #[serde(default)]
+    case_insensitive: bool,
+}
+
 #[handler]
 async fn pget(
     Path(pattern): Path<Key>,
+    Query(query): Query<PGetQuery>,
     Data(wb): Data<&CloneableWbApi>,
-    Data(privileges): Data<&Option<JwtClaims>>,
+    Data(privileges): Data<&RestPrivileges>,
 ) -> Result<Json<KeyValuePairs>> {
-    if let Some(privileges) = privileges {
-        if let Err(e) = privileges.authorize(&Privilege::Read, &pattern) {
-            return to_error_response(WorterbuchError::Unauthorized(e));
-        }
+    if let Err(e) = privileges.authorize(&Privilege::Read, &pattern) {
+        return to_error_response(WorterbuchError::Unauthorized(e));
     }
-    match wb.pget(pattern).await {
-        Ok(kvps) => Ok(Json(kvps)),
+    let sort = query.sort.map(|by| Sort {
+        pointer: (by != "key").then_some(by),
+        descending: query.descending,
+    });
+    let wb = privileges.api(wb);
+    let kvps = if query.case_insensitive {
+        wb.pget_ignoring_case(pattern).await
+    } else {
+        wb.pget(pattern).await
+    };
+    match kvps {
+        Ok(kvps) => Ok(Json(sort_and_window(
+            kvps,
+            sort.as_ref(),
+            query.offset,
+            query.limit,
+        ))),
+        Err(e) => to_error_response(e),
+    }
+}
+
+#[handler]
+async fn pkeys(
+    Path(pattern): Path<Key>,
+    Data(wb): Data<&CloneableWbApi>,
+    Data(privileges): Data<&RestPrivileges>,
+) -> Result<Json<Vec<Key>>> {
+    if let Err(e) = privileges.authorize(&Privilege::Read, &pattern) {
+        return to_error_response(WorterbuchError::Unauthorized(e));
+    }
+    match privileges.api(wb).pkeys(pattern).await {
+        Ok(keys) => Ok(Json(keys)),
         Err(e) => to_error_response(e),
     }
 }
@@ -171,33 +343,108 @@ async fn set(
     Path(key): Path<Key>,
     Json(value): Json<Value>,
     Data(wb): Data<&CloneableWbApi>,
-    Data(privileges): Data<&Option<JwtClaims>>,
+    Data(privileges): Data<&RestPrivileges>,
+) -> Result<Json<&'static str>> {
+    if let Err(e) = privileges.authorize(&Privilege::Write, &key) {
+        return to_error_response(WorterbuchError::Unauthorized(e));
+    }
+    let client_id = Uuid::new_v4();
+    match wb.set(key, value, client_id.to_string()).await {
+        Ok(()) => Ok(Json("Ok")),
+        Err(e) => to_error_response(e),
+    }
+}
+
+/// Expects an array of `{ "key": …, "value": … }` objects, which are stored as a single operation.
+#[handler]
+async fn set_many(
+    Json(key_value_pairs): Json<KeyValuePairs>,
+    Data(wb): Data<&CloneableWbApi>,
+    Data(privileges): Data<&RestPrivileges>,
 ) -> Result<Json<&'static str>> {
-    if let Some(privileges) = privileges {
-        if let Err(e) = privileges.authorize(&Privilege::Write, &key) {
+    for kvp in &key_value_pairs {
+        if let Err(e) = privileges.authorize(&Privilege::Write, &kvp.key) {
             return to_error_response(WorterbuchError::Unauthorized(e));
         }
     }
     let client_id = Uuid::new_v4();
-    match wb.set(key, value, client_id.to_string()).await {
+    match wb.set_many(key_value_pairs, client_id.to_string()).await {
         Ok(()) => Ok(Json("Ok")),
         Err(e) => to_error_response(e),
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct CasRequest {
+    #[serde(default)]
+    expected: Option<Value>,
+    #[serde(default)]
+    version: Option<u64>,
+    value: Value,
+}
+
+/// Answers a rejected swap with `409 Conflict` and the key's current value and version.
+#[handler]
+async fn cas(
+    Path(key): Path<Key>,
+    Json(req): Json<CasRequest>,
+    Data(wb): Data<&CloneableWbApi>,
+    Data(privileges): Data<&RestPrivileges>,
+) -> Result<Response> {
+    if let Err(e) = privileges.authorize(&Privilege::Write, &key) {
+        return to_error_response(WorterbuchError::Unauthorized(e));
+    }
+    let client_id = Uuid::new_v4();
+    match wb
+        .cas(
+            key,
+            req.expected,
+            req.version,
+            req.value,
+            client_id.to_string(),
+        )
+        .await
+    {
+        Ok(()) => Ok(Json("Ok").into_response()),
+        Err(WorterbuchError::CasConflict(_, conflict)) => Ok(Json(conflict)
+            .with_status(StatusCode::CONFLICT)
+            .into_response()),
+        Err(e) => to_error_response(e),
+    }
+}
+
+/// Answers with the value after the increment.
+#[handler]
+async fn increment(
+    Path(key): Path<Key>,
+    Json(delta): Json<Number>,
+    Data(wb): Data<&CloneableWbApi>,
+    Data(privileges): Data<&RestPrivileges>,
+) -> Result<Json<Value>> {
+    for privilege in [Privilege::Write, Privilege::Read] {
+        if let Err(e) = privileges.authorize(&privilege, &key) {
+            return to_error_response(WorterbuchError::Unauthorized(e));
+        }
+    }
+    let client_id = Uuid::new_v4();
+    match wb.increment(key, delta, client_id.to_string()).await {
+        Ok(value) => Ok(Json(value)),
+        Err(e) => to_error_response(e),
+    }
+}
+
 #[handler]
 async fn publish(
     Path(key): Path<Key>,
     Json(value): Json<Value>,
     Data(wb): Data<&CloneableWbApi>,
-    Data(privileges): Data<&Option<JwtClaims>>,
+    Data(privileges): Data<&RestPrivileges>,
 ) -> Result<Json<&'static str>> {
-    if let Some(privileges) = privileges {
-        if let Err(e) = privileges.authorize(&Privilege::Write, &key) {
-            return to_error_response(WorterbuchError::Unauthorized(e));
-        }
+    if let Err(e) = privileges.authorize(&Privilege::Write, &key) {
+        return to_error_response(WorterbuchError::Unauthorized(e));
     }
-    match wb.publish(key, value).await {
+    let client_id = Uuid::new_v4();
+    match wb.publish(key, value, client_id.to_string()).await {
         Ok(()) => Ok(Json("Ok")),
         Err(e) => to_error_response(e),
     }
@@ -207,12 +454,10 @@ async fn publish(
 async fn delete_value(
     Path(key): Path<Key>,
     Data(wb): Data<&CloneableWbApi>,
-    Data(privileges): Data<&Option<JwtClaims>>,
+    Data(privileges): Data<&RestPrivileges>,
 ) -> Result<Json<Value>> {
-    if let Some(privileges) = privileges {
-        if let Err(e) = privileges.authorize(&Privilege::Delete, &key) {
-            return to_error_response(WorterbuchError::Unauthorized(e));
-        }
+    if let Err(e) = privileges.authorize(&Privilege::Delete, &key) {
+        return to_error_response(WorterbuchError::Unauthorized(e));
     }
     let client_id = Uuid::new_v4();
     match wb.delete(key, client_id.to_string()).await {
@@ -225,12 +470,10 @@ async fn delete_value(
 async fn pdelete(
     Path(pattern): Path<Key>,
     Data(wb): Data<&CloneableWbApi>,
-    Data(privileges): Data<&Option<JwtClaims>>,
+    Data(privileges): Data<&RestPrivileges>,
 ) -> Result<Json<KeyValuePairs>> {
-    if let Some(privileges) = privileges {
-        if let Err(e) = privileges.authorize(&Privilege::Delete, &pattern) {
-            return to_error_response(WorterbuchError::Unauthorized(e));
-        }
+    if let Err(e) = privileges.authorize(&Privilege::Delete, &pattern) {
+        return to_error_response(WorterbuchError::Unauthorized(e));
     }
     let client_id = Uuid::new_v4();
     match wb.pdelete(pattern, client_id.to_string()).await {
@@ -243,12 +486,10 @@ async fn pdelete(
 async fn ls(
     Path(parent): Path<Key>,
     Data(wb): Data<&CloneableWbApi>,
-    Data(privileges): Data<&Option<JwtClaims>>,
+    Data(privileges): Data<&RestPrivileges>,
 ) -> Result<Json<Vec<RegularKeySegment>>> {
-    if let Some(privileges) = privileges {
-        if let Err(e) = privileges.authorize(&Privilege::Read, &format!("{parent}/?")) {
-            return to_error_response(WorterbuchError::Unauthorized(e));
-        }
+    if let Err(e) = privileges.authorize(&Privilege::Read, &format!("{parent}/?")) {
+        return to_error_response(WorterbuchError::Unauthorized(e));
     }
     match wb.ls(Some(parent)).await {
         Ok(kvps) => Ok(Json(kvps)),
@@ -259,12 +500,10 @@ async fn ls(
 #[handler]
 async fn ls_root(
     Data(wb): Data<&CloneableWbApi>,
-    Data(privileges): Data<&Option<JwtClaims>>,
+    Data(privileges): Data<&RestPrivileges>,
 ) -> Result<Json<Vec<RegularKeySegment>>> {
-    if let Some(privileges) = privileges {
-        if let Err(e) = privileges.authorize(&Privilege::Read, "?") {
-            return to_error_response(WorterbuchError::Unauthorized(e));
-        }
+    if let Err(e) = privileges.authorize(&Privilege::Read, "?") {
+        return to_error_response(WorterbuchError::Unauthorized(e));
     }
     match wb.ls(None).await {
         Ok(kvps) => Ok(Json(kvps)),
@@ -277,13 +516,11 @@ async fn subscribe(
     Path(key): Path<Key>,
     Query(params): Query<HashMap<String, String>>,
     Data(wb): Data<&CloneableWbApi>,
-    Data(privileges): Data<&Option<JwtClaims>>,
+    Data(privileges): Data<&RestPrivileges>,
     RemoteAddr(addr): &RemoteAddr,
 ) -> Result<SSE> {
-    if let Some(privileges) = privileges {
-        if let Err(e) = privileges.authorize(&Privilege::Read, &key) {
-            return to_error_response(WorterbuchError::Unauthorized(e));
-        }
+    if let Err(e) = privileges.authorize(&Privilege::Read, &key) {
+        return to_error_response(WorterbuchError::Unauthorized(e));
     }
     let client_id = Uuid::new_v4();
     let remote_addr = to_socket_addr(addr)?;
@@ -378,13 +615,11 @@ async fn psubscribe(
     Path(key): Path<Key>,
     Query(params): Query<HashMap<String, String>>,
     Data(wb): Data<&CloneableWbApi>,
-    Data(privileges): Data<&Option<JwtClaims>>,
+    Data(privileges): Data<&RestPrivileges>,
     RemoteAddr(addr): &RemoteAddr,
 ) -> Result<SSE> {
-    if let Some(privileges) = privileges {
-        if let Err(e) = privileges.authorize(&Privilege::Read, &key) {
-            return to_error_response(WorterbuchError::Unauthorized(e));
-        }
+    if let Err(e) = privileges.authorize(&Privilege::Read, &key) {
+        return to_error_response(WorterbuchError::Unauthorized(e));
     }
     let client_id = Uuid::new_v4();
     let remote_addr = to_socket_addr(addr)?;
@@ -399,7 +634,8 @@ async fn psubscribe(
         .map(|it| it.to_lowercase() != "false")
         .unwrap_or(false);
     let wb_unsub = wb.clone();
-    match wb
+    match privileges
+        .api(wb)
         .psubscribe(client_id, transaction_id, key, unique, live_only)
         .await
     {
@@ -445,13 +681,11 @@ async fn psubscribe(
 #[handler]
 async fn subscribels_root(
     Data(wb): Data<&CloneableWbApi>,
-    Data(privileges): Data<&Option<JwtClaims>>,
+    Data(privileges): Data<&RestPrivileges>,
     RemoteAddr(addr): &RemoteAddr,
 ) -> Result<SSE> {
-    if let Some(privileges) = privileges {
-        if let Err(e) = privileges.authorize(&Privilege::Read, "?") {
-            return to_error_response(WorterbuchError::Unauthorized(e));
-        }
+    if let Err(e) = privileges.authorize(&Privilege::Read, "?") {
+        return to_error_response(WorterbuchError::Unauthorized(e));
     }
     let client_id = Uuid::new_v4();
     let remote_addr = to_socket_addr(addr)?;
@@ -502,13 +736,11 @@ async fn subscribels_root(
 async fn subscribels(
     Path(parent): Path<Key>,
     Data(wb): Data<&CloneableWbApi>,
-    Data(privileges): Data<&Option<JwtClaims>>,
+    Data(privileges): Data<&RestPrivileges>,
     RemoteAddr(addr): &RemoteAddr,
 ) -> Result<SSE> {
-    if let Some(privileges) = privileges {
-        if let Err(e) = privileges.authorize(&Privilege::Read, &format!("{parent}/?")) {
-            return to_error_response(WorterbuchError::Unauthorized(e));
-        }
+    if let Err(e) = privileges.authorize(&Privilege::Read, &format!("{parent}/?")) {
+        return to_error_response(WorterbuchError::Unauthorized(e));
     }
     let client_id = Uuid::new_v4();
     let remote_addr = to_socket_addr(addr)?;
@@ -560,26 +792,106 @@ async fn subscribels(
 
 pub async fn start(
     worterbuch: CloneableWbApi,
-    tls: bool,
-    bind_addr: IpAddr,
-    port: u16,
-    public_addr: String,
+    endpoint: WsEndpoint,
     subsys: SubsystemHandle,
 ) -> anyhow::Result<()> {
+    let WsEndpoint {
+        endpoint: Endpoint {
+            tls,
+            bind_addr,
+            port,
+        },
+        public_addr,
+        certificates,
+        path,
+        subprotocols,
+    } = endpoint;
     let proto = if tls { "wss" } else { "ws" };
     let rest_proto = if tls { "https" } else { "http" };
 
     let addr = format!("{bind_addr}:{port}");
 
-    log::info!("Serving websocket endpoint at {proto}://{public_addr}:{port}/ws");
+    let mut paths = vec![path.clone()];
+    for version in worterbuch.supported_protocol_versions().await? {
+        paths.push(format!("{}/v{version}", path.trim_end_matches('/')));
+    }
+    let subprotocols = Subprotocols(subprotocols);
+
     let mut app = Route::new();
+    for path in paths {
+        log::info!("Serving websocket endpoint at {proto}://{public_addr}:{port}{path}");
+        app = app.at(
+            path,
+            get(ws
+                .with(AddData::new(subprotocols.clone()))
+                .with(AddData::new(worterbuch.clone()))),
+        );
+    }
+
+    let config = worterbuch.config().await?;
+    if config.admin_endpoint.is_none() {
+        app = rest_api(
+            app,
+            &worterbuch,
+            &config,
+            &format!("{rest_proto}://{public_addr}:{port}"),
+        );
+    }
+
+    log::info!("Serving server info at {rest_proto}://{public_addr}:{port}/info");
+    app = app.at("/info", get(info.with(AddData::new(worterbuch.clone()))));
+
+    if let Some(web_root_path) = config.web_root_path {
+        log::info!(
+            "Serving custom web app from {web_root_path} at {rest_proto}://{public_addr}:{port}/"
+        );
 
-    app = app.at("/ws", get(ws.with(AddData::new(worterbuch.clone()))));
+        app = app.nest(
+            "/",
+            StaticFilesEndpoint::new(web_root_path)
+                .index_file("index.html")
+                .fallback_to_index()
+                .redirect_to_slash_directory(),
+        );
+    }
+
+    serve(app, tls, addr, &certificates, subsys).await
+}
+
+/// Serves the REST API and admin routes separately from the websocket endpoint, so they can be
+/// bound to an address that is not reachable by regular clients.
+pub async fn start_admin(
+    worterbuch: CloneableWbApi,
+    endpoint: Endpoint,
+    certificates: Vec<TlsCertificate>,
+    subsys: SubsystemHandle,
+) -> anyhow::Result<()> {
+    let Endpoint {
+        tls,
+        bind_addr,
+        port,
+    } = endpoint;
+    let rest_proto = if tls { "https" } else { "http" };
+    let addr = format!("{bind_addr}:{port}");
 
+    log::info!("Serving admin endpoint at {rest_proto}://{addr}");
     let config = worterbuch.config().await?;
+    let mut app = rest_api(
+        Route::new(),
+        &worterbuch,
+        &config,
+        &format!("{rest_proto}://{addr}"),
+    );
+    app = app.at("/info", get(info.with(AddData::new(worterbuch.clone()))));
+
+    serve(app, tls, addr, &certificates, subsys).await
+}
+
+fn rest_api(mut app: Route, worterbuch: &CloneableWbApi, config: &Config, base_url: &str) -> Route {
     let rest_api_version = 1;
     let rest_root = format!("/api/v{rest_api_version}");
-    log::info!("Serving REST API at {rest_proto}://{public_addr}:{port}{rest_root}");
+    log::info!("Serving REST API at {base_url}{rest_root}");
+    log::info!("Serving AsyncAPI document at {base_url}{rest_root}/asyncapi.json");
     app = app
         .at(
             format!("{rest_root}/get/*"),
@@ -594,12 +906,41 @@ pub async fn start(
                     .with(AddData::new(worterbuch.clone())),
             ),
         )
+        .at(
+            format!("{rest_root}/setmany"),
+            post(
+                set_many
+                    .with(BearerAuth::new(config.clone()))
+                    .with(AddData::new(worterbuch.clone())),
+            ),
+        )
+        .at(
+            format!("{rest_root}/cas/*"),
+            post(
+                cas.with(BearerAuth::new(config.clone()))
+                    .with(AddData::new(worterbuch.clone())),
+            ),
+        )
+        .at(
+            format!("{rest_root}/increment/*"),
+            post(
+                increment
+                    .with(BearerAuth::new(config.clone()))
+                    .with(AddData::new(worterbuch.clone())),
+            ),
+        )
         .at(
             format!("{rest_root}/pget/*"),
             get(pget
                 .with(BearerAuth::new(config.clone()))
                 .with(AddData::new(worterbuch.clone()))),
         )
+        .at(
+            format!("{rest_root}/pkeys/*"),
+            get(pkeys
+                .with(BearerAuth::new(config.clone()))
+                .with(AddData::new(worterbuch.clone()))),
+        )
         .at(
             format!("{rest_root}/publish/*"),
             post(
@@ -659,36 +1000,102 @@ pub async fn start(
             get(subscribels
                 .with(BearerAuth::new(config.clone()))
                 .with(AddData::new(worterbuch.clone()))),
+        )
+        .at(
+            format!("{rest_root}/asyncapi.json"),
+            get(asyncapi.with(AddData::new(worterbuch.clone()))),
+        )
+        .at(
+            format!("{rest_root}/admin/usage"),
+            get(key_usage
+                .with(BearerAuth::new(config.clone()))
+                .with(AddData::new(worterbuch.clone()))),
+        )
+        .at(
+            format!("{rest_root}/admin/retention"),
+            get(stale_keys
+                .with(BearerAuth::new(config.clone()))
+                .with(AddData::new(worterbuch.clone()))),
+        )
+        .at(
+            format!("{rest_root}/admin/support-bundle"),
+            get(support_bundle
+                .with(BearerAuth::new(config.clone()))
+                .with(AddData::new(worterbuch.clone()))),
         );
 
-    log::info!("Serving server info at {rest_proto}://{public_addr}:{port}/info");
-    app = app.at("/info", get(info.with(AddData::new(worterbuch.clone()))));
-
-    if let Some(web_root_path) = config.web_root_path {
-        log::info!(
-            "Serving custom web app from {web_root_path} at {rest_proto}://{public_addr}:{port}/"
-        );
-
-        app = app.nest(
-            "/",
-            StaticFilesEndpoint::new(web_root_path)
-                .index_file("index.html")
-                .fallback_to_index()
-                .redirect_to_slash_directory(),
+    if config.prometheus_endpoint {
+        log::info!("Serving Prometheus metrics at {base_url}/metrics");
+        app = app.at(
+            "/metrics",
+            get(prometheus_metrics.with(AddData::new(worterbuch.clone()))),
         );
     }
 
-    poem::Server::new(TcpListener::bind(addr))
-        .run_with_graceful_shutdown(
-            app,
-            subsys.on_shutdown_requested(),
-            Some(Duration::from_secs(1)),
-        )
-        .await?;
+    app
+}
+
+async fn serve(
+    app: Route,
+    tls: bool,
+    addr: String,
+    certificates: &[TlsCertificate],
+    subsys: SubsystemHandle,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr);
+
+    if tls && !certificates.is_empty() {
+        let rustls = load_certificates(certificates).await?;
+        poem::Server::new(listener.rustls(rustls))
+            .run_with_graceful_shutdown(
+                app,
+                subsys.on_shutdown_requested(),
+                Some(Duration::from_secs(1)),
+            )
+            .await?;
+    } else {
+        if tls {
+            log::info!(
+                "No TLS certificates configured, expecting TLS to be terminated by a proxy."
+            );
+        }
+        poem::Server::new(listener)
+            .run_with_graceful_shutdown(
+                app,
+                subsys.on_shutdown_requested(),
+                Some(Duration::from_secs(1)),
+            )
+            .await?;
+    }
 
     Ok(())
 }
 
+async fn load_certificates(certificates: &[TlsCertificate]) -> anyhow::Result<RustlsConfig> {
+    let mut config = RustlsConfig::new();
+    for TlsCertificate {
+        server_name,
+        cert_file,
+        key_file,
+    } in certificates
+    {
+        let certificate = RustlsCertificate::new()
+            .cert(fs::read(cert_file).await?)
+            .key(fs::read(key_file).await?);
+        config = match server_name {
+            Some(server_name) => {
+                log::info!("Serving certificate {cert_file} for {server_name}");
+                config.certificate(server_name, certificate)
+            }
+            None => {
+                log::info!("Serving certificate {cert_file} by default");
+                config.fallback(certificate)
+            }
+        };
+    }
+    Ok(config)
+}
+
 fn to_socket_addr(addr: &Addr) -> Result<SocketAddr> {
     if let Addr::SocketAddr(it) = addr {
         Ok(it.to_owned())
@@ -18,15 +18,81 @@
  */
 
 use crate::{
-    auth::{get_claims, JwtClaims},
+    acl::SharedAcl,
+    auth::{get_claims, AuthorizationContext, JwtClaims, SharedAuthorizer},
+    replication::Replication,
+    server::common::{guarded_auth_attempt, CloneableWbApi},
+    users::{authenticate, check_protected, hides_user_db},
     Config,
 };
 use poem::{
     http::StatusCode,
     middleware::AddData,
-    web::headers::{self, authorization::Bearer, HeaderMapExt},
+    web::headers::{
+        self,
+        authorization::{Basic, Bearer},
+        HeaderMapExt,
+    },
     Endpoint, EndpointExt, Middleware, Request, Result,
 };
+use std::net::SocketAddr;
+use worterbuch_common::{
+    error::{AuthorizationError, AuthorizationResult},
+    Privilege,
+};
+
+/// The privileges of a REST or gRPC request, i.e. the claims of its JWT, the configured authorizer,
+/// the ACL and the replication role.
+#[derive(Debug, Clone)]
+pub struct RestPrivileges {
+    claims: Option<JwtClaims>,
+    authorizer: Option<SharedAuthorizer>,
+    acl: Option<SharedAcl>,
+    replication: Option<Replication>,
+    user_db: bool,
+}
+
+impl RestPrivileges {
+    pub fn new(claims: Option<JwtClaims>, config: &Config) -> Self {
+        Self {
+            claims,
+            authorizer: config.authorizer.clone(),
+            acl: config.acl.clone(),
+            replication: config.replication.clone(),
+            user_db: config.user_db,
+        }
+    }
+
+    pub fn authorize(&self, privilege: &Privilege, pattern: &str) -> AuthorizationResult<()> {
+        if let Some(claims) = &self.claims {
+            claims.authorize(privilege, pattern)?;
+        }
+        if self.user_db {
+            check_protected(self.claims.as_ref(), privilege, pattern)?;
+        }
+        if let Some(authorizer) = &self.authorizer {
+            authorizer.authorize(AuthorizationContext {
+                client_id: None,
+                claims: self.claims.as_ref(),
+                privilege,
+                pattern,
+            })?;
+        }
+        if let Some(acl) = &self.acl {
+            let roles = self.claims.as_ref().map(|c| c.roles.as_slice());
+            acl.authorize(roles.unwrap_or_default(), privilege, pattern)?;
+        }
+        if let Some(replication) = &self.replication {
+            replication.authorize(self.claims.as_ref(), privilege, pattern)?;
+        }
+        Ok(())
+    }
+
+    /// The handle to serve the request with, see [`CloneableWbApi::hiding_user_db`].
+    pub fn api(&self, wb: &CloneableWbApi) -> CloneableWbApi {
+        wb.hiding_user_db(self.user_db && hides_user_db(self.claims.as_ref()))
+    }
+}
 
 pub struct BearerAuth {
     config: Config,
@@ -56,7 +122,30 @@ pub struct BearerAuthEndpoint<E> {
 
 impl<E> BearerAuthEndpoint<E> {
     fn auth_required(&self) -> bool {
-        self.config.auth_token.is_some()
+        self.config.auth_required()
+    }
+
+    async fn basic_auth(&self, req: &Request, basic: Basic) -> AuthorizationResult<JwtClaims> {
+        if !self.config.user_db {
+            return Err(AuthorizationError::UserDatabaseDisabled);
+        }
+        match req.data::<CloneableWbApi>() {
+            Some(wb) => authenticate(wb, basic.username(), basic.password().to_owned()).await,
+            None => Err(AuthorizationError::InvalidCredentials),
+        }
+    }
+
+    async fn claims(&self, req: &Request) -> AuthorizationResult<JwtClaims> {
+        match req.headers().typed_get::<headers::Authorization<Basic>>() {
+            Some(basic) => self.basic_auth(req, basic.0).await,
+            None => {
+                let jwt = req
+                    .headers()
+                    .typed_get::<headers::Authorization<Bearer>>()
+                    .map(|it| it.0.token().to_owned());
+                get_claims(jwt.as_deref(), &self.config)
+            }
+        }
     }
 }
 
@@ -65,23 +154,31 @@ impl<E: Endpoint> Endpoint for BearerAuthEndpoint<E> {
     type Output = E::Output;
 
     async fn call(&self, req: Request) -> Result<Self::Output> {
-        if self.auth_required() {
-            let jwt = req
-                .headers()
-                .typed_get::<headers::Authorization<Bearer>>()
-                .map(|it| it.0.token().to_owned());
-
-            let claims = get_claims(jwt.as_deref(), &self.config)
-                .map_err(|e| poem::Error::new(e, StatusCode::UNAUTHORIZED))?;
-            (&self.ep)
-                .with(AddData::<Option<JwtClaims>>::new(Some(claims)))
-                .call(req)
-                .await
+        let claims = if self.auth_required() {
+            let claims = match req.data::<CloneableWbApi>() {
+                Some(wb) => {
+                    let remote_addr = req.remote_addr().as_socket_addr().map(SocketAddr::ip);
+                    guarded_auth_attempt(wb, remote_addr, self.claims(&req))
+                        .await
+                        .map_err(|e| poem::Error::new(e, StatusCode::INTERNAL_SERVER_ERROR))?
+                }
+                None => self.claims(&req).await,
+            };
+            let claims = claims.map_err(|e| {
+                let status = match e {
+                    AuthorizationError::LockedOut(_) => StatusCode::TOO_MANY_REQUESTS,
+                    _ => StatusCode::UNAUTHORIZED,
+                };
+                poem::Error::new(e, status)
+            })?;
+            Some(claims)
         } else {
-            (&self.ep)
-                .with(AddData::<Option<JwtClaims>>::new(None))
-                .call(req)
-                .await
-        }
+            None
+        };
+        let privileges = RestPrivileges::new(claims, &self.config);
+        (&self.ep)
+            .with(AddData::<RestPrivileges>::new(privileges))
+            .call(req)
+            .await
     }
 }
@@ -18,8 +18,12 @@
  */
 
 use crate::{
-    server::common::{
-        check_client_keepalive, process_incoming_message, send_keepalive, CloneableWbApi,
+    server::{
+        common::{
+            check_client_keepalive, finish_oneshot_session, process_incoming_binary_message,
+            process_incoming_message, send_keepalive, CloneableWbApi, InFlight,
+        },
+        prefix::strip_key_prefix,
     },
     stats::VERSION,
 };
@@ -35,20 +39,36 @@ use std::{
 };
 use tokio::{
     select, spawn,
-    sync::mpsc,
+    sync::{mpsc, watch},
     time::{sleep, MissedTickBehavior},
 };
 use uuid::Uuid;
-use worterbuch_common::{Protocol, ServerInfo, ServerMessage, Welcome};
+use worterbuch_common::{
+    wire::{Compression, WireFormat},
+    Protocol, ServerEvent, ServerInfo, ServerMessage, Welcome,
+};
 
 pub(crate) async fn serve(
     remote_addr: SocketAddr,
     worterbuch: CloneableWbApi,
     websocket: WebSocketStream,
+    oneshot: bool,
 ) -> anyhow::Result<()> {
     let client_id = Uuid::new_v4();
 
-    log::info!("New client connected: {client_id} ({remote_addr})");
+    if oneshot {
+        tracing::info!(
+            %client_id,
+            %remote_addr,
+            "New one-shot client connected: {client_id} ({remote_addr})"
+        );
+    } else {
+        tracing::info!(
+            %client_id,
+            %remote_addr,
+            "New client connected: {client_id} ({remote_addr})"
+        );
+    }
 
     if let Err(e) = worterbuch
         .connected(client_id, remote_addr, Protocol::WS)
@@ -58,8 +78,21 @@ pub(crate) async fn serve(
     } else {
         log::debug!("Receiving messages from client {client_id} ({remote_addr}) …",);
 
-        if let Err(e) = serve_loop(client_id, remote_addr, worterbuch.clone(), websocket).await {
+        if let Err(e) = serve_loop(
+            client_id,
+            remote_addr,
+            worterbuch.clone(),
+            websocket,
+            oneshot,
+        )
+        .await
+        {
             log::error!("Error in serve loop: {e}");
+            worterbuch
+                .emit_event(ServerEvent::Error(format!(
+                    "error in connection to client {client_id} ({remote_addr}): {e}"
+                )))
+                .await?;
         }
     }
 
@@ -75,9 +108,10 @@ async fn serve_loop(
     remote_addr: SocketAddr,
     worterbuch: CloneableWbApi,
     websocket: WebSocketStream,
+    oneshot: bool,
 ) -> anyhow::Result<()> {
     let config = worterbuch.config().await?;
-    let authorization_required = config.auth_token.is_some();
+    let authorization_required = config.auth_required();
     let send_timeout = config.send_timeout;
     let keepalive_timeout = config.keepalive_timeout;
     let mut keepalive_timer = tokio::time::interval(Duration::from_secs(1));
@@ -89,11 +123,20 @@ async fn serve_loop(
     let (mut ws_tx, mut ws_rx) = websocket.split();
     let (ws_send_tx, mut ws_send_rx) = mpsc::channel(config.channel_buffer_size);
     let (keepalive_tx_tx, mut keepalive_tx_rx) = mpsc::channel(config.channel_buffer_size);
+    let (key_prefix_tx, key_prefix_rx) = watch::channel(None);
+    let (wire_format_tx, wire_format_rx) = watch::channel((WireFormat::Json, Compression::None));
+    let in_flight = InFlight::default();
 
     // websocket send loop
-    spawn(async move {
+    let send_loop = spawn(async move {
         while let Some(msg) = ws_send_rx.recv().await {
-            if let Err(e) = send_with_timeout(msg, &mut ws_tx, send_timeout, &keepalive_tx_tx).await
+            let msg = match key_prefix_rx.borrow().as_deref() {
+                Some(prefix) => strip_key_prefix(msg, prefix),
+                None => msg,
+            };
+            let framing = *wire_format_rx.borrow();
+            if let Err(e) =
+                send_with_timeout(msg, framing, &mut ws_tx, send_timeout, &keepalive_tx_tx).await
             {
                 log::error!("Erros sending WS message: {e}");
                 break;
@@ -101,18 +144,25 @@ async fn serve_loop(
         }
     });
 
-    let protocol_version = worterbuch.supported_protocol_version().await?;
+    // one-shot clients know what they want, they don't need to be welcomed
+    if !oneshot {
+        let protocol_version = worterbuch.supported_protocol_version().await?;
+        let boot_id = worterbuch.boot_id().await?;
 
-    ws_send_tx
-        .send(ServerMessage::Welcome(Welcome {
-            client_id: client_id.to_string(),
-            info: ServerInfo {
-                version: VERSION.to_owned(),
-                authorization_required,
-                protocol_version,
-            },
-        }))
-        .await?;
+        ws_send_tx
+            .send(ServerMessage::Welcome(Welcome {
+                client_id: client_id.to_string(),
+                info: ServerInfo {
+                    version: VERSION.to_owned(),
+                    authorization_required,
+                    protocol_version,
+                    boot_id: Some(boot_id),
+                    wire_formats: WireFormat::binary(),
+                    compression: Compression::supported(),
+                },
+            }))
+            .await?;
+    }
 
     loop {
         select! {
@@ -126,21 +176,36 @@ async fn serve_loop(
                             last_keepalive_tx = keepalive;
                         }
                         log::trace!("Processing incoming message …");
-                        if let Message::Text(text) = incoming_msg {
-                            let (msg_processed, auth) = process_incoming_message(
+                        let (msg_processed, auth) = match incoming_msg {
+                            Message::Text(text) => process_incoming_message(
                                 client_id,
                                 &text,
                                 &worterbuch,
                                 &ws_send_tx,
-                                authorization_required,
                                 authorized,
-                                &config
+                                &config,
+                                &key_prefix_tx,
+                                Some(&wire_format_tx),
+                                &in_flight,
                             )
-                            .await?;
-                            authorized = auth;
-                            if !msg_processed {
-                                break;
-                            }
+                            .await?,
+                            Message::Binary(data) => process_incoming_binary_message(
+                                client_id,
+                                &data,
+                                &worterbuch,
+                                &ws_send_tx,
+                                authorized,
+                                &config,
+                                &key_prefix_tx,
+                                &wire_format_tx,
+                                &in_flight,
+                            )
+                            .await?,
+                            _ => (true, authorized),
+                        };
+                        authorized = auth;
+                        if !msg_processed {
+                            break;
                         }
                     },
                     Err(e) => {
@@ -161,7 +226,7 @@ async fn serve_loop(
                 },
                 None => break,
             },
-            _ = keepalive_timer.tick() => {
+            _ = keepalive_timer.tick(), if !oneshot => {
                 // check how long ago the last websocket message was received
                 check_client_keepalive(last_keepalive_rx, last_keepalive_tx, client_id, keepalive_timeout)?;
                 // send out websocket message if the last has been more than a second ago
@@ -170,18 +235,26 @@ async fn serve_loop(
         }
     }
 
+    if oneshot {
+        finish_oneshot_session(client_id, ws_send_tx, send_loop, send_timeout).await;
+    }
+
     Ok(())
 }
 
 async fn send_with_timeout(
     msg: ServerMessage,
+    (format, compression): (WireFormat, Compression),
     websocket: &mut WebSocketSender,
     send_timeout: Duration,
     keepalive_tx_tx: &mpsc::Sender<Instant>,
 ) -> anyhow::Result<()> {
     log::trace!("Sending with timeout {}s …", send_timeout.as_secs());
-    let json = serde_json::to_string(&msg)?;
-    let msg = Message::Text(json);
+    let msg = if format.is_binary() {
+        Message::Binary(format.encode(&msg, compression)?)
+    } else {
+        Message::Text(serde_json::to_string(&msg)?)
+    };
     select! {
         r = websocket.send(msg) => {
             r?;
@@ -0,0 +1,364 @@
+/*
+ *  Worterbuch session key prefix module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use worterbuch_common::{
+    topic, ClientMessage as CM, Key, KeyValuePair, PStateEvent, ServerMessage, StateEvent,
+    SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_ROOT_PREFIX,
+};
+
+/// Prepends the session's key prefix to all keys and patterns in a client message.
+pub(crate) fn add_key_prefix(msg: CM, prefix: &str) -> CM {
+    match msg {
+        CM::Get(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::Get(msg)
+        }
+        CM::GetAt(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::GetAt(msg)
+        }
+        CM::SetAt(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::SetAt(msg)
+        }
+        CM::PGet(mut msg) => {
+            msg.request_pattern = prefixed(prefix, msg.request_pattern);
+            CM::PGet(msg)
+        }
+        CM::PKeys(mut msg) => {
+            msg.request_pattern = prefixed(prefix, msg.request_pattern);
+            CM::PKeys(msg)
+        }
+        CM::GetHistory(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::GetHistory(msg)
+        }
+        CM::Set(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::Set(msg)
+        }
+        CM::SetMany(mut msg) => {
+            for kvp in &mut msg.key_value_pairs {
+                kvp.key = prefixed(prefix, std::mem::take(&mut kvp.key));
+            }
+            CM::SetMany(msg)
+        }
+        CM::SetExpiring(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::SetExpiring(msg)
+        }
+        CM::Expire(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::Expire(msg)
+        }
+        CM::SetNx(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::SetNx(msg)
+        }
+        CM::SetXx(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::SetXx(msg)
+        }
+        CM::CompareAndSwap(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::CompareAndSwap(msg)
+        }
+        CM::Increment(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::Increment(msg)
+        }
+        CM::ArrayPush(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::ArrayPush(msg)
+        }
+        CM::ArrayPop(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::ArrayPop(msg)
+        }
+        CM::ArrayRemove(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::ArrayRemove(msg)
+        }
+        CM::HintInvalidation(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::HintInvalidation(msg)
+        }
+        CM::Publish(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::Publish(msg)
+        }
+        CM::PublishRetained(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::PublishRetained(msg)
+        }
+        CM::Subscribe(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::Subscribe(msg)
+        }
+        CM::PSubscribe(mut msg) => {
+            msg.request_pattern = prefixed(prefix, msg.request_pattern);
+            CM::PSubscribe(msg)
+        }
+        CM::Delete(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::Delete(msg)
+        }
+        CM::PDelete(mut msg) => {
+            msg.request_pattern = prefixed(prefix, msg.request_pattern);
+            CM::PDelete(msg)
+        }
+        CM::Reserve(mut msg) => {
+            msg.request_pattern = prefixed(prefix, msg.request_pattern);
+            CM::Reserve(msg)
+        }
+        CM::Release(mut msg) => {
+            msg.request_pattern = prefixed(prefix, msg.request_pattern);
+            CM::Release(msg)
+        }
+        CM::Lock(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::Lock(msg)
+        }
+        CM::TryLock(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::TryLock(msg)
+        }
+        CM::Unlock(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::Unlock(msg)
+        }
+        CM::Restore(mut msg) => {
+            msg.request_pattern = prefixed(prefix, msg.request_pattern);
+            CM::Restore(msg)
+        }
+        CM::StartRecording(mut msg) => {
+            msg.request_pattern = prefixed(prefix, msg.request_pattern);
+            CM::StartRecording(msg)
+        }
+        CM::ReplayRecording(mut msg) => {
+            msg.target_prefix = prefixed(prefix, msg.target_prefix);
+            CM::ReplayRecording(msg)
+        }
+        CM::WhoSubscribes(mut msg) => {
+            msg.request_pattern = prefixed(prefix, msg.request_pattern);
+            CM::WhoSubscribes(msg)
+        }
+        CM::Ls(mut msg) => {
+            msg.parent = Some(prefixed_parent(prefix, msg.parent));
+            CM::Ls(msg)
+        }
+        CM::SubscribeLs(mut msg) => {
+            msg.parent = Some(prefixed_parent(prefix, msg.parent));
+            CM::SubscribeLs(msg)
+        }
+        CM::Transform(mut msg) => {
+            msg.key = prefixed(prefix, msg.key);
+            CM::Transform(msg)
+        }
+        CM::AuthorizationRequest(_)
+        | CM::AuthenticationRequest(_)
+        | CM::KeyPrefix(_)
+        | CM::SwitchWireFormat(_)
+        | CM::ClientInfo(_)
+        | CM::Unsubscribe(_)
+        | CM::Resync(_)
+        | CM::Sync(_)
+        | CM::Cancel(_)
+        | CM::StopRecording(_)
+        | CM::UnsubscribeLs(_)
+        | CM::SubscribeEvents(_)
+        | CM::Keepalive => msg,
+    }
+}
+
+/// Removes the session's key prefix from all keys and patterns in a server message.
+pub(crate) fn strip_key_prefix(msg: ServerMessage, prefix: &str) -> ServerMessage {
+    match msg {
+        ServerMessage::State(mut msg) => {
+            msg.event = match msg.event {
+                StateEvent::KeyValue(kvp) => StateEvent::KeyValue(stripped_kvp(prefix, kvp)),
+                StateEvent::Deleted(kvp) => StateEvent::Deleted(stripped_kvp(prefix, kvp)),
+            };
+            ServerMessage::State(msg)
+        }
+        ServerMessage::PState(mut msg) => {
+            msg.request_pattern = stripped(prefix, msg.request_pattern);
+            msg.event = match msg.event {
+                PStateEvent::KeyValuePairs(kvps) => PStateEvent::KeyValuePairs(
+                    kvps.into_iter()
+                        .map(|kvp| stripped_kvp(prefix, kvp))
+                        .collect(),
+                ),
+                PStateEvent::Deleted(kvps) => PStateEvent::Deleted(
+                    kvps.into_iter()
+                        .map(|kvp| stripped_kvp(prefix, kvp))
+                        .collect(),
+                ),
+            };
+            ServerMessage::PState(msg)
+        }
+        ServerMessage::Keys(mut msg) => {
+            msg.keys = msg
+                .keys
+                .into_iter()
+                .map(|key| stripped(prefix, key))
+                .collect();
+            ServerMessage::Keys(msg)
+        }
+        msg => msg,
+    }
+}
+
+pub(crate) fn prefixed(prefix: &str, key: Key) -> Key {
+    if key == SYSTEM_TOPIC_ROOT || key.starts_with(SYSTEM_TOPIC_ROOT_PREFIX) {
+        key
+    } else {
+        topic!(prefix, key)
+    }
+}
+
+fn prefixed_parent(prefix: &str, parent: Option<Key>) -> Key {
+    match parent {
+        Some(parent) => prefixed(prefix, parent),
+        None => prefix.to_owned(),
+    }
+}
+
+fn stripped(prefix: &str, key: Key) -> Key {
+    match key
+        .strip_prefix(prefix)
+        .and_then(|key| key.strip_prefix('/'))
+    {
+        Some(stripped) => stripped.to_owned(),
+        None => key,
+    }
+}
+
+fn stripped_kvp(prefix: &str, kvp: KeyValuePair) -> KeyValuePair {
+    KeyValuePair {
+        key: stripped(prefix, kvp.key),
+        value: kvp.value,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use worterbuch_common::{Get, Ls, PState, State};
+
+    #[test]
+    fn keys_are_prefixed() {
+        let msg = add_key_prefix(
+            CM::Get(Get {
+                transaction_id: 1,
+                key: "hello/world".to_owned(),
+            }),
+            "staging",
+        );
+        assert_eq!(
+            msg,
+            CM::Get(Get {
+                transaction_id: 1,
+                key: "staging/hello/world".to_owned(),
+            })
+        );
+
+        let msg = add_key_prefix(
+            CM::Get(Get {
+                transaction_id: 2,
+                key: "$SYS/clients".to_owned(),
+            }),
+            "staging",
+        );
+        assert_eq!(
+            msg,
+            CM::Get(Get {
+                transaction_id: 2,
+                key: "$SYS/clients".to_owned(),
+            })
+        );
+
+        let msg = add_key_prefix(
+            CM::Ls(Ls {
+                transaction_id: 3,
+                parent: None,
+            }),
+            "staging",
+        );
+        assert_eq!(
+            msg,
+            CM::Ls(Ls {
+                transaction_id: 3,
+                parent: Some("staging".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    fn prefixes_are_stripped() {
+        let msg = strip_key_prefix(
+            ServerMessage::State(State {
+                transaction_id: 1,
+                event: StateEvent::KeyValue(("staging/hello/world", json!(1)).into()),
+                deprecated: None,
+                seq: None,
+            }),
+            "staging",
+        );
+        assert_eq!(
+            msg,
+            ServerMessage::State(State {
+                transaction_id: 1,
+                event: StateEvent::KeyValue(("hello/world", json!(1)).into()),
+                deprecated: None,
+                seq: None,
+            })
+        );
+
+        let msg = strip_key_prefix(
+            ServerMessage::PState(PState {
+                transaction_id: 2,
+                request_pattern: "staging/#".to_owned(),
+                event: PStateEvent::KeyValuePairs(vec![
+                    ("staging/a", json!(1)).into(),
+                    ("$SYS/clients", json!(2)).into(),
+                ]),
+                deprecated: None,
+                more: None,
+                seq: None,
+            }),
+            "staging",
+        );
+        assert_eq!(
+            msg,
+            ServerMessage::PState(PState {
+                transaction_id: 2,
+                request_pattern: "#".to_owned(),
+                event: PStateEvent::KeyValuePairs(vec![
+                    ("a", json!(1)).into(),
+                    ("$SYS/clients", json!(2)).into(),
+                ]),
+                deprecated: None,
+                more: None,
+                seq: None,
+            })
+        );
+    }
+}
@@ -18,8 +18,12 @@
  */
 
 use crate::{
-    server::common::{
-        check_client_keepalive, process_incoming_message, send_keepalive, CloneableWbApi,
+    server::{
+        common::{
+            check_client_keepalive, finish_oneshot_session, process_incoming_message,
+            reject_message, send_keepalive, CloneableWbApi, InFlight,
+        },
+        prefix::strip_key_prefix,
     },
     stats::VERSION,
 };
@@ -29,25 +33,33 @@ use std::{
     time::{Duration, Instant},
 };
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
+    io::BufReader,
     net::{tcp::OwnedWriteHalf, TcpListener, TcpStream},
     select, spawn,
-    sync::mpsc,
+    sync::{mpsc, watch},
     time::{sleep, MissedTickBehavior},
 };
 use tokio_graceful_shutdown::SubsystemHandle;
 use uuid::Uuid;
-use worterbuch_common::{tcp::write_line_and_flush, Protocol, ServerInfo, ServerMessage, Welcome};
+use worterbuch_common::{
+    tcp::{write_line_and_flush, LimitedLines, Line},
+    Protocol, ServerEvent, ServerInfo, ServerMessage, Welcome,
+};
 
 pub async fn start(
     worterbuch: CloneableWbApi,
     bind_addr: IpAddr,
     port: u16,
+    oneshot: bool,
     subsys: SubsystemHandle,
 ) -> anyhow::Result<()> {
     let addr = format!("{bind_addr}:{port}");
 
-    log::info!("Serving TCP endpoint at {addr}");
+    if oneshot {
+        log::info!("Serving one-shot TCP endpoint at {addr}");
+    } else {
+        log::info!("Serving TCP endpoint at {addr}");
+    }
     let listener = TcpListener::bind(&addr).await?;
 
     let (conn_closed_tx, mut conn_closed_rx) = mpsc::channel(100);
@@ -75,7 +87,7 @@ pub async fn start(
                         let worterbuch = worterbuch.clone();
                         let conn_closed_tx = conn_closed_tx.clone();
                         spawn(async move {
-                            if let Err(e) = serve(remote_addr, worterbuch, socket).await {
+                            if let Err(e) = serve(remote_addr, worterbuch, socket, oneshot).await {
                                 log::error!("Connection to client {remote_addr} closed with error: {e}");
                             }
                             conn_closed_tx.send(()).await.ok();
@@ -100,10 +112,23 @@ async fn serve(
     remote_addr: SocketAddr,
     worterbuch: CloneableWbApi,
     socket: TcpStream,
+    oneshot: bool,
 ) -> anyhow::Result<()> {
     let client_id = Uuid::new_v4();
 
-    log::info!("New client connected: {client_id} ({remote_addr})");
+    if oneshot {
+        tracing::info!(
+            %client_id,
+            %remote_addr,
+            "New one-shot client connected: {client_id} ({remote_addr})"
+        );
+    } else {
+        tracing::info!(
+            %client_id,
+            %remote_addr,
+            "New client connected: {client_id} ({remote_addr})"
+        );
+    }
 
     if let Err(e) = worterbuch
         .connected(client_id, remote_addr, Protocol::TCP)
@@ -113,8 +138,15 @@ async fn serve(
     } else {
         log::debug!("Receiving messages from client {client_id} ({remote_addr}) …",);
 
-        if let Err(e) = serve_loop(client_id, remote_addr, worterbuch.clone(), socket).await {
+        if let Err(e) =
+            serve_loop(client_id, remote_addr, worterbuch.clone(), socket, oneshot).await
+        {
             log::error!("Error in serve loop: {e}");
+            worterbuch
+                .emit_event(ServerEvent::Error(format!(
+                    "error in connection to client {client_id} ({remote_addr}): {e}"
+                )))
+                .await?;
         }
     }
 
@@ -130,9 +162,10 @@ async fn serve_loop(
     remote_addr: SocketAddr,
     worterbuch: CloneableWbApi,
     socket: TcpStream,
+    oneshot: bool,
 ) -> anyhow::Result<()> {
     let config = worterbuch.config().await?;
-    let authorization_required = config.auth_token.is_some();
+    let authorization_required = config.auth_required();
     let send_timeout = config.send_timeout;
     let keepalive_timeout = config.keepalive_timeout;
     let mut keepalive_timer = tokio::time::interval(Duration::from_secs(1));
@@ -144,10 +177,16 @@ async fn serve_loop(
     let (tcp_rx, mut tcp_tx) = socket.into_split();
     let (tcp_send_tx, mut tcp_send_rx) = mpsc::channel(config.channel_buffer_size);
     let (keepalive_tx_tx, mut keepalive_tx_rx) = mpsc::channel(config.channel_buffer_size);
+    let (key_prefix_tx, key_prefix_rx) = watch::channel(None);
+    let in_flight = InFlight::default();
 
     // tcp socket send loop
-    spawn(async move {
+    let send_loop = spawn(async move {
         while let Some(msg) = tcp_send_rx.recv().await {
+            let msg = match key_prefix_rx.borrow().as_deref() {
+                Some(prefix) => strip_key_prefix(msg, prefix),
+                None => msg,
+            };
             if let Err(e) =
                 send_with_timeout(msg, &mut tcp_tx, send_timeout, &keepalive_tx_tx).await
             {
@@ -157,26 +196,36 @@ async fn serve_loop(
         }
     });
 
-    let tcp_rx = BufReader::new(tcp_rx);
-    let mut tcp_rx = tcp_rx.lines();
+    let mut tcp_rx = LimitedLines::new(
+        BufReader::new(tcp_rx),
+        config.decoder_limits.max_message_size,
+    );
 
-    let protocol_version = worterbuch.supported_protocol_version().await?;
+    // one-shot clients know what they want, they don't need to be welcomed
+    if !oneshot {
+        let protocol_version = worterbuch.supported_protocol_version().await?;
+        let boot_id = worterbuch.boot_id().await?;
 
-    tcp_send_tx
-        .send(ServerMessage::Welcome(Welcome {
-            client_id: client_id.to_string(),
-            info: ServerInfo {
-                version: VERSION.to_owned(),
-                authorization_required,
-                protocol_version,
-            },
-        }))
-        .await?;
+        tcp_send_tx
+            .send(ServerMessage::Welcome(Welcome {
+                client_id: client_id.to_string(),
+                info: ServerInfo {
+                    version: VERSION.to_owned(),
+                    authorization_required,
+                    protocol_version,
+                    boot_id: Some(boot_id),
+                    // binary frames cannot be told apart from line breaks
+                    wire_formats: Vec::new(),
+                    compression: Vec::new(),
+                },
+            }))
+            .await?;
+    }
 
     loop {
         select! {
             recv = tcp_rx.next_line() => match recv {
-                Ok(Some(json)) => {
+                Ok(Some(Line::Complete(json))) => {
                     last_keepalive_rx = Instant::now();
 
                     // drain the send buffer to make room for the response
@@ -189,9 +238,11 @@ async fn serve_loop(
                         &json,
                         &worterbuch,
                         &tcp_send_tx,
-                        authorization_required,
                         authorized,
-                        &config
+                        &config,
+                        &key_prefix_tx,
+                        None,
+                        &in_flight,
                     ).await?;
                     authorized = auth;
                     if !msg_processed {
@@ -199,6 +250,17 @@ async fn serve_loop(
                     }
                     log::trace!("Processing incoming message done.");
                 },
+                Ok(Some(Line::TooLong(len))) => {
+                    last_keepalive_rx = Instant::now();
+                    if let Err(e) = config.decoder_limits.check_size(len) {
+                        reject_message(e, &tcp_send_tx).await?;
+                    }
+                },
+                Ok(Some(Line::InvalidUtf8(pos))) => {
+                    // the protocol is JSON, so this client is not speaking it
+                    log::warn!("Client {client_id} ({remote_addr}) sent invalid UTF-8 at byte {pos}, closing connection.");
+                    break;
+                },
                 Ok(None) =>  break,
                 Err(e) => {
                     log::warn!("TCP stream of client {client_id} ({remote_addr}) closed with error:, {e}");
@@ -214,7 +276,7 @@ async fn serve_loop(
                 },
                 None => break,
             },
-            _ = keepalive_timer.tick() => {
+            _ = keepalive_timer.tick(), if !oneshot => {
                 // check how long ago the last websocket message was received
                 check_client_keepalive(last_keepalive_rx, last_keepalive_tx, client_id, keepalive_timeout)?;
                 // send out websocket message if the last has been more than a second ago
@@ -223,6 +285,11 @@ async fn serve_loop(
         }
     }
 
+    if oneshot {
+        // the client has closed its write half, send out all remaining responses before closing ours
+        finish_oneshot_session(client_id, tcp_send_tx, send_loop, send_timeout).await;
+    }
+
     Ok(())
 }
 
@@ -0,0 +1,201 @@
+/*
+ *  Worterbuch standby module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{server::common::CloneableWbApi, INTERNAL_CLIENT_ID};
+use anyhow::{anyhow, Result};
+use std::{collections::HashSet, fmt, time::Duration};
+use tokio::{select, time::sleep};
+use tokio_graceful_shutdown::SubsystemHandle;
+use worterbuch_client::{config::Config as ClientConfig, connect, SubscriptionEvent};
+use worterbuch_common::{
+    error::WorterbuchError, redact::REDACTED, Key, KeyValuePair, KeyValuePairs, PStateEvent,
+    SYSTEM_TOPIC_ROOT_PREFIX,
+};
+
+/// How long to wait before trying to reach the primary again after losing the connection.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Turns the server into a warm standby of another worterbuch instance. The standby subscribes
+/// to the whole store of the primary over the network, first receiving a full snapshot and then
+/// every change as it happens, so it can take over from the primary with at most a few in-flight
+/// changes lost. `$SYS` keys are local to each server and are not replicated.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Standby {
+    /// Address of the primary, e.g. `ws://primary:8080/ws` or `tcp://primary:8081`.
+    pub primary: String,
+    pub auth_token: Option<String>,
+}
+
+impl fmt::Debug for Standby {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Standby")
+            .field("primary", &self.primary)
+            .field("auth_token", &self.auth_token.as_ref().map(|_| REDACTED))
+            .finish()
+    }
+}
+
+impl Standby {
+    fn client_config(&self) -> Result<ClientConfig> {
+        client_config(&self.primary, self.auth_token.clone())
+    }
+}
+
+/// Creates the config for connecting to another server at an address like `ws://host:8080/ws`.
+pub(crate) fn client_config(server: &str, auth_token: Option<String>) -> Result<ClientConfig> {
+    let invalid = || anyhow!("invalid server address '{server}'");
+    let (proto, address) = server.split_once("://").ok_or_else(invalid)?;
+    let (authority, ws_path) = match address.split_once('/') {
+        Some((authority, path)) => (authority, format!("/{path}")),
+        None => (address, "/ws".to_owned()),
+    };
+    let (host_addr, port) = authority.rsplit_once(':').ok_or_else(invalid)?;
+    let port = port.parse().map_err(|_| invalid())?;
+
+    // not loading the client's env vars here, they would clash with the server's own config
+    Ok(ClientConfig {
+        proto: proto.to_owned(),
+        host_addr: host_addr.to_owned(),
+        port,
+        ws_path,
+        auth_token,
+        ..ClientConfig::default()
+    })
+}
+
+pub(crate) async fn follow(
+    worterbuch: CloneableWbApi,
+    standby: Standby,
+    subsys: SubsystemHandle,
+) -> Result<()> {
+    let config = standby.client_config()?;
+
+    loop {
+        log::info!("Following primary at {} …", standby.primary);
+        select! {
+            res = replicate(&worterbuch, config.clone()) => match res {
+                Ok(()) => log::warn!("Lost connection to primary."),
+                Err(e) => log::warn!("Error replicating from primary: {e}"),
+            },
+            _ = subsys.on_shutdown_requested() => break,
+        }
+        select! {
+            _ = sleep(RECONNECT_DELAY) => (),
+            _ = subsys.on_shutdown_requested() => break,
+        }
+    }
+
+    Ok(())
+}
+
+async fn replicate(worterbuch: &CloneableWbApi, config: ClientConfig) -> Result<()> {
+    let primary = connect(config, async {}).await?;
+    let (mut events, _) = primary
+        .psubscribe_marked_generic("#".to_owned(), true, false, None)
+        .await?;
+
+    // keys received as part of the initial snapshot, anything else is stale once it is complete
+    let mut snapshot = Some(HashSet::new());
+
+    while let Some(event) = events.recv().await {
+        match event {
+            SubscriptionEvent::Event(PStateEvent::KeyValuePairs(kvps)) => {
+                for kvp in replicated(kvps) {
+                    if let Some(snapshot) = &mut snapshot {
+                        snapshot.insert(kvp.key.clone());
+                    }
+                    worterbuch
+                        .set(kvp.key, kvp.value, INTERNAL_CLIENT_ID.to_owned())
+                        .await?;
+                }
+            }
+            SubscriptionEvent::Event(PStateEvent::Deleted(kvps)) => {
+                for kvp in replicated(kvps) {
+                    if let Some(snapshot) = &mut snapshot {
+                        snapshot.remove(&kvp.key);
+                    }
+                    delete(worterbuch, kvp.key).await?;
+                }
+            }
+            SubscriptionEvent::InitialStateComplete => {
+                if let Some(snapshot) = snapshot.take() {
+                    remove_stale(worterbuch, &snapshot).await?;
+                    log::info!(
+                        "Caught up with primary, {} keys replicated.",
+                        snapshot.len()
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn replicated(kvps: KeyValuePairs) -> impl Iterator<Item = KeyValuePair> {
+    kvps.into_iter()
+        .filter(|kvp| !kvp.key.starts_with(SYSTEM_TOPIC_ROOT_PREFIX))
+}
+
+async fn remove_stale(worterbuch: &CloneableWbApi, snapshot: &HashSet<Key>) -> Result<()> {
+    for kvp in replicated(worterbuch.pget("#".to_owned()).await?) {
+        if !snapshot.contains(&kvp.key) {
+            delete(worterbuch, kvp.key).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn delete(worterbuch: &CloneableWbApi, key: Key) -> Result<()> {
+    match worterbuch.delete(key, INTERNAL_CLIENT_ID.to_owned()).await {
+        Ok(_) | Err(WorterbuchError::NoSuchValue(_)) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn primary_address_is_parsed_into_client_config() {
+        let standby = Standby {
+            primary: "tcp://primary:8081".to_owned(),
+            auth_token: Some("secret".to_owned()),
+        };
+        let config = standby.client_config().expect("valid address");
+        assert_eq!(config.proto, "tcp");
+        assert_eq!(config.host_addr, "primary");
+        assert_eq!(config.port, 8081);
+        assert_eq!(config.auth_token.as_deref(), Some("secret"));
+
+        let standby = Standby {
+            primary: "ws://10.0.0.1:8080/api/ws".to_owned(),
+            auth_token: None,
+        };
+        let config = standby.client_config().expect("valid address");
+        assert_eq!(config.ws_path, "/api/ws");
+
+        let standby = Standby {
+            primary: "primary:8080".to_owned(),
+            auth_token: None,
+        };
+        assert!(standby.client_config().is_err());
+    }
+}
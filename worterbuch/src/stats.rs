@@ -17,18 +17,19 @@
  *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::{server::common::CloneableWbApi, INTERNAL_CLIENT_ID};
-use serde_json::json;
-use std::time::Duration;
-use tokio::{
-    select,
-    time::{interval, Instant},
+use crate::{worterbuch::Worterbuch, INTERNAL_CLIENT_ID};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    time::{Duration, Instant},
 };
-use tokio_graceful_shutdown::SubsystemHandle;
+use uuid::Uuid;
 #[cfg(not(feature = "commercial"))]
 use worterbuch_common::SYSTEM_TOPIC_SOURCES;
 use worterbuch_common::{
-    error::WorterbuchResult, topic, SYSTEM_TOPIC_LICENSE, SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_VERSION,
+    error::WorterbuchResult, topic, Key, SYSTEM_TOPIC_CLIENTS, SYSTEM_TOPIC_LICENSE,
+    SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_VERSION,
 };
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -39,28 +40,114 @@ pub const LICENSE: &str = "COMMERCIAL";
 #[cfg(not(feature = "commercial"))]
 pub const REPO: &str = env!("CARGO_PKG_REPOSITORY");
 
-pub async fn track_stats(wb: CloneableWbApi, subsys: SubsystemHandle) -> WorterbuchResult<()> {
-    let start = Instant::now();
+pub const SYSTEM_KEY_UPTIME: &str = "$SYS/uptime";
+pub const SYSTEM_KEY_VALUE_COUNT: &str = "$SYS/store/values/count";
+pub const SYSTEM_KEY_COMPACTION_RECLAIMED: &str = "$SYS/store/compaction/reclaimed";
+pub const SYSTEM_KEY_LS_CACHE_HITS: &str = "$SYS/stats/lscache/hits";
+pub const SYSTEM_KEY_LS_CACHE_MISSES: &str = "$SYS/stats/lscache/misses";
+pub const SYSTEM_KEY_LS_CACHE_HIT_RATE: &str = "$SYS/stats/lscache/hitRate";
+pub const SYSTEM_KEY_LS_CACHE_ENTRIES: &str = "$SYS/stats/lscache/entries";
 
+/// System keys whose values are not stored but computed whenever they are requested.
+pub const COMPUTED_KEYS: &[&str] = &[
+    SYSTEM_KEY_UPTIME,
+    SYSTEM_KEY_VALUE_COUNT,
+    SYSTEM_KEY_COMPACTION_RECLAIMED,
+    SYSTEM_KEY_LS_CACHE_HITS,
+    SYSTEM_KEY_LS_CACHE_MISSES,
+    SYSTEM_KEY_LS_CACHE_HIT_RATE,
+    SYSTEM_KEY_LS_CACHE_ENTRIES,
+];
+
+/// How often queued monitoring writes are applied to the store.
+pub const STATS_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Monitoring writes to `$SYS` that are applied in one batch per tick instead of one at a time, so
+/// they never hold up client requests. A later write to a key replaces any pending earlier one.
+#[derive(Debug)]
+pub struct StatsQueue {
+    writes: HashMap<Key, Option<Value>>,
+    recount: HashSet<Uuid>,
+    last_flush: Instant,
+}
+
+impl Default for StatsQueue {
+    fn default() -> Self {
+        Self {
+            writes: HashMap::new(),
+            recount: HashSet::new(),
+            last_flush: Instant::now(),
+        }
+    }
+}
+
+impl StatsQueue {
+    pub fn set(&mut self, key: Key, value: Value) {
+        self.writes.insert(key, Some(value));
+    }
+
+    pub fn delete(&mut self, key: Key) {
+        self.writes.insert(key, None);
+    }
+
+    /// Increments a counter, starting from a pending write to it or, if there is none, from the
+    /// given current value.
+    pub fn increment(&mut self, key: Key, current: impl FnOnce() -> u64) {
+        let pending = self
+            .writes
+            .get(&key)
+            .and_then(|v| v.as_ref())
+            .and_then(Value::as_u64);
+        let count = pending.unwrap_or_else(current) + 1;
+        self.writes.insert(key, Some(json!(count)));
+    }
+
+    /// Marks the subscription count of a client as outdated. It is recounted after all pending
+    /// writes have been applied.
+    pub fn recount_subscriptions(&mut self, client_id: Uuid) {
+        self.recount.insert(client_id);
+    }
+
+    /// Drops all pending writes of a client that disconnected, so they do not bring back its keys.
+    pub fn forget_client(&mut self, client_id: &Uuid) {
+        let prefix = topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_CLIENTS, client_id, "");
+        self.writes.retain(|key, _| !key.starts_with(&prefix));
+        self.recount.remove(client_id);
+    }
+
+    /// Whether client traffic kept the queue from being flushed for much longer than usual.
+    pub fn is_overdue(&self) -> bool {
+        self.last_flush.elapsed() > 10 * STATS_FLUSH_INTERVAL
+    }
+
+    pub fn take(&mut self) -> (HashMap<Key, Option<Value>>, HashSet<Uuid>) {
+        self.last_flush = Instant::now();
+        (mem::take(&mut self.writes), mem::take(&mut self.recount))
+    }
+}
+
+/// Writes the server's static system keys. These never change while the server is running, so no
+/// background task is needed to keep them up to date.
+pub async fn track_stats(wb: &mut Worterbuch) -> WorterbuchResult<()> {
     wb.set(
         topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_VERSION),
         json!(VERSION),
-        INTERNAL_CLIENT_ID.to_owned(),
+        INTERNAL_CLIENT_ID,
     )
     .await?;
 
     wb.set(
         topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_LICENSE),
         json!(LICENSE),
-        INTERNAL_CLIENT_ID.to_owned(),
+        INTERNAL_CLIENT_ID,
     )
     .await?;
 
     #[cfg(feature = "commercial")]
     wb.set(
         topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_LICENSE, "data"),
-        json!(wb.config().await?.license),
-        INTERNAL_CLIENT_ID.to_owned(),
+        json!(wb.config().license),
+        INTERNAL_CLIENT_ID,
     )
     .await?;
 
@@ -68,43 +155,9 @@ pub async fn track_stats(wb: CloneableWbApi, subsys: SubsystemHandle) -> Worterb
     wb.set(
         topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_SOURCES),
         json!(format!("{REPO}/releases/tag/v{VERSION}")),
-        INTERNAL_CLIENT_ID.to_owned(),
+        INTERNAL_CLIENT_ID,
     )
     .await?;
 
-    let mut interval = interval(Duration::from_secs(1));
-
-    loop {
-        select! {
-            _ = interval.tick() => update_stats(&wb, start).await?,
-            _ = subsys.on_shutdown_requested() => break,
-        }
-    }
-
     Ok(())
 }
-
-async fn update_stats(wb: &CloneableWbApi, start: Instant) -> WorterbuchResult<()> {
-    update_uptime(wb, start.elapsed()).await?;
-    update_message_count(wb).await?;
-    Ok(())
-}
-
-async fn update_uptime(wb: &CloneableWbApi, uptime: Duration) -> WorterbuchResult<()> {
-    wb.set(
-        format!("{SYSTEM_TOPIC_ROOT}/uptime"),
-        json!(uptime.as_secs()),
-        INTERNAL_CLIENT_ID.to_owned(),
-    )
-    .await
-}
-
-async fn update_message_count(wb: &CloneableWbApi) -> WorterbuchResult<()> {
-    let len = wb.len().await?;
-    wb.set(
-        format!("{SYSTEM_TOPIC_ROOT}/store/values/count"),
-        json!(len),
-        INTERNAL_CLIENT_ID.to_owned(),
-    )
-    .await
-}
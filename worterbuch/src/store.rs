@@ -18,19 +18,31 @@
  */
 
 use serde::{Deserialize, Serialize};
-use std::collections::{hash_map::Entry, HashMap};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    panic,
+    sync::Arc,
+    thread,
+};
 use worterbuch_common::{
     error::{WorterbuchError, WorterbuchResult},
-    parse_segments, KeySegment, KeyValuePair, KeyValuePairs, RegularKeySegment, Value,
+    parse_segments, Key, KeySegment, KeyValuePair, KeyValuePairs, RegularKeySegment, Value,
 };
 
-use crate::subscribers::{LsSubscriber, Subscriber, SubscriptionId};
+use crate::{
+    lscache::{LsCache, LsCacheStats},
+    subscribers::{LsSubscriber, Subscriber, SubscriptionId},
+};
 
 type NodeValue = Option<Value>;
-type Tree = HashMap<RegularKeySegment, Node>;
+type Tree = HashMap<RegularKeySegment, Arc<Node>>;
 type SubscribersTree = HashMap<RegularKeySegment, SubscribersNode>;
 type CanDelete = bool;
 
+/// Minimum number of sibling subtrees below the literal prefix of a pattern before `pget` scans them
+/// in parallel. Smaller scans are not worth the overhead of spawning threads.
+const PARALLEL_SCAN_THRESHOLD: usize = 64;
+
 pub type AffectedLsSubscribers = (Vec<LsSubscriber>, Vec<RegularKeySegment>);
 
 #[derive(Debug)]
@@ -48,12 +60,16 @@ impl StoreError {
 
 pub type StoreResult<T> = Result<T, StoreError>;
 
-#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Node {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub v: NodeValue,
     #[serde(skip_serializing_if = "Tree::is_empty", default = "Tree::default")]
     pub t: Tree,
+    /// Incremented every time the node's value changes. Versions are not persisted, values
+    /// restored from disk start out at version 1.
+    #[serde(skip)]
+    pub ver: u64,
 }
 
 #[derive(Debug, Default)]
@@ -68,9 +84,58 @@ pub struct StoreStats {
     num_entries: usize,
 }
 
+/// A point-in-time view of the store's data. Taking a snapshot is cheap: nodes are shared between
+/// the store and its snapshots and the store only copies the nodes it modifies while a snapshot
+/// still references them.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoreSnapshot {
+    data: Arc<Node>,
+}
+
+impl StoreSnapshot {
+    pub fn get_matches(&self, path: &[KeySegment]) -> StoreResult<Vec<KeyValuePair>> {
+        Store::nget_matches(&self.data, path)
+    }
+
+    pub fn visit_matches(
+        &self,
+        path: &[KeySegment],
+        visitor: &mut impl FnMut(KeyValuePair),
+    ) -> StoreResult<()> {
+        Store::nvisit_matches(&self.data, path, visitor)
+    }
+
+    /// Like [`StoreSnapshot::visit_matches`], but only passes the keys of the matches, without
+    /// copying their values.
+    pub fn visit_matching_keys(
+        &self,
+        path: &[KeySegment],
+        visitor: &mut impl FnMut(Key),
+    ) -> StoreResult<()> {
+        Store::nvisit_matching_keys(&self.data, path, visitor)
+    }
+
+    /// Like [`StoreSnapshot::visit_matches`], but regular segments of the pattern match keys
+    /// regardless of their case.
+    pub fn visit_matches_ignoring_case(
+        &self,
+        path: &[KeySegment],
+        visitor: &mut impl FnMut(KeyValuePair),
+    ) -> StoreResult<()> {
+        let path: Vec<KeySegment> = path
+            .iter()
+            .map(|segment| match segment {
+                KeySegment::Regular(elem) => KeySegment::Regular(elem.to_lowercase()),
+                other => other.clone(),
+            })
+            .collect();
+        Store::nvisit_matches_ignoring_case(&self.data, &mut Vec::new(), &path, visitor)
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Store {
-    data: Node,
+    data: Arc<Node>,
     #[serde(skip_serializing, default = "usize::default")]
     len: usize,
     #[serde(
@@ -79,6 +144,8 @@ pub struct Store {
         default = "SubscribersNode::default"
     )]
     subscribers: SubscribersNode,
+    #[serde(skip_serializing, skip_deserializing, default = "LsCache::default")]
+    ls_cache: LsCache,
 }
 
 impl Store {
@@ -96,8 +163,22 @@ impl Store {
         node.and_then(|n| n.v.as_ref())
     }
 
+    /// The version of the value stored at a non-wildcard key or 0 if there is no value.
+    pub fn version(&self, path: &[RegularKeySegment]) -> u64 {
+        match self.get_node(path) {
+            Some(node) if node.v.is_some() => node.ver.max(1),
+            _ => 0,
+        }
+    }
+
+    pub fn snapshot(&self) -> StoreSnapshot {
+        StoreSnapshot {
+            data: self.data.clone(),
+        }
+    }
+
     fn get_node(&self, path: &[RegularKeySegment]) -> Option<&Node> {
-        let mut current = &self.data;
+        let mut current: &Node = &self.data;
 
         for elem in path {
             if let Some(node) = current.t.get(elem) {
@@ -116,7 +197,7 @@ impl Store {
     ) -> Option<(Value, Vec<AffectedLsSubscribers>)> {
         let mut ls_subscribers = Vec::new();
         let removed = Store::ndelete(
-            &mut self.data,
+            Arc::make_mut(&mut self.data),
             path,
             Some(&self.subscribers),
             &mut ls_subscribers,
@@ -124,23 +205,185 @@ impl Store {
         .0;
         if removed.is_some() {
             self.len -= 1;
+            self.ls_cache.invalidate_ancestors(path);
         }
         removed.map(|it| (it, ls_subscribers))
     }
 
     /// retrieve values for a key containing at least one single-level wildcard and possibly a multi-level wildcard
     pub fn get_matches(&self, path: &[KeySegment]) -> StoreResult<Vec<KeyValuePair>> {
+        Store::nget_matches(&self.data, path)
+    }
+
+    /// Walks the tree and passes every key/value pair matching the pattern to the visitor as soon as
+    /// it is found, without collecting them first.
+    pub fn visit_matches(
+        &self,
+        path: &[KeySegment],
+        visitor: &mut impl FnMut(KeyValuePair),
+    ) -> StoreResult<()> {
+        Store::nvisit_matches(&self.data, path, visitor)
+    }
+
+    fn nget_matches(root: &Node, path: &[KeySegment]) -> StoreResult<Vec<KeyValuePair>> {
+        if let Some(matches) = Store::nget_matches_parallel(root, path)? {
+            return Ok(matches);
+        }
+
         let mut matches = Vec::new();
+        Store::nvisit_matches(root, path, &mut |kvp| matches.push(kvp))?;
+        Ok(matches)
+    }
+
+    fn nvisit_matches(
+        root: &Node,
+        path: &[KeySegment],
+        visitor: &mut impl FnMut(KeyValuePair),
+    ) -> StoreResult<()> {
         let traversed = vec![];
         Store::ncollect_matches(
-            &self.data,
+            root,
             traversed,
             path,
-            &mut matches,
+            &mut |key, value| visitor((key, value.to_owned()).into()),
             None,
             &mut Vec::new(),
-        )?;
-        Ok(matches)
+        )
+    }
+
+    fn nvisit_matching_keys(
+        root: &Node,
+        path: &[KeySegment],
+        visitor: &mut impl FnMut(Key),
+    ) -> StoreResult<()> {
+        let traversed = vec![];
+        Store::ncollect_matches(
+            root,
+            traversed,
+            path,
+            &mut |key, _| visitor(key),
+            None,
+            &mut Vec::new(),
+        )
+    }
+
+    /// Expects the regular segments of the path to be lowercase already.
+    fn nvisit_matches_ignoring_case<'n>(
+        node: &'n Node,
+        traversed: &mut Vec<&'n str>,
+        remaining: &[KeySegment],
+        visitor: &mut impl FnMut(KeyValuePair),
+    ) -> StoreResult<()> {
+        let Some((next, tail)) = remaining.split_first() else {
+            if let Some(value) = &node.v {
+                visitor((traversed.join("/"), value.to_owned()).into());
+            }
+            return Ok(());
+        };
+
+        let remaining = if next == &KeySegment::MultiWildcard {
+            if !tail.is_empty() {
+                return Err(StoreError::IllegalMultiWildcard);
+            }
+            if let Some(value) = &node.v {
+                visitor((traversed.join("/"), value.to_owned()).into());
+            }
+            remaining
+        } else {
+            tail
+        };
+
+        for (key, child) in &node.t {
+            if let KeySegment::Regular(elem) = next {
+                if &key.to_lowercase() != elem {
+                    continue;
+                }
+            }
+            traversed.push(key);
+            Store::nvisit_matches_ignoring_case(child, traversed, remaining, visitor)?;
+            traversed.pop();
+        }
+
+        Ok(())
+    }
+
+    /// Walks the literal prefix of the pattern and, if the first wildcard level below it has enough
+    /// children, scans the sibling subtrees on multiple threads. Returns `None` if the pattern is
+    /// better evaluated on the calling thread.
+    fn nget_matches_parallel(
+        root: &Node,
+        path: &[KeySegment],
+    ) -> StoreResult<Option<Vec<KeyValuePair>>> {
+        let workers = thread::available_parallelism().map_or(1, |it| it.get());
+        if workers <= 1 {
+            return Ok(None);
+        }
+
+        let mut node = root;
+        let mut traversed = Vec::new();
+        let mut remaining = path;
+        while let Some(KeySegment::Regular(elem)) = remaining.first() {
+            match node.t.get(elem) {
+                Some(child) => node = child,
+                None => return Ok(Some(Vec::new())),
+            }
+            traversed.push(elem.as_str());
+            remaining = &remaining[1..];
+        }
+
+        let tail = match remaining.first() {
+            Some(KeySegment::Wildcard) => &remaining[1..],
+            Some(KeySegment::MultiWildcard) if remaining.len() == 1 => remaining,
+            Some(KeySegment::MultiWildcard) => return Err(StoreError::IllegalMultiWildcard),
+            _ => return Ok(None),
+        };
+
+        if node.t.len() < PARALLEL_SCAN_THRESHOLD {
+            return Ok(None);
+        }
+
+        let mut matches = Vec::new();
+        if let (Some(KeySegment::MultiWildcard), Some(value)) = (remaining.first(), &node.v) {
+            matches.push((traversed.join("/"), value.to_owned()).into());
+        }
+
+        let children: Vec<(&RegularKeySegment, &Arc<Node>)> = node.t.iter().collect();
+        let chunk_size = children.len().div_ceil(workers);
+
+        let results: Vec<StoreResult<Vec<KeyValuePair>>> = thread::scope(|scope| {
+            let handles: Vec<_> = children
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let traversed = &traversed;
+                    scope.spawn(move || -> StoreResult<Vec<KeyValuePair>> {
+                        let mut matches = Vec::new();
+                        for (key, child) in chunk {
+                            let mut traversed_path = traversed.clone();
+                            traversed_path.push(key.as_str());
+                            Store::ncollect_matches(
+                                child,
+                                traversed_path,
+                                tail,
+                                &mut |key, value| matches.push((key, value.to_owned()).into()),
+                                None,
+                                &mut Vec::new(),
+                            )?;
+                        }
+                        Ok(matches)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+                .collect()
+        });
+
+        for result in results {
+            matches.extend(result?);
+        }
+
+        Ok(Some(matches))
     }
 
     pub fn delete_matches(
@@ -151,13 +394,16 @@ impl Store {
         let mut matches = Vec::new();
         let traversed_path = vec![];
         Store::ndelete_matches(
-            &mut self.data,
+            Arc::make_mut(&mut self.data),
             traversed_path,
             &mut matches,
             path,
             Some(&self.subscribers),
             &mut ls_subscribers,
         )?;
+        if !matches.is_empty() {
+            self.ls_cache.clear();
+        }
         if self.len < matches.len() {
             self.len = 0;
         } else {
@@ -181,7 +427,7 @@ impl Store {
         let tail = &relative_path[1..];
 
         if let Entry::Occupied(mut e) = node.t.entry(head.to_owned()) {
-            let next = e.get_mut();
+            let next = Arc::make_mut(e.get_mut());
             let (val, can_delete) = Store::ndelete(
                 next,
                 tail,
@@ -232,7 +478,7 @@ impl Store {
                     node,
                     traversed_path.clone(),
                     &[KeySegment::MultiWildcard],
-                    matches,
+                    &mut |key, value| matches.push((key, value.to_owned()).into()),
                     subscribers,
                     ls_subscribers,
                 )?;
@@ -284,7 +530,7 @@ impl Store {
     ) -> StoreResult<()> {
         traversed_path.push(id);
         if let Entry::Occupied(mut e) = node.t.entry(id.to_owned()) {
-            let child = e.get_mut();
+            let child = Arc::make_mut(e.get_mut());
             let can_delete = Store::ndelete_matches(
                 child,
                 traversed_path,
@@ -312,14 +558,13 @@ impl Store {
         node: &Node,
         mut traversed_path: Vec<&'p str>,
         remaining_path: &'p [KeySegment],
-        matches: &mut Vec<KeyValuePair>,
+        matches: &mut impl FnMut(Key, &Value),
         subscribers: Option<&SubscribersNode>,
         ls_subscribers: &mut Vec<(Vec<LsSubscriber>, Vec<String>)>,
     ) -> StoreResult<()> {
         if remaining_path.is_empty() {
             if let Some(value) = &node.v {
-                let key = traversed_path.join("/");
-                matches.push((key, value.to_owned()).into());
+                matches(traversed_path.join("/"), value);
             }
 
             return Ok(());
@@ -335,8 +580,7 @@ impl Store {
                 }
 
                 if let Some(value) = &node.v {
-                    let key = traversed_path.join("/");
-                    matches.push((key, value.to_owned()).into());
+                    matches(traversed_path.join("/"), value);
                 }
 
                 for (key, node) in &node.t {
@@ -405,20 +649,21 @@ impl Store {
     ) -> StoreResult<(bool, Vec<AffectedLsSubscribers>)> {
         let mut ls_subscribers = Vec::new();
         let changed = {
-            let mut current_node = &mut self.data;
+            let mut current_node = Arc::make_mut(&mut self.data);
             let mut current_subscribers = Some(&self.subscribers);
 
             for (i, elem) in path.iter().enumerate() {
                 current_node = match current_node.t.entry(elem.to_owned()) {
-                    Entry::Occupied(e) => e.into_mut(),
+                    Entry::Occupied(e) => Arc::make_mut(e.into_mut()),
                     Entry::Vacant(e) => {
+                        self.ls_cache.invalidate(&path[0..i]);
                         if let Some(subscribers) = current_subscribers {
                             if !subscribers.ls_subscribers.is_empty() {
                                 let subscribers = subscribers.ls_subscribers.clone();
                                 ls_subscribers.push((subscribers, &path[0..i]));
                             }
                         }
-                        e.insert(Node::default())
+                        Arc::make_mut(e.insert(Arc::default()))
                     }
                 };
 
@@ -433,6 +678,16 @@ impl Store {
 
             current_node.v = Some(value);
 
+            if changed {
+                // a node that still holds a value it was deserialized with has version 0, which is
+                // reported as 1 and must not be reused for the next value
+                current_node.ver = if inserted {
+                    current_node.ver
+                } else {
+                    current_node.ver.max(1)
+                } + 1;
+            }
+
             if inserted {
                 self.len += 1;
             }
@@ -461,7 +716,7 @@ impl Store {
         if path.is_empty() {
             panic!("path must not be empty!");
         }
-        let mut current = &self.data;
+        let mut current: &Node = &self.data;
 
         for elem in path {
             current = match current.t.get(elem.as_ref()) {
@@ -477,15 +732,78 @@ impl Store {
         self.data.t.keys().map(ToOwned::to_owned).collect()
     }
 
+    /// Same as [`Store::ls`]/[`Store::ls_root`], but serves frequently listed parents from a cache.
+    pub fn ls_cached(&mut self, path: &[impl AsRef<str>]) -> Option<Vec<RegularKeySegment>> {
+        if let Some(children) = self.ls_cache.get(path) {
+            return Some(children);
+        }
+        let children = if path.is_empty() {
+            Some(self.ls_root())
+        } else {
+            self.ls(path)
+        };
+        if let Some(children) = &children {
+            self.ls_cache.insert(path, children.clone());
+        }
+        children
+    }
+
+    pub fn ls_cache_stats(&self) -> LsCacheStats {
+        self.ls_cache.stats()
+    }
+
     pub fn merge(&mut self, other: Store) -> Vec<(String, Value)> {
         let mut insertions = Vec::new();
         let path = Vec::new();
-        Store::nmerge(&mut self.data, other.data, None, &mut insertions, &path);
+        Store::nmerge(
+            Arc::make_mut(&mut self.data),
+            Arc::unwrap_or_clone(other.data),
+            None,
+            &mut insertions,
+            &path,
+        );
         self.len = Store::ncount_values(&self.data);
+        self.ls_cache.clear();
         // TODO notify subscribers
         insertions
     }
 
+    /// Prunes branches of the tree that neither hold a value nor lead to one and returns the number
+    /// of nodes that were removed. Only nodes on the way to an empty branch are touched, so nodes
+    /// shared with a snapshot are not copied unless they actually change.
+    pub fn compact(&mut self) -> usize {
+        let reclaimed = Store::ncompact(&mut self.data);
+        if reclaimed > 0 {
+            self.ls_cache.clear();
+        }
+        reclaimed
+    }
+
+    fn ncompact(node: &mut Arc<Node>) -> usize {
+        if !Store::nneeds_compaction(node) {
+            return 0;
+        }
+
+        let node = Arc::make_mut(node);
+        let mut reclaimed = 0;
+        for child in node.t.values_mut() {
+            reclaimed += Store::ncompact(child);
+        }
+        let before = node.t.len();
+        node.t.retain(|_, child| !Store::nis_empty(child));
+        reclaimed + before - node.t.len()
+    }
+
+    fn nneeds_compaction(node: &Node) -> bool {
+        node.t
+            .values()
+            .any(|child| Store::nis_empty(child) || Store::nneeds_compaction(child))
+    }
+
+    fn nis_empty(node: &Node) -> bool {
+        node.v.is_none() && node.t.is_empty()
+    }
+
     pub fn count_entries(&mut self) {
         self.len = Store::ncount_values(&self.data);
     }
@@ -518,9 +836,15 @@ impl Store {
         for (key, other_node) in other.t {
             let own_node = match node.t.entry(key.clone()) {
                 Entry::Occupied(e) => e.into_mut(),
-                Entry::Vacant(e) => e.insert(Node::default()),
+                Entry::Vacant(e) => e.insert(Arc::default()),
             };
-            Store::nmerge(own_node, other_node, Some(&key), insertions, &path);
+            Store::nmerge(
+                Arc::make_mut(own_node),
+                Arc::unwrap_or_clone(other_node),
+                Some(&key),
+                insertions,
+                &path,
+            );
         }
     }
 
@@ -649,6 +973,24 @@ mod test {
         assert_eq!(store.get(&reg_key_segs("test/a/b")), None);
     }
 
+    #[test]
+    fn versions_only_change_with_the_value() {
+        let path = reg_key_segs("test/a/b");
+
+        let mut store = Store::default();
+        assert_eq!(store.version(&path), 0);
+
+        store.insert(&path, json!(1)).unwrap();
+        assert_eq!(store.version(&path), 1);
+
+        store.insert(&path, json!(1)).unwrap();
+        assert_eq!(store.version(&path), 1);
+
+        store.insert(&path, json!(2)).unwrap();
+        assert_eq!(store.version(&path), 2);
+        assert_eq!(store.version(&reg_key_segs("test/a")), 0);
+    }
+
     #[test]
     fn test_wildcard() {
         let path0 = reg_key_segs("trolo/a");
@@ -715,6 +1057,51 @@ mod test {
             .is_some());
     }
 
+    #[test]
+    fn test_compact() {
+        let mut store: Store = serde_json::from_str(
+            r#"{"data":{"t":{"a":{"t":{"b":{"t":{"c":{}}}}},"d":{"v":1,"t":{"e":{}}}}}}"#,
+        )
+        .unwrap();
+        let snapshot = store.snapshot();
+
+        assert_eq!(store.compact(), 4);
+        assert_eq!(store.compact(), 0);
+        assert_eq!(store.ls_root(), vec!["d".to_owned()]);
+        assert_eq!(store.ls(&["d"]), Some(Vec::new()));
+        assert_eq!(store.get(&reg_key_segs("d")), Some(&json!(1)));
+        assert_eq!(snapshot.data.t.len(), 2);
+    }
+
+    #[test]
+    fn test_parallel_wildcard() {
+        let mut store = Store::default();
+        for i in 0..PARALLEL_SCAN_THRESHOLD * 4 {
+            store
+                .insert(&reg_key_segs(&format!("devices/{i}/state")), json!(i))
+                .unwrap();
+            store
+                .insert(&reg_key_segs(&format!("devices/{i}/name")), json!("dev"))
+                .unwrap();
+        }
+        store
+            .insert(&reg_key_segs("devices"), json!("root"))
+            .unwrap();
+
+        let res = store.get_matches(&key_segs("devices/?/state")).unwrap();
+        assert_eq!(res.len(), PARALLEL_SCAN_THRESHOLD * 4);
+        assert!(res.contains(&("devices/7/state", json!(7)).into()));
+
+        let res = store.get_matches(&key_segs("devices/#")).unwrap();
+        assert_eq!(res.len(), PARALLEL_SCAN_THRESHOLD * 8 + 1);
+        assert!(res.contains(&("devices", json!("root")).into()));
+
+        let res = store.get_matches(&key_segs("nothing/?")).unwrap();
+        assert!(res.is_empty());
+
+        assert!(store.get_matches(&key_segs("devices/#/state")).is_err());
+    }
+
     #[test]
     fn test_multi_wildcard() {
         let path0 = reg_key_segs("trolo/a");
@@ -72,6 +72,10 @@ impl Subscriber {
     pub fn is_unique(&self) -> bool {
         self.unique
     }
+
+    pub fn id(&self) -> &SubscriptionId {
+        &self.id
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -156,6 +160,20 @@ impl Subscribers {
         removed
     }
 
+    pub fn find_subscriber(
+        &self,
+        pattern: &[KeySegment],
+        subscription: &SubscriptionId,
+    ) -> Option<&Subscriber> {
+        let mut current = &self.data;
+
+        for elem in pattern {
+            current = current.tree.get(elem)?;
+        }
+
+        current.subscribers.iter().find(|s| &s.id == subscription)
+    }
+
     pub fn remove_subscriber(&mut self, subscriber: Subscriber) {
         let mut current = &mut self.data;
 
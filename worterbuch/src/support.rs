@@ -0,0 +1,115 @@
+/*
+ *  Worterbuch support bundle module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{
+    encryption::{visit_values, Encryption},
+    logging,
+    server::common::CloneableWbApi,
+    stats::VERSION,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+use worterbuch_common::{
+    error::{Context, WorterbuchResult},
+    redact::REDACTED,
+    topic, KeyValuePairs, SYSTEM_TOPIC_ROOT,
+};
+
+/// Identifies support bundles. It is authenticated along with the bundle's content.
+pub const BUNDLE_FORMAT: &str = "worterbuch-support-bundle/1";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SupportBundle {
+    pub version: String,
+    pub created: u64,
+    pub config: String,
+    pub stats: KeyValuePairs,
+    pub logs: Vec<String>,
+    pub snapshot: Value,
+}
+
+/// Collects a snapshot of the store, the most recent log messages, the configuration and the
+/// server's `$SYS` stats and encrypts them with the support bundle key, which also proves that the
+/// bundle has not been tampered with. Values of sensitive keys and secrets from the configuration
+/// are redacted before anything is put into the bundle.
+pub async fn create(wb: &CloneableWbApi, key: &Encryption) -> WorterbuchResult<Value> {
+    let config = wb.config().await?;
+
+    let mut snapshot = wb.export().await?;
+    visit_values(&mut snapshot, &mut |path, value| {
+        if config.is_sensitive(path) {
+            *value = json!(REDACTED);
+        }
+        Ok(())
+    })?;
+
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    let bundle = SupportBundle {
+        version: VERSION.to_owned(),
+        created,
+        config: format!("{:#?}", config.redacted()),
+        stats: wb.pget(topic!(SYSTEM_TOPIC_ROOT, "#")).await?,
+        logs: logging::recent_logs(),
+        snapshot,
+    };
+    let data =
+        serde_json::to_vec(&bundle).context(|| "could not encode support bundle".to_owned())?;
+
+    Ok(json!({
+        "format": BUNDLE_FORMAT,
+        "created": created,
+        "bundle": key.seal_bytes(BUNDLE_FORMAT, data)?,
+    }))
+}
+
+/// Decrypts a bundle produced by [`create`].
+pub fn open(sealed: &Value, key: &Encryption) -> WorterbuchResult<SupportBundle> {
+    let encoded = sealed["bundle"].as_str().unwrap_or_default();
+    let data = key.open_bytes(BUNDLE_FORMAT, encoded)?;
+    serde_json::from_slice(&data).context(|| "could not decode support bundle".to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bundles_can_only_be_opened_with_the_right_key() {
+        let key = Encryption::new([3; 32], Vec::new());
+        let bundle = SupportBundle {
+            version: VERSION.to_owned(),
+            created: 0,
+            config: "Config".to_owned(),
+            stats: Vec::new(),
+            logs: vec!["0 INFO worterbuch: hello".to_owned()],
+            snapshot: json!({}),
+        };
+        let data = serde_json::to_vec(&bundle).unwrap();
+        let sealed = json!({ "bundle": key.seal_bytes(BUNDLE_FORMAT, data).unwrap() });
+
+        assert_eq!(open(&sealed, &key).unwrap(), bundle);
+        assert!(open(&sealed, &Encryption::new([4; 32], Vec::new())).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+/*
+ *  Worterbuch value templates module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::auth::pattern_matches;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use worterbuch_common::{
+    error::{ConfigError, ConfigResult},
+    KeySegment, RegularKeySegment, RequestPattern,
+};
+
+/// A default value for all keys matching a pattern.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValueTemplate {
+    pub pattern: RequestPattern,
+    pub value: Value,
+}
+
+/// An ordered list of value templates. If more than one template matches a key, the first one wins.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ValueTemplates(Vec<ValueTemplate>);
+
+impl ValueTemplates {
+    pub fn new(templates: Vec<ValueTemplate>) -> Self {
+        Self(templates)
+    }
+
+    pub fn load(path: &str) -> ConfigResult<Self> {
+        let json = fs::read_to_string(path).map_err(|e| {
+            ConfigError::InvalidValueTemplates(format!("could not read {path}: {e}"))
+        })?;
+        serde_json::from_str(&json)
+            .map_err(|e| ConfigError::InvalidValueTemplates(format!("could not parse {path}: {e}")))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn value_for(&self, key: &str) -> Option<&Value> {
+        self.0
+            .iter()
+            .find(|t| pattern_matches(&t.pattern, key))
+            .map(|t| &t.value)
+    }
+
+    /// Returns the literal child segments that templates define directly below the given parent.
+    pub fn children(&self, parent: &[&str]) -> Vec<RegularKeySegment> {
+        let mut children = Vec::new();
+
+        for template in &self.0 {
+            let pattern = KeySegment::parse(&template.pattern);
+            if pattern.len() <= parent.len() {
+                continue;
+            }
+            let parent_matches = pattern.iter().zip(parent).all(|(p, k)| match p {
+                KeySegment::Wildcard => true,
+                KeySegment::MultiWildcard => false,
+                KeySegment::Regular(p) => p == k,
+            });
+            if !parent_matches {
+                continue;
+            }
+            if let KeySegment::Regular(child) = &pattern[parent.len()] {
+                if !children.contains(child) {
+                    children.push(child.to_owned());
+                }
+            }
+        }
+
+        children
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn templates() -> ValueTemplates {
+        serde_json::from_value(json!([
+            { "pattern": "devices/?/config/brightness", "value": 100 },
+            { "pattern": "devices/?/config/#", "value": null },
+            { "pattern": "devices/?/name", "value": "unnamed" }
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn first_matching_template_wins() {
+        let templates = templates();
+        assert_eq!(
+            templates.value_for("devices/dev1/config/brightness"),
+            Some(&json!(100))
+        );
+        assert_eq!(
+            templates.value_for("devices/dev1/config/contrast"),
+            Some(&Value::Null)
+        );
+        assert_eq!(templates.value_for("devices/dev1/status"), None);
+    }
+
+    #[test]
+    fn children_are_listed_for_matching_parents() {
+        let templates = templates();
+        assert_eq!(
+            templates.children(&["devices", "dev1"]),
+            vec!["config".to_owned(), "name".to_owned()]
+        );
+        assert_eq!(
+            templates.children(&["devices", "dev1", "config"]),
+            vec!["brightness".to_owned()]
+        );
+        assert!(templates.children(&["devices"]).is_empty());
+        assert!(templates.children(&["sensors", "s1"]).is_empty());
+    }
+}
@@ -0,0 +1,241 @@
+/*
+ *  Worterbuch test utilities module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Utilities for integration tests against an in-process worterbuch server.
+//!
+//! ```no_run
+//! # async fn test() -> anyhow::Result<()> {
+//! use worterbuch::test_util::TestServer;
+//!
+//! let server = TestServer::start().await?;
+//! let client = server.connect().await?;
+//! server
+//!     .seed(&client, vec![("hello/world", serde_json::json!("test")).into()])
+//!     .await?;
+//! server.stop().await;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{run_worterbuch_with_config, Config, Endpoint, WsEndpoint};
+use anyhow::{anyhow, Result};
+use std::{
+    net::{Ipv4Addr, TcpListener},
+    path::PathBuf,
+    time::Duration,
+};
+use tokio::{
+    select, spawn,
+    sync::oneshot,
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+use tokio_graceful_shutdown::{SubsystemHandle, Toplevel};
+use uuid::Uuid;
+use worterbuch_client::{config::Config as ClientConfig, connect, Worterbuch};
+use worterbuch_common::{Key, KeyValuePairs, Value};
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A worterbuch server running in the current process on random local ports, using a temporary
+/// data directory that is removed when the server is stopped or dropped.
+pub struct TestServer {
+    tcp_port: u16,
+    ws_port: u16,
+    data_dir: PathBuf,
+    stop: Option<oneshot::Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    pub async fn start() -> Result<TestServer> {
+        let mut config = Config::new().await?;
+        TestServer::start_with_config(&mut config).await
+    }
+
+    /// Starts a server with the given config. Endpoints and data directory are overridden.
+    pub async fn start_with_config(config: &mut Config) -> Result<TestServer> {
+        let tcp_port = free_port()?;
+        let ws_port = free_port()?;
+        let data_dir = std::env::temp_dir().join(format!("worterbuch-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir)?;
+
+        config.tcp_endpoint = Some(Endpoint {
+            tls: false,
+            bind_addr: Ipv4Addr::LOCALHOST.into(),
+            port: tcp_port,
+        });
+        config.ws_endpoint = Some(WsEndpoint {
+            endpoint: Endpoint {
+                tls: false,
+                bind_addr: Ipv4Addr::LOCALHOST.into(),
+                port: ws_port,
+            },
+            public_addr: "localhost".to_owned(),
+            certificates: Vec::new(),
+            path: "/ws".to_owned(),
+            subprotocols: vec!["worterbuch".to_owned()],
+        });
+        config.use_persistence = true;
+        config.data_dir = data_dir.to_string_lossy().to_string();
+
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let config = config.clone();
+
+        let handle = spawn(async move {
+            let res = Toplevel::new()
+                .start("worterbuch", move |subsys| {
+                    run_worterbuch_with_config(subsys, config)
+                })
+                .start("test-control", move |subsys: SubsystemHandle| async move {
+                    select! {
+                        _ = stop_rx => subsys.request_shutdown(),
+                        _ = subsys.on_shutdown_requested() => (),
+                    }
+                    Ok::<(), anyhow::Error>(())
+                })
+                .handle_shutdown_requests(Duration::from_millis(1000))
+                .await;
+            if let Err(e) = res {
+                log::error!("Test server terminated with error: {e}");
+            }
+        });
+
+        let server = TestServer {
+            tcp_port,
+            ws_port,
+            data_dir,
+            stop: Some(stop_tx),
+            handle: Some(handle),
+        };
+
+        server.await_ready().await?;
+
+        Ok(server)
+    }
+
+    pub fn tcp_port(&self) -> u16 {
+        self.tcp_port
+    }
+
+    pub fn ws_port(&self) -> u16 {
+        self.ws_port
+    }
+
+    pub fn data_dir(&self) -> &PathBuf {
+        &self.data_dir
+    }
+
+    /// Returns a client config pointing to this server's TCP endpoint.
+    pub fn client_config(&self) -> ClientConfig {
+        ClientConfig {
+            proto: "tcp".to_owned(),
+            host_addr: "localhost".to_owned(),
+            port: self.tcp_port,
+            ..Default::default()
+        }
+    }
+
+    pub async fn connect(&self) -> Result<Worterbuch> {
+        Ok(connect(self.client_config(), async {}).await?)
+    }
+
+    /// Sets all given key/value pairs and waits until they can be read back.
+    pub async fn seed(&self, client: &Worterbuch, kvps: KeyValuePairs) -> Result<()> {
+        for kvp in &kvps {
+            client
+                .set_generic(kvp.key.clone(), kvp.value.clone())
+                .await?;
+        }
+        for kvp in kvps {
+            await_value(client, kvp.key, &kvp.value, STARTUP_TIMEOUT).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn stop(mut self) {
+        self.shutdown();
+        if let Some(handle) = self.handle.take() {
+            handle.await.ok();
+        }
+    }
+
+    async fn await_ready(&self) -> Result<()> {
+        let connect = async {
+            loop {
+                match connect(self.client_config(), async {}).await {
+                    Ok(client) => {
+                        client.close().await.ok();
+                        break;
+                    }
+                    Err(_) => sleep(Duration::from_millis(10)).await,
+                }
+            }
+        };
+        timeout(STARTUP_TIMEOUT, connect)
+            .await
+            .map_err(|_| anyhow!("test server did not start within {STARTUP_TIMEOUT:?}"))
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            stop.send(()).ok();
+        }
+        if let Err(e) = std::fs::remove_dir_all(&self.data_dir) {
+            log::warn!("Could not remove test data dir: {e}");
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Waits until the value of `key` equals `expected`, e.g. to make sure a change made by another
+/// client has been propagated.
+pub async fn await_value(
+    client: &Worterbuch,
+    key: Key,
+    expected: &Value,
+    wait: Duration,
+) -> Result<()> {
+    let (mut rx, tid) = client.subscribe_generic(key.clone(), false, false).await?;
+    let res = timeout(wait, async {
+        while let Some((value, _)) = rx.recv().await {
+            if value.as_ref() == Some(expected) {
+                return true;
+            }
+        }
+        false
+    })
+    .await;
+    client.unsubscribe(tid).await?;
+    match res {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(anyhow!("subscription to {key} ended unexpectedly")),
+        Err(_) => Err(anyhow!("{key} did not become {expected} within {wait:?}")),
+    }
+}
+
+fn free_port() -> Result<u16> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    Ok(listener.local_addr()?.port())
+}
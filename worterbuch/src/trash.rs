@@ -0,0 +1,86 @@
+/*
+ *  Worterbuch trash module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::server::common::CloneableWbApi;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{select, time::interval};
+use tokio_graceful_shutdown::SubsystemHandle;
+
+/// A soft-deleted value, stored under `$trash/<original key>` until the retention period expires.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashEntry {
+    pub value: Value,
+    /// Seconds since the UNIX epoch.
+    pub deleted_at: u64,
+}
+
+impl TrashEntry {
+    pub fn new(value: Value) -> Self {
+        TrashEntry {
+            value,
+            deleted_at: now(),
+        }
+    }
+
+    pub fn is_expired(&self, retention: Duration) -> bool {
+        now().saturating_sub(self.deleted_at) >= retention.as_secs()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub(crate) async fn purge_periodically(
+    worterbuch: CloneableWbApi,
+    retention: Duration,
+    subsys: SubsystemHandle,
+) -> Result<()> {
+    let mut interval = interval(retention.min(Duration::from_secs(60)));
+
+    loop {
+        select! {
+            _ = interval.tick() => worterbuch.purge_trash().await?,
+            _ = subsys.on_shutdown_requested() => break,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn trash_entries_expire_after_retention_period() {
+        let mut entry = TrashEntry::new(json!(1));
+        assert!(!entry.is_expired(Duration::from_secs(60)));
+        entry.deleted_at -= 61;
+        assert!(entry.is_expired(Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,246 @@
+/*
+ *  Worterbuch key usage analytics module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use serde::Serialize;
+use std::{collections::HashMap, time::Instant};
+
+/// Maximum number of distinct buckets that are tracked. Accesses to keys that would open a new
+/// bucket beyond this are counted in [`OVERFLOW_BUCKET`] instead.
+const MAX_BUCKETS: usize = 10_000;
+pub const OVERFLOW_BUCKET: &str = "<other>";
+
+/// Configures sampled tracking of key usage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyUsageSampling {
+    /// only every n-th access is recorded
+    pub sample_rate: u32,
+    /// number of leading key segments that make up a bucket
+    pub bucket_depth: usize,
+}
+
+impl Default for KeyUsageSampling {
+    fn default() -> Self {
+        Self {
+            sample_rate: 100,
+            bucket_depth: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Subscribe,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct UsageCounts {
+    pub reads: u64,
+    pub writes: u64,
+    pub subscribes: u64,
+}
+
+impl UsageCounts {
+    pub fn total(&self) -> u64 {
+        self.reads + self.writes + self.subscribes
+    }
+
+    fn scaled(&self, factor: u64) -> Self {
+        Self {
+            reads: self.reads * factor,
+            writes: self.writes * factor,
+            subscribes: self.subscribes * factor,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BucketUsage {
+    pub bucket: String,
+    #[serde(flatten)]
+    pub counts: UsageCounts,
+}
+
+/// Estimated access counts per key bucket. All counts are extrapolated from the sampled accesses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyUsageReport {
+    pub sample_rate: u32,
+    pub bucket_depth: usize,
+    pub tracked_secs: u64,
+    /// the most frequently accessed buckets
+    pub hot: Vec<BucketUsage>,
+    /// buckets that are written but never read or subscribed to, most written first
+    pub dead: Vec<BucketUsage>,
+}
+
+/// Counts sampled reads, writes and subscriptions per bucket of keys, where a bucket is made up of
+/// the first few segments of a key. Only every n-th access is looked at, so the overhead stays
+/// bounded regardless of the load.
+#[derive(Debug)]
+pub struct KeyUsage {
+    sampling: KeyUsageSampling,
+    accesses: u64,
+    buckets: HashMap<String, UsageCounts>,
+    since: Instant,
+}
+
+impl KeyUsage {
+    pub fn new(sampling: KeyUsageSampling) -> Self {
+        Self {
+            sampling: KeyUsageSampling {
+                sample_rate: sampling.sample_rate.max(1),
+                bucket_depth: sampling.bucket_depth.max(1),
+            },
+            accesses: 0,
+            buckets: HashMap::new(),
+            since: Instant::now(),
+        }
+    }
+
+    pub fn record(&mut self, key: &str, access: Access) {
+        self.accesses = self.accesses.wrapping_add(1);
+        if !self
+            .accesses
+            .is_multiple_of(self.sampling.sample_rate as u64)
+        {
+            return;
+        }
+
+        let bucket = bucket_of(key, self.sampling.bucket_depth);
+        let bucket = if self.buckets.len() >= MAX_BUCKETS && !self.buckets.contains_key(bucket) {
+            OVERFLOW_BUCKET
+        } else {
+            bucket
+        };
+
+        let counts = match self.buckets.get_mut(bucket) {
+            Some(counts) => counts,
+            None => self.buckets.entry(bucket.to_owned()).or_default(),
+        };
+        match access {
+            Access::Read => counts.reads += 1,
+            Access::Write => counts.writes += 1,
+            Access::Subscribe => counts.subscribes += 1,
+        }
+    }
+
+    pub fn report(&self, top: usize) -> KeyUsageReport {
+        let factor = self.sampling.sample_rate as u64;
+        let usage = |(bucket, counts): (&String, &UsageCounts)| BucketUsage {
+            bucket: bucket.to_owned(),
+            counts: counts.scaled(factor),
+        };
+
+        let mut hot: Vec<BucketUsage> = self.buckets.iter().map(usage).collect();
+        hot.sort_by(|a, b| {
+            b.counts
+                .total()
+                .cmp(&a.counts.total())
+                .then_with(|| a.bucket.cmp(&b.bucket))
+        });
+        hot.truncate(top);
+
+        let mut dead: Vec<BucketUsage> = self
+            .buckets
+            .iter()
+            .filter(|(_, c)| c.reads == 0 && c.subscribes == 0)
+            .map(usage)
+            .collect();
+        dead.sort_by(|a, b| {
+            b.counts
+                .writes
+                .cmp(&a.counts.writes)
+                .then_with(|| a.bucket.cmp(&b.bucket))
+        });
+        dead.truncate(top);
+
+        KeyUsageReport {
+            sample_rate: self.sampling.sample_rate,
+            bucket_depth: self.sampling.bucket_depth,
+            tracked_secs: self.since.elapsed().as_secs(),
+            hot,
+            dead,
+        }
+    }
+}
+
+fn bucket_of(key: &str, depth: usize) -> &str {
+    match key.match_indices('/').nth(depth - 1) {
+        Some((i, _)) => &key[..i],
+        None => key,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn usage_is_sampled_and_bucketed() {
+        let mut usage = KeyUsage::new(KeyUsageSampling {
+            sample_rate: 2,
+            bucket_depth: 2,
+        });
+
+        for _ in 0..10 {
+            usage.record("plant/line1/temperature", Access::Read);
+        }
+        for _ in 0..10 {
+            usage.record("plant/line1/pressure", Access::Write);
+        }
+        for _ in 0..4 {
+            usage.record("archive/2023/01", Access::Write);
+        }
+        usage.record("plant/#", Access::Subscribe);
+        usage.record("plant/#", Access::Subscribe);
+
+        let report = usage.report(10);
+        assert_eq!(report.sample_rate, 2);
+        assert_eq!(report.hot[0].bucket, "plant/line1");
+        assert_eq!(report.hot[0].counts.total(), 20);
+        assert_eq!(
+            report.dead,
+            vec![BucketUsage {
+                bucket: "archive/2023".to_owned(),
+                counts: UsageCounts {
+                    reads: 0,
+                    writes: 4,
+                    subscribes: 0
+                }
+            }]
+        );
+        assert!(report.hot.iter().any(|b| b.bucket == "plant/#"));
+        assert_eq!(usage.report(1).hot.len(), 1);
+    }
+
+    #[test]
+    fn buckets_are_bounded() {
+        let mut usage = KeyUsage::new(KeyUsageSampling {
+            sample_rate: 1,
+            bucket_depth: 1,
+        });
+        for i in 0..MAX_BUCKETS + 10 {
+            usage.record(&format!("key{i}/value"), Access::Write);
+        }
+        assert_eq!(usage.buckets.len(), MAX_BUCKETS + 1);
+        assert_eq!(usage.buckets[OVERFLOW_BUCKET].writes, 10);
+    }
+}
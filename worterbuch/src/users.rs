@@ -0,0 +1,266 @@
+/*
+ *  Worterbuch user database module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{auth::JwtClaims, server::common::CloneableWbApi, Worterbuch, INTERNAL_CLIENT_ID};
+use argon2::{
+    password_hash::{self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::OnceLock};
+use tokio::task::spawn_blocking;
+use worterbuch_common::{
+    error::{AuthorizationError, AuthorizationResult},
+    topic, Privilege, RequestPattern, AUTH_TOPIC_ROLES, AUTH_TOPIC_ROOT, AUTH_TOPIC_USERS,
+};
+
+pub const ADMIN: &str = "admin";
+
+/// A user of the built-in user database, stored under `$auth/users/<username>`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    /// argon2 hash of the user's password in PHC string format
+    pub password_hash: String,
+    /// names of the roles assigned to the user
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+/// The privileges granted by a role, stored under `$auth/roles/<role>`. Uses the same format as
+/// the privileges claim of a JWT.
+pub type Role = HashMap<Privilege, Vec<RequestPattern>>;
+
+pub fn hash_password(password: &str) -> Result<String, password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(e) => {
+            log::warn!("Invalid password hash in user database: {e}");
+            false
+        }
+    }
+}
+
+/// Hash that passwords of unknown users are checked against, so that looking up a user that does
+/// not exist takes as long as a wrong password.
+fn dummy_hash() -> String {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH
+        .get_or_init(|| {
+            hash_password(SaltString::generate(&mut OsRng).as_str()).unwrap_or_default()
+        })
+        .clone()
+}
+
+/// Checks a user's credentials and collects the privileges of all their roles.
+pub async fn authenticate(
+    worterbuch: &CloneableWbApi,
+    username: &str,
+    password: String,
+) -> AuthorizationResult<JwtClaims> {
+    if username.is_empty() || username.contains(['/', '?', '#']) {
+        return Err(AuthorizationError::InvalidCredentials);
+    }
+
+    let user: Option<User> = match worterbuch
+        .get(topic!(AUTH_TOPIC_ROOT, AUTH_TOPIC_USERS, username))
+        .await
+    {
+        Ok((_, value)) => Some(serde_json::from_value(value).map_err(|e| {
+            log::warn!("Invalid entry for user '{username}' in user database: {e}");
+            AuthorizationError::InvalidCredentials
+        })?),
+        Err(_) => None,
+    };
+
+    // argon2 is slow by design, don't block the runtime while it's working
+    let hash = match &user {
+        Some(user) => user.password_hash.clone(),
+        None => spawn_blocking(dummy_hash).await.unwrap_or_default(),
+    };
+    let valid = spawn_blocking(move || verify_password(&password, &hash))
+        .await
+        .unwrap_or(false);
+    let Some(user) = user.filter(|_| valid) else {
+        return Err(AuthorizationError::InvalidCredentials);
+    };
+
+    let mut privileges = Role::new();
+    for role_name in &user.roles {
+        match worterbuch
+            .get(topic!(AUTH_TOPIC_ROOT, AUTH_TOPIC_ROLES, role_name))
+            .await
+        {
+            Ok((_, value)) => match serde_json::from_value::<Role>(value) {
+                Ok(role) => {
+                    for (privilege, patterns) in role {
+                        privileges.entry(privilege).or_default().extend(patterns);
+                    }
+                }
+                Err(e) => log::warn!("Role '{role_name}' of user '{username}' is invalid: {e}"),
+            },
+            Err(_) => log::warn!("User '{username}' has unknown role '{role_name}'"),
+        }
+    }
+
+    Ok(JwtClaims {
+        sub: username.to_owned(),
+        name: username.to_owned(),
+        exp: 0,
+        worterbuch_privileges: privileges,
+        roles: user.roles,
+    })
+}
+
+/// Whether a pattern can match keys of the user database.
+pub fn is_protected(pattern: &str) -> bool {
+    let mut segments = pattern.split('/');
+    match segments.next() {
+        Some(AUTH_TOPIC_ROOT) | Some("#") => true,
+        Some("?") => segments.next().is_some(),
+        _ => false,
+    }
+}
+
+/// Whether a key belongs to the user database.
+pub fn is_user_db_key(key: &str) -> bool {
+    key.split('/').next() == Some(AUTH_TOPIC_ROOT)
+}
+
+fn grants_user_db(claims: Option<&JwtClaims>, privilege: &Privilege) -> bool {
+    claims
+        .and_then(|c| c.worterbuch_privileges.get(privilege))
+        .map(|patterns| patterns.iter().any(|p| is_user_db_key(p)))
+        .unwrap_or(false)
+}
+
+/// Whether the user database has to be left out of the results of a client's wildcard reads.
+pub fn hides_user_db(claims: Option<&JwtClaims>) -> bool {
+    !grants_user_db(claims, &Privilege::Read)
+}
+
+/// Wildcard grants like `#` do not give access to the user database, clients need a privilege
+/// that is explicitly granted on `$auth/...`. Reads with a wildcard first segment are allowed,
+/// the user database is left out of their results instead.
+pub fn check_protected(
+    claims: Option<&JwtClaims>,
+    privilege: &Privilege,
+    pattern: &str,
+) -> AuthorizationResult<()> {
+    if !is_protected(pattern) || (privilege == &Privilege::Read && !is_user_db_key(pattern)) {
+        return Ok(());
+    }
+
+    if grants_user_db(claims, privilege) {
+        Ok(())
+    } else {
+        Err(AuthorizationError::InsufficientPrivileges(
+            privilege.to_owned(),
+            pattern.to_owned(),
+        ))
+    }
+}
+
+/// Creates an admin user with full access, unless the user database already contains one.
+pub async fn bootstrap_admin(worterbuch: &mut Worterbuch, password: &str) -> anyhow::Result<()> {
+    let user_key = topic!(AUTH_TOPIC_ROOT, AUTH_TOPIC_USERS, ADMIN);
+    if worterbuch.get(&user_key).is_ok() {
+        return Ok(());
+    }
+
+    let all = vec!["#".to_owned(), topic!(AUTH_TOPIC_ROOT, "#")];
+    let role: Role = [Privilege::Read, Privilege::Write, Privilege::Delete]
+        .into_iter()
+        .map(|privilege| (privilege, all.clone()))
+        .collect();
+    worterbuch
+        .set(
+            topic!(AUTH_TOPIC_ROOT, AUTH_TOPIC_ROLES, ADMIN),
+            serde_json::to_value(role)?,
+            INTERNAL_CLIENT_ID,
+        )
+        .await?;
+
+    let user = User {
+        password_hash: hash_password(password)?,
+        roles: vec![ADMIN.to_owned()],
+    };
+    worterbuch
+        .set(user_key, serde_json::to_value(user)?, INTERNAL_CLIENT_ID)
+        .await?;
+
+    log::info!("Created user '{ADMIN}' in user database.");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn password_hashes_can_be_verified() {
+        let hash = hash_password("secret").expect("hashing failed");
+        assert!(verify_password("secret", &hash));
+        assert!(!verify_password("not secret", &hash));
+        assert!(!verify_password("secret", "not a hash"));
+    }
+
+    #[test]
+    fn user_database_is_protected_from_wildcard_grants() {
+        let claims = |patterns: Vec<&str>| JwtClaims {
+            sub: "test".to_owned(),
+            name: "test".to_owned(),
+            exp: 0,
+            worterbuch_privileges: [(
+                Privilege::Read,
+                patterns.into_iter().map(ToOwned::to_owned).collect(),
+            )]
+            .into(),
+            roles: Vec::new(),
+        };
+
+        assert!(is_protected("$auth/users/admin"));
+        assert!(is_protected("#"));
+        assert!(is_protected("?/users/#"));
+        assert!(!is_protected("?"));
+        assert!(!is_protected("hello/#"));
+
+        let user = claims(vec!["#"]);
+        let admin = claims(vec!["#", "$auth/#"]);
+        assert!(check_protected(Some(&user), &Privilege::Read, "hello/world").is_ok());
+        assert!(check_protected(Some(&user), &Privilege::Read, "$auth/users/admin").is_err());
+        assert!(check_protected(Some(&user), &Privilege::Read, "#").is_ok());
+        assert!(check_protected(Some(&user), &Privilege::Delete, "#").is_err());
+        assert!(hides_user_db(Some(&user)));
+        assert!(!hides_user_db(Some(&admin)));
+        assert!(check_protected(Some(&admin), &Privilege::Read, "$auth/users/admin").is_ok());
+        assert!(check_protected(Some(&admin), &Privilege::Write, "$auth/users/admin").is_err());
+        assert!(check_protected(None, &Privilege::Read, "$auth/#").is_err());
+    }
+}
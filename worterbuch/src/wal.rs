@@ -0,0 +1,304 @@
+/*
+ *  Worterbuch write-ahead log module
+ *
+ *  Copyright (C) 2024 Michael Bachmann
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Affero General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Affero General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Affero General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{config::Config, encryption::Encryption, worterbuch::Worterbuch, INTERNAL_CLIENT_ID};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+use worterbuch_common::{
+    error::{ConfigError, Context, WorterbuchError, WorterbuchResult},
+    Key,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PersistenceMode {
+    /// The whole store is written to disk every persistence interval.
+    #[default]
+    Snapshot,
+    /// Every change is appended to a write-ahead log as it happens. The log is compacted into a
+    /// snapshot once it exceeds the configured size.
+    Wal,
+    /// Periodic snapshots, each of which compacts the write-ahead log of the changes since.
+    Both,
+}
+
+impl PersistenceMode {
+    pub fn periodic_snapshots(self) -> bool {
+        matches!(self, PersistenceMode::Snapshot | PersistenceMode::Both)
+    }
+
+    pub fn wal(self) -> bool {
+        matches!(self, PersistenceMode::Wal | PersistenceMode::Both)
+    }
+}
+
+impl FromStr for PersistenceMode {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().trim() {
+            "snapshot" => Ok(PersistenceMode::Snapshot),
+            "wal" => Ok(PersistenceMode::Wal),
+            "both" => Ok(PersistenceMode::Both),
+            _ => Err(ConfigError::InvalidPersistenceMode(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum Entry {
+    Set {
+        key: Key,
+        value: Value,
+    },
+    /// The value is encrypted with the key as associated data, see [`Encryption::seal_value`].
+    SetEncrypted {
+        key: Key,
+        value: String,
+    },
+    Delete {
+        key: Key,
+    },
+}
+
+/// Append-only log of the changes made to the store since the last snapshot, one JSON entry per
+/// line. Entries are handed to the operating system as they are written, so they survive a crash
+/// of the server but not necessarily a power loss.
+///
+/// Compacting the log moves it aside before the snapshot is taken and removes it once the
+/// snapshot has been written. Replaying the moved log on top of a snapshot that already contains
+/// its changes is harmless, since every entry sets or deletes a key as a whole.
+pub struct Wal {
+    file: File,
+    path: PathBuf,
+    rotated_path: PathBuf,
+    size: u64,
+}
+
+impl Wal {
+    pub(crate) fn open(config: &Config) -> io::Result<Self> {
+        let (path, rotated_path) = file_paths(config);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Wal {
+            file,
+            path,
+            rotated_path,
+            size,
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub(crate) fn append(
+        &mut self,
+        key: &str,
+        value: Option<&Value>,
+        encryption: Option<&Encryption>,
+    ) -> WorterbuchResult<()> {
+        let key = key.to_owned();
+        let sealed = match (value, encryption) {
+            (Some(value), Some(encryption)) => encryption.seal_value(&key, value)?,
+            _ => None,
+        };
+        let entry = match (value, sealed) {
+            (Some(_), Some(value)) => Entry::SetEncrypted { key, value },
+            (Some(value), None) => Entry::Set {
+                key,
+                value: value.to_owned(),
+            },
+            (None, _) => Entry::Delete { key },
+        };
+        let mut line =
+            serde_json::to_vec(&entry).context(|| "could not encode WAL entry".to_owned())?;
+        line.push(b'\n');
+        self.file
+            .write_all(&line)
+            .context(|| format!("could not write to {:?}", self.path))?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Makes sure everything written so far has reached the disk.
+    pub(crate) fn sync(&mut self) -> WorterbuchResult<()> {
+        self.file
+            .flush()
+            .context(|| format!("could not flush {:?}", self.path))?;
+        self.file
+            .sync_data()
+            .context(|| format!("could not sync {:?}", self.path))
+    }
+
+    /// Moves the current log aside and starts a new one. If a previously moved log is still
+    /// around because its snapshot failed, the current log is appended to it instead.
+    pub(crate) fn rotate(&mut self) -> WorterbuchResult<()> {
+        self.file
+            .flush()
+            .context(|| format!("could not flush {:?}", self.path))?;
+        if self.rotated_path.exists() {
+            let mut rotated = OpenOptions::new()
+                .append(true)
+                .open(&self.rotated_path)
+                .context(|| format!("could not open {:?}", self.rotated_path))?;
+            let mut current =
+                File::open(&self.path).context(|| format!("could not open {:?}", self.path))?;
+            io::copy(&mut current, &mut rotated)
+                .context(|| format!("could not append to {:?}", self.rotated_path))?;
+        } else {
+            fs::rename(&self.path, &self.rotated_path)
+                .context(|| format!("could not move {:?}", self.path))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)
+            .context(|| format!("could not open {:?}", self.path))?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Replays the moved aside and the current log on top of the restored snapshot and attaches a
+/// log for all further changes.
+pub(crate) async fn recover(worterbuch: &mut Worterbuch, config: &Config) -> Result<()> {
+    let (path, rotated_path) = file_paths(config);
+    for path in [rotated_path, path] {
+        if !path.exists() {
+            continue;
+        }
+        log::info!("Replaying write-ahead log {path:?} …");
+        let replayed = replay(worterbuch, config, &path).await?;
+        log::info!("Replayed {replayed} entries.");
+    }
+    worterbuch.attach_wal(Wal::open(config)?);
+    Ok(())
+}
+
+async fn replay(worterbuch: &mut Worterbuch, config: &Config, path: &PathBuf) -> Result<usize> {
+    let file = File::open(path)?;
+    let mut replayed = 0;
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // the last entry may be incomplete if the server crashed while writing it
+        let entry = match serde_json::from_str(&line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::warn!("Skipping invalid entry in line {} of {path:?}: {e}", i + 1);
+                continue;
+            }
+        };
+        match entry {
+            Entry::Set { key, value } => {
+                worterbuch.set(key, value, INTERNAL_CLIENT_ID).await?;
+            }
+            Entry::SetEncrypted { key, value } => {
+                let Some(encryption) = &config.encryption else {
+                    return Err(ConfigError::InvalidEncryptionKey(format!(
+                        "{path:?} contains encrypted values but no encryption key is configured"
+                    ))
+                    .into());
+                };
+                let value = encryption.open_value(&key, &value)?;
+                worterbuch.set(key, value, INTERNAL_CLIENT_ID).await?;
+            }
+            Entry::Delete { key } => match worterbuch.delete(key, INTERNAL_CLIENT_ID).await {
+                Ok(_) | Err(WorterbuchError::NoSuchValue(_)) => (),
+                Err(e) => return Err(e.into()),
+            },
+        }
+        replayed += 1;
+    }
+    Ok(replayed)
+}
+
+/// Removes the moved aside log once a snapshot containing its changes has been written.
+pub(crate) async fn remove_rotated(config: &Config) -> Result<()> {
+    let (_, rotated_path) = file_paths(config);
+    if rotated_path.exists() {
+        tokio::fs::remove_file(rotated_path).await?;
+    }
+    Ok(())
+}
+
+fn file_paths(config: &Config) -> (PathBuf, PathBuf) {
+    let dir = PathBuf::from(&config.data_dir);
+
+    let mut path = dir.clone();
+    path.push(".store.wal");
+    let mut rotated_path = dir.clone();
+    rotated_path.push(".store.wal~");
+
+    (path, rotated_path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn wal_restores_changes_since_last_snapshot() {
+        let mut config = Config::new().await.expect("invalid config");
+        let dir = std::env::temp_dir().join(format!("wb-wal-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).expect("could not create data dir");
+        config.data_dir = dir.to_string_lossy().to_string();
+
+        let mut wb = Worterbuch::with_config(config.clone());
+        recover(&mut wb, &config).await.expect("recovery failed");
+        wb.set("a/b".to_owned(), json!(1), "test")
+            .await
+            .expect("set failed");
+        wb.set("a/c".to_owned(), json!(2), "test")
+            .await
+            .expect("set failed");
+        wb.rotate_wal().expect("rotation failed");
+        wb.set("a/c".to_owned(), json!(3), "test")
+            .await
+            .expect("set failed");
+        wb.delete("a/b".to_owned(), "test")
+            .await
+            .expect("delete failed");
+        drop(wb);
+
+        let mut restored = Worterbuch::with_config(config.clone());
+        recover(&mut restored, &config)
+            .await
+            .expect("recovery failed");
+        fs::remove_dir_all(&dir).ok();
+
+        assert!(restored.get(&"a/b".to_owned()).is_err());
+        assert_eq!(
+            restored.get(&"a/c".to_owned()).expect("no value").1,
+            json!(3)
+        );
+    }
+}
@@ -18,36 +18,80 @@
  */
 
 use crate::{
+    auth::pattern_matches,
     config::Config,
-    store::{Store, StoreStats},
+    expiry::Expiries,
+    history::History,
+    lockout::{AuthAttempts, AuthLockout},
+    locks::{Locks, Waiter},
+    metrics::{self, Metrics},
+    mode::{mode_key, Mode},
+    normalize::normalize,
+    recorder::Recorder,
+    reservations::Reservations,
+    retention::{RetentionAction, StaleKey},
+    stats::{
+        StatsQueue, COMPUTED_KEYS, SYSTEM_KEY_COMPACTION_RECLAIMED, SYSTEM_KEY_LS_CACHE_ENTRIES,
+        SYSTEM_KEY_LS_CACHE_HITS, SYSTEM_KEY_LS_CACHE_HIT_RATE, SYSTEM_KEY_LS_CACHE_MISSES,
+        SYSTEM_KEY_UPTIME, SYSTEM_KEY_VALUE_COUNT,
+    },
+    store::{Store, StoreSnapshot, StoreStats},
     subscribers::{LsSubscriber, Subscriber, Subscribers, SubscriptionId},
+    trash::TrashEntry,
+    usage::{Access, KeyUsage, KeyUsageReport},
+    users::is_user_db_key,
+    wal::Wal,
     INTERNAL_CLIENT_ID,
 };
 use hashlink::LinkedHashMap;
 use serde::{Deserialize, Serialize};
-use serde_json::{from_str, json, to_value, Value};
-use std::{collections::HashMap, fmt::Display, net::SocketAddr, ops::Deref, time::Duration};
+use serde_json::{from_str, from_value, json, to_value, Number, Value};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    mem,
+    net::{IpAddr, SocketAddr},
+    ops::Deref,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::{
     fs::File,
     io::{AsyncReadExt, AsyncWriteExt},
     select, spawn,
-    sync::mpsc::{self, channel, Receiver},
+    sync::{
+        mpsc::{self, channel, Receiver},
+        oneshot,
+    },
     time::sleep,
 };
 use uuid::Uuid;
 use worterbuch_common::{
     error::{Context, WorterbuchError, WorterbuchResult},
-    parse_segments, topic, GraveGoods, Key, KeySegment, KeyValuePairs, LastWill, PState,
-    PStateEvent, Path, Protocol, ProtocolVersion, RegularKeySegment, RequestPattern, ServerMessage,
-    TransactionId, SYSTEM_TOPIC_CLIENTS, SYSTEM_TOPIC_CLIENTS_ADDRESS,
-    SYSTEM_TOPIC_CLIENTS_PROTOCOL, SYSTEM_TOPIC_GRAVE_GOODS, SYSTEM_TOPIC_LAST_WILL,
-    SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_ROOT_PREFIX, SYSTEM_TOPIC_SUBSCRIPTIONS,
+    format_path, parse_segments,
+    recording::RecordedEvent,
+    redact::REDACTED,
+    topic, CasConflict, ClientEvent, ClientInfo, GraveGoods, HistoryEntry, InvalidationHint, Key,
+    KeySegment, KeyValuePair, KeyValuePairs, LastWill, PState, PStateEvent, Path, Protocol,
+    ProtocolVersion, RegularKeySegment, RequestPattern, ServerEvent, ServerMessage, Sort,
+    SubscriberInfo, TransactionId, SYSTEM_TOPIC_ACL, SYSTEM_TOPIC_AUTH, SYSTEM_TOPIC_CLIENTS,
+    SYSTEM_TOPIC_CLIENTS_ADDRESS, SYSTEM_TOPIC_CLIENTS_INFO, SYSTEM_TOPIC_CLIENTS_PROTOCOL,
+    SYSTEM_TOPIC_DEPRECATED, SYSTEM_TOPIC_EXPIRING, SYSTEM_TOPIC_GRAVE_GOODS,
+    SYSTEM_TOPIC_LAST_WILL, SYSTEM_TOPIC_LOCKS, SYSTEM_TOPIC_MODE, SYSTEM_TOPIC_QUEUE,
+    SYSTEM_TOPIC_REPLICATION, SYSTEM_TOPIC_RETENTION, SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_ROOT_PREFIX,
+    SYSTEM_TOPIC_SUBSCRIPTIONS, TRASH_TOPIC_ROOT, TRASH_TOPIC_ROOT_PREFIX,
 };
 
+/// The protocol version this server speaks.
+pub const PROTOCOL_VERSION: &str = "0.7";
+
 pub type Subscriptions = HashMap<SubscriptionId, Vec<KeySegment>>;
 pub type LsSubscriptions = HashMap<SubscriptionId, Vec<RegularKeySegment>>;
 
 type Map<K, V> = LinkedHashMap<K, V>;
+type LsNotifications = Vec<(Vec<LsSubscriber>, Vec<String>)>;
 
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Stats {
@@ -69,6 +113,8 @@ struct PStateAggregatorState {
     deleted_buffer: Map<Key, Value>,
     client_sub: mpsc::Sender<ServerMessage>,
     send_is_scheduled: bool,
+    seq: u64,
+    deprecated: Option<bool>,
 }
 
 impl PStateAggregatorState {
@@ -159,10 +205,14 @@ impl PStateAggregatorState {
     }
 
     async fn send_aggregated_pstate(&mut self, event: PStateEvent) -> Result<(), WorterbuchError> {
+        self.seq += 1;
         let pstate = PState {
             transaction_id: self.transaction_id,
             request_pattern: self.request_pattern.clone(),
             event,
+            deprecated: self.deprecated,
+            more: None,
+            seq: Some(self.seq),
         };
         self.client_sub.send(ServerMessage::PState(pstate)).await?;
         Ok(())
@@ -198,6 +248,8 @@ impl PStateAggregator {
         aggregate_duration: Duration,
         transaction_id: TransactionId,
         channel_buffer_size: usize,
+        last_seq: u64,
+        deprecated: Option<bool>,
     ) -> Self {
         let aggregator_state = PStateAggregatorState {
             aggregate_duration,
@@ -207,6 +259,8 @@ impl PStateAggregator {
             deleted_buffer: Map::new(),
             send_is_scheduled: false,
             transaction_id,
+            seq: last_seq,
+            deprecated,
         };
 
         let (aggregate_tx, aggregate_rx) = mpsc::channel(channel_buffer_size);
@@ -224,13 +278,150 @@ impl PStateAggregator {
     }
 }
 
+/// A modification of an array value, see [`Worterbuch::update_array`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArrayOp {
+    Push(Value),
+    Pop,
+    /// Removes all elements equal to the value.
+    Remove(Value),
+}
+
+/// A point-in-time view of a worterbuch that can be read outside of the worterbuch's own task, so
+/// long running reads don't keep it from processing writes.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    store: StoreSnapshot,
+    computed: KeyValuePairs,
+}
+
+impl Snapshot {
+    pub fn pget(&self, pattern: &str) -> WorterbuchResult<KeyValuePairs> {
+        let path: Vec<KeySegment> = KeySegment::parse(pattern);
+        let mut matches = self
+            .store
+            .get_matches(&path)
+            .map_err(|e| e.for_pattern(pattern.to_owned()))?;
+
+        for kvp in &self.computed {
+            if pattern_matches(pattern, &kvp.key) {
+                matches.push(kvp.clone());
+            }
+        }
+
+        Ok(matches)
+    }
+
+    /// Like [`Snapshot::pget`], but regular segments of the pattern match keys regardless of their
+    /// case. Since the matching keys can't be looked up directly, this visits all siblings on each
+    /// level of the pattern.
+    pub fn pget_ignoring_case(&self, pattern: &str) -> WorterbuchResult<KeyValuePairs> {
+        let path: Vec<KeySegment> = KeySegment::parse(pattern);
+        let mut matches = Vec::new();
+        self.store
+            .visit_matches_ignoring_case(&path, &mut |kvp| matches.push(kvp))
+            .map_err(|e| e.for_pattern(pattern.to_owned()))?;
+
+        let pattern = pattern.to_lowercase();
+        for kvp in &self.computed {
+            if pattern_matches(&pattern, &kvp.key.to_lowercase()) {
+                matches.push(kvp.clone());
+            }
+        }
+
+        Ok(matches)
+    }
+
+    /// The keys matching the pattern, without their values.
+    pub fn pkeys(&self, pattern: &str) -> WorterbuchResult<Vec<Key>> {
+        let path: Vec<KeySegment> = KeySegment::parse(pattern);
+        let mut keys = Vec::new();
+        self.store
+            .visit_matching_keys(&path, &mut |key| keys.push(key))
+            .map_err(|e| e.for_pattern(pattern.to_owned()))?;
+
+        for kvp in &self.computed {
+            if pattern_matches(pattern, &kvp.key) {
+                keys.push(kvp.key.clone());
+            }
+        }
+
+        Ok(keys)
+    }
+
+    /// Same as [`Snapshot::pget`], but hands the matches to `tx` in chunks of at most `chunk_size`
+    /// key/value pairs while walking the store instead of collecting them all first. Blocks whenever
+    /// the receiver falls behind, so this must not be called from within an async context.
+    pub fn pget_chunked(
+        &self,
+        pattern: &str,
+        chunk_size: usize,
+        tx: &mpsc::Sender<WorterbuchResult<KeyValuePairs>>,
+    ) {
+        let path: Vec<KeySegment> = KeySegment::parse(pattern);
+        let chunk_size = chunk_size.max(1);
+        let mut chunk = Vec::new();
+        let mut emit = |kvp: KeyValuePair| {
+            // the requester is gone (or cancelled the request), no need to collect any more
+            if tx.is_closed() {
+                return;
+            }
+            chunk.push(kvp);
+            if chunk.len() >= chunk_size {
+                tx.blocking_send(Ok(mem::take(&mut chunk))).ok();
+            }
+        };
+
+        if let Err(e) = self.store.visit_matches(&path, &mut emit) {
+            tx.blocking_send(Err(e.for_pattern(pattern.to_owned())))
+                .ok();
+            return;
+        }
+
+        for kvp in &self.computed {
+            if pattern_matches(pattern, &kvp.key) {
+                emit(kvp.clone());
+            }
+        }
+
+        if !chunk.is_empty() {
+            tx.blocking_send(Ok(chunk)).ok();
+        }
+    }
+
+    pub fn export(&self) -> WorterbuchResult<Value> {
+        export(&self.store)
+    }
+}
+
 pub struct Worterbuch {
     config: Config,
     store: Store,
     subscriptions: Subscriptions,
     ls_subscriptions: LsSubscriptions,
     subscribers: Subscribers,
+    event_subscribers: HashMap<SubscriptionId, mpsc::Sender<ServerEvent>>,
+    subscription_counts: HashMap<Uuid, usize>,
     clients: HashMap<Uuid, SocketAddr>,
+    auth_lockout: AuthLockout,
+    auth_attempts: AuthAttempts,
+    reclaimed_nodes: u64,
+    key_usage: Option<KeyUsage>,
+    /// last write of keys covered by a retention rule; keys that have not been written since the
+    /// server started count as written at startup
+    last_written: HashMap<Key, Instant>,
+    recorder: Recorder,
+    started: Instant,
+    boot_id: String,
+    stats: StatsQueue,
+    /// last values of computed keys that subscribers were notified of
+    computed_values: HashMap<&'static str, Value>,
+    /// TTLs are not persisted, values restored from persistence never expire
+    expiries: Expiries,
+    reservations: Reservations,
+    locks: Locks,
+    history: History,
+    wal: Option<Wal>,
 }
 
 impl Worterbuch {
@@ -239,26 +430,64 @@ impl Worterbuch {
     }
 
     pub fn with_config(config: Config) -> Worterbuch {
+        let key_usage = config.key_usage.map(KeyUsage::new);
+        let expiries = Expiries::new(config.expiry_warning);
         Worterbuch {
             config,
             clients: Default::default(),
             ls_subscriptions: Default::default(),
             store: Default::default(),
             subscribers: Default::default(),
+            event_subscribers: Default::default(),
+            subscription_counts: Default::default(),
             subscriptions: Default::default(),
+            auth_lockout: Default::default(),
+            auth_attempts: Default::default(),
+            reclaimed_nodes: 0,
+            key_usage,
+            last_written: Default::default(),
+            recorder: Default::default(),
+            started: Instant::now(),
+            boot_id: Uuid::new_v4().to_string(),
+            stats: Default::default(),
+            computed_values: Default::default(),
+            expiries,
+            reservations: Default::default(),
+            locks: Default::default(),
+            history: Default::default(),
+            wal: None,
         }
     }
 
     pub fn from_json(json: &str, config: Config) -> WorterbuchResult<Worterbuch> {
-        let mut store: Store = from_str(json).context(|| "Error parsing JSON".to_owned())?;
+        let mut store = parse_store(json, &config)?;
         store.count_entries();
+        let key_usage = config.key_usage.map(KeyUsage::new);
+        let expiries = Expiries::new(config.expiry_warning);
         Ok(Worterbuch {
             config,
             store,
             clients: Default::default(),
             ls_subscriptions: Default::default(),
             subscribers: Default::default(),
+            event_subscribers: Default::default(),
+            subscription_counts: Default::default(),
             subscriptions: Default::default(),
+            auth_lockout: Default::default(),
+            auth_attempts: Default::default(),
+            reclaimed_nodes: 0,
+            key_usage,
+            last_written: Default::default(),
+            recorder: Default::default(),
+            started: Instant::now(),
+            boot_id: Uuid::new_v4().to_string(),
+            stats: Default::default(),
+            computed_values: Default::default(),
+            expiries,
+            reservations: Default::default(),
+            locks: Default::default(),
+            history: Default::default(),
+            wal: None,
         })
     }
 
@@ -270,11 +499,34 @@ impl Worterbuch {
         self.store.is_empty()
     }
 
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            clients: self.clients.len(),
+            subscriptions: self.subscriptions.len(),
+            ls_subscriptions: self.ls_subscriptions.len(),
+            values: self.len(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            channel_backlog: 0,
+            persistence_millis: metrics::persistence_millis(),
+            messages_received: metrics::messages_received(),
+        }
+    }
+
+    /// Identifies this run of the server. Clients can tell from a changed boot ID that the server
+    /// was restarted while they were disconnected.
+    pub fn boot_id(&self) -> String {
+        self.boot_id.clone()
+    }
+
     pub fn supported_protocol_version(&self) -> ProtocolVersion {
-        "0.7".to_owned()
+        PROTOCOL_VERSION.to_owned()
     }
 
     pub fn get(&self, key: &Key) -> WorterbuchResult<(String, Value)> {
+        if let Some(value) = self.computed_value(key) {
+            return Ok((key.to_owned(), value));
+        }
+
         let path: Vec<RegularKeySegment> = parse_segments(key)?;
 
         match self.store.get(&path) {
@@ -286,776 +538,3115 @@ impl Worterbuch {
         }
     }
 
-    pub async fn set(&mut self, key: Key, value: Value, client_id: &str) -> WorterbuchResult<()> {
-        check_for_read_only_key(&key, client_id)?;
+    /// Like [`Worterbuch::get`], but falls back to the configured value templates for keys that
+    /// do not exist yet. Depending on the config the template value is either only returned or
+    /// also stored under the requested key.
+    /// Deprecated keys are read from their redirect target, if one is configured.
+    pub async fn get_or_template(&mut self, key: &Key) -> WorterbuchResult<(String, Value)> {
+        self.track_usage(key, Access::Read);
+        let resolved = self.resolve_deprecated(key, false)?;
+        match self.get(&resolved) {
+            Err(WorterbuchError::NoSuchValue(_)) => {
+                let Some(value) = self.config.value_templates.value_for(&resolved).cloned() else {
+                    return Err(WorterbuchError::NoSuchValue(key.to_owned()));
+                };
+                if self.config.materialize_value_templates {
+                    log::debug!("Materializing template value for {resolved}");
+                    self.set(resolved, value.clone(), INTERNAL_CLIENT_ID)
+                        .await?;
+                }
+                Ok((key.to_owned(), value))
+            }
+            Ok((_, value)) => Ok((key.to_owned(), value)),
+            Err(e) => Err(e),
+        }
+    }
 
-        let path: Vec<RegularKeySegment> = parse_segments(&key)?;
+    /// Resolves a deprecated key or pattern to its redirect target and counts its usage under
+    /// `$SYS/deprecated` with the next stats flush. Writes to deprecated keys may be rejected
+    /// depending on the config.
+    fn resolve_deprecated(&mut self, key: &str, write: bool) -> WorterbuchResult<Key> {
+        let (redirect, reject_writes) = match self.config.deprecations.find(key) {
+            Some(deprecation) => (deprecation.redirect_key(key), deprecation.reject_writes),
+            None => return Ok(key.to_owned()),
+        };
 
-        let (changed, ls_subscribers) = self
-            .store
-            .insert(&path, value.clone())
-            .map_err(|e| e.for_pattern(key.clone()))?;
+        log::debug!("Client accessed deprecated key {key}");
+        let usage_key = topic!(
+            SYSTEM_TOPIC_ROOT,
+            SYSTEM_TOPIC_DEPRECATED,
+            escape_wildcards(key),
+            if write { "writes" } else { "reads" }
+        );
+        let store = &self.store;
+        self.stats.increment(usage_key.clone(), || {
+            parse_segments(&usage_key)
+                .ok()
+                .and_then(|path| store.get(&path))
+                .and_then(Value::as_u64)
+                .unwrap_or(0)
+        });
 
-        log::trace!("Notifying ls subscribers …");
-        self.notify_ls_subscribers(ls_subscribers).await;
-        log::trace!("Notifying ls subscribers done.");
-        log::trace!("Notifying subscribers …");
-        self.notify_subscribers(&path, &key, &value, changed, false)
-            .await;
-        log::trace!("Notifying subscribers done.");
+        if write && reject_writes {
+            return Err(WorterbuchError::DeprecatedKey(key.to_owned(), redirect));
+        }
 
-        Ok(())
+        Ok(redirect.unwrap_or_else(|| key.to_owned()))
     }
 
-    pub async fn publish(&mut self, key: Key, value: Value) -> WorterbuchResult<()> {
-        let path: Vec<RegularKeySegment> = parse_segments(&key)?;
+    pub async fn set(&mut self, key: Key, value: Value, client_id: &str) -> WorterbuchResult<()> {
+        self.set_with_ttl(key, value, None, client_id).await?;
+        Ok(())
+    }
 
-        self.notify_subscribers(&path, &key, &value, true, false)
-            .await;
+    /// Like [`Worterbuch::set`], but returns the value the key had before, if any.
+    pub async fn get_and_set(
+        &mut self,
+        key: Key,
+        value: Value,
+        client_id: &str,
+    ) -> WorterbuchResult<Option<Value>> {
+        self.set_with_ttl(key, value, None, client_id).await
+    }
 
+    /// Sets a value that is deleted again once `ttl` has passed, unless it is set again before.
+    pub async fn set_expiring(
+        &mut self,
+        key: Key,
+        value: Value,
+        ttl: Duration,
+        client_id: &str,
+    ) -> WorterbuchResult<()> {
+        self.set_with_ttl(key, value, Some(ttl), client_id).await?;
         Ok(())
     }
 
-    pub fn pget(&self, pattern: &str) -> WorterbuchResult<KeyValuePairs> {
-        let path: Vec<KeySegment> = KeySegment::parse(pattern);
-        self.store
-            .get_matches(&path)
-            .map_err(|e| e.for_pattern(pattern.to_owned()))
+    /// Sets a value only if the key already exists (`exists`) or does not exist yet (`!exists`).
+    /// Otherwise the value is rejected with [`WorterbuchError::NoSuchValue`] or
+    /// [`WorterbuchError::KeyExists`] respectively.
+    pub async fn set_if(
+        &mut self,
+        key: Key,
+        value: Value,
+        exists: bool,
+        client_id: &str,
+    ) -> WorterbuchResult<()> {
+        let key = self.checked_write_key(key, client_id).await?;
+        let path: Vec<RegularKeySegment> = parse_segments(&key)?;
+        match (exists, self.store.get(&path).is_some()) {
+            (true, false) => Err(WorterbuchError::NoSuchValue(key)),
+            (false, true) => Err(WorterbuchError::KeyExists(key)),
+            _ => {
+                self.store_checked(key, value, None).await?;
+                Ok(())
+            }
+        }
     }
 
-    pub async fn subscribe(
+    /// Sets a value only if the key is still in the expected state. If a `version` is given, it
+    /// must match the key's current version, otherwise the current value must equal `expected`.
+    /// A missing or `null` expected value as well as version 0 mean that the key must not exist.
+    pub async fn compare_and_swap(
         &mut self,
-        client_id: Uuid,
-        transaction_id: TransactionId,
         key: Key,
-        unique: bool,
-        live_only: bool,
-    ) -> WorterbuchResult<(Receiver<PStateEvent>, SubscriptionId)> {
-        let path: Vec<KeySegment> = KeySegment::parse(&key);
-        let (tx, rx) = channel(self.config.channel_buffer_size);
-        let subscription = SubscriptionId::new(client_id, transaction_id);
-        let subscriber = Subscriber::new(subscription.clone(), path.clone(), tx.clone(), unique);
-        self.subscribers.add_subscriber(&path, subscriber);
-        if !live_only {
-            let matches = match self.get(&key) {
-                Ok((key, value)) => Some((key, value)),
-                Err(WorterbuchError::NoSuchValue(_)) => None,
-                Err(e) => return Err(e),
-            };
-            if let Some((key, value)) = matches {
-                tx.send(PStateEvent::KeyValuePairs(vec![(key, value).into()]))
-                    .await
-                    .expect("rx is neither closed nor dropped");
+        expected: Option<Value>,
+        version: Option<u64>,
+        value: Value,
+        client_id: &str,
+    ) -> WorterbuchResult<()> {
+        let key = self.checked_write_key(key, client_id).await?;
+        let path: Vec<RegularKeySegment> = parse_segments(&key)?;
+        let current = self.store.get(&path).cloned();
+        let current_version = self.store.version(&path);
+
+        let matches = match version {
+            Some(version) => version == current_version,
+            None => {
+                let expected = expected
+                    .filter(|v| !v.is_null())
+                    .map(|v| self.normalized(v));
+                current == expected
             }
+        };
+
+        if !matches {
+            return Err(WorterbuchError::CasConflict(
+                key,
+                CasConflict {
+                    value: current,
+                    version: current_version,
+                },
+            ));
         }
-        let subscription_id = SubscriptionId::new(client_id, transaction_id);
-        self.subscriptions.insert(subscription_id, path);
-        log::debug!("Total subscriptions: {}", self.subscriptions.len());
 
-        if self.config.extended_monitoring
-            && key != SYSTEM_TOPIC_ROOT
-            && !key.starts_with(SYSTEM_TOPIC_ROOT_PREFIX)
-        {
-            if let Err(e) = self
-                .set(
-                    topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_SUBSCRIPTIONS),
-                    json!(self.subscriptions.len()),
-                    INTERNAL_CLIENT_ID,
-                )
-                .await
-            {
-                log::warn!("Error in subscription monitoring: {e}");
-            }
+        self.store_checked(key, value, None).await?;
+        Ok(())
+    }
 
-            let subs_key = topic!(
-                SYSTEM_TOPIC_ROOT,
-                SYSTEM_TOPIC_CLIENTS,
-                client_id,
-                SYSTEM_TOPIC_SUBSCRIPTIONS
-            );
-            if let Err(e) = self
-                .set(
-                    topic!(subs_key, key),
-                    json!(transaction_id),
-                    INTERNAL_CLIENT_ID,
-                )
-                .await
-            {
-                log::warn!("Error in subscription monitoring: {e}");
-            }
+    /// Adds `delta` to the numeric value of a key, treating a missing value as `0`, and returns
+    /// the new value. Since the store processes one request at a time, concurrent increments
+    /// never get lost.
+    pub async fn increment(
+        &mut self,
+        key: Key,
+        delta: &Number,
+        client_id: &str,
+    ) -> WorterbuchResult<Value> {
+        let path: Vec<RegularKeySegment> = parse_segments(&key)?;
+        let sum = match self.store.get(&path) {
+            Some(Value::Number(current)) => add_numbers(current, delta),
+            Some(_) => None,
+            None => add_numbers(&Number::from(0), delta),
+        };
+        let Some(sum) = sum else {
+            return Err(WorterbuchError::NotANumber(key));
+        };
+        let value = Value::Number(sum);
+        self.set(key, value.clone(), client_id).await?;
+        Ok(value)
+    }
 
-            let subs = self.ls(&Some(subs_key))?.len();
-            self.set(
-                topic!(
-                    SYSTEM_TOPIC_ROOT,
-                    SYSTEM_TOPIC_CLIENTS,
-                    client_id,
-                    SYSTEM_TOPIC_SUBSCRIPTIONS
-                ),
-                json!(subs),
-                INTERNAL_CLIENT_ID,
-            )
-            .await?;
+    /// Modifies an array value in place and returns the resulting array. Pushing to a missing key
+    /// creates the array, all other operations require it to exist.
+    pub async fn update_array(
+        &mut self,
+        key: Key,
+        op: ArrayOp,
+        client_id: &str,
+    ) -> WorterbuchResult<Value> {
+        let path: Vec<RegularKeySegment> = parse_segments(&key)?;
+        let mut array = match (self.store.get(&path), &op) {
+            (Some(Value::Array(array)), _) => array.clone(),
+            (Some(_), _) => return Err(WorterbuchError::NotAnArray(key)),
+            (None, ArrayOp::Push(_)) => Vec::new(),
+            (None, _) => return Err(WorterbuchError::NoSuchValue(key)),
+        };
+        match op {
+            ArrayOp::Push(value) => array.push(value),
+            ArrayOp::Pop => {
+                array.pop();
+            }
+            ArrayOp::Remove(value) => {
+                let value = self.normalized(value);
+                array.retain(|v| v != &value);
+            }
         }
+        let value = self.normalized(Value::Array(array));
+        self.set(key, value.clone(), client_id).await?;
+        Ok(value)
+    }
 
-        Ok((rx, subscription))
+    /// Replaces the part of a value addressed by a JSON pointer. An empty pointer replaces the whole
+    /// value, any other pointer requires the key and the parent of the addressed location to exist.
+    pub async fn set_at(
+        &mut self,
+        key: Key,
+        pointer: &str,
+        value: Value,
+        client_id: &str,
+    ) -> WorterbuchResult<()> {
+        if pointer.is_empty() {
+            return self.set(key, value, client_id).await;
+        }
+        let path: Vec<RegularKeySegment> = parse_segments(&key)?;
+        let Some(mut document) = self.store.get(&path).cloned() else {
+            return Err(WorterbuchError::NoSuchValue(key));
+        };
+        let value = self.normalized(value);
+        if !set_pointer(&mut document, pointer, value) {
+            return Err(WorterbuchError::NoSuchValue(key + pointer));
+        }
+        self.set(key, document, client_id).await
     }
 
-    pub async fn psubscribe(
+    /// Changes the TTL of an existing value. Without a TTL the value no longer expires. Since an
+    /// expiring value is eventually deleted, changing its TTL is subject to the same checks as
+    /// writing it.
+    pub async fn expire(
         &mut self,
-        client_id: Uuid,
-        transaction_id: TransactionId,
-        pattern: RequestPattern,
-        unique: bool,
-        live_only: bool,
-    ) -> WorterbuchResult<(Receiver<PStateEvent>, SubscriptionId)> {
-        let path: Vec<KeySegment> = KeySegment::parse(&pattern);
-        let (tx, rx) = channel(self.config.channel_buffer_size);
-        let subscription = SubscriptionId::new(client_id, transaction_id);
-        let subscriber = Subscriber::new(
-            subscription.clone(),
-            path.clone().into_iter().map(|s| s.to_owned()).collect(),
-            tx.clone(),
-            unique,
-        );
-        self.subscribers.add_subscriber(&path, subscriber);
-        if !live_only {
-            let matches = self.pget(&pattern)?;
-            tx.send(PStateEvent::KeyValuePairs(matches))
-                .await
-                .expect("rx is neither closed nor dropped");
+        key: Key,
+        ttl: Option<Duration>,
+        client_id: &str,
+    ) -> WorterbuchResult<()> {
+        let key = self.checked_write_key(key, client_id).await?;
+        self.get(&key)?;
+        match ttl {
+            Some(ttl) => self.schedule_expiry(key, ttl),
+            None => self.expiries.cancel(&key),
         }
-        let subscription_id = SubscriptionId::new(client_id, transaction_id);
-        self.subscriptions.insert(subscription_id, path);
-        log::debug!("Total subscriptions: {}", self.subscriptions.len());
+        Ok(())
+    }
 
-        if self.config.extended_monitoring
-            && pattern != "#"
-            && pattern != SYSTEM_TOPIC_ROOT
-            && !pattern.starts_with(SYSTEM_TOPIC_ROOT_PREFIX)
-        {
-            if let Err(e) = self
-                .set(
-                    topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_SUBSCRIPTIONS),
-                    json!(self.subscriptions.len()),
-                    INTERNAL_CLIENT_ID,
-                )
-                .await
-            {
-                log::warn!("Error in subscription monitoring: {e}");
+    /// Schedules the deletion of a value and lets caches know when it becomes stale.
+    fn schedule_expiry(&mut self, key: Key, ttl: Duration) {
+        self.emit_event(ServerEvent::InvalidationHint(InvalidationHint {
+            key: key.clone(),
+            stale_in_millis: Some(ttl.as_millis() as u64),
+        }));
+        self.expiries.schedule(key, Instant::now() + ttl);
+    }
+
+    pub fn next_expiry(&self) -> Option<Instant> {
+        [self.expiries.next(), self.locks.next_expiry()]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// Deletes all values whose TTL has passed, notifying subscribers like an explicit delete, and
+    /// releases all locks whose lease has passed. Values that are about to expire are announced at
+    /// `$SYS/expiring/<key>` until they expire or get a new TTL.
+    pub async fn expire_keys(&mut self) {
+        let now = Instant::now();
+        for key in self.expiries.take_due(now) {
+            log::debug!("Value of {key} expired.");
+            match self.delete(key, INTERNAL_CLIENT_ID).await {
+                Ok(_) | Err(WorterbuchError::NoSuchValue(_)) => (),
+                Err(e) => log::warn!("Error deleting expired value: {e}"),
             }
-            let subs_key = topic!(
-                SYSTEM_TOPIC_ROOT,
-                SYSTEM_TOPIC_CLIENTS,
-                client_id,
-                SYSTEM_TOPIC_SUBSCRIPTIONS
-            );
-            if let Err(e) = self
-                .set(
-                    topic!(subs_key, escape_wildcards(&pattern)),
-                    json!(transaction_id),
-                    INTERNAL_CLIENT_ID,
-                )
-                .await
-            {
-                log::warn!("Error in subscription monitoring: {e}");
+        }
+        for (key, deadline) in self.expiries.take_warnings(now) {
+            let warning_key = topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_EXPIRING, key);
+            let value = json!({ "expiresInMillis": deadline.saturating_duration_since(now).as_millis() as u64 });
+            if let Err(e) = self.set(warning_key, value, INTERNAL_CLIENT_ID).await {
+                log::warn!("Error publishing expiry warning: {e}");
             }
-            if let Err(e) = self.update_subscription_count(client_id, &subs_key).await {
-                log::warn!("Error in subscription monitoring: {e}");
+        }
+        for key in self.expiries.take_withdrawn() {
+            let warning_key = topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_EXPIRING, key);
+            match self.delete(warning_key, INTERNAL_CLIENT_ID).await {
+                Ok(_) | Err(WorterbuchError::NoSuchValue(_)) => (),
+                Err(e) => log::warn!("Error clearing expiry warning: {e}"),
             }
         }
+        for key in self.locks.take_expired(now) {
+            log::debug!("Lease of lock on {key} expired.");
+            self.hand_over_lock(key).await;
+        }
+    }
 
-        Ok((rx, subscription))
+    async fn set_with_ttl(
+        &mut self,
+        key: Key,
+        value: Value,
+        ttl: Option<Duration>,
+        client_id: &str,
+    ) -> WorterbuchResult<Option<Value>> {
+        let key = self.checked_write_key(key, client_id).await?;
+        self.store_checked(key, value, ttl).await
     }
 
-    async fn update_subscription_count(
+    /// Writes a value to a key that already went through [`Worterbuch::checked_write_key`], so
+    /// conditional writes can check the key they actually write to.
+    async fn store_checked(
         &mut self,
-        client_id: Uuid,
-        subs_key: &str,
-    ) -> WorterbuchResult<()> {
-        let subs = self.sub_len(subs_key)?.unwrap_or(0);
-        self.set(
-            topic!(
-                SYSTEM_TOPIC_ROOT,
-                SYSTEM_TOPIC_CLIENTS,
-                client_id,
-                SYSTEM_TOPIC_SUBSCRIPTIONS
-            ),
-            json!(subs),
-            INTERNAL_CLIENT_ID,
-        )
-        .await
-    }
-
-    pub async fn subscribe_ls(
-        &mut self,
-        client_id: Uuid,
-        transaction_id: TransactionId,
-        parent: Option<Key>,
-    ) -> WorterbuchResult<(Receiver<Vec<RegularKeySegment>>, SubscriptionId)> {
-        let children = self.ls(&parent).unwrap_or_else(|_| Vec::new());
-        let path: Vec<RegularKeySegment> = parent
-            .map(|p| p.split('/').map(ToOwned::to_owned).collect())
-            .unwrap_or_default();
-        let (tx, rx) = channel(self.config.channel_buffer_size);
-        let subscription = SubscriptionId::new(client_id, transaction_id);
-        let subscriber = LsSubscriber::new(subscription.clone(), path.clone(), tx.clone());
-        self.store.add_ls_subscriber(&path, subscriber);
-        tx.send(children)
-            .await
-            .expect("rx is neither closed nor dropped");
-        let subscription_id = SubscriptionId::new(client_id, transaction_id);
-        self.ls_subscriptions.insert(subscription_id, path);
-        log::debug!("Total ls subscriptions: {}", self.ls_subscriptions.len());
-        Ok((rx, subscription))
-    }
+        key: Key,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> WorterbuchResult<Option<Value>> {
+        let value = self.normalized(value);
+        let path: Vec<RegularKeySegment> = parse_segments(&key)?;
+        let previous = self.store.get(&path).cloned();
 
-    pub fn export(&self) -> WorterbuchResult<Value> {
-        let mut value = to_value(&self.store)
-            .context(|| "Error generating JSON from worterbuch store during export".to_owned())?;
-        if let Some(Value::Object(obj)) = value.pointer_mut("/data/t") {
-            obj.remove(SYSTEM_TOPIC_ROOT);
+        self.store_value(key.clone(), value).await?;
+        match ttl {
+            Some(ttl) => self.schedule_expiry(key, ttl),
+            None => self.expiries.cancel(&key),
         }
-        Ok(value)
+        Ok(previous)
     }
 
-    pub async fn import(&mut self, json: &str) -> WorterbuchResult<Vec<(String, Value)>> {
-        log::debug!("Parsing store data …");
-        let store: Store =
-            from_str(json).context(|| "Error parsing JSON during import".to_owned())?;
-        log::debug!("Done. Merging nodes …");
-        let imported_values = self.store.merge(store);
+    /// Sets a value like [`Worterbuch::set`], but only notifies subscribers if it differs from the
+    /// stored one, regardless of whether they asked for unique updates. Returns whether the value
+    /// changed.
+    pub async fn publish_retained(
+        &mut self,
+        key: Key,
+        value: Value,
+        client_id: &str,
+    ) -> WorterbuchResult<bool> {
+        let key = self.checked_write_key(key, client_id).await?;
+        let value = self.normalized(value);
+        let path: Vec<RegularKeySegment> = parse_segments(&key)?;
 
-        for (key, val) in &imported_values {
-            let path: Vec<RegularKeySegment> = parse_segments(key)?;
-            self.notify_subscribers(
-                &path, key, val, // TODO only pass true if the value actually changed
-                true, false,
-            )
-            .await;
+        let (changed, ls_subscribers) = self.insert_value(&path, &key, &value)?;
+        self.expiries.cancel(&key);
+        self.notify_ls_subscribers(ls_subscribers).await;
+        if changed {
+            self.notify_subscribers(&path, &key, &value, true, false)
+                .await;
         }
 
-        Ok(imported_values)
-    }
-
-    pub async fn export_to_file(&self, file: &mut File) -> WorterbuchResult<()> {
-        log::debug!("Exporting to {file:?} …");
-        let json = self.export()?.to_string();
-        let json_bytes = json.as_bytes();
-
-        file.write_all(json_bytes)
-            .await
-            .context(|| format!("Error writing to file {file:?}"))?;
-        log::debug!("Done.");
-        Ok(())
+        Ok(changed)
     }
 
-    pub async fn import_from_file(&mut self, path: &Path) -> WorterbuchResult<()> {
-        log::info!("Importing from {path} …");
-        let mut file = File::open(path)
-            .await
-            .context(|| format!("Error opening file {path:?}"))?;
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents)
-            .await
-            .context(|| format!("Error reading file {path}"))?;
-        let json = String::from_utf8_lossy(&contents).to_string();
-        self.import(&json).await?;
-        log::info!("Done.");
-        Ok(())
+    /// Resolves deprecated keys and rejects keys the client must not write to. Writes of the
+    /// server itself skip the checks that only apply to clients.
+    async fn checked_write_key(&mut self, key: Key, client_id: &str) -> WorterbuchResult<Key> {
+        let key = if client_id != INTERNAL_CLIENT_ID {
+            self.track_usage(&key, Access::Write);
+            let key = self.resolve_deprecated(&key, true)?;
+            self.config.key_rules.check(&key)?;
+            key
+        } else {
+            key
+        };
+        check_for_read_only_key(&key, client_id, &self.config)?;
+        self.check_write_access(&key, client_id)?;
+        Ok(key)
     }
 
-    pub async fn unsubscribe(
-        &mut self,
-        client_id: Uuid,
-        transaction_id: TransactionId,
-    ) -> WorterbuchResult<()> {
-        let subscription = SubscriptionId::new(client_id, transaction_id);
-        self.do_unsubscribe(&subscription, client_id).await
+    fn normalized(&self, value: Value) -> Value {
+        if self.config.normalize_values {
+            normalize(value)
+        } else {
+            value
+        }
     }
 
-    async fn do_unsubscribe(
+    /// Sets several values as a single operation. All keys are checked before anything is written,
+    /// so an invalid or read-only key rejects the whole batch. Subscribers receive all changes
+    /// matching their pattern in a single event.
+    pub async fn set_many(
         &mut self,
-        subscription: &SubscriptionId,
-        client_id: Uuid,
+        key_value_pairs: KeyValuePairs,
+        client_id: &str,
     ) -> WorterbuchResult<()> {
-        if let Some(path) = self.subscriptions.remove(subscription) {
-            if self.config.extended_monitoring
-                && path[0] != KeySegment::MultiWildcard
-                && path[0].deref() != SYSTEM_TOPIC_ROOT
-            {
-                if let Err(e) = self
-                    .delete(
-                        topic!(
-                            SYSTEM_TOPIC_ROOT,
-                            SYSTEM_TOPIC_CLIENTS,
-                            client_id,
-                            SYSTEM_TOPIC_SUBSCRIPTIONS,
-                            escape_wildcards(
-                                &path
-                                    .iter()
-                                    .map(ToString::to_string)
-                                    .collect::<Vec<String>>()
-                                    .join("/")
-                            )
-                        ),
-                        INTERNAL_CLIENT_ID,
-                    )
-                    .await
-                {
-                    match e {
-                        WorterbuchError::NoSuchValue(_) => (/* will happen on disconnect */),
-                        _ => log::warn!("Error in subscription monitoring: {e}"),
-                    }
-                }
-            }
-            log::debug!("Remaining subscriptions: {}", self.subscriptions.len());
+        let mut checked = Vec::with_capacity(key_value_pairs.len());
+        for KeyValuePair { key, value } in key_value_pairs {
+            let key = self.checked_write_key(key, client_id).await?;
+            let path: Vec<RegularKeySegment> = parse_segments(&key)?;
+            checked.push((path, key, self.normalized(value)));
+        }
 
-            if self.config.extended_monitoring {
-                if let Err(e) = self
-                    .set(
-                        topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_SUBSCRIPTIONS),
-                        json!(self.subscriptions.len()),
-                        INTERNAL_CLIENT_ID,
-                    )
-                    .await
-                {
-                    log::warn!("Error in subscription monitoring: {e}");
-                }
-            }
-            if self.subscribers.unsubscribe(&path, subscription) {
-                Ok(())
-            } else {
-                Err(WorterbuchError::NotSubscribed)
-            }
-        } else {
-            Err(WorterbuchError::NotSubscribed)
+        let mut changes = Vec::with_capacity(checked.len());
+        let mut ls_subscribers = Vec::new();
+        for (path, key, value) in checked {
+            let (changed, ls) = self.insert_value(&path, &key, &value)?;
+            self.expiries.cancel(&key);
+            ls_subscribers.extend(ls);
+            changes.push((path, KeyValuePair { key, value }, changed));
         }
-    }
 
-    pub fn unsubscribe_ls(
-        &mut self,
-        client_id: Uuid,
-        transaction_id: TransactionId,
-    ) -> WorterbuchResult<()> {
-        let subscription = SubscriptionId::new(client_id, transaction_id);
-        self.do_unsubscribe_ls(&subscription)
+        self.notify_ls_subscribers(ls_subscribers).await;
+        self.notify_subscribers_batched(changes).await;
+
+        Ok(())
     }
 
-    fn do_unsubscribe_ls(&mut self, subscription: &SubscriptionId) -> WorterbuchResult<()> {
-        if let Some(path) = self.ls_subscriptions.remove(subscription) {
-            log::debug!(
-                "Remaining ls subscriptions: {}",
-                self.ls_subscriptions.len()
-            );
-            if self.store.unsubscribe_ls(&path, subscription) {
-                Ok(())
-            } else {
-                Err(WorterbuchError::NotSubscribed)
-            }
-        } else {
-            Err(WorterbuchError::NotSubscribed)
-        }
+    async fn store_value(&mut self, key: Key, value: Value) -> WorterbuchResult<()> {
+        let path: Vec<RegularKeySegment> = parse_segments(&key)?;
+
+        let (changed, ls_subscribers) = self.insert_value(&path, &key, &value)?;
+
+        log::trace!("Notifying ls subscribers …");
+        self.notify_ls_subscribers(ls_subscribers).await;
+        log::trace!("Notifying ls subscribers done.");
+        log::trace!("Notifying subscribers …");
+        self.notify_subscribers(&path, &key, &value, changed, false)
+            .await;
+        log::trace!("Notifying subscribers done.");
+
+        Ok(())
     }
 
-    async fn notify_subscribers(
+    /// Writes a value to the store and takes care of everything but notifying subscribers.
+    fn insert_value(
         &mut self,
         path: &[RegularKeySegment],
         key: &Key,
         value: &Value,
-        value_changed: bool,
-        deleted: bool,
-    ) {
-        let subscribers = self.subscribers.get_subscribers(path);
+    ) -> WorterbuchResult<(bool, LsNotifications)> {
+        if self.config.retention.find(key).is_some() {
+            self.last_written.insert(key.clone(), Instant::now());
+        }
 
-        let filtered_subscribers: Vec<Subscriber> = subscribers
-            .into_iter()
-            .filter(|s| value_changed || !s.is_unique())
-            .collect();
+        let old_value = if self.config.change_hooks.matches(key) {
+            self.store.get(path).cloned()
+        } else {
+            None
+        };
 
-        let len = filtered_subscribers.len();
-        log::trace!("Calling {} subscribers: {} = {:?} …", len, key, value);
-        for subscriber in filtered_subscribers {
-            let kvps = vec![(key.clone(), value.clone()).into()];
-            if let Err(e) = if deleted {
-                subscriber.send(PStateEvent::Deleted(kvps)).await
-            } else {
-                subscriber.send(PStateEvent::KeyValuePairs(kvps)).await
-            } {
-                log::debug!("Error calling subscriber: {e}");
-                self.subscribers.remove_subscriber(subscriber);
-            }
+        let (changed, ls_subscribers) = self
+            .store
+            .insert(path, value.clone())
+            .map_err(|e| e.for_pattern(key.clone()))?;
+
+        if changed {
+            self.config
+                .change_hooks
+                .notify(key, old_value.as_ref(), Some(value));
+            self.log_change(key, Some(value));
+        }
+
+        if let Some(rule) = self.config.history.find(key) {
+            self.history.record(key, value, rule.max_entries);
         }
-        log::trace!("Calling {} subscribers: {} = {:?} done.", len, key, value);
+
+        Ok((changed, ls_subscribers))
     }
 
-    async fn notify_ls_subscribers(
-        &mut self,
-        ls_subscribers: Vec<(Vec<LsSubscriber>, Vec<String>)>,
-    ) {
-        let len = ls_subscribers.len();
-        log::trace!("Calling {} ls subscribers …", len);
-        for (subscribers, new_children) in ls_subscribers {
-            for subscriber in subscribers {
-                if let Err(e) = subscriber.send(new_children.clone()).await {
-                    log::debug!("Error calling subscriber: {e}");
-                    self.store.remove_ls_subscriber(subscriber);
-                }
+    /// Claims exclusive write access to all keys matching `pattern` for the client's session.
+    /// Fails with [`WorterbuchError::KeyReserved`] if another client has reserved an overlapping
+    /// pattern.
+    pub fn reserve(&mut self, pattern: RequestPattern, client_id: &str) -> WorterbuchResult<()> {
+        match self.reservations.reserve(pattern.clone(), client_id) {
+            Ok(()) => {
+                log::debug!("Client {client_id} reserved {pattern}");
+                Ok(())
             }
+            Err(reserved) => Err(WorterbuchError::KeyReserved(pattern, reserved)),
         }
-        log::trace!("Calling {} ls subscribers done.", len);
     }
 
-    pub async fn delete(&mut self, key: Key, client_id: &str) -> WorterbuchResult<(String, Value)> {
-        check_for_read_only_key(&key, client_id)?;
-
-        let path: Vec<RegularKeySegment> = parse_segments(&key)?;
-
-        match self.store.delete(&path) {
-            Some((value, ls_subscribers)) => {
-                self.notify_ls_subscribers(ls_subscribers).await;
-                self.notify_subscribers(&path, &key, &value, true, true)
-                    .await;
-                Ok((key, value))
-            }
-            None => Err(WorterbuchError::NoSuchValue(key)),
+    /// Gives up a reservation made with [`Worterbuch::reserve`]. Releasing a pattern that is not
+    /// reserved by the client has no effect.
+    pub fn release(&mut self, pattern: &str, client_id: &str) {
+        if self.reservations.release(pattern, client_id) {
+            log::debug!("Client {client_id} released {pattern}");
         }
     }
 
-    pub async fn pdelete(
+    /// Locks a key for a client, or renews the lease of a lock the client already holds. Fails
+    /// with [`WorterbuchError::KeyLocked`] if another client holds the lock.
+    pub async fn try_lock(
         &mut self,
-        pattern: RequestPattern,
+        key: Key,
+        lease: Duration,
         client_id: &str,
-    ) -> WorterbuchResult<KeyValuePairs> {
-        self.internal_pdelete(pattern, false, client_id).await
+    ) -> WorterbuchResult<()> {
+        parse_segments(&key)?;
+        check_for_read_only_key(&key, client_id, &self.config)?;
+        if self
+            .locks
+            .acquire(&key, client_id, lease, Instant::now())
+            .is_err()
+        {
+            return Err(WorterbuchError::KeyLocked(key));
+        }
+        log::debug!("Client {client_id} locked {key} for {lease:?}");
+        self.publish_lock(key, client_id, lease).await;
+        Ok(())
     }
 
-    async fn internal_pdelete(
+    /// Like [`Worterbuch::try_lock`], but queues the client if the key is locked by another
+    /// client. `tx` is completed once the lock is granted.
+    pub async fn lock(
         &mut self,
-        pattern: RequestPattern,
-        skip_read_only_check: bool,
-        client_id: &str,
-    ) -> Result<Vec<worterbuch_common::KeyValuePair>, WorterbuchError> {
-        if !skip_read_only_check {
-            check_for_read_only_key(&pattern, client_id)?;
+        key: Key,
+        lease: Duration,
+        client_id: String,
+        tx: oneshot::Sender<WorterbuchResult<()>>,
+    ) {
+        match self.try_lock(key.clone(), lease, &client_id).await {
+            Err(WorterbuchError::KeyLocked(_)) => {
+                log::debug!("Client {client_id} is waiting for the lock on {key}");
+                self.locks.enqueue(
+                    key,
+                    Waiter {
+                        client_id,
+                        lease,
+                        tx,
+                    },
+                );
+            }
+            res => {
+                tx.send(res).ok();
+            }
         }
+    }
 
-        let path: Vec<KeySegment> = KeySegment::parse(&pattern);
+    /// Releases a client's lock on a key. Fails with [`WorterbuchError::KeyLocked`] if the key
+    /// is locked by another client, unlocking a key that is not locked has no effect.
+    pub async fn unlock(&mut self, key: Key, client_id: &str) -> WorterbuchResult<()> {
+        if self.locks.release(&key, client_id) {
+            log::debug!("Client {client_id} unlocked {key}");
+            self.hand_over_lock(key).await;
+            Ok(())
+        } else if self.locks.holder(&key).is_some() {
+            Err(WorterbuchError::KeyLocked(key))
+        } else {
+            Ok(())
+        }
+    }
 
-        match self
-            .store
-            .delete_matches(&path)
-            .map_err(|e| e.for_pattern(pattern))
-        {
-            Ok((deleted, ls_subscribers)) => {
-                self.notify_ls_subscribers(ls_subscribers).await;
-                for kvp in &deleted {
-                    let path = parse_segments(&kvp.key)?;
-                    self.notify_subscribers(&path, &kvp.key, &kvp.value, true, true)
-                        .await;
-                }
-                Ok(deleted)
+    /// Grants a released lock to the next client waiting for it, or clears its state if there is
+    /// none.
+    async fn hand_over_lock(&mut self, key: Key) {
+        let now = Instant::now();
+        while let Some(waiter) = self.locks.next_waiter(&key) {
+            if self
+                .locks
+                .acquire(&key, &waiter.client_id, waiter.lease, now)
+                .is_ok()
+                && waiter.tx.send(Ok(())).is_ok()
+            {
+                log::debug!("Client {} locked {key}", waiter.client_id);
+                self.publish_lock(key, &waiter.client_id, waiter.lease)
+                    .await;
+                return;
             }
-            Err(e) => Err(e),
+            self.locks.release(&key, &waiter.client_id);
+        }
+        let lock_key = topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_LOCKS, key);
+        match self.delete(lock_key, INTERNAL_CLIENT_ID).await {
+            Ok(_) | Err(WorterbuchError::NoSuchValue(_)) => (),
+            Err(e) => log::warn!("Error clearing lock state: {e}"),
         }
     }
 
-    pub fn ls(&self, parent: &Option<Key>) -> WorterbuchResult<Vec<RegularKeySegment>> {
-        let path = parent
-            .as_deref()
-            .map_or_else(Vec::new, |p| p.split('/').collect());
-        self.ls_path(&path)
+    async fn publish_lock(&mut self, key: Key, client_id: &str, lease: Duration) {
+        let lock_key = topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_LOCKS, key);
+        let value = json!({ "clientId": client_id, "leaseMillis": lease.as_millis() as u64 });
+        if let Err(e) = self.set(lock_key, value, INTERNAL_CLIENT_ID).await {
+            log::warn!("Error publishing lock state: {e}");
+        }
     }
 
-    fn ls_path(&self, path: &[&str]) -> WorterbuchResult<Vec<RegularKeySegment>> {
-        let children = if path.is_empty() {
-            Some(self.store.ls_root())
-        } else {
-            self.store.ls(path)
-        };
+    /// Rejects writes to keys that are reserved or locked by another client.
+    fn check_write_access(&self, key: &str, client_id: &str) -> WorterbuchResult<()> {
+        if client_id == INTERNAL_CLIENT_ID {
+            return Ok(());
+        }
+        if self.config.mode.read_only() && key != mode_key() {
+            return Err(WorterbuchError::ServerReadOnly);
+        }
+        let path = KeySegment::parse(key);
+        if let Some(reserved) = self.reservations.conflict(&path, client_id) {
+            return Err(WorterbuchError::KeyReserved(
+                key.to_owned(),
+                reserved.to_owned(),
+            ));
+        }
+        if let Some(locked) = self.locks.conflict(&path, client_id) {
+            return Err(WorterbuchError::KeyLocked(locked.to_owned()));
+        }
+        Ok(())
+    }
 
-        children.map_or_else(
-            || Err(WorterbuchError::NoSuchValue(path.join("/"))),
-            Result::Ok,
-        )
+    pub async fn publish(
+        &mut self,
+        key: Key,
+        value: Value,
+        client_id: &str,
+    ) -> WorterbuchResult<()> {
+        self.track_usage(&key, Access::Write);
+        let key = self.resolve_deprecated(&key, true)?;
+        self.config.key_rules.check(&key)?;
+        self.check_write_access(&key, client_id)?;
+        let value = self.normalized(value);
+
+        let path: Vec<RegularKeySegment> = parse_segments(&key)?;
+
+        self.notify_subscribers(&path, &key, &value, true, false)
+            .await;
+
+        Ok(())
     }
 
-    fn sub_len(&self, subkey: &str) -> WorterbuchResult<Option<usize>> {
-        self.store.count_sub_entries(subkey)
+    pub fn pget(&self, pattern: &str) -> WorterbuchResult<KeyValuePairs> {
+        self.snapshot().pget(pattern)
     }
 
-    pub async fn connected(
+    pub fn pkeys(&self, pattern: &str) -> WorterbuchResult<Vec<Key>> {
+        self.snapshot().pkeys(pattern)
+    }
+
+    /// Takes a snapshot to serve a pget for the given pattern from. Deprecated patterns are
+    /// resolved to their redirect target, the pattern to match is returned along with the snapshot.
+    pub fn pget_snapshot(&mut self, pattern: &str) -> WorterbuchResult<(RequestPattern, Snapshot)> {
+        self.track_usage(pattern, Access::Read);
+        let pattern = self.resolve_deprecated(pattern, false)?;
+        Ok((pattern, self.snapshot()))
+    }
+
+    fn track_usage(&mut self, key: &str, access: Access) {
+        if let Some(key_usage) = &mut self.key_usage {
+            key_usage.record(key, access);
+        }
+    }
+
+    /// The most and least used key buckets, if key usage tracking is enabled.
+    pub fn key_usage(&self, top: usize) -> Option<KeyUsageReport> {
+        self.key_usage.as_ref().map(|usage| usage.report(top))
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let computed = COMPUTED_KEYS
+            .iter()
+            .filter_map(|key| {
+                self.computed_value(key)
+                    .map(|value| (key.to_string(), value).into())
+            })
+            .collect();
+        Snapshot {
+            store: self.store.snapshot(),
+            computed,
+        }
+    }
+
+    /// The value as it may appear in logs, i.e. redacted if the key is sensitive.
+    fn loggable<'a>(&self, key: &str, value: &'a Value) -> Cow<'a, Value> {
+        if self.config.is_sensitive(key) {
+            Cow::Owned(json!(REDACTED))
+        } else {
+            Cow::Borrowed(value)
+        }
+    }
+
+    fn computed_value(&self, key: &str) -> Option<Value> {
+        match key {
+            SYSTEM_KEY_UPTIME => Some(json!(self.started.elapsed().as_secs())),
+            SYSTEM_KEY_VALUE_COUNT => Some(json!(self.len())),
+            SYSTEM_KEY_COMPACTION_RECLAIMED => Some(json!(self.reclaimed_nodes)),
+            SYSTEM_KEY_LS_CACHE_HITS => Some(json!(self.store.ls_cache_stats().hits)),
+            SYSTEM_KEY_LS_CACHE_MISSES => Some(json!(self.store.ls_cache_stats().misses)),
+            SYSTEM_KEY_LS_CACHE_HIT_RATE => Some(json!(self.store.ls_cache_stats().hit_rate())),
+            SYSTEM_KEY_LS_CACHE_ENTRIES => Some(json!(self.store.ls_cache_stats().entries)),
+            _ => None,
+        }
+    }
+
+    pub async fn subscribe(
         &mut self,
         client_id: Uuid,
-        remote_addr: SocketAddr,
-        protocol: &Protocol,
-    ) {
-        self.clients.insert(client_id, remote_addr);
-        let client_count_key = topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_CLIENTS);
-        if let Err(e) = self
-            .set(
-                client_count_key,
-                json!(self.clients.len()),
-                INTERNAL_CLIENT_ID,
-            )
-            .await
-        {
-            log::error!("Error updating client count: {e}");
+        transaction_id: TransactionId,
+        key: Key,
+        unique: bool,
+        live_only: bool,
+    ) -> WorterbuchResult<(Receiver<PStateEvent>, SubscriptionId)> {
+        self.check_subscription_limit(client_id)?;
+        self.track_usage(&key, Access::Subscribe);
+        let key = self.resolve_deprecated(&key, false)?;
+        let path: Vec<KeySegment> = KeySegment::parse(&key);
+        let (tx, rx) = channel(self.config.channel_buffer_size);
+        let subscription = SubscriptionId::new(client_id, transaction_id);
+        let subscriber = Subscriber::new(subscription.clone(), path.clone(), tx.clone(), unique);
+        self.subscribers.add_subscriber(&path, subscriber);
+        if !live_only {
+            let matches = match self.get(&key) {
+                Ok((key, value)) => vec![(key, value).into()],
+                Err(WorterbuchError::NoSuchValue(_)) => KeyValuePairs::new(),
+                Err(e) => return Err(e),
+            };
+            // sent even if empty so that the first event always carries the initial state
+            tx.send(PStateEvent::KeyValuePairs(matches))
+                .await
+                .expect("rx is neither closed nor dropped");
         }
-        if let Err(e) = self.set_client_protocol(&client_id, protocol).await {
-            log::error!("Error updating client protocol: {e}");
-        };
+        let subscription_id = SubscriptionId::new(client_id, transaction_id);
+        if self.subscriptions.insert(subscription_id, path).is_none() {
+            *self.subscription_counts.entry(client_id).or_default() += 1;
+        }
+        log::debug!("Total subscriptions: {}", self.subscriptions.len());
 
-        if let Err(e) = self.set_client_address(&client_id, &remote_addr).await {
-            log::error!("Error updating client address: {e}");
+        if self.config.extended_monitoring
+            && key != SYSTEM_TOPIC_ROOT
+            && !key.starts_with(SYSTEM_TOPIC_ROOT_PREFIX)
+        {
+            self.stats.set(
+                topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_SUBSCRIPTIONS),
+                json!(self.subscriptions.len()),
+            );
+            self.stats.set(
+                topic!(
+                    SYSTEM_TOPIC_ROOT,
+                    SYSTEM_TOPIC_CLIENTS,
+                    client_id,
+                    SYSTEM_TOPIC_SUBSCRIPTIONS,
+                    key
+                ),
+                json!(transaction_id),
+            );
+            self.stats.recount_subscriptions(client_id);
         }
+
+        Ok((rx, subscription))
     }
 
-    async fn set_client_protocol(
+    pub async fn psubscribe(
         &mut self,
-        client_id: &Uuid,
-        protocol: &Protocol,
-    ) -> WorterbuchResult<()> {
-        let protocol = serde_json::to_value(protocol).map_err(|e| {
-            WorterbuchError::SerDeError(e, "could not convert protocol to value".to_owned())
-        })?;
+        client_id: Uuid,
+        transaction_id: TransactionId,
+        pattern: RequestPattern,
+        unique: bool,
+        live_only: bool,
+    ) -> WorterbuchResult<(Receiver<PStateEvent>, SubscriptionId)> {
+        self.check_subscription_limit(client_id)?;
+        self.track_usage(&pattern, Access::Subscribe);
+        let pattern = self.resolve_deprecated(&pattern, false)?;
+        let path: Vec<KeySegment> = KeySegment::parse(&pattern);
+        let (tx, rx) = channel(self.config.channel_buffer_size);
+        let subscription = SubscriptionId::new(client_id, transaction_id);
+        let subscriber = Subscriber::new(
+            subscription.clone(),
+            path.clone().into_iter().map(|s| s.to_owned()).collect(),
+            tx.clone(),
+            unique,
+        );
+        self.subscribers.add_subscriber(&path, subscriber);
+        if !live_only {
+            let matches = self.pget(&pattern)?;
+            tx.send(PStateEvent::KeyValuePairs(matches))
+                .await
+                .expect("rx is neither closed nor dropped");
+        }
+        let subscription_id = SubscriptionId::new(client_id, transaction_id);
+        if self.subscriptions.insert(subscription_id, path).is_none() {
+            *self.subscription_counts.entry(client_id).or_default() += 1;
+        }
+        log::debug!("Total subscriptions: {}", self.subscriptions.len());
+
+        if self.config.extended_monitoring
+            && pattern != "#"
+            && pattern != SYSTEM_TOPIC_ROOT
+            && !pattern.starts_with(SYSTEM_TOPIC_ROOT_PREFIX)
+        {
+            self.stats.set(
+                topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_SUBSCRIPTIONS),
+                json!(self.subscriptions.len()),
+            );
+            self.stats.set(
+                topic!(
+                    SYSTEM_TOPIC_ROOT,
+                    SYSTEM_TOPIC_CLIENTS,
+                    client_id,
+                    SYSTEM_TOPIC_SUBSCRIPTIONS,
+                    escape_wildcards(&pattern)
+                ),
+                json!(transaction_id),
+            );
+            self.stats.recount_subscriptions(client_id);
+        }
+
+        Ok((rx, subscription))
+    }
+
+    /// Applies all queued monitoring writes to the store in one go.
+    pub async fn flush_stats(&mut self) {
+        let (writes, recount) = self.stats.take();
+        for (key, value) in writes {
+            let res = match value {
+                Some(value) => self.set(key, value, INTERNAL_CLIENT_ID).await,
+                None => self.delete(key, INTERNAL_CLIENT_ID).await.map(|_| ()),
+            };
+            match res {
+                Ok(()) | Err(WorterbuchError::NoSuchValue(_)) => (),
+                Err(e) => log::warn!("Error in subscription monitoring: {e}"),
+            }
+        }
+        for client_id in recount {
+            if let Err(e) = self.update_subscription_count(client_id).await {
+                log::warn!("Error in subscription monitoring: {e}");
+            }
+        }
+        self.notify_computed_subscribers().await;
+    }
+
+    /// Computed keys are not stored, so no write ever notifies their subscribers. Instead they are
+    /// notified on every stats flush of those values that changed since the last one.
+    async fn notify_computed_subscribers(&mut self) {
+        for key in COMPUTED_KEYS {
+            let Some(value) = self.computed_value(key) else {
+                continue;
+            };
+            if self.computed_values.get(key) == Some(&value) {
+                continue;
+            }
+            let path: Vec<RegularKeySegment> = key.split('/').map(ToOwned::to_owned).collect();
+            self.notify_subscribers(&path, &key.to_string(), &value, true, false)
+                .await;
+            self.computed_values.insert(*key, value);
+        }
+    }
+
+    /// Publishes the number of requests waiting in the request queue under `$SYS/queue/depth`.
+    pub fn track_queue_depth(&mut self, depth: usize) {
+        self.stats.set(
+            topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_QUEUE, "depth"),
+            json!(depth),
+        );
+    }
+
+    pub fn stats_overdue(&self) -> bool {
+        self.stats.is_overdue()
+    }
+
+    async fn update_subscription_count(&mut self, client_id: Uuid) -> WorterbuchResult<()> {
+        let subs_key = topic!(
+            SYSTEM_TOPIC_ROOT,
+            SYSTEM_TOPIC_CLIENTS,
+            client_id,
+            SYSTEM_TOPIC_SUBSCRIPTIONS
+        );
+        let subs = self.sub_len(&subs_key)?.unwrap_or(0);
         self.set(
             topic!(
                 SYSTEM_TOPIC_ROOT,
                 SYSTEM_TOPIC_CLIENTS,
                 client_id,
-                SYSTEM_TOPIC_CLIENTS_PROTOCOL
+                SYSTEM_TOPIC_SUBSCRIPTIONS
             ),
-            protocol,
+            json!(subs),
             INTERNAL_CLIENT_ID,
         )
         .await
     }
 
-    async fn set_client_address(
+    pub async fn subscribe_ls(
         &mut self,
-        client_id: &Uuid,
-        remote_addr: &SocketAddr,
-    ) -> WorterbuchResult<()> {
-        let remote_addr = serde_json::to_value(remote_addr).map_err(|e| {
-            WorterbuchError::SerDeError(e, "could not convert remote address to value".to_owned())
-        })?;
-        self.set(
-            topic!(
-                SYSTEM_TOPIC_ROOT,
-                SYSTEM_TOPIC_CLIENTS,
-                client_id,
-                SYSTEM_TOPIC_CLIENTS_ADDRESS
-            ),
-            remote_addr,
-            INTERNAL_CLIENT_ID,
-        )
-        .await
+        client_id: Uuid,
+        transaction_id: TransactionId,
+        parent: Option<Key>,
+    ) -> WorterbuchResult<(Receiver<Vec<RegularKeySegment>>, SubscriptionId)> {
+        let children = self.ls(&parent).unwrap_or_else(|_| Vec::new());
+        let path: Vec<RegularKeySegment> = parent
+            .map(|p| p.split('/').map(ToOwned::to_owned).collect())
+            .unwrap_or_default();
+        let (tx, rx) = channel(self.config.channel_buffer_size);
+        let subscription = SubscriptionId::new(client_id, transaction_id);
+        let subscriber = LsSubscriber::new(subscription.clone(), path.clone(), tx.clone());
+        self.store.add_ls_subscriber(&path, subscriber);
+        tx.send(children)
+            .await
+            .expect("rx is neither closed nor dropped");
+        let subscription_id = SubscriptionId::new(client_id, transaction_id);
+        self.ls_subscriptions.insert(subscription_id, path);
+        log::debug!("Total ls subscriptions: {}", self.ls_subscriptions.len());
+        Ok((rx, subscription))
+    }
+
+    pub fn export(&self) -> WorterbuchResult<Value> {
+        export(&self.store)
+    }
+
+    pub async fn import(&mut self, json: &str) -> WorterbuchResult<Vec<(String, Value)>> {
+        log::debug!("Parsing store data …");
+        let store = parse_store(json, &self.config)?;
+        log::debug!("Done. Merging nodes …");
+        let imported_values = self.store.merge(store);
+
+        for (key, val) in &imported_values {
+            self.log_change(key, Some(val));
+            let path: Vec<RegularKeySegment> = parse_segments(key)?;
+            self.notify_subscribers(
+                &path, key, val, // TODO only pass true if the value actually changed
+                true, false,
+            )
+            .await;
+        }
+
+        Ok(imported_values)
+    }
+
+    pub async fn export_to_file(&self, file: &mut File) -> WorterbuchResult<()> {
+        log::debug!("Exporting to {file:?} …");
+        let mut export = self.export()?;
+        if let Some(encryption) = &self.config.encryption {
+            encryption.seal_store(&mut export)?;
+        }
+        let json = export.to_string();
+        let json_bytes = json.as_bytes();
+
+        file.write_all(json_bytes)
+            .await
+            .context(|| format!("Error writing to file {file:?}"))?;
+        log::debug!("Done.");
+        Ok(())
     }
 
-    fn grave_goods(&self, client_id: &Uuid) -> Option<GraveGoods> {
-        let key = topic!(
-            SYSTEM_TOPIC_ROOT,
-            SYSTEM_TOPIC_CLIENTS,
-            client_id,
-            SYSTEM_TOPIC_GRAVE_GOODS
+    pub async fn import_from_file(&mut self, path: &Path) -> WorterbuchResult<()> {
+        log::info!("Importing from {path} …");
+        let mut file = File::open(path)
+            .await
+            .context(|| format!("Error opening file {path:?}"))?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)
+            .await
+            .context(|| format!("Error reading file {path}"))?;
+        let json = String::from_utf8_lossy(&contents).to_string();
+        self.import(&json).await?;
+        log::info!("Done.");
+        Ok(())
+    }
+
+    pub async fn unsubscribe(
+        &mut self,
+        client_id: Uuid,
+        transaction_id: TransactionId,
+    ) -> WorterbuchResult<()> {
+        let subscription = SubscriptionId::new(client_id, transaction_id);
+        if self.event_subscribers.remove(&subscription).is_some() {
+            return Ok(());
+        }
+        self.do_unsubscribe(&subscription, client_id).await
+    }
+
+    /// Delivers the current state of a subscription again, as if it had just been made.
+    pub async fn resync(
+        &mut self,
+        client_id: Uuid,
+        transaction_id: TransactionId,
+    ) -> WorterbuchResult<()> {
+        let subscription = SubscriptionId::new(client_id, transaction_id);
+        let Some(path) = self.subscriptions.get(&subscription) else {
+            return Err(WorterbuchError::NotSubscribed);
+        };
+        let Some(subscriber) = self.subscribers.find_subscriber(path, &subscription) else {
+            return Err(WorterbuchError::NotSubscribed);
+        };
+        let matches = self.pget(&format_path(path))?;
+        if let Err(e) = subscriber.send(PStateEvent::KeyValuePairs(matches)).await {
+            log::debug!("Could not resync subscription {subscription:?}: {e}");
+        }
+        Ok(())
+    }
+
+    fn check_subscription_limit(&self, client_id: Uuid) -> WorterbuchResult<()> {
+        if let Some(max) = self.config.max_subscriptions_per_client {
+            let count = self
+                .subscription_counts
+                .get(&client_id)
+                .copied()
+                .unwrap_or_default();
+            if count >= max {
+                log::warn!("Client {client_id} has reached its subscription limit of {max}.");
+                return Err(WorterbuchError::TooManySubscriptions(max));
+            }
+        }
+        Ok(())
+    }
+
+    /// The most recent `limit` values of a key, oldest first. Empty if no history rule applies.
+    pub fn get_history(&self, key: &Key, limit: Option<usize>) -> Vec<HistoryEntry> {
+        self.history.get(key, limit)
+    }
+
+    /// Lists all subscriptions whose pattern matches the given key or overlaps with the given
+    /// pattern.
+    pub fn who_subscribes(&self, pattern: &RequestPattern) -> Vec<SubscriberInfo> {
+        let path = KeySegment::parse(pattern);
+        let mut subscribers: Vec<SubscriberInfo> = self
+            .subscriptions
+            .iter()
+            .filter(|(_, sub_path)| patterns_overlap(sub_path, &path))
+            .map(|(id, sub_path)| SubscriberInfo {
+                client_id: id.client_id.to_string(),
+                transaction_id: id.transaction_id,
+                pattern: format_path(sub_path),
+            })
+            .collect();
+        subscribers.sort_by(|a, b| {
+            (&a.client_id, a.transaction_id).cmp(&(&b.client_id, b.transaction_id))
+        });
+        subscribers
+    }
+
+    pub fn subscribe_events(
+        &mut self,
+        client_id: Uuid,
+        transaction_id: TransactionId,
+    ) -> Receiver<ServerEvent> {
+        let (tx, rx) = channel(self.config.channel_buffer_size);
+        let subscription = SubscriptionId::new(client_id, transaction_id);
+        self.event_subscribers.insert(subscription, tx);
+        rx
+    }
+
+    pub fn emit_event(&mut self, event: ServerEvent) {
+        log::trace!(
+            "Emitting server event to {} subscribers: {event}",
+            self.event_subscribers.len()
+        );
+        self.event_subscribers
+            .retain(|subscription, tx| match tx.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    log::warn!(
+                        "Event queue of client {} is full, dropping event.",
+                        subscription.client_id
+                    );
+                    true
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            });
+    }
+
+    async fn do_unsubscribe(
+        &mut self,
+        subscription: &SubscriptionId,
+        client_id: Uuid,
+    ) -> WorterbuchResult<()> {
+        if let Some(path) = self.subscriptions.remove(subscription) {
+            if let Some(count) = self.subscription_counts.get_mut(&client_id) {
+                *count -= 1;
+                if *count == 0 {
+                    self.subscription_counts.remove(&client_id);
+                }
+            }
+            if self.config.extended_monitoring
+                && path[0] != KeySegment::MultiWildcard
+                && path[0].deref() != SYSTEM_TOPIC_ROOT
+            {
+                self.stats.delete(topic!(
+                    SYSTEM_TOPIC_ROOT,
+                    SYSTEM_TOPIC_CLIENTS,
+                    client_id,
+                    SYSTEM_TOPIC_SUBSCRIPTIONS,
+                    escape_wildcards(
+                        &path
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<String>>()
+                            .join("/")
+                    )
+                ));
+                self.stats.recount_subscriptions(client_id);
+            }
+            log::debug!("Remaining subscriptions: {}", self.subscriptions.len());
+
+            if self.config.extended_monitoring {
+                self.stats.set(
+                    topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_SUBSCRIPTIONS),
+                    json!(self.subscriptions.len()),
+                );
+            }
+            if self.subscribers.unsubscribe(&path, subscription) {
+                Ok(())
+            } else {
+                Err(WorterbuchError::NotSubscribed)
+            }
+        } else {
+            Err(WorterbuchError::NotSubscribed)
+        }
+    }
+
+    pub fn unsubscribe_ls(
+        &mut self,
+        client_id: Uuid,
+        transaction_id: TransactionId,
+    ) -> WorterbuchResult<()> {
+        let subscription = SubscriptionId::new(client_id, transaction_id);
+        self.do_unsubscribe_ls(&subscription)
+    }
+
+    fn do_unsubscribe_ls(&mut self, subscription: &SubscriptionId) -> WorterbuchResult<()> {
+        if let Some(path) = self.ls_subscriptions.remove(subscription) {
+            log::debug!(
+                "Remaining ls subscriptions: {}",
+                self.ls_subscriptions.len()
+            );
+            if self.store.unsubscribe_ls(&path, subscription) {
+                Ok(())
+            } else {
+                Err(WorterbuchError::NotSubscribed)
+            }
+        } else {
+            Err(WorterbuchError::NotSubscribed)
+        }
+    }
+
+    async fn notify_subscribers(
+        &mut self,
+        path: &[RegularKeySegment],
+        key: &Key,
+        value: &Value,
+        value_changed: bool,
+        deleted: bool,
+    ) {
+        self.recorder.record(key, (!deleted).then_some(value));
+
+        let subscribers = self.subscribers.get_subscribers(path);
+
+        let filtered_subscribers: Vec<Subscriber> = subscribers
+            .into_iter()
+            .filter(|s| value_changed || !s.is_unique())
+            .collect();
+
+        let len = filtered_subscribers.len();
+        log::trace!(
+            "Calling {} subscribers: {} = {:?} …",
+            len,
+            key,
+            self.loggable(key, value)
+        );
+        for subscriber in filtered_subscribers {
+            let kvps = vec![(key.clone(), value.clone()).into()];
+            if let Err(e) = if deleted {
+                subscriber.send(PStateEvent::Deleted(kvps)).await
+            } else {
+                subscriber.send(PStateEvent::KeyValuePairs(kvps)).await
+            } {
+                log::debug!("Error calling subscriber: {e}");
+                self.subscribers.remove_subscriber(subscriber);
+            }
+        }
+        log::trace!(
+            "Calling {} subscribers: {} = {:?} done.",
+            len,
+            key,
+            self.loggable(key, value)
+        );
+    }
+
+    /// Like [`Worterbuch::notify_subscribers`] for several changed values at once, sending each
+    /// subscriber a single event with all values matching its pattern.
+    async fn notify_subscribers_batched(
+        &mut self,
+        changes: Vec<(Vec<RegularKeySegment>, KeyValuePair, bool)>,
+    ) {
+        let mut batches: Vec<(Subscriber, KeyValuePairs)> = Vec::new();
+        let mut batch_index: HashMap<SubscriptionId, usize> = HashMap::new();
+
+        for (path, kvp, value_changed) in changes {
+            self.recorder.record(&kvp.key, Some(&kvp.value));
+            for subscriber in self.subscribers.get_subscribers(&path) {
+                if !value_changed && subscriber.is_unique() {
+                    continue;
+                }
+                match batch_index.get(subscriber.id()) {
+                    Some(i) => batches[*i].1.push(kvp.clone()),
+                    None => {
+                        batch_index.insert(subscriber.id().clone(), batches.len());
+                        batches.push((subscriber, vec![kvp.clone()]));
+                    }
+                }
+            }
+        }
+
+        log::trace!("Calling {} subscribers …", batches.len());
+        for (subscriber, kvps) in batches {
+            if let Err(e) = subscriber.send(PStateEvent::KeyValuePairs(kvps)).await {
+                log::debug!("Error calling subscriber: {e}");
+                self.subscribers.remove_subscriber(subscriber);
+            }
+        }
+        log::trace!("Calling subscribers done.");
+    }
+
+    async fn notify_ls_subscribers(
+        &mut self,
+        ls_subscribers: Vec<(Vec<LsSubscriber>, Vec<String>)>,
+    ) {
+        let len = ls_subscribers.len();
+        log::trace!("Calling {} ls subscribers …", len);
+        for (subscribers, new_children) in ls_subscribers {
+            for subscriber in subscribers {
+                if let Err(e) = subscriber.send(new_children.clone()).await {
+                    log::debug!("Error calling subscriber: {e}");
+                    self.store.remove_ls_subscriber(subscriber);
+                }
+            }
+        }
+        log::trace!("Calling {} ls subscribers done.", len);
+    }
+
+    pub async fn delete(&mut self, key: Key, client_id: &str) -> WorterbuchResult<(String, Value)> {
+        let key = if client_id != INTERNAL_CLIENT_ID {
+            self.resolve_deprecated(&key, true)?
+        } else {
+            key
+        };
+        check_for_read_only_key(&key, client_id, &self.config)?;
+        self.check_write_access(&key, client_id)?;
+
+        let path: Vec<RegularKeySegment> = parse_segments(&key)?;
+
+        match self.store.delete(&path) {
+            Some((value, ls_subscribers)) => {
+                self.last_written.remove(&key);
+                self.expiries.cancel(&key);
+                self.config.change_hooks.notify(&key, Some(&value), None);
+                self.log_change(&key, None);
+                self.notify_ls_subscribers(ls_subscribers).await;
+                self.notify_subscribers(&path, &key, &value, true, true)
+                    .await;
+                self.move_to_trash(&key, &value, client_id).await;
+                Ok((key, value))
+            }
+            None => Err(WorterbuchError::NoSuchValue(key)),
+        }
+    }
+
+    pub async fn pdelete(
+        &mut self,
+        pattern: RequestPattern,
+        client_id: &str,
+    ) -> WorterbuchResult<KeyValuePairs> {
+        self.internal_pdelete(pattern, false, client_id).await
+    }
+
+    async fn internal_pdelete(
+        &mut self,
+        pattern: RequestPattern,
+        skip_read_only_check: bool,
+        client_id: &str,
+    ) -> Result<Vec<worterbuch_common::KeyValuePair>, WorterbuchError> {
+        let pattern = if client_id != INTERNAL_CLIENT_ID {
+            self.resolve_deprecated(&pattern, true)?
+        } else {
+            pattern
+        };
+        if !skip_read_only_check {
+            check_for_read_only_key(&pattern, client_id, &self.config)?;
+        }
+        self.check_write_access(&pattern, client_id)?;
+
+        let path: Vec<KeySegment> = KeySegment::parse(&pattern);
+
+        match self
+            .store
+            .delete_matches(&path)
+            .map_err(|e| e.for_pattern(pattern))
+        {
+            Ok((deleted, ls_subscribers)) => {
+                self.notify_ls_subscribers(ls_subscribers).await;
+                for kvp in &deleted {
+                    self.last_written.remove(&kvp.key);
+                    self.expiries.cancel(&kvp.key);
+                    self.config
+                        .change_hooks
+                        .notify(&kvp.key, Some(&kvp.value), None);
+                    self.log_change(&kvp.key, None);
+                    let path = parse_segments(&kvp.key)?;
+                    self.notify_subscribers(&path, &kvp.key, &kvp.value, true, true)
+                        .await;
+                    self.move_to_trash(&kvp.key, &kvp.value, client_id).await;
+                }
+                Ok(deleted)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Keeps a copy of a value deleted by a client under `$trash` if soft-delete is enabled. Users
+    /// are not kept, their password hashes would no longer be protected in the trash.
+    async fn move_to_trash(&mut self, key: &str, value: &Value, client_id: &str) {
+        if self.config.trash_retention.is_none()
+            || client_id == INTERNAL_CLIENT_ID
+            || key.starts_with(TRASH_TOPIC_ROOT_PREFIX)
+            || key.starts_with(SYSTEM_TOPIC_ROOT_PREFIX)
+            || is_user_db_key(key)
+        {
+            return;
+        }
+
+        let entry = match to_value(TrashEntry::new(value.to_owned())) {
+            Ok(it) => it,
+            Err(e) => {
+                log::error!("Could not move {key} to trash: {e}");
+                return;
+            }
+        };
+
+        if let Err(e) = self.store_value(topic!(TRASH_TOPIC_ROOT, key), entry).await {
+            log::error!("Could not move {key} to trash: {e}");
+        }
+    }
+
+    /// Restores all soft-deleted keys matching the pattern from the trash.
+    pub async fn restore(
+        &mut self,
+        pattern: RequestPattern,
+        client_id: &str,
+    ) -> WorterbuchResult<KeyValuePairs> {
+        let trashed = self.pget(&topic!(TRASH_TOPIC_ROOT, pattern))?;
+
+        let mut restored = KeyValuePairs::new();
+
+        for kvp in trashed {
+            let Some(key) = kvp.key.strip_prefix(TRASH_TOPIC_ROOT_PREFIX) else {
+                continue;
+            };
+            let key = key.to_owned();
+            let entry: TrashEntry = serde_json::from_value(kvp.value)
+                .context(|| format!("invalid trash entry for key {key}"))?;
+            self.set(key.clone(), entry.value.clone(), client_id)
+                .await?;
+            self.delete(kvp.key, INTERNAL_CLIENT_ID).await?;
+            restored.push((key, entry.value).into());
+        }
+
+        Ok(restored)
+    }
+
+    pub(crate) fn attach_wal(&mut self, wal: Wal) {
+        self.wal = Some(wal);
+    }
+
+    pub fn wal_size(&self) -> Option<u64> {
+        self.wal.as_ref().map(Wal::size)
+    }
+
+    /// Writes the write-ahead log through to the disk. Returns `false` if there is no log, in
+    /// which case changes only become durable with the next snapshot.
+    pub fn sync_wal(&mut self) -> WorterbuchResult<bool> {
+        match &mut self.wal {
+            Some(wal) => wal.sync().map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Starts a new write-ahead log, keeping the current one aside until the next snapshot is
+    /// written.
+    pub fn rotate_wal(&mut self) -> WorterbuchResult<()> {
+        match &mut self.wal {
+            Some(wal) => wal.rotate(),
+            None => Ok(()),
+        }
+    }
+
+    /// System keys are not persisted, so they are not logged either. If a change cannot be
+    /// logged, the server switches to read-only mode, so that it does not accept any more changes
+    /// that would be lost in a crash.
+    fn log_change(&mut self, key: &str, value: Option<&Value>) {
+        let Some(wal) = &mut self.wal else {
+            return;
+        };
+        if key.starts_with(SYSTEM_TOPIC_ROOT_PREFIX) {
+            return;
+        }
+        if let Err(e) = wal.append(key, value, self.config.encryption.as_ref()) {
+            log::error!("Could not write change of {key} to write-ahead log: {e}");
+            if !self.config.mode.read_only() {
+                log::error!("Switching server to read-only mode to avoid losing changes.");
+                self.config.mode.set_mode(Mode::ReadOnly);
+                self.stats
+                    .set(mode_key(), json!(Mode::ReadOnly.to_string()));
+            }
+        }
+    }
+
+    /// Prunes empty branches left behind by deletions from the store.
+    pub fn compact(&mut self) -> usize {
+        let reclaimed = self.store.compact();
+        if reclaimed > 0 {
+            log::debug!("Compaction reclaimed {reclaimed} empty nodes");
+            self.reclaimed_nodes += reclaimed as u64;
+        }
+        reclaimed
+    }
+
+    /// Permanently deletes all trash entries whose retention period has expired.
+    pub async fn purge_trash(&mut self) -> WorterbuchResult<()> {
+        let Some(retention) = self.config.trash_retention else {
+            return Ok(());
+        };
+
+        for kvp in self.pget(&topic!(TRASH_TOPIC_ROOT, "#"))? {
+            let expired = serde_json::from_value::<TrashEntry>(kvp.value)
+                .map(|e| e.is_expired(retention))
+                .unwrap_or(true);
+            if expired {
+                log::debug!("Purging {} from trash", kvp.key);
+                self.delete(kvp.key, INTERNAL_CLIENT_ID).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists all keys that have not been written for longer than their retention rule allows.
+    pub fn stale_keys(&self) -> WorterbuchResult<Vec<StaleKey>> {
+        let now = Instant::now();
+        let mut seen = HashSet::new();
+        let mut stale = Vec::new();
+
+        for rule in self.config.retention.rules() {
+            for kvp in self.pget(&rule.pattern)? {
+                // keys matching more than one rule belong to the first one
+                if !seen.insert(kvp.key.clone()) {
+                    continue;
+                }
+                let last_written = self.last_written.get(&kvp.key).unwrap_or(&self.started);
+                let idle_secs = now.saturating_duration_since(*last_written).as_secs();
+                if idle_secs >= rule.max_age_secs {
+                    stale.push(StaleKey {
+                        key: kvp.key,
+                        pattern: rule.pattern.clone(),
+                        idle_secs,
+                        action: rule.action,
+                    });
+                }
+            }
+        }
+
+        Ok(stale)
+    }
+
+    /// Deletes stale keys whose retention rule says so and publishes the number of keys that are
+    /// only flagged under `$SYS/retention/flagged`.
+    pub async fn enforce_retention(&mut self) -> WorterbuchResult<Vec<StaleKey>> {
+        let stale = self.stale_keys()?;
+
+        let mut flagged = 0;
+        for key in &stale {
+            match key.action {
+                RetentionAction::Flag => flagged += 1,
+                RetentionAction::Delete => {
+                    log::info!(
+                        "Deleting {}, it has not been written for {} seconds",
+                        key.key,
+                        key.idle_secs
+                    );
+                    if let Err(e) = self.delete(key.key.clone(), INTERNAL_CLIENT_ID).await {
+                        log::error!("Could not delete stale key {}: {e}", key.key);
+                    }
+                }
+            }
+        }
+
+        self.store_value(
+            topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_RETENTION, "flagged"),
+            json!(flagged),
+        )
+        .await?;
+
+        Ok(stale)
+    }
+
+    pub fn start_recording(&mut self, name: String, pattern: RequestPattern) {
+        self.recorder.start(name, pattern);
+    }
+
+    /// Stops a recording and returns the number of recorded events.
+    pub fn stop_recording(&mut self, name: &str) -> WorterbuchResult<usize> {
+        self.recorder.stop(name)
+    }
+
+    pub fn recording_pattern(&self, name: &str) -> WorterbuchResult<RequestPattern> {
+        self.recorder.pattern(name)
+    }
+
+    pub fn recording(
+        &self,
+        name: &str,
+    ) -> WorterbuchResult<(RequestPattern, Arc<Vec<RecordedEvent>>)> {
+        self.recorder.recording(name)
+    }
+
+    pub fn ls(&mut self, parent: &Option<Key>) -> WorterbuchResult<Vec<RegularKeySegment>> {
+        let path = parent
+            .as_deref()
+            .map_or_else(Vec::new, |p| p.split('/').collect());
+        self.ls_path(&path)
+    }
+
+    fn ls_path(&mut self, path: &[&str]) -> WorterbuchResult<Vec<RegularKeySegment>> {
+        let mut children = self.store.ls_cached(path);
+
+        for key in COMPUTED_KEYS {
+            let segments: Vec<&str> = key.split('/').collect();
+            if segments.len() > path.len() && segments[..path.len()] == *path {
+                let child = segments[path.len()].to_owned();
+                let children = children.get_or_insert_with(Vec::new);
+                if !children.contains(&child) {
+                    children.push(child);
+                }
+            }
+        }
+
+        for child in self.config.value_templates.children(path) {
+            let children = children.get_or_insert_with(Vec::new);
+            if !children.contains(&child) {
+                children.push(child);
+            }
+        }
+
+        children.map_or_else(
+            || Err(WorterbuchError::NoSuchValue(path.join("/"))),
+            Result::Ok,
+        )
+    }
+
+    fn sub_len(&self, subkey: &str) -> WorterbuchResult<Option<usize>> {
+        self.store.count_sub_entries(subkey)
+    }
+
+    pub async fn connected(
+        &mut self,
+        client_id: Uuid,
+        remote_addr: SocketAddr,
+        protocol: &Protocol,
+    ) {
+        self.clients.insert(client_id, remote_addr);
+        self.stats.set(
+            topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_CLIENTS),
+            json!(self.clients.len()),
+        );
+        if let Err(e) = self.set_client_protocol(&client_id, protocol).await {
+            log::error!("Error updating client protocol: {e}");
+        };
+
+        if let Err(e) = self.set_client_address(&client_id, &remote_addr).await {
+            log::error!("Error updating client address: {e}");
+        }
+
+        self.emit_event(ServerEvent::ClientConnected(ClientEvent {
+            client_id: client_id.to_string(),
+            remote_addr: remote_addr.to_string(),
+        }));
+    }
+
+    async fn set_client_protocol(
+        &mut self,
+        client_id: &Uuid,
+        protocol: &Protocol,
+    ) -> WorterbuchResult<()> {
+        let protocol = serde_json::to_value(protocol).map_err(|e| {
+            WorterbuchError::SerDeError(e, "could not convert protocol to value".to_owned())
+        })?;
+        self.set(
+            topic!(
+                SYSTEM_TOPIC_ROOT,
+                SYSTEM_TOPIC_CLIENTS,
+                client_id,
+                SYSTEM_TOPIC_CLIENTS_PROTOCOL
+            ),
+            protocol,
+            INTERNAL_CLIENT_ID,
+        )
+        .await
+    }
+
+    pub async fn set_client_info(
+        &mut self,
+        client_id: &Uuid,
+        info: &ClientInfo,
+    ) -> WorterbuchResult<()> {
+        let info = serde_json::to_value(info).map_err(|e| {
+            WorterbuchError::SerDeError(e, "could not convert client info to value".to_owned())
+        })?;
+        self.set(
+            topic!(
+                SYSTEM_TOPIC_ROOT,
+                SYSTEM_TOPIC_CLIENTS,
+                client_id,
+                SYSTEM_TOPIC_CLIENTS_INFO
+            ),
+            info,
+            INTERNAL_CLIENT_ID,
+        )
+        .await
+    }
+
+    async fn set_client_address(
+        &mut self,
+        client_id: &Uuid,
+        remote_addr: &SocketAddr,
+    ) -> WorterbuchResult<()> {
+        let remote_addr = serde_json::to_value(remote_addr).map_err(|e| {
+            WorterbuchError::SerDeError(e, "could not convert remote address to value".to_owned())
+        })?;
+        self.set(
+            topic!(
+                SYSTEM_TOPIC_ROOT,
+                SYSTEM_TOPIC_CLIENTS,
+                client_id,
+                SYSTEM_TOPIC_CLIENTS_ADDRESS
+            ),
+            remote_addr,
+            INTERNAL_CLIENT_ID,
+        )
+        .await
+    }
+
+    fn grave_goods(&self, client_id: &Uuid) -> Option<GraveGoods> {
+        let key = topic!(
+            SYSTEM_TOPIC_ROOT,
+            SYSTEM_TOPIC_CLIENTS,
+            client_id,
+            SYSTEM_TOPIC_GRAVE_GOODS
+        );
+        let value = self.get(&key).ok();
+        value.and_then(|it| serde_json::from_value(it.1).ok())
+    }
+
+    fn last_wills(&self, client_id: &Uuid) -> Option<LastWill> {
+        let key = topic!(
+            SYSTEM_TOPIC_ROOT,
+            SYSTEM_TOPIC_CLIENTS,
+            client_id,
+            SYSTEM_TOPIC_LAST_WILL
+        );
+        let value = self.get(&key).ok();
+        value.and_then(|it| serde_json::from_value(it.1).ok())
+    }
+
+    pub fn remote_addr(&self, client_id: &Uuid) -> Option<SocketAddr> {
+        self.clients.get(client_id).copied()
+    }
+
+    /// Returns how much longer an address is locked out after too many failed authentication
+    /// attempts, if it is.
+    /// Records the outcome of an authentication attempt, unless the address is locked out, in which
+    /// case the attempt is rejected regardless of its outcome and the remaining lockout is returned.
+    /// Checking and recording in one call makes sure parallel attempts cannot slip past a lockout
+    /// caused by one of them.
+    pub async fn auth_attempt(&mut self, remote_addr: IpAddr, success: bool) -> Option<Duration> {
+        let now = Instant::now();
+        if let Some(remaining) = self.auth_lockout.locked_out(&remote_addr, now) {
+            self.auth_attempts.rejected += 1;
+            self.publish_auth_stats().await;
+            return Some(remaining);
+        }
+        if success {
+            self.auth_attempts.succeeded += 1;
+            self.auth_lockout.succeeded(&remote_addr);
+        } else {
+            self.auth_attempts.failed += 1;
+            if let Some(lockout) = self.auth_lockout.failed(remote_addr, now) {
+                log::warn!(
+                    "Too many failed authentication attempts from {remote_addr}, locking it out for {} s.",
+                    lockout.as_secs()
+                );
+            }
+        }
+        self.publish_auth_stats().await;
+        None
+    }
+
+    async fn publish_auth_stats(&mut self) {
+        let AuthAttempts {
+            succeeded,
+            failed,
+            rejected,
+        } = self.auth_attempts;
+        let locked_out = self.auth_lockout.locked_out_addresses(Instant::now());
+        let stats = [
+            (
+                topic!(
+                    SYSTEM_TOPIC_ROOT,
+                    SYSTEM_TOPIC_AUTH,
+                    "attempts",
+                    "succeeded"
+                ),
+                json!(succeeded),
+            ),
+            (
+                topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_AUTH, "attempts", "failed"),
+                json!(failed),
+            ),
+            (
+                topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_AUTH, "attempts", "rejected"),
+                json!(rejected),
+            ),
+            (
+                topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_AUTH, "lockedOut"),
+                json!(locked_out),
+            ),
+        ];
+        for (key, value) in stats {
+            if let Err(e) = self.set(key, value, INTERNAL_CLIENT_ID).await {
+                log::error!("Error updating auth stats: {e}");
+            }
+        }
+    }
+
+    pub async fn disconnected(
+        &mut self,
+        client_id: Uuid,
+        remote_addr: SocketAddr,
+    ) -> WorterbuchResult<()> {
+        let grave_goods = self.grave_goods(&client_id);
+        let last_wills = self.last_wills(&client_id);
+
+        let pattern = topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_CLIENTS, client_id, "#");
+        if self.config.extended_monitoring {
+            log::debug!("Deleting {pattern}");
+            if let Err(e) = self.pdelete(pattern, INTERNAL_CLIENT_ID).await {
+                log::warn!("Error in subscription monitoring: {e}");
+            }
+        }
+        self.clients.remove(&client_id);
+        let released = self.reservations.release_all(&client_id.to_string());
+        if !released.is_empty() {
+            log::info!(
+                "Releasing {} reservation(s) of client {client_id} ({remote_addr}).",
+                released.len()
+            );
+        }
+        let unlocked = self.locks.release_all(&client_id.to_string());
+        if !unlocked.is_empty() {
+            log::info!(
+                "Releasing {} lock(s) of client {client_id} ({remote_addr}).",
+                unlocked.len()
+            );
+        }
+        for key in unlocked {
+            self.hand_over_lock(key).await;
+        }
+        self.stats.set(
+            topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_CLIENTS),
+            json!(self.clients.len()),
+        );
+
+        let subscription_keys: Vec<SubscriptionId> = self
+            .subscriptions
+            .keys()
+            .filter(|k| k.client_id == client_id)
+            .map(ToOwned::to_owned)
+            .collect();
+        log::info!(
+            "Removing {} subscription(s) of client {client_id} ({remote_addr}).",
+            subscription_keys.len()
+        );
+        for subscription in subscription_keys {
+            if let Err(e) = self.do_unsubscribe(&subscription, client_id).await {
+                log::error!("Inconsistent subscription state: {e}");
+            }
+        }
+        self.event_subscribers
+            .retain(|subscription, _| subscription.client_id != client_id);
+        self.emit_event(ServerEvent::ClientDisconnected(ClientEvent {
+            client_id: client_id.to_string(),
+            remote_addr: remote_addr.to_string(),
+        }));
+
+        if let Some(grave_goods) = grave_goods {
+            log::info!("Burying grave goods of client {client_id} ({remote_addr}).");
+
+            for grave_good in grave_goods {
+                log::debug!(
+                    "Deleting grave good key of client {client_id} ({remote_addr}): {} ",
+                    grave_good
+                );
+                if let Err(e) = self.pdelete(grave_good, &client_id.to_string()).await {
+                    log::error!("Error burying grave goods for client {client_id}: {e}");
+                }
+            }
+        } else {
+            log::info!("Client {client_id} ({remote_addr}) has no grave goods.");
+        }
+
+        if let Some(last_wills) = last_wills {
+            log::info!("Publishing last will of client {client_id} ({remote_addr}).");
+
+            for last_will in last_wills {
+                log::debug!(
+                    "Setting last will of client {client_id} ({remote_addr}): {} = {}",
+                    last_will.key,
+                    self.loggable(&last_will.key, &last_will.value)
+                );
+                if let Err(e) = self
+                    .set(last_will.key, last_will.value, &client_id.to_string())
+                    .await
+                {
+                    log::error!("Error setting last will of client {client_id}: {e}");
+                }
+            }
+        } else {
+            log::info!("Client {client_id} ({remote_addr}) has no last will.");
+        }
+
+        if self.config.extended_monitoring {
+            self.stats.set(
+                topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_SUBSCRIPTIONS),
+                json!(self.subscriptions.len()),
+            );
+        }
+        self.stats.forget_client(&client_id);
+
+        if let Err(e) = self
+            .delete(
+                topic!(
+                    SYSTEM_TOPIC_ROOT,
+                    SYSTEM_TOPIC_CLIENTS,
+                    client_id,
+                    SYSTEM_TOPIC_CLIENTS_PROTOCOL
+                ),
+                INTERNAL_CLIENT_ID,
+            )
+            .await
+        {
+            log::debug!("Error updating client protocol: {e}");
+        }
+
+        if let Err(e) = self
+            .delete(
+                topic!(
+                    SYSTEM_TOPIC_ROOT,
+                    SYSTEM_TOPIC_CLIENTS,
+                    client_id,
+                    SYSTEM_TOPIC_CLIENTS_ADDRESS
+                ),
+                INTERNAL_CLIENT_ID,
+            )
+            .await
+        {
+            log::debug!("Error updating client address: {e}");
+        }
+
+        Ok(())
+    }
+}
+
+fn check_for_read_only_key(key: &str, client_id: &str, config: &Config) -> WorterbuchResult<()> {
+    if client_id == INTERNAL_CLIENT_ID {
+        // modification is made internally by the server, so everything is allowed
+        return Ok(());
+    }
+
+    let path: Vec<&str> = key.split('/').collect();
+
+    if path.is_empty() || path[0] != SYSTEM_TOPIC_ROOT {
+        // path is outside the protected $SYS prefix
+        return Ok(());
+    }
+
+    if config.acl.is_some() && path.len() > 1 && path[1] == SYSTEM_TOPIC_ACL {
+        // the ACL can be edited live, access is controlled by the ACL itself
+        return Ok(());
+    }
+
+    if path.len() == 2 && path[1] == SYSTEM_TOPIC_MODE {
+        // the server mode can be switched for maintenance
+        return Ok(());
+    }
+
+    if config.replication.is_some() && path.len() == 3 && path[1] == SYSTEM_TOPIC_REPLICATION {
+        // the replication role can be switched for a manual failover
+        return Ok(());
+    }
+
+    if path.len() <= 3 || path[1] != SYSTEM_TOPIC_CLIENTS || path[2] != client_id {
+        // the only writable values are under $SYS/clients/[client_id]]/#
+        return Err(WorterbuchError::ReadOnlyKey(key.to_owned()));
+    }
+
+    if path[3] == SYSTEM_TOPIC_GRAVE_GOODS || path[3] == SYSTEM_TOPIC_LAST_WILL {
+        // clients may modify their last will or grave goods at any time
+        return Ok(());
+    }
+
+    // TODO potentially whitelist more fields clients may change
+
+    Err(WorterbuchError::ReadOnlyKey(key.to_owned()))
+}
+
+/// Parses an exported store, decrypting sensitive values if encryption is configured.
+/// Serializes a store or a snapshot of it, without the `$SYS` keys.
+fn export(store: &impl Serialize) -> WorterbuchResult<Value> {
+    let mut value = to_value(store)
+        .context(|| "Error generating JSON from worterbuch store during export".to_owned())?;
+    if let Some(Value::Object(obj)) = value.pointer_mut("/data/t") {
+        obj.remove(SYSTEM_TOPIC_ROOT);
+    }
+    Ok(value)
+}
+
+fn parse_store(json: &str, config: &Config) -> WorterbuchResult<Store> {
+    let Some(encryption) = &config.encryption else {
+        return from_str(json).context(|| "Error parsing JSON".to_owned());
+    };
+    let mut value: Value = from_str(json).context(|| "Error parsing JSON".to_owned())?;
+    encryption.open_store(&mut value)?;
+    from_value(value).context(|| "Error parsing decrypted JSON".to_owned())
+}
+
+fn escape_wildcards(pattern: &str) -> String {
+    pattern.replace('#', "%23").replace('?', "%3F")
+}
+
+/// Adds two JSON numbers, keeping integers as integers unless the sum overflows.
+fn add_numbers(a: &Number, b: &Number) -> Option<Number> {
+    if let Some(sum) = a
+        .as_i64()
+        .zip(b.as_i64())
+        .and_then(|(a, b)| a.checked_add(b))
+    {
+        return Some(Number::from(sum));
+    }
+    Number::from_f64(a.as_f64()? + b.as_f64()?)
+}
+
+/// Sorts the matches of a pget as requested and cuts out the requested window.
+pub(crate) fn sort_and_window(
+    mut kvps: KeyValuePairs,
+    sort: Option<&Sort>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> KeyValuePairs {
+    if let Some(sort) = sort {
+        kvps.sort_by(|a, b| compare_matches(a, b, sort));
+    }
+    kvps.into_iter()
+        .skip(offset.unwrap_or(0))
+        .take(limit.unwrap_or(usize::MAX))
+        .collect()
+}
+
+fn compare_matches(a: &KeyValuePair, b: &KeyValuePair, sort: &Sort) -> Ordering {
+    let order = match &sort.pointer {
+        None => a.key.cmp(&b.key),
+        Some(pointer) => match (a.value.pointer(pointer), b.value.pointer(pointer)) {
+            (Some(x), Some(y)) => compare_values(x, y).then_with(|| a.key.cmp(&b.key)),
+            // matches without the sort field go last regardless of the direction
+            (Some(_), None) => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Greater,
+            (None, None) => return a.key.cmp(&b.key),
+        },
+    };
+    if sort.descending {
+        order.reverse()
+    } else {
+        order
+    }
+}
+
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        _ => type_rank(a).cmp(&type_rank(b)),
+    }
+}
+
+fn type_rank(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+    }
+}
+
+/// Writes to the location addressed by an RFC 6901 JSON pointer, adding missing object members and
+/// appending to arrays for a final `-`. Returns `false` if the parent location does not exist.
+fn set_pointer(document: &mut Value, pointer: &str, value: Value) -> bool {
+    let Some((parent, last)) = pointer.rsplit_once('/') else {
+        return false;
+    };
+    let last = last.replace("~1", "/").replace("~0", "~");
+    match document.pointer_mut(parent) {
+        Some(Value::Object(map)) => {
+            map.insert(last, value);
+            true
+        }
+        Some(Value::Array(array)) => match last.parse::<usize>() {
+            Ok(index) if index < array.len() => {
+                array[index] = value;
+                true
+            }
+            Ok(index) if index == array.len() => {
+                array.push(value);
+                true
+            }
+            _ if last == "-" => {
+                array.push(value);
+                true
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+pub(crate) fn patterns_overlap(a: &[KeySegment], b: &[KeySegment]) -> bool {
+    match (a.split_first(), b.split_first()) {
+        (None, None) => true,
+        (Some((KeySegment::MultiWildcard, _)), Some(_))
+        | (Some(_), Some((KeySegment::MultiWildcard, _))) => true,
+        (Some((head_a, tail_a)), Some((head_b, tail_b))) => {
+            let heads_match = matches!(head_a, KeySegment::Wildcard)
+                || matches!(head_b, KeySegment::Wildcard)
+                || head_a == head_b;
+            heads_match && patterns_overlap(tail_a, tail_b)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        deprecations::{Deprecation, Deprecations},
+        history::{HistoryRule, HistoryRules},
+        hooks::KeyChange,
+        retention::{RetentionRule, RetentionRules},
+        templates::{ValueTemplate, ValueTemplates},
+    };
+    use tokio::task::spawn_blocking;
+
+    #[test]
+    fn subscription_patterns_overlap_with_keys_and_patterns() {
+        let overlap =
+            |a: &str, b: &str| patterns_overlap(&KeySegment::parse(a), &KeySegment::parse(b));
+        assert!(overlap("hello/world", "hello/world"));
+        assert!(overlap("hello/?", "hello/world"));
+        assert!(overlap("hello/#", "hello/world/foo"));
+        assert!(overlap("hello/?/foo", "hello/world/?"));
+        assert!(overlap("#", "hello"));
+        assert!(!overlap("hello/?", "hello/world/foo"));
+        assert!(!overlap("hello/world", "hello/there"));
+        assert!(!overlap("hello/world/foo", "hello/world"));
+    }
+
+    #[tokio::test]
+    async fn change_hooks_see_old_and_new_values() {
+        dotenv::dotenv().ok();
+        let changes = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut config = Config::new().await.unwrap();
+        let recorded = changes.clone();
+        config.change_hooks.on_change("a/#", move |change| {
+            recorded.lock().unwrap().push(change.clone());
+        });
+        let mut wb = Worterbuch::with_config(config);
+
+        wb.set("a/b".to_owned(), json!(1), "test").await.unwrap();
+        wb.set("a/b".to_owned(), json!(1), "test").await.unwrap();
+        wb.set("a/b".to_owned(), json!(2), "test").await.unwrap();
+        wb.set("c/d".to_owned(), json!(3), "test").await.unwrap();
+        wb.delete("a/b".to_owned(), "test").await.unwrap();
+
+        let change = |old: Option<Value>, new: Option<Value>| KeyChange {
+            key: "a/b".to_owned(),
+            old_value: old,
+            new_value: new,
+        };
+        assert_eq!(
+            *changes.lock().unwrap(),
+            vec![
+                change(None, Some(json!(1))),
+                change(Some(json!(1)), Some(json!(2))),
+                change(Some(json!(2)), None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_and_set_returns_previous_value() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+
+        let previous = wb
+            .get_and_set("a/b".to_owned(), json!(1), "test")
+            .await
+            .unwrap();
+        assert_eq!(previous, None);
+
+        let previous = wb
+            .get_and_set("a/b".to_owned(), json!(2), "test")
+            .await
+            .unwrap();
+        assert_eq!(previous, Some(json!(1)));
+        assert_eq!(wb.get(&"a/b".to_owned()).unwrap().1, json!(2));
+    }
+
+    #[tokio::test]
+    async fn increment_adds_to_numeric_values() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        let key = "counter".to_owned();
+
+        let value = wb
+            .increment(key.clone(), &Number::from(2), "test")
+            .await
+            .unwrap();
+        assert_eq!(value, json!(2));
+        let value = wb
+            .increment(key.clone(), &Number::from(-5), "test")
+            .await
+            .unwrap();
+        assert_eq!(value, json!(-3));
+        let value = wb
+            .increment(key.clone(), &Number::from_f64(0.5).unwrap(), "test")
+            .await
+            .unwrap();
+        assert_eq!(value, json!(-2.5));
+        assert_eq!(wb.get(&key).unwrap().1, json!(-2.5));
+
+        wb.set(key.clone(), json!("text"), "test").await.unwrap();
+        assert!(matches!(
+            wb.increment(key, &Number::from(1), "test").await,
+            Err(WorterbuchError::NotANumber(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn arrays_are_modified_in_place() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        let key = "queue".to_owned();
+
+        assert!(matches!(
+            wb.update_array(key.clone(), ArrayOp::Pop, "test").await,
+            Err(WorterbuchError::NoSuchValue(_))
+        ));
+        for value in [json!(1), json!(2), json!(1), json!(3)] {
+            wb.update_array(key.clone(), ArrayOp::Push(value), "test")
+                .await
+                .unwrap();
+        }
+        let value = wb
+            .update_array(key.clone(), ArrayOp::Remove(json!(1)), "test")
+            .await
+            .unwrap();
+        assert_eq!(value, json!([2, 3]));
+        let value = wb
+            .update_array(key.clone(), ArrayOp::Pop, "test")
+            .await
+            .unwrap();
+        assert_eq!(value, json!([2]));
+        assert_eq!(wb.get(&key).unwrap().1, json!([2]));
+
+        wb.set(key.clone(), json!({}), "test").await.unwrap();
+        assert!(matches!(
+            wb.update_array(key, ArrayOp::Push(json!(1)), "test").await,
+            Err(WorterbuchError::NotAnArray(_))
+        ));
+    }
+
+    #[test]
+    fn pget_results_are_sorted_and_windowed() {
+        let kvps: KeyValuePairs = vec![
+            ("s/a".to_owned(), json!({"t": 20})).into(),
+            ("s/b".to_owned(), json!({"t": 25.5})).into(),
+            ("s/c".to_owned(), json!({"h": 40})).into(),
+            ("s/d".to_owned(), json!({"t": 18})).into(),
+        ];
+        let keys = |kvps: KeyValuePairs| kvps.into_iter().map(|kvp| kvp.key).collect::<Vec<_>>();
+
+        let by_key = Sort {
+            pointer: None,
+            descending: true,
+        };
+        assert_eq!(
+            keys(sort_and_window(
+                kvps.clone(),
+                Some(&by_key),
+                Some(1),
+                Some(2)
+            )),
+            vec!["s/c", "s/b"]
+        );
+
+        let by_temperature = Sort {
+            pointer: Some("/t".to_owned()),
+            descending: true,
+        };
+        assert_eq!(
+            keys(sort_and_window(
+                kvps.clone(),
+                Some(&by_temperature),
+                None,
+                None
+            )),
+            vec!["s/b", "s/a", "s/d", "s/c"]
+        );
+        assert_eq!(
+            keys(sort_and_window(kvps, Some(&by_temperature), None, Some(2))),
+            vec!["s/b", "s/a"]
+        );
+    }
+
+    #[tokio::test]
+    async fn values_are_patched_at_json_pointers() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        wb.set("a".to_owned(), json!({"b": [1, 2], "c/d": 3}), "test")
+            .await
+            .unwrap();
+
+        wb.set_at("a".to_owned(), "/b/0", json!(5), "test")
+            .await
+            .unwrap();
+        wb.set_at("a".to_owned(), "/b/-", json!(6), "test")
+            .await
+            .unwrap();
+        wb.set_at("a".to_owned(), "/c~1d", json!(7), "test")
+            .await
+            .unwrap();
+        wb.set_at("a".to_owned(), "/e", json!({}), "test")
+            .await
+            .unwrap();
+        assert_eq!(
+            wb.get(&"a".to_owned()).unwrap().1,
+            json!({"b": [5, 2, 6], "c/d": 7, "e": {}})
+        );
+
+        assert!(matches!(
+            wb.set_at("a".to_owned(), "/x/y", json!(1), "test").await,
+            Err(WorterbuchError::NoSuchValue(_))
+        ));
+        assert!(matches!(
+            wb.set_at("a".to_owned(), "/b/7", json!(1), "test").await,
+            Err(WorterbuchError::NoSuchValue(_))
+        ));
+        assert!(matches!(
+            wb.set_at("missing".to_owned(), "/b", json!(1), "test")
+                .await,
+            Err(WorterbuchError::NoSuchValue(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn pget_can_ignore_case() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        wb.set("Sensors/Kitchen/Temp".to_owned(), json!(1), "test")
+            .await
+            .unwrap();
+        wb.set("sensors/bath/TEMP".to_owned(), json!(2), "test")
+            .await
+            .unwrap();
+        wb.set("sensors/bath/humidity".to_owned(), json!(3), "test")
+            .await
+            .unwrap();
+
+        assert!(wb.pget("sensors/?/temp").unwrap().is_empty());
+
+        let mut keys: Vec<Key> = wb
+            .snapshot()
+            .pget_ignoring_case("sensors/?/temp")
+            .unwrap()
+            .into_iter()
+            .map(|kvp| kvp.key)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, vec!["Sensors/Kitchen/Temp", "sensors/bath/TEMP"]);
+
+        let mut keys: Vec<Key> = wb
+            .snapshot()
+            .pget_ignoring_case("SENSORS/#")
+            .unwrap()
+            .into_iter()
+            .map(|kvp| kvp.key)
+            .collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![
+                "Sensors/Kitchen/Temp",
+                "sensors/bath/TEMP",
+                "sensors/bath/humidity"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn pkeys_returns_matching_keys_only() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        wb.set("a/b".to_owned(), json!(1), "test").await.unwrap();
+        wb.set("a/c/d".to_owned(), json!(2), "test").await.unwrap();
+        wb.set("e/f".to_owned(), json!(3), "test").await.unwrap();
+
+        let mut keys = wb.pkeys("a/#").unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["a/b", "a/c/d"]);
+        assert_eq!(wb.pkeys("?/f").unwrap(), vec!["e/f"]);
+        assert!(wb.pkeys("a/#/b").is_err());
+    }
+
+    #[tokio::test]
+    async fn set_if_checks_whether_key_exists() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+
+        assert!(matches!(
+            wb.set_if("a/b".to_owned(), json!(1), true, "test").await,
+            Err(WorterbuchError::NoSuchValue(_))
+        ));
+        wb.set_if("a/b".to_owned(), json!(1), false, "test")
+            .await
+            .unwrap();
+        assert!(matches!(
+            wb.set_if("a/b".to_owned(), json!(2), false, "test").await,
+            Err(WorterbuchError::KeyExists(_))
+        ));
+        wb.set_if("a/b".to_owned(), json!(3), true, "test")
+            .await
+            .unwrap();
+        assert_eq!(wb.get(&"a/b".to_owned()).unwrap().1, json!(3));
+    }
+
+    #[tokio::test]
+    async fn export_removes_system_keys() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        wb.set("hello/world".to_owned(), json!("test"), INTERNAL_CLIENT_ID)
+            .await
+            .unwrap();
+        wb.set(
+            "$SYS/something".to_owned(),
+            json!("this should not be exported"),
+            INTERNAL_CLIENT_ID,
+        )
+        .await
+        .unwrap();
+        let export = wb.export().unwrap();
+        assert_eq!(
+            r#"{"data":{"t":{"hello":{"t":{"world":{"v":"test"}}}}}}"#,
+            &serde_json::to_string(&export).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn client_info_is_published_under_sys() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        let client_id = Uuid::new_v4();
+        let info = ClientInfo {
+            name: "kitchen panel".to_owned(),
+            version: None,
+            tags: [("room".to_owned(), "kitchen".to_owned())].into(),
+        };
+        wb.set_client_info(&client_id, &info).await.unwrap();
+
+        let key = topic!(
+            SYSTEM_TOPIC_ROOT,
+            SYSTEM_TOPIC_CLIENTS,
+            client_id,
+            SYSTEM_TOPIC_CLIENTS_INFO
+        );
+        assert_eq!(
+            wb.get(&key).unwrap().1,
+            json!({ "name": "kitchen panel", "tags": { "room": "kitchen" } })
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_values_are_deleted() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        let key = "presence/kitchen".to_owned();
+        wb.set_expiring(key.clone(), json!(true), Duration::ZERO, INTERNAL_CLIENT_ID)
+            .await
+            .unwrap();
+        wb.set_expiring(
+            "presence/garden".to_owned(),
+            json!(true),
+            Duration::from_secs(3600),
+            INTERNAL_CLIENT_ID,
+        )
+        .await
+        .unwrap();
+        assert!(wb.get(&key).is_ok());
+
+        wb.expire_keys().await;
+        assert!(wb.get(&key).is_err());
+        assert!(wb.get(&"presence/garden".to_owned()).is_ok());
+
+        wb.expire("presence/garden".to_owned(), None, INTERNAL_CLIENT_ID)
+            .await
+            .unwrap();
+        assert_eq!(wb.next_expiry(), None);
+    }
+
+    #[tokio::test]
+    async fn expiring_values_are_announced_ahead_of_their_ttl() {
+        dotenv::dotenv().ok();
+        let mut config = Config::new().await.unwrap();
+        config.expiry_warning = Some(Duration::from_secs(3600));
+        let mut wb = Worterbuch::with_config(config);
+        let key = "leases/worker1".to_owned();
+        let warning_key = topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_EXPIRING, key);
+
+        wb.set_expiring(
+            key.clone(),
+            json!(true),
+            Duration::from_secs(60),
+            INTERNAL_CLIENT_ID,
+        )
+        .await
+        .unwrap();
+        wb.expire_keys().await;
+        let expires_in = wb.get(&warning_key).unwrap().1["expiresInMillis"]
+            .as_u64()
+            .unwrap();
+        assert!(expires_in > 0 && expires_in <= 60_000);
+        assert!(wb.get(&key).is_ok());
+
+        wb.expire(key.clone(), None, INTERNAL_CLIENT_ID)
+            .await
+            .unwrap();
+        wb.expire_keys().await;
+        assert!(wb.get(&warning_key).is_err());
+        assert_eq!(wb.next_expiry(), None);
+    }
+
+    #[tokio::test]
+    async fn expiring_values_send_invalidation_hints() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        let mut events = wb.subscribe_events(Uuid::new_v4(), 1);
+
+        wb.set_expiring(
+            "presence/kitchen".to_owned(),
+            json!(true),
+            Duration::from_secs(5),
+            INTERNAL_CLIENT_ID,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ServerEvent::InvalidationHint(InvalidationHint {
+                key: "presence/kitchen".to_owned(),
+                stale_in_millis: Some(5000),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn history_is_only_kept_for_matching_keys() {
+        dotenv::dotenv().ok();
+        let mut config = Config::new().await.unwrap();
+        config.history = HistoryRules::new(vec![HistoryRule {
+            pattern: "sensors/#".to_owned(),
+            max_entries: 2,
+        }]);
+        let mut wb = Worterbuch::with_config(config);
+
+        for i in 0..3 {
+            wb.set("sensors/temp".to_owned(), json!(i), INTERNAL_CLIENT_ID)
+                .await
+                .unwrap();
+            wb.set("settings/mode".to_owned(), json!(i), INTERNAL_CLIENT_ID)
+                .await
+                .unwrap();
+        }
+
+        let values: Vec<Value> = wb
+            .get_history(&"sensors/temp".to_owned(), None)
+            .into_iter()
+            .map(|e| e.value)
+            .collect();
+        assert_eq!(values, vec![json!(1), json!(2)]);
+        assert!(wb.get_history(&"settings/mode".to_owned(), None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn compare_and_swap_rejects_stale_writes() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        let key = "counter".to_owned();
+
+        wb.compare_and_swap(key.clone(), None, None, json!(1), INTERNAL_CLIENT_ID)
+            .await
+            .unwrap();
+        wb.compare_and_swap(
+            key.clone(),
+            Some(json!(1)),
+            None,
+            json!(2),
+            INTERNAL_CLIENT_ID,
+        )
+        .await
+        .unwrap();
+        wb.compare_and_swap(key.clone(), None, Some(2), json!(3), INTERNAL_CLIENT_ID)
+            .await
+            .unwrap();
+
+        let res = wb
+            .compare_and_swap(
+                key.clone(),
+                Some(json!(2)),
+                None,
+                json!(4),
+                INTERNAL_CLIENT_ID,
+            )
+            .await;
+        let Err(WorterbuchError::CasConflict(_, conflict)) = res else {
+            panic!("expected a conflict, got {res:?}");
+        };
+        assert_eq!(
+            conflict,
+            CasConflict {
+                value: Some(json!(3)),
+                version: 3
+            }
+        );
+        assert_eq!(wb.get(&key).unwrap().1, json!(3));
+    }
+
+    #[tokio::test]
+    async fn reserved_keys_can_only_be_written_by_their_owner() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        let owner = Uuid::new_v4();
+        let other = Uuid::new_v4().to_string();
+
+        wb.reserve("service/a/#".to_owned(), &owner.to_string())
+            .unwrap();
+        assert!(matches!(
+            wb.reserve("service/#".to_owned(), &other),
+            Err(WorterbuchError::KeyReserved(_, _))
+        ));
+        wb.set("service/a/state".to_owned(), json!(1), &owner.to_string())
+            .await
+            .unwrap();
+        assert!(matches!(
+            wb.set("service/a/state".to_owned(), json!(2), &other).await,
+            Err(WorterbuchError::KeyReserved(_, _))
+        ));
+        assert!(matches!(
+            wb.pdelete("service/#".to_owned(), &other).await,
+            Err(WorterbuchError::KeyReserved(_, _))
+        ));
+
+        wb.disconnected(owner, "127.0.0.1:1234".parse().unwrap())
+            .await
+            .unwrap();
+        wb.set("service/a/state".to_owned(), json!(2), &other)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn locks_are_handed_to_waiting_clients_on_unlock() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        let lease = Duration::from_secs(10);
+        let lock_key = topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_LOCKS, "jobs/import");
+
+        wb.try_lock("jobs/import".to_owned(), lease, "1")
+            .await
+            .unwrap();
+        assert_eq!(wb.get(&lock_key).unwrap().1["clientId"], json!("1"));
+        assert!(matches!(
+            wb.try_lock("jobs/import".to_owned(), lease, "2").await,
+            Err(WorterbuchError::KeyLocked(_))
+        ));
+        assert!(matches!(
+            wb.set("jobs/import".to_owned(), json!(1), "2").await,
+            Err(WorterbuchError::KeyLocked(_))
+        ));
+
+        let (tx, mut rx) = oneshot::channel();
+        wb.lock("jobs/import".to_owned(), lease, "2".to_owned(), tx)
+            .await;
+        assert!(rx.try_recv().is_err());
+        assert!(matches!(
+            wb.unlock("jobs/import".to_owned(), "2").await,
+            Err(WorterbuchError::KeyLocked(_))
+        ));
+
+        wb.unlock("jobs/import".to_owned(), "1").await.unwrap();
+        rx.try_recv().unwrap().unwrap();
+        assert_eq!(wb.get(&lock_key).unwrap().1["clientId"], json!("2"));
+        wb.set("jobs/import".to_owned(), json!(1), "2")
+            .await
+            .unwrap();
+
+        wb.unlock("jobs/import".to_owned(), "2").await.unwrap();
+        assert!(wb.get(&lock_key).is_err());
+    }
+
+    #[tokio::test]
+    async fn read_only_mode_rejects_modifications() {
+        dotenv::dotenv().ok();
+        let config = Config::new().await.unwrap();
+        let mode = config.mode.clone();
+        let mut wb = Worterbuch::with_config(config);
+
+        wb.set("a/b".to_owned(), json!(1), "1").await.unwrap();
+        mode.set_mode(Mode::ReadOnly);
+
+        assert!(matches!(
+            wb.set("a/b".to_owned(), json!(2), "1").await,
+            Err(WorterbuchError::ServerReadOnly)
+        ));
+        assert!(matches!(
+            wb.publish("a/c".to_owned(), json!(2), "1").await,
+            Err(WorterbuchError::ServerReadOnly)
+        ));
+        assert!(matches!(
+            wb.delete("a/b".to_owned(), "1").await,
+            Err(WorterbuchError::ServerReadOnly)
+        ));
+        assert!(matches!(
+            wb.pdelete("a/#".to_owned(), "1").await,
+            Err(WorterbuchError::ServerReadOnly)
+        ));
+        assert!(matches!(
+            wb.expire("a/b".to_owned(), Some(Duration::ZERO), "1").await,
+            Err(WorterbuchError::ServerReadOnly)
+        ));
+        assert_eq!(wb.get(&"a/b".to_owned()).unwrap().1, json!(1));
+
+        // the mode itself must stay writable to be able to switch back
+        wb.set(mode_key(), json!("read-write"), "1").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn deprecated_keys_are_redirected_for_all_operations() {
+        dotenv::dotenv().ok();
+        let mut config = Config::new().await.unwrap();
+        config.deprecations = Deprecations::new(vec![Deprecation {
+            pattern: "old/#".to_owned(),
+            redirect: Some("new".to_owned()),
+            reject_writes: false,
+        }]);
+        let mut wb = Worterbuch::with_config(config);
+
+        let (mut rx, _) = wb
+            .subscribe(Uuid::new_v4(), 1, "old/a".to_owned(), false, true)
+            .await
+            .unwrap();
+        wb.set("old/a".to_owned(), json!(1), "1").await.unwrap();
+        assert_eq!(wb.get(&"new/a".to_owned()).unwrap().1, json!(1));
+        let Ok(PStateEvent::KeyValuePairs(kvps)) = rx.try_recv() else {
+            panic!("subscriber of the deprecated key was not notified");
+        };
+        assert_eq!(kvps[0].key, "new/a");
+
+        let (pattern, _) = wb.pget_snapshot("old/#").unwrap();
+        assert_eq!(pattern, "new/#");
+
+        wb.delete("old/a".to_owned(), "1").await.unwrap();
+        assert!(wb.get(&"new/a".to_owned()).is_err());
+
+        let reads = topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_DEPRECATED, "old/a/reads");
+        assert!(wb.get(&reads).is_err());
+        wb.flush_stats().await;
+        assert_eq!(wb.get(&reads).unwrap().1, json!(1));
+        let writes = topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_DEPRECATED, "old/a/writes");
+        assert_eq!(wb.get(&writes).unwrap().1, json!(2));
+    }
+
+    #[tokio::test]
+    async fn conditional_writes_check_the_redirected_key() {
+        dotenv::dotenv().ok();
+        let mut config = Config::new().await.unwrap();
+        config.deprecations = Deprecations::new(vec![Deprecation {
+            pattern: "old/#".to_owned(),
+            redirect: Some("new".to_owned()),
+            reject_writes: false,
+        }]);
+        let mut wb = Worterbuch::with_config(config);
+
+        wb.set_if("old/a".to_owned(), json!(1), false, "1")
+            .await
+            .unwrap();
+        assert!(matches!(
+            wb.set_if("old/a".to_owned(), json!(2), false, "1").await,
+            Err(WorterbuchError::KeyExists(_))
+        ));
+        wb.compare_and_swap("old/a".to_owned(), Some(json!(1)), None, json!(3), "1")
+            .await
+            .unwrap();
+        assert!(matches!(
+            wb.compare_and_swap("old/a".to_owned(), None, None, json!(4), "1")
+                .await,
+            Err(WorterbuchError::CasConflict(..))
+        ));
+        assert_eq!(wb.get(&"new/a".to_owned()).unwrap().1, json!(3));
+        assert!(wb.get(&"old/a".to_owned()).is_err());
+    }
+
+    #[tokio::test]
+    async fn computed_key_subscribers_are_notified_on_stats_flush() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        let (mut rx, _) = wb
+            .subscribe(
+                Uuid::new_v4(),
+                1,
+                SYSTEM_KEY_VALUE_COUNT.to_owned(),
+                true,
+                false,
+            )
+            .await
+            .unwrap();
+        rx.try_recv().unwrap();
+
+        wb.set("a/b".to_owned(), json!(1), "1").await.unwrap();
+        wb.flush_stats().await;
+        let Ok(PStateEvent::KeyValuePairs(kvps)) = rx.try_recv() else {
+            panic!("no update of the value count");
+        };
+        assert_eq!(kvps[0].value, json!(1));
+
+        wb.flush_stats().await;
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn client_count_is_written_on_stats_flush() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        let key = topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_CLIENTS);
+        wb.connected(
+            Uuid::new_v4(),
+            "127.0.0.1:1234".parse().unwrap(),
+            &Protocol::WS,
+        )
+        .await;
+        assert!(wb.get(&key).is_err());
+
+        wb.flush_stats().await;
+        assert_eq!(wb.get(&key).unwrap().1, json!(1));
+    }
+
+    #[tokio::test]
+    async fn retention_rules_flag_or_delete_stale_keys() {
+        dotenv::dotenv().ok();
+        let mut config = Config::new().await.unwrap();
+        config.retention = RetentionRules::new(vec![
+            RetentionRule {
+                pattern: "devices/old/#".to_owned(),
+                max_age_secs: 0,
+                action: RetentionAction::Delete,
+            },
+            RetentionRule {
+                pattern: "devices/#".to_owned(),
+                max_age_secs: 0,
+                action: RetentionAction::Flag,
+            },
+            RetentionRule {
+                pattern: "settings/#".to_owned(),
+                max_age_secs: 3600,
+                action: RetentionAction::Delete,
+            },
+        ]);
+        let mut wb = Worterbuch::with_config(config);
+        for key in ["devices/old/a", "devices/new/b", "settings/c"] {
+            wb.set(key.to_owned(), json!(1), INTERNAL_CLIENT_ID)
+                .await
+                .unwrap();
+        }
+
+        let mut stale: Vec<Key> = wb
+            .stale_keys()
+            .unwrap()
+            .into_iter()
+            .map(|s| s.key)
+            .collect();
+        stale.sort();
+        assert_eq!(stale, vec!["devices/new/b", "devices/old/a"]);
+
+        wb.enforce_retention().await.unwrap();
+        assert!(wb.get(&"devices/old/a".to_owned()).is_err());
+        assert!(wb.get(&"devices/new/b".to_owned()).is_ok());
+        assert!(wb.get(&"settings/c".to_owned()).is_ok());
+        assert_eq!(
+            wb.get(&topic!(
+                SYSTEM_TOPIC_ROOT,
+                SYSTEM_TOPIC_RETENTION,
+                "flagged"
+            ))
+            .unwrap()
+            .1,
+            json!(1)
         );
-        let value = self.get(&key).ok();
-        value.and_then(|it| serde_json::from_value(it.1).ok())
     }
 
-    fn last_wills(&self, client_id: &Uuid) -> Option<LastWill> {
-        let key = topic!(
-            SYSTEM_TOPIC_ROOT,
-            SYSTEM_TOPIC_CLIENTS,
-            client_id,
-            SYSTEM_TOPIC_LAST_WILL
+    #[tokio::test]
+    async fn computed_system_keys_are_visible() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        wb.set("hello/world".to_owned(), json!("test"), INTERNAL_CLIENT_ID)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            wb.get(&SYSTEM_KEY_VALUE_COUNT.to_owned()).unwrap().1,
+            json!(1)
+        );
+        assert!(wb
+            .ls(&Some("$SYS".to_owned()))
+            .unwrap()
+            .contains(&"uptime".to_owned()));
+        let kvps = wb.pget("$SYS/store/#").unwrap();
+        assert_eq!(
+            kvps,
+            vec![
+                (SYSTEM_KEY_VALUE_COUNT, json!(1)).into(),
+                (SYSTEM_KEY_COMPACTION_RECLAIMED, json!(0)).into()
+            ]
         );
-        let value = self.get(&key).ok();
-        value.and_then(|it| serde_json::from_value(it.1).ok())
     }
 
-    pub async fn disconnected(
-        &mut self,
-        client_id: Uuid,
-        remote_addr: SocketAddr,
-    ) -> WorterbuchResult<()> {
-        let grave_goods = self.grave_goods(&client_id);
-        let last_wills = self.last_wills(&client_id);
-
-        let pattern = topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_CLIENTS, client_id, "#");
-        if self.config.extended_monitoring {
-            log::debug!("Deleting {pattern}");
-            if let Err(e) = self.pdelete(pattern, INTERNAL_CLIENT_ID).await {
-                log::warn!("Error in subscription monitoring: {e}");
-            }
-        }
-        self.clients.remove(&client_id);
-        let client_count_key = topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_CLIENTS);
-        if let Err(e) = self
-            .set(
-                client_count_key,
-                json!(self.clients.len()),
-                INTERNAL_CLIENT_ID,
-            )
+    #[tokio::test]
+    async fn ls_cache_is_invalidated_on_structural_changes() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        let parent = Some("hello".to_owned());
+        wb.set("hello/world".to_owned(), json!(1), INTERNAL_CLIENT_ID)
             .await
-        {
-            log::error!("Error updating client count: {e}");
-        }
+            .unwrap();
 
-        let subscription_keys: Vec<SubscriptionId> = self
-            .subscriptions
-            .keys()
-            .filter(|k| k.client_id == client_id)
-            .map(ToOwned::to_owned)
-            .collect();
-        log::info!(
-            "Removing {} subscription(s) of client {client_id} ({remote_addr}).",
-            subscription_keys.len()
+        assert_eq!(wb.ls(&parent).unwrap(), vec!["world".to_owned()]);
+        assert_eq!(wb.ls(&parent).unwrap(), vec!["world".to_owned()]);
+        assert_eq!(
+            wb.get(&SYSTEM_KEY_LS_CACHE_HITS.to_owned()).unwrap().1,
+            json!(1)
         );
-        for subscription in subscription_keys {
-            if let Err(e) = self.do_unsubscribe(&subscription, client_id).await {
-                log::error!("Inconsistent subscription state: {e}");
-            }
-        }
 
-        if let Some(grave_goods) = grave_goods {
-            log::info!("Burying grave goods of client {client_id} ({remote_addr}).");
-
-            for grave_good in grave_goods {
-                log::debug!(
-                    "Deleting grave good key of client {client_id} ({remote_addr}): {} ",
-                    grave_good
-                );
-                if let Err(e) = self.pdelete(grave_good, &client_id.to_string()).await {
-                    log::error!("Error burying grave goods for client {client_id}: {e}");
-                }
-            }
-        } else {
-            log::info!("Client {client_id} ({remote_addr}) has no grave goods.");
-        }
+        wb.set("hello/there/x".to_owned(), json!(2), INTERNAL_CLIENT_ID)
+            .await
+            .unwrap();
+        let mut children = wb.ls(&parent).unwrap();
+        children.sort();
+        assert_eq!(children, vec!["there".to_owned(), "world".to_owned()]);
 
-        if let Some(last_wills) = last_wills {
-            log::info!("Publishing last will of client {client_id} ({remote_addr}).");
+        wb.delete("hello/world".to_owned(), INTERNAL_CLIENT_ID)
+            .await
+            .unwrap();
+        assert_eq!(wb.ls(&parent).unwrap(), vec!["there".to_owned()]);
+        assert_eq!(
+            wb.get(&SYSTEM_KEY_LS_CACHE_MISSES.to_owned()).unwrap().1,
+            json!(3)
+        );
+    }
 
-            for last_will in last_wills {
-                log::debug!(
-                    "Setting last will of client {client_id} ({remote_addr}): {} = {}",
-                    last_will.key,
-                    last_will.value
-                );
-                if let Err(e) = self
-                    .set(last_will.key, last_will.value, &client_id.to_string())
-                    .await
-                {
-                    log::error!("Error setting last will of client {client_id}: {e}");
-                }
-            }
-        } else {
-            log::info!("Client {client_id} ({remote_addr}) has no last will.");
+    #[tokio::test]
+    async fn pget_chunked_splits_matches_into_chunks() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        for i in 0..5 {
+            wb.set(format!("hello/{i}"), json!(i), INTERNAL_CLIENT_ID)
+                .await
+                .unwrap();
         }
 
-        if self.config.extended_monitoring {
-            if let Err(e) = self
-                .set(
-                    topic!(SYSTEM_TOPIC_ROOT, SYSTEM_TOPIC_SUBSCRIPTIONS),
-                    json!(self.subscriptions.len()),
-                    INTERNAL_CLIENT_ID,
-                )
-                .await
-            {
-                log::warn!("Error in subscription monitoring: {e}");
-            }
+        let snapshot = wb.snapshot();
+        let (tx, mut rx) = mpsc::channel(1);
+        let walk = spawn_blocking(move || {
+            snapshot.pget_chunked("hello/?", 2, &tx);
+            snapshot.pget_chunked("hello/#/world", 2, &tx);
+        });
+
+        let mut sizes = Vec::new();
+        for _ in 0..3 {
+            sizes.push(rx.recv().await.unwrap().unwrap().len());
         }
+        assert_eq!(sizes, vec![2, 2, 1]);
+        assert!(rx.recv().await.unwrap().is_err());
+        walk.await.unwrap();
+    }
 
-        if let Err(e) = self
-            .delete(
-                topic!(
-                    SYSTEM_TOPIC_ROOT,
-                    SYSTEM_TOPIC_CLIENTS,
-                    client_id,
-                    SYSTEM_TOPIC_CLIENTS_PROTOCOL
-                ),
-                INTERNAL_CLIENT_ID,
-            )
+    #[tokio::test]
+    async fn snapshots_do_not_see_later_writes() {
+        dotenv::dotenv().ok();
+        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
+        wb.set("hello/world".to_owned(), json!(1), INTERNAL_CLIENT_ID)
             .await
-        {
-            log::debug!("Error updating client protocol: {e}");
-        }
+            .unwrap();
 
-        if let Err(e) = self
-            .delete(
-                topic!(
-                    SYSTEM_TOPIC_ROOT,
-                    SYSTEM_TOPIC_CLIENTS,
-                    client_id,
-                    SYSTEM_TOPIC_CLIENTS_ADDRESS
-                ),
-                INTERNAL_CLIENT_ID,
-            )
+        let snapshot = wb.snapshot();
+        wb.set("hello/world".to_owned(), json!(2), INTERNAL_CLIENT_ID)
             .await
-        {
-            log::debug!("Error updating client address: {e}");
-        }
+            .unwrap();
+        wb.set("hello/there".to_owned(), json!(3), INTERNAL_CLIENT_ID)
+            .await
+            .unwrap();
 
-        Ok(())
+        assert_eq!(
+            snapshot.pget("hello/?").unwrap(),
+            vec![("hello/world", json!(1)).into()]
+        );
+        assert_eq!(wb.pget("hello/?").unwrap().len(), 2);
+        assert_eq!(wb.get(&"hello/world".to_owned()).unwrap().1, json!(2));
     }
-}
 
-fn check_for_read_only_key(key: &str, client_id: &str) -> WorterbuchResult<()> {
-    if client_id == INTERNAL_CLIENT_ID {
-        // modification is made internally by the server, so everything is allowed
-        return Ok(());
+    #[tokio::test]
+    async fn value_templates_are_materialized() {
+        dotenv::dotenv().ok();
+        let mut config = Config::new().await.unwrap();
+        config.value_templates = ValueTemplates::new(vec![ValueTemplate {
+            pattern: "devices/?/brightness".to_owned(),
+            value: json!(100),
+        }]);
+        config.materialize_value_templates = true;
+        let mut wb = Worterbuch::with_config(config);
+
+        assert_eq!(
+            wb.ls(&Some("devices/dev1".to_owned())).unwrap(),
+            vec!["brightness".to_owned()]
+        );
+        assert!(wb.get(&"devices/dev1/brightness".to_owned()).is_err());
+        assert_eq!(
+            wb.get_or_template(&"devices/dev1/brightness".to_owned())
+                .await
+                .unwrap()
+                .1,
+            json!(100)
+        );
+        assert_eq!(
+            wb.get(&"devices/dev1/brightness".to_owned()).unwrap().1,
+            json!(100)
+        );
+        assert!(wb
+            .get_or_template(&"devices/dev1/contrast".to_owned())
+            .await
+            .is_err());
     }
 
-    let path: Vec<&str> = key.split('/').collect();
+    #[tokio::test]
+    async fn deleted_values_can_be_restored() {
+        dotenv::dotenv().ok();
+        let mut config = Config::new().await.unwrap();
+        config.trash_retention = Some(Duration::from_secs(60));
+        let mut wb = Worterbuch::with_config(config);
+        let client_id = Uuid::new_v4().to_string();
 
-    if path.is_empty() || path[0] != SYSTEM_TOPIC_ROOT {
-        // path is outside the protected $SYS prefix
-        return Ok(());
-    }
+        wb.set("a/b".to_owned(), json!(1), &client_id)
+            .await
+            .unwrap();
+        wb.set("a/c".to_owned(), json!(2), &client_id)
+            .await
+            .unwrap();
+        wb.pdelete("a/#".to_owned(), &client_id).await.unwrap();
 
-    if path.len() <= 3 || path[1] != SYSTEM_TOPIC_CLIENTS || path[2] != client_id {
-        // the only writable values are under $SYS/clients/[client_id]]/#
-        return Err(WorterbuchError::ReadOnlyKey(key.to_owned()));
-    }
+        assert!(wb.get(&"a/b".to_owned()).is_err());
+        assert_eq!(wb.pget("$trash/a/#").unwrap().len(), 2);
 
-    if path[3] == SYSTEM_TOPIC_GRAVE_GOODS || path[3] == SYSTEM_TOPIC_LAST_WILL {
-        // clients may modify their last will or grave goods at any time
-        return Ok(());
-    }
+        wb.set("$auth/users/test".to_owned(), json!({}), &client_id)
+            .await
+            .unwrap();
+        wb.delete("$auth/users/test".to_owned(), &client_id)
+            .await
+            .unwrap();
+        assert!(wb.pget("$trash/$auth/#").unwrap().is_empty());
 
-    // TODO potentially whitelist more fields clients may change
+        let restored = wb.restore("a/b".to_owned(), &client_id).await.unwrap();
+        assert_eq!(restored, vec![("a/b", json!(1)).into()]);
+        assert_eq!(wb.get(&"a/b".to_owned()).unwrap().1, json!(1));
+        assert_eq!(wb.pget("$trash/a/#").unwrap().len(), 1);
 
-    Err(WorterbuchError::ReadOnlyKey(key.to_owned()))
-}
+        wb.purge_trash().await.unwrap();
+        assert_eq!(wb.pget("$trash/a/#").unwrap().len(), 1);
+    }
 
-fn escape_wildcards(pattern: &str) -> String {
-    pattern.replace('#', "%23").replace('?', "%3F")
-}
+    #[tokio::test]
+    async fn subscription_limit_is_enforced() {
+        dotenv::dotenv().ok();
+        let mut config = Config::new().await.unwrap();
+        config.max_subscriptions_per_client = Some(2);
+        let mut wb = Worterbuch::with_config(config);
+        let client_id = Uuid::new_v4();
 
-#[cfg(test)]
-mod test {
-    use super::*;
+        let _sub1 = wb
+            .subscribe(client_id, 1, "a/b".to_owned(), false, true)
+            .await
+            .unwrap();
+        let _sub2 = wb
+            .psubscribe(client_id, 2, "a/#".to_owned(), false, true)
+            .await
+            .unwrap();
+        assert!(matches!(
+            wb.subscribe(client_id, 3, "a/c".to_owned(), false, true)
+                .await,
+            Err(WorterbuchError::TooManySubscriptions(2))
+        ));
+
+        wb.unsubscribe(client_id, 1).await.unwrap();
+        let _sub3 = wb
+            .subscribe(client_id, 3, "a/c".to_owned(), false, true)
+            .await
+            .unwrap();
+    }
 
     #[tokio::test]
-    async fn export_removes_system_keys() {
+    async fn set_many_notifies_each_subscriber_once() {
         dotenv::dotenv().ok();
-        let mut wb = Worterbuch::with_config(Config::new().await.unwrap());
-        wb.set("hello/world".to_owned(), json!("test"), INTERNAL_CLIENT_ID)
+        let config = Config::new().await.unwrap();
+        let mut wb = Worterbuch::with_config(config);
+        let client_id = Uuid::new_v4();
+
+        let (mut rx, _) = wb
+            .psubscribe(client_id, 1, "a/#".to_owned(), false, true)
             .await
             .unwrap();
-        wb.set(
-            "$SYS/something".to_owned(),
-            json!("this should not be exported"),
-            INTERNAL_CLIENT_ID,
+        wb.set_many(
+            vec![
+                ("a/b", json!(1)).into(),
+                ("c/d", json!(2)).into(),
+                ("a/e", json!(3)).into(),
+            ],
+            "test",
         )
         .await
         .unwrap();
-        let export = wb.export().unwrap();
+
         assert_eq!(
-            r#"{"data":{"t":{"hello":{"t":{"world":{"v":"test"}}}}}}"#,
-            &serde_json::to_string(&export).unwrap()
+            rx.try_recv().unwrap(),
+            PStateEvent::KeyValuePairs(vec![("a/b", json!(1)).into(), ("a/e", json!(3)).into()])
+        );
+        assert!(rx.try_recv().is_err());
+        assert_eq!(wb.get(&"c/d".to_owned()).unwrap().1, json!(2));
+
+        let res = wb
+            .set_many(
+                vec![("a/f", json!(4)).into(), ("a/#", json!(5)).into()],
+                "test",
+            )
+            .await;
+        assert!(res.is_err());
+        assert!(wb.get(&"a/f".to_owned()).is_err());
+    }
+
+    #[tokio::test]
+    async fn publish_retained_only_notifies_changes() {
+        dotenv::dotenv().ok();
+        let config = Config::new().await.unwrap();
+        let mut wb = Worterbuch::with_config(config);
+        let client_id = Uuid::new_v4();
+
+        let (mut rx, _) = wb
+            .psubscribe(client_id, 1, "a/#".to_owned(), false, true)
+            .await
+            .unwrap();
+
+        assert!(wb
+            .publish_retained("a/b".to_owned(), json!(1), "test")
+            .await
+            .unwrap());
+        assert!(!wb
+            .publish_retained("a/b".to_owned(), json!(1), "test")
+            .await
+            .unwrap());
+        assert!(wb
+            .publish_retained("a/b".to_owned(), json!(2), "test")
+            .await
+            .unwrap());
+
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            PStateEvent::KeyValuePairs(vec![("a/b", json!(1)).into()])
+        );
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            PStateEvent::KeyValuePairs(vec![("a/b", json!(2)).into()])
         );
+        assert!(rx.try_recv().is_err());
+        assert_eq!(wb.get(&"a/b".to_owned()).unwrap().1, json!(2));
+    }
+
+    #[tokio::test]
+    async fn resync_delivers_current_state_again() {
+        dotenv::dotenv().ok();
+        let config = Config::new().await.unwrap();
+        let mut wb = Worterbuch::with_config(config);
+        let client_id = Uuid::new_v4();
+
+        let (mut rx, _) = wb
+            .psubscribe(client_id, 1, "a/#".to_owned(), false, true)
+            .await
+            .unwrap();
+        wb.set("a/b".to_owned(), json!(1), INTERNAL_CLIENT_ID)
+            .await
+            .unwrap();
+        let expected = PStateEvent::KeyValuePairs(vec![("a/b", json!(1)).into()]);
+        assert_eq!(rx.try_recv().unwrap(), expected);
+
+        wb.resync(client_id, 1).await.unwrap();
+        assert_eq!(rx.try_recv().unwrap(), expected);
+
+        assert!(matches!(
+            wb.resync(client_id, 2).await,
+            Err(WorterbuchError::NotSubscribed)
+        ));
     }
 }